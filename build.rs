@@ -0,0 +1,153 @@
+//! Radio settings baked into the firmware (see src/preset.rs)
+//!
+//! Each of `node_id`, `network_id`, `lora_freq` and `region` comes from
+//! its `WK3_*` environment variable if set, else from `node.toml` next to
+//! Cargo.toml, else the default; the checked values go to
+//! `$OUT_DIR/preset.rs`.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Addresses the setup menu offers, 0 being Join, as `menu::ADDRESSES`
+const ADDRESSES: std::ops::RangeInclusive<u16> = 0..=16;
+/// `AT+NETWORKID` values the module accepts, as `menu::NETWORK_IDS`
+const NETWORK_IDS: [u16; 14] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 18];
+/// Bands the module can tune to, in MHz, as `hopping`'s range
+const BANDS_MHZ: std::ops::RangeInclusive<u16> = 410..=1020;
+
+struct Region {
+    /// `region` value
+    name: &'static str,
+    /// `region::Region` variant
+    variant: &'static str,
+    /// Bands it allows, and its default, as src/region.rs
+    bands_mhz: std::ops::RangeInclusive<u16>,
+    default_mhz: u16,
+}
+
+const REGIONS: [Region; 3] = [
+    Region { name: "eu868", variant: "Eu868", bands_mhz: 863..=870, default_mhz: 868 },
+    Region { name: "us915", variant: "Us915", bands_mhz: 902..=928, default_mhz: 915 },
+    Region { name: "au915", variant: "Au915", bands_mhz: 915..=928, default_mhz: 923 },
+];
+
+struct Setting {
+    /// Key in node.toml
+    key: &'static str,
+    env: &'static str,
+    /// Allowed values, for the error message and the check
+    allowed: &'static str,
+    valid: fn(u16) -> bool,
+}
+
+const NODE_ID: Setting = Setting {
+    key: "node_id",
+    env: "WK3_NODE_ID",
+    allowed: "0-16 (0 joins)",
+    valid: |id| ADDRESSES.contains(&id),
+};
+const NETWORK_ID: Setting = Setting {
+    key: "network_id",
+    env: "WK3_NETWORK_ID",
+    allowed: "3-15 or 18",
+    valid: |id| NETWORK_IDS.contains(&id),
+};
+const LORA_FREQ: Setting = Setting {
+    key: "lora_freq",
+    env: "WK3_LORA_FREQ",
+    allowed: "410-1020 (MHz)",
+    valid: |mhz| BANDS_MHZ.contains(&mhz),
+};
+
+const REGION_ENV: &str = "WK3_REGION";
+const REGION_KEY: &str = "region";
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR");
+    let toml = Path::new(&manifest_dir).join("node.toml");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", toml.display());
+    let file = fs::read_to_string(&toml).map(|text| parse_toml(&text)).unwrap_or_default();
+
+    let region = region(&file);
+    let node_id = value(&NODE_ID, &file);
+    let network_id = value(&NETWORK_ID, &file).unwrap_or(18);
+    let band_mhz = match (value(&LORA_FREQ, &file), region) {
+        (Some(mhz), Some(region)) if !region.bands_mhz.contains(&mhz) => {
+            panic!("lora_freq = {} is outside region {} ({:?} MHz)", mhz, region.name, region.bands_mhz)
+        }
+        (Some(mhz), _) => mhz,
+        (None, Some(region)) => region.default_mhz,
+        (None, None) => 915,
+    };
+    let region_code = match region {
+        Some(region) => format!("Some(crate::region::Region::{})", region.variant),
+        None => "None".to_string(),
+    };
+
+    let out = Path::new(&env::var("OUT_DIR").expect("OUT_DIR")).join("preset.rs");
+    let code = format!(
+        "/// `AT+ADDRESS` this build starts with, `None` for the node's own default\n\
+         pub const NODE_ID: Option<u16> = {:?};\n\
+         /// `AT+NETWORKID` this build starts with\n\
+         pub const NETWORK_ID: u8 = {};\n\
+         /// `AT+BAND` this build starts with, in MHz\n\
+         pub const LORA_FREQ_MHZ: u16 = {};\n\
+         /// The region whose rules this build follows (see region.rs)\n\
+         pub const REGION: Option<crate::region::Region> = {};\n",
+        node_id, network_id, band_mhz, region_code
+    );
+    fs::write(out, code).expect("writing preset.rs");
+}
+
+/// The setting from the environment, else node.toml; a bad value stops
+/// the build
+fn value(setting: &Setting, file: &[(String, String)]) -> Option<u16> {
+    println!("cargo:rerun-if-env-changed={}", setting.env);
+    let (text, from) = match env::var(setting.env) {
+        Ok(text) => (text, setting.env),
+        Err(_) => (file.iter().find(|(key, _)| key == setting.key)?.1.clone(), "node.toml"),
+    };
+    match text.trim().parse() {
+        Ok(v) if (setting.valid)(v) => Some(v),
+        _ => panic!("{} = {:?} from {}: must be {}", setting.key, text, from, setting.allowed),
+    }
+}
+
+/// The region from the environment, else node.toml; an unknown one stops
+/// the build
+fn region(file: &[(String, String)]) -> Option<&'static Region> {
+    println!("cargo:rerun-if-env-changed={}", REGION_ENV);
+    let (text, from) = match env::var(REGION_ENV) {
+        Ok(text) => (text, REGION_ENV),
+        Err(_) => (file.iter().find(|(key, _)| key == REGION_KEY)?.1.clone(), "node.toml"),
+    };
+    let name = text.trim().to_ascii_lowercase();
+    match REGIONS.iter().find(|region| region.name == name) {
+        Some(region) => Some(region),
+        None => panic!("region = {:?} from {}: must be one of eu868, us915, au915", text, from),
+    }
+}
+
+/// `key = value` lines, `#` comments and blank lines; anything else, or
+/// an unknown key, stops the build rather than being ignored
+fn parse_toml(text: &str) -> Vec<(String, String)> {
+    let keys = [NODE_ID.key, NETWORK_ID.key, LORA_FREQ.key, REGION_KEY];
+    let mut pairs = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            panic!("node.toml line {}: expected `key = value`", number + 1);
+        };
+        let key = key.trim();
+        if !keys.contains(&key) {
+            panic!("node.toml line {}: unknown key `{}` (expected one of {:?})", number + 1, key, keys);
+        }
+        pairs.push((key.to_string(), value.trim().trim_matches('"').to_string()));
+    }
+    pairs
+}
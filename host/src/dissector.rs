@@ -0,0 +1,208 @@
+//! A Wireshark Lua dissector for `wk3 pcap` captures
+//!
+//! Written by `wk3 pcap --dissector <file>` from the protocol crate's own
+//! message types, so it can't fall behind the firmware. Copy it to
+//! Wireshark's personal plugins folder (Help > About > Folders) or load it
+//! with `wireshark -X lua_script:wk3.lua capture.pcapng`.
+//!
+//! It shows the pseudo-header, the frame header with the message type by
+//! name, the body bytes and whether the CRC checks (an expert error if
+//! not), with the sequence number of ACKs, NACKs and readings. Bodies are postcard and are left
+//! undecoded; `wk3 watch` decodes them in full. Filter with e.g.
+//! `wk3.type == 0x03` or `wk3.crc_ok == 0`.
+
+use std::fmt::Write as _;
+
+use wk3_protocol::{
+    CRC_LEN, FRAME_MAGIC, FRAME_VERSION, HEADER_LEN, MESSAGE_TYPES, MSG_TYPE_ACK, MSG_TYPE_NACK, MSG_TYPE_SENSOR_DATA,
+    OFFSET_HOPS, OFFSET_LEN, OFFSET_NODE, OFFSET_TYPE, OFFSET_VERSION,
+};
+
+use crate::pcapng::{KIND_FRAME, KIND_LINE, PSEUDO_HEADER_LEN};
+
+/// The dissector's source
+pub fn lua() -> String {
+    let mut types = String::new();
+    for (t, name) in MESSAGE_TYPES {
+        let _ = writeln!(types, "    [{}] = \"{}\",", t, name);
+    }
+    LUA.replace("@TYPES@", &types)
+        .replace("@PSEUDO_HEADER_LEN@", &PSEUDO_HEADER_LEN.to_string())
+        .replace("@MAGIC@", &FRAME_MAGIC.to_string())
+        .replace("@VERSION@", &FRAME_VERSION.to_string())
+        .replace("@OFFSET_VERSION@", &OFFSET_VERSION.to_string())
+        .replace("@OFFSET_NODE@", &OFFSET_NODE.to_string())
+        .replace("@OFFSET_HOPS@", &OFFSET_HOPS.to_string())
+        .replace("@OFFSET_TYPE@", &OFFSET_TYPE.to_string())
+        .replace("@OFFSET_LEN@", &OFFSET_LEN.to_string())
+        .replace("@HEADER_LEN@", &HEADER_LEN.to_string())
+        .replace("@CRC_LEN@", &CRC_LEN.to_string())
+        .replace("@KIND_FRAME@", &KIND_FRAME.to_string())
+        .replace("@KIND_LINE@", &KIND_LINE.to_string())
+        .replace("@ACK@", &MSG_TYPE_ACK.to_string())
+        .replace("@NACK@", &MSG_TYPE_NACK.to_string())
+        .replace("@SENSOR_DATA@", &MSG_TYPE_SENSOR_DATA.to_string())
+}
+
+const LUA: &str = r#"-- wk3 LoRa frames in `wk3 pcap` captures (LINKTYPE_USER0)
+-- Generated by `wk3 pcap --dissector`; regenerate rather than edit.
+
+local wk3 = Proto("wk3", "wk3 LoRa frame")
+
+local kinds = { [@KIND_FRAME@] = "Radio payload", [@KIND_LINE@] = "Module line" }
+local types = {
+@TYPES@}
+
+local f = wk3.fields
+f.kind = ProtoField.uint8("wk3.kind", "Record", base.DEC, kinds)
+f.rx_time = ProtoField.uint32("wk3.rx_time_ms", "Node 2 uptime (ms)")
+f.rssi = ProtoField.int16("wk3.rssi", "RSSI (dBm)")
+f.snr = ProtoField.int16("wk3.snr", "SNR (dB)")
+f.magic = ProtoField.uint8("wk3.magic", "Magic", base.HEX)
+f.version = ProtoField.uint8("wk3.version", "Version")
+f.node = ProtoField.uint8("wk3.node", "Sender node")
+f.hops = ProtoField.uint8("wk3.hops", "Relay hops", base.DEC, nil, 0x0F)
+f.route_len = ProtoField.uint8("wk3.route_len", "Route length", base.DEC, nil, 0x30)
+f.route = ProtoField.uint8("wk3.route", "Route relay")
+f.type = ProtoField.uint8("wk3.type", "Type", base.HEX, types)
+f.len = ProtoField.uint8("wk3.len", "Body length")
+f.seq = ProtoField.uint16("wk3.seq", "Sequence")
+f.slot = ProtoField.uint8("wk3.slot", "Transmit slot")
+f.body = ProtoField.bytes("wk3.body", "Body (postcard)")
+f.crc = ProtoField.uint16("wk3.crc", "CRC-16", base.HEX)
+f.crc_ok = ProtoField.bool("wk3.crc_ok", "CRC good")
+f.line = ProtoField.string("wk3.line", "Line")
+
+local crc_bad = ProtoExpert.new("wk3.crc_bad", "CRC mismatch", expert.group.CHECKSUM, expert.severity.ERROR)
+local bad_header = ProtoExpert.new("wk3.bad_header", "Not a wk3 frame header", expert.group.MALFORMED, expert.severity.ERROR)
+wk3.experts = { crc_bad, bad_header }
+
+-- CRC-16/IBM-3740: polynomial 0x1021, initial 0xFFFF, as `calculate_crc16`
+local function crc16(tvb)
+    local crc = 0xFFFF
+    for i = 0, tvb:len() - 1 do
+        crc = bit.bxor(crc, bit.lshift(tvb(i, 1):uint(), 8))
+        for _ = 1, 8 do
+            if bit.band(crc, 0x8000) ~= 0 then
+                crc = bit.band(bit.bxor(bit.lshift(crc, 1), 0x1021), 0xFFFF)
+            else
+                crc = bit.band(bit.lshift(crc, 1), 0xFFFF)
+            end
+        end
+    end
+    return crc
+end
+
+-- A postcard varint: value and length, or nil if it runs off the end
+local function varint(tvb, offset)
+    local value, shift = 0, 0
+    for i = offset, math.min(offset + 2, tvb:len() - 1) do
+        local byte = tvb(i, 1):uint()
+        value = value + bit.lshift(bit.band(byte, 0x7F), shift)
+        if byte < 0x80 then
+            return value, i - offset + 1
+        end
+        shift = shift + 7
+    end
+    return nil
+end
+
+function wk3.dissector(tvb, pinfo, root)
+    if tvb:len() < @PSEUDO_HEADER_LEN@ then
+        return 0
+    end
+    pinfo.cols.protocol = "wk3"
+    local tree = root:add(wk3, tvb())
+    local kind = tvb(0, 1):uint()
+    tree:add(f.kind, tvb(0, 1))
+    tree:add_le(f.rx_time, tvb(1, 4))
+    local data = tvb(@PSEUDO_HEADER_LEN@):tvb()
+
+    if kind == @KIND_LINE@ then
+        tree:add(f.line, tvb(@PSEUDO_HEADER_LEN@))
+        pinfo.cols.info = "Module: " .. data:raw():gsub("[\r\n]+$", "")
+        return tvb:len()
+    end
+
+    tree:add_le(f.rssi, tvb(5, 2))
+    tree:add_le(f.snr, tvb(7, 2))
+    if data:len() < 1 then
+        return tvb:len()
+    end
+
+    -- ACK and NACK are `[type][seq][node]`, without a header or CRC
+    local first = data(0, 1):uint()
+    if first == @ACK@ or first == @NACK@ then
+        tree:add(f.type, data(0, 1))
+        local info = types[first]
+        local seq, len = varint(data, 1)
+        if seq and data:len() > 1 + len then
+            tree:add(f.seq, data(1, len), seq)
+            tree:add(f.node, data(1 + len, 1))
+            info = info .. " N" .. data(1 + len, 1):uint() .. " #" .. seq
+            -- A live reading's ACK goes on with the sender's SlotGrant
+            if data:len() > 2 + len then
+                tree:add(f.slot, data(2 + len, 1))
+                info = info .. " slot " .. data(2 + len, 1):uint()
+            end
+        end
+        pinfo.cols.info = info
+        return tvb:len()
+    end
+
+    if data:len() < @HEADER_LEN@ + @CRC_LEN@ or first ~= @MAGIC@ then
+        tree:add_proto_expert_info(bad_header)
+        pinfo.cols.info = "Not a wk3 frame"
+        return tvb:len()
+    end
+    tree:add(f.magic, data(0, 1))
+    local version = tree:add(f.version, data(@OFFSET_VERSION@, 1))
+    tree:add(f.node, data(@OFFSET_NODE@, 1))
+    tree:add(f.hops, data(@OFFSET_HOPS@, 1))
+    tree:add(f.route_len, data(@OFFSET_HOPS@, 1))
+    -- A routed frame's relay addresses sit between the header and the body
+    local route_len = math.floor(data(@OFFSET_HOPS@, 1):uint() / 16) % 4
+    local body_at = @HEADER_LEN@ + route_len
+    local msg_type = data(@OFFSET_TYPE@, 1):uint()
+    tree:add(f.type, data(@OFFSET_TYPE@, 1))
+    local len_item = tree:add(f.len, data(@OFFSET_LEN@, 1))
+    local info = types[msg_type] or string.format("type 0x%02X", msg_type)
+    if data(@OFFSET_VERSION@, 1):uint() ~= @VERSION@ then
+        version:add_proto_expert_info(bad_header, "Unknown version")
+        info = info .. " [version]"
+    end
+
+    local body_len = data(@OFFSET_LEN@, 1):uint()
+    if body_at + body_len + @CRC_LEN@ ~= data:len() then
+        len_item:add_proto_expert_info(bad_header, "Length doesn't match the payload")
+        pinfo.cols.info = info .. " [length]"
+        return tvb:len()
+    end
+    for i = 0, route_len - 1 do
+        tree:add(f.route, data(@HEADER_LEN@ + i, 1))
+    end
+    if body_len > 0 then
+        tree:add(f.body, data(body_at, body_len))
+    end
+    if msg_type == @SENSOR_DATA@ and body_len > 0 then
+        local seq, len = varint(data, body_at)
+        if seq then
+            tree:add(f.seq, data(body_at, len), seq)
+            info = info .. " #" .. seq
+        end
+    end
+    local crc_at = body_at + body_len
+    local received = data(crc_at, 2):uint()
+    local calculated = crc16(data(0, crc_at):tvb())
+    tree:add(f.crc, data(crc_at, 2))
+    local ok = tree:add(f.crc_ok, data(crc_at, 2), received == calculated)
+    if received ~= calculated then
+        ok:add_proto_expert_info(crc_bad, string.format("CRC 0x%04X, expected 0x%04X", received, calculated))
+        info = info .. " [CRC error]"
+    end
+    pinfo.cols.info = info
+    return tvb:len()
+end
+
+DissectorTable.get("wtap_encap"):add((wtap_encaps or wtap).USER0, wk3)
+"#;
@@ -0,0 +1,138 @@
+//! Firmware upload to Node 1 through Node 2 (`wk3 <port> flash-remote`)
+//!
+//! The image goes to Node 2's port as `FirmwareBegin` and `FirmwareChunk`
+//! frames wrapped in stream records. The begin names the image's length
+//! and SHA-256; Node 2 signs it with the command key and sends it on over
+//! LoRa, and the chunks unchanged. Node 1 takes only a begin signed with a
+//! counter it hasn't seen, writes the chunks to the staging area in its
+//! SPI flash, stages the image only if it hashes to the signed digest, and
+//! answers each frame with a `FirmwareStatus`, which comes back through
+//! `stream on`. Every status names the offset Node 1 wants next, so one frame is in
+//! flight at a time and a lost frame or status only costs a resend.
+//!
+//! Node 1 keeps what it has received across resets. Running the same
+//! upload again, after a timeout or with the tool stopped part-way, picks
+//! up at the offset it reports; a different image starts over.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use wk3_protocol::auth::TAG_LEN;
+use wk3_protocol::stream::{self, Deframer};
+use wk3_protocol::{
+    FirmwareBegin, FirmwareChunk, FirmwareStatus, FIRMWARE_CHUNK_LEN, FIRMWARE_DIGEST_LEN, FIRMWARE_MAX_LEN,
+    FW_BAD_IMAGE, FW_IDLE, FW_RECEIVING, FW_STAGED, MSG_TYPE_FW_BEGIN, MSG_TYPE_FW_CHUNK, NODE_HOST,
+};
+
+use crate::{decode, Body, MAX_RECORD};
+
+/// Send `image`, resending a frame after `wait` without an answer, up to
+/// `tries` times in a row
+pub fn upload(port: &mut (impl Read + Write), image: &[u8], wait: Duration, tries: u32) -> io::Result<()> {
+    let len = u32::try_from(image.len())
+        .ok()
+        .filter(|&len| len <= FIRMWARE_MAX_LEN)
+        .ok_or_else(|| io::Error::other(format!("image of {} bytes is over {}", image.len(), FIRMWARE_MAX_LEN)))?;
+    let digest: [u8; FIRMWARE_DIGEST_LEN] = Sha256::digest(image).into();
+    let mut out = io::stdout().lock();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    writeln!(out, "{} bytes, SHA-256 {}, {} chunks", len, hex, len.div_ceil(FIRMWARE_CHUNK_LEN as u32))?;
+
+    // Node 2 fills in the counter and tag
+    let begin = FirmwareBegin { len, digest, counter: 0, tag: [0; TAG_LEN] };
+    port.write_all(b"stream on\r")?;
+    let result = exchange(port, &mut out, image, begin, wait, tries);
+    port.write_all(b"stream off\r")?;
+    writeln!(out)?;
+    result
+}
+
+fn exchange(
+    port: &mut (impl Read + Write),
+    out: &mut impl Write,
+    image: &[u8],
+    begin: FirmwareBegin,
+    wait: Duration,
+    tries: u32,
+) -> io::Result<()> {
+    let started = Instant::now();
+    // `None` until Node 1 says where to start
+    let mut next = None;
+    let mut sent_at = send(port, image, begin, next)?;
+    let mut tried = 1;
+    let mut deframer: Deframer<MAX_RECORD> = Deframer::new();
+    let mut buf = [0u8; 256];
+    let mut records = Vec::new();
+    loop {
+        let n = match port.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(e) => return Err(e),
+        };
+        for &byte in &buf[..n] {
+            if let Some(frame) = deframer.push(byte) {
+                decode(frame, &mut records);
+            }
+        }
+        for record in records.drain(..) {
+            let Body::Firmware(status) = record.body else { continue };
+            // Statuses left over from an upload of another image
+            let ours = status.len == begin.len && status.id == begin.id();
+            if !ours && matches!(status.state, FW_RECEIVING | FW_STAGED | FW_BAD_IMAGE) {
+                continue;
+            }
+            match status.state {
+                FW_RECEIVING => {
+                    progress(out, &status, started)?;
+                    next = Some(status.next);
+                }
+                // Node 1 reset since: begin again, which resumes
+                FW_IDLE => next = None,
+                FW_STAGED => {
+                    progress(out, &status, started)?;
+                    return Ok(());
+                }
+                _ => return Err(io::Error::other(format!("Node 1: {}", status.state_name()))),
+            }
+            sent_at = send(port, image, begin, next)?;
+            tried = 1;
+        }
+        if sent_at.elapsed() >= wait {
+            if tried == tries {
+                return Err(io::Error::other(format!(
+                    "no answer from Node 1 after {} tries; run again to resume",
+                    tries
+                )));
+            }
+            sent_at = send(port, image, begin, next)?;
+            tried += 1;
+        }
+    }
+}
+
+/// Write the begin record, or the chunk at `next`; when it went
+fn send(port: &mut impl Write, image: &[u8], begin: FirmwareBegin, next: Option<u32>) -> io::Result<Instant> {
+    let mut buf = [0u8; MAX_RECORD];
+    let record = match next {
+        None => stream::encode(NODE_HOST, MSG_TYPE_FW_BEGIN, &begin, &mut buf),
+        Some(offset) => {
+            let start = offset as usize;
+            let end = (start + FIRMWARE_CHUNK_LEN).min(image.len());
+            let chunk = FirmwareChunk { offset, data: &image[start..end] };
+            stream::encode(NODE_HOST, MSG_TYPE_FW_CHUNK, &chunk, &mut buf)
+        }
+    }
+    .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+    port.write_all(record)?;
+    port.flush()?;
+    Ok(Instant::now())
+}
+
+/// Rewrite the progress line
+fn progress(out: &mut impl Write, status: &FirmwareStatus, started: Instant) -> io::Result<()> {
+    let pct = if status.len > 0 { 100.0 * status.next as f32 / status.len as f32 } else { 100.0 };
+    let secs = started.elapsed().as_secs_f32();
+    write!(out, "\r{:>7}/{} bytes {:>5.1} % {:>6.0} s  {}  ", status.next, status.len, pct, secs, status.state_name())?;
+    out.flush()
+}
@@ -0,0 +1,99 @@
+//! Several receivers at once (`wk3 <port>,<port>... <command>`)
+//!
+//! With more than one Node 2 in range of Node 1, each hears some frames the
+//! others miss. Given several ports, `wk3` reads them all, keeps one copy
+//! of each frame and tags it with the gateway that heard it best: a poor
+//! man's multi-gateway LoRa network.
+//!
+//! Copies are the same reading when the node and sequence number match,
+//! live and stored readings kept apart; any other record when its decoded
+//! contents match. The first copy is held for `WINDOW` so the others can
+//! arrive, then passed on with the strongest RSSI (SNR breaking ties) and
+//! the number of gateways that heard it. Copies arriving after that,
+//! Node 1's retries included, are dropped for `MEMORY`. Frames that failed
+//! their CRC differ at each gateway and go straight through.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::{Body, Record};
+
+/// How long the first copy waits for the others
+pub const WINDOW: Duration = Duration::from_millis(500);
+/// How long a frame passed on is remembered
+const MEMORY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Key {
+    Reading { node: u8, seq: u16, stored: bool },
+    Other { node: u8, msg_type: u8, body: String },
+}
+
+impl Key {
+    /// `None` for records that aren't deduplicated
+    fn of(record: &Record) -> Option<Self> {
+        let node = record.link?.node;
+        match &record.body {
+            Body::Bad(_) => None,
+            Body::Reading { packet, stored } => {
+                Some(Key::Reading { node, seq: packet.seq_num, stored: stored.is_some() })
+            }
+            body => Some(Key::Other { node, msg_type: record.msg_type, body: format!("{:?}", body) }),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Merger {
+    /// First copies waiting out the window, oldest first
+    held: VecDeque<(Key, Instant, Record)>,
+    /// Frames passed on, oldest first
+    seen: VecDeque<(Key, Instant)>,
+}
+
+impl Merger {
+    /// Take a record from `gateway`; one that isn't deduplicated comes
+    /// straight back
+    pub fn add(&mut self, gateway: usize, mut record: Record, now: Instant) -> Option<Record> {
+        let Some(link) = &mut record.link else { return Some(record) };
+        link.gateway = Some(gateway);
+        let Some(key) = Key::of(&record) else { return Some(record) };
+        if self.seen.iter().any(|(k, _)| *k == key) {
+            return None;
+        }
+        match self.held.iter_mut().find(|(k, _, _)| *k == key) {
+            Some((_, _, best)) => {
+                let (Some(new), Some(old)) = (record.link, &mut best.link) else { return None };
+                let heard_by = old.heard_by + 1;
+                if (new.rssi, new.snr) > (old.rssi, old.snr) {
+                    *best = record;
+                }
+                if let Some(link) = &mut best.link {
+                    link.heard_by = heard_by;
+                }
+            }
+            None => self.held.push_back((key, now, record)),
+        }
+        None
+    }
+
+    /// Records whose window has closed, in the order they were first heard
+    pub fn due(&mut self, now: Instant) -> Vec<Record> {
+        while self.seen.front().is_some_and(|(_, at)| now.duration_since(*at) >= MEMORY) {
+            self.seen.pop_front();
+        }
+        let mut out = Vec::new();
+        while self.held.front().is_some_and(|(_, at, _)| now.duration_since(*at) >= WINDOW) {
+            if let Some((key, _, record)) = self.held.pop_front() {
+                self.seen.push_back((key, now));
+                out.push(record);
+            }
+        }
+        out
+    }
+
+    /// Everything still held, at the end of a run
+    pub fn flush(&mut self) -> Vec<Record> {
+        self.held.drain(..).map(|(_, _, record)| record).collect()
+    }
+}
@@ -0,0 +1,48 @@
+//! InfluxDB line protocol
+//!
+//! One line per point, `measurement,tag=v,... field=v,... timestamp`, which
+//! Telegraf's `inputs.execd` or `influx write` take as they are. Integers
+//! carry the `i` suffix so the field types stay fixed from the first point;
+//! timestamps are whole seconds, so set `precision = "1s"`.
+
+use std::fmt::Write;
+
+pub struct Line {
+    text: String,
+    fields: usize,
+}
+
+impl Line {
+    /// Start a point; tag keys and values must not need escaping
+    pub fn new(measurement: &str, tags: &[(&str, &str)]) -> Self {
+        let mut text = String::from(measurement);
+        for (key, value) in tags {
+            let _ = write!(text, ",{}={}", key, value);
+        }
+        Self { text, fields: 0 }
+    }
+
+    pub fn float(&mut self, key: &str, value: f32) -> &mut Self {
+        // NaN and infinity aren't valid field values
+        if value.is_finite() {
+            self.field(key, format_args!("{}", value));
+        }
+        self
+    }
+
+    pub fn int(&mut self, key: &str, value: i64) -> &mut Self {
+        self.field(key, format_args!("{}i", value))
+    }
+
+    fn field(&mut self, key: &str, value: std::fmt::Arguments) -> &mut Self {
+        let sep = if self.fields == 0 { ' ' } else { ',' };
+        let _ = write!(self.text, "{}{}={}", sep, key, value);
+        self.fields += 1;
+        self
+    }
+
+    /// The finished line, without its newline
+    pub fn finish(&self, timestamp_s: u64) -> String {
+        format!("{} {}", self.text, timestamp_s)
+    }
+}
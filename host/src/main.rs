@@ -0,0 +1,956 @@
+//! `wk3`: read the nodes' framed records on a PC
+//!
+//! Opens a node's ST-LINK serial port, asks for its binary records and
+//! decodes them with the firmware's own `wk3-protocol` crate:
+//!
+//! - on Node 2, `stream on` forwards every payload it receives as a
+//!   `RadioFrame`, CRC errors included;
+//! - on Node 1, `export` sends its backlog and crash log once.
+//!
+//! Shell text on the same port (prompts, replies) is skipped by the
+//! deframer. Subcommands:
+//!
+//! ```text
+//! wk3 /dev/ttyACM0 watch                 one line per record
+//! wk3 /dev/ttyACM0 dump --csv > log.csv  readings as CSV
+//! wk3 /dev/ttyACM1 dump --export --csv   Node 1's export as CSV
+//! wk3 /dev/ttyACM0 stats --seconds 600   frame counts, CRC errors, loss, RSSI
+//! wk3 /dev/ttyACM0 tui                   live dashboard: gauges, RSSI/SNR, loss, records
+//! wk3 /dev/ttyACM0 mqtt                  topic<TAB>payload lines to republish
+//! wk3 /dev/ttyACM0 influx | telegraf ...  InfluxDB line protocol
+//! wk3 /dev/ttyACM0 log                   Node 1's tunnelled log lines
+//! wk3 /dev/ttyACM0 send interval 60      signed command to Node 1, via Node 2
+//! wk3 /dev/ttyACM0 pcap cap.pcapng       raw frames for Wireshark
+//! wk3 /dev/ttyACM0 replay cap.pcapng     feed a capture back into Node 2
+//! wk3 /dev/ttyACM0 flash-remote fw.bin   stage a firmware image on Node 1, via Node 2
+//! wk3 /dev/ttyACM0,/dev/ttyACM2 watch    two Node 2s, one copy of each frame
+//! ```
+//!
+//! `--prometheus-textfile <path>` also keeps a metrics file up to date for
+//! node-exporter, whatever the subcommand.
+//!
+//! Build it for the host; the workspace default target is the STM32:
+//! `cargo run -p wk3-host --target x86_64-unknown-linux-gnu -- ...`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::{Parser, Subcommand};
+use gateways::Merger;
+use influx::Line;
+use prometheus::Textfile;
+use wk3_protocol::mqtt;
+use wk3_protocol::stream::{self, Deframer, ExportBegin, ExportEnd, RadioFrame};
+use wk3_protocol::{
+    decode_frame, frame_node, tlv, BroadcastAck, CommandResult, ConfigAck, ConfigReport, CrashReport, FirmwareStatus, FrameError, LinkStats, LogBatch, ReplayBatch, SensorDataPacket,
+    StoredReading, COMMAND_DONE, MSG_TYPE_BROADCAST_ACK, MSG_TYPE_COMMAND_RESULT, MSG_TYPE_CONFIG_ACK, MSG_TYPE_CONFIG_REPORT, MSG_TYPE_CRASH_REPORT, MSG_TYPE_FW_STATUS, MSG_TYPE_LINK_STATS, MSG_TYPE_LOG,
+    MSG_TYPE_REPLAY_BATCH, MSG_TYPE_SENSOR_DATA, MSG_TYPE_STORED, NODE_1,
+};
+
+mod dissector;
+mod flash;
+mod gateways;
+mod influx;
+mod pcapng;
+mod prometheus;
+mod tui;
+
+/// Longest record accepted: a full radio payload with its link figures
+const MAX_RECORD: usize = 300;
+/// Spacing of `replay --fast`, and of frames whose capture times went
+/// backwards (Node 2 restarted)
+const FAST_GAP: Duration = Duration::from_millis(100);
+/// `tlv::FLAG_RAW_TH` readings carry SHT31 ticks (see the firmware's `raw`)
+const TICKS: f32 = 65535.0;
+
+#[derive(Parser)]
+#[command(name = "wk3", about = "Decode the nodes' binary records from a serial port")]
+struct Cli {
+    /// Serial port, e.g. /dev/ttyACM0 or COM5; several Node 2s as
+    /// /dev/ttyACM0,/dev/ttyACM2 (see gateways.rs)
+    #[arg(value_name = "PORT", value_delimiter = ',', num_args = 1, required = true)]
+    ports: Vec<String>,
+    #[arg(long, default_value_t = 115_200)]
+    baud: u32,
+    /// Also keep a node-exporter textfile of last values and counters here
+    /// (e.g. /var/lib/node_exporter/textfile_collector/wk3.prom)
+    #[arg(long, value_name = "PATH")]
+    prometheus_textfile: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print each record as it arrives
+    Watch,
+    /// Print the readings received
+    Dump {
+        /// CSV with a header line instead of text
+        #[arg(long)]
+        csv: bool,
+        /// Ask Node 1 for an `export` and stop at its end record
+        #[arg(long)]
+        export: bool,
+    },
+    /// Count frames, CRC errors and lost sequence numbers, then summarise
+    Stats {
+        /// Stop after this long; otherwise at end of input
+        #[arg(long)]
+        seconds: Option<u64>,
+    },
+    /// Live dashboard: gauges for the last reading, RSSI and SNR history,
+    /// loss and a scrolling record log; `q` quits
+    Tui,
+    /// Print live readings as `topic<TAB>payload` lines, as Node 2's
+    /// `mqtt on` does
+    Mqtt,
+    /// Print readings as InfluxDB line protocol, timestamped by this PC;
+    /// stored readings are back-dated by their age
+    Influx,
+    /// Print the log lines Node 1 sends over LoRa while its `tunnel` is on
+    Log,
+    /// Have Node 2 sign and send a command to Node 1, then wait for its
+    /// answer; Node 2 holds the key, so this needs no secrets
+    Send {
+        #[command(subcommand)]
+        command: Remote,
+        /// Give up waiting for the answer after this long
+        #[arg(long, default_value_t = 10)]
+        seconds: u64,
+    },
+    /// Capture every frame Node 2 hears, CRC errors and stray module
+    /// lines included (`stream tap`), as pcapng for Wireshark
+    Pcap {
+        file: PathBuf,
+        /// Also write the Lua dissector Wireshark needs for the capture
+        #[arg(long, value_name = "LUA")]
+        dissector: Option<PathBuf>,
+    },
+    /// Send a `pcap` capture back to Node 2 as if its radio heard it again;
+    /// needs firmware built with `--features ingest`
+    Replay {
+        file: PathBuf,
+        /// Don't keep the captured spacing, just leave Node 2 time to parse
+        #[arg(long)]
+        fast: bool,
+    },
+    /// Stage a firmware image in Node 1's SPI flash over the radio, through
+    /// Node 2; run it again to resume an upload that stopped
+    FlashRemote {
+        file: PathBuf,
+        /// Resend a frame after this long without an answer
+        #[arg(long, default_value_t = 5)]
+        seconds: u64,
+        /// Give up after this many sends of one frame
+        #[arg(long, default_value_t = 10)]
+        tries: u32,
+    },
+}
+
+/// `n1 ...` commands in Node 2's shell
+#[derive(Subcommand, Clone, Copy)]
+enum Remote {
+    /// Seconds between readings
+    Interval { seconds: u16 },
+    /// Cap on Node 1's TX power in dBm, saved to flash
+    Txpower { dbm: u8 },
+    Reboot,
+    /// Several settings at once, saved to flash; Node 1 answers with the
+    /// ones in force, and with nothing given just answers
+    Set {
+        /// Seconds between readings
+        #[arg(long)]
+        interval: Option<u16>,
+        /// Cap on the TX power in dBm
+        #[arg(long)]
+        txpower: Option<u8>,
+        /// Send-on-delta on (`true`) or off (`false`)
+        #[arg(long)]
+        delta: Option<bool>,
+        /// Temperature change that sends a reading, °C
+        #[arg(long)]
+        temp: Option<f32>,
+        /// Humidity change that sends a reading, % RH
+        #[arg(long)]
+        hum: Option<f32>,
+        /// Relative gas resistance change that sends a reading, %
+        #[arg(long)]
+        gas: Option<u16>,
+        /// Longest silence before a reading is sent regardless, seconds
+        #[arg(long)]
+        beat: Option<u16>,
+    },
+    /// Ask for Node 1's firmware version, region, radio settings, power
+    /// and thresholds, changing nothing
+    Config,
+}
+
+impl Remote {
+    fn shell_line(self) -> String {
+        match self {
+            Remote::Interval { seconds } => format!("n1 interval {}\r", seconds),
+            Remote::Txpower { dbm } => format!("n1 txpower {}\r", dbm),
+            Remote::Reboot => "n1 reboot\r".into(),
+            Remote::Config => "n1 config\r".into(),
+            Remote::Set { interval, txpower, delta, temp, hum, gas, beat } => {
+                let mut line = String::from("n1 set");
+                let words = [
+                    ("interval", interval.map(|s| s.to_string())),
+                    ("txpower", txpower.map(|dbm| dbm.to_string())),
+                    ("delta", delta.map(|on| if on { "on" } else { "off" }.to_string())),
+                    ("temp", temp.map(|t| t.to_string())),
+                    ("hum", hum.map(|h| h.to_string())),
+                    ("gas", gas.map(|g| g.to_string())),
+                    ("beat", beat.map(|s| s.to_string())),
+                ];
+                for (name, value) in words {
+                    if let Some(value) = value {
+                        line += &format!(" {} {}", name, value);
+                    }
+                }
+                line + "\r"
+            }
+        }
+    }
+}
+
+/// Link figures Node 2 had for a frame
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    /// Sender's address from the frame header
+    node: u8,
+    rx_time_ms: u32,
+    rssi: i16,
+    snr: i16,
+    /// With several ports, the one that heard it best and how many did
+    gateway: Option<usize>,
+    heard_by: u8,
+}
+
+#[derive(Debug)]
+enum Body {
+    /// A reading; `stored` for backfill, replay and export, with its age
+    /// (`None` if from before Node 1's last reset)
+    Reading { packet: SensorDataPacket, stored: Option<Option<u32>> },
+    Crash { line: u32, file: String, message: String },
+    /// Node 1's answer to a remote command
+    Result(CommandResult),
+    /// Node 1's answer to `n1 set`, with the settings in force
+    Config(ConfigAck),
+    /// A node's answer to `n1 config` or `all config`
+    Report(ConfigReport),
+    /// Node 1's tunnelled log lines
+    Log(LogBatch),
+    /// Where a `flash-remote` upload stands on Node 1
+    Firmware(FirmwareStatus),
+    /// A node's answer to an `all ack ...` broadcast
+    BroadcastAck(BroadcastAck),
+    /// A node's own counts of how its link is going
+    LinkStats(LinkStats),
+    /// A frame this tool only counts
+    Other(u8),
+    /// A forwarded payload that failed its CRC or didn't decode
+    Bad(FrameError),
+    Begin(ExportBegin),
+    End(ExportEnd),
+}
+
+#[derive(Debug)]
+struct Record {
+    /// `None` for records from Node 1's export
+    link: Option<Link>,
+    msg_type: u8,
+    body: Body,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(&cli) {
+        eprintln!("wk3: {}: {}", cli.ports.join(","), e);
+        std::process::exit(1);
+    }
+}
+
+/// What a port's reader thread has for the main loop
+enum Input {
+    /// A checked record from gateway `.0`
+    Frame(usize, Vec<u8>),
+    /// The port closed, or failed
+    Closed(io::Result<()>),
+}
+
+fn run(cli: &Cli) -> io::Result<()> {
+    let mut ports = Vec::new();
+    for name in &cli.ports {
+        let port = serialport::new(name, cli.baud).timeout(Duration::from_millis(200)).open();
+        ports.push(port.map_err(|e| io::Error::other(format!("{}: {}", name, e)))?);
+    }
+    let export = matches!(cli.command, Command::Dump { export: true, .. });
+    let single = export || matches!(cli.command, Command::Send { .. } | Command::Replay { .. } | Command::FlashRemote { .. });
+    if single && ports.len() > 1 {
+        return Err(io::Error::other("this subcommand takes a single port"));
+    }
+    if let Command::Replay { file, fast } = &cli.command {
+        return replay(&mut ports[0], file, *fast);
+    }
+    if let Command::FlashRemote { file, seconds, tries } = &cli.command {
+        return flash::upload(&mut ports[0], &fs::read(file)?, Duration::from_secs(*seconds), *tries);
+    }
+    let start: &[u8] = match cli.command {
+        _ if export => b"export\r",
+        Command::Pcap { .. } => b"stream tap\r",
+        _ => b"stream on\r",
+    };
+    for port in &mut ports {
+        port.write_all(start)?;
+    }
+    if let Command::Send { command, .. } = cli.command {
+        ports[0].write_all(command.shell_line().as_bytes())?;
+    }
+
+    let deadline = match cli.command {
+        Command::Stats { seconds: Some(s) } | Command::Send { seconds: s, .. } => {
+            Some(Instant::now() + Duration::from_secs(s))
+        }
+        _ => None,
+    };
+    let mut answer = None;
+    let mut out = io::stdout().lock();
+    let mut stats = Stats::default();
+    let mut textfile = cli.prometheus_textfile.clone().map(Textfile::new);
+    if let Command::Dump { csv: true, .. } = cli.command {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+    let mut capture = None;
+    let mut dashboard = match cli.command {
+        Command::Tui => Some(tui::Dashboard::new()?),
+        _ => None,
+    };
+    if let Command::Pcap { file, dissector } = &cli.command {
+        if let Some(lua) = dissector {
+            fs::write(lua, dissector::lua())?;
+        }
+        capture = Some(pcapng::Writer::new(BufWriter::new(File::create(file)?))?);
+    }
+
+    // One reader per port; with several, copies of a frame are merged
+    let (tx, rx) = mpsc::channel();
+    let serial_errors = Arc::new(AtomicU32::new(0));
+    for (gateway, port) in ports.iter().enumerate() {
+        let reader = port.try_clone().map_err(io::Error::from)?;
+        let (tx, serial_errors) = (tx.clone(), serial_errors.clone());
+        thread::spawn(move || read_port(reader, gateway, &tx, &serial_errors));
+    }
+    drop(tx);
+    let mut merger = (ports.len() > 1).then(Merger::default);
+    let mut open = ports.len();
+
+    let mut records = Vec::new();
+    'read: while deadline.is_none_or(|d| Instant::now() < d) {
+        let mut ready = Vec::new();
+        // Nothing for a while; the dashboard still redraws
+        match rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(Input::Frame(gateway, frame)) => {
+                if let Some(capture) = &mut capture {
+                    capture.add(&frame)?;
+                }
+                decode(&frame, &mut records);
+                for record in records.drain(..) {
+                    match &mut merger {
+                        Some(merger) => ready.extend(merger.add(gateway, record, Instant::now())),
+                        None => ready.push(record),
+                    }
+                }
+            }
+            Ok(Input::Closed(result)) => {
+                result?;
+                open -= 1;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => open = 0,
+        }
+        if let Some(merger) = &mut merger {
+            ready.extend(if open == 0 { merger.flush() } else { merger.due(Instant::now()) });
+        }
+        for record in ready {
+            match &cli.command {
+                Command::Watch => writeln!(out, "{}", describe(&record))?,
+                Command::Dump { csv, .. } => {
+                    if let Body::Reading { packet, stored } = &record.body {
+                        if *csv {
+                            writeln!(out, "{}", csv_row(record.link, packet, *stored))?;
+                        } else {
+                            writeln!(out, "{}", describe(&record))?;
+                        }
+                    }
+                }
+                Command::Stats { .. } => stats.add(&record),
+                Command::Mqtt => {
+                    if let (Body::Reading { packet, stored: None }, Some(link)) = (&record.body, record.link) {
+                        write!(out, "{}", mqtt_lines(packet, link))?;
+                    }
+                }
+                Command::Influx => {
+                    if let Body::Reading { packet, stored } = &record.body {
+                        if let Some(line) = influx_line(packet, record.link, *stored) {
+                            writeln!(out, "{}", line)?;
+                        }
+                    }
+                }
+                Command::Log => {
+                    if let Body::Log(batch) = &record.body {
+                        write!(out, "{}", log_lines(batch))?;
+                    }
+                }
+                Command::Send { .. } => {
+                    match &record.body {
+                        Body::Result(result) => {
+                            writeln!(out, "{}", describe(&record))?;
+                            answer = Some(*result);
+                        }
+                        Body::Config(ack) => {
+                            writeln!(out, "{}", describe(&record))?;
+                            answer = Some(CommandResult { counter: ack.counter, status: ack.status });
+                        }
+                        Body::Report(report) => {
+                            writeln!(out, "{}", describe(&record))?;
+                            answer = Some(CommandResult { counter: report.counter, status: report.status });
+                        }
+                        _ => {}
+                    }
+                }
+                Command::Tui | Command::Pcap { .. } | Command::Replay { .. } | Command::FlashRemote { .. } => {}
+            }
+            if let Some(textfile) = &mut textfile {
+                textfile.add(&record);
+            }
+            if let Some(dashboard) = &mut dashboard {
+                dashboard.add(&record);
+            }
+            if (export && matches!(record.body, Body::End(_))) || answer.is_some() {
+                break 'read;
+            }
+        }
+        if let Some(textfile) = &mut textfile {
+            textfile.write(serial_errors.load(Ordering::Relaxed))?;
+        }
+        if let Some(capture) = &mut capture {
+            capture.flush()?;
+        }
+        if let Some(dashboard) = &mut dashboard {
+            if !dashboard.update(serial_errors.load(Ordering::Relaxed))? {
+                break;
+            }
+        }
+        out.flush()?;
+        if open == 0 {
+            break;
+        }
+    }
+    drop(dashboard);
+
+    if !export {
+        for port in &mut ports {
+            port.write_all(b"stream off\r")?;
+        }
+    }
+    if let Some(capture) = &capture {
+        eprintln!("{} packets captured", capture.packets());
+    }
+    if let Command::Send { .. } = cli.command {
+        match answer {
+            Some(CommandResult { status: COMMAND_DONE, .. }) => {}
+            Some(result) => return Err(io::Error::other(format!("Node 1 refused: {}", result.status_name()))),
+            None => return Err(io::Error::other("no answer from Node 1")),
+        }
+    }
+    if let Command::Stats { .. } = cli.command {
+        stats.stream_errors = serial_errors.load(Ordering::Relaxed);
+        stats.print(&mut out)?;
+    }
+    Ok(())
+}
+
+/// Pass `port`'s checked records to the main loop until it closes, the
+/// main loop is gone, or a read fails
+fn read_port(mut port: impl Read, gateway: usize, tx: &Sender<Input>, serial_errors: &AtomicU32) {
+    let mut deframer: Deframer<MAX_RECORD> = Deframer::new();
+    let mut buf = [0u8; 256];
+    let result = loop {
+        let n = match port.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => break Err(e),
+        };
+        let errors = deframer.errors();
+        for &byte in &buf[..n] {
+            if let Some(frame) = deframer.push(byte) {
+                if tx.send(Input::Frame(gateway, frame.to_vec())).is_err() {
+                    return;
+                }
+            }
+        }
+        serial_errors.fetch_add(deframer.errors() - errors, Ordering::Relaxed);
+    };
+    let _ = tx.send(Input::Closed(result));
+}
+
+/// Write a capture's records to Node 2 in order, spaced as they arrived
+fn replay(port: &mut impl Write, file: &Path, fast: bool) -> io::Result<()> {
+    let packets = pcapng::read(&fs::read(file)?)?;
+    let mut out = io::stdout().lock();
+    let mut buf = [0u8; MAX_RECORD];
+    let mut last_ms = None;
+    for (i, packet) in packets.iter().enumerate() {
+        if let Some(last_ms) = last_ms {
+            let gap = match packet.rx_time_ms.checked_sub(last_ms) {
+                Some(ms) if !fast => Duration::from_millis(ms.into()),
+                _ => FAST_GAP,
+            };
+            std::thread::sleep(gap);
+        }
+        last_ms = Some(packet.rx_time_ms);
+        let record = packet.record(&mut buf).map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        port.write_all(record)?;
+        port.flush()?;
+        let kind = if packet.kind == pcapng::KIND_LINE { "line" } else { "frame" };
+        writeln!(out, "{}/{} {} of {} bytes", i + 1, packets.len(), kind, packet.data.len())?;
+    }
+    Ok(())
+}
+
+/// The readings and other messages in one checked record
+fn decode(frame: &[u8], out: &mut Vec<Record>) {
+    let Ok((msg_type, body)) = decode_frame(frame) else { return };
+    match msg_type {
+        stream::RADIO_FRAME => {
+            let Ok(radio) = postcard::from_bytes::<RadioFrame>(body) else { return };
+            let link = Some(Link {
+                node: frame_node(radio.payload).unwrap_or(NODE_1),
+                rx_time_ms: radio.rx_time_ms,
+                rssi: radio.rssi,
+                snr: radio.snr,
+                gateway: None,
+                heard_by: 1,
+            });
+            match decode_frame(radio.payload) {
+                Ok((msg_type, body)) => decode_message(link, msg_type, body, out),
+                Err(e) => out.push(Record { link, msg_type: 0, body: Body::Bad(e) }),
+            }
+        }
+        stream::EXPORT_BEGIN => {
+            if let Ok(begin) = postcard::from_bytes(body) {
+                out.push(Record { link: None, msg_type, body: Body::Begin(begin) });
+            }
+        }
+        stream::EXPORT_END => {
+            if let Ok(end) = postcard::from_bytes(body) {
+                out.push(Record { link: None, msg_type, body: Body::End(end) });
+            }
+        }
+        _ => decode_message(None, msg_type, body, out),
+    }
+}
+
+/// A radio-protocol message; a replay batch gives one record per reading
+fn decode_message(link: Option<Link>, msg_type: u8, body: &[u8], out: &mut Vec<Record>) {
+    let bad = |link| Record { link, msg_type, body: Body::Bad(FrameError::Decode) };
+    match msg_type {
+        MSG_TYPE_SENSOR_DATA => match postcard::from_bytes(body) {
+            Ok(packet) => out.push(Record { link, msg_type, body: Body::Reading { packet, stored: None } }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_STORED => match postcard::from_bytes::<StoredReading>(body) {
+            Ok(s) => out.push(Record { link, msg_type, body: Body::Reading { packet: s.reading, stored: Some(s.age_s) } }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_REPLAY_BATCH => match postcard::from_bytes::<ReplayBatch>(body) {
+            Ok(batch) => out.extend(batch.readings.into_iter().map(|s| Record {
+                link,
+                msg_type,
+                body: Body::Reading { packet: s.reading, stored: Some(s.age_s) },
+            })),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_CRASH_REPORT => match postcard::from_bytes::<CrashReport>(body) {
+            Ok(r) => out.push(Record {
+                link,
+                msg_type,
+                body: Body::Crash { line: r.line, file: r.file.into(), message: r.message.into() },
+            }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_COMMAND_RESULT => match postcard::from_bytes(body) {
+            Ok(result) => out.push(Record { link, msg_type, body: Body::Result(result) }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_CONFIG_ACK => match postcard::from_bytes(body) {
+            Ok(ack) => out.push(Record { link, msg_type, body: Body::Config(ack) }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_CONFIG_REPORT => match postcard::from_bytes(body) {
+            Ok(report) => out.push(Record { link, msg_type, body: Body::Report(report) }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_LOG => match postcard::from_bytes(body) {
+            Ok(batch) => out.push(Record { link, msg_type, body: Body::Log(batch) }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_FW_STATUS => match postcard::from_bytes(body) {
+            Ok(status) => out.push(Record { link, msg_type, body: Body::Firmware(status) }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_BROADCAST_ACK => match postcard::from_bytes(body) {
+            Ok(ack) => out.push(Record { link, msg_type, body: Body::BroadcastAck(ack) }),
+            Err(_) => out.push(bad(link)),
+        },
+        MSG_TYPE_LINK_STATS => match postcard::from_bytes(body) {
+            Ok(stats) => out.push(Record { link, msg_type, body: Body::LinkStats(stats) }),
+            Err(_) => out.push(bad(link)),
+        },
+        _ => out.push(Record { link, msg_type, body: Body::Other(msg_type) }),
+    }
+}
+
+/// Temperature in °C and humidity in %, scaled or raw
+fn temperature_humidity(packet: &SensorDataPacket) -> (f32, f32) {
+    let flags = tlv::find_u8(&packet.tlv, tlv::FLAGS).unwrap_or(0);
+    if flags & tlv::FLAG_RAW_TH != 0 {
+        (-45.0 + 175.0 * packet.temperature as u16 as f32 / TICKS, 100.0 * packet.humidity as f32 / TICKS)
+    } else {
+        (packet.temperature as f32 / 10.0, packet.humidity as f32 / 100.0)
+    }
+}
+
+fn describe(record: &Record) -> String {
+    let mut link = match record.link {
+        Some(l @ Link { gateway: Some(g), .. }) => {
+            format!("[gw{} of {} {:>10} ms {:>4} dBm {:>3} dB] ", g + 1, l.heard_by, l.rx_time_ms, l.rssi, l.snr)
+        }
+        Some(l) => format!("[{:>10} ms {:>4} dBm {:>3} dB] ", l.rx_time_ms, l.rssi, l.snr),
+        None => String::new(),
+    };
+    // Node 1 is the usual sender; any other is named
+    if let Some(Link { node, .. }) = record.link.filter(|l| l.node != NODE_1) {
+        link += &format!("N{} ", node);
+    }
+    let text = match &record.body {
+        Body::Reading { packet, stored } => {
+            let (t, h) = temperature_humidity(packet);
+            let mut s = format!("#{} {:.1} °C {:.1} % {} Ω", packet.seq_num, t, h, packet.gas_resistance);
+            if let Some(iaq) = packet.iaq {
+                s += &format!(" IAQ {}", iaq);
+            }
+            if let Some(co2) = tlv::find_u16(&packet.tlv, tlv::CO2_PPM) {
+                s += &format!(" CO2 {} ppm", co2);
+            }
+            if let Some(mv) = tlv::find_u16(&packet.tlv, tlv::BATTERY_MV) {
+                s += &format!(" {} mV", mv);
+            }
+            match stored {
+                Some(Some(age_s)) => s += &format!(" (stored, {} s old)", age_s),
+                Some(None) => s += " (stored, before a reset)",
+                None => {}
+            }
+            s
+        }
+        Body::Crash { line, file, message } => format!("CRASH at {}:{}: {}", file, line, message),
+        Body::Result(r) => format!("command #{}: {}", r.counter, r.status_name()),
+        Body::Config(c) => format!(
+            "config #{}: {}, interval {} s, TX power cap {} dBm, delta {} ({:.1} °C {:.1} % gas {} % beat {} s)",
+            c.counter,
+            c.reason(),
+            c.interval_s,
+            c.tx_power_dbm,
+            if c.delta_enabled { "on" } else { "off" },
+            c.delta_temp_deci_c as f32 / 10.0,
+            c.delta_humidity_deci_pct as f32 / 10.0,
+            c.delta_gas_percent,
+            c.delta_heartbeat_s
+        ),
+        Body::Report(r) if r.status != COMMAND_DONE => format!("config report #{}: {}", r.counter, r.status_name()),
+        Body::Report(r) => format!(
+            "config report #{}: node {} v{}.{}.{} region {}, net {} {} MHz SF{} BW{}k CR4/{} preamble {}, {} dBm (cap {}), duty {}, {} hop channels, interval {} s, delta {} ({:.1} °C {:.1} % gas {} % beat {} s)",
+            r.counter,
+            r.address,
+            r.firmware[0],
+            r.firmware[1],
+            r.firmware[2],
+            r.region_name(),
+            r.network_id,
+            r.band_mhz,
+            r.spreading_factor,
+            r.bandwidth_khz,
+            4 + r.coding_rate,
+            r.preamble,
+            r.tx_power_dbm,
+            r.tx_power_limit_dbm,
+            match r.duty_permille {
+                0 => "none".to_string(),
+                p => format!("{}.{}%", p / 10, p % 10),
+            },
+            r.hop_channels,
+            r.interval_s,
+            if r.delta_enabled { "on" } else { "off" },
+            r.delta_temp_deci_c as f32 / 10.0,
+            r.delta_humidity_deci_pct as f32 / 10.0,
+            r.delta_gas_percent,
+            r.delta_heartbeat_s
+        ),
+        Body::Log(batch) => log_lines(batch).trim_end().to_string(),
+        Body::Firmware(f) => format!("firmware: {} of {} bytes, {}", f.next, f.len, f.state_name()),
+        Body::BroadcastAck(ack) => format!("broadcast #{}: {}", ack.id, ack.status_name()),
+        Body::LinkStats(s) => format!(
+            "link stats: sent {}, ACKed {}, retries {}, CRC failures {}, UART errors {}, up {} s",
+            s.frames_sent, s.acks_received, s.retries, s.crc_failures, s.uart_errors, s.uptime_s
+        ),
+        Body::Other(t) => format!("type {} frame", t),
+        Body::Bad(e) => format!("bad frame: {:?}", e),
+        Body::Begin(b) => format!(
+            "export: node up {} s, {} readings, {} crashes",
+            b.uptime_s, b.readings, b.crashes
+        ),
+        Body::End(e) => format!("export done, {} records", e.records),
+    };
+    link + &text
+}
+
+/// One line per tunnelled log line, and one for any Node 1 dropped
+fn log_lines(batch: &LogBatch) -> String {
+    let mut s = String::new();
+    if batch.dropped > 0 {
+        s += &format!("N1 log: {} lines dropped\n", batch.dropped);
+    }
+    for line in &batch.lines {
+        s += &format!("N1 {:>7} s {:<5} {}\n", line.uptime_s, line.level_name(), line.text);
+    }
+    s
+}
+
+/// The lines `mqtt::write_lines` gives for a live reading
+fn mqtt_lines(packet: &SensorDataPacket, link: Link) -> String {
+    let (temperature_c, humidity_pct) = temperature_humidity(packet);
+    let probes_c = probes_c(packet);
+    let soil_pct = soil_pct(packet);
+    let reading = mqtt::Reading {
+        seq: packet.seq_num,
+        temperature_c,
+        humidity_pct,
+        gas_ohms: packet.gas_resistance,
+        iaq: packet.iaq,
+        co2_ppm: tlv::find_u16(&packet.tlv, tlv::CO2_PPM),
+        battery_mv: tlv::find_u16(&packet.tlv, tlv::BATTERY_MV),
+        probes_c: &probes_c,
+        soil_pct: &soil_pct,
+        rssi: link.rssi,
+        snr: link.snr,
+    };
+    let mut lines = String::new();
+    let _ = mqtt::write_lines(&mut lines, &format!("n{}", link.node), &reading);
+    lines
+}
+
+/// A reading as a line of `env` measurements; `None` for a stored reading
+/// of unknown age, which can't be placed in time
+fn influx_line(packet: &SensorDataPacket, link: Option<Link>, stored: Option<Option<u32>>) -> Option<String> {
+    let now_s = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (timestamp_s, source) = match stored {
+        None => (now_s, "live"),
+        Some(age_s) => (now_s.saturating_sub(u64::from(age_s?)), "stored"),
+    };
+    let (temperature_c, humidity_pct) = temperature_humidity(packet);
+    let node = link.map_or(NODE_1, |l| l.node).to_string();
+    let gateway = link.and_then(|l| l.gateway).map(|g| (g + 1).to_string());
+    let mut line = match &gateway {
+        Some(g) => Line::new("env", &[("node", node.as_str()), ("source", source), ("gateway", g)]),
+        None => Line::new("env", &[("node", node.as_str()), ("source", source)]),
+    };
+    line.float("temp", temperature_c)
+        .float("rh", humidity_pct)
+        .int("gas", packet.gas_resistance.into())
+        .int("seq", packet.seq_num.into());
+    if let Some(iaq) = packet.iaq {
+        line.int("iaq", iaq.into());
+    }
+    if let Some(co2) = tlv::find_u16(&packet.tlv, tlv::CO2_PPM) {
+        line.int("co2", co2.into());
+    }
+    if let Some(mv) = tlv::find_u16(&packet.tlv, tlv::BATTERY_MV) {
+        line.int("battery_mv", mv.into());
+    }
+    for (i, c) in probes_c(packet).into_iter().enumerate() {
+        if let Some(c) = c {
+            line.float(&format!("probe{}", i + 1), c);
+        }
+    }
+    for (i, pct) in soil_pct(packet).into_iter().enumerate() {
+        if let Some(pct) = pct {
+            line.int(&format!("soil{}", i + 1), pct.into());
+        }
+    }
+    if let Some(link) = link {
+        line.int("rssi", link.rssi.into()).int("snr", link.snr.into());
+    }
+    Some(line.finish(timestamp_s))
+}
+
+/// DS18B20 probe temperatures in °C; `i16::MIN` marks a failed probe
+fn probes_c(packet: &SensorDataPacket) -> Vec<Option<f32>> {
+    tlv::find(&packet.tlv, tlv::PROBE_TEMPS)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|b| match i16::from_le_bytes([b[0], b[1]]) {
+            i16::MIN => None,
+            centi => Some(centi as f32 / 100.0),
+        })
+        .collect()
+}
+
+/// Soil moisture per probe; 0xFF marks an uncalibrated one
+fn soil_pct(packet: &SensorDataPacket) -> Vec<Option<u8>> {
+    tlv::find(&packet.tlv, tlv::SOIL_MOISTURE)
+        .unwrap_or_default()
+        .iter()
+        .map(|&p| (p != 0xFF).then_some(p))
+        .collect()
+}
+
+const CSV_HEADER: &str =
+    "rx_time_ms,rssi_dbm,snr_db,seq,stored,age_s,temp_c,humidity_pct,gas_ohm,iaq,co2_ppm,battery_mv";
+
+fn csv_row(link: Option<Link>, packet: &SensorDataPacket, stored: Option<Option<u32>>) -> String {
+    fn opt<T: ToString>(value: Option<T>) -> String {
+        value.map_or_else(String::new, |v| v.to_string())
+    }
+    let (t, h) = temperature_humidity(packet);
+    format!(
+        "{},{},{},{},{},{},{:.2},{:.2},{},{},{},{}",
+        opt(link.map(|l| l.rx_time_ms)),
+        opt(link.map(|l| l.rssi)),
+        opt(link.map(|l| l.snr)),
+        packet.seq_num,
+        u8::from(stored.is_some()),
+        opt(stored.flatten()),
+        t,
+        h,
+        packet.gas_resistance,
+        opt(packet.iaq),
+        opt(tlv::find_u16(&packet.tlv, tlv::CO2_PPM)),
+        opt(tlv::find_u16(&packet.tlv, tlv::BATTERY_MV)),
+    )
+}
+
+#[derive(Default)]
+struct Stats {
+    by_type: BTreeMap<u8, u32>,
+    crc_errors: u32,
+    decode_errors: u32,
+    /// Serial records dropped by the deframer
+    stream_errors: u32,
+    live: u32,
+    stored: u32,
+    /// Last live sequence number per sender
+    last_seq: BTreeMap<u8, u16>,
+    /// Live sequence numbers skipped, as Node 2's `stats` counts them
+    missed: u32,
+    rssi: Spread,
+    snr: Spread,
+    /// Live readings by the gateway that heard them best, with several ports
+    best_gateway: BTreeMap<usize, u32>,
+}
+
+/// Running minimum, maximum and mean
+#[derive(Default)]
+struct Spread {
+    min: i16,
+    max: i16,
+    sum: i64,
+    count: u32,
+}
+
+impl Spread {
+    fn add(&mut self, value: i16) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += i64::from(value);
+        self.count += 1;
+    }
+
+    fn mean(&self) -> Option<f32> {
+        (self.count > 0).then(|| self.sum as f32 / self.count as f32)
+    }
+}
+
+impl Stats {
+    fn add(&mut self, record: &Record) {
+        match &record.body {
+            Body::Bad(FrameError::Crc { .. }) => self.crc_errors += 1,
+            Body::Bad(_) => self.decode_errors += 1,
+            body => {
+                *self.by_type.entry(record.msg_type).or_default() += 1;
+                if let Some(link) = record.link {
+                    self.rssi.add(link.rssi);
+                    self.snr.add(link.snr);
+                }
+                match body {
+                    Body::Reading { stored: Some(_), .. } => self.stored += 1,
+                    Body::Reading { packet, stored: None } => {
+                        self.live += 1;
+                        if let Some(gateway) = record.link.and_then(|l| l.gateway) {
+                            *self.best_gateway.entry(gateway).or_default() += 1;
+                        }
+                        let node = record.link.map_or(NODE_1, |l| l.node);
+                        if let Some(last) = self.last_seq.insert(node, packet.seq_num) {
+                            let gap = packet.seq_num.wrapping_sub(last);
+                            // A big jump backwards is the node restarting, not loss
+                            if (2..0x8000).contains(&gap) {
+                                self.missed += u32::from(gap - 1);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn print(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "frames by type:")?;
+        for (t, n) in &self.by_type {
+            writeln!(out, "  0x{:02X}  {}", t, n)?;
+        }
+        writeln!(out, "CRC errors:        {}", self.crc_errors)?;
+        writeln!(out, "decode errors:     {}", self.decode_errors)?;
+        writeln!(out, "serial errors:     {}", self.stream_errors)?;
+        writeln!(out, "readings:          {} live, {} stored", self.live, self.stored)?;
+        let expected = self.live + self.missed;
+        if expected > 0 {
+            let loss = 100.0 * self.missed as f32 / expected as f32;
+            writeln!(out, "sequence gaps:     {} missed ({:.1} % loss)", self.missed, loss)?;
+        }
+        for (name, spread) in [("RSSI dBm", &self.rssi), ("SNR dB", &self.snr)] {
+            if let Some(mean) = spread.mean() {
+                writeln!(out, "{:<18} min {} max {} mean {:.1}", format!("{}:", name), spread.min, spread.max, mean)?;
+            }
+        }
+        if !self.best_gateway.is_empty() {
+            writeln!(out, "best gateway:")?;
+            for (gateway, n) in &self.best_gateway {
+                writeln!(out, "  gw{}  {} readings", gateway + 1, n)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! pcapng capture of Node 2's `stream tap` records, for Wireshark
+//!
+//! `wk3 <port> pcap <file>` writes one packet per `RadioFrame` or
+//! `RawLine`, timestamped by this PC, on an interface of link type
+//! `LINKTYPE_USER0`. Each packet is a small pseudo-header followed by what
+//! came off the air:
+//!
+//! ```text
+//! [kind u8][rx_time_ms u32 LE][rssi i16 LE][snr i16 LE][data ...]
+//! ```
+//!
+//! `kind` is `KIND_FRAME` for a radio payload (`[header][postcard][CRC]`) or
+//! `KIND_LINE` for a module line, whose RSSI and SNR are zero. Wireshark
+//! needs `--dissector` (see `dissector.rs`) to make sense of it.
+//!
+//! `read` takes such a capture back apart for `wk3 replay`.
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use wk3_protocol::stream::{self, RadioFrame, RawLine};
+use wk3_protocol::{decode_frame, FrameError, NODE_HOST};
+
+/// A radio payload, CRC unchecked
+pub const KIND_FRAME: u8 = 0;
+/// A line from the LoRa module that wasn't a `+RCV`
+pub const KIND_LINE: u8 = 1;
+/// Pseudo-header ahead of the data
+pub const PSEUDO_HEADER_LEN: usize = 9;
+/// `LINKTYPE_USER0`, free for private use
+const LINK_TYPE: u16 = 147;
+
+const SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const OPT_END: u16 = 0;
+const IF_NAME: u16 = 2;
+
+pub struct Writer<W: Write> {
+    out: W,
+    packets: u32,
+}
+
+impl<W: Write> Writer<W> {
+    /// Start a capture: section header and the one interface
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes()); // Major version
+        shb.extend_from_slice(&0u16.to_le_bytes()); // Minor version
+        shb.extend_from_slice(&(-1i64).to_le_bytes()); // Section length unknown
+        block(&mut out, SECTION_HEADER, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINK_TYPE.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes()); // No snap length
+        option(&mut idb, IF_NAME, b"wk3");
+        option(&mut idb, OPT_END, &[]);
+        block(&mut out, INTERFACE_DESCRIPTION, &idb)?;
+        Ok(Self { out, packets: 0 })
+    }
+
+    /// Add a checked stream record if it is one this capture holds
+    pub fn add(&mut self, record: &[u8]) -> io::Result<()> {
+        let Ok((msg_type, body)) = decode_frame(record) else { return Ok(()) };
+        let mut data = Vec::new();
+        match msg_type {
+            stream::RADIO_FRAME => {
+                let Ok(frame) = postcard::from_bytes::<RadioFrame>(body) else { return Ok(()) };
+                header(&mut data, KIND_FRAME, frame.rx_time_ms, frame.rssi, frame.snr);
+                data.extend_from_slice(frame.payload);
+            }
+            stream::RAW_LINE => {
+                let Ok(raw) = postcard::from_bytes::<RawLine>(body) else { return Ok(()) };
+                header(&mut data, KIND_LINE, raw.rx_time_ms, 0, 0);
+                data.extend_from_slice(raw.line);
+            }
+            _ => return Ok(()),
+        }
+        self.packet(&data)
+    }
+
+    fn packet(&mut self, data: &[u8]) -> io::Result<()> {
+        // Microseconds, the default `if_tsresol`
+        let us = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64);
+        let mut epb = Vec::new();
+        epb.extend_from_slice(&0u32.to_le_bytes()); // Interface
+        epb.extend_from_slice(&((us >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(us as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes()); // Captured
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes()); // Original
+        epb.extend_from_slice(data);
+        pad(&mut epb);
+        block(&mut self.out, ENHANCED_PACKET, &epb)?;
+        self.packets += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Packets written so far
+    pub fn packets(&self) -> u32 {
+        self.packets
+    }
+}
+
+/// A packet of a capture, pseudo-header decoded
+pub struct Packet {
+    pub kind: u8,
+    pub rx_time_ms: u32,
+    pub rssi: i16,
+    pub snr: i16,
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    /// The `stream tap` record it was captured from
+    pub fn record<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8], FrameError> {
+        if self.kind == KIND_LINE {
+            stream::encode(NODE_HOST, stream::RAW_LINE, &RawLine { rx_time_ms: self.rx_time_ms, line: &self.data }, buf)
+        } else {
+            let frame = RadioFrame { rx_time_ms: self.rx_time_ms, rssi: self.rssi, snr: self.snr, payload: &self.data };
+            stream::encode(NODE_HOST, stream::RADIO_FRAME, &frame, buf)
+        }
+    }
+}
+
+/// The packets of a capture `Writer` made, in order
+///
+/// Other pcapng files read as long as they are little-endian; packets too
+/// short for the pseudo-header are skipped.
+pub fn read(file: &[u8]) -> io::Result<Vec<Packet>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("not a wk3 capture: {}", what));
+    let u32_at = |at: usize| u32::from_le_bytes([file[at], file[at + 1], file[at + 2], file[at + 3]]);
+    let mut packets = Vec::new();
+    let mut at = 0;
+    while at + 12 <= file.len() {
+        let (block_type, len) = (u32_at(at), u32_at(at + 4) as usize);
+        if len < 12 || at + len > file.len() {
+            return Err(invalid("truncated block"));
+        }
+        if block_type == SECTION_HEADER && (len < 28 || u32_at(at + 8) != BYTE_ORDER_MAGIC) {
+            return Err(invalid("big-endian or damaged section header"));
+        }
+        if block_type == ENHANCED_PACKET && len >= 32 {
+            let captured = u32_at(at + 20) as usize;
+            let data = file.get(at + 28..at + 28 + captured).ok_or_else(|| invalid("truncated packet"))?;
+            if let [kind, t0, t1, t2, t3, r0, r1, s0, s1, rest @ ..] = data {
+                packets.push(Packet {
+                    kind: *kind,
+                    rx_time_ms: u32::from_le_bytes([*t0, *t1, *t2, *t3]),
+                    rssi: i16::from_le_bytes([*r0, *r1]),
+                    snr: i16::from_le_bytes([*s0, *s1]),
+                    data: rest.to_vec(),
+                });
+            }
+        }
+        at += len;
+    }
+    Ok(packets)
+}
+
+fn header(data: &mut Vec<u8>, kind: u8, rx_time_ms: u32, rssi: i16, snr: i16) {
+    data.push(kind);
+    data.extend_from_slice(&rx_time_ms.to_le_bytes());
+    data.extend_from_slice(&rssi.to_le_bytes());
+    data.extend_from_slice(&snr.to_le_bytes());
+}
+
+/// A block: type, total length, `body` (already padded), total length again
+fn block(out: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let len = (body.len() + 12) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&len.to_le_bytes())
+}
+
+fn option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
@@ -0,0 +1,127 @@
+//! Metrics for node-exporter's textfile collector
+//!
+//! `--prometheus-textfile <path>` keeps a file of the last live reading,
+//! the link figures and running counters. It is rewritten after each batch
+//! of records through a temporary file and a rename, so the collector never
+//! reads half of one. Counters start at zero with each run of `wk3`, which
+//! `rate()` and `increase()` handle as a counter reset.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use wk3_protocol::{tlv, SensorDataPacket};
+
+use crate::{probes_c, soil_pct, temperature_humidity, Body, Link, Record, Stats};
+
+/// The newest live reading
+struct Last {
+    packet: SensorDataPacket,
+    link: Link,
+    at_s: u64,
+}
+
+pub struct Textfile {
+    path: PathBuf,
+    stats: Stats,
+    last: Option<Last>,
+    changed: bool,
+}
+
+impl Textfile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, stats: Stats::default(), last: None, changed: true }
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        self.stats.add(record);
+        if let (Body::Reading { packet, stored: None }, Some(link)) = (&record.body, record.link) {
+            let at_s = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            self.last = Some(Last { packet: packet.clone(), link, at_s });
+        }
+        self.changed = true;
+    }
+
+    /// Rewrite the file if anything arrived since the last call
+    pub fn write(&mut self, serial_errors: u32) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, self.render(serial_errors))?;
+        fs::rename(&tmp, &self.path)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    fn render(&self, serial_errors: u32) -> String {
+        let mut out = String::new();
+        let s = &self.stats;
+
+        header(&mut out, "wk3_frames_total", "counter", "Frames received by message type");
+        for (t, n) in &s.by_type {
+            let _ = writeln!(out, "wk3_frames_total{{type=\"0x{:02x}\"}} {}", t, n);
+        }
+        header(&mut out, "wk3_frame_errors_total", "counter", "Payloads rejected by Node 2's checks");
+        let _ = writeln!(out, "wk3_frame_errors_total{{kind=\"crc\"}} {}", s.crc_errors);
+        let _ = writeln!(out, "wk3_frame_errors_total{{kind=\"decode\"}} {}", s.decode_errors);
+        header(&mut out, "wk3_serial_errors_total", "counter", "Serial records dropped for a bad length or CRC");
+        let _ = writeln!(out, "wk3_serial_errors_total {}", serial_errors);
+        header(&mut out, "wk3_readings_total", "counter", "Readings received, live or replayed from Node 1's flash");
+        let _ = writeln!(out, "wk3_readings_total{{source=\"live\"}} {}", s.live);
+        let _ = writeln!(out, "wk3_readings_total{{source=\"stored\"}} {}", s.stored);
+        header(&mut out, "wk3_sequence_missed_total", "counter", "Live sequence numbers skipped");
+        let _ = writeln!(out, "wk3_sequence_missed_total {}", s.missed);
+
+        let Some(last) = &self.last else { return out };
+        let node = "node=\"1\"";
+        let (temperature_c, humidity_pct) = temperature_humidity(&last.packet);
+        gauge(&mut out, "wk3_rssi_dbm", "RSSI of the last live reading", node, last.link.rssi);
+        gauge(&mut out, "wk3_snr_db", "SNR of the last live reading", node, last.link.snr);
+        gauge(&mut out, "wk3_last_reading_timestamp_seconds", "When the last live reading arrived", node, last.at_s);
+        gauge(&mut out, "wk3_sequence", "Sequence number of the last live reading", node, last.packet.seq_num);
+        gauge(&mut out, "wk3_temperature_celsius", "Temperature", node, temperature_c);
+        gauge(&mut out, "wk3_humidity_percent", "Relative humidity", node, humidity_pct);
+        gauge(&mut out, "wk3_gas_resistance_ohms", "BME680 gas resistance", node, last.packet.gas_resistance);
+        if let Some(iaq) = last.packet.iaq {
+            gauge(&mut out, "wk3_iaq", "Indoor air quality index", node, iaq);
+        }
+        if let Some(co2) = tlv::find_u16(&last.packet.tlv, tlv::CO2_PPM) {
+            gauge(&mut out, "wk3_co2_ppm", "CO2 concentration", node, co2);
+        }
+        if let Some(mv) = tlv::find_u16(&last.packet.tlv, tlv::BATTERY_MV) {
+            gauge(&mut out, "wk3_battery_volts", "Battery voltage", node, mv as f32 / 1000.0);
+        }
+        let probes = probes_c(&last.packet);
+        if probes.iter().any(Option::is_some) {
+            header(&mut out, "wk3_probe_temperature_celsius", "gauge", "DS18B20 probe temperature");
+            for (i, c) in probes.iter().enumerate() {
+                if let Some(c) = c {
+                    let _ = writeln!(out, "wk3_probe_temperature_celsius{{{},probe=\"{}\"}} {}", node, i + 1, c);
+                }
+            }
+        }
+        let soil = soil_pct(&last.packet);
+        if soil.iter().any(Option::is_some) {
+            header(&mut out, "wk3_soil_moisture_percent", "gauge", "Soil moisture");
+            for (i, pct) in soil.iter().enumerate() {
+                if let Some(pct) = pct {
+                    let _ = writeln!(out, "wk3_soil_moisture_percent{{{},probe=\"{}\"}} {}", node, i + 1, pct);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, labels: &str, value: impl std::fmt::Display) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+}
@@ -0,0 +1,194 @@
+//! Live dashboard in the terminal (`wk3 <port> tui`)
+//!
+//! Gauges for the last live reading, RSSI and SNR sparklines over the last
+//! readings, the running counters `stats` prints, and the newest records
+//! as `watch` prints them. Redrawn after every read from the port, so at
+//! least five times a second; `q`, Esc or Ctrl-C quits and puts the
+//! terminal back.
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use wk3_protocol::{tlv, SensorDataPacket};
+
+use crate::{describe, temperature_humidity, Body, Record, Stats};
+
+/// Live readings kept for the sparklines, more than a wide terminal shows
+const HISTORY: usize = 300;
+/// Records kept for the log pane
+const LOG_LINES: usize = 500;
+/// Sparkline floors: bars are the value above these
+const RSSI_FLOOR: i16 = -140;
+const SNR_FLOOR: i16 = -20;
+
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    state: State,
+}
+
+#[derive(Default)]
+struct State {
+    stats: Stats,
+    last: Option<(SensorDataPacket, Instant)>,
+    rssi: VecDeque<i16>,
+    snr: VecDeque<i16>,
+    log: VecDeque<(Style, String)>,
+}
+
+impl Dashboard {
+    /// Take over the terminal until dropped
+    pub fn new() -> io::Result<Self> {
+        Ok(Self { terminal: ratatui::try_init()?, state: State::default() })
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        let state = &mut self.state;
+        state.stats.add(record);
+        if let (Body::Reading { packet, stored: None }, Some(link)) = (&record.body, record.link) {
+            push(&mut state.rssi, link.rssi, HISTORY);
+            push(&mut state.snr, link.snr, HISTORY);
+            state.last = Some((packet.clone(), Instant::now()));
+        }
+        let style = match record.body {
+            Body::Bad(_) | Body::Crash { .. } => Style::new().fg(Color::Red),
+            Body::Log(_) | Body::Result(_) | Body::Config(_) | Body::Report(_) | Body::BroadcastAck(_) => Style::new().fg(Color::Yellow),
+            Body::Reading { stored: Some(_), .. } => Style::new().fg(Color::DarkGray),
+            _ => Style::new(),
+        };
+        for line in describe(record).lines() {
+            push(&mut state.log, (style, line.to_string()), LOG_LINES);
+        }
+    }
+
+    /// Handle keys and redraw; `false` once the user has quit
+    pub fn update(&mut self, serial_errors: u32) -> io::Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)) {
+                    return Ok(false);
+                }
+            }
+        }
+        self.state.stats.stream_errors = serial_errors;
+        let state = &self.state;
+        self.terminal.draw(|frame| state.render(frame))?;
+        Ok(true)
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+impl State {
+    fn render(&self, frame: &mut Frame) {
+        let [top, sparks, log] =
+            Layout::vertical([Constraint::Length(7), Constraint::Length(7), Constraint::Min(4)]).areas(frame.area());
+        let [gauges, counters] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(top);
+        let [rssi, snr] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(sparks);
+
+        self.render_gauges(frame, gauges);
+        frame.render_widget(Paragraph::new(self.counters()).block(Block::bordered().title(" Link ")), counters);
+        render_spark(frame, rssi, "RSSI", "dBm", &self.rssi, RSSI_FLOOR, 0);
+        render_spark(frame, snr, "SNR", "dB", &self.snr, SNR_FLOOR, 20);
+
+        let rows = log.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(rows))
+            .map(|(style, text)| Line::styled(text.as_str(), *style))
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Records (q to quit) ")), log);
+    }
+
+    fn render_gauges(&self, frame: &mut Frame, area: Rect) {
+        let Some((packet, at)) = &self.last else {
+            let waiting = Paragraph::new("waiting for a live reading...");
+            frame.render_widget(waiting.block(Block::bordered().title(" Last reading ")), area);
+            return;
+        };
+        let title = format!(" Reading #{}, {} s ago ", packet.seq_num, at.elapsed().as_secs());
+        let block = Block::bordered().title(title);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let (temperature_c, humidity_pct) = temperature_humidity(packet);
+        let mut gauges = vec![
+            ("Temp", temperature_c, -10.0, 40.0, format!("{:.1} °C", temperature_c), Color::Red),
+            ("Hum", humidity_pct, 0.0, 100.0, format!("{:.1} %", humidity_pct), Color::Blue),
+        ];
+        if let Some(iaq) = packet.iaq {
+            gauges.push(("IAQ", iaq as f32, 0.0, 500.0, iaq.to_string(), Color::Magenta));
+        }
+        if let Some(co2) = tlv::find_u16(&packet.tlv, tlv::CO2_PPM) {
+            gauges.push(("CO2", co2 as f32, 400.0, 2000.0, format!("{} ppm", co2), Color::Green));
+        }
+        if let Some(mv) = tlv::find_u16(&packet.tlv, tlv::BATTERY_MV) {
+            let volts = mv as f32 / 1000.0;
+            gauges.push(("Batt", volts, 3.0, 4.2, format!("{:.2} V", volts), Color::Yellow));
+        }
+        let rows = Layout::vertical(vec![Constraint::Length(1); gauges.len()]).split(inner);
+        for ((name, value, min, max, label, color), row) in gauges.into_iter().zip(rows.iter()) {
+            let ratio = ((value - min) / (max - min)).clamp(0.0, 1.0);
+            let gauge = Gauge::default()
+                .gauge_style(Style::new().fg(color))
+                .ratio(ratio.into())
+                .label(format!("{:<4} {}", name, label));
+            frame.render_widget(gauge, *row);
+        }
+    }
+
+    fn counters(&self) -> Vec<Line<'static>> {
+        let s = &self.stats;
+        let expected = s.live + s.missed;
+        let loss = if expected > 0 { 100.0 * s.missed as f32 / expected as f32 } else { 0.0 };
+        vec![
+            Line::from(format!("readings  {} live, {} stored", s.live, s.stored)),
+            Line::from(format!("missed    {} ({:.1} % loss)", s.missed, loss)),
+            Line::from(format!("errors    {} CRC, {} decode", s.crc_errors, s.decode_errors)),
+            Line::from(format!("serial    {} bad records", s.stream_errors)),
+            Line::from(format!("frames    {}", s.by_type.values().sum::<u32>())),
+        ]
+    }
+}
+
+/// A sparkline of `history`, newest on the right, scaled from `floor` to `ceiling`
+fn render_spark(frame: &mut Frame, area: Rect, name: &str, unit: &str, history: &VecDeque<i16>, floor: i16, ceiling: i16) {
+    let title = match history.back() {
+        Some(last) => {
+            let (min, max) = history.iter().fold((i16::MAX, i16::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+            format!(" {} {} {} (min {} max {}) ", name, last, unit, min, max)
+        }
+        None => format!(" {} ", name),
+    };
+    let width = area.width.saturating_sub(2) as usize;
+    let bars: Vec<u64> = history
+        .iter()
+        .skip(history.len().saturating_sub(width))
+        .map(|&v| (i32::from(v) - i32::from(floor)).max(0) as u64)
+        .collect();
+    let spark = Sparkline::default()
+        .block(Block::bordered().title(title))
+        .data(bars)
+        .max((ceiling - floor) as u64)
+        .style(Style::new().fg(Color::Cyan));
+    frame.render_widget(spark, area);
+}
+
+fn push<T>(queue: &mut VecDeque<T>, item: T, len: usize) {
+    if queue.len() == len {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
@@ -1,6 +1,10 @@
 MEMORY
 {
-  /* STM32F446RE has 512 KB Flash and 128 KB RAM */
-  FLASH (rx) : ORIGIN = 0x08000000, LENGTH = 512K
+  /* STM32F446RE has 512 KB Flash and 128 KB RAM.
+   * Firmware is limited to sectors 0-4 (128 KB); sectors 5-7 (3 x 128 KB)
+   * are reserved for persistent storage. Sector 5 holds unacknowledged
+   * readings (see src/backlog.rs), sector 6 the node settings (see
+   * src/config.rs), sector 7 the crash log (see src/crash.rs). */
+  FLASH (rx) : ORIGIN = 0x08000000, LENGTH = 128K
   RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 128K
-}
\ No newline at end of file
+}
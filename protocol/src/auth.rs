@@ -0,0 +1,76 @@
+//! Authentication of remote commands and firmware uploads
+//!
+//! Anyone with a LoRa module can send Node 1 a frame, and a CRC only
+//! catches noise. A `CommandPacket` therefore carries a tag: the first
+//! `TAG_LEN` bytes of HMAC-SHA256, keyed with a secret both nodes are built
+//! with, over the message type, the counter (LE) and the postcard-encoded
+//! command. Eight bytes keep the downlink short while leaving a forger a
+//! 2^-64 chance per attempt, and every attempt costs airtime.
+//!
+//! A `FirmwareBegin` is tagged the same way, over its type, counter, length
+//! (LE) and the image's SHA-256, and draws on the same counter. The chunks
+//! that follow aren't tagged: Node 1 stages the image only if its digest
+//! matches the signed one.
+
+use hmac::{Hmac, Mac};
+use postcard::experimental::max_size::MaxSize;
+use sha2::Sha256;
+
+use crate::{FirmwareBegin, RemoteCommand, MSG_TYPE_COMMAND, MSG_TYPE_FW_BEGIN};
+
+pub const KEY_LEN: usize = 16;
+pub const TAG_LEN: usize = 8;
+
+/// Room for the longest encoding of any command
+const BODY_LEN: usize = RemoteCommand::POSTCARD_MAX_SIZE;
+
+/// The tag for `command` sent with `counter`; an error if the command
+/// won't encode, rather than a tag that leaves it out
+pub fn tag(key: &[u8; KEY_LEN], counter: u32, command: &RemoteCommand) -> postcard::Result<[u8; TAG_LEN]> {
+    command_mac(key, counter, command).map(truncate)
+}
+
+/// Whether `tag` is right for `command` and `counter`, in constant time;
+/// never for a command that won't encode
+pub fn verify(key: &[u8; KEY_LEN], counter: u32, command: &RemoteCommand, tag: &[u8; TAG_LEN]) -> bool {
+    command_mac(key, counter, command).is_ok_and(|mac| mac.verify_truncated_left(tag).is_ok())
+}
+
+/// The tag for `begin`, its own `tag` aside
+pub fn firmware_tag(key: &[u8; KEY_LEN], begin: &FirmwareBegin) -> [u8; TAG_LEN] {
+    truncate(firmware_mac(key, begin))
+}
+
+/// Whether `begin.tag` is right for the rest of `begin`, in constant time
+pub fn verify_firmware(key: &[u8; KEY_LEN], begin: &FirmwareBegin) -> bool {
+    firmware_mac(key, begin).verify_truncated_left(&begin.tag).is_ok()
+}
+
+fn truncate(mac: Hmac<Sha256>) -> [u8; TAG_LEN] {
+    let full = mac.finalize().into_bytes();
+    let mut tag = [0u8; TAG_LEN];
+    tag.copy_from_slice(&full[..TAG_LEN]);
+    tag
+}
+
+fn mac(key: &[u8; KEY_LEN], msg_type: u8, counter: u32) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(&[msg_type]);
+    mac.update(&counter.to_le_bytes());
+    mac
+}
+
+fn command_mac(key: &[u8; KEY_LEN], counter: u32, command: &RemoteCommand) -> postcard::Result<Hmac<Sha256>> {
+    let mut body = [0u8; BODY_LEN];
+    let body = postcard::to_slice(command, &mut body)?;
+    let mut mac = mac(key, MSG_TYPE_COMMAND, counter);
+    mac.update(body);
+    Ok(mac)
+}
+
+fn firmware_mac(key: &[u8; KEY_LEN], begin: &FirmwareBegin) -> Hmac<Sha256> {
+    let mut mac = mac(key, MSG_TYPE_FW_BEGIN, begin.counter);
+    mac.update(&begin.len.to_le_bytes());
+    mac.update(&begin.digest);
+    mac
+}
@@ -0,0 +1,993 @@
+//! Over-the-air message definitions shared by both nodes
+//!
+//! Every LoRa payload starts with a six-byte header, so a decoder that
+//! knows nothing of postcard can still find, check and sort frames:
+//!
+//! ```text
+//! [FRAME_MAGIC][FRAME_VERSION][node][hops][msg_type][body len][postcard body (len)][CRC-16 (2, big-endian)]
+//! ```
+//!
+//! `node` is the sender's LoRa address, so one receiver can tell several
+//! sensor nodes apart; `hops` counts the relays a frame has passed through
+//! (see `relay_frame`) in its low four bits. The next two hold the length
+//! of an optional route: the relays' addresses, a byte each, between the
+//! header and the body, for a frame that is to take a set path rather
+//! than whichever relay `via` names (see `route_frame`). Frames without a
+//! route are laid out as before.
+//!
+//! The CRC covers the header and the body. ACK/NACK keep their original
+//! CRC-less layout (`AckPacket` already begins with its `msg_type` byte,
+//! never `FRAME_MAGIC`); `frame_type` tells the two apart.
+//!
+//! This crate builds for the host as well as the nodes, so the `wk3` tool
+//! (`host/`) decodes frames with the same types the firmware sends them
+//! with. The firmware enables the `defmt` feature and uses it as
+//! `wk3_binary_protocol::protocol`.
+
+#![no_std]
+
+pub mod auth;
+pub mod mqtt;
+pub mod stream;
+
+use heapless::Vec;
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+// Message type constants (first payload byte)
+pub const MSG_TYPE_ACK: u8 = 1;
+pub const MSG_TYPE_NACK: u8 = 2;
+pub const MSG_TYPE_SENSOR_DATA: u8 = 3;
+pub const MSG_TYPE_CRASH_REPORT: u8 = 4;
+pub const MSG_TYPE_HEALTH: u8 = 5;
+pub const MSG_TYPE_NODE_INFO: u8 = 6;
+pub const MSG_TYPE_CALIBRATE: u8 = 7;
+pub const MSG_TYPE_SENSOR_FAULT: u8 = 8;
+pub const MSG_TYPE_SUMMARY: u8 = 9;
+pub const MSG_TYPE_EVENT: u8 = 10;
+/// Node 2 -> Node 1, body is a `panel::Setting`
+pub const MSG_TYPE_DISPLAY: u8 = 11;
+pub const MSG_TYPE_POWER_FAIL: u8 = 12;
+/// A reading replayed from Node 1's backlog, body is a `StoredReading`
+pub const MSG_TYPE_STORED: u8 = 13;
+/// Node 2 -> Node 1, body is a `ReplayRequest`
+pub const MSG_TYPE_REPLAY_REQUEST: u8 = 14;
+/// Readings Node 2 asked for, body is a `ReplayBatch`
+pub const MSG_TYPE_REPLAY_BATCH: u8 = 15;
+/// Node 2 -> Node 1, body is a `CommandPacket`
+pub const MSG_TYPE_COMMAND: u8 = 16;
+/// Node 1's answer to a `CommandPacket`, body is a `CommandResult`
+pub const MSG_TYPE_COMMAND_RESULT: u8 = 17;
+/// Node 1's log lines while `tunnel` is on, body is a `LogBatch`
+pub const MSG_TYPE_LOG: u8 = 18;
+/// Host -> Node 1 through Node 2: start or resume a firmware upload, body
+/// is a `FirmwareBegin`
+pub const MSG_TYPE_FW_BEGIN: u8 = 19;
+/// Host -> Node 1 through Node 2: part of the image, body is a `FirmwareChunk`
+pub const MSG_TYPE_FW_CHUNK: u8 = 20;
+/// Node 1's answer to either, body is a `FirmwareStatus`
+pub const MSG_TYPE_FW_STATUS: u8 = 21;
+/// Node 2 -> every node on `BROADCAST_ADDRESS`, body is a `BroadcastPacket`
+pub const MSG_TYPE_BROADCAST: u8 = 22;
+/// A node's answer to a broadcast that asked for one, body is a `BroadcastAck`
+pub const MSG_TYPE_BROADCAST_ACK: u8 = 23;
+/// A node with address 0 asking for one, body is a `JoinRequest`
+pub const MSG_TYPE_JOIN_REQUEST: u8 = 24;
+/// Node 2's answer on `BROADCAST_ADDRESS`, body is a `JoinAccept`
+pub const MSG_TYPE_JOIN_ACCEPT: u8 = 25;
+/// A link check from either end, body is a `Ping`
+pub const MSG_TYPE_PING: u8 = 26;
+/// The answer to it, body is a `Pong`
+pub const MSG_TYPE_PONG: u8 = 27;
+/// Node 1's answer to a `SetConfig` command, body is a `ConfigAck`
+pub const MSG_TYPE_CONFIG_ACK: u8 = 28;
+/// A sensor node's answer to a `GetConfig` command, body is a `ConfigReport`
+pub const MSG_TYPE_CONFIG_REPORT: u8 = 29;
+/// Either end's view of the link, body is a `LinkStats`
+pub const MSG_TYPE_LINK_STATS: u8 = 30;
+
+/// Every message type by name, as PROTOCOL.md lists them
+pub const MESSAGE_TYPES: &[(u8, &str)] = &[
+    (MSG_TYPE_ACK, "Ack"),
+    (MSG_TYPE_NACK, "Nack"),
+    (MSG_TYPE_SENSOR_DATA, "SensorData"),
+    (MSG_TYPE_CRASH_REPORT, "CrashReport"),
+    (MSG_TYPE_HEALTH, "Health"),
+    (MSG_TYPE_NODE_INFO, "NodeInfo"),
+    (MSG_TYPE_CALIBRATE, "Calibrate"),
+    (MSG_TYPE_SENSOR_FAULT, "SensorFault"),
+    (MSG_TYPE_SUMMARY, "Summary"),
+    (MSG_TYPE_EVENT, "Event"),
+    (MSG_TYPE_DISPLAY, "Display"),
+    (MSG_TYPE_POWER_FAIL, "PowerFail"),
+    (MSG_TYPE_STORED, "StoredReading"),
+    (MSG_TYPE_REPLAY_REQUEST, "ReplayRequest"),
+    (MSG_TYPE_REPLAY_BATCH, "ReplayBatch"),
+    (MSG_TYPE_COMMAND, "Command"),
+    (MSG_TYPE_COMMAND_RESULT, "CommandResult"),
+    (MSG_TYPE_LOG, "Log"),
+    (MSG_TYPE_FW_BEGIN, "FirmwareBegin"),
+    (MSG_TYPE_FW_CHUNK, "FirmwareChunk"),
+    (MSG_TYPE_FW_STATUS, "FirmwareStatus"),
+    (MSG_TYPE_BROADCAST, "Broadcast"),
+    (MSG_TYPE_BROADCAST_ACK, "BroadcastAck"),
+    (MSG_TYPE_JOIN_REQUEST, "JoinRequest"),
+    (MSG_TYPE_JOIN_ACCEPT, "JoinAccept"),
+    (MSG_TYPE_PING, "Ping"),
+    (MSG_TYPE_PONG, "Pong"),
+    (MSG_TYPE_CONFIG_ACK, "ConfigAck"),
+    (MSG_TYPE_CONFIG_REPORT, "ConfigReport"),
+    (MSG_TYPE_LINK_STATS, "LinkStats"),
+];
+
+/// First byte of every framed payload
+pub const FRAME_MAGIC: u8 = 0xB3;
+/// Second byte; raised when a frame or body layout changes incompatibly
+pub const FRAME_VERSION: u8 = 4;
+/// Magic, version, sender, hop count, type and body length
+pub const HEADER_LEN: usize = 6;
+/// CRC-16 at the end, big-endian
+pub const CRC_LEN: usize = 2;
+// Header field offsets
+pub const OFFSET_MAGIC: usize = 0;
+pub const OFFSET_VERSION: usize = 1;
+pub const OFFSET_NODE: usize = 2;
+pub const OFFSET_HOPS: usize = 3;
+pub const OFFSET_TYPE: usize = 4;
+pub const OFFSET_LEN: usize = 5;
+/// Relays a frame may pass through; a relay drops one that has had this many
+pub const MAX_HOPS: u8 = 2;
+/// Relays a route can name, one per hop
+pub const MAX_ROUTE: usize = MAX_HOPS as usize;
+/// The hop count's bits in the hops byte; the route length sits above
+const HOPS_MASK: u8 = 0x0F;
+const ROUTE_SHIFT: u8 = 4;
+const ROUTE_MASK: u8 = 0x03;
+
+/// Sender ID of the original sensor node, and the default LoRa address
+/// of any node; Node 2's sequence stats, replay and downlinks are for it
+pub const NODE_1: u8 = 1;
+/// Sender ID of frames the host tool writes, which has no LoRa address
+pub const NODE_HOST: u8 = 0;
+/// RYLR998 destination address every module on the network receives
+pub const BROADCAST_ADDRESS: u16 = 0;
+
+// `EventPacket::kind` values
+pub const EVENT_MOTION: u8 = 1;
+
+/// Sensor data packet for binary transmission
+/// Size: ~12 bytes (postcard serialized) vs 24 bytes (text format)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorDataPacket {
+    pub seq_num: u16,           // Sequence number for duplicate detection
+    pub temperature: i16,       // Temperature in centidegrees (e.g., 2710 = 27.1°C)
+    pub humidity: u16,          // Humidity in basis points (e.g., 5600 = 56.0%)
+    pub gas_resistance: u32,    // Gas resistance in ohms
+    pub heater_step: u8,        // Index into `gas::HEATER_PROFILE` the gas reading was taken at
+    pub iaq: Option<u16>,       // IAQ index 0-500, `None` until the gas baseline has burnt in
+    pub tlv: Vec<u8, TLV_CAPACITY>, // Optional readings, see `tlv`
+}
+
+/// Room for the optional readings in a `SensorDataPacket`
+pub const TLV_CAPACITY: usize = 40;
+
+/// Optional readings as `[tag][len][value]` records
+///
+/// Quantities only some nodes measure (CO2 needs an SCD40) go here rather
+/// than into fixed fields. A receiver skips tags it doesn't know by their
+/// length, so new ones can be added without breaking older gateways.
+/// Values are little-endian.
+pub mod tlv {
+    use heapless::Vec;
+
+    /// CO2 concentration in ppm, u16
+    pub const CO2_PPM: u8 = 1;
+    /// Battery voltage in mV, u16 (see `battery`)
+    pub const BATTERY_MV: u8 = 2;
+    /// Packet flags, u8 (`FLAG_*`)
+    pub const FLAGS: u8 = 3;
+    /// DS18B20 probe temperatures, one i16 per probe in 0.01 °C (see `ds18b20`)
+    pub const PROBE_TEMPS: u8 = 4;
+    /// GNSS position and fix quality, 10 bytes (see `nmea::Fix::encode`)
+    pub const GNSS_FIX: u8 = 5;
+    /// Soil moisture, one u8 percent per probe (see `soil`)
+    pub const SOIL_MOISTURE: u8 = 6;
+
+    /// `FLAGS` bit: `temperature`/`humidity` are raw SHT31 ticks (see `raw`)
+    pub const FLAG_RAW_TH: u8 = 1 << 0;
+
+    /// Append a record; `false` if it doesn't fit
+    pub fn push<const N: usize>(buf: &mut Vec<u8, N>, tag: u8, value: &[u8]) -> bool {
+        if buf.len() + 2 + value.len() > N {
+            return false;
+        }
+        let _ = buf.push(tag);
+        let _ = buf.push(value.len() as u8);
+        buf.extend_from_slice(value).is_ok()
+    }
+
+    /// The records in `buf`, stopping at the first truncated one
+    pub fn records(mut buf: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+        core::iter::from_fn(move || {
+            let (&tag, rest) = buf.split_first()?;
+            let (&len, rest) = rest.split_first()?;
+            let value = rest.get(..len as usize)?;
+            buf = &rest[len as usize..];
+            Some((tag, value))
+        })
+    }
+
+    /// The value of the first `tag` record
+    pub fn find(buf: &[u8], tag: u8) -> Option<&[u8]> {
+        records(buf).find(|&(t, _)| t == tag).map(|(_, value)| value)
+    }
+
+    /// The value of the first `tag` record as a u16
+    pub fn find_u16(buf: &[u8], tag: u8) -> Option<u16> {
+        Some(u16::from_le_bytes(find(buf, tag)?.try_into().ok()?))
+    }
+
+    /// The value of the first `tag` record as a u8
+    pub fn find_u8(buf: &[u8], tag: u8) -> Option<u8> {
+        match find(buf, tag)? {
+            &[value] => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// ACK/NACK packet for acknowledgment
+/// Size: 3-5 bytes (1 byte msg_type + seq_num varint + 1 byte node)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AckPacket {
+    pub msg_type: u8,   // 1 = ACK (success), 2 = NACK (CRC failure)
+    pub seq_num: u16,   // Which packet we're acknowledging
+    pub node: u8,       // Whose: the frame header's node, so a relay can tell two nodes' #n apart
+}
+
+/// Node 2's transmit slot for a sensor node, after the ACK of a live reading
+///
+/// Nodes sharing a channel and a reporting interval collide every time
+/// once their timers line up. Node 2 numbers the nodes it hears and gives
+/// each a slot `slot_s` seconds wide; a node moves its sampling so that
+/// its readings arrive `slot * slot_s` seconds into each interval on Node
+/// 2's clock. `rx_ms` is that clock when the acknowledged reading arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SlotGrant {
+    pub slot: u8,
+    pub slot_s: u8,
+    pub rx_ms: u32,
+}
+
+/// An ACK or NACK with the slot grant after it, if any
+///
+/// The grant is appended rather than added to `AckPacket`, so an ACK
+/// without one still decodes and older decoders still read the sequence.
+pub fn decode_ack(payload: &[u8]) -> Option<(AckPacket, Option<SlotGrant>)> {
+    let (ack, rest) = postcard::take_from_bytes::<AckPacket>(payload).ok()?;
+    Some((ack, postcard::from_bytes(rest).ok()))
+}
+
+/// `ack`, then `slot` if there is one, into `buf`
+pub fn encode_ack<'b>(ack: &AckPacket, slot: Option<&SlotGrant>, buf: &'b mut [u8]) -> Result<&'b [u8], FrameError> {
+    let len = postcard::to_slice(ack, buf).map_err(|_| FrameError::Decode)?.len();
+    let slot_len = match slot {
+        Some(slot) => postcard::to_slice(slot, &mut buf[len..]).map_err(|_| FrameError::Decode)?.len(),
+        None => 0,
+    };
+    Ok(&buf[..len + slot_len])
+}
+
+/// Panic location and message, sent once by a crashing node
+///
+/// Strings are truncated by the sender to keep the frame well under the
+/// RYLR998's 240-byte payload limit.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CrashReport<'a> {
+    pub line: u32,
+    pub file: &'a str,
+    pub message: &'a str,
+}
+
+/// Periodic node health, sent without ACK
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HealthPacket {
+    pub uptime_s: u32,
+    pub reset_cause: u8,          // `ResetCause` discriminant from the last boot
+    pub cpu_load_permille: u16,   // Busy time over the last report window
+    pub max_isr_us: u16,          // Longest single task/ISR run in the window
+    pub max_lock_us: u16,         // Longest lock hold (0 without `lock-metrics`)
+    pub battery_mode: u8,         // `battery::Mode` discriminant
+}
+
+/// Sent once after boot: what this node is and what it measures
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub sensors: u8,              // Bit mask of `sensor::SensorKind::bit`
+    pub boot_count: u32,          // Boots since the config sector was first written
+    pub reset_cause: u8,          // `ResetCause` discriminant of this boot
+}
+
+/// Sent instead of a reading when a sensor read fails, without ACK
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SensorFaultPacket {
+    pub sensor: u8,               // `sensor::SensorKind` discriminant
+    pub error: u8,                // `sensor::SensorError` code
+}
+
+/// Hourly `[min, max, mean]` of every sample, sent without ACK
+///
+/// Scaled like `SensorDataPacket`; see `summary`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SummaryPacket {
+    pub period_s: u16,            // Time covered, nominally `summary::PERIOD_S`
+    pub samples: u16,             // Samples taken, sent or not
+    pub temperature: [i16; 3],    // 0.1 °C
+    pub humidity: [u16; 3],       // 0.01 %
+    pub gas: [u32; 3],            // Ohms, all 0 without a gas reading
+    pub co2: Option<[u16; 3]>,    // ppm, from an SCD40
+}
+
+/// Something happened that shouldn't wait for the next sample (see `motion`)
+///
+/// Sent at once, without ACK.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EventPacket {
+    pub kind: u8,                 // `EVENT_*`
+    pub count: u16,               // Events of this kind since boot, wrapping
+    pub suppressed: u16,          // Events rate-limited since the last packet
+}
+
+/// Downlink: set one sensor's calibration trim on Node 1
+///
+/// Not ACKed; Node 1 saves it to flash and the next reading shows the effect.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CalibrationCommand {
+    pub sensor: u8,                   // `sensor::SensorKind` discriminant
+    pub temp_offset_centi_c: i16,     // Added to temperature, 0.01 °C
+    pub humidity_slope_permille: u16, // Humidity scale, 1000 = unchanged
+}
+
+/// Remote management of Node 1, from `n1 ...` in Node 2's shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RemoteCommand {
+    /// Seconds between readings, saved like the setup menu's
+    SetInterval(u16),
+    /// Cap on the radio's output power in dBm, saved with Node 1's settings
+    SetTxPower(u8),
+    Reboot,
+    /// LoRa spreading factor (7-12), until the next reset; only sent as a
+    /// broadcast, since the gateway has to move with every node
+    SetSpreadingFactor(u8),
+    /// Take a reading and send it now; under a broadcast, after the
+    /// node's delay within `window_ms`
+    ReportNow,
+    /// Several settings at once, saved to flash and answered with a
+    /// `ConfigAck` rather than a `CommandResult`
+    SetConfig(ConfigChange),
+    /// Changes nothing; answered with a `ConfigReport`, under a broadcast
+    /// after the node's delay within `window_ms`
+    GetConfig,
+}
+
+/// The settings a `SetConfig` changes; `None` leaves one as it is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigChange {
+    pub interval_s: Option<u16>,
+    /// Cap on the radio's output power
+    pub tx_power_dbm: Option<u8>,
+    /// Send-on-delta on or off, and its thresholds
+    pub delta_enabled: Option<bool>,
+    pub delta_temp_deci_c: Option<u16>,
+    pub delta_humidity_deci_pct: Option<u16>,
+    pub delta_gas_percent: Option<u16>,
+    pub delta_heartbeat_s: Option<u16>,
+}
+
+/// Downlink: a `RemoteCommand` that Node 1 only obeys if it's genuine
+///
+/// `counter` must be above every counter Node 1 has accepted, so a frame
+/// recorded off the air can't be played again; `tag` proves the sender
+/// knows the shared key (see `auth`). Answered with a `CommandResult`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommandPacket {
+    pub counter: u32,
+    pub command: RemoteCommand,
+    pub tag: [u8; auth::TAG_LEN],
+}
+
+// `CommandResult::status` values
+pub const COMMAND_DONE: u8 = 0;
+/// The tag didn't match: wrong key, or a forged or damaged frame
+pub const COMMAND_BAD_TAG: u8 = 1;
+/// The counter was not above the last accepted one
+pub const COMMAND_REPLAYED: u8 = 2;
+/// Authentic, but the value is out of range
+pub const COMMAND_INVALID: u8 = 3;
+
+/// Node 1 -> Node 2: what became of command `counter`; not ACKed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommandResult {
+    pub counter: u32,
+    pub status: u8, // `COMMAND_*`
+}
+
+impl CommandResult {
+    /// Short name of `status`
+    pub fn status_name(&self) -> &'static str {
+        command_status_name(self.status)
+    }
+}
+
+/// Node 1 -> Node 2: what became of `SetConfig` command `counter`, with
+/// the settings in force afterwards (unchanged if refused); not ACKed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigAck {
+    pub counter: u32,
+    pub status: u8, // `COMMAND_*`
+    pub interval_s: u16,
+    pub tx_power_dbm: u8,
+    pub delta_enabled: bool,
+    pub delta_temp_deci_c: u16,
+    pub delta_humidity_deci_pct: u16,
+    pub delta_gas_percent: u16,
+    pub delta_heartbeat_s: u16,
+    /// Why the change was refused, when `status` is `COMMAND_INVALID`
+    pub error: Option<ConfigError>,
+}
+
+impl ConfigAck {
+    /// Short name of `status`
+    pub fn status_name(&self) -> &'static str {
+        command_status_name(self.status)
+    }
+
+    /// What became of the change: `error`'s name if there is one, else
+    /// `status`'s
+    pub fn reason(&self) -> &'static str {
+        self.error.map_or_else(|| self.status_name(), ConfigError::name)
+    }
+}
+
+/// Why a node refused a configuration value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// Outside the range the setting takes at all
+    OutOfRange,
+    /// A band or channel outside the build's region
+    FrequencyOutOfRegion,
+    /// A spreading factor the module can't run at the bandwidth, or a
+    /// bandwidth the region doesn't allow
+    IllegalModulation,
+    /// An interval so short the node's readings would break its duty cycle
+    IntervalBelowDutyFloor,
+    /// TX power above the region's limit
+    PowerAboveLimit,
+    /// A duty-cycle limit looser than the region's
+    DutyCycleAboveRegion,
+}
+
+impl ConfigError {
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigError::OutOfRange => "out of range",
+            ConfigError::FrequencyOutOfRegion => "frequency outside the region",
+            ConfigError::IllegalModulation => "SF/BW not allowed",
+            ConfigError::IntervalBelowDutyFloor => "interval below the duty-cycle floor",
+            ConfigError::PowerAboveLimit => "TX power above the limit",
+            ConfigError::DutyCycleAboveRegion => "duty cycle above the region's",
+        }
+    }
+}
+
+// `ConfigReport::region` values, the build's `WK3_REGION`
+pub const REGION_NONE: u8 = 0;
+pub const REGION_EU868: u8 = 1;
+pub const REGION_US915: u8 = 2;
+pub const REGION_AU915: u8 = 3;
+
+/// A sensor node -> Node 2: everything that governs what it sends, as in
+/// force when `GetConfig` command `counter` came; not ACKed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigReport {
+    pub counter: u32,
+    pub status: u8, // `COMMAND_*`
+    /// Firmware version, major, minor, patch
+    pub firmware: [u8; 3],
+    pub region: u8, // `REGION_*`
+    /// `AT+ADDRESS`, `AT+NETWORKID` and `AT+BAND`
+    pub address: u16,
+    pub network_id: u8,
+    pub band_mhz: u16,
+    /// `AT+PARAMETER` in use; coding rate 4/(4+n)
+    pub spreading_factor: u8,
+    pub bandwidth_khz: u16,
+    pub coding_rate: u8,
+    pub preamble: u16,
+    /// Output power in use, and the cap on it
+    pub tx_power_dbm: u8,
+    pub tx_power_limit_dbm: u8,
+    /// Duty-cycle limit in force, 0 for none
+    pub duty_permille: u16,
+    /// Channels hopped over, 0 with hopping off
+    pub hop_channels: u8,
+    pub interval_s: u16,
+    pub delta_enabled: bool,
+    pub delta_temp_deci_c: u16,
+    pub delta_humidity_deci_pct: u16,
+    pub delta_gas_percent: u16,
+    pub delta_heartbeat_s: u16,
+}
+
+impl ConfigReport {
+    /// Short name of `status`
+    pub fn status_name(&self) -> &'static str {
+        command_status_name(self.status)
+    }
+
+    /// `WK3_REGION` as the build was given it
+    pub fn region_name(&self) -> &'static str {
+        match self.region {
+            REGION_NONE => "none",
+            REGION_EU868 => "eu868",
+            REGION_US915 => "us915",
+            REGION_AU915 => "au915",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Short name of a `COMMAND_*` status
+pub fn command_status_name(status: u8) -> &'static str {
+    match status {
+        COMMAND_DONE => "done",
+        COMMAND_BAD_TAG => "bad tag",
+        COMMAND_REPLAYED => "replayed",
+        COMMAND_INVALID => "invalid value",
+        _ => "unknown",
+    }
+}
+
+/// Longest tunnelled log line; longer ones are cut
+pub const LOG_TEXT_LEN: usize = 48;
+/// Lines per `LogBatch`, which keeps it inside one payload
+pub const LOG_BATCH_LEN: usize = 4;
+
+/// One log line from Node 1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLine {
+    pub uptime_s: u32,
+    pub level: u8, // `log::Level` discriminant: 0 error, 1 warn, 2 info
+    pub text: heapless::String<LOG_TEXT_LEN>,
+}
+
+impl LogLine {
+    /// Name of `level`, as the debug shell's `log` command spells it
+    pub fn level_name(&self) -> &'static str {
+        match self.level {
+            0 => "error",
+            1 => "warn",
+            2 => "info",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Node 1 -> Node 2: log lines queued since the last batch; not ACKed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogBatch {
+    /// Lines lost to a full queue since the last batch went out
+    pub dropped: u16,
+    pub lines: Vec<LogLine, LOG_BATCH_LEN>,
+}
+
+/// Image bytes per `FirmwareChunk`; the framed chunk stays inside one
+/// payload and Node 1's receive buffer
+pub const FIRMWARE_CHUNK_LEN: usize = 192;
+/// Largest image accepted: the 128 KB the firmware may use, flash
+/// sectors 0-4 (see memory.x)
+pub const FIRMWARE_MAX_LEN: u32 = 128 * 1024;
+/// A SHA-256 of the image
+pub const FIRMWARE_DIGEST_LEN: usize = 32;
+
+/// Start an upload of an image of `len` bytes with SHA-256 `digest`
+///
+/// Signed like a `CommandPacket`, with the same counter: the host sends it
+/// with `counter` and `tag` zero and Node 2 fills them in (see
+/// `auth::firmware_tag`). The same image again resumes where Node 1 got
+/// to, across resets; any other starts over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareBegin {
+    pub len: u32,
+    pub digest: [u8; FIRMWARE_DIGEST_LEN],
+    pub counter: u32,
+    pub tag: [u8; auth::TAG_LEN],
+}
+
+impl FirmwareBegin {
+    /// The first four bytes of `digest`, which tell uploads apart in a
+    /// `FirmwareStatus`
+    pub fn id(&self) -> u32 {
+        u32::from_le_bytes([self.digest[0], self.digest[1], self.digest[2], self.digest[3]])
+    }
+}
+
+/// Image bytes from `offset`; Node 1 only takes the offset it asked for
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FirmwareChunk<'a> {
+    pub offset: u32,
+    pub data: &'a [u8],
+}
+
+// `FirmwareStatus::state` values
+/// Waiting for the chunk at `next`
+pub const FW_RECEIVING: u8 = 0;
+/// All `len` bytes arrived and the digest matched: the image is staged
+pub const FW_STAGED: u8 = 1;
+/// Over `FIRMWARE_MAX_LEN`, or all `len` bytes arrived but the digest
+/// didn't match; begin again
+pub const FW_BAD_IMAGE: u8 = 2;
+/// Node 1 has no flash to stage an image in (no `littlefs`, or no chip)
+pub const FW_NO_STORE: u8 = 3;
+/// The flash refused a write, e.g. full
+pub const FW_STORE_ERROR: u8 = 4;
+/// A chunk arrived with no upload begun
+pub const FW_IDLE: u8 = 5;
+/// The `FirmwareBegin`'s tag was wrong or its counter already used
+pub const FW_REFUSED: u8 = 6;
+
+/// Node 1 -> Node 2: where an upload stands; not ACKed, the next
+/// `FirmwareBegin` or `FirmwareChunk` asks again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareStatus {
+    /// The upload's image: length and `FirmwareBegin::id`
+    pub len: u32,
+    pub id: u32,
+    /// Bytes received so far: the offset of the chunk wanted next
+    pub next: u32,
+    pub state: u8, // `FW_*`
+}
+
+impl FirmwareStatus {
+    /// Short name of `state`
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            FW_RECEIVING => "receiving",
+            FW_STAGED => "staged",
+            FW_BAD_IMAGE => "bad image",
+            FW_NO_STORE => "no store",
+            FW_STORE_ERROR => "store error",
+            FW_IDLE => "idle",
+            FW_REFUSED => "refused",
+            _ => "unknown",
+        }
+    }
+}
+
+// `BroadcastPacket::ack` values
+/// Nobody answers: fire and forget, and no ACK storm
+pub const BROADCAST_SILENT: u8 = 0;
+/// Each node answers with a `BroadcastAck` after a random delay within
+/// `window_ms`, so the answers don't all collide
+pub const BROADCAST_ACK_SPREAD: u8 = 1;
+
+/// `BroadcastPacket::group` for every node; 1-`MAX_GROUP` name a group
+pub const BROADCAST_EVERYONE: u8 = 0;
+/// Highest group a node can be in
+pub const MAX_GROUP: u8 = 8;
+
+/// Node 2 -> every node: one message for all of them, sent once
+///
+/// `msg_type` and `body` are the message as it would be framed to a single
+/// node (e.g. a `MSG_TYPE_DISPLAY` setting or a signed `MSG_TYPE_COMMAND`).
+/// Under a broadcast, nodes send no reply of their own (no
+/// `CommandResult`); `ack` says whether they answer at all, and `group`
+/// which nodes act on it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BroadcastPacket<'a> {
+    /// Node 2's broadcast number, echoed in the ACKs
+    pub id: u16,
+    pub ack: u8, // `BROADCAST_SILENT` or `BROADCAST_ACK_SPREAD`
+    /// `BROADCAST_EVERYONE`, or the group whose members alone take it
+    pub group: u8,
+    /// Spread of the ACK delays, and of readings asked for with `ReportNow`
+    pub window_ms: u16,
+    pub msg_type: u8,
+    pub body: &'a [u8],
+}
+
+/// A node got broadcast `id`; `status` as a `CommandResult`'s for a
+/// command, `COMMAND_DONE` otherwise. Not ACKed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BroadcastAck {
+    pub id: u16,
+    pub status: u8,
+}
+
+impl BroadcastAck {
+    /// Short name of `status`
+    pub fn status_name(&self) -> &'static str {
+        command_status_name(self.status)
+    }
+}
+
+/// A new node asking Node 2 for an address; sent with address 0 and
+/// sender 0 until one comes back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JoinRequest {
+    /// The node's MCU unique ID folded to 32 bits; tells joining nodes apart
+    pub uid: u32,
+}
+
+/// Node 2's answer to a `JoinRequest`, broadcast since the node has no
+/// address yet; every other node drops it by `uid`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JoinAccept {
+    pub uid: u32,
+    /// RYLR998 `AT+ADDRESS` to take, also the node's sender ID
+    pub address: u16,
+    /// `AT+NETWORKID` to move to
+    pub network_id: u8,
+    /// Seconds between readings
+    pub tx_interval_s: u16,
+}
+
+/// A link check, answered straight away with a `Pong` by the module it is
+/// sent to; never relayed, not ACKed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ping {
+    pub id: u16,
+    /// The sender's clock; comes back in the `Pong` for the round trip
+    pub sent_ms: u32,
+}
+
+impl Ping {
+    /// The answer to this ping, heard at `rssi` and `snr`
+    pub fn answer(&self, rssi: i16, snr: i16) -> Pong {
+        Pong { id: self.id, sent_ms: self.sent_ms, rssi, snr }
+    }
+}
+
+/// The answer to a `Ping`: its `id` and `sent_ms`, and how the ping was
+/// heard, so the sender has both directions of the link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pong {
+    pub id: u16,
+    pub sent_ms: u32,
+    /// dBm
+    pub rssi: i16,
+    /// dB
+    pub snr: i16,
+}
+
+/// How the link looks from the sending end, every few minutes (see
+/// `linkstats`); not ACKed
+///
+/// Counters run from the sender's boot and wrap. Node 2 never resends, so
+/// its `retries` stay 0, and its `acks_received` counts the answers its
+/// downlinks got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkStats {
+    pub uptime_s: u32,
+    pub frames_sent: u32,
+    pub acks_received: u32,
+    pub retries: u32,
+    pub crc_failures: u32,        // Frames heard whose CRC didn't match
+    pub uart_errors: u32,         // Overrun, noise or framing errors on the module's UART
+}
+
+/// Node 1's supply is collapsing (see `brownout`)
+///
+/// Sent once from the PVD interrupt, not ACKed: silence after it is a
+/// power loss, not a radio problem.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerFailPacket {
+    pub uptime_s: u32,
+    pub last_seq: u16, // Last sensor frame sent before the collapse
+}
+
+/// A reading Node 1 stored while the link was down (see `backlog`)
+///
+/// ACKed by `reading.seq_num` like a live packet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredReading {
+    pub age_s: Option<u32>, // Seconds since the reading, `None` if Node 1 reset since
+    pub reading: SensorDataPacket,
+}
+
+/// Node 2 is missing readings `from..=to` and asks for them again
+///
+/// Not ACKed: Node 2 asks again for whatever still hasn't arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReplayRequest {
+    pub from: u16,
+    pub to: u16,
+}
+
+/// Most readings Node 1 serves for one `ReplayRequest`; longer ranges are cut
+pub const REPLAY_MAX_RANGE: u16 = 64;
+
+/// Readings per `ReplayBatch`; fewer go out when large ones wouldn't fit
+/// in one payload
+pub const REPLAY_BATCH_LEN: usize = 3;
+
+/// Readings served for a `ReplayRequest`, in sequence order; not ACKed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayBatch {
+    pub readings: Vec<StoredReading, REPLAY_BATCH_LEN>,
+}
+
+/// Why a received payload was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    TooShort,
+    /// Not `FRAME_MAGIC` and `FRAME_VERSION`, or a body length that doesn't
+    /// match the payload's
+    Header,
+    Crc { received: u16, calculated: u16 },
+    Decode,
+}
+
+/// Calculate CRC-16 checksum for data integrity
+/// Uses CRC-16-IBM-3740 (CCITT with 0xFFFF initial value)
+pub fn calculate_crc16(data: &[u8]) -> u16 {
+    use crc::{Crc, CRC_16_IBM_3740};
+    const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+    CRC16.checksum(data)
+}
+
+/// Serialize `msg` behind a frame header naming sender `node` and append
+/// the CRC
+///
+/// Returns the complete payload to hand to `AT+SEND`.
+pub fn encode_frame<'b, T: Serialize>(
+    node: u8,
+    msg_type: u8,
+    msg: &T,
+    buf: &'b mut [u8],
+) -> Result<&'b [u8], FrameError> {
+    if buf.len() < HEADER_LEN + CRC_LEN {
+        return Err(FrameError::TooShort);
+    }
+    let body_len = {
+        let end = (buf.len() - CRC_LEN).min(HEADER_LEN + u8::MAX as usize);
+        postcard::to_slice(msg, &mut buf[HEADER_LEN..end]).map_err(|_| FrameError::Decode)?.len()
+    };
+    buf[OFFSET_MAGIC] = FRAME_MAGIC;
+    buf[OFFSET_VERSION] = FRAME_VERSION;
+    buf[OFFSET_NODE] = node;
+    buf[OFFSET_HOPS] = 0;
+    buf[OFFSET_TYPE] = msg_type;
+    buf[OFFSET_LEN] = body_len as u8;
+    let data_len = HEADER_LEN + body_len;
+    Ok(seal(&mut buf[..data_len + CRC_LEN]))
+}
+
+/// Write the CRC over the rest of `frame` into its last two bytes
+fn seal(frame: &mut [u8]) -> &[u8] {
+    let data_len = frame.len() - CRC_LEN;
+    let crc = calculate_crc16(&frame[..data_len]);
+    frame[data_len] = (crc >> 8) as u8;       // High byte
+    frame[data_len + 1] = (crc & 0xFF) as u8; // Low byte
+    frame
+}
+
+/// A checked frame copied into `buf` one hop further on, CRC redone, for
+/// a relay to send again; `FrameError::Header` once it has had `MAX_HOPS`
+pub fn relay_frame<'b>(payload: &[u8], buf: &'b mut [u8]) -> Result<&'b [u8], FrameError> {
+    decode_frame(payload)?;
+    if payload[OFFSET_HOPS] & HOPS_MASK >= MAX_HOPS {
+        return Err(FrameError::Header);
+    }
+    let frame = buf.get_mut(..payload.len()).ok_or(FrameError::TooShort)?;
+    frame.copy_from_slice(payload);
+    frame[OFFSET_HOPS] += 1;
+    Ok(seal(frame))
+}
+
+/// A checked frame that hasn't been relayed yet, copied into `buf` with
+/// `route` inserted after its header and the CRC redone
+///
+/// `route` names the relays in the order the frame is to pass them, at
+/// most `MAX_ROUTE`; the sender hands the result to the first of them.
+/// `FrameError::TooShort` if `buf` can't hold the longer frame.
+pub fn route_frame<'b>(payload: &[u8], route: &[u8], buf: &'b mut [u8]) -> Result<&'b [u8], FrameError> {
+    decode_frame(payload)?;
+    if route.len() > MAX_ROUTE || payload[OFFSET_HOPS] != 0 {
+        return Err(FrameError::Header);
+    }
+    let frame = buf.get_mut(..payload.len() + route.len()).ok_or(FrameError::TooShort)?;
+    let body_at = HEADER_LEN + route.len();
+    frame[..HEADER_LEN].copy_from_slice(&payload[..HEADER_LEN]);
+    frame[HEADER_LEN..body_at].copy_from_slice(route);
+    frame[body_at..].copy_from_slice(&payload[HEADER_LEN..]);
+    frame[OFFSET_HOPS] = (route.len() as u8) << ROUTE_SHIFT;
+    Ok(seal(frame))
+}
+
+/// Validate a CRC-protected payload and split it into type and body
+pub fn decode_frame(payload: &[u8]) -> Result<(u8, &[u8]), FrameError> {
+    if payload.len() < HEADER_LEN + CRC_LEN {
+        return Err(FrameError::TooShort);
+    }
+    let route_len = route_len(payload[OFFSET_HOPS]);
+    if payload[OFFSET_MAGIC] != FRAME_MAGIC
+        || payload[OFFSET_VERSION] != FRAME_VERSION
+        || route_len > MAX_ROUTE
+        || HEADER_LEN + route_len + payload[OFFSET_LEN] as usize + CRC_LEN != payload.len()
+    {
+        return Err(FrameError::Header);
+    }
+
+    let data_len = payload.len() - CRC_LEN;
+    let data = &payload[..data_len];
+    let received = ((payload[data_len] as u16) << 8) | (payload[data_len + 1] as u16);
+    let calculated = calculate_crc16(data);
+
+    if received != calculated {
+        return Err(FrameError::Crc { received, calculated });
+    }
+
+    Ok((data[OFFSET_TYPE], &data[HEADER_LEN + route_len..]))
+}
+
+/// Route length from a frame's hops byte
+fn route_len(hops: u8) -> usize {
+    ((hops >> ROUTE_SHIFT) & ROUTE_MASK) as usize
+}
+
+/// The sender of a framed payload, before any checks; `None` for ACK/NACK,
+/// which only ever come from Node 2
+pub fn frame_node(payload: &[u8]) -> Option<u8> {
+    match payload {
+        [FRAME_MAGIC, _, node, ..] => Some(*node),
+        _ => None,
+    }
+}
+
+/// Relays a framed payload has passed through, before any checks
+pub fn frame_hops(payload: &[u8]) -> Option<u8> {
+    match payload {
+        [FRAME_MAGIC, _, _, hops, ..] => Some(hops & HOPS_MASK),
+        _ => None,
+    }
+}
+
+/// The relays a framed payload is routed through, empty if it isn't;
+/// `None` if it is cut short. Before any other checks.
+pub fn frame_route(payload: &[u8]) -> Option<&[u8]> {
+    match payload {
+        [FRAME_MAGIC, _, _, hops, ..] => payload.get(HEADER_LEN..HEADER_LEN + route_len(*hops)),
+        _ => None,
+    }
+}
+
+/// The message type of a payload, framed or ACK/NACK, before any checks
+pub fn frame_type(payload: &[u8]) -> Option<u8> {
+    match payload {
+        [FRAME_MAGIC, _, _, _, msg_type, ..] => Some(*msg_type),
+        [msg_type @ (MSG_TYPE_ACK | MSG_TYPE_NACK), ..] => Some(*msg_type),
+        _ => None,
+    }
+}
@@ -0,0 +1,75 @@
+//! `topic<TAB>payload` lines for an MQTT bridge
+//!
+//! Node 2's `mqtt on` and the host's `wk3 mqtt` print each live reading as
+//! one line per quantity, so a few lines of shell can republish them to a
+//! broker (and from there to Home Assistant) without knowing the protocol:
+//!
+//! ```text
+//! sensors/n1/temperature<TAB>27.10
+//! sensors/n1/humidity<TAB>56.00
+//! sensors/n1/gas<TAB>51234
+//! sensors/n1/rssi<TAB>-71
+//! ```
+//!
+//! Topic and payload are separated by a single tab. Payloads are bare
+//! numbers. A quantity the reading doesn't have (no SCD40, a failed probe)
+//! gets no line rather than an empty payload, so the broker keeps its last
+//! good value. Lines end in `\n` alone.
+
+use core::fmt::{self, Display, Write};
+
+/// First topic level
+pub const PREFIX: &str = "sensors";
+/// Second topic level for readings from Node 1
+pub const NODE_1: &str = "n1";
+
+/// A reading in engineering units, with the link figures it arrived with
+#[derive(Debug, Clone, Copy)]
+pub struct Reading<'a> {
+    pub seq: u16,
+    pub temperature_c: f32,
+    pub humidity_pct: f32,
+    pub gas_ohms: u32,
+    pub iaq: Option<u16>,
+    pub co2_ppm: Option<u16>,
+    pub battery_mv: Option<u16>,
+    /// DS18B20 probes in send order; topics `probe1`, `probe2`, ...
+    pub probes_c: &'a [Option<f32>],
+    /// Soil probes in order; topics `soil1`, `soil2`, ...
+    pub soil_pct: &'a [Option<u8>],
+    pub rssi: i16,
+    pub snr: i16,
+}
+
+/// Write `reading` from `node` as lines
+pub fn write_lines<W: Write>(out: &mut W, node: &str, reading: &Reading) -> fmt::Result {
+    line(out, node, "temperature", format_args!("{:.2}", reading.temperature_c))?;
+    line(out, node, "humidity", format_args!("{:.2}", reading.humidity_pct))?;
+    line(out, node, "gas", reading.gas_ohms)?;
+    if let Some(iaq) = reading.iaq {
+        line(out, node, "iaq", iaq)?;
+    }
+    if let Some(co2) = reading.co2_ppm {
+        line(out, node, "co2", co2)?;
+    }
+    if let Some(mv) = reading.battery_mv {
+        line(out, node, "battery", mv)?;
+    }
+    for (i, probe) in reading.probes_c.iter().enumerate() {
+        if let Some(c) = probe.filter(|c| c.is_finite()) {
+            line(out, node, format_args!("probe{}", i + 1), format_args!("{:.2}", c))?;
+        }
+    }
+    for (i, soil) in reading.soil_pct.iter().enumerate() {
+        if let Some(pct) = soil {
+            line(out, node, format_args!("soil{}", i + 1), pct)?;
+        }
+    }
+    line(out, node, "rssi", reading.rssi)?;
+    line(out, node, "snr", reading.snr)?;
+    line(out, node, "seq", reading.seq)
+}
+
+fn line<W: Write>(out: &mut W, node: &str, topic: impl Display, value: impl Display) -> fmt::Result {
+    writeln!(out, "{}/{}/{}\t{}", PREFIX, node, topic, value)
+}
@@ -0,0 +1,173 @@
+//! Binary records on a serial line
+//!
+//! A node's debug UART carries shell text as well as binary data, so each
+//! record is framed for a host to find and check in the byte stream:
+//!
+//! ```text
+//! [0xA5 0x5A][len u16 LE][header (6)][postcard body ...][CRC-16 u16 BE]
+//!            \_ covers ->|<--------- a radio frame, see the crate --------->|
+//! ```
+//!
+//! `len` counts the header, body and CRC, which are exactly what
+//! `encode_frame` produces for the radio, so one decoder serves both. Two
+//! kinds of stream use it:
+//!
+//! - Node 1's `export`: an `ExportBegin`, its stored readings
+//!   (`MSG_TYPE_STORED`) and crash reports (`MSG_TYPE_CRASH_REPORT`), then
+//!   an `ExportEnd`.
+//! - Node 2's `stream on`: a `RadioFrame` for every payload it receives,
+//!   holding the payload as it came off the air, CRC errors included.
+//!   `stream tap` adds a `RawLine` for every other line from the LoRa
+//!   module, such as a malformed `+RCV` or an `+OK`.
+//!
+//! A host that loses sync skips to the next `0xA5 0x5A` whose CRC checks.
+
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_frame, encode_frame, FrameError, CRC_LEN, HEADER_LEN};
+
+pub const SYNC: [u8; 2] = [0xA5, 0x5A];
+/// Record types outside the radio protocol's range
+pub const EXPORT_BEGIN: u8 = 0x80;
+pub const EXPORT_END: u8 = 0x81;
+pub const RADIO_FRAME: u8 = 0x82;
+pub const RAW_LINE: u8 = 0x83;
+/// Sync and length ahead of each record
+pub const PREFIX_LEN: usize = SYNC.len() + 2;
+
+/// First record of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExportBegin {
+    /// Node uptime when the export started; stored ages are relative to it
+    pub uptime_s: u32,
+    /// Backlog slots in use, including any unreadable ones
+    pub readings: u32,
+    /// Crash log slots in use
+    pub crashes: u32,
+}
+
+/// Last record of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExportEnd {
+    /// Records sent between `ExportBegin` and this one
+    pub records: u32,
+}
+
+/// A payload Node 2 received, with the link figures the module gave it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RadioFrame<'a> {
+    /// Node 2's uptime at reception
+    pub rx_time_ms: u32,
+    pub rssi: i16,
+    pub snr: i16,
+    /// The radio frame, to be checked with `decode_frame`
+    pub payload: &'a [u8],
+}
+
+/// A line from the LoRa module that isn't a well-formed `+RCV`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RawLine<'a> {
+    /// Node 2's uptime at reception
+    pub rx_time_ms: u32,
+    /// As received, line ending included
+    pub line: &'a [u8],
+}
+
+/// Frame `record` as type `msg_type` from `node` into `buf`; the bytes to
+/// write out
+pub fn encode<'b, T: Serialize>(
+    node: u8,
+    msg_type: u8,
+    record: &T,
+    buf: &'b mut [u8],
+) -> Result<&'b [u8], FrameError> {
+    if buf.len() < PREFIX_LEN {
+        return Err(FrameError::TooShort);
+    }
+    let (prefix, rest) = buf.split_at_mut(PREFIX_LEN);
+    let len = encode_frame(node, msg_type, record, rest)?.len();
+    prefix[..2].copy_from_slice(&SYNC);
+    prefix[2..].copy_from_slice(&(len as u16).to_le_bytes());
+    Ok(&buf[..PREFIX_LEN + len])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Sync0,
+    Sync1,
+    Len(Option<u8>),
+    Body(usize),
+}
+
+/// Picks records out of a byte stream, for hosts and Node 2's `ingest`
+///
+/// `N` is the longest record accepted; longer lengths are taken for noise.
+pub struct Deframer<const N: usize> {
+    state: State,
+    buf: Vec<u8, N>,
+    /// Records dropped for a bad CRC or length
+    errors: u32,
+}
+
+impl<const N: usize> Deframer<N> {
+    pub const fn new() -> Self {
+        Self { state: State::Sync0, buf: Vec::new(), errors: 0 }
+    }
+
+    /// Feed one byte; a complete record whose CRC checks comes back as
+    /// `[header][body][CRC]`, ready for `decode_frame`
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        self.state = match self.state {
+            State::Sync0 if byte == SYNC[0] => State::Sync1,
+            State::Sync0 => State::Sync0,
+            State::Sync1 if byte == SYNC[1] => State::Len(None),
+            // A repeated first sync byte may still start a record
+            State::Sync1 if byte == SYNC[0] => State::Sync1,
+            State::Sync1 => State::Sync0,
+            State::Len(None) => State::Len(Some(byte)),
+            State::Len(Some(low)) => {
+                let len = u16::from_le_bytes([low, byte]) as usize;
+                if len < HEADER_LEN + CRC_LEN || len > N {
+                    self.errors += 1;
+                    State::Sync0
+                } else {
+                    self.buf.clear();
+                    State::Body(len)
+                }
+            }
+            State::Body(len) => {
+                let _ = self.buf.push(byte);
+                if self.buf.len() < len {
+                    State::Body(len)
+                } else {
+                    self.state = State::Sync0;
+                    if decode_frame(&self.buf).is_ok() {
+                        return Some(&self.buf);
+                    }
+                    self.errors += 1;
+                    State::Sync0
+                }
+            }
+        };
+        None
+    }
+
+    /// Not part-way through a record
+    pub fn idle(&self) -> bool {
+        self.state == State::Sync0
+    }
+
+    /// Records dropped so far
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+}
+
+impl<const N: usize> Default for Deframer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
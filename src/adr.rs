@@ -0,0 +1,230 @@
+//! Adaptive data rate: Node 2 trimming links that have margin to spare (`adr`)
+//!
+//! A node that Node 2 hears 20 dB above the noise floor is spending battery
+//! it doesn't need to. With `adr on`, Node 2 averages each node's SNR over
+//! `WINDOW` readings and compares it with what the demodulator needs at the
+//! current spreading factor. More than `HIGH_MARGIN_DB` to spare and the
+//! node is told to drop its TX power by `POWER_STEP_DBM`; less than
+//! `LOW_MARGIN_DB` and it is told to raise it again. The band between the
+//! two, and a fresh window after every step, keep it from see-sawing.
+//!
+//! The spreading factor can't differ between nodes: the gateway's module
+//! only listens at one. It is stepped for the whole network by a broadcast
+//! (see `broadcast`), after which Node 2 follows: down once every node
+//! heard lately is at `MIN_TX_POWER_DBM` with margin for the 2.5 dB a step
+//! costs, up as soon as a node at full power runs short. Commands carry
+//! absolute values, so one that is lost is put right by the next.
+//!
+//! Steps stay inside what a node would accept (see `command`): power up
+//! to the region's limit, spreading factors up to what the module runs at
+//! the bandwidth in use.
+//!
+//! Either end falls back if a step leaves the link dead. A node that had
+//! its settings changed and then gets no ACK for `FALLBACK_MISSES` readings
+//! in a row returns to full power and its boot spreading factor; Node 2
+//! returns to its boot spreading factor after `FALLBACK_MS` without a
+//! reading from anyone. Both then meet where they started.
+
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use heapless::Vec;
+
+use crate::airtime::LoraParams;
+use crate::peers::MAX_PEERS;
+use crate::region;
+
+/// Spreading factors a node accepts
+pub const SF_RANGE: RangeInclusive<u8> = 7..=12;
+/// Lowest TX power ADR asks for
+pub const MIN_TX_POWER_DBM: u8 = 4;
+/// One power step
+const POWER_STEP_DBM: u8 = 3;
+/// SNR margin that calls for saving power, and the one that calls for more
+const HIGH_MARGIN_DB: f32 = 10.0;
+const LOW_MARGIN_DB: f32 = 4.0;
+/// What a spreading factor step costs or gains in required SNR
+const SF_STEP_DB: f32 = 2.5;
+/// Readings averaged before a node's link is judged
+const WINDOW: u8 = 8;
+/// Weight of each reading in the SNR average
+const SNR_WEIGHT: f32 = 1.0 / 4.0;
+/// A node not heard for this long doesn't hold the spreading factor up
+const STALE_MS: u32 = 600_000;
+/// Node 2 returns to its boot spreading factor after this long unheard
+const FALLBACK_MS: u32 = 300_000;
+/// Readings a node lets go unanswered before returning to its boot settings
+pub const FALLBACK_MISSES: u8 = 3;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ADJUSTED: AtomicBool = AtomicBool::new(false);
+static MISSES: AtomicU8 = AtomicU8::new(0);
+
+/// Node 2: step the nodes' settings to their links
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// SNR in dB the demodulator needs at `sf`
+pub fn required_snr_db(sf: u8) -> f32 {
+    -7.5 - SF_STEP_DB * (sf.saturating_sub(7)) as f32
+}
+
+/// Node: Node 2 changed this node's TX power or spreading factor
+pub fn note_adjusted() {
+    ADJUSTED.store(true, Ordering::Relaxed);
+}
+
+/// Node: a reading was ACKed
+pub fn note_answered() {
+    MISSES.store(0, Ordering::Relaxed);
+}
+
+/// Node: a reading went unanswered; `true` when it's time to fall back to
+/// the boot settings, once per adjustment
+pub fn note_missed() -> bool {
+    let misses = MISSES.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+    if misses < FALLBACK_MISSES || !ADJUSTED.swap(false, Ordering::Relaxed) {
+        return false;
+    }
+    MISSES.store(0, Ordering::Relaxed);
+    true
+}
+
+/// A command for the nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Step {
+    /// To one node: cap its TX power
+    TxPower { node: u8, dbm: u8 },
+    /// To every node, then Node 2 itself
+    SpreadingFactor(u8),
+}
+
+/// What Node 2 knows of one node's link
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct Link {
+    pub node: u8,
+    /// The power last asked of it
+    pub tx_power_dbm: u8,
+    pub snr_avg: f32,
+    /// Readings since the last step
+    readings: u8,
+    last_ms: u32,
+}
+
+impl Link {
+    fn judged(&self) -> bool {
+        self.readings >= WINDOW
+    }
+}
+
+/// Node 2's ADR state
+#[derive(Debug, Clone)]
+pub struct Adr {
+    links: Vec<Link, MAX_PEERS>,
+    sf: u8,
+    boot_sf: u8,
+    /// Highest spreading factor the module runs at the bandwidth
+    max_sf: u8,
+    last_heard_ms: u32,
+}
+
+impl Adr {
+    /// Starting from the modem settings the module was set up with
+    pub fn new(params: LoraParams) -> Self {
+        let sf = params.spreading_factor;
+        let max_sf = params.max_spreading_factor().min(*SF_RANGE.end());
+        Self { links: Vec::new(), sf, boot_sf: sf, max_sf, last_heard_ms: 0 }
+    }
+
+    /// The spreading factor the network is on
+    pub fn sf(&self) -> u8 {
+        self.sf
+    }
+
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    /// A live reading from `node` with `snr`; the step its link calls for,
+    /// if any
+    pub fn record(&mut self, node: u8, snr: i16, now_ms: u32) -> Option<Step> {
+        self.last_heard_ms = now_ms;
+        let index = match self.links.iter().position(|l| l.node == node) {
+            Some(index) => index,
+            None => {
+                if self.links.is_full() {
+                    let stalest = (0..self.links.len())
+                        .max_by_key(|&i| now_ms.wrapping_sub(self.links[i].last_ms))
+                        .unwrap_or(0);
+                    self.links.remove(stalest);
+                }
+                let fresh = Link { node, tx_power_dbm: region::max_tx_dbm(), snr_avg: snr as f32, readings: 0, last_ms: now_ms };
+                let _ = self.links.push(fresh);
+                self.links.len() - 1
+            }
+        };
+        let link = &mut self.links[index];
+        link.snr_avg += (snr as f32 - link.snr_avg) * SNR_WEIGHT;
+        link.readings = link.readings.saturating_add(1);
+        link.last_ms = now_ms;
+        if !enabled() || !link.judged() {
+            return None;
+        }
+
+        let margin = link.snr_avg - required_snr_db(self.sf);
+        if margin < LOW_MARGIN_DB {
+            if link.tx_power_dbm < region::max_tx_dbm() {
+                link.tx_power_dbm = (link.tx_power_dbm + POWER_STEP_DBM).min(region::max_tx_dbm());
+                link.readings = 0;
+                return Some(Step::TxPower { node, dbm: link.tx_power_dbm });
+            }
+            if self.sf < self.max_sf {
+                return Some(self.step_sf(self.sf + 1));
+            }
+        } else if margin > HIGH_MARGIN_DB {
+            if link.tx_power_dbm > MIN_TX_POWER_DBM {
+                link.tx_power_dbm = link.tx_power_dbm.saturating_sub(POWER_STEP_DBM).max(MIN_TX_POWER_DBM);
+                link.readings = 0;
+                return Some(Step::TxPower { node, dbm: link.tx_power_dbm });
+            }
+            // Only when no node heard lately would run short one step down
+            let floor = required_snr_db(self.sf - 1) + HIGH_MARGIN_DB;
+            let all_spare = self
+                .links
+                .iter()
+                .filter(|l| now_ms.wrapping_sub(l.last_ms) < STALE_MS)
+                .all(|l| l.judged() && l.tx_power_dbm == MIN_TX_POWER_DBM && l.snr_avg > floor);
+            if self.sf > *SF_RANGE.start() && all_spare {
+                return Some(self.step_sf(self.sf - 1));
+            }
+        }
+        None
+    }
+
+    /// The boot spreading factor, if the network has gone quiet on another
+    pub fn fallback(&mut self, now_ms: u32) -> Option<u8> {
+        if self.sf == self.boot_sf || now_ms.wrapping_sub(self.last_heard_ms) < FALLBACK_MS {
+            return None;
+        }
+        self.sf = self.boot_sf;
+        for link in &mut self.links {
+            link.tx_power_dbm = region::max_tx_dbm();
+            link.readings = 0;
+        }
+        Some(self.sf)
+    }
+
+    /// Every link needs a fresh window at the new spreading factor
+    fn step_sf(&mut self, sf: u8) -> Step {
+        self.sf = sf;
+        for link in &mut self.links {
+            link.readings = 0;
+        }
+        Step::SpreadingFactor(sf)
+    }
+}
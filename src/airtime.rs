@@ -0,0 +1,282 @@
+//! Duty-cycle accounting for regulated bands
+//!
+//! In Europe the 863-870 MHz band allows a transmitter on air for 1% of
+//! any hour (ETSI EN 300 220), 433 MHz for 10%. `Accountant` keeps the
+//! airtime each node spent over the last hour, in one-minute buckets, and
+//! says whether the next frame still fits; callers that can wait are told
+//! how long, the rest drop the frame. Every refusal is logged and counted.
+//!
+//! Airtime comes from the LoRa time-on-air formula (Semtech AN1200.13)
+//! with the module's `AT+PARAMETER` settings, for the bytes handed to the
+//! module; whatever header the RYLR998 adds is not known, so a limit set
+//! by hand should leave some margin.
+//!
+//! The limit follows the configured band unless set with the shell's
+//! `airtime` command, and is never looser than the build's region's: a
+//! looser one is refused. The window runs on the monotonic, which stands
+//! still in STOP on a low-power Node 1; time it sleeps isn't credited, so
+//! the budget there is only ever stricter than the rule.
+//!
+//! The limit also sets a floor under Node 1's interval: a reading every
+//! `interval_floor_s` seconds is as often as its largest frame can go.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use crate::protocol::ConfigError;
+use crate::region;
+
+/// Minutes in the sliding window
+const BUCKETS: usize = 60;
+const MINUTE_MS: u32 = 60_000;
+/// Budget per permille of duty cycle: a thousandth of an hour
+const US_PER_PERMILLE: u64 = 3_600_000;
+
+/// `OVERRIDE` value for "follow the band"
+const AUTO: u16 = u16::MAX;
+
+static BAND_MHZ: AtomicU16 = AtomicU16::new(0);
+static OVERRIDE: AtomicU16 = AtomicU16::new(AUTO);
+
+/// The regulatory limit for a band, in permille; 0 means none
+///
+/// Only the ETSI bands are known. US915 limits dwell time per channel
+/// rather than duty cycle, which a single-channel link doesn't hit.
+pub fn band_limit_permille(band_mhz: u16) -> u16 {
+    match band_mhz {
+        863..=870 => 10,
+        433..=434 => 100,
+        _ => 0,
+    }
+}
+
+/// The band in use, for the default limit
+pub fn set_band(band_mhz: u16) {
+    BAND_MHZ.store(band_mhz, Ordering::Relaxed);
+}
+
+/// A limit in permille (0 = none), or `None` to follow the band again;
+/// one looser than the region's is refused
+pub fn set_limit(permille: Option<u16>) -> Result<(), ConfigError> {
+    match (permille, region::duty_permille()) {
+        (Some(p), _) if p > 1000 => return Err(ConfigError::OutOfRange),
+        (Some(p), Some(cap)) if p == 0 || p > cap => return Err(ConfigError::DutyCycleAboveRegion),
+        _ => {}
+    }
+    OVERRIDE.store(permille.unwrap_or(AUTO), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Duty-cycle limit in force, in permille; 0 means none
+///
+/// Never looser than the region's (see `region`).
+pub fn limit_permille() -> u16 {
+    let limit = match OVERRIDE.load(Ordering::Relaxed) {
+        AUTO => band_limit_permille(BAND_MHZ.load(Ordering::Relaxed)),
+        permille => permille,
+    };
+    match region::duty_permille() {
+        Some(cap) if limit == 0 || limit > cap => cap,
+        _ => limit,
+    }
+}
+
+/// Whether the limit was set by hand rather than from the band
+pub fn limit_overridden() -> bool {
+    OVERRIDE.load(Ordering::Relaxed) != AUTO
+}
+
+/// Shortest interval between `len`-byte frames at `params` that the limit
+/// in force allows, in whole seconds; 0 without a limit
+pub fn interval_floor_s(params: &LoraParams, len: usize) -> u16 {
+    match limit_permille() {
+        0 => 0,
+        permille => {
+            let floor_us = u64::from(params.time_on_air_us(len)) * 1000 / u64::from(permille);
+            floor_us.div_ceil(1_000_000).min(u64::from(u16::MAX)) as u16
+        }
+    }
+}
+
+/// Modem settings, as in `AT+PARAMETER=<sf>,<bw>,<cr>,<preamble>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct LoraParams {
+    pub spreading_factor: u8,
+    pub bandwidth_hz: u32,
+    /// Coding rate 4/(4+n), n = 1-4
+    pub coding_rate: u8,
+    pub preamble: u16,
+}
+
+impl LoraParams {
+    /// Parse the `AT+PARAMETER` argument, e.g. `"7,9,1,7"`
+    pub fn parse(parameter: &str) -> Option<Self> {
+        let mut fields = parameter.split(',').map(|f| f.trim().parse::<u16>().ok());
+        let spreading_factor = fields.next()??;
+        let bandwidth_hz = match fields.next()?? {
+            7 => 125_000,
+            8 => 250_000,
+            9 => 500_000,
+            _ => return None,
+        };
+        let coding_rate = fields.next()??;
+        let preamble = fields.next()??;
+        if !(5..=12).contains(&spreading_factor) || !(1..=4).contains(&coding_rate) {
+            return None;
+        }
+        Some(Self { spreading_factor: spreading_factor as u8, bandwidth_hz, coding_rate: coding_rate as u8, preamble })
+    }
+
+    /// Highest spreading factor the RYLR998 takes at this bandwidth: SF9
+    /// at 125 kHz, SF10 at 250, SF11 at 500
+    pub fn max_spreading_factor(&self) -> u8 {
+        match self.bandwidth_hz {
+            125_000 => 9,
+            250_000 => 10,
+            _ => 11,
+        }
+    }
+
+    /// The same settings at spreading factor `sf`
+    pub fn with_spreading_factor(self, sf: u8) -> Self {
+        Self { spreading_factor: sf, ..self }
+    }
+
+    /// The `AT+PARAMETER` argument, as `parse` takes it
+    pub fn write_parameter<W: core::fmt::Write>(&self, out: &mut W) -> core::fmt::Result {
+        let bandwidth = match self.bandwidth_hz {
+            125_000 => 7,
+            250_000 => 8,
+            _ => 9,
+        };
+        write!(out, "{},{},{},{}", self.spreading_factor, bandwidth, self.coding_rate, self.preamble)
+    }
+
+    /// Time on air for a `len`-byte payload, explicit header and CRC on
+    pub fn time_on_air_us(&self, len: usize) -> u32 {
+        let sf = self.spreading_factor as i32;
+        let symbol_us = (1u32 << sf) * 1_000_000 / self.bandwidth_hz;
+        // Low data rate optimisation, which the module turns on above 16 ms symbols
+        let ldro = (symbol_us > 16_000) as i32;
+
+        let bits = 8 * len as i32 - 4 * sf + 28 + 16;
+        let per_block = 4 * (sf - 2 * ldro);
+        let blocks = ((bits + per_block - 1) / per_block).max(0) as u32;
+        let payload_symbols = 8 + blocks * (self.coding_rate as u32 + 4);
+
+        // Preamble plus 4.25 symbols of sync word, in quarter symbols
+        let preamble_us = (self.preamble as u32 * 4 + 17) * symbol_us / 4;
+        preamble_us + payload_symbols * symbol_us
+    }
+}
+
+/// Whether a frame may go out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Admit {
+    Now,
+    /// Once this many ms of old airtime have left the window
+    After(u32),
+    /// Longer than the whole hour's budget
+    Never,
+}
+
+pub struct Accountant {
+    params: LoraParams,
+    /// Airtime per minute, indexed by minute modulo `BUCKETS`
+    buckets: [u32; BUCKETS],
+    minute: u32,
+    refused: u32,
+}
+
+impl Accountant {
+    pub const fn new(params: LoraParams) -> Self {
+        Self { params, buckets: [0; BUCKETS], minute: 0, refused: 0 }
+    }
+
+    pub fn params(&self) -> LoraParams {
+        self.params
+    }
+
+    /// Charge later frames at new modem settings
+    pub fn set_params(&mut self, params: LoraParams) {
+        self.params = params;
+    }
+
+    /// Whether a `len`-byte frame fits at `now_ms`, without charging it
+    pub fn check(&mut self, now_ms: u32, len: usize) -> Admit {
+        self.advance(now_ms);
+        let limit = limit_permille();
+        if limit == 0 {
+            return Admit::Now;
+        }
+        let budget = limit as u64 * US_PER_PERMILLE;
+        let airtime = self.params.time_on_air_us(len) as u64;
+        let used = self.used();
+        if used + airtime <= budget {
+            return Admit::Now;
+        }
+
+        let admit = if airtime > budget {
+            Admit::Never
+        } else {
+            // Oldest minute first: when does enough of it age out?
+            let mut left = used;
+            let mut admit = Admit::Never;
+            for age in 0..BUCKETS as u32 {
+                let expires = self.minute + 1 + age;
+                left -= self.buckets[expires as usize % BUCKETS] as u64;
+                if left + airtime <= budget {
+                    admit = Admit::After((expires * MINUTE_MS).wrapping_sub(now_ms));
+                    break;
+                }
+            }
+            admit
+        };
+        self.refused = self.refused.wrapping_add(1);
+        defmt::warn!("Duty cycle {}/1000: {}us frame refused ({}), {}us of {}us used this hour",
+            limit, airtime, admit, used, budget);
+        admit
+    }
+
+    /// Charge a `len`-byte frame that was sent at `now_ms`
+    pub fn record(&mut self, now_ms: u32, len: usize) {
+        self.advance(now_ms);
+        let bucket = &mut self.buckets[self.minute as usize % BUCKETS];
+        *bucket = bucket.saturating_add(self.params.time_on_air_us(len));
+    }
+
+    /// `check`, and charge the frame if it may go out now
+    pub fn admit(&mut self, now_ms: u32, len: usize) -> Admit {
+        let admit = self.check(now_ms, len);
+        if admit == Admit::Now {
+            self.record(now_ms, len);
+        }
+        admit
+    }
+
+    /// Airtime over the last hour, in µs
+    pub fn used_us(&mut self, now_ms: u32) -> u64 {
+        self.advance(now_ms);
+        self.used()
+    }
+
+    /// Frames refused since boot
+    pub fn refused(&self) -> u32 {
+        self.refused
+    }
+
+    fn used(&self) -> u64 {
+        self.buckets.iter().map(|&us| us as u64).sum()
+    }
+
+    /// Empty the buckets of minutes that have passed since the last call
+    ///
+    /// The millisecond clock wrapping after 49 days clears the window.
+    fn advance(&mut self, now_ms: u32) {
+        let minute = now_ms / MINUTE_MS;
+        let passed = minute.wrapping_sub(self.minute).min(BUCKETS as u32);
+        for step in 1..=passed {
+            self.buckets[(self.minute.wrapping_add(step)) as usize % BUCKETS] = 0;
+        }
+        self.minute = minute;
+    }
+}
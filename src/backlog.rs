@@ -0,0 +1,233 @@
+//! Flash history of sent readings: store-and-forward and replay on request
+//!
+//! A reading whose ACK doesn't come within `MAX_RETRIES` used to be gone,
+//! so every outage left a hole in Node 2's data. Now every reading Node 1
+//! sends goes into flash sector 5 with its uptime, and stays marked pending
+//! until its ACK arrives. After a loss the next ACK shows the link is back,
+//! and the pending readings are replayed oldest first as `StoredReading`
+//! frames (`MSG_TYPE_STORED`), each waiting for its own ACK like a live one.
+//!
+//! Acknowledged readings stay in the sector too, so Node 2 can ask for a
+//! range it's still missing (a `ReplayRequest`); `lookup` finds them by
+//! sequence number. Only readings from this boot are served, since the
+//! numbers start again after a reset.
+//!
+//! The sector is an append-only array of 96-byte slots:
+//!   [magic u32][state u32][uptime_s u32][len u32][postcard SensorDataPacket ...]
+//! as in the crash log, `state` starts erased (pending) and is programmed to
+//! 0 once Node 2 has the reading. That's 1365 readings, 3.8 hours at the
+//! default 10 s interval and far more with send-on-delta. When every slot
+//! is used the sector is erased if nothing in it is pending; otherwise new
+//! readings are not stored, so the oldest data survives. At a reading every
+//! 10 s that's an erase every 3.8 hours, and the sector's 10,000 rated
+//! erase cycles last about four years. Records outlive a reset; their age
+//! just can't be told once the uptime has restarted.
+//!
+//! A reading whose ACK alone was lost reaches Node 2 twice, the second
+//! time as a backfill with the same sequence number.
+
+use crate::flash::{self, BACKLOG_SECTOR};
+use crate::protocol::{SensorDataPacket, StoredReading};
+
+const SLOT_SIZE: u32 = 96;
+const SLOT_HEADER: u32 = 16;
+const SLOT_COUNT: u32 = BACKLOG_SECTOR.size / SLOT_SIZE;
+const SLOT_MAGIC: u32 = 0x424B_4C47; // "BKLG"
+const STATE_PENDING: u32 = 0xFFFF_FFFF;
+const STATE_SENT: u32 = 0;
+const ERASED: u32 = 0xFFFF_FFFF;
+
+fn slot_addr(index: u32) -> u32 {
+    BACKLOG_SECTOR.base + index * SLOT_SIZE
+}
+
+fn is_pending(index: u32) -> bool {
+    let addr = slot_addr(index);
+    flash::read_word(addr) == SLOT_MAGIC && flash::read_word(addr + 4) == STATE_PENDING
+}
+
+/// A slot's uptime and reading, if it holds one that decodes
+fn read_slot(index: u32) -> Option<(u32, SensorDataPacket)> {
+    let addr = slot_addr(index);
+    if flash::read_word(addr) != SLOT_MAGIC {
+        return None;
+    }
+    let len = flash::read_word(addr + 12) as usize;
+    let body = flash::read_bytes(addr + SLOT_HEADER, len.min((SLOT_SIZE - SLOT_HEADER) as usize));
+    let reading = postcard::from_bytes(body).ok()?;
+    Some((flash::read_word(addr + 8), reading))
+}
+
+/// A slot's sequence number, read without decoding the whole reading
+fn slot_seq(index: u32) -> Option<u16> {
+    let addr = slot_addr(index);
+    if flash::read_word(addr) != SLOT_MAGIC {
+        return None;
+    }
+    // `seq_num` is the packet's first field
+    let body = flash::read_bytes(addr + SLOT_HEADER, 3);
+    postcard::take_from_bytes::<u16>(body).ok().map(|(seq, _)| seq)
+}
+
+pub struct Backlog {
+    /// First erased slot
+    end: u32,
+    /// No pending slot comes before this one
+    cursor: u32,
+    /// First slot written since boot; older uptimes are from another run
+    boot_start: u32,
+    /// Slot and sequence number of the live reading awaiting its ACK
+    live: Option<(u32, u16)>,
+    /// Slot and sequence number of the replayed reading awaiting its ACK
+    in_flight: Option<(u32, u16)>,
+    /// Readings not stored since boot because the sector was full
+    dropped: u32,
+}
+
+impl Backlog {
+    /// Find where the sector left off; call once from `init`
+    pub fn load() -> Self {
+        let end = (0..SLOT_COUNT)
+            .find(|&i| flash::read_word(slot_addr(i)) == ERASED)
+            .unwrap_or(SLOT_COUNT);
+        let mut backlog = Self { end, cursor: 0, boot_start: end, live: None, in_flight: None, dropped: 0 };
+        backlog.skip_sent();
+        backlog
+    }
+
+    /// Readings waiting for the link, counting one in flight
+    pub fn pending(&self) -> u32 {
+        (self.cursor..self.end).filter(|&i| is_pending(i)).count() as u32
+    }
+
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// A live reading just went out at `uptime_s`: store it, pending its ACK
+    pub fn sent_live(&mut self, uptime_s: u32, packet: &SensorDataPacket) {
+        self.live = self.store(uptime_s, packet).map(|slot| (slot, packet.seq_num));
+    }
+
+    /// Node 2 acknowledged `seq`; true if readings are waiting to be replayed
+    pub fn acked(&mut self, seq: u16) -> bool {
+        for entry in [&mut self.live, &mut self.in_flight] {
+            if let Some((slot, _)) = entry.filter(|&(_, s)| s == seq) {
+                if flash::program_words(slot_addr(slot) + 4, &[STATE_SENT]).is_err() {
+                    defmt::error!("Backlog slot {} not marked sent; it will be replayed again", slot);
+                }
+                *entry = None;
+            }
+        }
+        self.skip_sent();
+        self.cursor < self.end
+    }
+
+    /// No ACK for `seq` after every retry: it stays pending for replay
+    pub fn lost(&mut self, seq: u16) {
+        for entry in [&mut self.live, &mut self.in_flight] {
+            if entry.is_some_and(|(_, s)| s == seq) {
+                *entry = None;
+            }
+        }
+    }
+
+    /// The reading sent this boot as `seq`, newest first if it wrapped
+    pub fn lookup(&self, seq: u16, uptime_s: u32) -> Option<StoredReading> {
+        let slot = (self.boot_start..self.end).rev().find(|&slot| slot_seq(slot) == Some(seq))?;
+        let (at_s, reading) = read_slot(slot)?;
+        Some(StoredReading { age_s: Some(uptime_s.wrapping_sub(at_s)), reading })
+    }
+
+    /// Slots written so far, readable or not, for `export`
+    pub fn slots(&self) -> u32 {
+        self.end
+    }
+
+    /// Slot `slot`'s reading and its age at `uptime_s`, for `export`
+    pub fn stored(&self, slot: u32, uptime_s: u32) -> Option<StoredReading> {
+        if slot >= self.end {
+            return None;
+        }
+        let (at_s, reading) = read_slot(slot)?;
+        let age_s = (slot >= self.boot_start).then(|| uptime_s.wrapping_sub(at_s));
+        Some(StoredReading { age_s, reading })
+    }
+
+    /// The oldest pending reading, now in flight; `None` if there is none
+    ///
+    /// Its age is worked out against `uptime_s`.
+    pub fn next(&mut self, uptime_s: u32) -> Option<StoredReading> {
+        self.skip_sent();
+        let live = self.live.map(|(slot, _)| slot);
+        for slot in (self.cursor..self.end).filter(|&i| is_pending(i) && Some(i) != live) {
+            match read_slot(slot) {
+                Some((at_s, reading)) => {
+                    let age_s = (slot >= self.boot_start).then(|| uptime_s.wrapping_sub(at_s));
+                    self.in_flight = Some((slot, reading.seq_num));
+                    return Some(StoredReading { age_s, reading });
+                }
+                None => {
+                    // Mark it done rather than trip over it on every replay
+                    defmt::error!("Backlog slot {} unreadable, skipping", slot);
+                    let _ = flash::program_words(slot_addr(slot) + 4, &[STATE_SENT]);
+                }
+            }
+        }
+        None
+    }
+
+    /// Give up on the reading in flight without marking it sent
+    pub fn release(&mut self) {
+        self.in_flight = None;
+    }
+
+    fn skip_sent(&mut self) {
+        while self.cursor < self.end && !is_pending(self.cursor) {
+            self.cursor += 1;
+        }
+    }
+
+    /// Append a reading, pending; its slot, or `None` if it wasn't stored
+    fn store(&mut self, at_s: u32, packet: &SensorDataPacket) -> Option<u32> {
+        if self.end == SLOT_COUNT {
+            self.skip_sent();
+            if self.cursor < self.end {
+                self.dropped += 1;
+                defmt::warn!("Backlog full ({} readings pending), not storing #{}", self.pending(), packet.seq_num);
+                return None;
+            }
+            defmt::info!("Backlog all acknowledged, erasing sector {}", BACKLOG_SECTOR.number);
+            if flash::erase_sector(&BACKLOG_SECTOR).is_err() {
+                defmt::error!("Backlog erase failed, not storing #{}", packet.seq_num);
+                return None;
+            }
+            self.end = 0;
+            self.cursor = 0;
+            self.boot_start = 0;
+        }
+
+        let addr = slot_addr(self.end);
+        let mut body = [0xFFu8; (SLOT_SIZE - SLOT_HEADER) as usize];
+        let Ok(encoded) = postcard::to_slice(packet, &mut body) else {
+            defmt::error!("Backlog: reading #{} too large to store", packet.seq_num);
+            return None;
+        };
+        let len = encoded.len();
+
+        // Body first, magic last: a half-written slot never looks valid
+        let written = flash::program_bytes(addr + SLOT_HEADER, &body[..len])
+            .and_then(|_| flash::program_words(addr + 8, &[at_s, len as u32]))
+            .and_then(|_| flash::program_words(addr, &[SLOT_MAGIC]));
+        // Even a failed slot is used up: its words are no longer erased
+        let slot = self.end;
+        self.end += 1;
+        match written {
+            Ok(()) => Some(slot),
+            Err(e) => {
+                defmt::error!("Backlog write failed for #{}: {}", packet.seq_num, e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Collision avoidance for sensor nodes sharing a channel (`backoff`)
+//!
+//! Nodes powered up together with the same interval transmit in step and
+//! collide every time. Until Node 2 has given a node its slot (see
+//! slots.rs), and again once a reading goes unanswered, the node adds a
+//! random 0-`JITTER_S` s to each interval, so two nodes that collided once
+//! drift apart instead of colliding again.
+//!
+//! The RYLR998 has no channel-activity check over its AT interface, so
+//! listening before talking goes on what the node can hear: a `+RCV` in
+//! the last `QUIET_MS` means a frame was just on the air and its ACK may
+//! be about to follow, and the sample waits a random 1-`MAX_DEFER_S` ticks.
+//! Frames between other nodes and Node 2 aren't addressed to this module
+//! and go unheard; broadcasts, relayed ACKs and downlinks are heard.
+//!
+//! The counts since boot are shown by `backoff` in the shell; the switch
+//! is saved with the other shell settings.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Most a slotless node adds to an interval
+pub const JITTER_S: u32 = 2;
+/// Most ticks a sample waits for a busy channel
+pub const MAX_DEFER_S: u32 = 3;
+/// A frame heard this recently keeps the channel busy: long enough for
+/// its ACK at the slower spreading factors
+const QUIET_MS: u32 = 1_000;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static HEARD: AtomicBool = AtomicBool::new(false);
+static LAST_HEARD_MS: AtomicU32 = AtomicU32::new(0);
+
+static JITTERED: AtomicU32 = AtomicU32::new(0);
+static DEFERRED: AtomicU32 = AtomicU32::new(0);
+static UNANSWERED: AtomicU32 = AtomicU32::new(0);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// The module reported a received frame at `now_ms`
+pub fn heard(now_ms: u32) {
+    LAST_HEARD_MS.store(now_ms, Ordering::Relaxed);
+    HEARD.store(true, Ordering::Relaxed);
+}
+
+/// Whether to hold a transmission back for a frame heard just now
+pub fn channel_busy(now_ms: u32) -> bool {
+    enabled() && HEARD.load(Ordering::Relaxed) && now_ms.wrapping_sub(LAST_HEARD_MS.load(Ordering::Relaxed)) < QUIET_MS
+}
+
+/// A reading got no ACK after every retry, most likely lost to a collision
+pub fn note_unanswered() {
+    UNANSWERED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// What collision avoidance has done since boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Stats {
+    /// Intervals lengthened at random
+    pub jittered: u32,
+    /// Samples held back for a busy channel
+    pub deferred: u32,
+    /// Readings never ACKed
+    pub unanswered: u32,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        jittered: JITTERED.load(Ordering::Relaxed),
+        deferred: DEFERRED.load(Ordering::Relaxed),
+        unanswered: UNANSWERED.load(Ordering::Relaxed),
+    }
+}
+
+/// xorshift32; nodes have no RNG, and this only has to differ between them
+pub struct Rng(u32);
+
+impl Rng {
+    /// Seed from what differs between nodes and boots: the node's address,
+    /// its boot count and any startup time count
+    pub fn new(node: u8, boot_count: u32, ticks: u32) -> Self {
+        let seed = (u32::from(node) << 24) ^ boot_count.rotate_left(8) ^ ticks ^ 0x9E37_79B9;
+        Self(seed.max(1))
+    }
+
+    /// Uniform-enough in `0..n`
+    pub fn below(&mut self, n: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 % n.max(1)
+    }
+
+    /// Seconds to add to the next interval, 0 with backoff off
+    pub fn jitter_s(&mut self) -> u32 {
+        if !enabled() {
+            return 0;
+        }
+        let jitter = self.below(JITTER_S + 1);
+        if jitter > 0 {
+            JITTERED.fetch_add(1, Ordering::Relaxed);
+        }
+        jitter
+    }
+
+    /// Ticks to hold a sample back for a busy channel
+    pub fn defer_s(&mut self) -> u32 {
+        DEFERRED.fetch_add(1, Ordering::Relaxed);
+        1 + self.below(MAX_DEFER_S)
+    }
+}
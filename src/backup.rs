@@ -0,0 +1,76 @@
+//! RTC backup registers
+//!
+//! The 20 backup registers keep their contents through any reset, and
+//! through a power cut too with a cell on VBAT (the Nucleo ties VBAT to
+//! VDD, so there they only survive resets). They're shared out here:
+//!
+//! | Registers | Owner |
+//! |-----------|-------|
+//! | 0-2       | `brownout` record (Node 1) |
+//! | 3-8       | `RxCounters` (Node 2) |
+//!
+//! Writes need the PWR clock and the DBP bit, set by `init` (or by
+//! `brownout::init`, which also arms the PVD).
+//!
+//! Node 2's receive statistics are saved here after every frame, so a
+//! watchdog reset or a reflash no longer zeroes "packets received" or the
+//! CRC-failure count, and a reading Node 1 retries across Node 2's reset is
+//! still recognised as a duplicate.
+
+use stm32f4xx_hal::pac;
+
+use crate::stats::RxCounters;
+
+// RCC / PWR bits (RM0390 sections 5.4 and 6.3)
+const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+const PWR_CR_DBP: u32 = 1 << 8;
+
+/// Offset of RTC_BKP0R; the other 19 follow at 4-byte steps
+const RTC_BKP0R: usize = 0x50;
+
+const RX_MAGIC: u32 = 0x5258_5354; // "RXST"
+const RX_FIRST: usize = 3;
+/// Bit 16 of the sequence register: a sequence number has been seen
+const SEQ_VALID: u32 = 1 << 16;
+
+pub fn register(index: usize) -> *mut u32 {
+    (pac::RTC::ptr() as usize + RTC_BKP0R + 4 * index) as *mut u32
+}
+
+/// Enable writes to the backup domain; call once from `init`
+pub fn init() {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+    rcc.apb1enr().modify(|r, w| unsafe { w.bits(r.bits() | RCC_APB1ENR_PWREN) });
+    pwr.cr().modify(|r, w| unsafe { w.bits(r.bits() | PWR_CR_DBP) });
+}
+
+/// Counters saved by `save_rx` before the last reset, if any
+pub fn load_rx() -> Option<RxCounters> {
+    unsafe {
+        if register(RX_FIRST).read_volatile() != RX_MAGIC {
+            return None;
+        }
+        let seq = register(RX_FIRST + 5).read_volatile();
+        Some(RxCounters {
+            total: register(RX_FIRST + 1).read_volatile(),
+            lost: register(RX_FIRST + 2).read_volatile(),
+            duplicates: register(RX_FIRST + 3).read_volatile(),
+            crc_errors: register(RX_FIRST + 4).read_volatile(),
+            last_seq: (seq & SEQ_VALID != 0).then_some(seq as u16),
+        })
+    }
+}
+
+/// Save the counters; a few register writes, cheap enough for every frame
+pub fn save_rx(counters: &RxCounters) {
+    let seq = counters.last_seq.map_or(0, |seq| SEQ_VALID | seq as u32);
+    unsafe {
+        register(RX_FIRST + 1).write_volatile(counters.total);
+        register(RX_FIRST + 2).write_volatile(counters.lost);
+        register(RX_FIRST + 3).write_volatile(counters.duplicates);
+        register(RX_FIRST + 4).write_volatile(counters.crc_errors);
+        register(RX_FIRST + 5).write_volatile(seq);
+        register(RX_FIRST).write_volatile(RX_MAGIC);
+    }
+}
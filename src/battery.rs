@@ -0,0 +1,166 @@
+//! Sensor-node battery voltage and charge estimate
+//!
+//! Node 1 reads its single Li-ion cell through a 100k/100k divider into PA0
+//! (ADC1 channel 0), since a full cell (4.2 V) is above the ADC's 3.3 V
+//! reference. The voltage travels as a `tlv::BATTERY_MV` record and Node 2
+//! turns it into a percentage for the header icon.
+//!
+//! Charge isn't linear in voltage; `percent` interpolates a typical
+//! resting discharge curve, good to about 10 % under the node's light load.
+//!
+//! As the cell runs down, `Mode` trades service for runtime: a longer TX
+//! interval, lower TX power and the display off. The mode travels in every
+//! health packet so the gateway can tell a quiet node from a dying one.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::protocol::ConfigError;
+use crate::region;
+
+/// The module's highest output power
+pub const MAX_TX_POWER_DBM: u8 = 22;
+
+/// Cap on every mode's TX power, set remotely (see `command`); the
+/// region's limit applies on top
+static TX_POWER_LIMIT: AtomicU8 = AtomicU8::new(MAX_TX_POWER_DBM);
+
+/// Battery volts per volt at the ADC pin
+pub const DIVIDER_RATIO: u32 = 2;
+
+/// At or below this the icon is drawn as a warning
+pub const LOW_PERCENT: u8 = 20;
+
+/// Below this (about 15 %) the node drops to `Mode::Low`
+pub const LOW_MV: u16 = 3700;
+
+/// Below this (about 5 %) the node drops to `Mode::Critical`
+pub const CRITICAL_MV: u16 = 3500;
+
+/// A mode is only left once the cell is this far back above its threshold,
+/// so sag under load doesn't flip it on every sample
+pub const HYSTERESIS_MV: u16 = 100;
+
+/// (millivolts, percent), highest first
+const DISCHARGE_CURVE: [(u16, u8); 11] = [
+    (4200, 100),
+    (4060, 90),
+    (3980, 80),
+    (3920, 70),
+    (3870, 60),
+    (3820, 50),
+    (3790, 40),
+    (3770, 30),
+    (3730, 20),
+    (3680, 10),
+    (3300, 0),
+];
+
+/// Battery voltage for an ADC reading of `pin_mv`
+pub fn from_pin_millivolts(pin_mv: u16) -> u16 {
+    (pin_mv as u32 * DIVIDER_RATIO).min(u16::MAX as u32) as u16
+}
+
+/// Estimated state of charge, 0-100
+pub fn percent(mv: u16) -> u8 {
+    let (top_mv, _) = DISCHARGE_CURVE[0];
+    if mv >= top_mv {
+        return 100;
+    }
+    for pair in DISCHARGE_CURVE.windows(2) {
+        let ((hi_mv, hi_pct), (lo_mv, lo_pct)) = (pair[0], pair[1]);
+        if mv >= lo_mv {
+            let span = (hi_mv - lo_mv) as u32;
+            let above = (mv - lo_mv) as u32;
+            return lo_pct + ((hi_pct - lo_pct) as u32 * above / span) as u8;
+        }
+    }
+    0
+}
+
+/// Operating mode chosen from the battery voltage, mildest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+#[repr(u8)]
+pub enum Mode {
+    Normal = 0,
+    /// TX interval x3, 14 dBm, display off
+    Low = 1,
+    /// TX interval x10, 8 dBm, display off
+    Critical = 2,
+}
+
+impl Mode {
+    /// The mode for a reading of `mv`, coming from `self`
+    ///
+    /// Worse modes are entered as soon as the voltage is under their
+    /// threshold; better ones only past it plus `HYSTERESIS_MV`.
+    pub fn update(self, mv: u16) -> Self {
+        let under = if mv < CRITICAL_MV {
+            Mode::Critical
+        } else if mv < LOW_MV {
+            Mode::Low
+        } else {
+            Mode::Normal
+        };
+        if under >= self {
+            return under;
+        }
+        if mv >= LOW_MV + HYSTERESIS_MV {
+            Mode::Normal
+        } else if mv >= CRITICAL_MV + HYSTERESIS_MV {
+            Mode::Low.max(under)
+        } else {
+            self
+        }
+    }
+
+    /// Multiplier on the configured TX interval
+    pub fn interval_factor(self) -> u32 {
+        match self {
+            Mode::Normal => 1,
+            Mode::Low => 3,
+            Mode::Critical => 10,
+        }
+    }
+
+    /// RYLR998 output power (`AT+CRFOP`); 22 dBm is the module's default
+    ///
+    /// Never above `tx_power_limit`.
+    pub fn tx_power_dbm(self) -> u8 {
+        let dbm = match self {
+            Mode::Normal => MAX_TX_POWER_DBM,
+            Mode::Low => 14,
+            Mode::Critical => 8,
+        };
+        dbm.min(tx_power_limit())
+    }
+
+    pub fn display_on(self) -> bool {
+        self == Mode::Normal
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Mode::Normal),
+            1 => Some(Mode::Low),
+            2 => Some(Mode::Critical),
+            _ => None,
+        }
+    }
+}
+
+pub fn tx_power_limit() -> u8 {
+    TX_POWER_LIMIT.load(Ordering::Relaxed).min(region::max_tx_dbm())
+}
+
+/// A TX power cap, if the region allows it
+pub fn check_tx_power_limit(dbm: u8) -> Result<(), ConfigError> {
+    (dbm <= region::max_tx_dbm()).then_some(()).ok_or(ConfigError::PowerAboveLimit)
+}
+
+/// Cap the TX power; `apply_battery_mode` sends it, and it is saved with
+/// the other settings. A cap above what the region allows is refused.
+pub fn set_tx_power_limit(dbm: u8) -> Result<(), ConfigError> {
+    check_tx_power_limit(dbm)?;
+    TX_POWER_LIMIT.store(dbm, Ordering::Relaxed);
+    Ok(())
+}
@@ -1,35 +1,145 @@
 #![no_std]
 #![no_main]
 
-use panic_probe as _;
+use wk3_binary_protocol as _; // panic handler (crash.rs)
 use defmt_rtt as _;
 
-#[rtic::app(device = stm32f4xx_hal::pac, peripherals = true)]
+// Prefix every defmt log line with milliseconds since boot
+defmt::timestamp!("{=u64:ms}", app::monotonics::now().duration_since_epoch().to_millis());
+
+#[rtic::app(device = stm32f4xx_hal::pac, peripherals = true, dispatchers = [SAI1, SAI2, QUADSPI])]
 mod app {
     use stm32f4xx_hal::{
         prelude::*,
-        gpio::{Output, Pin},
+        gpio::{Edge, ExtiPin, Input, Output, Pin},
         pac,
         timer::{CounterHz, Event},
         serial::{Serial, Config as SerialConfig, Event as SerialEvent},
-        i2c::I2c,
         rcc::Config,
     };
-
+    #[cfg(not(feature = "st7789"))]
+    use stm32f4xx_hal::i2c::I2c;
+    #[cfg(feature = "st7789")]
+    use stm32f4xx_hal::{gpio::NoPin, spi::{Mode, Phase, Polarity, Spi}};
+    #[cfg(feature = "usb-json")]
+    use stm32f4xx_hal::otg_fs::{UsbBus, UsbBusType, USB};
+
+    #[cfg(not(feature = "st7789"))]
     use shared_bus::CortexMMutex;
-    use ssd1306::{prelude::*, Ssd1306, mode::BufferedGraphicsMode};
-    use display_interface_i2c::I2CInterface;
     use embedded_graphics::{
-        mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
+        mono_font::MonoTextStyleBuilder,
         pixelcolor::BinaryColor,
         prelude::*,
         text::Text,
     };
-    use heapless::{String, Vec};
+    use heapless::{Deque, String, Vec};
+    use heapless::spsc::{Consumer, Producer, Queue};
     use core::fmt::Write as _;
 
+    use wk3_binary_protocol::adr::{Adr, Step};
+    use wk3_binary_protocol::airtime::{self, Accountant, Admit, LoraParams};
+    use wk3_binary_protocol::backup;
+    use wk3_binary_protocol::battery;
+    use wk3_binary_protocol::cpu::{self, IsrStats, LoadMeter};
+    use wk3_binary_protocol::config::{self, NodeConfig, Settings};
+    use wk3_binary_protocol::broadcast;
+    use wk3_binary_protocol::command;
+    use wk3_binary_protocol::crash;
+    use wk3_binary_protocol::dirty::{Clip, DirtyRows};
+    use wk3_binary_protocol::export;
+    use wk3_binary_protocol::factory::{self, Hold};
+    use wk3_binary_protocol::mqtt;
+    use wk3_binary_protocol::gaps::Gaps;
+    use wk3_binary_protocol::hopping;
+    use wk3_binary_protocol::peers::{Peers, MAX_PEERS};
+    #[cfg(feature = "ingest")]
+    use wk3_binary_protocol::ingest;
+    use wk3_binary_protocol::join::{self, Joined};
+    use wk3_binary_protocol::linkstats;
+    use wk3_binary_protocol::uplink::{self, Uplink};
+    use wk3_binary_protocol::display::{self, Canvas, Panel};
+    use wk3_binary_protocol::ds18b20;
+    use wk3_binary_protocol::calibration;
+    use wk3_binary_protocol::senders;
+    use wk3_binary_protocol::shell::{self, Command, LineBuffer, Order};
+    use wk3_binary_protocol::slots;
+    use wk3_binary_protocol::soil;
+    use wk3_binary_protocol::stats::{RxError, RxStats, SeqEvent, LOSS_WINDOW};
+    use wk3_binary_protocol::menu::{self, Field, Menu, Outcome};
+    use wk3_binary_protocol::metrics;
+    use wk3_binary_protocol::module;
+    use wk3_binary_protocol::panel;
+    use wk3_binary_protocol::ping::{self, Echo, Pinger};
+    use wk3_binary_protocol::nmea::Fix;
+    use wk3_binary_protocol::power::{self, Board, PowerProfile, Wake};
+    use wk3_binary_protocol::preset;
+    use wk3_binary_protocol::{debug, trace};
+    use wk3_binary_protocol::raw;
+    use wk3_binary_protocol::region;
+    use wk3_binary_protocol::relay::{self, Relay};
+    use wk3_binary_protocol::reset::ResetCause;
+    use wk3_binary_protocol::scan::{self, Scan, Tune};
+    #[cfg(feature = "sd-log")]
+    use wk3_binary_protocol::sdlog;
+    use wk3_binary_protocol::time::{self, Mono};
+    use wk3_binary_protocol::ui::{self, Activity, DisplayPower, Page, Reading, Screen, Screensaver, Trends};
+    #[cfg(feature = "usb-json")]
+    use wk3_binary_protocol::usbjson::{self, Gateway};
+    use systick_monotonic::ExtU64;
+    use wk3_binary_protocol::watchdog::{self, Checkpoint, Supervisor};
+
+    // --- Task Priority Plan ---
+    //
+    //   5  uart4_handler    (HW, UART4)  drain RX bytes - must beat the 1-byte UART FIFO; write queued TX
+    //   4  radio_tx         (SW)         ACKs and other outgoing frames, queued for uart4_handler
+    //   3  process_frame    (SW)         CRC, postcard decode, queue to display
+    //   2  tim2_handler     (HW, TIM2)   heartbeat LED, metrics, schedules refresh
+    //   2  button_handler   (HW, EXTI)   PC13 page switch / setup menu, schedules refresh
+    //   1  display_refresh  (SW)         slow I2C draw + flush
+    //   1  activity_pulse   (SW)         RX/TX corner indicator (partial flush)
+    //   1  apply_config     (SW)         save menu settings to flash + AT commands
+    //   1  debug_shell      (HW, USART2) ST-LINK VCP command line
+    //   1  cpu_stats        (SW)         CPU load / ISR / lock report every 10s
+    //
+    // The display is only ever touched at priority 1, so its ceiling is 1 and
+    // a 40ms I2C flush can be preempted by everything above it. UART4 belongs
+    // to uart4_handler alone: other tasks copy whole AT lines into lora_tx
+    // and the TXE interrupt writes them, so even a 240-byte frame (~21ms on
+    // the wire) never masks RX. lora_tx (ceiling 5) is held for that copy
+    // and the other cross-level resources (rx_stats) for microseconds;
+    // build with `--features lock-metrics` to log the measured hold times.
+
+    // Per-task run times; every task body opens one of these first
+    static UART4_STATS: IsrStats = IsrStats::new("uart4");
+    static RADIO_TX_STATS: IsrStats = IsrStats::new("radio_tx");
+    static PROCESS_STATS: IsrStats = IsrStats::new("process_frame");
+    static TIM2_STATS: IsrStats = IsrStats::new("tim2");
+    static DISPLAY_STATS: IsrStats = IsrStats::new("display_refresh");
+    static ACTIVITY_STATS: IsrStats = IsrStats::new("activity_pulse");
+    static SHELL_STATS: IsrStats = IsrStats::new("debug_shell");
+    static BUTTON_STATS: IsrStats = IsrStats::new("button");
+    static CONFIG_STATS: IsrStats = IsrStats::new("apply_config");
+    static SD_STATS: IsrStats = IsrStats::new("sd_log");
+    static USB_STATS: IsrStats = IsrStats::new("usb");
+    static STREAM_STATS: IsrStats = IsrStats::new("forward_frame");
+    static MQTT_STATS: IsrStats = IsrStats::new("mqtt_lines");
+    static SETTINGS_STATS: IsrStats = IsrStats::new("save_settings");
+    static GAPS_STATS: IsrStats = IsrStats::new("request_replay");
+    static JOIN_STATS: IsrStats = IsrStats::new("save_joined");
+    static ADR_STATS: IsrStats = IsrStats::new("adr_step");
+    static HOP_STATS: IsrStats = IsrStats::new("hop_channel");
+    static SCAN_STATS: IsrStats = IsrStats::new("apply_scan");
+    static PING_STATS: IsrStats = IsrStats::new("ping");
+    static FACTORY_STATS: IsrStats = IsrStats::new("factory_hold");
+    static LINK_STATS: IsrStats = IsrStats::new("link_report");
+
     // --- Configuration Constants ---
     const NODE_ID: &str = "N2";              // Node identifier for display
+    const LORA_PARAMETER: &str = region::PARAMETER;  // AT+PARAMETER: the region's SF/BW (see region.rs)
+    const BUTTON_DEBOUNCE_MS: u32 = 200;     // Ignore contact bounce after a press
+    const ACTIVITY_PULSE_MS: u64 = 150;      // How long the RX/TX indicator stays lit
+    const ADR_FOLLOW_MS: u64 = 2_000;        // Time for a spreading factor broadcast to go out first
+    const FACTORY_RECHECK_MS: u64 = 100;     // Factory reset due a moment after its timer (see factory.rs)
 
     // UART RX buffer size - sized for RYLR998 capabilities
     // RYLR998 supports 240-byte payloads (NOT LoRaWAN's 51-byte limit!)
@@ -37,83 +147,246 @@ mod app {
     // Example: "+RCV=1,240,<240 bytes>,-20,12\r\n" = ~265 bytes total
     // 255 bytes gives headroom for current payloads (~44 bytes) plus future expansion
     const RX_BUFFER_SIZE: usize = 255;
+    /// A `stream on` record: a full payload with its link figures, framed
+    const STREAM_RECORD_LEN: usize = 272;
+    /// A frame sent on as is (a `flash-remote` upload, or relayed): up to
+    /// the RYLR998's payload limit
+    const RAW_FRAME_LEN: usize = 240;
+    /// `mqtt on` output for one reading, every optional value present
+    const MQTT_LINES_LEN: usize = 512;
+
+    // Inter-task queue depths (heapless spsc holds N-1 items)
+    // RX: decoded packets waiting for the display tick (7 packets = 3.5s at 2 Hz refresh)
+    // TX: outgoing radio commands waiting for the UART to be free
+    const RX_QUEUE_LEN: usize = 8;
+    const TX_QUEUE_LEN: usize = 4;
+    // Bytes on their way out of UART4: two of the longest AT+SEND lines
+    const LORA_TX_LEN: usize = 2 * (24 + 240 + 2);
+
+    /// What `queue_line` has handed `uart4_handler` to write
+    type LoraTx = Deque<u8, LORA_TX_LEN>;
+
+    // IWDG timeout: 8 heartbeats at 2 Hz; every supervised task runs at least once per tick
+    const WATCHDOG_TIMEOUT_MS: u32 = 4_000;
+
+    const CPU_STATS_INTERVAL_SECS: u64 = 10;  // Well inside cpu::LoadMeter's 51s wrap
+    const REPLAY_CHECK_SECS: u64 = 10;       // How often missing ranges are looked at (see gaps.rs)
+    const LINK_INTERVAL_SECS: u64 = linkstats::PERIOD_S as u64;
+    const LOW_POWER: bool = cfg!(feature = "low-power");  // 42 MHz core (see power.rs)
+
+    // Pins set up in `init`; everything else is parked by `power::gate_unused`
+    const BOARD: Board = Board {
+        pins: [
+            // PA2/3 USART2, PA5 LED, PA11/12 USB
+            0b0000_0000_0010_1100 | if cfg!(feature = "usb-json") { 0b11 << 11 } else { 0 },
+            if cfg!(feature = "st7789") {
+                // PB1 RST, PB2 BL, PB12-15 SPI2 CS/SCK/DC/MOSI
+                0b1111_0000_0000_0110
+            } else {
+                // PB8/9 I2C1
+                0b0000_0011_0000_0000
+            } | if cfg!(feature = "sd-log") {
+                // PB3-6 SPI1 SCK/MISO/MOSI/CS for the SD card
+                0b0000_0000_0111_1000
+            } else {
+                0
+            },
+            // PC10/11 UART4, PC13 button
+            0b0010_1100_0000_0000,
+        ],
+        wake: if cfg!(feature = "usb-json") {
+            &[Wake::Tim2, Wake::Usart2, Wake::Uart4, Wake::OtgFs]
+        } else {
+            &[Wake::Tim2, Wake::Usart2, Wake::Uart4]
+        },
+    };
 
-    const NETWORK_ID: u8 = 18;               // LoRa network ID
-    const LORA_FREQ: u32 = 915;              // LoRa frequency in MHz (915 for US)
+    // Radio settings until the setup menu saves others (see config.rs); the
+    // build can set them (see preset.rs)
+    const DEFAULT_CONFIG: NodeConfig = NodeConfig {
+        // Always where sensor nodes send, whatever `node_id` the build has
+        address: relay::GATEWAY,
+        network_id: preset::NETWORK_ID,
+        band_mhz: preset::LORA_FREQ_MHZ,
+        tx_interval_s: 10,       // Given to nodes that join (see join.rs)
+    };
+    // Node 2 only answers; its TX interval is the one joining nodes get
+    const MENU_FIELDS: &[Field] = &[Field::Address, Field::Network, Field::Band, Field::Interval];
+
+    // --- Binary Protocol (shared with Node 1, see protocol.rs) ---
+    use wk3_binary_protocol::protocol::{
+        decode_ack, decode_frame, encode_ack, encode_frame, tlv, AckPacket, SlotGrant, CalibrationCommand, CrashReport, FrameError, HealthPacket,
+        NodeInfo, SensorDataPacket, SensorFaultPacket, MSG_TYPE_ACK, MSG_TYPE_CALIBRATE, MSG_TYPE_CRASH_REPORT,
+        MSG_TYPE_HEALTH, MSG_TYPE_NACK, MSG_TYPE_NODE_INFO, MSG_TYPE_SENSOR_DATA, MSG_TYPE_SENSOR_FAULT,
+        MSG_TYPE_SUMMARY, SummaryPacket, MSG_TYPE_EVENT, EVENT_MOTION, EventPacket, MSG_TYPE_DISPLAY,
+        MSG_TYPE_POWER_FAIL, PowerFailPacket, MSG_TYPE_STORED, StoredReading, MSG_TYPE_REPLAY_REQUEST,
+        ReplayRequest, MSG_TYPE_REPLAY_BATCH, ReplayBatch, stream::{RadioFrame, RawLine}, MSG_TYPE_COMMAND, CommandPacket,
+        MSG_TYPE_COMMAND_RESULT, CommandResult, COMMAND_DONE, MSG_TYPE_LOG, LogBatch, frame_type, frame_node, frame_hops, NODE_1, OFFSET_TYPE,
+        MSG_TYPE_BROADCAST, MSG_TYPE_BROADCAST_ACK, BroadcastAck, BROADCAST_ADDRESS, MSG_TYPE_JOIN_REQUEST, JoinRequest,
+        MSG_TYPE_JOIN_ACCEPT, JoinAccept, RemoteCommand, BROADCAST_EVERYONE,
+        MSG_TYPE_FW_BEGIN, MSG_TYPE_FW_CHUNK, MSG_TYPE_FW_STATUS, FirmwareBegin, FirmwareStatus, FW_RECEIVING, NODE_HOST,
+        MSG_TYPE_PING, MSG_TYPE_PONG, Ping, Pong, MSG_TYPE_CONFIG_ACK, ConfigAck, MSG_TYPE_CONFIG_REPORT, ConfigReport,
+        MSG_TYPE_LINK_STATS, LinkStats,
+    };
+    use wk3_binary_protocol::sensor::{SensorFault, SensorKind};
+
+    /// Send a calibration downlink to Node 1 (CRC frame, not ACKed)
+    fn send_calibration(tx: &mut impl rtic::Mutex<T = LoraTx>, cmd: &CalibrationCommand) {
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_CALIBRATE, cmd, &mut frame_buf) else {
+            defmt::error!("Failed to serialize calibration command");
+            return;
+        };
+        send_to_node1(tx, frame);
+        defmt::info!("Calibration sent: {}", cmd);
+    }
 
-    // --- Binary Protocol Data Structures ---
-    use serde::{Serialize, Deserialize};
+    /// Send a display setting downlink to Node 1 (CRC frame, not ACKed)
+    fn send_display(tx: &mut impl rtic::Mutex<T = LoraTx>, setting: &panel::Setting) {
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_DISPLAY, setting, &mut frame_buf) else {
+            defmt::error!("Failed to serialize display setting");
+            return;
+        };
+        send_to_node1(tx, frame);
+        defmt::info!("Display setting sent: {}", setting);
+    }
 
-    /// Sensor data packet for binary transmission (must match Node 1)
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-    pub struct SensorDataPacket {
-        pub seq_num: u16,           // Sequence number for duplicate detection
-        pub temperature: i16,       // Temperature in centidegrees (e.g., 2710 = 27.1°C)
-        pub humidity: u16,          // Humidity in basis points (e.g., 5600 = 56.0%)
-        pub gas_resistance: u32,    // Gas resistance in ohms
+    /// Send a signed remote command to node `to` (CRC frame, answered with
+    /// a `CommandResult`)
+    fn send_remote(tx: &mut impl rtic::Mutex<T = LoraTx>, to: u16, packet: &CommandPacket) {
+        let mut frame_buf = [0u8; 48];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_COMMAND, packet, &mut frame_buf) else {
+            defmt::error!("Failed to serialize remote command");
+            return;
+        };
+        send_to(tx, to, frame);
+        defmt::info!("Remote command #{} sent to {}: {}", packet.counter, to, packet.command);
     }
 
-    /// ACK/NACK packet for acknowledgment (must match Node 1)
-    /// Size: 3 bytes (1 byte msg_type + 2 bytes seq_num)
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-    pub struct AckPacket {
-        pub msg_type: u8,   // 1 = ACK (success), 2 = NACK (CRC failure)
-        pub seq_num: u16,   // Which packet we're acknowledging
+    /// Ask Node 1 to replay readings still missing (CRC frame, not ACKed)
+    fn send_replay_request(tx: &mut impl rtic::Mutex<T = LoraTx>, request: &ReplayRequest) {
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_REPLAY_REQUEST, request, &mut frame_buf) else {
+            defmt::error!("Failed to serialize replay request");
+            return;
+        };
+        send_to_node1(tx, frame);
+        defmt::info!("Replay request sent: #{}..={}", request.from, request.to);
     }
 
-    // Message type constants
-    const MSG_TYPE_ACK: u8 = 1;
-    const MSG_TYPE_NACK: u8 = 2;
+    /// Broadcast to every node on the network, for `group`'s members or
+    /// all of them (CRC frame, ACKed only if `ack`, and then by each node
+    /// after its own delay)
+    fn send_broadcast(tx: &mut impl rtic::Mutex<T = LoraTx>, id: u16, ack: bool, group: u8, payload: &broadcast::Payload) {
+        let mut frame_buf = [0u8; broadcast::MAX_FRAME_LEN];
+        let Ok(frame) = broadcast::encode(config::node_id(), id, ack, group, payload, &mut frame_buf) else {
+            defmt::error!("Failed to serialize broadcast");
+            return;
+        };
+        send_to(tx, BROADCAST_ADDRESS, frame);
+        defmt::info!("Broadcast #{} sent (ack {}, group {}): {}", id, ack, group, payload);
+    }
 
-    /// Calculate CRC-16 checksum for data integrity
-    fn calculate_crc16(data: &[u8]) -> u16 {
-        use crc::{Crc, CRC_16_IBM_3740};
-        const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
-        CRC16.checksum(data)
+    /// Answer a join request (CRC frame, not ACKed); broadcast, since the
+    /// node has no address of its own yet
+    fn send_join_accept(tx: &mut impl rtic::Mutex<T = LoraTx>, accept: &JoinAccept) {
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_JOIN_ACCEPT, accept, &mut frame_buf) else {
+            defmt::error!("Failed to serialize join accept");
+            return;
+        };
+        send_to(tx, BROADCAST_ADDRESS, frame);
+        defmt::info!("Join accept sent: node {} for uid {=u32:08X}", accept.address, accept.uid);
     }
 
-    /// Send ACK packet to Node 1
-    /// Format: AT+SEND=1,<length>,<binary_ack_packet>\r\n
-    fn send_ack(uart: &mut Serial<pac::UART4>, seq_num: u16, is_ack: bool) {
+    /// Check the link to `to` (CRC frame, answered with a Pong, not ACKed)
+    fn send_ping(tx: &mut impl rtic::Mutex<T = LoraTx>, to: u16, ping: &Ping) {
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_PING, ping, &mut frame_buf) else {
+            defmt::error!("Failed to serialize ping");
+            return;
+        };
+        send_to(tx, to, frame);
+        debug!("Ping #{} sent to {}", ping.id, to);
+    }
+
+    /// Answer a ping from `to` with how it was heard (CRC frame, not ACKed)
+    fn send_pong(tx: &mut impl rtic::Mutex<T = LoraTx>, to: u16, pong: &Pong) {
+        let mut frame_buf = [0u8; 24];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_PONG, pong, &mut frame_buf) else {
+            defmt::error!("Failed to serialize pong");
+            return;
+        };
+        send_to(tx, to, frame);
+        debug!("Pong #{} sent to {}", pong.id, to);
+    }
+
+    /// This end's view of the link for every node (CRC frame, not ACKed)
+    fn send_link_stats(tx: &mut impl rtic::Mutex<T = LoraTx>, stats: &LinkStats) {
+        let mut frame_buf = [0u8; 40];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_LINK_STATS, stats, &mut frame_buf) else {
+            defmt::error!("Failed to serialize link stats");
+            return;
+        };
+        send_to(tx, BROADCAST_ADDRESS, frame);
+        defmt::info!("Link stats sent: {} frames, {} answers, {} CRC failures", stats.frames_sent, stats.acks_received, stats.crc_failures);
+    }
+
+    fn send_to_node1(tx: &mut impl rtic::Mutex<T = LoraTx>, frame: &[u8]) {
+        send_to(tx, NODE_1.into(), frame);
+    }
+
+    fn send_to(tx: &mut impl rtic::Mutex<T = LoraTx>, address: u16, frame: &[u8]) {
         use heapless::String;
         use core::fmt::Write;
 
+        // Long enough for "AT+SEND=65535,240,"
+        let mut prefix: String<24> = String::new();
+        let _ = core::write!(prefix, "AT+SEND={},{},", address, frame.len());
+        queue_line(tx, &[prefix.as_bytes(), frame]);
+        linkstats::note_sent();
+    }
+
+    /// Queue one line for the module, `parts` and then \r\n, whole
+    ///
+    /// `uart4_handler` writes it out a byte per TXE interrupt, so nothing
+    /// holds the UART while it goes. If an earlier line still fills the
+    /// queue this spins until there is room, taking the lock only to look.
+    fn queue_line(tx: &mut impl rtic::Mutex<T = LoraTx>, parts: &[&[u8]]) {
+        let len = parts.iter().map(|part| part.len()).sum::<usize>() + 2;
+        if len > LORA_TX_LEN {
+            defmt::error!("{} byte line too long for the UART queue", len);
+            return;
+        }
+        while !tx.lock(|queue| metrics::LORA_UART.measure(|| {
+            if queue.capacity() - queue.len() < len {
+                return false;
+            }
+            for &b in parts.iter().copied().flatten().chain(b"\r\n") {
+                let _ = queue.push_back(b);
+            }
+            true
+        })) {}
+        rtic::pend(pac::Interrupt::UART4);
+    }
+
+    /// Send node `node`'s ACK packet to address `to`, the node or its relay
+    /// Format: AT+SEND=<to>,<length>,<binary_ack_packet>[<slot grant>]\r\n
+    fn send_ack(tx: &mut impl rtic::Mutex<T = LoraTx>, to: u16, node: u8, seq_num: u16, slot: Option<&SlotGrant>, is_ack: bool) {
         let ack_packet = AckPacket {
             msg_type: if is_ack { MSG_TYPE_ACK } else { MSG_TYPE_NACK },
             seq_num,
+            node,
         };
 
         // Serialize ACK packet
-        let mut ack_buffer = [0u8; 8];
-        match postcard::to_slice(&ack_packet, &mut ack_buffer) {
+        let mut ack_buffer = [0u8; 16];
+        match encode_ack(&ack_packet, slot, &mut ack_buffer) {
             Ok(serialized) => {
-                let ack_len = serialized.len();
-
-                // Send AT command: AT+SEND=1,<length>,<ack_data>\r\n
-                // Address 1 = Node 1 (sender)
-                let cmd_prefix = "AT+SEND=1,";
-                for b in cmd_prefix.as_bytes() {
-                    let _ = nb::block!(uart.write(*b));
-                }
-
-                // Send length as ASCII
-                let mut len_str: String<8> = String::new();
-                let _ = core::write!(len_str, "{},", ack_len);
-                for b in len_str.as_bytes() {
-                    let _ = nb::block!(uart.write(*b));
-                }
-
-                // Send binary ACK payload
-                for b in serialized {
-                    let _ = nb::block!(uart.write(*b));
-                }
-
-                // Send \r\n terminator
-                let _ = nb::block!(uart.write(b'\r'));
-                let _ = nb::block!(uart.write(b'\n'));
-
-                defmt::info!("{} sent for packet #{}",
-                    if is_ack { "ACK" } else { "NACK" }, seq_num);
+                send_to(tx, to, serialized);
+                defmt::info!("{} sent to {} for packet #{}",
+                    if is_ack { "ACK" } else { "NACK" }, to, seq_num);
             }
             Err(_) => {
                 defmt::error!("Failed to serialize ACK packet");
@@ -122,8 +395,10 @@ mod app {
     }
 
     // --- Bridge for embedded-hal 1.0 -> 0.2.7 ---
+    #[cfg(not(feature = "st7789"))]
     pub struct I2cCompat<I2C>(pub I2C);
 
+    #[cfg(not(feature = "st7789"))]
     impl<I2C> embedded_hal_0_2::blocking::i2c::Write for I2cCompat<I2C>
     where I2C: embedded_hal::i2c::I2c {
         type Error = I2C::Error;
@@ -132,6 +407,7 @@ mod app {
         }
     }
 
+    #[cfg(not(feature = "st7789"))]
     impl<I2C> embedded_hal_0_2::blocking::i2c::Read for I2cCompat<I2C>
     where I2C: embedded_hal::i2c::I2c {
         type Error = I2C::Error;
@@ -140,6 +416,7 @@ mod app {
         }
     }
 
+    #[cfg(not(feature = "st7789"))]
     impl<I2C> embedded_hal_0_2::blocking::i2c::WriteRead for I2cCompat<I2C>
     where I2C: embedded_hal::i2c::I2c {
         type Error = I2C::Error;
@@ -148,65 +425,318 @@ mod app {
         }
     }
 
+    #[cfg(not(feature = "st7789"))]
     type MyI2c = I2c<pac::I2C1>;
+    #[cfg(not(feature = "st7789"))]
     type BusManager = shared_bus::BusManager<CortexMMutex<I2cCompat<MyI2c>>>;
+    #[cfg(not(feature = "st7789"))]
     type I2cProxy = shared_bus::I2cProxy<'static, CortexMMutex<I2cCompat<MyI2c>>>;
 
-    type LoraDisplay = Ssd1306<I2CInterface<I2cProxy>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+    #[cfg(not(feature = "st7789"))]
+    type LoraDisplay = display::Oled<I2cProxy>;
+
+    // Colour TFT on SPI2 instead of the OLED (see src/tft.rs for wiring)
+    #[cfg(feature = "st7789")]
+    type TftSpi = embedded_hal_bus::spi::ExclusiveDevice<
+        Spi<pac::SPI2>, Pin<'B', 12, Output>, embedded_hal_bus::spi::NoDelay>;
+    #[cfg(feature = "st7789")]
+    type LoraDisplay = wk3_binary_protocol::tft::Tft<
+        display_interface_spi::SPIInterface<TftSpi, Pin<'B', 14, Output>>,
+        Pin<'B', 1, Output>,
+        Pin<'B', 2, Output>,
+    >;
+
+    #[cfg(feature = "sd-log")]
+    type SdCard = embedded_sdmmc::SdCard<
+        embedded_hal_bus::spi::ExclusiveDevice<stm32f4xx_hal::spi::Spi<pac::SPI1>, Pin<'B', 6, Output>, embedded_hal_bus::spi::NoDelay>,
+        time::CycleDelay,
+    >;
+
+    #[monotonic(binds = SysTick, default = true)]
+    type MonoTimer = Mono;
+
+    /// Milliseconds since boot from the RTIC monotonic
+    fn now_ms() -> u32 {
+        monotonics::now().duration_since_epoch().to_millis() as u32
+    }
+
+    /// Whole seconds since boot from the RTIC monotonic
+    fn uptime_s() -> u32 {
+        monotonics::now().duration_since_epoch().to_secs() as u32
+    }
+
+    /// `stream` reply: off, on, or tap with the module's other lines
+    fn stream_state() -> &'static str {
+        match (export::streaming(), export::tapping()) {
+            (false, _) => "off",
+            (true, false) => "on",
+            (true, true) => "tap",
+        }
+    }
 
     #[derive(Debug, Clone, Copy)]
     pub struct SensorData {
         pub temperature: f32,
         pub humidity: f32,
         pub gas_resistance: u32,
+        pub heater_step: u8,
+        pub iaq: Option<u16>,
+        pub co2_ppm: Option<u16>,
+        pub battery_mv: Option<u16>,
+        /// DS18B20 probes in send order, `None` past the last or for one that failed
+        pub probes_c: [Option<f32>; ds18b20::MAX_PROBES],
+        /// Soil moisture per probe, `None` where not calibrated
+        pub soil_pct: [Option<u8>; soil::PROBES],
+        /// Node 1's position, if it has a GNSS module
+        pub gnss: Option<Fix>,
         pub packet_num: u16,
     }
 
+    /// Outgoing radio work queued by the application for `radio_tx`
+    #[derive(Debug, Clone, Copy, defmt::Format)]
+    pub enum RadioCommand {
+        /// To the address the frame was heard from: its node, or a relay;
+        /// a live reading's carries its node's slot (see slots.rs)
+        Ack { to: u16, node: u8, seq_num: u16, slot: Option<SlotGrant> },
+        /// `cal` from the shell: trim a sensor on Node 1
+        Calibrate(CalibrationCommand),
+        /// `n1 display ...` from the shell: Node 1's panel settings
+        Display(panel::Setting),
+        /// Readings still missing after a sequence gap (see gaps.rs)
+        Replay(ReplayRequest),
+        /// `n1 interval|txpower|reboot|set` from the shell, or an ADR power step
+        /// for any node; signed (see command.rs)
+        Remote { to: u16, packet: CommandPacket },
+        /// A firmware upload frame from `wk3 flash-remote`, sent on as is
+        Upload(RawFrame),
+        /// `relay on`: a frame from another node, one hop further on, for
+        /// `via` or the next relay on its route (see relay.rs)
+        Relay { to: u16, frame: RawFrame },
+        /// `all ...` or `group <g> ...` from the shell: one frame for every
+        /// sensor node, or a group's (see broadcast.rs)
+        Broadcast { id: u16, ack: bool, group: u8, payload: broadcast::Payload },
+        /// The address for a node that asked to join, to every node (see join.rs)
+        JoinAccept(JoinAccept),
+        /// `ping` from the shell; stamped with the time as it goes out
+        Ping { to: u16, id: u16 },
+        /// The answer to a ping heard from `to` (see ping.rs)
+        Pong { to: u16, pong: Pong },
+        /// This end's link counters, to every node (see linkstats.rs)
+        LinkStats(LinkStats),
+    }
+
+    impl RadioCommand {
+        /// Longest frame the command can produce, charged in full against
+        /// the duty cycle
+        fn max_frame_len(&self) -> usize {
+            match self {
+                RadioCommand::Ack { .. } => 12,
+                RadioCommand::Calibrate(_) | RadioCommand::Display(_) | RadioCommand::Replay(_) | RadioCommand::JoinAccept(_) | RadioCommand::Ping { .. } => 16,
+                RadioCommand::Pong { .. } => 24,
+                RadioCommand::LinkStats(_) => 38,
+                RadioCommand::Remote { .. } => 43,
+                RadioCommand::Upload(frame) | RadioCommand::Relay { frame, .. } => frame.len as usize,
+                RadioCommand::Broadcast { .. } => broadcast::MAX_FRAME_LEN,
+            }
+        }
+    }
+
+    /// A complete radio frame, copied off the shell's port or the radio
+    #[derive(Debug, Clone, Copy)]
+    pub struct RawFrame {
+        bytes: [u8; RAW_FRAME_LEN],
+        len: u8,
+    }
+
+    impl RawFrame {
+        /// `None` if `frame` is too long for the radio
+        fn new(frame: &[u8]) -> Option<Self> {
+            let mut bytes = [0u8; RAW_FRAME_LEN];
+            bytes.get_mut(..frame.len())?.copy_from_slice(frame);
+            Some(Self { bytes, len: frame.len() as u8 })
+        }
+
+        fn as_bytes(&self) -> &[u8] {
+            &self.bytes[..self.len as usize]
+        }
+    }
+
+    impl defmt::Format for RawFrame {
+        fn format(&self, f: defmt::Formatter) {
+            defmt::write!(f, "RawFrame(type {}, {} bytes)", self.bytes[OFFSET_TYPE], self.len);
+        }
+    }
+
     #[shared]
     struct Shared {
-        lora_uart: Serial<pac::UART4>,
+        lora_tx: LoraTx,         // AT lines for the module, written from the TXE interrupt
         display: LoraDisplay,
-        last_packet: Option<ParsedMessage>,
-        packets_received: u32,
+        rx_stats: RxStats,       // Sequence-gap loss and packet rate (totals kept in backup registers)
+        gaps: Gaps,              // Sequence ranges to ask Node 1 to replay
+        peers: Peers,            // Last reading and link per sensor node (see peers.rs)
+        page: Page,              // Display page, advanced by the user button
+        saver: Screensaver,      // Dims/blanks the OLED when nothing happens
+        config: NodeConfig,      // Radio settings in use (flash sector 6)
+        menu: Option<Menu>,      // Setup menu, while open
+        tx_producer: Producer<'static, RadioCommand, TX_QUEUE_LEN>,  // process_frame/shell -> radio_tx
+        airtime: Accountant,     // Duty-cycle budget for ACKs and downlinks (see airtime.rs)
+        adr: Adr,                // Link margin per node and the network's spreading factor (see adr.rs)
+        scan: Scan,              // Looking for nodes on other settings after a silence (see scan.rs)
+        pinger: Pinger,          // The last `ping` and its answer (see ping.rs)
+        command_counter: u32,    // Last remote command number used
+        broadcasts: u16,         // Last broadcast id used
+        console: Serial<pac::USART2>,  // Debug shell on the ST-LINK VCP, and `stream on` records
+        #[cfg(feature = "usb-json")]
+        usb: Gateway<UsbBusType>,  // JSON lines to a USB host (see usbjson.rs)
     }
 
     #[local]
     struct Local {
         led: Pin<'A', 5, Output>,
         timer: CounterHz<pac::TIM2>,
+        lora_uart: Serial<pac::UART4>,
         rx_buffer: Vec<u8, RX_BUFFER_SIZE>,
+        rx_producer: Producer<'static, ParsedMessage, RX_QUEUE_LEN>,   // process_frame -> display
+        rx_consumer: Consumer<'static, ParsedMessage, RX_QUEUE_LEN>,
+        tx_consumer: Consumer<'static, RadioCommand, TX_QUEUE_LEN>,
+        latest_packet: Option<ParsedMessage>,                          // Most recent packet shown on the display
+        watchdog: Supervisor,
+        shell: LineBuffer,
+        uplink: Uplink,                                                // Host records on the shell's port (see uplink.rs)
+        button: Pin<'C', 13, Input>,                                   // Blue button on Nucleo (PC13), EXTI13
+        reset_cause: ResetCause,                                       // Shown on the node page
+        settings: Settings,                                            // Shell settings as last saved
+        hop_plan: hopping::Plan,                                       // Hop channels as last saved
+        joined: Joined,                                                // Addresses handed out (see join.rs)
+        #[cfg(feature = "sd-log")]
+        sd: Option<sdlog::Logger<SdCard>>,                             // CSV log, if a card was found at boot
     }
 
     #[derive(Debug, Clone, Copy)]
     pub struct ParsedMessage {
+        /// Sender's address from the frame header (see peers.rs)
+        pub node: u8,
+        /// Address it was heard from: the sender, or a relay (see relay.rs)
+        pub from: u16,
         pub sensor_data: SensorData,
         pub rssi: i16,
         pub snr: i16,
+        pub rx_time_ms: u32,    // Monotonic time the frame was parsed
+    }
+
+    /// A received frame the application acts on
+    #[derive(Debug, Clone)]
+    pub enum Received {
+        Reading(ParsedMessage),
+        Fault(SensorFault),
+        PowerFail(PowerFailPacket),
+        /// A reading a node stored during an outage; `age_s` as sent
+        Backfill { node: u8, from: u16, data: SensorData, age_s: Option<u32> },
+        /// Readings Node 2 asked for with a `ReplayRequest`
+        Batch(ReplayBatch),
+        /// A node without an address asking for one (see join.rs)
+        Join(JoinRequest),
+        /// A link check from the module at `from`, to answer (see ping.rs)
+        Ping { from: u16, answer: Pong },
+        /// The answer to our own
+        Pong { from: u16, pong: Pong, link: ping::Link },
+        /// A sensor node's own view of the link (see linkstats.rs)
+        LinkStats(linkstats::Heard),
     }
 
     // Helper function to send AT command and wait for response
     fn send_at_command(uart: &mut Serial<pac::UART4>, cmd: &str) {
         defmt::info!("Sending AT command: {}", cmd);
+        write_line(uart, cmd);
 
-        // Send command
-        for byte in cmd.as_bytes() {
+        // Wait a bit for module to process
+        time::busy_wait_ms(100);
+    }
+
+    /// `send_at_command` for tasks: the line goes through `queue_line`, and
+    /// the pause holds nothing
+    fn queue_at_command(tx: &mut impl rtic::Mutex<T = LoraTx>, cmd: &str) {
+        defmt::info!("Sending AT command: {}", cmd);
+        queue_line(tx, &[cmd.as_bytes()]);
+        time::busy_wait_ms(100);
+    }
+
+    /// Write one AT command and its \r\n without waiting for the reply
+    fn write_line(uart: &mut Serial<pac::UART4>, cmd: &str) {
+        for byte in cmd.as_bytes().iter().chain(b"\r\n") {
             let _ = nb::block!(uart.write(*byte));
         }
+    }
 
-        // Send \r\n
-        let _ = nb::block!(uart.write(b'\r'));
-        let _ = nb::block!(uart.write(b'\n'));
-
-        // Wait a bit for module to process
-        cortex_m::asm::delay(8_400_000); // ~100ms at 84 MHz
+    /// Write one AT command and wait up to `module::REPLY_MS` for the
+    /// module's answer
+    fn exchange_at_command(uart: &mut Serial<pac::UART4>, cmd: &str) -> Option<module::Reply> {
+        defmt::info!("Sending AT command: {}", cmd);
+        write_line(uart, cmd);
+        let mut lines = module::Lines::new();
+        let mut reply = None;
+        time::poll_ms(module::REPLY_MS, || {
+            if let Ok(byte) = uart.read() {
+                reply = lines.push(byte);
+            }
+            reply.is_some()
+        });
+        reply
     }
 
-    #[init]
+    #[init(local = [
+        rx_queue: Queue<ParsedMessage, RX_QUEUE_LEN> = Queue::new(),
+        tx_queue: Queue<RadioCommand, TX_QUEUE_LEN> = Queue::new(),
+    ])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
-        let dp = cx.device;
+        let mut dp = cx.device;
+        let mut core = cx.core;
+
+        // Reset cause must be read before anything can reset us again
+        let reset_cause = ResetCause::read_and_clear(&dp.RCC);
+        defmt::info!("N2 reset cause: {}", reset_cause);
+        if let Some(missed) = watchdog::take_missed() {
+            if reset_cause == ResetCause::IndependentWatchdog {
+                watchdog::log_missed(missed);
+            }
+        }
+        let previous_crash = crash::take_unreported();
+        if let Some(report) = previous_crash.as_ref().and_then(|crash| crash.report()) {
+            defmt::error!("N2 previous crash at {}:{}: {}", report.file, report.line, report.message);
+        }
+        crash::set_radio_peer(1);
+        let boot_count = config::count_boot();
+        defmt::info!("N2 boot #{} ({})", boot_count, reset_cause);
+        if command::DEV_KEY_IN_USE {
+            defmt::warn!("Remote commands use the development key; set WK3_COMMAND_KEY for a field build");
+        }
+        let settings = config::load_settings().unwrap_or_else(Settings::current);
+        settings.apply();
+        defmt::info!("N2 settings: {}", settings);
+        let hop_plan = hopping::load().unwrap_or_default();
+        hop_plan.apply();
+        backup::init();
+        let rx_stats = match backup::load_rx() {
+            Some(counters) => {
+                defmt::info!("N2 receive counters carried over: {}", counters);
+                RxStats::restore(counters)
+            }
+            None => RxStats::new(),
+        };
 
         // 1. Configure RCC clocks
-        let mut rcc = dp.RCC.freeze(Config::hsi().sysclk(84.MHz()));
+        #[cfg(not(feature = "usb-json"))]
+        let mut rcc = dp.RCC.freeze(Config::hsi().sysclk(time::SYSCLK_HZ.Hz()));
+        // USB needs 48 MHz to 0.25 %, beyond the HSI: run from the ST-LINK's
+        // 8 MHz MCO on PH0 instead, which the Nucleo connects by default
+        #[cfg(feature = "usb-json")]
+        let mut rcc = dp.RCC.freeze(
+            Config::hse(8.MHz()).bypass_hse_oscillator().sysclk(time::SYSCLK_HZ.Hz()).require_pll48clk(),
+        );
+
+        // Cycle counter first: busy_wait_ms() relies on it during AT configuration below
+        time::enable_cycle_counter(&mut core.DCB, &mut core.DWT);
+        let mono = Mono::new(core.SYST, time::SYSCLK_HZ);
 
         // 2. Split GPIOs
         let gpioa = dp.GPIOA.split(&mut rcc);
@@ -215,6 +745,13 @@ mod app {
 
         let led = gpioa.pa5.into_push_pull_output();
 
+        // User button (external pull-up, active-low) on EXTI13
+        let mut syscfg = dp.SYSCFG.constrain(&mut rcc);
+        let mut button = gpioc.pc13.into_input();
+        button.make_interrupt_source(&mut syscfg);
+        button.trigger_on_edge(&mut dp.EXTI, Edge::RisingFalling);  // Press and release, for long presses
+        button.enable_interrupt(&mut dp.EXTI);
+
         // --- UART4 for LoRa ---
         let tx = gpioc.pc10.into_alternate();
         let rx = gpioc.pc11.into_alternate();
@@ -227,18 +764,38 @@ mod app {
 
         // Configure LoRa module before enabling RX interrupt
         defmt::info!("Configuring LoRa module (Node 2)...");
+        let config = config::load().unwrap_or(DEFAULT_CONFIG);
+        region::log();
+        let config = NodeConfig { band_mhz: region::checked_band(config.band_mhz), ..config };
+        defmt::info!("N2 config: {}", config);
         send_at_command(&mut lora_uart, "AT");
-        send_at_command(&mut lora_uart, "AT+ADDRESS=2");
-
-        let mut cmd_buf: String<32> = String::new();
-        let _ = core::write!(cmd_buf, "AT+NETWORKID={}", NETWORK_ID);
-        send_at_command(&mut lora_uart, cmd_buf.as_str());
-
-        cmd_buf.clear();
-        let _ = core::write!(cmd_buf, "AT+BAND={}000000", LORA_FREQ);
-        send_at_command(&mut lora_uart, cmd_buf.as_str());
-
-        send_at_command(&mut lora_uart, "AT+PARAMETER=7,9,1,7");
+        // Only what the module doesn't already have is written (see module.rs)
+        let applied = module::Applied::new(&config, LORA_PARAMETER, battery::tx_power_limit());
+        let last_applied = module::load();
+        let report = module::bring_up(&applied, last_applied.as_ref(), |cmd| exchange_at_command(&mut lora_uart, cmd));
+        defmt::info!("Module settings written: {} of {}", report.written, module::Item::ALL.len());
+        if report.failed == 0 && last_applied.as_ref() != Some(&applied) {
+            if let Err(e) = module::save(&applied) {
+                defmt::warn!("Module settings save failed: {}", e);
+            }
+        }
+        airtime::set_band(config.band_mhz);
+        hopping::set_network(config.band_mhz, config.network_id);
+        config::set_node_id(config.address);
+
+        // Send the last crash again now the radio is up: the frame sent
+        // while crashing often doesn't make it (see crash.rs)
+        let mut accountant = Accountant::new(LoraParams::parse(LORA_PARAMETER).expect("LORA_PARAMETER"));
+        if let Some(crash) = previous_crash {
+            match crash.transmit() {
+                Some(len) => {
+                    accountant.record(0, len);
+                    crash.mark_reported();
+                    defmt::info!("Crash report resent ({} bytes)", len);
+                }
+                None => defmt::warn!("Crash report not sent, retrying next boot"),
+            }
+        }
 
         // Flush any pending responses from configuration BEFORE enabling interrupt
         while lora_uart.read().is_ok() {}
@@ -255,33 +812,107 @@ mod app {
         defmt::info!("LoRa module configured");
         lora_uart.listen(SerialEvent::RxNotEmpty);
 
+        // --- USART2 debug shell (ST-LINK virtual COM port) ---
+        let mut console = Serial::new(
+            dp.USART2,
+            (gpioa.pa2.into_alternate(), gpioa.pa3.into_alternate()),
+            SerialConfig::default().baudrate(115200.bps()),
+            &mut rcc
+        ).unwrap();
+        console.listen(SerialEvent::RxNotEmpty);
+
         // --- I2C1 for Display ---
-        let scl = gpiob.pb8.into_alternate_open_drain();
-        let sda = gpiob.pb9.into_alternate_open_drain();
-        let i2c = I2c::new(dp.I2C1, (scl, sda), 100.kHz(), &mut rcc);
+        #[cfg(not(feature = "st7789"))]
+        let mut display = {
+            let scl = gpiob.pb8.into_alternate_open_drain();
+            let sda = gpiob.pb9.into_alternate_open_drain();
+            let i2c = I2c::new(dp.I2C1, (scl, sda), 100.kHz(), &mut rcc);
 
-        let i2c_compat = I2cCompat(i2c);
-        let bus: &'static BusManager = shared_bus::new_cortexm!(I2cCompat<MyI2c> = i2c_compat).unwrap();
+            let i2c_compat = I2cCompat(i2c);
+            let bus: &'static BusManager = shared_bus::new_cortexm!(I2cCompat<MyI2c> = i2c_compat).unwrap();
 
-        // --- Display ---
-        let interface = I2CInterface::new(bus.acquire_i2c(), 0x3C, 0x40);
-        let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
-            .into_buffered_graphics_mode();
-        display.init().unwrap();
+            display::new(bus.acquire_i2c()).unwrap()
+        };
+
+        // --- SPI2 for the TFT ---
+        #[cfg(feature = "st7789")]
+        let mut display = {
+            let mode = Mode { polarity: Polarity::IdleHigh, phase: Phase::CaptureOnSecondTransition };
+            let spi = Spi::new(
+                dp.SPI2,
+                (gpiob.pb13.into_alternate(), NoPin::new(), gpiob.pb15.into_alternate()),
+                mode,
+                21.MHz(),
+                &mut rcc,
+            );
+            let cs = gpiob.pb12.into_push_pull_output();
+            let device = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(spi, cs).unwrap();
+            let interface = display_interface_spi::SPIInterface::new(device, gpiob.pb14.into_push_pull_output());
+            wk3_binary_protocol::tft::new(
+                interface,
+                gpiob.pb1.into_push_pull_output(),
+                gpiob.pb2.into_push_pull_output(),
+                &mut time::CycleDelay,
+            )
+            .unwrap()
+        };
+
+        // --- SPI1 for the SD card log ---
+        #[cfg(feature = "sd-log")]
+        let sd = {
+            use stm32f4xx_hal::spi::{Mode as SpiMode, Phase as SpiPhase, Polarity as SpiPolarity, Spi as SdSpi};
+            // 400 kHz until the card is initialised, see sdlog.rs
+            let spi = SdSpi::new(
+                dp.SPI1,
+                (gpiob.pb3.into_alternate(), gpiob.pb4.into_alternate(), gpiob.pb5.into_alternate()),
+                SpiMode { polarity: SpiPolarity::IdleLow, phase: SpiPhase::CaptureOnFirstTransition },
+                400.kHz(),
+                &mut rcc,
+            );
+            let mut cs = gpiob.pb6.into_push_pull_output();
+            cs.set_high();
+            let device = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(spi, cs).unwrap();
+            match sdlog::Logger::mount(embedded_sdmmc::SdCard::new(device, time::CycleDelay)) {
+                Ok(logger) => {
+                    sdlog::spi1_full_speed();
+                    defmt::info!("SD: card mounted, logging as run {}", logger.run());
+                    Some(logger)
+                }
+                Err(e) => {
+                    defmt::warn!("SD: no usable card ({}), not logging", e);
+                    None
+                }
+            }
+        };
+
+        // --- USB OTG FS serial port for JSON lines ---
+        #[cfg(feature = "usb-json")]
+        let usb = {
+            let otg = USB::new(
+                (dp.OTG_FS_GLOBAL, dp.OTG_FS_DEVICE, dp.OTG_FS_PWRCLK),
+                (gpioa.pa11, gpioa.pa12),
+                &rcc.clocks,
+            );
+            let endpoints = cortex_m::singleton!(: [u32; 1024] = [0; 1024]).unwrap();
+            let bus = cortex_m::singleton!(: usb_device::bus::UsbBusAllocator<UsbBusType> =
+                UsbBus::new(otg, endpoints)).unwrap();
+            defmt::info!("USB: JSON lines on the CDC-ACM port");
+            Gateway::new(bus)
+        };
 
         // Initial display message
         let style = MonoTextStyleBuilder::new()
-            .font(&FONT_6X10)
+            .font(display::FONT)
             .text_color(BinaryColor::On)
             .build();
-        let _ = display.clear(BinaryColor::Off);
-        Text::new("N2 RECEIVER", Point::new(0, 8), style).draw(&mut display).ok();
+        display.clear_frame();
+        Text::new("N2 RECEIVER", Point::new(0, display::ROWS[0]), style).draw(&mut display).ok();
 
         let mut init_buf: String<32> = String::new();
-        let _ = core::write!(init_buf, "Net:{} {}MHz", NETWORK_ID, LORA_FREQ);
-        Text::new(&init_buf, Point::new(0, 20), style).draw(&mut display).ok();
+        let _ = core::write!(init_buf, "Net:{} {}MHz", config.network_id, config.band_mhz);
+        Text::new(&init_buf, Point::new(0, display::ROWS[1]), style).draw(&mut display).ok();
 
-        Text::new("Waiting...", Point::new(0, 32), style).draw(&mut display).ok();
+        Text::new("Waiting...", Point::new(0, display::ROWS[2]), style).draw(&mut display).ok();
         let _ = display.flush();
 
         // --- Timer for LED blinking ---
@@ -289,75 +920,425 @@ mod app {
         timer.start(2.Hz()).unwrap();  // 2 Hz for heartbeat
         timer.listen(Event::Update);
 
+        // --- Inter-task queues ---
+        let (rx_producer, rx_consumer) = cx.local.rx_queue.split();
+        let (tx_producer, tx_consumer) = cx.local.tx_queue.split();
+
+        power::gate_unused(&BOARD);
+
+        // --- Watchdog (last: init itself takes longer than the timeout) ---
+        let watchdog = Supervisor::start(dp.IWDG, WATCHDOG_TIMEOUT_MS, &[
+            Checkpoint::Heartbeat,
+            Checkpoint::Processing,
+            Checkpoint::RadioTx,
+            Checkpoint::Display,
+        ]);
+
+        let _ = cpu_stats::spawn_after(CPU_STATS_INTERVAL_SECS.secs());
+        let _ = request_replay::spawn_after(REPLAY_CHECK_SECS.secs());
+        let _ = link_report::spawn_after(LINK_INTERVAL_SECS.secs());
+        let _ = hop_channel::spawn();
+
         (
             Shared {
-                lora_uart,
+                lora_tx: Deque::new(),
                 display,
-                last_packet: None,
-                packets_received: 0,
+                rx_stats,
+                gaps: Gaps::new(),
+                peers: Peers::new(),
+                page: Page::Live,
+                saver: Screensaver::new(),
+                config,
+                menu: None,
+                tx_producer,
+                airtime: accountant,
+                adr: Adr::new(LoraParams::parse(LORA_PARAMETER).expect("LORA_PARAMETER")),
+                scan: Scan::new(),
+                pinger: Pinger::new(),
+                // Counters keep rising across resets, as the nodes require
+                command_counter: boot_count << 16,
+                broadcasts: 0,
+                console,
+                #[cfg(feature = "usb-json")]
+                usb,
             },
             Local {
                 led,
                 timer,
+                lora_uart,
                 rx_buffer: Vec::new(),
+                rx_producer,
+                rx_consumer,
+                tx_consumer,
+                latest_packet: None,
+                watchdog,
+                shell: LineBuffer::new(),
+                uplink: Uplink::new(),
+                button,
+                reset_cause,
+                settings,
+                hop_plan,
+                joined: join::load().unwrap_or_default(),
+                #[cfg(feature = "sd-log")]
+                sd,
             },
-            init::Monotonics()
+            init::Monotonics(mono)
         )
     }
 
-    #[task(binds = TIM2, shared = [display, last_packet, packets_received], local = [led, timer])]
+    // Idle: sleep the core until the next interrupt
+    //
+    // Every task is interrupt driven, so there is nothing to do between ISRs.
+    // WFI only gates the core clock: SysTick (the monotonic), TIM2 and UART4
+    // keep running and any of them wakes the core within a few cycles, so RX
+    // latency is unaffected and the RYLR998 stays in RX throughout.
+    //
+    // With `low-power` the core also runs at 42 MHz for good, which roughly
+    // halves both the Sleep and the run current. APB1 keeps its clock, so
+    // UART4 and the display bus don't notice (see `power::PowerProfile`).
+    // STOP isn't an option here: no USART can wake the F446 from it, and
+    // waking on the RX pin's edge would lose the first bytes of each frame
+    // while the PLL relocks.
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        if LOW_POWER {
+            power::set_profile(PowerProfile::Economy);
+        }
+        loop {
+            cortex_m::asm::wfi();
+        }
+    }
+
+    // Heartbeat: LED, watchdog, and kicking the display refresh
+    #[task(binds = TIM2, priority = 2, shared = [adr, config, scan], local = [led, timer, watchdog])]
     fn tim2_handler(mut cx: tim2_handler::Context) {
+        let _busy = TIM2_STATS.enter();
         cx.local.timer.clear_flags(stm32f4xx_hal::timer::Flag::Update);
         cx.local.led.toggle();
 
-        // Copy packet data quickly while holding lock
-        let packet_copy = cx.shared.last_packet.lock(|pkt_opt| *pkt_opt);
-        let total_count = cx.shared.packets_received.lock(|count| *count);
+        // Feed the IWDG only if every task checked in since the last feed
+        watchdog::check_in(Checkpoint::Heartbeat);
+        cx.local.watchdog.poll();
 
-        defmt::info!("N2 Timer: total_count={}, has_packet={}", total_count, packet_copy.is_some());
+        // Already pending means the previous refresh is still flushing - skip this one
+        let _ = display_refresh::spawn();
 
-        // Update display OUTSIDE locks (slow I2C is OK here in timer context)
-        if let Some(parsed) = packet_copy {
-            cx.shared.display.lock(|disp| {
-                let _ = disp.clear(BinaryColor::Off);
-                let style = MonoTextStyleBuilder::new()
-                    .font(&FONT_6X10)
-                    .text_color(BinaryColor::On)
-                    .build();
+        // Prove the priority 3 and 4 dispatchers are still servicing tasks
+        let _ = processing_probe::spawn();
+        let _ = radio_tx::spawn();
 
-                let mut buf: String<64> = String::new();
+        // No readings for a while on a stepped spreading factor (see adr.rs)
+        if let Some(sf) = cx.shared.adr.lock(|adr| adr.fallback(now_ms())) {
+            defmt::warn!("ADR: nothing heard, back to SF{}", sf);
+            let _ = apply_spreading_factor::spawn(sf);
+        }
 
-                // Line 1: Temperature & Humidity
-                let _ = core::write!(buf, "T:{:.1}C H:{:.0}%",
-                    parsed.sensor_data.temperature, parsed.sensor_data.humidity);
-                Text::new(&buf, Point::new(0, 8), style).draw(disp).ok();
+        // Nothing heard for a long while: try other settings (see scan.rs)
+        let sf = cx.shared.adr.lock(|adr| adr.sf());
+        let (network_id, interval_s) = cx.shared.config.lock(|config| (config.network_id, config.tx_interval_s));
+        let home = Tune { network_id, sf };
+        let dwell_ms = u32::from(interval_s) * 1_000 + scan::MARGIN_MS;
+        if let Some(tune) = cx.shared.scan.lock(|scan| scan.poll(now_ms(), home, dwell_ms)) {
+            let _ = apply_scan::spawn(tune);
+        }
+    }
 
-                buf.clear();
-                // Line 2: Gas resistance
-                let _ = core::write!(buf, "Gas:{:.0}k",
-                    parsed.sensor_data.gas_resistance as f32 / 1000.0);
-                Text::new(&buf, Point::new(0, 20), style).draw(disp).ok();
+    // Watchdog probe - shares process_frame's priority (and dispatcher), so it
+    // only runs if frame processing is not wedged
+    #[task(priority = 3)]
+    fn processing_probe(_: processing_probe::Context) {
+        watchdog::check_in(Checkpoint::Processing);
+    }
 
-                buf.clear();
-                // Line 3: Node ID and packet info
-                let _ = core::write!(buf, "{} RX #{:04}",
-                    NODE_ID, parsed.sensor_data.packet_num);
-                Text::new(&buf, Point::new(0, 32), style).draw(disp).ok();
+    // CPU load, per-task run times and lock hold times, every 10s
+    //
+    // Validates the "keep the ISR fast" rule: uart4 max must stay well
+    // under one byte time at 115200 baud (~87us) or the FIFO overruns.
+    #[task(priority = 1, local = [meter: LoadMeter = LoadMeter::new(0)])]
+    fn cpu_stats(cx: cpu_stats::Context) {
+        let load = cx.local.meter.take_load(now_ms());
+        cpu::report(load, &[
+            &UART4_STATS, &RADIO_TX_STATS, &PROCESS_STATS,
+            &TIM2_STATS, &BUTTON_STATS, &DISPLAY_STATS, &ACTIVITY_STATS, &SHELL_STATS, &CONFIG_STATS, &SD_STATS,
+            &SETTINGS_STATS, &GAPS_STATS, &USB_STATS, &STREAM_STATS, &MQTT_STATS, &JOIN_STATS, &ADR_STATS,
+            &HOP_STATS, &SCAN_STATS, &PING_STATS, &FACTORY_STATS, &LINK_STATS,
+        ]);
+        metrics::report();
+
+        let _ = cpu_stats::spawn_after(CPU_STATS_INTERVAL_SECS.secs());
+    }
 
-                buf.clear();
-                // Line 4: Network ID and frequency
-                let _ = core::write!(buf, "Net:{} {}MHz",
-                    NETWORK_ID, LORA_FREQ);
-                Text::new(&buf, Point::new(0, 44), style).draw(disp).ok();
+    /// What the display pages need from a decoded packet
+    fn reading(parsed: &ParsedMessage) -> Reading {
+        Reading {
+            temperature: parsed.sensor_data.temperature,
+            humidity: parsed.sensor_data.humidity,
+            gas_resistance: parsed.sensor_data.gas_resistance,
+            heater_step: parsed.sensor_data.heater_step,
+            iaq: parsed.sensor_data.iaq,
+            co2_ppm: parsed.sensor_data.co2_ppm,
+            battery_mv: parsed.sensor_data.battery_mv,
+            packet_num: parsed.sensor_data.packet_num,
+            rssi: parsed.rssi,
+            snr: parsed.snr,
+        }
+    }
+
+    // User button: short press steps to the next display page (or just wakes
+    // the display), long press opens the setup menu; inside the menu the two
+    // presses change a value and move to the next field (see menu.rs)
+    //
+    // Fires on both edges: the press starts timing, the release acts.
+    #[task(binds = EXTI15_10, priority = 2, shared = [page, saver, menu, config], local = [button, last_edge_ms: u32 = 0, pressed_at: Option<u32> = None])]
+    fn button_handler(mut cx: button_handler::Context) {
+        let _busy = BUTTON_STATS.enter();
+        cx.local.button.clear_interrupt_pending_bit();
+
+        let now = now_ms();
+        if time::elapsed_ms(now, *cx.local.last_edge_ms) < BUTTON_DEBOUNCE_MS {
+            return;
+        }
+        *cx.local.last_edge_ms = now;
+
+        // Active-low: low after the edge means it was just pressed
+        if cx.local.button.is_low() {
+            *cx.local.pressed_at = Some(now);
+            factory::press(now);
+            let _ = factory_hold::spawn_after((factory::HOLD_MS as u64).millis(), now);
+            return;
+        }
+        let Some(pressed_at) = cx.local.pressed_at.take() else { return };
+        let long = time::elapsed_ms(now, pressed_at) >= menu::LONG_PRESS_MS;
+        // Let go during the countdown: no reset, and no menu either
+        if factory::release(now) {
+            defmt::info!("Factory reset cancelled");
+            let _ = display_refresh::spawn();
+            return;
+        }
+
+        // A press on a dimmed or blank screen only wakes it
+        if cx.shared.saver.lock(|saver| saver.wake(now)) {
+            let _ = display_refresh::spawn();
+            return;
+        }
+        // The first short press after a crash only takes the notice down
+        if !long && crash::acknowledge() {
+            defmt::info!("Crash notice acknowledged");
+            let _ = display_refresh::spawn();
+            return;
+        }
+
+        let menu_open = cx.shared.menu.lock(|menu| menu.is_some());
+        match (menu_open, long) {
+            (false, false) => {
+                let page = cx.shared.page.lock(|page| {
+                    *page = page.next();
+                    *page
+                });
+                defmt::info!("Display page: {}", page);
+            }
+            (false, true) => {
+                let config = cx.shared.config.lock(|config| *config);
+                cx.shared.menu.lock(|menu| *menu = Some(Menu::open(config, MENU_FIELDS)));
+                defmt::info!("Setup menu opened");
+            }
+            (true, false) => cx.shared.menu.lock(|menu| {
+                if let Some(menu) = menu {
+                    menu.short_press();
+                }
+            }),
+            (true, true) => {
+                let outcome = cx.shared.menu.lock(|menu| {
+                    let outcome = menu.as_mut()?.long_press();
+                    if outcome.is_some() {
+                        *menu = None;
+                    }
+                    outcome
+                });
+                match outcome {
+                    Some(Outcome::Save(config)) => {
+                        let _ = apply_config::spawn(config);
+                    }
+                    Some(Outcome::Cancel) => defmt::info!("Setup menu cancelled"),
+                    None => {}
+                }
+            }
+        }
 
-                buf.clear();
-                // Line 5: RSSI and SNR with total count
-                let _ = core::write!(buf, "RSSI:{} SNR:{} #{}",
-                    parsed.rssi, parsed.snr, total_count);
-                Text::new(&buf, Point::new(0, 56), style).draw(disp).ok();
+        // Redraw now rather than on the next heartbeat
+        let _ = display_refresh::spawn();
+    }
 
-                let _ = disp.flush();  // Slow I2C flush is safe here
-            });
+    // Save settings from the setup menu and push them to the radio module
+    //
+    // The AT commands pace themselves with 100 ms busy waits, here at
+    // priority 1 with nothing locked, so the radio path carries on.
+    #[task(priority = 1, shared = [lora_tx, config])]
+    fn apply_config(mut cx: apply_config::Context, config: NodeConfig) {
+        let _busy = CONFIG_STATS.enter();
+        if let Err(e) = config.check() {
+            defmt::warn!("Config {} refused: {}", config, e);
+            return;
+        }
+        defmt::info!("Applying config: {}", config);
+        if let Err(e) = config::save(&config) {
+            defmt::error!("Config save failed: {}", e);
+        }
+        // The module's "+OK" replies land in uart4 afterwards and are ignored there
+        config.write_at_commands(|cmd| queue_at_command(&mut cx.shared.lora_tx, cmd));
+        cx.shared.config.lock(|current| *current = config);
+        airtime::set_band(config.band_mhz);
+        hopping::set_network(config.band_mhz, config.network_id);
+        config::set_node_id(config.address);
+    }
+
+    // Display refresh - lowest priority so slow I2C never delays the radio
+    #[task(priority = 1, shared = [display, rx_stats, page, saver, menu, config, peers, scan, pinger], local = [rx_consumer, latest_packet, reset_cause, trends: Trends = Trends::new(), dirty: DirtyRows = DirtyRows::new(), shown: Option<(DisplayPower, u8)> = None])]
+    fn display_refresh(mut cx: display_refresh::Context) {
+        let _busy = DISPLAY_STATS.enter();
+        // Drain every packet decoded since the last tick; the newest one is displayed
+        let mut drained = 0u8;
+        while let Some(parsed) = cx.local.rx_consumer.dequeue() {
+            drained += 1;
+            cx.local.trends.push(&reading(&parsed));
+            *cx.local.latest_packet = Some(parsed);
+        }
+
+        let packet_copy = *cx.local.latest_packet;
+        let now = now_ms();
+        let rx = cx.shared.rx_stats.lock(|stats| metrics::COUNTERS.measure(|| stats.snapshot(now)));
+        let page = cx.shared.page.lock(|page| *page);
+        let config = cx.shared.config.lock(|config| *config);
+        let menu = cx.shared.menu.lock(|menu| *menu);
+        let peers = cx.shared.peers.lock(|peers| peers.clone());
+        let scan = cx.shared.scan.lock(|scan| scan.status());
+        let ping = cx.shared.pinger.lock(|pinger| pinger.shown(now));
+        let countdown = factory::countdown(now);
+
+        // Link age: how long since the last valid frame was parsed
+        let link_age_ms = packet_copy.map(|p| time::elapsed_ms(now, p.rx_time_ms));
+
+        defmt::info!("N2 Timer: total={} rate={}/min loss={}.{}% of {} drained={} link_age_ms={}",
+            rx.total, rx.per_minute, rx.loss_permille / 10, rx.loss_permille % 10, rx.loss_expected,
+            drained, link_age_ms);
+
+        let screen = Screen {
+            node_id: NODE_ID,
+            reading: packet_copy.as_ref().map(reading),
+            link_age_ms,
+            rx,
+            uptime_ms: now,
+            uptime_s: uptime_s(),
+            reset_cause: *cx.local.reset_cause,
+            crash_notice: crash::notice(),
+            network_id: config.network_id,
+            lora_freq_mhz: config.band_mhz as u32,
+            lora_parameter: LORA_PARAMETER,
+            watchdog_timeout_ms: WATCHDOG_TIMEOUT_MS,
+            trends: cx.local.trends,
+            peers: &peers,
+            scan,
+            ping,
+        };
+
+        // New packets count as activity for the screensaver
+        let saver_power = cx.shared.saver.lock(|saver| {
+            if drained > 0 || countdown.is_some() {
+                saver.wake(now);
+            }
+            saver.update(now);
+            saver.power()
+        });
+        // `display off` and the night window blank it whatever the saver says,
+        // except for the setup menu and a factory reset countdown
+        let power = if menu.is_none() && countdown.is_none() && !panel::wanted_on(screen.uptime_s) {
+            DisplayPower::Blank
+        } else {
+            saver_power
+        };
+        let contrast = panel::contrast();
+        let changed = cx.local.shown.replace((power, contrast)) != Some((power, contrast));
+
+        // Update display OUTSIDE other locks (slow I2C is OK at priority 1)
+        cx.shared.display.lock(|disp| metrics::DISPLAY.measure(|| {
+            if changed {
+                defmt::info!("Display power: {} contrast {}", power, contrast);
+                if power == DisplayPower::Dimmed {
+                    let _ = disp.set_dimmed(true);
+                } else {
+                    let _ = disp.set_contrast_level(contrast);
+                }
+                let _ = disp.set_on(power != DisplayPower::Blank);
+            }
+            // Nothing visible to update while blank; skip the 40ms flush.
+            // Otherwise redraw just the rows that differ from what's shown.
+            if power != DisplayPower::Blank {
+                if let Some(area) = cx.local.dirty.changed(|f| render(f, countdown, &menu, page, &screen)) {
+                    let _ = disp.fill_solid(&area, BinaryColor::Off);
+                    render(&mut Clip::new(disp, area), countdown, &menu, page, &screen);
+                    let _ = disp.flush();  // Slow I2C flush is safe here
+                }
+            }
+        }));
+
+        // A hung I2C flush never gets here, which starves the watchdog
+        watchdog::check_in(Checkpoint::Display);
+    }
+
+    /// A factory reset countdown while the button is held, the setup menu
+    /// while it's open, otherwise the current page
+    fn render<D: Canvas>(d: &mut D, countdown: Option<u32>, menu: &Option<Menu>, page: Page, screen: &Screen) {
+        match (countdown, menu) {
+            (Some(seconds), _) => factory::draw(d, Some(seconds)),
+            (None, Some(menu)) => menu::draw(d, menu),
+            (None, None) => ui::draw(d, page, screen),
+        }
+    }
+
+    // A button hold that has lasted `factory::HOLD_MS`: wipe the settings
+    // and reboot on the defaults (see factory.rs). display_refresh shows
+    // the countdown until then.
+    #[task(priority = 1, capacity = 2, shared = [display])]
+    fn factory_hold(mut cx: factory_hold::Context, pressed_at: u32) {
+        let _busy = FACTORY_STATS.enter();
+        match factory::hold(pressed_at, now_ms()) {
+            Hold::Released => return,
+            Hold::Counting(_) => {
+                let _ = factory_hold::spawn_after(FACTORY_RECHECK_MS.millis(), pressed_at);
+                return;
+            }
+            Hold::Due => {}
+        }
+        cx.shared.display.lock(|disp| {
+            disp.clear_frame();
+            factory::draw(disp, None);
+            let _ = disp.flush();
+        });
+        defmt::warn!("Factory reset from the button");
+        if let Err(e) = config::factory_reset() {
+            defmt::error!("Factory reset of flash failed: {}", e);
+        }
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    // Corner indicator: light up on RX or ACK, then clear after a short pulse
+    //
+    // Only the 7x7 corner is redrawn, so the flush is a few bytes and the
+    // link shows signs of life between the 2 Hz full refreshes.
+    #[task(priority = 1, capacity = 4, shared = [display, saver])]
+    fn activity_pulse(mut cx: activity_pulse::Context, activity: Activity) {
+        let _busy = ACTIVITY_STATS.enter();
+        if cx.shared.saver.lock(|saver| saver.power()) == DisplayPower::Blank || !panel::wanted_on(uptime_s()) {
+            return;
+        }
+
+        cx.shared.display.lock(|disp| metrics::DISPLAY.measure(|| {
+            ui::draw_activity(disp, activity);
+            let _ = disp.flush();
+        }));
+
+        if activity != Activity::Idle {
+            let _ = activity_pulse::spawn_after(ACTIVITY_PULSE_MS.millis(), Activity::Idle);
         }
     }
 
@@ -367,84 +1348,1184 @@ mod app {
     // Previous attempts with extensive ORE flag checking, status register logging,
     // and diagnostic code caused data corruption/scrambling.
     //
-    // This simpler version from commit 80c7c5e works reliably:
+    // The ISR only captures bytes:
     // 1. Read all available bytes
     // 2. Check for message terminator (\n)
-    // 3. Process complete message OUTSIDE the UART lock
-    // 4. Clear buffer for next message
+    // 3. Hand the complete frame to `process_frame` (lower priority)
+    // 4. Start a fresh buffer for the next message
+    //
+    // CRC, postcard decode, state updates and the ACK all happen in
+    // `process_frame`, so ISR time stays bounded no matter what arrives.
     //
-    // NO display updates here - those happen in the timer interrupt
-    #[task(binds = UART4, shared = [lora_uart, last_packet, packets_received], local = [rx_buffer])]
+    // It also writes what `queue_line` has queued, as many bytes as the
+    // data register takes now and the rest on later TXE interrupts. The
+    // UART belongs to this task alone, so sending never holds off
+    // reception.
+    #[task(binds = UART4, priority = 5, shared = [lora_tx], local = [lora_uart, rx_buffer])]
     fn uart4_handler(mut cx: uart4_handler::Context) {
+        let _busy = UART4_STATS.enter();
+        let uart = cx.local.lora_uart;
         // Read ALL available bytes from UART in one interrupt
         let mut should_process = false;
         let mut bytes_read = 0u16;
 
-        cx.shared.lora_uart.lock(|uart| {
-            // Drain all available bytes from UART buffer
-            while let Ok(byte) = uart.read() {
-                bytes_read += 1;
-                // Add byte to buffer (with overflow protection)
-                if cx.local.rx_buffer.len() < RX_BUFFER_SIZE {
-                    let _ = cx.local.rx_buffer.push(byte);
+        loop {
+            let byte = match uart.read() {
+                Ok(byte) => byte,
+                Err(nb::Error::WouldBlock) => break,
+                // Overrun, noise or framing: the HAL has cleared it,
+                // and the frame it hit fails its CRC
+                Err(nb::Error::Other(_)) => {
+                    linkstats::note_uart_error();
+                    continue;
                 }
-                // Check for complete message (ends with \n)
-                if byte == b'\n' {
-                    should_process = true;
+            };
+            bytes_read += 1;
+            // Add byte to buffer (with overflow protection)
+            if cx.local.rx_buffer.len() < RX_BUFFER_SIZE {
+                let _ = cx.local.rx_buffer.push(byte);
+            }
+            // Check for complete message (ends with \n)
+            if byte == b'\n' {
+                should_process = true;
+            }
+        }
+
+        // Priority 5 is the queue's ceiling, so this lock never waits
+        cx.shared.lora_tx.lock(|queue| {
+            while let Some(&byte) = queue.front() {
+                if uart.write(byte).is_err() {
+                    break;
                 }
+                queue.pop_front();
+            }
+            if queue.is_empty() {
+                uart.unlisten(SerialEvent::TxEmpty);
+            } else {
+                uart.listen(SerialEvent::TxEmpty);
             }
         });
 
         // Log that we got UART interrupt and how many bytes
         if bytes_read > 0 {
-            defmt::info!("UART INT: {} bytes, complete={}", bytes_read, should_process);
+            trace!("UART INT: {} bytes, complete={}", bytes_read, should_process);
         }
 
-        // Process message OUTSIDE uart lock to allow new interrupts
         if should_process {
-            // Debug: log buffer length and attempt to show as text
-            defmt::info!("Processing buffer: {} bytes", cx.local.rx_buffer.len());
-            if let Ok(msg_text) = core::str::from_utf8(cx.local.rx_buffer.as_slice()) {
-                defmt::info!("Buffer as text: {}", msg_text);
-            }
-
-            // Parse +RCV message format: +RCV=<Address>,<Length>,<Data>,<RSSI>,<SNR>\r\n
-            // The <Data> part is now BINARY (not text), but RSSI/SNR are still text
-            if let Some(parsed) = parse_binary_lora_message(cx.local.rx_buffer.as_slice()) {
-                defmt::info!("Binary RX - T:{} H:{} G:{} Pkt:{} RSSI:{} SNR:{}",
-                    parsed.sensor_data.temperature, parsed.sensor_data.humidity,
-                    parsed.sensor_data.gas_resistance, parsed.sensor_data.packet_num,
-                    parsed.rssi, parsed.snr);
-
-                // Store parsed data for timer interrupt to display
-                cx.shared.last_packet.lock(|last_pkt| {
-                    *last_pkt = Some(parsed);
-                });
+            // Move the frame out so the ISR can keep collecting immediately
+            let frame = core::mem::replace(cx.local.rx_buffer, Vec::new());
+            if process_frame::spawn(frame, now_ms()).is_err() {
+                defmt::warn!("process_frame queue full, frame dropped");
+            }
+        }
+    }
 
-                cx.shared.packets_received.lock(|count| {
-                    *count += 1;
-                });
+    // Debug shell: echo typed characters and run each completed line
+    //
+    // Lowest priority: a command reply is a few ms of blocking writes at
+    // 115200 baud, which must never delay the radio path. With `stream on`
+    // forwarded frames share the port, so replies may come between records.
+    #[task(binds = USART2, priority = 1, shared = [tx_producer, airtime, peers, adr, scan, pinger, command_counter, broadcasts, console], local = [shell, uplink])]
+    fn debug_shell(mut cx: debug_shell::Context) {
+        let _busy = SHELL_STATS.enter();
+        cx.shared.console.lock(|console| while let Ok(byte) = console.read() {
+            // Records from the host are taken unechoed
+            let record = match cx.local.uplink.push(byte) {
+                uplink::Byte::Shell => None,
+                uplink::Byte::Taken => continue,
+                uplink::Byte::Record(record) => Some(record),
+            };
+            if let Some(record) = record {
+                match frame_type(record) {
+                    // Firmware upload: Node 1 answers each frame itself. The
+                    // begin arrives unsigned and is signed here, like a command
+                    Some(MSG_TYPE_FW_BEGIN) => match sign_upload(&mut cx.shared.command_counter, record) {
+                        Some(frame) => cx.shared.tx_producer.lock(|producer| {
+                            queue_radio_command(producer, RadioCommand::Upload(frame))
+                        }),
+                        None => defmt::warn!("Firmware begin from the host not understood"),
+                    },
+                    Some(MSG_TYPE_FW_CHUNK) => match RawFrame::new(record) {
+                        Some(frame) => cx.shared.tx_producer.lock(|producer| {
+                            queue_radio_command(producer, RadioCommand::Upload(frame))
+                        }),
+                        None => defmt::warn!("Upload frame of {} bytes too long for the radio", record.len()),
+                    },
+                    // A replayed frame goes the way a received one does
+                    #[cfg(feature = "ingest")]
+                    Some(export::RADIO_FRAME | export::RAW_LINE) => match ingest::module_line(record) {
+                        Some(line) => {
+                            if process_frame::spawn(line, now_ms()).is_err() {
+                                defmt::warn!("process_frame queue full, replayed frame dropped");
+                            }
+                        }
+                        None => defmt::warn!("Ingest: record not replayable"),
+                    },
+                    other => defmt::warn!("Host record of type {} ignored", other),
+                }
+                continue;
+            }
+            let _ = nb::block!(console.write(byte));
+            if cx.local.shell.push(byte) {
+                let _ = console.write_str("\r\n");
+                match shell::execute(cx.local.shell.line(), console) {
+                    Some(Command::Calibrate(sensor, trim)) => {
+                        // Node 1 saves it and applies it from its next sample
+                        let cmd = calibration::command(sensor, trim);
+                        cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Calibrate(cmd)));
+                        let _ = console.write_str("sent to N1: ");
+                        let _ = shell::write_trim(console, sensor, &trim);
+                    }
+                    Some(Command::ShowDisplay) => {
+                        let _ = shell::write_display(console, uptime_s());
+                    }
+                    Some(Command::SetDisplay(setting)) => {
+                        // display_refresh picks it up within half a second
+                        setting.apply(uptime_s());
+                        defmt::info!("Display {} from shell", setting);
+                        let _ = shell::write_display(console, uptime_s());
+                    }
+                    Some(Command::SendDisplay(setting)) => {
+                        cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Display(setting)));
+                        let _ = console.write_str("sent to N1\r\n");
+                    }
+                    Some(Command::SendRemote(cmd)) => match sign_next(&mut cx.shared.command_counter, cmd) {
+                        Some(packet) => {
+                            let to = NODE_1.into();
+                            cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Remote { to, packet }));
+                            let _ = write!(console, "sent to N1 as command #{}\r\n", packet.counter);
+                        }
+                        None => {
+                            let _ = console.write_str("couldn't sign it, not sent\r\n");
+                        }
+                    },
+                    Some(Command::SendAll { ack, group, order }) => {
+                        let payload = match order {
+                            Order::Display(setting) => Some(broadcast::Payload::Display(setting)),
+                            Order::Remote(cmd) => sign_next(&mut cx.shared.command_counter, cmd).map(broadcast::Payload::Remote),
+                        };
+                        match payload {
+                            Some(payload) => {
+                                let id = next_broadcast(&mut cx.shared.broadcasts);
+                                cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Broadcast { id, ack, group, payload }));
+                                let _ = write!(console, "broadcast #{}\r\n", id);
+                            }
+                            None => {
+                                let _ = console.write_str("couldn't sign it, not sent\r\n");
+                            }
+                        }
+                    }
+                    Some(Command::ShowAirtime) => {
+                        let (used_us, refused) = cx.shared.airtime.lock(|airtime| (airtime.used_us(now_ms()), airtime.refused()));
+                        let _ = shell::write_airtime(console, used_us, refused);
+                    }
+                    Some(Command::ShowLinkStats) => {
+                        let peers = cx.shared.peers.lock(|peers| peers.clone());
+                        let heard = peers.iter().filter_map(|peer| peer.link);
+                        let _ = shell::write_linkstats(console, uptime_s(), heard, now_ms());
+                    }
+                    Some(Command::ShowStream) => {
+                        let _ = write!(console, "stream: {}\r\n", stream_state());
+                    }
+                    Some(Command::ShowPeers) => {
+                        let peers = cx.shared.peers.lock(|peers| peers.clone());
+                        let _ = shell::write_peers(console, &peers, now_ms());
+                    }
+                    Some(Command::ShowAdr) => {
+                        let state = cx.shared.adr.lock(|adr| adr.clone());
+                        let _ = shell::write_adr(console, &state);
+                    }
+                    Some(Command::ShowScan) => {
+                        let status = cx.shared.scan.lock(|scan| scan.status());
+                        let _ = shell::write_scan(console, status);
+                    }
+                    Some(Command::Ping(to)) => {
+                        let to = to.unwrap_or(NODE_1.into());
+                        let id = cx.shared.pinger.lock(|pinger| pinger.start(to, now_ms()));
+                        cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Ping { to, id }));
+                        let _ = shell::write_ping(console, ping::Outcome::Waiting { id, to });
+                        let _ = ping_result::spawn_after((ping::TIMEOUT_MS as u64).millis(), id, None);
+                    }
+                    Some(Command::SetStream(on)) => {
+                        export::set_streaming(on);
+                        export::set_tapping(false);
+                        defmt::info!("Frame streaming {} from shell", on);
+                        let _ = write!(console, "stream: {}\r\n", stream_state());
+                    }
+                    Some(Command::SetTap) => {
+                        export::set_streaming(true);
+                        export::set_tapping(true);
+                        defmt::info!("Frame streaming with module lines from shell");
+                        let _ = write!(console, "stream: {}\r\n", stream_state());
+                    }
+                    Some(_) => {
+                        let _ = console.write_str("Node 1 only\r\n");
+                    }
+                    None => {}
+                }
+                let _ = save_settings::spawn();
+                let _ = console.write_str("> ");
+                cx.local.shell.clear();
+            }
+        });
+    }
 
-                // Send ACK back to Node 1 (CRC validation passed)
-                cx.shared.lora_uart.lock(|uart| {
-                    send_ack(uart, parsed.sensor_data.packet_num, true);
-                });
-            } else {
-                defmt::warn!("Failed to parse binary message");
+    // Save the shell settings and hop channels if a command or downlink
+    // changed any
+    //
+    // Spawned after every shell line, so it only writes when something
+    // differs from the last save.
+    #[task(priority = 1, local = [settings, hop_plan])]
+    fn save_settings(cx: save_settings::Context) {
+        let _busy = SETTINGS_STATS.enter();
+        let hop_plan = hopping::Plan::current();
+        if hop_plan != *cx.local.hop_plan {
+            match hopping::save(&hop_plan) {
+                Ok(()) => *cx.local.hop_plan = hop_plan,
+                Err(e) => defmt::error!("Hop channels save failed: {}", e),
             }
+        }
+        let settings = Settings::current();
+        if settings == *cx.local.settings {
+            return;
+        }
+        defmt::info!("Saving settings: {}", settings);
+        match config::save_settings(&settings) {
+            Ok(()) => *cx.local.settings = settings,
+            Err(e) => defmt::error!("Settings save failed: {}", e),
+        }
+    }
 
-            // Clear buffer for next message
-            cx.local.rx_buffer.clear();
+    // Save the join table after a node got a new address
+    //
+    // Flash writes stall for a while, so they're kept out of process_frame.
+    #[task(priority = 1, capacity = 2)]
+    fn save_joined(_: save_joined::Context, joined: Joined) {
+        let _busy = JOIN_STATS.enter();
+        if let Err(e) = join::save(&joined) {
+            defmt::error!("Join table save failed: {}", e);
+        }
+    }
+
+    // Frame processing - runs below the UART ISR and above the display refresh
+    //
+    // Validates CRC, decodes the postcard payload, queues the packet for the
+    // display and queues the ACK. Because this is a software task at priority 3,
+    // the display's slow I2C flush (priority 1) can no longer hold off the
+    // radio path, and a long decode can no longer hold off the UART ISR.
+    //
+    // In relay mode it only passes frames on (see relay.rs).
+    #[task(priority = 3, capacity = 4, shared = [rx_stats, gaps, tx_producer, peers, adr, config, scan, pinger], local = [rx_producer, joined, relay: Relay = Relay::new()])]
+    fn process_frame(mut cx: process_frame::Context, frame: Vec<u8, RX_BUFFER_SIZE>, rx_time_ms: u32) {
+        let _busy = PROCESS_STATS.enter();
+        // Debug: log buffer length and attempt to show as text
+        debug!("Processing buffer: {} bytes", frame.len());
+        if let Ok(msg_text) = core::str::from_utf8(frame.as_slice()) {
+            trace!("Buffer as text: {}", msg_text);
+        }
+        // Good or bad, the host sees it as it came off the air
+        if export::streaming() && forward_frame::spawn(frame.clone(), rx_time_ms).is_err() {
+            defmt::warn!("Stream behind, frame not forwarded");
+        }
+        // A good frame ends a scan, or shows ours are the right settings (see scan.rs)
+        if let Ok(Some(rcv)) = split_rcv(frame.as_slice()) {
+            if let (Ok(_), Some(node)) = (decode_frame(rcv.payload), frame_node(rcv.payload)) {
+                if let Some(home) = cx.shared.scan.lock(|scan| scan.heard(node, rx_time_ms)) {
+                    let _ = apply_scan::spawn(home);
+                }
+            }
+        }
+        // A relay answers pings itself rather than passing them on (see ping.rs)
+        let link_check = matches!(split_rcv(frame.as_slice()), Ok(Some(rcv))
+            if matches!(frame_type(rcv.payload), Some(MSG_TYPE_PING | MSG_TYPE_PONG)));
+        if relay::enabled() && !link_check {
+            relay_frame(&mut cx.shared.tx_producer, cx.local.relay, &frame, rx_time_ms);
+            return;
+        }
+
+        // Parse +RCV message format: +RCV=<Address>,<Length>,<Data>,<RSSI>,<SNR>\r\n
+        // The <Data> part is now BINARY (not text), but RSSI/SNR are still text
+        let parsed = match parse_binary_lora_message(frame.as_slice(), rx_time_ms) {
+            Ok(Some(Received::Reading(parsed))) => parsed,
+            Ok(Some(Received::Fault(fault))) => {
+                let count = cx.shared.rx_stats.lock(|stats| metrics::COUNTERS.measure(|| stats.record_fault(fault, rx_time_ms)));
+                defmt::warn!("N1 SENSOR FAULT: {} {} (total {})", fault.sensor.name(), fault.error, count);
+                let _ = activity_pulse::spawn(Activity::Rx);
+                return;
+            }
+            // Old news: logged and ACKed, but kept off the display and the sequence stats
+            Ok(Some(Received::Backfill { node, from, data, age_s })) => {
+                if node == NODE_1 {
+                    backfill(&mut cx.shared.rx_stats, &mut cx.shared.gaps, &data, age_s, rx_time_ms);
+                } else {
+                    defmt::info!("N{} BACKFILL #{}", node, data.packet_num);
+                }
+                let _ = activity_pulse::spawn(Activity::Rx);
+                cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Ack {
+                    to: from,
+                    node,
+                    seq_num: data.packet_num,
+                    slot: None,
+                }));
+                return;
+            }
+            // Backfills too, but Node 2 asks again rather than ACKing them
+            Ok(Some(Received::Batch(batch))) => {
+                for stored in &batch.readings {
+                    let data = sensor_data(&stored.reading);
+                    backfill(&mut cx.shared.rx_stats, &mut cx.shared.gaps, &data, stored.age_s, rx_time_ms);
+                }
+                let _ = activity_pulse::spawn(Activity::Rx);
+                return;
+            }
+            Ok(Some(Received::Join(request))) => {
+                let config = cx.shared.config.lock(|config| *config);
+                let heard: Vec<u8, MAX_PEERS> = cx.shared.peers.lock(|peers| peers.iter().map(|peer| peer.node).collect());
+                // Not this node's own address, nor one a node is using
+                let taken = |address: u8| u16::from(address) == config.address || heard.contains(&address);
+                let joined = cx.local.joined;
+                let known = joined.get(request.uid).is_some();
+                let Some(address) = joined.assign(request.uid, taken) else {
+                    defmt::warn!("Join request from uid {=u32:08X}: no address left", request.uid);
+                    return;
+                };
+                if !known && save_joined::spawn(joined.clone()).is_err() {
+                    defmt::warn!("Join table not saved: still saving the last one");
+                }
+                let accept = JoinAccept {
+                    uid: request.uid,
+                    address: address.into(),
+                    network_id: config.network_id,
+                    tx_interval_s: config.tx_interval_s,
+                };
+                let _ = activity_pulse::spawn(Activity::Rx);
+                cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::JoinAccept(accept)));
+                return;
+            }
+            Ok(Some(Received::Ping { from, answer })) => {
+                let _ = activity_pulse::spawn(Activity::Rx);
+                cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Pong { to: from, pong: answer }));
+                return;
+            }
+            Ok(Some(Received::Pong { from, pong, link })) => {
+                let _ = activity_pulse::spawn(Activity::Rx);
+                match cx.shared.pinger.lock(|pinger| pinger.answered(from, &pong, link, rx_time_ms)) {
+                    Some(echo) => {
+                        let _ = ping_result::spawn(echo.id, Some(echo));
+                    }
+                    None => debug!("Pong #{} from {} not for the ping out", pong.id, from),
+                }
+                return;
+            }
+            Ok(Some(Received::LinkStats(heard))) => {
+                if !cx.shared.peers.lock(|peers| peers.record_link(heard)) {
+                    debug!("Link stats from N{} before any reading, not kept", heard.node);
+                }
+                return;
+            }
+            Ok(Some(Received::PowerFail(packet))) => {
+                cx.shared.rx_stats.lock(|stats| metrics::COUNTERS.measure(|| stats.record_power_fail(rx_time_ms)));
+                defmt::error!("N1 POWER FAILING after {}s, last packet #{}", packet.uptime_s, packet.last_seq);
+                let _ = activity_pulse::spawn(Activity::Rx);
+                return;
+            }
+            Ok(None) => return,
+            Err(error) => {
+                let count = cx.shared.rx_stats.lock(|stats| metrics::COUNTERS.measure(|| {
+                    let count = stats.record_error(error, rx_time_ms);
+                    backup::save_rx(&stats.counters());
+                    count
+                }));
+                defmt::warn!("RX error: {} (total {})", error, count);
+                return;
+            }
+        };
+
+        defmt::info!("Binary RX - T:{} H:{} G:{} (step {}) IAQ:{} Pkt:{} RSSI:{} SNR:{}",
+            parsed.sensor_data.temperature, parsed.sensor_data.humidity,
+            parsed.sensor_data.gas_resistance, parsed.sensor_data.heater_step,
+            parsed.sensor_data.iaq, parsed.sensor_data.packet_num, parsed.rssi, parsed.snr);
+        if parsed.sensor_data.probes_c.iter().any(Option::is_some) {
+            defmt::info!("Binary RX - probes: {}", parsed.sensor_data.probes_c);
+        }
+        if parsed.sensor_data.soil_pct.iter().any(Option::is_some) {
+            defmt::info!("Binary RX - soil: {}", parsed.sensor_data.soil_pct);
+        }
+        // One line per packet for range maps: position, quality and link
+        if let Some(fix) = parsed.sensor_data.gnss {
+            defmt::info!("Binary RX - GNSS: {=i32} {=i32} q={} sats={} RSSI:{} SNR:{}",
+                fix.lat_e7, fix.lon_e7, fix.quality, fix.satellites, parsed.rssi, parsed.snr);
+        }
+
+        let _ = activity_pulse::spawn(Activity::Rx);
+
+        let data = &parsed.sensor_data;
+        let slot = cx.shared.peers.lock(|peers| {
+            peers.record(parsed.node, data.packet_num, data.temperature, data.humidity, data.iaq, parsed.rssi, parsed.snr, rx_time_ms);
+            peers.slot(parsed.node)
+        });
+        // Live readings keep their node in its slot (see slots.rs)
+        let slot = slot.map(|slot| slots::grant(slot, parsed.rx_time_ms));
+        // A relayed frame's SNR is the relay's link, not the node's
+        if parsed.from == u16::from(parsed.node) {
+            let step = cx.shared.adr.lock(|adr| adr.record(parsed.node, parsed.snr, rx_time_ms));
+            if let Some(step) = step {
+                if adr_step::spawn(step).is_err() {
+                    defmt::warn!("ADR step {} dropped", step);
+                }
+            }
+        }
+        // Other sensor nodes get an ACK and their peer entry; the display,
+        // logs, sequence stats and replay follow Node 1
+        if parsed.node != NODE_1 {
+            defmt::info!("N{} reading #{}", parsed.node, data.packet_num);
+            cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Ack {
+                to: parsed.from,
+                node: parsed.node,
+                seq_num: data.packet_num,
+                slot,
+            }));
+            return;
+        }
+
+        // Recorded first, so the JSON line carries the loss this packet left
+        let seq = parsed.sensor_data.packet_num;
+        let (event, loss_permille) = cx.shared.rx_stats.lock(|stats| metrics::COUNTERS.measure(|| {
+            let event = stats.record(seq, rx_time_ms);
+            backup::save_rx(&stats.counters());
+            (event, stats.loss_permille())
+        }));
+
+        // Queue parsed data for the timer interrupt to display
+        if cx.local.rx_producer.enqueue(parsed).is_err() {
+            defmt::warn!("RX queue full, packet #{} not displayed", parsed.sensor_data.packet_num);
+        }
+        #[cfg(feature = "sd-log")]
+        log_to_sd(&parsed.sensor_data, sdlog::Source::Live { rssi: parsed.rssi, snr: parsed.snr }, rx_time_ms);
+        #[cfg(feature = "usb-json")]
+        send_json(&parsed.sensor_data, usbjson::Source::Live { rssi: parsed.rssi, snr: parsed.snr, loss_permille }, rx_time_ms);
+        if mqtt::enabled() && mqtt_lines::spawn(parsed.sensor_data, parsed.rssi, parsed.snr).is_err() {
+            defmt::warn!("MQTT lines behind, packet #{} not printed", parsed.sensor_data.packet_num);
+        }
+
+        match event {
+            SeqEvent::Gap(missed) => {
+                defmt::warn!("Sequence gap: {} packet(s) lost before #{}, loss {}.{}% over the last {}",
+                    missed, seq, loss_permille / 10, loss_permille % 10, LOSS_WINDOW);
+                cx.shared.gaps.lock(|gaps| gaps.add(seq, missed, rx_time_ms));
+            }
+            SeqEvent::Duplicate => defmt::warn!("Duplicate packet #{}", seq),
+            SeqEvent::Restart => {
+                defmt::info!("N1 sequence restarted at #{}", seq);
+                cx.shared.gaps.lock(|gaps| gaps.clear());
+            }
+            SeqEvent::First | SeqEvent::InOrder => {}
+        }
+
+        // Queue ACK back to Node 1, or its relay (CRC validation passed)
+        cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Ack {
+            to: parsed.from,
+            node: parsed.node,
+            seq_num: parsed.sensor_data.packet_num,
+            slot,
+        }));
+    }
+
+    /// Relay mode: send a frame from another node on upstream, or pass an
+    /// ACK from upstream down to the node whose reading it is
+    fn relay_frame(
+        tx_producer: &mut impl rtic::Mutex<T = Producer<'static, RadioCommand, TX_QUEUE_LEN>>,
+        relay: &mut Relay,
+        frame: &[u8],
+        rx_time_ms: u32,
+    ) {
+        let Ok(Some(rcv)) = split_rcv(frame) else {
+            return;
+        };
+        // Only ACKs come down from upstream for relaying; its broadcasts are for us
+        if rcv.from == relay::upstream() && frame_type(rcv.payload) != Some(MSG_TYPE_ACK) {
+            return;
+        }
+        let command = match frame_type(rcv.payload) {
+            // ACKs are unframed, so this is the only look they get
+            Some(MSG_TYPE_ACK) => {
+                let Some((ack, slot)) = decode_ack(rcv.payload) else {
+                    return;
+                };
+                let Some(to) = relay.ack_target(ack.node, ack.seq_num, rx_time_ms) else {
+                    debug!("ACK N{} #{} from {} not for a relayed reading", ack.node, ack.seq_num, rcv.from);
+                    return;
+                };
+                debug!("Relaying ACK N{} #{} to {}", ack.node, ack.seq_num, to);
+                RadioCommand::Ack { to, node: ack.node, seq_num: ack.seq_num, slot }
+            }
+            _ => {
+                let mut buf = [0u8; RAW_FRAME_LEN];
+                match relay.forward(rcv.payload, rcv.from, config::node_id(), rx_time_ms, &mut buf) {
+                    Ok((to, relayed)) => match RawFrame::new(relayed) {
+                        Some(relayed) => {
+                            debug!("Relaying {} from {} to {} (RSSI {})", relayed, rcv.from, to, rcv.rssi);
+                            RadioCommand::Relay { to, frame: relayed }
+                        }
+                        None => return,
+                    },
+                    Err(skip) => {
+                        debug!("Not relayed: {}", skip);
+                        return;
+                    }
+                }
+            }
+        };
+        let _ = activity_pulse::spawn(Activity::Rx);
+        tx_producer.lock(|producer| queue_radio_command(producer, command));
+    }
+
+    /// Log and count a reading replayed from Node 1's flash, and take it
+    /// off the missing list
+    fn backfill(
+        rx_stats: &mut impl rtic::Mutex<T = RxStats>,
+        gaps: &mut impl rtic::Mutex<T = Gaps>,
+        data: &SensorData,
+        age_s: Option<u32>,
+        rx_time_ms: u32,
+    ) {
+        let count = rx_stats.lock(|stats| metrics::COUNTERS.measure(|| stats.record_backfill()));
+        gaps.lock(|gaps| gaps.fill(data.packet_num));
+        match age_s {
+            Some(age_s) => defmt::info!("N1 BACKFILL #{}, {}s old: T:{} H:{} G:{} (total {})",
+                data.packet_num, age_s, data.temperature, data.humidity, data.gas_resistance, count),
+            None => defmt::info!("N1 BACKFILL #{}, from before N1's last reset: T:{} H:{} G:{} (total {})",
+                data.packet_num, data.temperature, data.humidity, data.gas_resistance, count),
+        }
+        #[cfg(feature = "sd-log")]
+        log_to_sd(data, sdlog::Source::Backfill { age_s }, rx_time_ms);
+        #[cfg(feature = "usb-json")]
+        send_json(data, usbjson::Source::Backfill { age_s }, rx_time_ms);
+        #[cfg(not(any(feature = "sd-log", feature = "usb-json")))]
+        let _ = rx_time_ms;
+    }
+
+    // Missed-range replay: ask Node 1 for readings a gap left out
+    //
+    // Every `REPLAY_CHECK_SECS`; at most one request per run, so a burst of
+    // gaps doesn't flood the duty cycle. See gaps.rs for the timing.
+    #[task(priority = 1, shared = [gaps, tx_producer])]
+    fn request_replay(mut cx: request_replay::Context) {
+        let _busy = GAPS_STATS.enter();
+        let now = now_ms();
+        let (request, missing) = cx.shared.gaps.lock(|gaps| (gaps.due(now), gaps.missing()));
+        if let Some(request) = request {
+            defmt::info!("{} reading(s) missing, asking N1 for #{}..={}", missing, request.from, request.to);
+            cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::Replay(request)));
+        }
+        let _ = request_replay::spawn_after(REPLAY_CHECK_SECS.secs());
+    }
+
+    // Link stats: this end's counters for every node (see linkstats.rs)
+    //
+    // Queued like any downlink, so over the duty cycle it is dropped; the
+    // next carries the same counters further on.
+    #[task(priority = 1, shared = [tx_producer])]
+    fn link_report(mut cx: link_report::Context) {
+        let _busy = LINK_STATS.enter();
+        let stats = linkstats::ours(uptime_s());
+        cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, RadioCommand::LinkStats(stats)));
+        let _ = link_report::spawn_after(LINK_INTERVAL_SECS.secs());
+    }
+
+    /// Hand a reading received at `rx_time_ms` to `sd_log`
+    #[cfg(feature = "sd-log")]
+    fn log_to_sd(data: &SensorData, source: sdlog::Source, rx_time_ms: u32) {
+        let record = sdlog::Record {
+            seq: data.packet_num,
+            source,
+            temperature_c: data.temperature,
+            humidity_pct: data.humidity,
+            gas_ohms: data.gas_resistance,
+            iaq: data.iaq,
+            co2_ppm: data.co2_ppm,
+            battery_mv: data.battery_mv,
+        };
+        if sd_log::spawn(record, rx_time_ms / 1000).is_err() {
+            defmt::warn!("SD log behind, packet #{} not logged", data.packet_num);
+        }
+    }
+
+    // SD card log: one CSV line per reading (see sdlog.rs)
+    //
+    // Lowest priority, since a write waits out the card's busy time (tens
+    // to a few hundred ms); the queue covers a few packets in the meantime.
+    #[cfg(feature = "sd-log")]
+    #[task(priority = 1, capacity = 4, local = [sd])]
+    fn sd_log(cx: sd_log::Context, record: sdlog::Record, uptime_s: u32) {
+        let _busy = SD_STATS.enter();
+        if let Some(logger) = cx.local.sd {
+            logger.append(&record, uptime_s);
+        }
+    }
+
+    /// Hand a reading received at `rx_time_ms` to `usb_json`
+    #[cfg(feature = "usb-json")]
+    fn send_json(data: &SensorData, source: usbjson::Source, rx_time_ms: u32) {
+        let record = usbjson::Record {
+            seq: data.packet_num,
+            source,
+            temperature_c: data.temperature,
+            humidity_pct: data.humidity,
+            gas_ohms: data.gas_resistance,
+            iaq: data.iaq,
+            co2_ppm: data.co2_ppm,
+            battery_mv: data.battery_mv,
+            probes_c: data.probes_c,
+            soil_pct: data.soil_pct,
+            gnss: data.gnss,
+        };
+        if usb_json::spawn(record, rx_time_ms).is_err() {
+            defmt::warn!("USB output behind, packet #{} not sent", data.packet_num);
+        }
+    }
+
+    // USB JSON lines: format a reading and queue it for the host (see usbjson.rs)
+    #[cfg(feature = "usb-json")]
+    #[task(priority = 1, capacity = 4, shared = [usb])]
+    fn usb_json(mut cx: usb_json::Context, record: usbjson::Record, rx_time_ms: u32) {
+        let _busy = USB_STATS.enter();
+        cx.shared.usb.lock(|usb| usb.send(&record, rx_time_ms));
+    }
+
+    // USB OTG FS interrupt: enumeration, control requests, and draining
+    // queued lines as the host reads them
+    //
+    // Above the display and shell but below frame processing; the USB
+    // stack tolerates tens of milliseconds between polls.
+    #[cfg(feature = "usb-json")]
+    #[task(binds = OTG_FS, priority = 2, shared = [usb])]
+    fn usb_poll(mut cx: usb_poll::Context) {
+        let _busy = USB_STATS.enter();
+        cx.shared.usb.lock(|usb| usb.poll());
+    }
+
+    // `stream on`: send a received frame to the host as a `RadioFrame` record
+    //
+    // With `stream tap`, lines that aren't a well-formed `+RCV` go out as they
+    // are in a `RawLine` record.
+    //
+    // Lowest priority, like the shell it shares the port with; a record is
+    // about 23 ms of blocking writes at 115200 baud.
+    #[task(priority = 1, capacity = 2, shared = [console])]
+    fn forward_frame(mut cx: forward_frame::Context, frame: Vec<u8, RX_BUFFER_SIZE>, rx_time_ms: u32) {
+        let _busy = STREAM_STATS.enter();
+        let mut buf = [0u8; STREAM_RECORD_LEN];
+        let encoded = match split_rcv(&frame) {
+            Ok(Some(rcv)) => {
+                let record = RadioFrame { rx_time_ms, rssi: rcv.rssi, snr: rcv.snr, payload: rcv.payload };
+                export::encode(config::node_id(), export::RADIO_FRAME, &record, &mut buf)
+            }
+            _ if export::tapping() => export::encode(config::node_id(), export::RAW_LINE, &RawLine { rx_time_ms, line: &frame }, &mut buf),
+            _ => return,
+        };
+        match encoded {
+            Ok(bytes) => cx.shared.console.lock(|console| {
+                for &b in bytes {
+                    let _ = nb::block!(console.write(b));
+                }
+            }),
+            Err(e) => defmt::warn!("Frame not streamed: {}", e),
+        }
+    }
+
+    // MQTT bridge: print a live reading as topic/payload lines (see mqtt.rs)
+    //
+    // Lowest priority, sharing the port with the shell; a reading is 10-20
+    // ms of blocking writes at 115200 baud.
+    #[task(priority = 1, capacity = 4, shared = [console])]
+    fn mqtt_lines(mut cx: mqtt_lines::Context, data: SensorData, rssi: i16, snr: i16) {
+        let _busy = MQTT_STATS.enter();
+        let reading = mqtt::Reading {
+            seq: data.packet_num,
+            temperature_c: data.temperature,
+            humidity_pct: data.humidity,
+            gas_ohms: data.gas_resistance,
+            iaq: data.iaq,
+            co2_ppm: data.co2_ppm,
+            battery_mv: data.battery_mv,
+            probes_c: &data.probes_c,
+            soil_pct: &data.soil_pct,
+            rssi,
+            snr,
+        };
+        let mut lines: String<MQTT_LINES_LEN> = String::new();
+        if mqtt::write_lines(&mut lines, mqtt::NODE_1, &reading).is_err() {
+            defmt::warn!("MQTT lines for packet #{} cut short", data.packet_num);
+        }
+        cx.shared.console.lock(|console| {
+            for &b in lines.as_bytes() {
+                let _ = nb::block!(console.write(b));
+            }
+        });
+    }
+
+    // Carry out what ADR asked for in process_frame (see adr.rs)
+    //
+    // A power step goes to its node alone; a spreading factor goes to every
+    // node, and Node 2 follows once the broadcast is on its way.
+    #[task(priority = 1, capacity = 2, shared = [tx_producer, command_counter, broadcasts])]
+    fn adr_step(mut cx: adr_step::Context, step: Step) {
+        let _busy = ADR_STATS.enter();
+        defmt::info!("ADR: {}", step);
+        let cmd = match step {
+            Step::TxPower { node, dbm } => {
+                let Some(packet) = sign_next(&mut cx.shared.command_counter, RemoteCommand::SetTxPower(dbm)) else {
+                    defmt::error!("ADR: couldn't sign the power step");
+                    return;
+                };
+                RadioCommand::Remote { to: node.into(), packet }
+            }
+            Step::SpreadingFactor(sf) => {
+                let Some(packet) = sign_next(&mut cx.shared.command_counter, RemoteCommand::SetSpreadingFactor(sf)) else {
+                    defmt::error!("ADR: couldn't sign the spreading factor step");
+                    return;
+                };
+                let id = next_broadcast(&mut cx.shared.broadcasts);
+                let _ = apply_spreading_factor::spawn_after(ADR_FOLLOW_MS.millis(), sf);
+                RadioCommand::Broadcast { id, ack: false, group: BROADCAST_EVERYONE, payload: broadcast::Payload::Remote(packet) }
+            }
+        };
+        cx.shared.tx_producer.lock(|producer| queue_radio_command(producer, cmd));
+    }
+
+    // Listen on a new spreading factor; ADR's, or the boot one again
+    #[task(priority = 1, shared = [lora_tx, airtime])]
+    fn apply_spreading_factor(mut cx: apply_spreading_factor::Context, sf: u8) {
+        let _busy = ADR_STATS.enter();
+        let params = cx.shared.airtime.lock(|airtime| airtime.params()).with_spreading_factor(sf);
+        defmt::warn!("Spreading factor: SF{}", sf);
+
+        let mut cmd: String<32> = String::new();
+        let _ = cmd.push_str("AT+PARAMETER=");
+        let _ = params.write_parameter(&mut cmd);
+        queue_at_command(&mut cx.shared.lora_tx, &cmd);
+        cx.shared.airtime.lock(|airtime| airtime.set_params(params));
+    }
+
+    // Move to the next dwell's channel, and come back at the one after
+    // (see hopping.rs)
+    #[task(priority = 1, shared = [lora_tx])]
+    fn hop_channel(mut cx: hop_channel::Context) {
+        let _busy = HOP_STATS.enter();
+        let now = now_ms();
+        if let Some(khz) = hopping::retune(hopping::channel_khz(now)) {
+            debug!("Hopping to {} kHz", khz);
+            let mut cmd: String<24> = String::new();
+            let _ = core::write!(cmd, "AT+BAND={}000", khz);
+            queue_at_command(&mut cx.shared.lora_tx, &cmd);
         }
+        let _ = hop_channel::spawn_after(hopping::next_dwell_ms(now).millis());
+    }
+
+    // Listen on other settings while scanning, or go back to ours
+    #[task(priority = 1, capacity = 2, shared = [lora_tx, airtime])]
+    fn apply_scan(mut cx: apply_scan::Context, tune: Tune) {
+        let _busy = SCAN_STATS.enter();
+        let params = cx.shared.airtime.lock(|airtime| airtime.params()).with_spreading_factor(tune.sf);
+        defmt::info!("Scan: listening on net {} SF{}", tune.network_id, tune.sf);
+
+        let mut cmd: String<32> = String::new();
+        let _ = core::write!(cmd, "AT+NETWORKID={}", tune.network_id);
+        queue_at_command(&mut cx.shared.lora_tx, &cmd);
+        cmd.clear();
+        let _ = cmd.push_str("AT+PARAMETER=");
+        let _ = params.write_parameter(&mut cmd);
+        queue_at_command(&mut cx.shared.lora_tx, &cmd);
+        cx.shared.airtime.lock(|airtime| airtime.set_params(params));
+    }
+
+    // A ping's answer, or `TIMEOUT_MS` after it was sent with `None`: to
+    // the shell, between whatever else is typed there
+    #[task(priority = 1, capacity = 3, shared = [pinger, console])]
+    fn ping_result(mut cx: ping_result::Context, id: u16, echo: Option<Echo>) {
+        let _busy = PING_STATS.enter();
+        let outcome = match echo {
+            Some(echo) => ping::Outcome::Echo(echo),
+            None => match cx.shared.pinger.lock(|pinger| pinger.expire(id, now_ms())) {
+                Some(lost) => lost,
+                // Answered in time
+                None => return,
+            },
+        };
+        defmt::info!("Ping {}", outcome);
+        cx.shared.console.lock(|console| {
+            let _ = console.write_str("\r");
+            let _ = shell::write_ping(console, outcome);
+            let _ = console.write_str("> ");
+        });
+    }
+
+    /// Sign `cmd` with the next command number; `None`, with the number
+    /// left unused, if it won't encode
+    fn sign_next(counter: &mut impl rtic::Mutex<T = u32>, cmd: RemoteCommand) -> Option<CommandPacket> {
+        counter.lock(|counter| {
+            let packet = command::sign(*counter + 1, cmd)?;
+            *counter += 1;
+            Some(packet)
+        })
+    }
+
+    /// The host's firmware begin `record`, signed with the next command
+    /// number and framed again; `None` if it doesn't decode
+    fn sign_upload(counter: &mut impl rtic::Mutex<T = u32>, record: &[u8]) -> Option<RawFrame> {
+        let (_, body) = decode_frame(record).ok()?;
+        let begin: FirmwareBegin = postcard::from_bytes(body).ok()?;
+        let begin = counter.lock(|counter| {
+            *counter += 1;
+            command::sign_firmware(*counter, begin)
+        });
+        let mut frame_buf = [0u8; RAW_FRAME_LEN];
+        let node = frame_node(record).unwrap_or(NODE_HOST);
+        let frame = encode_frame(node, MSG_TYPE_FW_BEGIN, &begin, &mut frame_buf).ok()?;
+        RawFrame::new(frame)
+    }
+
+    /// The next broadcast id
+    fn next_broadcast(broadcasts: &mut impl rtic::Mutex<T = u16>) -> u16 {
+        broadcasts.lock(|broadcasts| {
+            *broadcasts = broadcasts.wrapping_add(1);
+            *broadcasts
+        })
+    }
+
+    /// Queue outgoing radio work and make sure `radio_tx` runs to drain it
+    fn queue_radio_command(producer: &mut Producer<'static, RadioCommand, TX_QUEUE_LEN>, cmd: RadioCommand) {
+        if producer.enqueue(cmd).is_err() {
+            defmt::warn!("TX queue full, dropping {}", cmd);
+            return;
+        }
+        // Already pending is fine: the running instance drains everything queued
+        let _ = radio_tx::spawn();
+    }
+
+    // Radio transmit task - sole consumer of the outgoing command queue
+    //
+    // Over the duty cycle a command is dropped rather than held: an ACK is
+    // no use once Node 1 has timed out, and a shell downlink can be typed
+    // again.
+    #[task(priority = 4, shared = [lora_tx, airtime], local = [tx_consumer])]
+    fn radio_tx(mut cx: radio_tx::Context) {
+        let _busy = RADIO_TX_STATS.enter();
+        while let Some(cmd) = cx.local.tx_consumer.dequeue() {
+            let now = now_ms();
+            if cx.shared.airtime.lock(|airtime| airtime.admit(now, cmd.max_frame_len())) != Admit::Now {
+                defmt::warn!("Duty cycle: dropped {}", cmd);
+                continue;
+            }
+            let tx = &mut cx.shared.lora_tx;
+            match cmd {
+                RadioCommand::Ack { to, node, seq_num, slot } => send_ack(tx, to, node, seq_num, slot.as_ref(), true),
+                RadioCommand::Calibrate(cmd) => send_calibration(tx, &cmd),
+                RadioCommand::Display(setting) => send_display(tx, &setting),
+                RadioCommand::Replay(request) => send_replay_request(tx, &request),
+                RadioCommand::Remote { to, packet } => send_remote(tx, to, &packet),
+                RadioCommand::Upload(frame) => send_to_node1(tx, frame.as_bytes()),
+                RadioCommand::Relay { to, frame } => send_to(tx, to, frame.as_bytes()),
+                RadioCommand::Broadcast { id, ack, group, payload } => send_broadcast(tx, id, ack, group, &payload),
+                RadioCommand::JoinAccept(accept) => send_join_accept(tx, &accept),
+                RadioCommand::Ping { to, id } => send_ping(tx, to, &Ping { id, sent_ms: now }),
+                RadioCommand::Pong { to, pong } => send_pong(tx, to, &pong),
+                RadioCommand::LinkStats(stats) => send_link_stats(tx, &stats),
+            }
+            let _ = activity_pulse::spawn(Activity::Tx);
+        }
+        watchdog::check_in(Checkpoint::RadioTx);
     }
 
     /// Parse binary LoRa message from RYLR998
     /// Format: +RCV=<Address>,<Length>,<BinaryData>,<RSSI>,<SNR>\r\n
-    /// where <BinaryData> is postcard-serialized SensorDataPacket
-    fn parse_binary_lora_message(buffer: &[u8]) -> Option<ParsedMessage> {
-        // Check prefix: must start with "+RCV="
+    /// where <BinaryData> is a CRC-protected frame (see protocol.rs)
+    ///
+    /// `Ok(None)` for module responses and valid frames that are only logged.
+    fn parse_binary_lora_message(buffer: &[u8], rx_time_ms: u32) -> Result<Option<Received>, RxError> {
+        let Some(Rcv { from, payload: binary_payload, rssi, snr }) = split_rcv(buffer)? else {
+            return Ok(None);
+        };
+
+        // Payload format: [magic][version][node][hops][msg_type][len][data bytes...][CRC high byte][CRC low byte]
+        let (msg_type, body) = match decode_frame(binary_payload) {
+            Ok(frame) => frame,
+            Err(FrameError::Crc { received, calculated }) => {
+                defmt::error!("CRC FAIL! Received: 0x{:04X}, Calculated: 0x{:04X}",
+                    received, calculated);
+                linkstats::note_crc_failure();
+                return Err(RxError::Crc);
+            }
+            Err(e) => {
+                defmt::warn!("Bad frame: {}", e);
+                return Err(RxError::Format);
+            }
+        };
+
+        let node = frame_node(binary_payload).unwrap_or(NODE_1);
+        debug!("CRC OK, type {} from node {} ({} hops)", msg_type, node, frame_hops(binary_payload).unwrap_or(0));
+        // A neighbour's nodes on our network ID (see senders.rs)
+        if !senders::admit(node) {
+            debug!("Frame from node {} dropped by the sender list", node);
+            return Ok(None);
+        }
+        // An answer to one of our downlinks is as good as its ACK
+        if matches!(msg_type, MSG_TYPE_COMMAND_RESULT | MSG_TYPE_CONFIG_ACK | MSG_TYPE_CONFIG_REPORT | MSG_TYPE_BROADCAST_ACK | MSG_TYPE_FW_STATUS | MSG_TYPE_PONG) {
+            linkstats::note_ack();
+        }
+
+        let sensor_packet: SensorDataPacket = match msg_type {
+            MSG_TYPE_SENSOR_DATA => match postcard::from_bytes(body) {
+                Ok(pkt) => pkt,
+                Err(_) => {
+                    defmt::error!("Postcard deserialization failed");
+                    return Err(RxError::Decode);
+                }
+            },
+            MSG_TYPE_HEALTH => {
+                if let Ok(h) = postcard::from_bytes::<HealthPacket>(body) {
+                    defmt::info!("N1 HEALTH: up={}s reset={} cpu={}.{}% max_isr={}us max_lock={}us",
+                        h.uptime_s, ResetCause::from_u8(h.reset_cause), h.cpu_load_permille / 10, h.cpu_load_permille % 10,
+                        h.max_isr_us, h.max_lock_us);
+                    match battery::Mode::from_u8(h.battery_mode) {
+                        Some(battery::Mode::Normal) => {}
+                        Some(mode) => defmt::warn!("N1 HEALTH: low-battery mode {}", mode),
+                        None => defmt::warn!("N1 HEALTH: unknown battery mode {}", h.battery_mode),
+                    }
+                }
+                return Ok(None);
+            }
+            MSG_TYPE_NODE_INFO => {
+                if let Ok(info) = postcard::from_bytes::<NodeInfo>(body) {
+                    defmt::info!("N1 INFO: sensors {=u8:b}", info.sensors);
+                    match ResetCause::from_u8(info.reset_cause) {
+                        // Field resets worth a look stand out from the routine ones
+                        Some(cause @ (ResetCause::IndependentWatchdog | ResetCause::WindowWatchdog | ResetCause::BrownOut)) =>
+                            defmt::warn!("N1 INFO: boot #{} after {}", info.boot_count, cause),
+                        Some(cause) => defmt::info!("N1 INFO: boot #{} after {}", info.boot_count, cause),
+                        None => defmt::warn!("N1 INFO: boot #{}, unknown reset cause {}", info.boot_count, info.reset_cause),
+                    }
+                    for kind in SensorKind::ALL.iter().filter(|k| info.sensors & k.bit() != 0) {
+                        defmt::info!("N1 INFO:   {=str}", kind.name());
+                    }
+                }
+                return Ok(None);
+            }
+            MSG_TYPE_EVENT => {
+                let event = postcard::from_bytes::<EventPacket>(body).map_err(|_| RxError::Decode)?;
+                match event.kind {
+                    EVENT_MOTION => defmt::warn!("N1 MOTION #{} ({} more rate-limited)", event.count, event.suppressed),
+                    other => defmt::warn!("N1 EVENT kind {} #{}", other, event.count),
+                }
+                return Ok(None);
+            }
+            MSG_TYPE_STORED => {
+                let stored = postcard::from_bytes::<StoredReading>(body).map_err(|_| RxError::Decode)?;
+                return Ok(Some(Received::Backfill { node, from, data: sensor_data(&stored.reading), age_s: stored.age_s }));
+            }
+            MSG_TYPE_REPLAY_BATCH => {
+                let batch = postcard::from_bytes::<ReplayBatch>(body).map_err(|_| RxError::Decode)?;
+                return Ok(Some(Received::Batch(batch)));
+            }
+            // Last gasp from Node 1's brown-out interrupt
+            MSG_TYPE_POWER_FAIL => {
+                let packet = postcard::from_bytes::<PowerFailPacket>(body).map_err(|_| RxError::Decode)?;
+                return Ok(Some(Received::PowerFail(packet)));
+            }
+            // Hourly min/max/mean; logged so the trend survives lost live packets
+            MSG_TYPE_SUMMARY => {
+                let s = postcard::from_bytes::<SummaryPacket>(body).map_err(|_| RxError::Decode)?;
+                let [t_min, t_max, t_mean] = s.temperature.map(|t| t as f32 / 10.0);
+                let [h_min, h_max, h_mean] = s.humidity.map(|h| h as f32 / 100.0);
+                defmt::info!("N1 SUMMARY: {} samples over {}s", s.samples, s.period_s);
+                defmt::info!("N1 SUMMARY:   T min {} max {} mean {}", t_min, t_max, t_mean);
+                defmt::info!("N1 SUMMARY:   H min {} max {} mean {}", h_min, h_max, h_mean);
+                defmt::info!("N1 SUMMARY:   G min {} max {} mean {}", s.gas[0], s.gas[1], s.gas[2]);
+                if let Some([c_min, c_max, c_mean]) = s.co2 {
+                    defmt::info!("N1 SUMMARY:   CO2 min {} max {} mean {}", c_min, c_max, c_mean);
+                }
+                return Ok(None);
+            }
+            MSG_TYPE_SENSOR_FAULT => {
+                let packet = postcard::from_bytes::<SensorFaultPacket>(body).map_err(|_| RxError::Decode)?;
+                // A sensor or code from a newer Node 1 is still worth logging
+                return match SensorFault::from_packet(&packet) {
+                    Some(fault) => Ok(Some(Received::Fault(fault))),
+                    None => {
+                        defmt::warn!("N1 SENSOR FAULT: sensor {} code {}", packet.sensor, packet.error);
+                        Ok(None)
+                    }
+                };
+            }
+            MSG_TYPE_BROADCAST_ACK => {
+                let ack = postcard::from_bytes::<BroadcastAck>(body).map_err(|_| RxError::Decode)?;
+                match ack.status {
+                    COMMAND_DONE => defmt::info!("N{} got broadcast #{}", node, ack.id),
+                    _ => defmt::warn!("N{} got broadcast #{}: refused, {}", node, ack.id, ack.status_name()),
+                }
+                return Ok(None);
+            }
+            MSG_TYPE_JOIN_REQUEST => {
+                let request = postcard::from_bytes::<JoinRequest>(body).map_err(|_| RxError::Decode)?;
+                return Ok(Some(Received::Join(request)));
+            }
+            MSG_TYPE_PING => {
+                let ping = postcard::from_bytes::<Ping>(body).map_err(|_| RxError::Decode)?;
+                debug!("Ping #{} from {} at {}dBm", ping.id, from, rssi);
+                return Ok(Some(Received::Ping { from, answer: ping.answer(rssi, snr) }));
+            }
+            MSG_TYPE_PONG => {
+                let pong = postcard::from_bytes::<Pong>(body).map_err(|_| RxError::Decode)?;
+                return Ok(Some(Received::Pong { from, pong, link: ping::Link { rssi, snr } }));
+            }
+            // A sensor node's own view of the link (see linkstats.rs)
+            MSG_TYPE_LINK_STATS => {
+                let stats = postcard::from_bytes::<LinkStats>(body).map_err(|_| RxError::Decode)?;
+                return Ok(Some(Received::LinkStats(linkstats::heard(node, stats, rx_time_ms))));
+            }
+            // Another gateway's answer to a join request
+            MSG_TYPE_JOIN_ACCEPT => {
+                debug!("Join accept from node {} ignored", node);
+                return Ok(None);
+            }
+            // Another gateway's `all ...`; it's for the sensor nodes
+            MSG_TYPE_BROADCAST => {
+                debug!("Broadcast from node {} ignored", node);
+                return Ok(None);
+            }
+            MSG_TYPE_COMMAND_RESULT => {
+                let result = postcard::from_bytes::<CommandResult>(body).map_err(|_| RxError::Decode)?;
+                match result.status {
+                    COMMAND_DONE => defmt::info!("N1 COMMAND #{}: done", result.counter),
+                    _ => defmt::warn!("N1 COMMAND #{}: refused, {}", result.counter, result.status_name()),
+                }
+                return Ok(None);
+            }
+            // Node 1's answer to `n1 set`: what it has in force now
+            MSG_TYPE_CONFIG_ACK => {
+                let ack = postcard::from_bytes::<ConfigAck>(body).map_err(|_| RxError::Decode)?;
+                if ack.status != COMMAND_DONE {
+                    defmt::warn!("N1 CONFIG #{}: refused, {}", ack.counter, ack.reason());
+                }
+                defmt::info!(
+                    "N1 CONFIG #{}: interval {} s, TX power cap {} dBm, delta {} ({}°C {}% gas {}% beat {} s)",
+                    ack.counter,
+                    ack.interval_s,
+                    ack.tx_power_dbm,
+                    if ack.delta_enabled { "on" } else { "off" },
+                    ack.delta_temp_deci_c as f32 / 10.0,
+                    ack.delta_humidity_deci_pct as f32 / 10.0,
+                    ack.delta_gas_percent,
+                    ack.delta_heartbeat_s
+                );
+                return Ok(None);
+            }
+            // A node's answer to `n1 config` or `all config`: everything it
+            // sends by; the host gets the frame itself through `stream`
+            MSG_TYPE_CONFIG_REPORT => {
+                let report = postcard::from_bytes::<ConfigReport>(body).map_err(|_| RxError::Decode)?;
+                if report.status != COMMAND_DONE {
+                    defmt::warn!("N{} CONFIG #{}: refused, {}", node, report.counter, report.status_name());
+                    return Ok(None);
+                }
+                let [major, minor, patch] = report.firmware;
+                defmt::info!(
+                    "N{} CONFIG #{}: v{}.{}.{}, region {}, addr {} net {} {} MHz, SF{} BW{}k CR4/{} preamble {}",
+                    node,
+                    report.counter,
+                    major,
+                    minor,
+                    patch,
+                    report.region_name(),
+                    report.address,
+                    report.network_id,
+                    report.band_mhz,
+                    report.spreading_factor,
+                    report.bandwidth_khz,
+                    4 + report.coding_rate,
+                    report.preamble
+                );
+                defmt::info!(
+                    "N{} CONFIG #{}: {} dBm (cap {}), duty {}/1000, {} hop channels, interval {} s, delta {} ({}°C {}% gas {}% beat {} s)",
+                    node,
+                    report.counter,
+                    report.tx_power_dbm,
+                    report.tx_power_limit_dbm,
+                    report.duty_permille,
+                    report.hop_channels,
+                    report.interval_s,
+                    if report.delta_enabled { "on" } else { "off" },
+                    report.delta_temp_deci_c as f32 / 10.0,
+                    report.delta_humidity_deci_pct as f32 / 10.0,
+                    report.delta_gas_percent,
+                    report.delta_heartbeat_s
+                );
+                return Ok(None);
+            }
+            // Node 1's tunnelled log lines (see tunnel.rs); the host gets
+            // the frame itself through `stream`
+            MSG_TYPE_LOG => {
+                let batch = postcard::from_bytes::<LogBatch>(body).map_err(|_| RxError::Decode)?;
+                if batch.dropped > 0 {
+                    defmt::warn!("N1 LOG: {} lines dropped", batch.dropped);
+                }
+                for line in &batch.lines {
+                    defmt::info!("N1 LOG {}s {}: {}", line.uptime_s, line.level_name(), line.text.as_str());
+                }
+                return Ok(None);
+            }
+            // Where a `wk3 flash-remote` upload stands; the host follows it
+            // through `stream`
+            MSG_TYPE_FW_STATUS => {
+                let status = postcard::from_bytes::<FirmwareStatus>(body).map_err(|_| RxError::Decode)?;
+                if status.state == FW_RECEIVING {
+                    debug!("N1 FIRMWARE: {} of {} bytes", status.next, status.len);
+                } else {
+                    defmt::info!("N1 FIRMWARE: {} bytes {}", status.len, status.state_name());
+                }
+                return Ok(None);
+            }
+            // Node 1 panicked: log what it managed to send before resetting
+            MSG_TYPE_CRASH_REPORT => {
+                if let Ok(report) = postcard::from_bytes::<CrashReport>(body) {
+                    defmt::error!("N1 CRASHED at {}:{}: {}",
+                        report.file, report.line, report.message);
+                }
+                return Ok(None);
+            }
+            other => {
+                defmt::warn!("Unknown message type {}", other);
+                return Err(RxError::UnknownType);
+            }
+        };
+
+        Ok(Some(Received::Reading(ParsedMessage {
+            node,
+            from,
+            sensor_data: sensor_data(&sensor_packet),
+            rssi,
+            snr,
+            rx_time_ms,
+        })))
+    }
+
+    /// The fields of a `+RCV` line
+    struct Rcv<'a> {
+        /// The transmitting module's address
+        from: u16,
+        payload: &'a [u8],
+        rssi: i16,
+        snr: i16,
+    }
+
+    /// Split `+RCV=<Address>,<Length>,<Data>,<RSSI>,<SNR>` into the
+    /// transmitter's address, the binary payload and the link figures;
+    /// `None` for any other module output
+    fn split_rcv(buffer: &[u8]) -> Result<Option<Rcv<'_>>, RxError> {
+        // Anything else (e.g. "+OK" after an AT+SEND) is a module response, not a frame
         if buffer.len() < 10 || &buffer[0..5] != b"+RCV=" {
-            return None;
+            return Ok(None);
         }
 
         // Find first two commas by scanning bytes
@@ -462,13 +2543,17 @@ mod app {
             }
         }
 
-        let comma1 = comma1_pos?;
-        let comma2 = comma2_pos?;
+        let comma1 = comma1_pos.ok_or(RxError::Format)?;
+        let comma2 = comma2_pos.ok_or(RxError::Format)?;
+
+        // Address before the first comma (ASCII)
+        let from_str = core::str::from_utf8(&buffer[5..comma1]).map_err(|_| RxError::Format)?;
+        let from: u16 = from_str.parse().map_err(|_| RxError::Format)?;
 
         // Extract length from between commas (this is ASCII text)
         let len_bytes = &buffer[comma1 + 1..comma2];
-        let len_str = core::str::from_utf8(len_bytes).ok()?;
-        let payload_len: usize = len_str.parse().ok()?;
+        let len_str = core::str::from_utf8(len_bytes).map_err(|_| RxError::Format)?;
+        let payload_len: usize = len_str.parse().map_err(|_| RxError::Format)?;
 
         // Binary payload starts after second comma
         let payload_start = comma2 + 1;
@@ -476,72 +2561,57 @@ mod app {
 
         if payload_end > buffer.len() {
             defmt::warn!("Payload exceeds buffer");
-            return None;
+            return Err(RxError::Format);
         }
 
         let binary_payload = &buffer[payload_start..payload_end];
 
-        // Payload format: [data bytes...][CRC high byte][CRC low byte]
-        // Minimum payload: 3 bytes (1 byte data + 2 bytes CRC)
-        if binary_payload.len() < 3 {
-            defmt::warn!("Payload too short for CRC validation");
-            return None;
-        }
-
-        // Split payload: data is everything except last 2 bytes
-        let data_len = binary_payload.len() - 2;
-        let data_bytes = &binary_payload[0..data_len];
-        let received_crc_high = binary_payload[data_len];
-        let received_crc_low = binary_payload[data_len + 1];
-        let received_crc = ((received_crc_high as u16) << 8) | (received_crc_low as u16);
-
-        // Calculate CRC on data portion
-        let calculated_crc = calculate_crc16(data_bytes);
-
-        // Validate CRC
-        if received_crc != calculated_crc {
-            defmt::error!("CRC FAIL! Received: 0x{:04X}, Calculated: 0x{:04X}",
-                received_crc, calculated_crc);
-            return None;
-        }
-
-        defmt::info!("CRC OK: 0x{:04X}", received_crc);
-
-        // Deserialize with postcard (only the data portion, not the CRC)
-        let sensor_packet: SensorDataPacket = match postcard::from_bytes(data_bytes) {
-            Ok(pkt) => pkt,
-            Err(_) => {
-                defmt::error!("Postcard deserialization failed");
-                return None;
-            }
-        };
-
         // Parse RSSI and SNR after the binary payload (this is ASCII text)
         // Format: ,<rssi>,<snr>\r\n
         let after_payload_bytes = &buffer[payload_end..];
-        let after_payload_str = core::str::from_utf8(after_payload_bytes).ok()?;
+        let after_payload_str = core::str::from_utf8(after_payload_bytes).map_err(|_| RxError::Format)?;
 
         let parts: Vec<&str, 4> = after_payload_str.split(',').collect();
         if parts.len() < 3 {
-            return None;
+            return Err(RxError::Format);
         }
 
-        let rssi: i16 = parts[1].parse().ok()?;
-        let snr: i16 = parts[2].trim().parse().ok()?;
+        let rssi: i16 = parts[1].parse().map_err(|_| RxError::Format)?;
+        let snr: i16 = parts[2].trim().parse().map_err(|_| RxError::Format)?;
 
-        // Convert from binary format to display format
-        let temp_c = sensor_packet.temperature as f32 / 10.0;
-        let humid_pct = sensor_packet.humidity as f32 / 100.0;
+        Ok(Some(Rcv { from, payload: binary_payload, rssi, snr }))
+    }
 
-        Some(ParsedMessage {
-            sensor_data: SensorData {
-                temperature: temp_c,
-                humidity: humid_pct,
-                gas_resistance: sensor_packet.gas_resistance,
-                packet_num: sensor_packet.seq_num,
-            },
-            rssi,
-            snr,
-        })
+    /// Convert from binary format to display format; raw-mode packets carry
+    /// SHT31 ticks instead (see raw.rs)
+    fn sensor_data(packet: &SensorDataPacket) -> SensorData {
+        let flags = tlv::find_u8(&packet.tlv, tlv::FLAGS).unwrap_or(0);
+        let (temp_c, humid_pct) = if flags & raw::flags::RAW_TH != 0 {
+            trace!("Raw T/H ticks: {} {}", packet.temperature as u16, packet.humidity);
+            (raw::temperature_c(packet.temperature as u16), raw::humidity_pct(packet.humidity))
+        } else {
+            (packet.temperature as f32 / 10.0, packet.humidity as f32 / 100.0)
+        };
+
+        let mut probes_c = [None; ds18b20::MAX_PROBES];
+        if let Some(value) = tlv::find(&packet.tlv, tlv::PROBE_TEMPS) {
+            for (slot, t) in probes_c.iter_mut().zip(ds18b20::decode(value)) {
+                *slot = t;
+            }
+        }
+
+        SensorData {
+            temperature: temp_c,
+            humidity: humid_pct,
+            gas_resistance: packet.gas_resistance,
+            heater_step: packet.heater_step,
+            iaq: packet.iaq,
+            co2_ppm: tlv::find_u16(&packet.tlv, tlv::CO2_PPM),
+            battery_mv: tlv::find_u16(&packet.tlv, tlv::BATTERY_MV),
+            probes_c,
+            soil_pct: tlv::find(&packet.tlv, tlv::SOIL_MOISTURE).map_or([None; soil::PROBES], soil::decode),
+            gnss: tlv::find(&packet.tlv, tlv::GNSS_FIX).and_then(Fix::decode),
+            packet_num: packet.seq_num,
+        }
     }
 }
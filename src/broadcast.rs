@@ -0,0 +1,100 @@
+//! One downlink for every sensor node at once (`all ...`)
+//!
+//! The RYLR998 delivers a frame sent to address 0 to every module on the
+//! network. Node 2 uses that for announcements and commands meant for all
+//! its sensor nodes: `all display ...` and `all interval|txpower|reboot`
+//! go out once as a `BroadcastPacket` wrapping the same message `n1 ...`
+//! would send, rather than once per node.
+//!
+//! A broadcast says whether the nodes answer. By default they stay
+//! silent, as a reply from every node at the same instant would just
+//! collide (an ACK storm). `all ack ...` asks each node for a
+//! `BroadcastAck` instead, sent after a random delay within
+//! `ACK_WINDOW_MS` so that most get through; Node 2 logs whoever answered.
+//! A reading asked for with `report` waits for the same delay, ACKed or
+//! not.
+//!
+//! `group <g> ...` is the same broadcast for the members of one group
+//! only (see `group`).
+
+use crate::panel;
+use crate::protocol::{
+    encode_frame, BroadcastPacket, CommandPacket, FrameError, BROADCAST_ACK_SPREAD, BROADCAST_SILENT,
+    MSG_TYPE_BROADCAST, MSG_TYPE_COMMAND, MSG_TYPE_DISPLAY,
+};
+
+/// Spread of the nodes' ACK delays; wide enough for a few ACK airtimes
+/// each at the slowest settings
+pub const ACK_WINDOW_MS: u16 = 4_000;
+
+/// Longest body a broadcast carries (a signed `CommandPacket`)
+const BODY_LEN: usize = 40;
+/// Longest broadcast frame: header, id, policy, group, window, type, body
+/// and CRC
+pub const MAX_FRAME_LEN: usize = 65;
+
+/// What a broadcast tells every node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Payload {
+    Display(panel::Setting),
+    Remote(CommandPacket),
+}
+
+impl Payload {
+    fn msg_type(&self) -> u8 {
+        match self {
+            Payload::Display(_) => MSG_TYPE_DISPLAY,
+            Payload::Remote(_) => MSG_TYPE_COMMAND,
+        }
+    }
+
+    /// The payload of a received broadcast; `None` for a message type
+    /// that can't be broadcast or a body that doesn't decode
+    pub fn decode(packet: &BroadcastPacket) -> Option<Self> {
+        match packet.msg_type {
+            MSG_TYPE_DISPLAY => postcard::from_bytes(packet.body).ok().map(Payload::Display),
+            MSG_TYPE_COMMAND => postcard::from_bytes(packet.body).ok().map(Payload::Remote),
+            _ => None,
+        }
+    }
+}
+
+/// Frame broadcast `id` of `payload` for `group` into `buf`, asking for
+/// ACKs or not
+pub fn encode<'b>(node: u8, id: u16, ack: bool, group: u8, payload: &Payload, buf: &'b mut [u8]) -> Result<&'b [u8], FrameError> {
+    let mut body = [0u8; BODY_LEN];
+    let body = match payload {
+        Payload::Display(setting) => postcard::to_slice(setting, &mut body),
+        Payload::Remote(packet) => postcard::to_slice(packet, &mut body),
+    }
+    .map_err(|_| FrameError::Decode)?;
+    let packet = BroadcastPacket {
+        id,
+        ack: if ack { BROADCAST_ACK_SPREAD } else { BROADCAST_SILENT },
+        group,
+        window_ms: ACK_WINDOW_MS,
+        msg_type: payload.msg_type(),
+        body,
+    };
+    encode_frame(node, MSG_TYPE_BROADCAST, &packet, buf)
+}
+
+/// Whether the nodes answer `packet` with a `BroadcastAck`
+pub fn wants_ack(packet: &BroadcastPacket) -> bool {
+    packet.ack == BROADCAST_ACK_SPREAD
+}
+
+/// How long `node` waits before answering `packet` or sending what it
+/// asked for
+///
+/// There is no RNG on the nodes: the delay mixes the node's address, the
+/// broadcast number and the time since boot, which differ enough between
+/// nodes to spread the answers over the window.
+pub fn delay_ms(packet: &BroadcastPacket, node: u8, now_ms: u32) -> u32 {
+    // xorshift32 over the mix, so neighbouring inputs land far apart
+    let mut x = (u32::from(node) << 16 | u32::from(packet.id)) ^ now_ms.rotate_left(11) ^ 0x9E37_79B9;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    x % u32::from(packet.window_ms.max(1))
+}
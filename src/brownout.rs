@@ -0,0 +1,125 @@
+//! Last-gasp report when Node 1's supply collapses
+//!
+//! From Node 2's side a pulled battery looks exactly like a lost link: the
+//! packets just stop. The programmable voltage detector (PVD) interrupts as
+//! VDD falls through 2.9 V, well above the ~1.8 V where the MCU stops, and
+//! `last_gasp` spends the time the rail's capacitance buys on:
+//!
+//! 1. saving a `Record` to the RTC backup registers, and
+//! 2. a `PowerFailPacket` straight to UART4, with the same raw writes as
+//!    the panic handler.
+//!
+//! Handing the frame to the module takes about 2 ms, but the module then
+//! needs the rail for the whole airtime (~100 ms at SF9, up to 140 mA), so
+//! the packet only makes it out with a supercap or a few thousand µF on
+//! the 3.3 V rail. The record is the fallback: `take_saved` returns it at
+//! the next boot. The backup registers keep it through a reset or a dip,
+//! but through a full outage only with a cell on VBAT (the Nucleo ties VBAT
+//! to VDD).
+//!
+//! The slow discharge of a healthy battery is `battery`'s job; this is for
+//! the supply going away in milliseconds.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use stm32f4xx_hal::pac;
+
+use crate::backup::register as backup_register;
+use crate::config;
+use crate::crash;
+use crate::protocol::{encode_frame, PowerFailPacket, MSG_TYPE_POWER_FAIL};
+
+// RCC / PWR bits (RM0390 sections 5.4 and 6.3)
+const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+const PWR_CR_PVDE: u32 = 1 << 4;
+const PWR_CR_PLS_MASK: u32 = 0b111 << 5;
+/// PVD threshold 2.9 V (falling edge, the highest level)
+const PWR_CR_PLS_2V9: u32 = 0b111 << 5;
+const PWR_CR_DBP: u32 = 1 << 8;
+
+/// EXTI line wired to the PVD output
+const EXTI_PVD: u32 = 1 << 16;
+
+/// Backup registers 0-2 (see `backup`)
+const RECORD_MAGIC: u32 = 0x4741_5350; // "GASP"
+
+/// Sequence number of the last sensor frame sent
+static LAST_SEQ: AtomicU16 = AtomicU16::new(0);
+
+/// What Node 1 was doing when the supply last collapsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Record {
+    pub uptime_s: u32,
+    pub last_seq: u16,
+    /// Collapses seen since the backup domain last lost power
+    pub failures: u16,
+}
+
+/// Arm the PVD interrupt; call once from `init`
+///
+/// Also opens the backup domain for writing, as `power::init_wakeup` does.
+pub fn init() {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+    let exti = unsafe { &*pac::EXTI::ptr() };
+
+    rcc.apb1enr().modify(|r, w| unsafe { w.bits(r.bits() | RCC_APB1ENR_PWREN) });
+    pwr.cr().modify(|r, w| unsafe {
+        w.bits((r.bits() & !PWR_CR_PLS_MASK) | PWR_CR_PLS_2V9 | PWR_CR_PVDE | PWR_CR_DBP)
+    });
+
+    // PVDO rises as VDD falls below the threshold
+    exti.rtsr().modify(|r, w| unsafe { w.bits(r.bits() | EXTI_PVD) });
+    exti.imr().modify(|r, w| unsafe { w.bits(r.bits() | EXTI_PVD) });
+}
+
+/// Note the sequence number of a sensor frame just sent
+pub fn note_sent(seq: u16) {
+    LAST_SEQ.store(seq, Ordering::Relaxed);
+}
+
+/// Save the record and send the power-fail frame; call from the `PVD` handler
+///
+/// Returns normally: if the rail was only dipping, the node carries on and
+/// a later dip reports again.
+pub fn last_gasp(uptime_s: u32) {
+    unsafe { (*pac::EXTI::ptr()).pr().write(|w| w.bits(EXTI_PVD)) };
+
+    let last_seq = LAST_SEQ.load(Ordering::Relaxed);
+    let failures = unsafe {
+        let count = backup_register(2).read_volatile() >> 16;
+        let failures = (count as u16).wrapping_add(1);
+        backup_register(1).write_volatile(uptime_s);
+        backup_register(2).write_volatile((failures as u32) << 16 | last_seq as u32);
+        // Magic last, like the flash records: a half-written one never counts
+        backup_register(0).write_volatile(RECORD_MAGIC);
+        failures
+    };
+
+    defmt::error!("Supply below 2.9 V: uptime {}s, last packet #{}, failure {}", uptime_s, last_seq, failures);
+
+    let packet = PowerFailPacket { uptime_s, last_seq };
+    let mut frame_buf = [0u8; 16];
+    if let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_POWER_FAIL, &packet, &mut frame_buf) {
+        crash::send_raw(frame);
+    }
+}
+
+/// The record left by the last collapse, if any
+///
+/// Call once at boot. The record is cleared, but the failure count it
+/// carries is kept for the next one.
+pub fn take_saved() -> Option<Record> {
+    unsafe {
+        if backup_register(0).read_volatile() != RECORD_MAGIC {
+            return None;
+        }
+        backup_register(0).write_volatile(0);
+        let packed = backup_register(2).read_volatile();
+        Some(Record {
+            uptime_s: backup_register(1).read_volatile(),
+            last_seq: packed as u16,
+            failures: (packed >> 16) as u16,
+        })
+    }
+}
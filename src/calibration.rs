@@ -0,0 +1,127 @@
+//! Per-sensor calibration trims, applied on Node 1 before packing
+//!
+//! Two sensors of the same model rarely agree to better than a few tenths
+//! of a degree, and humidity sensors drift in gain rather than offset. Each
+//! sensor therefore has a `Trim`: an offset added to its temperature and a
+//! slope its humidity is scaled by. Trims are applied to that sensor's own
+//! readings before they are merged into the `Measurement`, so a correction
+//! for the BME680 never touches the SHT31's values.
+//!
+//! The table is saved in the config sector next to `NodeConfig` (see
+//! `config`) and set either from Node 1's shell or by a `Calibrate` downlink
+//! from Node 2 (`cal` in either shell).
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::flash::FlashError;
+use crate::protocol::CalibrationCommand;
+use crate::sensor::{Measurement, SensorKind};
+
+/// Correction for one sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct Trim {
+    /// Added to the temperature, in hundredths of a degree
+    pub temp_offset_centi_c: i16,
+    /// Humidity is multiplied by this / 1000
+    pub humidity_slope_permille: u16,
+}
+
+impl Trim {
+    /// No correction
+    pub const IDENTITY: Trim = Trim { temp_offset_centi_c: 0, humidity_slope_permille: 1000 };
+
+    /// `cal <sensor> <offset> <slope>` arguments: °C and percent (`-0.4`, `102.5`)
+    pub fn parse(offset_c: &str, slope_pct: &str) -> Option<Self> {
+        let offset = offset_c.parse::<f32>().ok().filter(|v| (-10.0..=10.0).contains(v))?;
+        let slope = slope_pct.parse::<f32>().ok().filter(|v| (50.0..=150.0).contains(v))?;
+        // Round half away from zero (`as` truncates)
+        let half = if offset < 0.0 { -0.5 } else { 0.5 };
+        Some(Trim {
+            temp_offset_centi_c: (offset * 100.0 + half) as i16,
+            humidity_slope_permille: (slope * 10.0 + 0.5) as u16,
+        })
+    }
+
+    pub fn temp_offset_c(&self) -> f32 {
+        self.temp_offset_centi_c as f32 / 100.0
+    }
+
+    pub fn humidity_slope_pct(&self) -> f32 {
+        self.humidity_slope_permille as f32 / 10.0
+    }
+
+    /// Correct the readings in `m` (one sensor's, not yet merged)
+    pub fn apply(&self, m: &mut Measurement) {
+        if let Some(t) = m.temperature_c.as_mut() {
+            *t += self.temp_offset_c();
+        }
+        if let Some(h) = m.humidity_pct.as_mut() {
+            *h = (*h * self.humidity_slope_permille as f32 / 1000.0).clamp(0.0, 100.0);
+        }
+    }
+}
+
+impl Default for Trim {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// One `Trim` per `SensorKind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct Calibration {
+    trims: [Trim; SensorKind::ALL.len()],
+}
+
+impl Calibration {
+    pub const IDENTITY: Calibration = Calibration { trims: [Trim::IDENTITY; SensorKind::ALL.len()] };
+
+    pub fn trim(&self, sensor: SensorKind) -> Trim {
+        self.trims[sensor as usize]
+    }
+
+    pub fn set(&mut self, sensor: SensorKind, trim: Trim) {
+        self.trims[sensor as usize] = trim;
+    }
+
+    /// Apply a received downlink; `false` if it names an unknown sensor
+    pub fn apply_command(&mut self, cmd: &CalibrationCommand) -> bool {
+        let Some(sensor) = SensorKind::from_index(cmd.sensor) else { return false };
+        self.set(sensor, Trim {
+            temp_offset_centi_c: cmd.temp_offset_centi_c,
+            humidity_slope_permille: cmd.humidity_slope_permille,
+        });
+        true
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl config::Record for Calibration {
+    const MAGIC: u32 = 0x4341_4C42; // "CALB"
+    const VERSION: u8 = 1;
+}
+
+/// The downlink that sets `trim` for `sensor`
+pub fn command(sensor: SensorKind, trim: Trim) -> CalibrationCommand {
+    CalibrationCommand {
+        sensor: sensor as u8,
+        temp_offset_centi_c: trim.temp_offset_centi_c,
+        humidity_slope_permille: trim.humidity_slope_permille,
+    }
+}
+
+/// The saved trims, if any
+pub fn load() -> Option<Calibration> {
+    config::load_record()
+}
+
+/// Save `calibration` as the newest trims
+pub fn save(calibration: &Calibration) -> Result<(), FlashError> {
+    config::save_record(calibration)
+}
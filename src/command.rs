@@ -0,0 +1,288 @@
+//! Authenticated remote commands: interval, TX power, reboot and config
+//! from Node 2
+//!
+//! `n1 interval <s>`, `n1 txpower <dBm>` and `n1 reboot` in Node 2's shell
+//! (or `wk3 send` on the host) go to Node 1 as a `CommandPacket`. Node 2
+//! numbers them from its boot count, so the counter keeps rising across its
+//! resets; Node 1 remembers the highest counter it accepted in flash sector
+//! 6 and refuses anything at or below it, then checks the tag (see
+//! `protocol::auth`) and answers with a `CommandResult`.
+//!
+//! `n1 set ...` changes several settings in one `SetConfig`: the interval,
+//! the TX power cap and the send-on-delta thresholds. They are checked
+//! together, so one bad value changes none, then saved with the rest of
+//! Node 1's settings, and the answer is a `ConfigAck` with the values in
+//! force afterwards. A refusal there carries a `ConfigError` naming the
+//! rule the value broke: out of range, TX power above the region's limit,
+//! a spreading factor the module can't run at the bandwidth, or an
+//! interval under the floor the duty cycle sets for Node 1's largest frame
+//! (see `airtime::interval_floor_s`).
+//!
+//! `n1 config` (`GetConfig`) changes nothing and is answered with a
+//! `ConfigReport`: firmware version, region, radio settings, TX power,
+//! duty cycle, interval and delta thresholds. Broadcast with `all config`,
+//! every node answers after its own delay, so one command audits a fleet.
+//!
+//! A firmware upload (`wk3 flash-remote`) is authorised the same way: Node
+//! 2 numbers and tags the host's `FirmwareBegin` from the same counter
+//! (`sign_firmware`), and Node 1 checks it like a command before taking any
+//! of the image (`check_firmware`).
+//!
+//! Both nodes must be built with the same key, 32 hex digits in the
+//! `WK3_COMMAND_KEY` environment variable. Without it the firmware falls
+//! back to a development key anyone with this source knows, and says so
+//! at boot.
+
+use crate::airtime::{self, LoraParams};
+use crate::battery;
+use crate::config::{self, LastCommand, NodeConfig, Settings};
+use crate::delta::{self, Setting};
+use crate::hopping;
+use crate::protocol::auth::{self, KEY_LEN};
+use crate::protocol::{
+    CommandPacket, ConfigAck, ConfigChange, ConfigError, ConfigReport, FirmwareBegin, RemoteCommand,
+    COMMAND_BAD_TAG, COMMAND_INVALID, COMMAND_REPLAYED,
+};
+use crate::region;
+
+const DEV_KEY: [u8; KEY_LEN] = *b"wk3-dev-key-0000";
+
+/// The key both nodes were built with
+pub const KEY: [u8; KEY_LEN] = match option_env!("WK3_COMMAND_KEY") {
+    Some(hex) => parse_key(hex),
+    None => DEV_KEY,
+};
+
+/// Built without `WK3_COMMAND_KEY`
+pub const DEV_KEY_IN_USE: bool = option_env!("WK3_COMMAND_KEY").is_none();
+
+/// Shortest and longest interval accepted, as the setup menu offers
+pub const INTERVAL_RANGE_S: core::ops::RangeInclusive<u16> = 5..=3600;
+/// Largest temperature and humidity threshold, in tenths, as `delta` takes
+const DELTA_MAX_DECI: u16 = 1000;
+
+/// This firmware's version, as Cargo.toml gives it
+const FIRMWARE: [u8; 3] = [
+    parse_u8(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+const fn parse_u8(digits: &str) -> u8 {
+    let digits = digits.as_bytes();
+    let mut value = 0u8;
+    let mut i = 0;
+    while i < digits.len() {
+        value = value * 10 + (digits[i] - b'0');
+        i += 1;
+    }
+    value
+}
+
+const fn parse_key(hex: &str) -> [u8; KEY_LEN] {
+    let hex = hex.as_bytes();
+    assert!(hex.len() == 2 * KEY_LEN, "WK3_COMMAND_KEY must be 32 hex digits");
+    let mut key = [0u8; KEY_LEN];
+    let mut i = 0;
+    while i < KEY_LEN {
+        key[i] = nibble(hex[2 * i]) << 4 | nibble(hex[2 * i + 1]);
+        i += 1;
+    }
+    key
+}
+
+const fn nibble(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        b'A'..=b'F' => c - b'A' + 10,
+        _ => panic!("WK3_COMMAND_KEY must be 32 hex digits"),
+    }
+}
+
+/// Node 2: sign `command` as number `counter`; `None` if it won't encode
+pub fn sign(counter: u32, command: RemoteCommand) -> Option<CommandPacket> {
+    let tag = auth::tag(&KEY, counter, &command).ok()?;
+    Some(CommandPacket { counter, command, tag })
+}
+
+/// Node 2: sign the host's `begin` as number `counter`
+pub fn sign_firmware(counter: u32, begin: FirmwareBegin) -> FirmwareBegin {
+    let begin = FirmwareBegin { counter, ..begin };
+    FirmwareBegin { tag: auth::firmware_tag(&KEY, &begin), ..begin }
+}
+
+/// Why Node 1 won't carry out a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Refusal {
+    BadTag,
+    Replayed,
+    /// Authentic, but a value broke a rule
+    Invalid(ConfigError),
+}
+
+impl Refusal {
+    /// The `COMMAND_*` status that says so
+    pub fn status(self) -> u8 {
+        match self {
+            Refusal::BadTag => COMMAND_BAD_TAG,
+            Refusal::Replayed => COMMAND_REPLAYED,
+            Refusal::Invalid(_) => COMMAND_INVALID,
+        }
+    }
+
+    /// The rule a value broke, for a `ConfigAck`
+    pub fn error(self) -> Option<ConfigError> {
+        match self {
+            Refusal::Invalid(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Refusal::BadTag => "bad tag",
+            Refusal::Replayed => "replayed",
+            Refusal::Invalid(e) => e.name(),
+        }
+    }
+}
+
+/// Node 1: whether to carry out `packet`, given the highest counter
+/// accepted so far, the modem settings `params` in use and `floor_s`, the
+/// shortest interval the duty cycle allows
+pub fn check(packet: &CommandPacket, last: u32, params: LoraParams, floor_s: u16) -> Result<(), Refusal> {
+    if !auth::verify(&KEY, packet.counter, &packet.command, &packet.tag) {
+        return Err(Refusal::BadTag);
+    }
+    if packet.counter <= last {
+        return Err(Refusal::Replayed);
+    }
+    let valid = match packet.command {
+        RemoteCommand::SetInterval(s) => check_interval(s, floor_s),
+        RemoteCommand::SetTxPower(dbm) => battery::check_tx_power_limit(dbm),
+        RemoteCommand::Reboot => Ok(()),
+        RemoteCommand::SetSpreadingFactor(sf) => region::check_modulation(&params.with_spreading_factor(sf)),
+        RemoteCommand::ReportNow => Ok(()),
+        RemoteCommand::SetConfig(change) => check_change(&change, floor_s),
+        RemoteCommand::GetConfig => Ok(()),
+    };
+    valid.map_err(Refusal::Invalid)
+}
+
+/// Node 1: whether to start the upload `begin` asks for, given the highest
+/// counter accepted so far
+pub fn check_firmware(begin: &FirmwareBegin, last: u32) -> Result<(), Refusal> {
+    if !auth::verify_firmware(&KEY, begin) {
+        return Err(Refusal::BadTag);
+    }
+    if begin.counter <= last {
+        return Err(Refusal::Replayed);
+    }
+    Ok(())
+}
+
+/// An interval within `INTERVAL_RANGE_S` and no shorter than `floor_s`
+pub fn check_interval(interval_s: u16, floor_s: u16) -> Result<(), ConfigError> {
+    if !INTERVAL_RANGE_S.contains(&interval_s) {
+        Err(ConfigError::OutOfRange)
+    } else if interval_s < floor_s {
+        Err(ConfigError::IntervalBelowDutyFloor)
+    } else {
+        Ok(())
+    }
+}
+
+/// Every value `change` gives is one the shell would take and the region
+/// allows; the first that isn't says why
+fn check_change(change: &ConfigChange, floor_s: u16) -> Result<(), ConfigError> {
+    if let Some(s) = change.interval_s {
+        check_interval(s, floor_s)?;
+    }
+    if let Some(dbm) = change.tx_power_dbm {
+        battery::check_tx_power_limit(dbm)?;
+    }
+    let in_range = change.delta_temp_deci_c.is_none_or(|t| t <= DELTA_MAX_DECI)
+        && change.delta_humidity_deci_pct.is_none_or(|h| h <= DELTA_MAX_DECI)
+        && change.delta_heartbeat_s.is_none_or(|s| s > 0);
+    in_range.then_some(()).ok_or(ConfigError::OutOfRange)
+}
+
+/// Node 1: put a checked `change` in force, all but the interval, which
+/// goes through `apply_config` like the setup menu's
+pub fn apply_change(change: &ConfigChange) {
+    if let Some(on) = change.delta_enabled {
+        delta::set_enabled(on);
+    }
+    let thresholds = [
+        change.delta_temp_deci_c.map(Setting::Temp),
+        change.delta_humidity_deci_pct.map(Setting::Humidity),
+        change.delta_gas_percent.map(Setting::Gas),
+        change.delta_heartbeat_s.map(Setting::Heartbeat),
+    ];
+    for setting in thresholds.into_iter().flatten() {
+        setting.apply();
+    }
+    if let Some(dbm) = change.tx_power_dbm {
+        // Checked with the rest in `check`
+        let _ = battery::set_tx_power_limit(dbm);
+    }
+}
+
+/// Node 1: the answer to `SetConfig` command `counter`, with the settings
+/// in force, `interval_s`, the interval they set or the one kept, and the
+/// `error` that refused them, if one did
+pub fn config_ack(counter: u32, status: u8, interval_s: u16, error: Option<ConfigError>) -> ConfigAck {
+    let settings = Settings::current();
+    ConfigAck {
+        counter,
+        status,
+        interval_s,
+        tx_power_dbm: settings.tx_power_limit,
+        delta_enabled: settings.delta_enabled,
+        delta_temp_deci_c: settings.delta_temp_deci_c,
+        delta_humidity_deci_pct: settings.delta_humidity_deci_pct,
+        delta_gas_percent: settings.delta_gas_percent,
+        delta_heartbeat_s: settings.delta_heartbeat_s,
+        error,
+    }
+}
+
+/// Node 1: the answer to `GetConfig` command `counter`, from `config`,
+/// the modem settings `params` and the power `tx_power_dbm` in use
+pub fn config_report(counter: u32, status: u8, config: &NodeConfig, params: LoraParams, tx_power_dbm: u8) -> ConfigReport {
+    let settings = Settings::current();
+    ConfigReport {
+        counter,
+        status,
+        firmware: FIRMWARE,
+        region: region::code(),
+        address: config.address,
+        network_id: config.network_id,
+        band_mhz: config.band_mhz,
+        spreading_factor: params.spreading_factor,
+        bandwidth_khz: (params.bandwidth_hz / 1_000) as u16,
+        coding_rate: params.coding_rate,
+        preamble: params.preamble,
+        tx_power_dbm,
+        tx_power_limit_dbm: settings.tx_power_limit,
+        duty_permille: airtime::limit_permille(),
+        hop_channels: hopping::channels().len() as u8,
+        interval_s: config.tx_interval_s,
+        delta_enabled: settings.delta_enabled,
+        delta_temp_deci_c: settings.delta_temp_deci_c,
+        delta_humidity_deci_pct: settings.delta_humidity_deci_pct,
+        delta_gas_percent: settings.delta_gas_percent,
+        delta_heartbeat_s: settings.delta_heartbeat_s,
+    }
+}
+
+pub fn last_accepted() -> u32 {
+    config::load_record::<LastCommand>().unwrap_or_default().counter
+}
+
+/// Remember `counter` before acting on its command, so a reset mid-way
+/// can't let it be replayed
+pub fn accept(counter: u32) -> Result<(), crate::flash::FlashError> {
+    config::save_record(&LastCommand { counter })
+}
@@ -0,0 +1,536 @@
+//! Persistent node settings in flash sector 6
+//!
+//! The radio settings and transmit interval used to be constants, so moving
+//! a unit to another network meant reflashing it. This sector is now where
+//! every setting lives: `NodeConfig` from the setup menu, the sensor trims
+//! from `calibration` (shell or downlink), the probe endpoints from `soil`,
+//! and `Settings`, everything else the shell can change. Each is loaded at
+//! boot, falling back to the firmware's defaults, and saved back on change;
+//! the constants are only the defaults. The sector also keeps a `BootCount`,
+//! bumped by `count_boot` early in every boot.
+//!
+//! The sector is an append-only array of 64-byte slots:
+//!   [magic u32][version u8][len u8][CRC-16 u16][postcard record ...]
+//! The magic says which `Record` a slot holds and the newest valid slot of
+//! each kind wins. The CRC covers the version, length and record, so a slot
+//! torn by a reset or a worn cell is skipped in favour of the one before
+//! it. A firmware that changes a record's fields bumps its `VERSION`; a
+//! slot of an older version is then upgraded by the record's `migrate`,
+//! which fills the new fields with their defaults, and saved again at the
+//! new version when it is loaded at boot (`Settings` does this; the other
+//! records start from defaults). A slot of a newer version, after flashing an older
+//! firmware, is skipped.
+//! Saving programs the next free slot and only erases the sector once all
+//! 2048 are used, which spreads the wear over the whole sector; an erase
+//! stalls instruction fetches from the single flash bank for 1-2 s, well
+//! inside the watchdog timeout but long enough that it shouldn't happen on
+//! every save.
+//!
+//! On a Node 1 with an EEPROM on its sensor bus, `NodeConfig`, the trims
+//! and the endpoints live there instead (see `eeprom`); this sector is then
+//! only read for them as a fallback.
+
+use core::fmt::Write as _;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use heapless::{String, Vec};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::adr;
+use crate::airtime;
+use crate::backoff;
+use crate::battery;
+use crate::command::INTERVAL_RANGE_S;
+use crate::delta::{self, Setting};
+use crate::filter::{self, Mode};
+use crate::flash::{self, FlashError, CONFIG_SECTOR};
+use crate::format;
+use crate::group;
+use crate::join::Joined;
+use crate::log::{self, Level};
+use crate::menu::NETWORK_IDS;
+use crate::mqtt;
+use crate::pair;
+use crate::panel;
+use crate::protocol::{calculate_crc16, ConfigError, MAX_ROUTE, NODE_1};
+use crate::raw;
+use crate::region;
+use crate::relay;
+use crate::scan;
+use crate::senders;
+use crate::tunnel;
+use crate::ui;
+
+const SLOT_SIZE: u32 = 64;
+const SLOT_HEADER: u32 = 8;
+const BODY_SIZE: usize = (SLOT_SIZE - SLOT_HEADER) as usize;
+const SLOT_COUNT: u32 = CONFIG_SECTOR.size / SLOT_SIZE;
+const ERASED: u32 = 0xFFFF_FFFF;
+/// Kinds of record a sector erase can carry over, newest of each
+const KINDS: usize = 12;
+/// Kinds a factory reset keeps (see `factory`)
+const KEPT_ON_RESET: [u32; 3] = [BootCount::MAGIC, LastCommand::MAGIC, Joined::MAGIC];
+
+/// A kind of record kept in the sector
+pub trait Record: Serialize + DeserializeOwned {
+    /// Marks this kind's slots
+    const MAGIC: u32;
+    /// Bumped whenever the fields change
+    const VERSION: u8;
+
+    /// The record from a slot of an older `version`; `None`, the default,
+    /// drops it
+    fn migrate(_version: u8, _body: &[u8]) -> Option<Self> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct NodeConfig {
+    /// RYLR998 `AT+ADDRESS`; 0 on a sensor node that has yet to join
+    pub address: u16,
+    /// RYLR998 `AT+NETWORKID` (3-15 or 18)
+    pub network_id: u8,
+    /// Carrier frequency in MHz (`AT+BAND`)
+    pub band_mhz: u16,
+    /// Seconds between automatic sensor transmissions (Node 1)
+    pub tx_interval_s: u16,
+}
+
+impl Record for NodeConfig {
+    const MAGIC: u32 = 0x434F_4E46; // "CONF"
+    const VERSION: u8 = 1;
+}
+
+impl NodeConfig {
+    /// Whether the band is in the region and the network ID and interval
+    /// are ones the module and the menu take
+    pub fn check(&self) -> Result<(), ConfigError> {
+        region::check_khz(u32::from(self.band_mhz) * 1_000)?;
+        if !NETWORK_IDS.contains(&self.network_id) || !INTERVAL_RANGE_S.contains(&self.tx_interval_s) {
+            return Err(ConfigError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Send the radio settings to the module, one AT command per call
+    pub fn write_at_commands(&self, mut send: impl FnMut(&str)) {
+        let mut cmd: String<32> = String::new();
+        let _ = write!(cmd, "AT+ADDRESS={}", self.address);
+        send(&cmd);
+
+        cmd.clear();
+        let _ = write!(cmd, "AT+NETWORKID={}", self.network_id);
+        send(&cmd);
+
+        cmd.clear();
+        let _ = write!(cmd, "AT+BAND={}000000", self.band_mhz);
+        send(&cmd);
+    }
+}
+
+/// This node's sender ID, stamped on every frame it builds
+static NODE_ID: AtomicU8 = AtomicU8::new(NODE_1);
+
+/// Sender ID for outgoing frames: the node's LoRa address
+pub fn node_id() -> u8 {
+    NODE_ID.load(Ordering::Relaxed)
+}
+
+/// Follow a new `NodeConfig::address`; the menu keeps it to 0-16, 0 while
+/// joining (see `join`)
+pub fn set_node_id(address: u16) {
+    NODE_ID.store(address as u8, Ordering::Relaxed);
+}
+
+/// The shell's runtime settings, saved whenever one changes
+///
+/// Each node saves the lot, including settings only the other one uses,
+/// so a field always holds that node's own value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct Settings {
+    pub log_level: u8,
+    pub saver_minutes: u16,
+    pub fahrenheit: bool,
+    pub delta_enabled: bool,
+    pub delta_temp_deci_c: u16,
+    pub delta_humidity_deci_pct: u16,
+    pub delta_gas_percent: u16,
+    pub delta_heartbeat_s: u16,
+    pub filter_mode: u8,
+    pub filter_window: u8,
+    pub raw_values: bool,
+    /// `None` follows the band
+    pub airtime_permille: Option<u16>,
+    pub display_on: bool,
+    pub contrast: u8,
+    pub night: Option<(u16, u16)>,
+    /// Node 2 prints readings for an MQTT bridge
+    pub mqtt_lines: bool,
+    /// Node 1 forwards log lines up to this `Level`, `None` is off
+    pub tunnel_level: Option<u8>,
+    /// Node 2 relays frames instead of acting as the gateway
+    pub relay: bool,
+    /// Address uplinks go to, the gateway or a relay
+    pub upstream: u16,
+    /// Relays Node 1's uplinks are pinned to, 0 past the route's end
+    pub route: [u8; MAX_ROUTE],
+    /// Node 1 jitters its interval and waits out a busy channel
+    pub backoff: bool,
+    /// Node 2 steps the nodes' TX power and spreading factor
+    pub adr: bool,
+    /// Node 2's sender list: what it means, and a bit per node ID
+    pub senders_mode: u8,
+    pub senders: u32,
+    /// Broadcast groups a sensor node is in, a bit each
+    pub groups: u8,
+    /// Two sensor nodes ACK and show each other's readings
+    pub pair: bool,
+    /// Node 2 scans other settings for nodes after a long silence
+    pub scan: bool,
+    /// Node 1's cap on its TX power in dBm, from `n1 txpower`, `n1 set` or
+    /// ADR
+    pub tx_power_limit: u8,
+}
+
+impl Record for Settings {
+    const MAGIC: u32 = 0x5345_5453; // "SETS"
+    const VERSION: u8 = 12;
+
+    /// Fields have only ever been added, so an older slot holds those of
+    /// its version and before, in the order they are declared. Each is read
+    /// below with the version it came in; the rest keep their defaults.
+    /// A new field goes here too, in its place, with the new `VERSION`.
+    fn migrate(version: u8, body: &[u8]) -> Option<Self> {
+        let mut settings = Self::current();
+        let mut old = Fields { version, body };
+        old.take(1, &mut settings.log_level)?;
+        old.take(1, &mut settings.saver_minutes)?;
+        old.take(1, &mut settings.fahrenheit)?;
+        old.take(1, &mut settings.delta_enabled)?;
+        old.take(1, &mut settings.delta_temp_deci_c)?;
+        old.take(1, &mut settings.delta_humidity_deci_pct)?;
+        old.take(1, &mut settings.delta_gas_percent)?;
+        old.take(1, &mut settings.delta_heartbeat_s)?;
+        old.take(1, &mut settings.filter_mode)?;
+        old.take(1, &mut settings.filter_window)?;
+        old.take(1, &mut settings.raw_values)?;
+        old.take(1, &mut settings.airtime_permille)?;
+        old.take(1, &mut settings.display_on)?;
+        old.take(1, &mut settings.contrast)?;
+        old.take(1, &mut settings.night)?;
+        old.take(2, &mut settings.mqtt_lines)?;
+        old.take(3, &mut settings.tunnel_level)?;
+        old.take(4, &mut settings.relay)?;
+        old.take(4, &mut settings.upstream)?;
+        old.take(10, &mut settings.route)?;
+        old.take(5, &mut settings.backoff)?;
+        old.take(6, &mut settings.adr)?;
+        old.take(7, &mut settings.senders_mode)?;
+        old.take(7, &mut settings.senders)?;
+        old.take(8, &mut settings.groups)?;
+        old.take(9, &mut settings.pair)?;
+        old.take(11, &mut settings.scan)?;
+        old.take(12, &mut settings.tx_power_limit)?;
+        old.body.is_empty().then_some(settings)
+    }
+}
+
+/// An older record's body, read a field at a time
+struct Fields<'a> {
+    version: u8,
+    body: &'a [u8],
+}
+
+impl Fields<'_> {
+    /// Read `field` if the record's version has it, i.e. is `since` or
+    /// later; `None` if the body doesn't hold it
+    fn take<T: DeserializeOwned>(&mut self, since: u8, field: &mut T) -> Option<()> {
+        if self.version >= since {
+            let (value, rest) = postcard::take_from_bytes(self.body).ok()?;
+            *field = value;
+            self.body = rest;
+        }
+        Some(())
+    }
+}
+
+/// Boots since the sector was first written, this one included
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct BootCount {
+    pub boots: u32,
+}
+
+impl Record for BootCount {
+    const MAGIC: u32 = 0x424F_4F54; // "BOOT"
+    const VERSION: u8 = 1;
+}
+
+/// Node 1: the highest remote command counter accepted (see `command`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct LastCommand {
+    pub counter: u32,
+}
+
+impl Record for LastCommand {
+    const MAGIC: u32 = 0x434D_4443; // "CMDC"
+    const VERSION: u8 = 1;
+}
+
+impl Settings {
+    /// The settings in force now
+    pub fn current() -> Self {
+        let delta = delta::thresholds();
+        let mut route = [0; MAX_ROUTE];
+        for (slot, address) in route.iter_mut().zip(relay::route()) {
+            *slot = address;
+        }
+        Self {
+            log_level: log::level() as u8,
+            saver_minutes: ui::saver_minutes(),
+            fahrenheit: format::fahrenheit(),
+            delta_enabled: delta.enabled,
+            delta_temp_deci_c: (delta.temp_c * 10.0 + 0.5) as u16,
+            delta_humidity_deci_pct: (delta.humidity_pct * 10.0 + 0.5) as u16,
+            delta_gas_percent: delta.gas_percent,
+            delta_heartbeat_s: delta.heartbeat_s,
+            filter_mode: filter::mode() as u8,
+            filter_window: filter::window() as u8,
+            raw_values: raw::enabled(),
+            airtime_permille: airtime::limit_overridden().then(airtime::limit_permille),
+            display_on: !panel::forced_off(),
+            contrast: panel::contrast(),
+            night: panel::night(),
+            mqtt_lines: mqtt::enabled(),
+            tunnel_level: tunnel::level().map(|l| l as u8),
+            relay: relay::enabled(),
+            upstream: relay::upstream(),
+            route,
+            backoff: backoff::enabled(),
+            adr: adr::enabled(),
+            senders_mode: senders::mode() as u8,
+            senders: senders::listed_mask(),
+            groups: group::membership(),
+            pair: pair::enabled(),
+            scan: scan::enabled(),
+            tx_power_limit: battery::tx_power_limit(),
+        }
+    }
+
+    /// Put these settings in force; call once from `init`
+    pub fn apply(&self) {
+        if let Some(level) = Level::from_index(self.log_level) {
+            log::set_level(level);
+        }
+        ui::set_saver_minutes(self.saver_minutes);
+        format::set_fahrenheit(self.fahrenheit);
+        delta::set_enabled(self.delta_enabled);
+        Setting::Temp(self.delta_temp_deci_c).apply();
+        Setting::Humidity(self.delta_humidity_deci_pct).apply();
+        Setting::Gas(self.delta_gas_percent).apply();
+        Setting::Heartbeat(self.delta_heartbeat_s).apply();
+        if let Some(mode) = Mode::from_index(self.filter_mode) {
+            filter::set(mode, self.filter_window as usize);
+        }
+        raw::set_enabled(self.raw_values);
+        mqtt::set_enabled(self.mqtt_lines);
+        tunnel::set_level(self.tunnel_level.and_then(Level::from_index));
+        relay::set_enabled(self.relay);
+        relay::set_upstream(self.upstream);
+        relay::set_route(&self.route);
+        backoff::set_enabled(self.backoff);
+        adr::set_enabled(self.adr);
+        if let Some(mode) = senders::Mode::from_index(self.senders_mode) {
+            senders::set_mode(mode);
+        }
+        senders::set_listed_mask(self.senders);
+        group::set_membership(self.groups);
+        pair::set_enabled(self.pair);
+        scan::set_enabled(self.scan);
+        // Saved before the build had its region, say: the default stays
+        if let Err(e) = battery::set_tx_power_limit(self.tx_power_limit) {
+            defmt::warn!("Saved TX power cap of {} dBm refused: {}", self.tx_power_limit, e);
+        }
+        if let Err(e) = airtime::set_limit(self.airtime_permille) {
+            defmt::warn!("Saved duty-cycle limit refused: {}", e);
+        }
+        for setting in [
+            panel::Setting::Power(self.display_on),
+            panel::Setting::Contrast(self.contrast),
+            panel::Setting::Night(self.night),
+        ] {
+            setting.apply(0);
+        }
+    }
+}
+
+fn slot_addr(index: u32) -> u32 {
+    CONFIG_SECTOR.base + index * SLOT_SIZE
+}
+
+/// Slots in use, in write order (stops at the first erased slot)
+fn used_slots() -> Range<u32> {
+    let used = (0..SLOT_COUNT)
+        .find(|&i| flash::read_word(slot_addr(i)) == ERASED)
+        .unwrap_or(SLOT_COUNT);
+    0..used
+}
+
+/// The record in slot `index` and the version it was saved at, if it is an
+/// intact one of kind `T` this firmware can read
+fn decode<T: Record>(index: u32) -> Option<(T, u8)> {
+    if flash::read_word(slot_addr(index)) != T::MAGIC {
+        return None;
+    }
+    let (version, body) = read_slot(index)?;
+    if version > T::VERSION {
+        return None;
+    }
+    let record = match version {
+        current if current == T::VERSION => postcard::from_bytes(body).ok(),
+        old => T::migrate(old, body),
+    };
+    record.map(|record| (record, version))
+}
+
+/// The version and record bytes of slot `index`, if its CRC holds
+fn read_slot(index: u32) -> Option<(u8, &'static [u8])> {
+    let addr = slot_addr(index);
+    let [version, len, crc_lo, crc_hi] = flash::read_word(addr + 4).to_le_bytes();
+    if len as usize > BODY_SIZE {
+        return None;
+    }
+    let body = flash::read_bytes(addr + SLOT_HEADER, len as usize);
+    if slot_crc(version, body) != u16::from_le_bytes([crc_lo, crc_hi]) {
+        defmt::warn!("Config slot {} fails its CRC, skipped", index);
+        return None;
+    }
+    Some((version, body))
+}
+
+/// CRC over the version, length and record
+fn slot_crc(version: u8, body: &[u8]) -> u16 {
+    let mut covered = [0u8; 2 + BODY_SIZE];
+    covered[0] = version;
+    covered[1] = body.len() as u8;
+    covered[2..2 + body.len()].copy_from_slice(body);
+    calculate_crc16(&covered[..2 + body.len()])
+}
+
+/// The newest intact record of kind `T`, if any, upgraded if it is older
+pub(crate) fn load_record<T: Record>() -> Option<T> {
+    used_slots().rev().find_map(decode::<T>).map(|(record, _)| record)
+}
+
+/// `load_record`, saving an upgraded record again at the new version so
+/// the next firmware finds it current
+fn load_upgraded<T: Record>() -> Option<T> {
+    let (record, version) = used_slots().rev().find_map(decode::<T>)?;
+    if version != T::VERSION {
+        defmt::info!("Config record {=u32:#x} upgraded from version {} to {}", T::MAGIC, version, T::VERSION);
+        if let Err(e) = save_record(&record) {
+            defmt::warn!("Upgraded config record save failed: {}", e);
+        }
+    }
+    Some(record)
+}
+
+/// Append `record` as the newest of its kind
+///
+/// Erasing a full sector would also drop the other record kinds, so the
+/// newest of each is carried over into the fresh sector first.
+pub(crate) fn save_record<T: Record>(record: &T) -> Result<(), FlashError> {
+    let mut index = used_slots().end;
+    if index == SLOT_COUNT {
+        defmt::warn!("Config sector full, erasing sector {}", CONFIG_SECTOR.number);
+        index = erase_keeping(|magic| magic != T::MAGIC)?;
+    }
+    program_slot(index, record)
+}
+
+/// Erase the sector for a factory reset (see `factory`), keeping only the
+/// boot count, the last command counter and the join table
+pub fn factory_reset() -> Result<(), FlashError> {
+    erase_keeping(|magic| KEPT_ON_RESET.contains(&magic)).map(drop)
+}
+
+/// Erase the sector and program back, unchanged, the newest intact slot of
+/// each kind `keep` takes; the first free slot after them
+///
+/// Slots are copied whole rather than decoded, so every kind of record is
+/// carried over without being listed here, at whatever version it has.
+fn erase_keeping(keep: impl Fn(u32) -> bool) -> Result<u32, FlashError> {
+    let mut magics: Vec<u32, KINDS> = Vec::new();
+    let mut slots: Vec<[u8; SLOT_SIZE as usize], KINDS> = Vec::new();
+    for index in used_slots().rev() {
+        let magic = flash::read_word(slot_addr(index));
+        if magics.contains(&magic) || !keep(magic) {
+            continue;
+        }
+        let Some((_, body)) = read_slot(index) else { continue };
+        let mut slot = [0xFFu8; SLOT_SIZE as usize];
+        let len = SLOT_HEADER as usize + body.len();
+        slot[..len].copy_from_slice(flash::read_bytes(slot_addr(index), len));
+        if magics.push(magic).is_err() || slots.push(slot).is_err() {
+            defmt::error!("Config record {=u32:#x} dropped: more than {} kinds", magic, KINDS);
+        }
+    }
+    flash::erase_sector(&CONFIG_SECTOR)?;
+    for (index, (magic, slot)) in magics.iter().zip(&slots).enumerate() {
+        let addr = slot_addr(index as u32);
+        let len = SLOT_HEADER as usize + slot[5] as usize;
+        // Header and body first, magic last, as in `program_slot`
+        flash::program_bytes(addr + 4, &slot[4..len])?;
+        flash::program_words(addr, &[*magic])?;
+    }
+    Ok(slots.len() as u32)
+}
+
+fn program_slot<T: Record>(index: u32, record: &T) -> Result<(), FlashError> {
+    let addr = slot_addr(index);
+
+    let mut body = [0xFFu8; BODY_SIZE];
+    // Records are at most a few dozen bytes of varints, so a too-large one is a bug
+    let body = postcard::to_slice(record, &mut body).map_err(|_| FlashError::Program)?;
+    let header = [T::VERSION, body.len() as u8];
+    let crc = slot_crc(T::VERSION, body).to_le_bytes();
+
+    // Body first, magic last: a half-written slot never looks valid
+    flash::program_bytes(addr + SLOT_HEADER, body)?;
+    flash::program_words(addr + 4, &[u32::from_le_bytes([header[0], header[1], crc[0], crc[1]])])?;
+    flash::program_words(addr, &[T::MAGIC])
+}
+
+/// The most recently saved configuration, if any
+pub fn load() -> Option<NodeConfig> {
+    load_upgraded()
+}
+
+/// Append `config` as the newest record
+pub fn save(config: &NodeConfig) -> Result<(), FlashError> {
+    save_record(config)
+}
+
+/// The saved shell settings, if any, upgraded from an older firmware's
+pub fn load_settings() -> Option<Settings> {
+    load_upgraded()
+}
+
+/// Append `settings` as the newest shell settings
+pub fn save_settings(settings: &Settings) -> Result<(), FlashError> {
+    save_record(settings)
+}
+
+/// Count this boot and return the new total
+///
+/// One slot per boot, so the sector is erased every couple of thousand.
+/// A failed save is logged, and the count is still right for this boot.
+pub fn count_boot() -> u32 {
+    let count = BootCount { boots: load_record::<BootCount>().unwrap_or_default().boots.wrapping_add(1) };
+    if let Err(e) = save_record(&count) {
+        defmt::error!("Boot count save failed: {}", e);
+    }
+    count.boots
+}
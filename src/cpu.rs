@@ -0,0 +1,136 @@
+//! CPU load and interrupt duration statistics
+//!
+//! Every task body opens an `IsrStats::enter` guard. The guard records the
+//! task's own duration in DWT cycles (count, max, total) and, for the
+//! outermost task only, adds the time to a global busy counter. Nested
+//! preemption is therefore counted once in the load figure but included in
+//! the duration of the task it preempted, which is the latency that task
+//! actually saw.
+//!
+//! Load is busy cycles divided by the wall-clock window from the SysTick
+//! monotonic. Counting busy time rather than idle time keeps the figure
+//! right whether or not CYCCNT keeps running during WFI (it does while a
+//! probe sets DBGMCU sleep debugging, it doesn't otherwise).
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::peripheral::DWT;
+
+use crate::time;
+
+/// Duration statistics for one interrupt or software task
+pub struct IsrStats {
+    pub name: &'static str,
+    count: AtomicU32,
+    max_cycles: AtomicU32,
+    total_cycles: AtomicU32,
+}
+
+/// Point-in-time copy of an `IsrStats`, reset on read
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct IsrSnapshot {
+    pub name: &'static str,
+    pub count: u32,
+    pub max_us: u32,
+    pub avg_us: u32,
+}
+
+/// Nesting depth of measured tasks, and when the outermost one started
+static DEPTH: AtomicU32 = AtomicU32::new(0);
+static OUTER_START: AtomicU32 = AtomicU32::new(0);
+static BUSY_CYCLES: AtomicU32 = AtomicU32::new(0);
+
+/// Open while a measured task runs; records on drop
+pub struct Busy<'a> {
+    stats: &'a IsrStats,
+    start: u32,
+}
+
+impl IsrStats {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            count: AtomicU32::new(0),
+            max_cycles: AtomicU32::new(0),
+            total_cycles: AtomicU32::new(0),
+        }
+    }
+
+    /// Start measuring; bind the guard for the whole task body
+    /// (`let _busy = cpu::UART4.enter();`)
+    #[inline(always)]
+    pub fn enter(&self) -> Busy<'_> {
+        let start = DWT::cycle_count();
+        // A preempting task always exits before we resume, so depth is
+        // back to its previous value by the time we look at it again
+        if DEPTH.fetch_add(1, Ordering::Relaxed) == 0 {
+            OUTER_START.store(start, Ordering::Relaxed);
+        }
+        Busy { stats: self, start }
+    }
+
+    /// Take a snapshot and start a new measurement window
+    pub fn take(&self) -> IsrSnapshot {
+        let cycles_per_us = time::core_hz() / 1_000_000;
+        let count = self.count.swap(0, Ordering::Relaxed);
+        let total = self.total_cycles.swap(0, Ordering::Relaxed);
+        let max = self.max_cycles.swap(0, Ordering::Relaxed);
+        IsrSnapshot {
+            name: self.name,
+            count,
+            max_us: max / cycles_per_us,
+            avg_us: if count > 0 { total / count / cycles_per_us } else { 0 },
+        }
+    }
+}
+
+impl Drop for Busy<'_> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let end = DWT::cycle_count();
+        let held = end.wrapping_sub(self.start);
+        self.stats.count.fetch_add(1, Ordering::Relaxed);
+        self.stats.total_cycles.fetch_add(held, Ordering::Relaxed);
+        self.stats.max_cycles.fetch_max(held, Ordering::Relaxed);
+
+        if DEPTH.fetch_sub(1, Ordering::Relaxed) == 1 {
+            let outer = end.wrapping_sub(OUTER_START.load(Ordering::Relaxed));
+            BUSY_CYCLES.fetch_add(outer, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Tracks the window between `take_load` calls
+pub struct LoadMeter {
+    window_start_ms: u32,
+}
+
+impl LoadMeter {
+    pub const fn new(now_ms: u32) -> Self {
+        Self { window_start_ms: now_ms }
+    }
+
+    /// CPU utilization since the last call, in permille (0-1000)
+    ///
+    /// Call at least every ~50 s: BUSY_CYCLES wraps at 2^32 cycles (51 s
+    /// fully loaded at 84 MHz).
+    pub fn take_load(&mut self, now_ms: u32) -> u16 {
+        let busy = BUSY_CYCLES.swap(0, Ordering::Relaxed) as u64;
+        let window_ms = time::elapsed_ms(now_ms, self.window_start_ms).max(1) as u64;
+        self.window_start_ms = now_ms;
+        let window_cycles = window_ms * (time::core_hz() as u64 / 1_000);
+        (busy * 1_000 / window_cycles).min(1_000) as u16
+    }
+}
+
+/// Log `stats` and return the largest max duration among them (µs)
+pub fn report(load_permille: u16, stats: &[&IsrStats]) -> u32 {
+    defmt::info!("CPU load: {}.{}%", load_permille / 10, load_permille % 10);
+    let mut worst = 0;
+    for s in stats {
+        let snap = s.take();
+        defmt::info!("ISR {}: n={} max={}us avg={}us", snap.name, snap.count, snap.max_us, snap.avg_us);
+        worst = worst.max(snap.max_us);
+    }
+    worst
+}
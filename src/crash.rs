@@ -0,0 +1,479 @@
+//! Panic and HardFault handlers: report on the OLED, in flash, and over the radio
+//!
+//! Replaces panic-probe so a field unit with no debugger attached still
+//! leaves a trail. In order, the handlers:
+//!
+//! 1. log the crash over defmt (as panic-probe did),
+//! 2. append a record to the crash log sector (survives reset),
+//! 3. send a `CrashReport` frame via the RYLR998 if UART4 is running,
+//! 4. draw the location on the SSD1306,
+//!
+//! then halt for the debugger or, standalone, reset after a few seconds,
+//! feeding the IWDG meanwhile so the next boot sees a software reset.
+//! Every step talks to registers directly with bounded waits: the crash may
+//! have happened in the middle of an I2C or UART transfer, and the RTIC
+//! resources that normally own these peripherals can't be locked here.
+//!
+//! A HardFault has no source location, so its record is named `HardFault`
+//! and its message holds the stacked PC, LR and xPSR, the configurable
+//! fault status (CFSR) and the faulting address (BFAR or MMFAR) when it's
+//! valid; that just fits the message's 64 characters.
+//!
+//! The frame sent while crashing often doesn't make it: a fault can leave
+//! the radio mid-command, or the UART not running at all. So the next boot
+//! sends the stored record again once the radio is set up (`StoredCrash::
+//! transmit`), and only then marks it reported. Until the button is
+//! pressed, the node's display also shows that it recovered from a crash
+//! (`notice`, `acknowledge`).
+
+use core::fmt::Write as _;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use cortex_m::peripheral::{DCB, SCB};
+use cortex_m_rt::{exception, ExceptionFrame};
+use embedded_graphics::{
+    mono_font::MonoTextStyleBuilder,
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::Text,
+};
+use stm32f4xx_hal::pac;
+
+use crate::display::{self, Panel};
+use crate::config;
+use crate::flash::{self, CRASH_SECTOR};
+use crate::power;
+use crate::protocol::{encode_frame, CrashReport, MSG_TYPE_CRASH_REPORT};
+use crate::time;
+
+// Field limits keep a slot at 128 bytes and the radio frame < 120 bytes
+const FILE_LEN: usize = 40;
+const MESSAGE_LEN: usize = 64;
+
+/// LoRa address of the peer that should hear our crash report (0 = none)
+static RADIO_PEER: AtomicU8 = AtomicU8::new(0);
+/// The last boot ended in a crash and nobody has pressed the button since
+static NOTICE: AtomicBool = AtomicBool::new(false);
+
+// SCB fault status registers (PM0214 section 4.4)
+const SCB_CFSR: *const u32 = 0xE000_ED28 as *const u32;
+const SCB_MMFAR: *const u32 = 0xE000_ED34 as *const u32;
+const SCB_BFAR: *const u32 = 0xE000_ED38 as *const u32;
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+/// Tell the panic handler where to send crash reports
+pub fn set_radio_peer(address: u8) {
+    RADIO_PEER.store(address, Ordering::Relaxed);
+}
+
+// --- Fixed-size text buffer that truncates instead of failing ---
+
+struct TruncBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> TruncBuf<N> {
+    const fn new() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        // Truncation may split a UTF-8 sequence; drop the partial tail
+        match core::str::from_utf8(&self.buf[..self.len]) {
+            Ok(s) => s,
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&self.buf[..e.valid_up_to()]) },
+        }
+    }
+}
+
+impl<const N: usize> core::fmt::Write for TruncBuf<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let take = s.len().min(N - self.len);
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        Ok(())
+    }
+}
+
+/// Keep the tail of a path ("...c/bin/node2.rs" beats "/home/user/...")
+fn file_tail(file: &str) -> &str {
+    let mut start = file.len().saturating_sub(FILE_LEN);
+    while !file.is_char_boundary(start) {
+        start += 1;
+    }
+    &file[start..]
+}
+
+// --- Crash log in flash ---
+//
+// The sector is an append-only array of 128-byte slots:
+//   [magic u32][state u32][len u32][postcard CrashReport ...]
+// state starts erased (0xFFFFFFFF = unreported) and is programmed to 0 once
+// the record has been reported at boot, so each crash is logged once. The
+// sector is only erased at boot when full - never from the panic handler.
+
+const SLOT_SIZE: u32 = 128;
+const SLOT_HEADER: u32 = 12;
+const SLOT_COUNT: u32 = CRASH_SECTOR.size / SLOT_SIZE;
+const SLOT_MAGIC: u32 = 0x4352_5348; // "CRSH"
+const STATE_UNREPORTED: u32 = 0xFFFF_FFFF;
+const STATE_REPORTED: u32 = 0;
+
+fn slot_addr(index: u32) -> u32 {
+    CRASH_SECTOR.base + index * SLOT_SIZE
+}
+
+/// First erased slot, or `None` when the sector is full
+fn free_slot() -> Option<u32> {
+    (0..SLOT_COUNT).find(|&i| flash::read_word(slot_addr(i)) == 0xFFFF_FFFF)
+}
+
+fn store(report: &CrashReport) {
+    let Some(index) = free_slot() else { return };
+    let addr = slot_addr(index);
+
+    let mut body = [0xFFu8; (SLOT_SIZE - SLOT_HEADER) as usize];
+    let Ok(encoded) = postcard::to_slice(report, &mut body) else { return };
+    let len = encoded.len() as u32;
+
+    // Body first, magic last: a half-written slot never looks valid
+    let _ = flash::program_bytes(addr + SLOT_HEADER, &body[..len as usize]);
+    let _ = flash::program_words(addr + 8, &[len]);
+    let _ = flash::program_words(addr, &[SLOT_MAGIC]);
+}
+
+/// A crash record read back from flash at boot
+pub struct StoredCrash {
+    slot: u32,
+}
+
+impl StoredCrash {
+    /// Decode the record (borrows straight from flash)
+    pub fn report(&self) -> Option<CrashReport<'static>> {
+        let addr = slot_addr(self.slot);
+        let len = flash::read_word(addr + 8) as usize;
+        if len > (SLOT_SIZE - SLOT_HEADER) as usize {
+            return None;
+        }
+        postcard::from_bytes(flash::read_bytes(addr + SLOT_HEADER, len)).ok()
+    }
+
+    /// Mark the record reported so the next boot skips it
+    pub fn mark_reported(self) {
+        let _ = flash::program_words(slot_addr(self.slot) + 4, &[STATE_REPORTED]);
+    }
+
+    /// Send the record to the radio peer with raw UART4 writes
+    ///
+    /// For `init`, once the radio is configured and before the UART4
+    /// interrupt is enabled. Returns the frame length if it went out.
+    pub fn transmit(&self) -> Option<usize> {
+        transmit(&self.report()?)
+    }
+}
+
+/// Whether the "recovered from crash" notice is up
+pub fn notice() -> bool {
+    NOTICE.load(Ordering::Relaxed)
+}
+
+/// Take the notice down; `false` if it wasn't up, so the press does what
+/// it normally would
+pub fn acknowledge() -> bool {
+    NOTICE.swap(false, Ordering::Relaxed)
+}
+
+/// Crash log slots written so far, for `export`
+pub fn logged() -> u32 {
+    free_slot().unwrap_or(SLOT_COUNT)
+}
+
+/// The record in slot `index`, reported or not, for `export`
+pub fn logged_report(index: u32) -> Option<CrashReport<'static>> {
+    let addr = slot_addr(index);
+    (index < SLOT_COUNT && flash::read_word(addr) == SLOT_MAGIC).then(|| StoredCrash { slot: index }.report())?
+}
+
+/// Boot-time crash log maintenance
+///
+/// Returns the oldest unreported crash, if any, and makes sure there is a
+/// free slot for the next panic (erasing the sector when it has filled up).
+pub fn take_unreported() -> Option<StoredCrash> {
+    let pending = (0..SLOT_COUNT)
+        .take_while(|&i| flash::read_word(slot_addr(i)) != 0xFFFF_FFFF)
+        .find(|&i| {
+            flash::read_word(slot_addr(i)) == SLOT_MAGIC
+                && flash::read_word(slot_addr(i) + 4) == STATE_UNREPORTED
+        });
+
+    if pending.is_none() && free_slot().is_none() {
+        defmt::warn!("Crash log full, erasing sector {}", CRASH_SECTOR.number);
+        let _ = flash::erase_sector(&CRASH_SECTOR);
+    }
+    if pending.is_some() {
+        NOTICE.store(true, Ordering::Relaxed);
+    }
+
+    pending.map(|slot| StoredCrash { slot })
+}
+
+// --- Radio: raw UART4 writes ---
+
+const USART_SR_TXE: u32 = 1 << 7;
+const USART_SR_TC: u32 = 1 << 6;
+const USART_CR1_UE: u32 = 1 << 13;
+const USART_CR1_TE: u32 = 1 << 3;
+
+/// Bounded spin on a register condition (~10ms at 84 MHz)
+fn wait_for(mut ready: impl FnMut() -> bool) -> bool {
+    for _ in 0..200_000 {
+        if ready() {
+            return true;
+        }
+    }
+    false
+}
+
+fn uart_write(bytes: &[u8]) -> bool {
+    let uart = unsafe { &*pac::UART4::ptr() };
+    for &b in bytes {
+        if !wait_for(|| uart.sr().read().bits() & USART_SR_TXE != 0) {
+            return false;
+        }
+        uart.dr().write(|w| unsafe { w.bits(b as u32) });
+    }
+    true
+}
+
+/// Send `frame` to the radio peer with raw UART4 writes
+///
+/// For code that can't lock the UART resource: the panic handler and the
+/// brown-out interrupt (see `brownout`). Whatever command the interrupted
+/// code was halfway through is cut off with a line end first. Returns once
+/// the last byte has left the UART, `false` if nothing could be sent.
+pub(crate) fn send_raw(frame: &[u8]) -> bool {
+    let peer = RADIO_PEER.load(Ordering::Relaxed);
+    let uart = unsafe { &*pac::UART4::ptr() };
+    let cr1 = uart.cr1().read().bits();
+    if peer == 0 || cr1 & (USART_CR1_UE | USART_CR1_TE) != (USART_CR1_UE | USART_CR1_TE) {
+        return false;
+    }
+
+    if !uart_write(b"\r\n") {
+        return false;
+    }
+    // Node 1's module may be asleep between packets (see power.rs)
+    if cfg!(feature = "low-power") {
+        if !uart_write(b"AT+MODE=0\r\n") {
+            return false;
+        }
+        time::busy_wait_ms(power::RADIO_WAKE_MS);
+    }
+
+    let mut prefix = TruncBuf::<24>::new();
+    let _ = write!(prefix, "AT+SEND={},{},", peer, frame.len());
+
+    uart_write(prefix.as_str().as_bytes())
+        && uart_write(frame)
+        && uart_write(b"\r\n")
+        && wait_for(|| uart.sr().read().bits() & USART_SR_TC != 0)
+}
+
+/// Send `report` to the radio peer; the frame length if it went out
+fn transmit(report: &CrashReport) -> Option<usize> {
+    let mut frame_buf = [0u8; 128];
+    let frame = encode_frame(config::node_id(), MSG_TYPE_CRASH_REPORT, report, &mut frame_buf).ok()?;
+    send_raw(frame).then_some(frame.len())
+}
+
+// --- Display: raw I2C1 master writes ---
+
+const I2C_CR1_PE: u32 = 1 << 0;
+const I2C_CR1_START: u32 = 1 << 8;
+const I2C_CR1_STOP: u32 = 1 << 9;
+const I2C_CR1_SWRST: u32 = 1 << 15;
+const I2C_SR1_SB: u32 = 1 << 0;
+const I2C_SR1_ADDR: u32 = 1 << 1;
+const I2C_SR1_BTF: u32 = 1 << 2;
+const I2C_SR1_TXE: u32 = 1 << 7;
+const I2C_SR1_AF: u32 = 1 << 10;
+
+/// Blocking I2C1 writer that doesn't depend on the (possibly locked) HAL driver
+struct PanicI2c;
+
+#[derive(Debug)]
+struct PanicI2cError;
+
+impl PanicI2c {
+    /// Software-reset I2C1 to abandon whatever transfer the panic interrupted
+    fn recover() -> Self {
+        let i2c = unsafe { &*pac::I2C1::ptr() };
+        let cr2 = i2c.cr2().read().bits();
+        let ccr = i2c.ccr().read().bits();
+        let trise = i2c.trise().read().bits();
+        i2c.cr1().write(|w| unsafe { w.bits(I2C_CR1_SWRST) });
+        i2c.cr1().write(|w| unsafe { w.bits(0) });
+        i2c.cr2().write(|w| unsafe { w.bits(cr2) });
+        i2c.ccr().write(|w| unsafe { w.bits(ccr) });
+        i2c.trise().write(|w| unsafe { w.bits(trise) });
+        i2c.cr1().write(|w| unsafe { w.bits(I2C_CR1_PE) });
+        PanicI2c
+    }
+}
+
+impl embedded_hal_0_2::blocking::i2c::Write for PanicI2c {
+    type Error = PanicI2cError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        let i2c = unsafe { &*pac::I2C1::ptr() };
+        let sr1 = || i2c.sr1().read().bits();
+
+        i2c.cr1().modify(|r, w| unsafe { w.bits(r.bits() | I2C_CR1_START) });
+        if !wait_for(|| sr1() & I2C_SR1_SB != 0) {
+            return Err(PanicI2cError);
+        }
+
+        i2c.dr().write(|w| unsafe { w.bits((addr as u32) << 1) });
+        if !wait_for(|| sr1() & (I2C_SR1_ADDR | I2C_SR1_AF) != 0) || sr1() & I2C_SR1_AF != 0 {
+            i2c.cr1().modify(|r, w| unsafe { w.bits(r.bits() | I2C_CR1_STOP) });
+            return Err(PanicI2cError);
+        }
+        let _ = i2c.sr2().read(); // SR1 then SR2 read clears ADDR
+
+        for &b in bytes {
+            if !wait_for(|| sr1() & I2C_SR1_TXE != 0) {
+                return Err(PanicI2cError);
+            }
+            i2c.dr().write(|w| unsafe { w.bits(b as u32) });
+        }
+
+        let done = wait_for(|| sr1() & I2C_SR1_BTF != 0);
+        i2c.cr1().modify(|r, w| unsafe { w.bits(r.bits() | I2C_CR1_STOP) });
+        if done { Ok(()) } else { Err(PanicI2cError) }
+    }
+}
+
+fn draw(title: &str, report: &CrashReport) {
+    let Ok(mut display) = display::new(PanicI2c::recover()) else { return };
+
+    let style = MonoTextStyleBuilder::new()
+        .font(display::FONT)
+        .text_color(BinaryColor::On)
+        .build();
+    display.clear_frame();
+
+    let rows = display::ROWS;
+    let columns = display::COLUMNS;
+    Text::new(title, Point::new(0, rows[0]), style).draw(&mut display).ok();
+
+    // File on one line, line number, then the message wrapped over two rows
+    let file = report.file;
+    let file = file.get(file.len().saturating_sub(columns)..).unwrap_or(file);
+    Text::new(file, Point::new(0, rows[1]), style).draw(&mut display).ok();
+
+    let mut line = TruncBuf::<32>::new();
+    let _ = write!(line, "line {}", report.line);
+    Text::new(line.as_str(), Point::new(0, rows[2]), style).draw(&mut display).ok();
+
+    let msg = report.message.as_bytes();
+    for (chunk, &y) in msg.chunks(columns).zip(&rows[3..]) {
+        if let Ok(text) = core::str::from_utf8(chunk) {
+            Text::new(text, Point::new(0, y), style).draw(&mut display).ok();
+        }
+    }
+
+    let _ = Panel::flush(&mut display);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let mut message = TruncBuf::<MESSAGE_LEN>::new();
+    let _ = write!(message, "{}", info.message());
+
+    let (file, line) = info
+        .location()
+        .map(|l| (file_tail(l.file()), l.line()))
+        .unwrap_or(("<unknown>", 0));
+
+    let report = CrashReport { line, file, message: message.as_str() };
+    defmt::error!("PANIC at {}:{}: {}", report.file, report.line, report.message);
+
+    record_and_report("!! PANIC !!", &report);
+    if DCB::is_debugger_attached() {
+        // Same as panic-probe: fault so probe-rs stops and prints a backtrace
+        cortex_m::asm::udf();
+    }
+    restart();
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    let cfsr = SCB_CFSR.read_volatile();
+
+    let mut message = TruncBuf::<MESSAGE_LEN>::new();
+    let _ = write!(message, "PC={:08X} LR={:08X} PSR={:08X} CFSR={:08X}",
+        frame.pc(), frame.lr(), frame.xpsr(), cfsr);
+    if cfsr & CFSR_BFARVALID != 0 {
+        let _ = write!(message, " BFAR={:08X}", SCB_BFAR.read_volatile());
+    } else if cfsr & CFSR_MMARVALID != 0 {
+        let _ = write!(message, " MMFAR={:08X}", SCB_MMFAR.read_volatile());
+    }
+
+    let report = CrashReport { line: 0, file: "HardFault", message: message.as_str() };
+    defmt::error!("HARD FAULT: {}", report.message);
+
+    record_and_report("!! HARD FAULT !!", &report);
+    if DCB::is_debugger_attached() {
+        cortex_m::asm::bkpt();
+    }
+    restart();
+}
+
+/// Steps 2-4 of the crash sequence, shared by both handlers
+fn record_and_report(title: &str, report: &CrashReport) {
+    feed_watchdog();
+    store(report);
+    feed_watchdog();
+    if transmit(report).is_some() {
+        // Give the module time to key up before we possibly reset
+        hold_ms(500);
+    }
+    draw(title, report);
+}
+
+/// Standalone: leave the message up long enough to read, then reboot
+fn restart() -> ! {
+    hold_ms(5_000);
+    SCB::sys_reset();
+}
+
+// --- Watchdog ---
+
+const IWDG_KR_RELOAD: u32 = 0xAAAA;
+
+/// Reload the IWDG with a raw key write
+///
+/// The supervisor that normally feeds it can't run from here, and the
+/// waits above add up to more than `WATCHDOG_TIMEOUT_MS`. Left alone, the
+/// IWDG would reset the node before `sys_reset` and the next boot would
+/// report a watchdog reset instead of a software one. Harmless when the
+/// IWDG hasn't been started.
+fn feed_watchdog() {
+    unsafe { (*pac::IWDG::ptr()).kr().write(|w| w.bits(IWDG_KR_RELOAD)) };
+}
+
+/// `time::busy_wait_ms`, feeding the IWDG every 100 ms
+fn hold_ms(ms: u32) {
+    let mut left = ms;
+    while left > 0 {
+        feed_watchdog();
+        let step = left.min(100);
+        time::busy_wait_ms(step);
+        left -= step;
+    }
+}
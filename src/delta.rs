@@ -0,0 +1,176 @@
+//! Send-on-delta: only transmit readings that moved
+//!
+//! Node 1 still samples every TX interval, but a sample is only sent when
+//! it differs from the last one sent by more than a threshold, or when
+//! nothing has gone out for the heartbeat period (so the gateway can tell
+//! a quiet room from a dead node). In a steady room that cuts airtime by an
+//! order of magnitude while a door opening still shows up within one
+//! interval.
+//!
+//! Gas resistance depends on the heater step (see `gas`), so it is compared
+//! against the last reading sent at the same step, as a relative change.
+//!
+//! The thresholds are runtime settings, changed from the shell (`delta`)
+//! like the log level; `delta off` sends every sample as before.
+
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use crate::gas::HEATER_PROFILE;
+use crate::sensor::Measurement;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static TEMP_DECI_C: AtomicU16 = AtomicU16::new(5);
+static HUMIDITY_DECI_PCT: AtomicU16 = AtomicU16::new(20);
+static GAS_PERCENT: AtomicU16 = AtomicU16::new(10);
+static HEARTBEAT_S: AtomicU16 = AtomicU16::new(300);
+
+/// The thresholds, as set from the shell
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Thresholds {
+    pub enabled: bool,
+    pub temp_c: f32,
+    pub humidity_pct: f32,
+    /// Relative change in gas resistance
+    pub gas_percent: u16,
+    /// Longest silence before a sample is sent regardless
+    pub heartbeat_s: u16,
+}
+
+pub fn thresholds() -> Thresholds {
+    Thresholds {
+        enabled: ENABLED.load(Ordering::Relaxed),
+        temp_c: TEMP_DECI_C.load(Ordering::Relaxed) as f32 / 10.0,
+        humidity_pct: HUMIDITY_DECI_PCT.load(Ordering::Relaxed) as f32 / 10.0,
+        gas_percent: GAS_PERCENT.load(Ordering::Relaxed),
+        heartbeat_s: HEARTBEAT_S.load(Ordering::Relaxed),
+    }
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// One threshold, named as in the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Setting {
+    /// Tenths of a degree
+    Temp(u16),
+    /// Tenths of a percent RH
+    Humidity(u16),
+    Gas(u16),
+    Heartbeat(u16),
+}
+
+impl Setting {
+    /// `temp 0.5`, `hum 2`, `gas 10`, `beat 300`
+    pub fn parse(name: &str, value: &str) -> Option<Self> {
+        let tenths = || value.parse::<f32>().ok().filter(|v| (0.0..=100.0).contains(v)).map(|v| (v * 10.0 + 0.5) as u16);
+        match name {
+            "temp" => tenths().map(Setting::Temp),
+            "hum" => tenths().map(Setting::Humidity),
+            "gas" => value.parse().ok().map(Setting::Gas),
+            "beat" => value.parse().ok().filter(|&s| s > 0).map(Setting::Heartbeat),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self) {
+        match self {
+            Setting::Temp(v) => TEMP_DECI_C.store(v, Ordering::Relaxed),
+            Setting::Humidity(v) => HUMIDITY_DECI_PCT.store(v, Ordering::Relaxed),
+            Setting::Gas(v) => GAS_PERCENT.store(v, Ordering::Relaxed),
+            Setting::Heartbeat(v) => HEARTBEAT_S.store(v, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Why a sample is being sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Reason {
+    /// Send-on-delta is off: every sample goes
+    Interval,
+    /// Nothing sent yet since boot
+    First,
+    Temperature,
+    Humidity,
+    Gas,
+    Heartbeat,
+}
+
+impl Reason {
+    /// Short form for the Node 1 status screen
+    pub fn label(self) -> &'static str {
+        match self {
+            Reason::Interval => "AUTO",
+            Reason::First => "FIRST",
+            Reason::Temperature => "TEMP",
+            Reason::Humidity => "HUM",
+            Reason::Gas => "GAS",
+            Reason::Heartbeat => "BEAT",
+        }
+    }
+}
+
+/// What was last sent, to compare new samples against
+pub struct Gate {
+    last: Option<(f32, f32)>,
+    last_gas: [Option<u32>; HEATER_PROFILE.len()],
+    last_sent_ms: u32,
+}
+
+impl Gate {
+    pub const fn new() -> Self {
+        Self { last: None, last_gas: [None; HEATER_PROFILE.len()], last_sent_ms: 0 }
+    }
+
+    /// Whether `m` should be sent, and why; `None` means skip it
+    pub fn check(&self, m: &Measurement, now_ms: u32) -> Option<Reason> {
+        let t = thresholds();
+        if !t.enabled {
+            return Some(Reason::Interval);
+        }
+        let Some((temp, humidity)) = self.last else { return Some(Reason::First) };
+
+        if m.temperature_c.is_some_and(|v| (v - temp).abs() >= t.temp_c) {
+            return Some(Reason::Temperature);
+        }
+        if m.humidity_pct.is_some_and(|v| (v - humidity).abs() >= t.humidity_pct) {
+            return Some(Reason::Humidity);
+        }
+        if let (Some(gas), Some(step)) = (m.gas_ohms, m.heater_step) {
+            match self.last_gas.get(step as usize).copied().flatten() {
+                Some(last) if last > 0 => {
+                    let change = (gas as f32 - last as f32).abs() / last as f32 * 100.0;
+                    if change >= t.gas_percent as f32 {
+                        return Some(Reason::Gas);
+                    }
+                }
+                // First reading at this step: nothing to compare, don't force a send
+                _ => {}
+            }
+        }
+        if now_ms.wrapping_sub(self.last_sent_ms) >= t.heartbeat_s as u32 * 1000 {
+            return Some(Reason::Heartbeat);
+        }
+        None
+    }
+
+    /// Remember `m` as the last sample sent
+    pub fn sent(&mut self, m: &Measurement, now_ms: u32) {
+        if let (Some(temp), Some(humidity)) = (m.temperature_c, m.humidity_pct) {
+            self.last = Some((temp, humidity));
+        }
+        if let (Some(gas), Some(step)) = (m.gas_ohms, m.heater_step) {
+            if let Some(slot) = self.last_gas.get_mut(step as usize) {
+                *slot = Some(gas);
+            }
+        }
+        self.last_sent_ms = now_ms;
+    }
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,161 @@
+//! Redraw only the text rows that changed
+//!
+//! A full clear-and-flush pushes the whole 1K frame over 100 kHz I2C twice
+//! a second, even when nothing but the "last 12s" counter moved. Instead the
+//! refresh task first renders the page into a `Fingerprint`, which draws
+//! nothing and just hashes the pixels falling in each of the five row bands.
+//! Bands whose hash matches the previous frame are left alone; the rest are
+//! cleared and redrawn through a `Clip`, so the SSD1306 driver only flushes
+//! that part of the frame (the SH1106 driver always sends the whole buffer,
+//! and the TFT has none). If nothing changed, there is no flush at all.
+
+use core::convert::Infallible;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*, primitives::Rectangle};
+
+use crate::display::{self, Accent, Canvas, FONT, ROWS};
+
+const BANDS: usize = ROWS.len();
+
+/// First pixel row of band `i`: just above the glyphs of text row `i`
+fn band_top(i: usize) -> i32 {
+    if i == 0 {
+        0
+    } else {
+        ROWS[i] - FONT.baseline as i32 - 1
+    }
+}
+
+fn band_of(y: i32) -> usize {
+    (1..BANDS).rev().find(|&i| y >= band_top(i)).unwrap_or(0)
+}
+
+/// Hashes what a page would draw, per row band, without drawing it
+pub struct Fingerprint {
+    hashes: [u32; BANDS],
+    accent: u32,
+}
+
+impl Fingerprint {
+    const fn new() -> Self {
+        // FNV-1a offset basis
+        Self { hashes: [0x811C_9DC5; BANDS], accent: 0 }
+    }
+}
+
+impl OriginDimensions for Fingerprint {
+    fn size(&self) -> Size {
+        Size::new(display::WIDTH, display::HEIGHT)
+    }
+}
+
+impl DrawTarget for Fingerprint {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounds = self.bounding_box();
+        for Pixel(p, color) in pixels {
+            if !bounds.contains(p) {
+                continue;
+            }
+            let word = (p.x as u32) << 16 | (p.y as u32) << 1 | color.is_on() as u32;
+            let hash = &mut self.hashes[band_of(p.y)];
+            *hash = (*hash ^ word ^ self.accent).wrapping_mul(0x0100_0193);
+        }
+        Ok(())
+    }
+}
+
+impl Canvas for Fingerprint {
+    // A colour change is a change on the TFT even if the pixels aren't
+    fn set_accent(&mut self, accent: Accent) {
+        self.accent = (accent as u32) << 24;
+    }
+}
+
+/// The bands drawn last time, to compare the next frame against
+pub struct DirtyRows {
+    previous: Option<[u32; BANDS]>,
+}
+
+impl DirtyRows {
+    pub const fn new() -> Self {
+        Self { previous: None }
+    }
+
+    /// Render with `draw` and return the area spanning the changed bands
+    ///
+    /// `None` means the panel already shows exactly this frame.
+    pub fn changed(&mut self, draw: impl FnOnce(&mut Fingerprint)) -> Option<Rectangle> {
+        let mut fingerprint = Fingerprint::new();
+        draw(&mut fingerprint);
+        let hashes = fingerprint.hashes;
+
+        let differs = |i: usize| self.previous.map_or(true, |prev| prev[i] != hashes[i]);
+        let first = (0..BANDS).find(|&i| differs(i));
+        let last = (0..BANDS).rev().find(|&i| differs(i));
+        self.previous = Some(hashes);
+
+        let (first, last) = (first?, last?);
+        let top = band_top(first);
+        let bottom = if last + 1 < BANDS { band_top(last + 1) } else { display::HEIGHT as i32 };
+        Some(Rectangle::new(Point::new(0, top), Size::new(display::WIDTH, (bottom - top) as u32)))
+    }
+}
+
+impl Default for DirtyRows {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drawing restricted to `area` of the wrapped canvas
+///
+/// Like embedded-graphics' `Clipped`, but it passes `set_accent` through.
+pub struct Clip<'a, D> {
+    target: &'a mut D,
+    area: Rectangle,
+}
+
+impl<'a, D: Canvas> Clip<'a, D> {
+    pub fn new(target: &'a mut D, area: Rectangle) -> Self {
+        Self { target, area }
+    }
+}
+
+impl<D: Canvas> Dimensions for Clip<'_, D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D: Canvas> DrawTarget for Clip<'_, D> {
+    type Color = BinaryColor;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let area = self.area;
+        self.target.draw_iter(pixels.into_iter().filter(|Pixel(p, _)| area.contains(*p)))
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.area);
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+        self.target.fill_solid(&area, color)
+    }
+}
+
+impl<D: Canvas> Canvas for Clip<'_, D> {
+    fn set_accent(&mut self, accent: Accent) {
+        self.target.set_accent(accent);
+    }
+}
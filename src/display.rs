@@ -0,0 +1,207 @@
+//! OLED controller abstraction
+//!
+//! Cheap 1.3" modules use the SH1106, whose 132-column RAM makes the
+//! ssd1306 driver render everything shifted by two pixels. Both controllers
+//! sit behind `Panel`, and `Oled` names whichever one the build selected:
+//!
+//! - default: SSD1306 (0.96" modules)
+//! - `--features sh1106`: SH1106
+//!
+//! Everything that draws (the UI pages, node 1's status screen, the panic
+//! handler) goes through `new` and `Panel`, so switching controllers is a
+//! build flag rather than a code edit.
+//!
+//! Geometry is a build flag too. The default is 128x64; `--features
+//! oled-128x32` selects the half-height modules and swaps FONT_6X10 for
+//! FONT_4X6, so the same five rows (and a few more columns) still fit.
+//! Drawing code positions text with `ROWS` and `FONT` rather than literal
+//! pixel offsets.
+//!
+//! `--features st7789` moves Node 2 to a 240x240 colour TFT (see `tft`).
+//! The geometry switches to FONT_10X20 on that panel; the OLED driver stays
+//! available for Node 1 and the panic screen.
+
+use embedded_graphics::{
+    mono_font::{iso_8859_1 as fonts, MonoFont},
+    pixelcolor::BinaryColor,
+    prelude::*,
+};
+use embedded_hal_0_2::blocking::i2c::Write;
+
+#[cfg(not(feature = "sh1106"))]
+use display_interface_i2c::I2CInterface;
+#[cfg(not(feature = "sh1106"))]
+use ssd1306::{mode::BufferedGraphicsMode, prelude::*, Ssd1306};
+
+/// 7-bit I2C address shared by both controllers
+pub const I2C_ADDRESS: u8 = 0x3C;
+
+pub use geometry::{FONT, HEIGHT, ROWS, WIDTH};
+
+#[cfg(not(any(feature = "oled-128x32", feature = "st7789")))]
+mod geometry {
+    use super::*;
+
+    pub const WIDTH: u32 = 128;
+    pub const HEIGHT: u32 = 64;
+    /// Text font sized so five rows fill the panel
+    pub const FONT: &MonoFont<'static> = &fonts::FONT_6X10;
+    /// Baselines of the five text rows
+    pub const ROWS: [i32; 5] = [8, 20, 32, 44, 56];
+}
+
+#[cfg(all(feature = "oled-128x32", not(feature = "st7789")))]
+mod geometry {
+    use super::*;
+
+    pub const WIDTH: u32 = 128;
+    pub const HEIGHT: u32 = 32;
+    pub const FONT: &MonoFont<'static> = &fonts::FONT_4X6;
+    pub const ROWS: [i32; 5] = [5, 11, 17, 23, 29];
+}
+
+#[cfg(feature = "st7789")]
+mod geometry {
+    use super::*;
+
+    pub const WIDTH: u32 = 240;
+    pub const HEIGHT: u32 = 240;
+    pub const FONT: &MonoFont<'static> = &fonts::FONT_10X20;
+    pub const ROWS: [i32; 5] = [18, 66, 114, 162, 210];
+}
+
+/// Characters of `FONT` per row
+pub const COLUMNS: usize = (WIDTH / FONT.character_size.width) as usize;
+
+/// The controller failed to respond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PanelError;
+
+/// Meaning of what is drawn next, for panels that can show colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Accent {
+    Normal,
+    Good,
+    Fair,
+    Poor,
+}
+
+/// Anything the UI pages can be drawn into: a panel, a clipped part of
+/// one, or the fingerprinting target in `dirty`
+pub trait Canvas: DrawTarget<Color = BinaryColor> {
+    /// Colour for `BinaryColor::On` until the next call; ignored by the OLEDs
+    fn set_accent(&mut self, _accent: Accent) {}
+}
+
+/// A buffered monochrome display
+///
+/// Method names avoid the drivers' inherent ones (the SH1106 driver has a
+/// no-argument `clear` that would shadow `DrawTarget::clear`).
+pub trait Panel: Canvas {
+    /// Blank the frame buffer (not the panel)
+    fn clear_frame(&mut self);
+
+    /// Send the frame buffer to the controller
+    fn flush(&mut self) -> Result<(), PanelError>;
+
+    /// Minimum contrast (burn-in protection) or normal contrast
+    fn set_dimmed(&mut self, dimmed: bool) -> Result<(), PanelError>;
+
+    /// Contrast 0-255, replacing normal contrast until dimmed again
+    fn set_contrast_level(&mut self, level: u8) -> Result<(), PanelError>;
+
+    /// Switch the panel off without losing its RAM
+    fn set_on(&mut self, on: bool) -> Result<(), PanelError>;
+}
+
+#[cfg(all(not(feature = "sh1106"), not(feature = "oled-128x32")))]
+type Geometry = DisplaySize128x64;
+#[cfg(all(not(feature = "sh1106"), feature = "oled-128x32"))]
+type Geometry = DisplaySize128x32;
+
+#[cfg(not(feature = "sh1106"))]
+pub type Oled<I> = Ssd1306<I2CInterface<I>, Geometry, BufferedGraphicsMode<Geometry>>;
+
+#[cfg(feature = "sh1106")]
+pub type Oled<I> = sh1106::mode::GraphicsMode<sh1106::interface::I2cInterface<I>>;
+
+/// Create and initialise the configured controller on `i2c`
+#[cfg(not(feature = "sh1106"))]
+pub fn new<I: Write>(i2c: I) -> Result<Oled<I>, PanelError> {
+    let interface = I2CInterface::new(i2c, I2C_ADDRESS, 0x40);
+    let mut display = Ssd1306::new(interface, Geometry {}, DisplayRotation::Rotate0)
+        .into_buffered_graphics_mode();
+    display.init().map_err(|_| PanelError)?;
+    Ok(display)
+}
+
+#[cfg(not(feature = "sh1106"))]
+impl<I: Write> Canvas for Oled<I> {}
+
+#[cfg(not(feature = "sh1106"))]
+impl<I: Write> Panel for Oled<I> {
+    fn clear_frame(&mut self) {
+        let _ = DrawTarget::clear(self, BinaryColor::Off);
+    }
+
+    fn flush(&mut self) -> Result<(), PanelError> {
+        Ssd1306::flush(self).map_err(|_| PanelError)
+    }
+
+    fn set_dimmed(&mut self, dimmed: bool) -> Result<(), PanelError> {
+        let brightness = if dimmed { Brightness::DIMMEST } else { Brightness::NORMAL };
+        self.set_brightness(brightness).map_err(|_| PanelError)
+    }
+
+    fn set_contrast_level(&mut self, level: u8) -> Result<(), PanelError> {
+        // Same pre-charge as every preset above DIMMEST
+        self.set_brightness(Brightness::custom(0x2, level)).map_err(|_| PanelError)
+    }
+
+    fn set_on(&mut self, on: bool) -> Result<(), PanelError> {
+        self.set_display_on(on).map_err(|_| PanelError)
+    }
+}
+
+/// Create and initialise the configured controller on `i2c`
+#[cfg(feature = "sh1106")]
+pub fn new<I: Write>(i2c: I) -> Result<Oled<I>, PanelError> {
+    #[cfg(not(feature = "oled-128x32"))]
+    let size = sh1106::displaysize::DisplaySize::Display128x64;
+    #[cfg(feature = "oled-128x32")]
+    let size = sh1106::displaysize::DisplaySize::Display128x32;
+
+    let mut display: Oled<I> = sh1106::Builder::new()
+        .with_size(size)
+        .with_i2c_addr(I2C_ADDRESS)
+        .connect_i2c(i2c)
+        .into();
+    display.init().map_err(|_| PanelError)?;
+    Ok(display)
+}
+
+#[cfg(feature = "sh1106")]
+impl<I: Write> Canvas for Oled<I> {}
+
+#[cfg(feature = "sh1106")]
+impl<I: Write> Panel for Oled<I> {
+    fn clear_frame(&mut self) {
+        sh1106::mode::GraphicsMode::clear(self);
+    }
+
+    fn flush(&mut self) -> Result<(), PanelError> {
+        sh1106::mode::GraphicsMode::flush(self).map_err(|_| PanelError)
+    }
+
+    fn set_dimmed(&mut self, dimmed: bool) -> Result<(), PanelError> {
+        self.set_contrast(if dimmed { 0x00 } else { 0x80 }).map_err(|_| PanelError)
+    }
+
+    fn set_contrast_level(&mut self, level: u8) -> Result<(), PanelError> {
+        self.set_contrast(level).map_err(|_| PanelError)
+    }
+
+    fn set_on(&mut self, on: bool) -> Result<(), PanelError> {
+        self.display_on(on).map_err(|_| PanelError)
+    }
+}
@@ -0,0 +1,109 @@
+//! DS18B20 external temperature probes on the 1-Wire bus
+//!
+//! For deployments watching soil or water alongside the air, Node 1 (feature
+//! `ds18b20`) reads up to `MAX_PROBES` waterproof DS18B20 probes on PB5 and
+//! sends them as one `tlv::PROBE_TEMPS` record. Probes are found by ROM
+//! search at boot and keep that order, so a probe's position in the record
+//! is stable as long as the set of probes doesn't change.
+//!
+//! A 12-bit conversion takes 750 ms, far too long to wait for inside the
+//! sample tick. `Probes::read` instead collects the conversion started on
+//! the previous sample and starts the next, so probe values are one TX
+//! interval old. Probes must be powered from VDD; parasite power would
+//! need a strong pull-up during conversion.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use heapless::Vec;
+
+use crate::onewire::{self, OneWire, Rom};
+
+/// Probes read, and values in a `PROBE_TEMPS` record
+pub const MAX_PROBES: usize = 4;
+
+/// Record value for a probe that didn't answer or failed its CRC
+pub const NO_READING: i16 = i16::MIN;
+
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xCC;
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+/// DS18B20 family code, the first ROM byte
+const FAMILY: u8 = 0x28;
+
+pub struct Probes<P, D> {
+    bus: OneWire<P, D>,
+    roms: Vec<Rom, MAX_PROBES>,
+    /// A conversion was started and its results not yet read
+    converting: bool,
+}
+
+impl<P: InputPin + OutputPin, D: DelayNs> Probes<P, D> {
+    /// Search the bus; finding no probes isn't an error, just nothing to send
+    pub fn new(mut bus: OneWire<P, D>) -> Self {
+        let roms = match bus.search::<MAX_PROBES>() {
+            Ok(roms) => roms.into_iter().filter(|rom| rom[0] == FAMILY).collect(),
+            Err(e) => {
+                defmt::warn!("DS18B20 search failed: {}", e);
+                Vec::new()
+            }
+        };
+        Self { bus, roms, converting: false }
+    }
+
+    pub fn count(&self) -> usize {
+        self.roms.len()
+    }
+
+    /// Last cycle's temperatures in 0.01 °C, in probe order, then start
+    /// the next conversion
+    ///
+    /// Empty on the first call (nothing converted yet) or without probes.
+    pub fn read(&mut self) -> Vec<i16, MAX_PROBES> {
+        let mut temps = Vec::new();
+        if self.converting {
+            for i in 0..self.roms.len() {
+                let rom = self.roms[i];
+                let _ = temps.push(self.read_probe(&rom).unwrap_or(NO_READING));
+            }
+        }
+        self.converting = !self.roms.is_empty() && self.start_conversion().is_ok();
+        temps
+    }
+
+    /// All probes at once
+    fn start_conversion(&mut self) -> Result<(), onewire::Error> {
+        self.bus.reset()?;
+        self.bus.write_bytes(&[SKIP_ROM, CONVERT_T]);
+        Ok(())
+    }
+
+    fn read_probe(&mut self, rom: &Rom) -> Result<i16, onewire::Error> {
+        self.bus.reset()?;
+        self.bus.write_byte(MATCH_ROM);
+        self.bus.write_bytes(rom);
+        self.bus.write_byte(READ_SCRATCHPAD);
+        let mut scratchpad = [0u8; 9];
+        self.bus.read_bytes(&mut scratchpad);
+        if onewire::crc8(&scratchpad[..8]) != scratchpad[8] {
+            return Err(onewire::Error::Crc);
+        }
+        // 1/16 °C, two's complement
+        let sixteenths = i16::from_le_bytes([scratchpad[0], scratchpad[1]]) as i32;
+        Ok((sixteenths * 100 / 16) as i16)
+    }
+}
+
+/// The value of a `PROBE_TEMPS` record
+pub fn encode(temps: &[i16]) -> Vec<u8, { 2 * MAX_PROBES }> {
+    temps.iter().take(MAX_PROBES).flat_map(|t| t.to_le_bytes()).collect()
+}
+
+/// Probe temperatures in °C from a `PROBE_TEMPS` record, `None` for
+/// `NO_READING`
+pub fn decode(value: &[u8]) -> impl Iterator<Item = Option<f32>> + '_ {
+    value.chunks_exact(2).map(|b| match i16::from_le_bytes([b[0], b[1]]) {
+        NO_READING => None,
+        centi => Some(centi as f32 / 100.0),
+    })
+}
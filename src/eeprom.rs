@@ -0,0 +1,256 @@
+//! Settings and lifetime counters in an external 24LCxx EEPROM
+//!
+//! Flash sector 6 holds Node 1's settings well enough, but every erase
+//! stalls the core for a second or two and the sector is lost with a full
+//! chip erase. A 24LC32 or larger on the sensor I2C bus (address 0x50, A0-A2
+//! tied low) takes the settings over when it answers at boot, and also keeps
+//! counters that outlive both resets and reflashing. Without one, `config`,
+//! `calibration` and `soil` keep using flash as before; the shell's
+//! `config::Settings` stay in flash either way.
+//!
+//! The first 1792 bytes are 32-byte slots, one page each on every part from
+//! the 24LC32 up:
+//!   [version u8][kind u8][seq u16][len u8][0xFF][CRC-16 u16][postcard record ...]
+//! Each `Kind` owns a ring of slots and the valid slot with the newest
+//! `seq` wins. Writes go round the ring, so each cell sees a fraction of the
+//! saves, and a record identical to the newest isn't written at all. The
+//! parts are rated for a million cycles per cell, so the hourly counter
+//! save lasts millennia rather than years.
+//!
+//! `seq` is written with the record and the CRC covers both, so a write
+//! cut short by a reset leaves the previous slot in charge. Slots with
+//! another `LAYOUT_VERSION` are skipped: a firmware that changes a record
+//! bumps it and starts from the flash copy or defaults again.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embedded_hal_0_2::blocking::i2c::{Write, WriteRead};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::calculate_crc16;
+use crate::time;
+
+/// 7-bit address with A0-A2 low
+pub const ADDRESS: u8 = 0x50;
+/// Bumped whenever a record's fields change
+const LAYOUT_VERSION: u8 = 1;
+const SLOT_SIZE: usize = 32;
+const SLOT_HEADER: usize = 8;
+const BODY_SIZE: usize = SLOT_SIZE - SLOT_HEADER;
+/// A page write takes at most 5 ms; polled in 1 ms steps
+const WRITE_TIMEOUT_MS: u32 = 10;
+
+/// Live sensor frames sent since boot
+static SENT: AtomicU32 = AtomicU32::new(0);
+/// Frames given up on after every retry since boot
+static GAVE_UP: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EepromError {
+    /// No ACK from the chip, or the transfer failed
+    Bus,
+    /// Still busy with a write cycle after `WRITE_TIMEOUT_MS`
+    Timeout,
+    /// The record doesn't fit in a slot (a firmware bug)
+    TooLarge,
+}
+
+/// What a ring of slots holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum Kind {
+    /// `config::NodeConfig`
+    Config = 0,
+    /// `calibration::Calibration`
+    Calibration = 1,
+    /// `soil::SoilCalibration`
+    Soil = 2,
+    /// `Lifetime`
+    Lifetime = 3,
+}
+
+impl Kind {
+    const ALL: [Kind; 4] = [Kind::Config, Kind::Calibration, Kind::Soil, Kind::Lifetime];
+
+    /// First slot and slot count; settings change rarely, counters hourly
+    fn ring(self) -> (u16, u16) {
+        match self {
+            Kind::Config => (0, 8),
+            Kind::Calibration => (8, 8),
+            Kind::Soil => (16, 8),
+            Kind::Lifetime => (24, 32),
+        }
+    }
+}
+
+/// Counters kept across every boot of the node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct Lifetime {
+    pub boots: u32,
+    /// Whole hours of uptime; the part-hour before each reset is lost
+    pub hours: u32,
+    pub frames_sent: u32,
+    /// Frames that ran out of retries
+    pub frames_gave_up: u32,
+}
+
+impl Lifetime {
+    /// These counters plus this boot's, `uptime_s` into it
+    pub fn with_session(&self, uptime_s: u32) -> Self {
+        Self {
+            boots: self.boots,
+            hours: self.hours.wrapping_add(uptime_s / 3600),
+            frames_sent: self.frames_sent.wrapping_add(SENT.load(Ordering::Relaxed)),
+            frames_gave_up: self.frames_gave_up.wrapping_add(GAVE_UP.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Count a live sensor frame sent
+pub fn note_sent() {
+    SENT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a frame given up on after every retry
+pub fn note_gave_up() {
+    GAVE_UP.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Newest valid slot of one kind
+#[derive(Debug, Clone, Copy)]
+struct Head {
+    slot: u16,
+    seq: u16,
+}
+
+pub struct Eeprom<I2C> {
+    i2c: I2C,
+    heads: [Option<Head>; Kind::ALL.len()],
+}
+
+impl<I2C, E> Eeprom<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Find each kind's newest slot; `None` if nothing answers at `ADDRESS`
+    pub fn probe(i2c: I2C) -> Option<Self> {
+        let mut eeprom = Self { i2c, heads: [None; Kind::ALL.len()] };
+        for kind in Kind::ALL {
+            let (first, count) = kind.ring();
+            for slot in first..first + count {
+                let mut buf = [0u8; SLOT_SIZE];
+                eeprom.read(slot * SLOT_SIZE as u16, &mut buf).ok()?;
+                let Some(seq) = valid_seq(&buf, kind) else { continue };
+                let head = &mut eeprom.heads[kind as usize];
+                if head.map_or(true, |h| newer(seq, h.seq)) {
+                    *head = Some(Head { slot, seq });
+                }
+            }
+        }
+        Some(eeprom)
+    }
+
+    /// The newest record of `kind`, if one was ever saved
+    pub fn load<T: DeserializeOwned>(&mut self, kind: Kind) -> Option<T> {
+        let head = self.heads[kind as usize]?;
+        let mut buf = [0u8; SLOT_SIZE];
+        self.read(head.slot * SLOT_SIZE as u16, &mut buf).ok()?;
+        valid_seq(&buf, kind)?;
+        postcard::from_bytes(&buf[SLOT_HEADER..SLOT_HEADER + buf[4] as usize]).ok()
+    }
+
+    /// Save `record` as the newest of `kind`, unless it already is
+    ///
+    /// Returns whether a slot was written. Blocks for the write cycle,
+    /// about 5 ms.
+    pub fn save<T: Serialize>(&mut self, kind: Kind, record: &T) -> Result<bool, EepromError> {
+        let mut slot_buf = [0xFFu8; SLOT_SIZE];
+        let len = postcard::to_slice(record, &mut slot_buf[SLOT_HEADER..])
+            .map_err(|_| EepromError::TooLarge)?
+            .len();
+
+        let head = self.heads[kind as usize];
+        if let Some(head) = head {
+            let mut current = [0u8; SLOT_SIZE];
+            self.read(head.slot * SLOT_SIZE as u16, &mut current).map_err(|_| EepromError::Bus)?;
+            if valid_seq(&current, kind).is_some() && current[4] as usize == len
+                && current[SLOT_HEADER..SLOT_HEADER + len] == slot_buf[SLOT_HEADER..SLOT_HEADER + len]
+            {
+                return Ok(false);
+            }
+        }
+
+        let (first, count) = kind.ring();
+        let (slot, seq) = match head {
+            Some(head) => (first + (head.slot - first + 1) % count, head.seq.wrapping_add(1)),
+            None => (first, 0),
+        };
+        slot_buf[0] = LAYOUT_VERSION;
+        slot_buf[1] = kind as u8;
+        slot_buf[2..4].copy_from_slice(&seq.to_le_bytes());
+        slot_buf[4] = len as u8;
+        let crc = slot_crc(&slot_buf, len);
+        slot_buf[6..8].copy_from_slice(&crc.to_le_bytes());
+
+        self.write_page(slot * SLOT_SIZE as u16, &slot_buf[..SLOT_HEADER + len])?;
+        self.heads[kind as usize] = Some(Head { slot, seq });
+        Ok(true)
+    }
+
+    /// Drop every record of `kind`, for a factory reset; blocks for a
+    /// write cycle per slot
+    pub fn forget(&mut self, kind: Kind) -> Result<(), EepromError> {
+        let (first, count) = kind.ring();
+        for slot in first..first + count {
+            // An erased header is no layout's
+            self.write_page(slot * SLOT_SIZE as u16, &[0xFF; SLOT_HEADER])?;
+        }
+        self.heads[kind as usize] = None;
+        Ok(())
+    }
+
+    fn read(&mut self, addr: u16, buf: &mut [u8]) -> Result<(), E> {
+        self.i2c.write_read(ADDRESS, &addr.to_be_bytes(), buf)
+    }
+
+    /// Write within one 32-byte page and wait out the write cycle
+    fn write_page(&mut self, addr: u16, bytes: &[u8]) -> Result<(), EepromError> {
+        let mut buf = [0u8; 2 + SLOT_SIZE];
+        buf[..2].copy_from_slice(&addr.to_be_bytes());
+        buf[2..2 + bytes.len()].copy_from_slice(bytes);
+        self.i2c.write(ADDRESS, &buf[..2 + bytes.len()]).map_err(|_| EepromError::Bus)?;
+
+        // The chip doesn't ACK its address until the cycle is done
+        for _ in 0..WRITE_TIMEOUT_MS {
+            time::busy_wait_ms(1);
+            if self.i2c.write_read(ADDRESS, &addr.to_be_bytes(), &mut [0u8]).is_ok() {
+                return Ok(());
+            }
+        }
+        Err(EepromError::Timeout)
+    }
+}
+
+/// The slot's `seq` if it holds an intact `kind` record of this layout
+fn valid_seq(buf: &[u8; SLOT_SIZE], kind: Kind) -> Option<u16> {
+    let len = buf[4] as usize;
+    if buf[0] != LAYOUT_VERSION || buf[1] != kind as u8 || len > BODY_SIZE {
+        return None;
+    }
+    let crc = u16::from_le_bytes([buf[6], buf[7]]);
+    (crc == slot_crc(buf, len)).then(|| u16::from_le_bytes([buf[2], buf[3]]))
+}
+
+/// CRC over the header up to the CRC field and the record
+fn slot_crc(buf: &[u8; SLOT_SIZE], len: usize) -> u16 {
+    let mut covered = [0u8; 6 + BODY_SIZE];
+    covered[..6].copy_from_slice(&buf[..6]);
+    covered[6..6 + len].copy_from_slice(&buf[SLOT_HEADER..SLOT_HEADER + len]);
+    calculate_crc16(&covered[..6 + len])
+}
+
+/// Whether `a` was written after `b`, allowing for `seq` wrapping
+fn newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
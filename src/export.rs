@@ -0,0 +1,94 @@
+//! Bulk export of stored data over the debug UART
+//!
+//! A field unit's flash holds hours of readings (`backlog`) and its crash
+//! log. Typing `export` in Node 1's shell streams all of it to the laptop
+//! on the VCP as binary records framed as in `protocol::stream`: an
+//! `ExportBegin`, one `StoredReading` (`MSG_TYPE_STORED`) per backlog slot,
+//! oldest first, then one `CrashReport` per crash log entry, and finally an
+//! `ExportEnd` giving the number of records between. `wk3 dump` on the
+//! host reads them back.
+//!
+//! Records go out a batch at a time from a low-priority task, so a long
+//! export doesn't hold off the sensor tick; at 115200 baud a full backlog
+//! takes about five seconds. Shell replies and echo are off until the end.
+//!
+//! Node 2 uses the same framing for `stream on`, which forwards every
+//! payload it receives as it arrives; `streaming` is that switch.
+//! `stream tap` also forwards the module's other lines (`tapping`), so a
+//! capture shows everything the radio said.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub use crate::protocol::stream::{
+    encode, ExportBegin, ExportEnd, EXPORT_BEGIN, EXPORT_END, RADIO_FRAME, RAW_LINE, SYNC,
+};
+
+/// An export is streaming; the shell stays quiet until it's done
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Node 2 forwards received payloads to the host
+static STREAMING: AtomicBool = AtomicBool::new(false);
+/// Node 2 forwards the module's other lines too
+static TAPPING: AtomicBool = AtomicBool::new(false);
+
+/// How far an export has got, carried from one batch to the next
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub begin: ExportBegin,
+    /// Backlog slots first, then crash log slots
+    pub next: u32,
+    pub records: u32,
+}
+
+impl Progress {
+    /// Start an export; `None` if one is already running
+    pub fn start(begin: ExportBegin) -> Option<Self> {
+        (!ACTIVE.swap(true, Ordering::Relaxed)).then_some(Self { begin, next: 0, records: 0 })
+    }
+
+    /// The backlog slot or crash log slot to send next
+    pub fn item(&self) -> Item {
+        if self.next < self.begin.readings {
+            Item::Reading(self.next)
+        } else if self.next - self.begin.readings < self.begin.crashes {
+            Item::Crash(self.next - self.begin.readings)
+        } else {
+            Item::Done
+        }
+    }
+
+    /// The export is over: the end record to send, and the shell is back
+    pub fn finish(&self) -> ExportEnd {
+        ACTIVE.store(false, Ordering::Relaxed);
+        ExportEnd { records: self.records }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    Reading(u32),
+    Crash(u32),
+    Done,
+}
+
+/// Whether an export is streaming
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Whether Node 2 forwards received payloads (`stream on`)
+pub fn streaming() -> bool {
+    STREAMING.load(Ordering::Relaxed)
+}
+
+pub fn set_streaming(on: bool) {
+    STREAMING.store(on, Ordering::Relaxed);
+}
+
+/// Whether Node 2 also forwards lines that aren't frames (`stream tap`)
+pub fn tapping() -> bool {
+    TAPPING.load(Ordering::Relaxed)
+}
+
+pub fn set_tapping(on: bool) {
+    TAPPING.store(on, Ordering::Relaxed);
+}
@@ -0,0 +1,98 @@
+//! Factory reset: the user button held for `HOLD_MS`
+//!
+//! A unit set up wrong in the field, on a network ID nobody remembers or
+//! a band the gateway isn't on, can't be reached to be put right. Holding
+//! its user button for ten seconds puts it back as it left the bench.
+//! After `SHOW_AFTER_MS` the display counts down the seconds left, and
+//! letting go then cancels rather than opening the setup menu. At zero
+//! the node wipes its settings and reboots on the firmware's defaults: the
+//! build's `node_id`, network ID and band (see `preset`), so a build with
+//! `node_id = 0` comes back asking to join.
+//!
+//! Wiped: the setup menu's config, the sensor and soil trims and the
+//! shell's settings, from flash sector 6 and from the EEPROM or SPI flash
+//! store where they live there, and the hop list and module record with
+//! the rest of sector 6. Kept: the boot count and the highest
+//! command counter accepted, without which Node 2's commands would be
+//! refused as replays or old ones replayed, and Node 2's join table, so
+//! no address is handed out twice. The command key is built in and isn't
+//! touched.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use heapless::String;
+
+use crate::display::Canvas;
+use crate::time;
+use crate::ui;
+
+/// How long the button is held to reset
+pub const HOLD_MS: u32 = 10_000;
+/// Held this long, the countdown shows and letting go cancels
+pub const SHOW_AFTER_MS: u32 = 3_000;
+
+static PRESSED_AT_MS: AtomicU32 = AtomicU32::new(0);
+static HELD: AtomicBool = AtomicBool::new(false);
+
+/// Where a hold stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Hold {
+    /// Let go, or another press has started since
+    Released,
+    /// Whole seconds still to hold
+    Counting(u32),
+    /// Held long enough
+    Due,
+}
+
+/// The button went down at `now_ms`
+pub fn press(now_ms: u32) {
+    PRESSED_AT_MS.store(now_ms, Ordering::Relaxed);
+    HELD.store(true, Ordering::Relaxed);
+}
+
+/// The button came up at `now_ms`; `true` if the countdown was showing,
+/// in which case letting go only cancels it
+pub fn release(now_ms: u32) -> bool {
+    let held = HELD.swap(false, Ordering::Relaxed);
+    held && time::elapsed_ms(now_ms, PRESSED_AT_MS.load(Ordering::Relaxed)) >= SHOW_AFTER_MS
+}
+
+/// The press that started at `pressed_at_ms`, as of `now_ms`
+pub fn hold(pressed_at_ms: u32, now_ms: u32) -> Hold {
+    if !HELD.load(Ordering::Relaxed) || PRESSED_AT_MS.load(Ordering::Relaxed) != pressed_at_ms {
+        return Hold::Released;
+    }
+    match HOLD_MS.saturating_sub(time::elapsed_ms(now_ms, pressed_at_ms)) {
+        0 => Hold::Due,
+        left_ms => Hold::Counting(left_ms.div_ceil(1_000)),
+    }
+}
+
+/// Seconds left on the countdown if one is showing, for a display that
+/// redraws on its own
+pub fn countdown(now_ms: u32) -> Option<u32> {
+    let pressed_at_ms = PRESSED_AT_MS.load(Ordering::Relaxed);
+    if time::elapsed_ms(now_ms, pressed_at_ms) < SHOW_AFTER_MS {
+        return None;
+    }
+    match hold(pressed_at_ms, now_ms) {
+        Hold::Counting(seconds) => Some(seconds),
+        Hold::Released | Hold::Due => None,
+    }
+}
+
+/// The countdown with `seconds` left, or the reset itself at `None`
+pub fn draw<D: Canvas>(d: &mut D, seconds: Option<u32>) {
+    let mut buf: String<24> = String::new();
+    match seconds {
+        Some(seconds) => {
+            let _ = write!(buf, "FACTORY RESET IN {}s", seconds);
+            ui::line(d, 0, &buf);
+            ui::line(d, 1, "Let go to cancel");
+        }
+        None => ui::line(d, 0, "FACTORY RESET..."),
+    }
+    ui::line(d, 2, "Settings are wiped");
+}
@@ -0,0 +1,123 @@
+//! Smoothing temperature and humidity before they are packed
+//!
+//! Single SHT31 readings jitter by a few hundredths of a degree and the
+//! BME680's by more, which is enough to trip tight send-on-delta thresholds
+//! (see `delta`) on noise alone. `Smoother` keeps the last few samples and
+//! replaces each new one with their mean or median; the median also throws
+//! away a lone spike entirely.
+//!
+//! Mode and window are runtime settings, changed from the shell (`filter`)
+//! like the delta thresholds. The window is in samples, one per TX
+//! interval, so a long window trades noise for lag.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use heapless::HistoryBuffer;
+
+use crate::sensor::Measurement;
+
+/// Longest window the shell accepts
+pub const MAX_WINDOW: usize = 8;
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Median as u8);
+static WINDOW: AtomicU8 = AtomicU8::new(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Mode {
+    /// Send each sample as read
+    Off = 0,
+    Mean = 1,
+    Median = 2,
+}
+
+impl Mode {
+    const ALL: [Mode; 3] = [Mode::Off, Mode::Mean, Mode::Median];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Off => "off",
+            Mode::Mean => "mean",
+            Mode::Median => "median",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The mode with discriminant `index`, as saved in `config::Settings`
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+pub fn mode() -> Mode {
+    Mode::ALL[MODE.load(Ordering::Relaxed) as usize]
+}
+
+/// Samples combined into each output
+pub fn window() -> usize {
+    WINDOW.load(Ordering::Relaxed) as usize
+}
+
+/// Change the filter; `window` is clamped to 1..=`MAX_WINDOW`
+pub fn set(mode: Mode, window: usize) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+    WINDOW.store(window.clamp(1, MAX_WINDOW) as u8, Ordering::Relaxed);
+}
+
+/// Recent raw temperature and humidity samples
+pub struct Smoother {
+    temperature: HistoryBuffer<f32, MAX_WINDOW>,
+    humidity: HistoryBuffer<f32, MAX_WINDOW>,
+}
+
+impl Smoother {
+    pub const fn new() -> Self {
+        Self { temperature: HistoryBuffer::new(), humidity: HistoryBuffer::new() }
+    }
+
+    /// Record `m`'s raw values and replace them with the filtered ones
+    ///
+    /// Until the window has filled, the samples so far are used.
+    pub fn apply(&mut self, m: &mut Measurement) {
+        let (mode, window) = (mode(), window());
+        if let Some(t) = m.temperature_c.as_mut() {
+            self.temperature.write(*t);
+            *t = combine(&self.temperature, mode, window).unwrap_or(*t);
+        }
+        if let Some(h) = m.humidity_pct.as_mut() {
+            self.humidity.write(*h);
+            *h = combine(&self.humidity, mode, window).unwrap_or(*h);
+        }
+    }
+}
+
+impl Default for Smoother {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The newest `window` samples of `history`, combined per `mode`
+fn combine(history: &HistoryBuffer<f32, MAX_WINDOW>, mode: Mode, window: usize) -> Option<f32> {
+    let mut all = [0.0f32; MAX_WINDOW];
+    for (slot, &v) in all.iter_mut().zip(history.oldest_ordered()) {
+        *slot = v;
+    }
+    let len = history.len();
+    let n = window.min(len);
+    if n == 0 {
+        return None;
+    }
+    let recent = &mut all[len - n..len];
+
+    match mode {
+        Mode::Off => None,
+        Mode::Mean => Some(recent.iter().sum::<f32>() / n as f32),
+        Mode::Median => {
+            recent.sort_unstable_by(|a, b| a.total_cmp(b));
+            Some(if n % 2 == 1 { recent[n / 2] } else { (recent[n / 2 - 1] + recent[n / 2]) / 2.0 })
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Internal flash erase/program (RM0390 section 3.6)
+//!
+//! Register access goes through raw bit constants rather than PAC field
+//! names so the same code works from `init`, from tasks, and from the panic
+//! handler where the HAL objects may be in any state. All operations are
+//! blocking and must not run from code executing out of the sector being
+//! erased (storage sectors sit above the firmware, see `memory.x`).
+
+use stm32f4xx_hal::pac;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+// FLASH_CR bits
+const CR_PG: u32 = 1 << 0;
+const CR_SER: u32 = 1 << 1;
+const CR_SNB_SHIFT: u32 = 3;
+const CR_PSIZE_X32: u32 = 0b10 << 8;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+// FLASH_SR bits
+const SR_EOP: u32 = 1 << 0;
+const SR_OPERR: u32 = 1 << 1;
+const SR_WRPERR: u32 = 1 << 4;
+const SR_PGAERR: u32 = 1 << 5;
+const SR_PGPERR: u32 = 1 << 6;
+const SR_PGSERR: u32 = 1 << 7;
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = SR_OPERR | SR_WRPERR | SR_PGAERR | SR_PGPERR | SR_PGSERR;
+
+// FLASH_ACR data cache bits
+const ACR_DCEN: u32 = 1 << 10;
+const ACR_DCRST: u32 = 1 << 12;
+
+/// One erasable flash sector
+#[derive(Debug, Clone, Copy)]
+pub struct Sector {
+    pub number: u8,
+    pub base: u32,
+    pub size: u32,
+}
+
+/// Sector 5 (128K): readings waiting for an ACK, see `backlog`
+pub const BACKLOG_SECTOR: Sector = Sector { number: 5, base: 0x0802_0000, size: 128 * 1024 };
+
+/// Sector 6 (128K): node settings, see `config`
+pub const CONFIG_SECTOR: Sector = Sector { number: 6, base: 0x0804_0000, size: 128 * 1024 };
+
+/// Sector 7 (128K): crash records, see `crash`
+pub const CRASH_SECTOR: Sector = Sector { number: 7, base: 0x0806_0000, size: 128 * 1024 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FlashError {
+    Alignment,
+    WriteProtected,
+    Program,
+    Operation,
+}
+
+fn regs() -> &'static pac::flash::RegisterBlock {
+    unsafe { &*pac::FLASH::ptr() }
+}
+
+fn wait_idle() -> Result<(), FlashError> {
+    let flash = regs();
+    while flash.sr().read().bits() & SR_BSY != 0 {}
+    let sr = flash.sr().read().bits();
+    // Status bits are cleared by writing 1
+    flash.sr().write(|w| unsafe { w.bits(sr & (SR_ERRORS | SR_EOP)) });
+    if sr & SR_WRPERR != 0 {
+        Err(FlashError::WriteProtected)
+    } else if sr & (SR_PGAERR | SR_PGPERR | SR_PGSERR) != 0 {
+        Err(FlashError::Program)
+    } else if sr & SR_OPERR != 0 {
+        Err(FlashError::Operation)
+    } else {
+        Ok(())
+    }
+}
+
+fn unlock() {
+    let flash = regs();
+    if flash.cr().read().bits() & CR_LOCK != 0 {
+        flash.keyr().write(|w| unsafe { w.bits(KEY1) });
+        flash.keyr().write(|w| unsafe { w.bits(KEY2) });
+    }
+}
+
+fn lock() {
+    regs().cr().modify(|r, w| unsafe { w.bits(r.bits() | CR_LOCK) });
+}
+
+/// Invalidate the ART data cache so reads see freshly erased/programmed data
+fn reset_data_cache() {
+    let flash = regs();
+    flash.acr().modify(|r, w| unsafe { w.bits(r.bits() & !ACR_DCEN) });
+    flash.acr().modify(|r, w| unsafe { w.bits(r.bits() | ACR_DCRST) });
+    flash.acr().modify(|r, w| unsafe { w.bits((r.bits() & !ACR_DCRST) | ACR_DCEN) });
+}
+
+/// Erase a whole sector (1-2 s for a 128K sector; blocks the caller)
+pub fn erase_sector(sector: &Sector) -> Result<(), FlashError> {
+    let flash = regs();
+    wait_idle()?;
+    unlock();
+    flash.cr().write(|w| unsafe {
+        w.bits(CR_PSIZE_X32 | CR_SER | ((sector.number as u32) << CR_SNB_SHIFT))
+    });
+    flash.cr().modify(|r, w| unsafe { w.bits(r.bits() | CR_STRT) });
+    let result = wait_idle();
+    flash.cr().write(|w| unsafe { w.bits(0) });
+    lock();
+    reset_data_cache();
+    result
+}
+
+/// Program 32-bit words starting at `addr` (must be word aligned, erased)
+pub fn program_words(addr: u32, words: &[u32]) -> Result<(), FlashError> {
+    if addr % 4 != 0 {
+        return Err(FlashError::Alignment);
+    }
+    let flash = regs();
+    wait_idle()?;
+    unlock();
+    flash.cr().write(|w| unsafe { w.bits(CR_PSIZE_X32 | CR_PG) });
+    let mut result = Ok(());
+    for (i, word) in words.iter().enumerate() {
+        unsafe { ((addr as *mut u32).add(i)).write_volatile(*word) };
+        result = wait_idle();
+        if result.is_err() {
+            break;
+        }
+    }
+    flash.cr().write(|w| unsafe { w.bits(0) });
+    lock();
+    reset_data_cache();
+    result
+}
+
+/// Program a byte slice, padding the final word with 0xFF
+pub fn program_bytes(addr: u32, bytes: &[u8]) -> Result<(), FlashError> {
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let mut word = [0xFFu8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        program_words(addr + (i as u32) * 4, &[u32::from_le_bytes(word)])?;
+    }
+    Ok(())
+}
+
+/// Read a 32-bit word from flash
+pub fn read_word(addr: u32) -> u32 {
+    unsafe { (addr as *const u32).read_volatile() }
+}
+
+/// Borrow `len` bytes of flash at `addr`
+pub fn read_bytes(addr: u32, len: usize) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+}
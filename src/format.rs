@@ -0,0 +1,114 @@
+//! Value formatting shared by every screen
+//!
+//! Each wrapper implements `Display` with its unit attached, so pages write
+//! `write!(buf, "T:{} H:{}", Temp(t), Humidity(h))` instead of repeating
+//! precision, scaling and unit suffixes. The display fonts are the
+//! ISO 8859-1 variants, which have the degree sign.
+//!
+//! Temperatures are stored and sent in °C; `Temp` converts to °F for
+//! display when the unit is switched from the shell (`units f`).
+
+use core::fmt::{self, Display, Formatter};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static FAHRENHEIT: AtomicBool = AtomicBool::new(false);
+
+pub fn fahrenheit() -> bool {
+    FAHRENHEIT.load(Ordering::Relaxed)
+}
+
+pub fn set_fahrenheit(on: bool) {
+    FAHRENHEIT.store(on, Ordering::Relaxed);
+}
+
+/// Temperature in °C, shown with one decimal in the selected unit
+#[derive(Debug, Clone, Copy)]
+pub struct Temp(pub f32);
+
+impl Display for Temp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if fahrenheit() {
+            write!(f, "{:.1}°F", self.0 * 9.0 / 5.0 + 32.0)
+        } else {
+            write!(f, "{:.1}°C", self.0)
+        }
+    }
+}
+
+/// Relative humidity in percent, whole numbers
+#[derive(Debug, Clone, Copy)]
+pub struct Humidity(pub f32);
+
+impl Display for Humidity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0}%", self.0)
+    }
+}
+
+/// Resistance in ohms, scaled to k/M with about three significant digits
+///
+/// The fonts have no omega, so the unit is the bare prefix ("125k").
+#[derive(Debug, Clone, Copy)]
+pub struct Ohms(pub u32);
+
+impl Display for Ohms {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let ohms = self.0;
+        match ohms {
+            0..=999 => write!(f, "{}", ohms),
+            1_000..=9_999 => write!(f, "{:.2}k", ohms as f32 / 1e3),
+            10_000..=999_999 => write!(f, "{:.0}k", ohms as f32 / 1e3),
+            _ => write!(f, "{:.1}M", ohms as f32 / 1e6),
+        }
+    }
+}
+
+/// Received signal strength
+#[derive(Debug, Clone, Copy)]
+pub struct Dbm(pub i16);
+
+impl Display for Dbm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}dBm", self.0)
+    }
+}
+
+/// Signal-to-noise ratio
+#[derive(Debug, Clone, Copy)]
+pub struct Db(pub i16);
+
+impl Display for Db {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}dB", self.0)
+    }
+}
+
+/// Permille as a percentage with one decimal ("2.5%")
+#[derive(Debug, Clone, Copy)]
+pub struct Permille(pub u16);
+
+impl Display for Permille {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}%", self.0 / 10, self.0 % 10)
+    }
+}
+
+/// Seconds as the two most significant units: 12s, 5m07s, 3h02m, 2d03h
+#[derive(Debug, Clone, Copy)]
+pub struct Age(pub u32);
+
+impl Display for Age {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let secs = self.0;
+        let (m, h, days) = (secs / 60, secs / 3600, secs / 86_400);
+        if days > 0 {
+            write!(f, "{}d{:02}h", days, h % 24)
+        } else if h > 0 {
+            write!(f, "{}h{:02}m", h, m % 60)
+        } else if m > 0 {
+            write!(f, "{}m{:02}s", m, secs % 60)
+        } else {
+            write!(f, "{}s", secs)
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Sequence ranges Node 2 is missing, and when to ask for them again
+//!
+//! Node 1 replays readings it never got an ACK for on its own (see
+//! `backlog`), but a reading can also go missing with Node 1 none the
+//! wiser: dropped over the duty cycle, or not stored because its flash was
+//! full at the time. Node 2 notes every sequence gap here. Backfilled
+//! readings are taken out as they arrive, and whatever is still missing a
+//! minute later is asked for with a `ReplayRequest`. Node 1 answers from
+//! its flash with `ReplayBatch` frames, and a range still missing is asked
+//! for again, up to `MAX_ASKS` times.
+//!
+//! Node 1 only serves readings from its current boot, so a sequence
+//! restart drops every range.
+
+use heapless::Vec;
+
+use crate::protocol::{ReplayRequest, REPLAY_MAX_RANGE};
+
+/// Ranges tracked at once; a new one replaces the oldest
+const MAX_GAPS: usize = 8;
+/// Time Node 1's own replay gets before a range is asked for
+const FIRST_ASK_MS: u32 = 60_000;
+const ASK_INTERVAL_MS: u32 = 60_000;
+const MAX_ASKS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+struct Gap {
+    from: u16,
+    len: u16,
+    due_ms: u32,
+    asked: u8,
+}
+
+pub struct Gaps {
+    gaps: Vec<Gap, MAX_GAPS>,
+    /// Readings given up on after `MAX_ASKS` requests
+    abandoned: u32,
+}
+
+impl Gaps {
+    pub const fn new() -> Self {
+        Self { gaps: Vec::new(), abandoned: 0 }
+    }
+
+    /// `missed` readings were skipped before `seq`, noticed at `now_ms`
+    pub fn add(&mut self, seq: u16, missed: u16, now_ms: u32) {
+        // Of a longer gap, only the readings just before `seq` are asked for
+        let len = missed.min(REPLAY_MAX_RANGE);
+        if len == 0 {
+            return;
+        }
+        if self.gaps.is_full() {
+            let oldest = self.gaps.remove(0);
+            defmt::warn!("Too many gaps, giving up on #{}+{}", oldest.from, oldest.len);
+            self.abandoned += oldest.len as u32;
+        }
+        let _ = self.gaps.push(Gap { from: seq.wrapping_sub(len), len, due_ms: now_ms.wrapping_add(FIRST_ASK_MS), asked: 0 });
+    }
+
+    /// A backfilled reading arrived; take it out of its range
+    pub fn fill(&mut self, seq: u16) {
+        let Some(index) = self.gaps.iter().position(|g| seq.wrapping_sub(g.from) < g.len) else {
+            return;
+        };
+        let gap = self.gaps[index];
+        let offset = seq.wrapping_sub(gap.from);
+        let before = Gap { len: offset, ..gap };
+        let after = Gap { from: seq.wrapping_add(1), len: gap.len - offset - 1, ..gap };
+        self.gaps.remove(index);
+        for part in [before, after] {
+            // A split that doesn't fit is asked for again whole; Node 1 just resends one
+            if part.len > 0 && self.gaps.push(part).is_err() {
+                let _ = self.gaps.push(gap);
+            }
+        }
+    }
+
+    /// Node 1's sequence restarted: its old numbers can't be asked for
+    pub fn clear(&mut self) {
+        self.gaps.clear();
+    }
+
+    /// A range to ask for at `now_ms`, if one is due; it's asked again later
+    pub fn due(&mut self, now_ms: u32) -> Option<ReplayRequest> {
+        let index = self.gaps.iter().position(|g| now_ms.wrapping_sub(g.due_ms) < u32::MAX / 2)?;
+        let gap = &mut self.gaps[index];
+        if gap.asked == MAX_ASKS {
+            let gap = self.gaps.remove(index);
+            defmt::warn!("Readings #{}+{} never replayed, giving up", gap.from, gap.len);
+            self.abandoned += gap.len as u32;
+            return None;
+        }
+        gap.asked += 1;
+        gap.due_ms = now_ms.wrapping_add(ASK_INTERVAL_MS);
+        Some(ReplayRequest { from: gap.from, to: gap.from.wrapping_add(gap.len - 1) })
+    }
+
+    /// Readings still missing
+    pub fn missing(&self) -> u32 {
+        self.gaps.iter().map(|g| g.len as u32).sum()
+    }
+
+    /// Readings given up on since boot
+    pub fn abandoned(&self) -> u32 {
+        self.abandoned
+    }
+}
+
+impl Default for Gaps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,54 @@
+//! BME688 gas scanning: stepping the hot plate through a heater profile
+//!
+//! A metal-oxide gas sensor's resistance depends strongly on the hot plate
+//! temperature, and different gases respond at different temperatures. One
+//! fixed set-point (the BME680 default of 300 °C) gives a single number; a
+//! scan over several set-points gives a signature that can tell, say,
+//! ethanol from humidity drift.
+//!
+//! The `bme680` driver only knows forced mode, so the scan is done there:
+//! each forced measurement uses the next step of `HEATER_PROFILE`, and the
+//! step index travels in the sensor packet (`heater_step`). Gas readings
+//! are only comparable with others taken at the same step, so the receiver
+//! must never mix them. The same code works on a BME680.
+
+/// One heater set-point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct HeaterStep {
+    /// Hot plate target temperature
+    pub temp_c: u16,
+    /// How long the plate is held there before the gas reading
+    pub duration_ms: u16,
+}
+
+/// The scan, cooler to hotter (the sensor caps the plate at 400 °C)
+pub const HEATER_PROFILE: [HeaterStep; 4] = [
+    HeaterStep { temp_c: 200, duration_ms: 150 },
+    HeaterStep { temp_c: 250, duration_ms: 150 },
+    HeaterStep { temp_c: 300, duration_ms: 150 },
+    HeaterStep { temp_c: 350, duration_ms: 150 },
+];
+
+/// Set-point of a received `heater_step`, if it's one this build knows
+pub fn step(index: u8) -> Option<HeaterStep> {
+    HEATER_PROFILE.get(index as usize).copied()
+}
+
+/// Position in the scan, advanced once per measurement
+#[derive(Debug, Default)]
+pub struct Scanner {
+    index: u8,
+}
+
+impl Scanner {
+    pub const fn new() -> Self {
+        Self { index: 0 }
+    }
+
+    /// The step to measure at now; the following call returns the next one
+    pub fn advance(&mut self) -> (u8, HeaterStep) {
+        let index = self.index;
+        self.index = (index + 1) % HEATER_PROFILE.len() as u8;
+        (index, HEATER_PROFILE[index as usize])
+    }
+}
@@ -0,0 +1,47 @@
+//! Groups of sensor nodes for broadcasts (`groups`, `group <g> ...`)
+//!
+//! `all ...` reaches every node, which is too much once a network has a
+//! greenhouse's worth of nodes and a field's. Each node can be a member of
+//! up to `MAX_GROUP` groups, numbered 1-8 and set with `groups` in its own
+//! shell; Node 2's `group <g> ...` sends a broadcast that only the members
+//! of group g act on. The others drop it like a frame for another address.
+//! Replies and readings asked for with `report` are spread over the
+//! broadcast's window as `all ack` spreads its ACKs (see `broadcast`), so
+//! the members don't all answer at once.
+//!
+//! Membership is saved with the other shell settings.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+pub use crate::protocol::{BROADCAST_EVERYONE, MAX_GROUP};
+
+/// A bit per group, group 1 in bit 0
+static MEMBERSHIP: AtomicU8 = AtomicU8::new(0);
+
+/// The groups this node is in, as they are saved
+pub fn membership() -> u8 {
+    MEMBERSHIP.load(Ordering::Relaxed)
+}
+
+pub fn set_membership(mask: u8) {
+    MEMBERSHIP.store(mask, Ordering::Relaxed);
+}
+
+/// The bit for `group` (1-`MAX_GROUP`), 0 for any other number
+pub fn bit(group: u8) -> u8 {
+    match group {
+        1..=MAX_GROUP => 1 << (group - 1),
+        _ => 0,
+    }
+}
+
+/// Whether a broadcast to `group` is for this node
+pub fn takes(group: u8) -> bool {
+    group == BROADCAST_EVERYONE || membership() & bit(group) != 0
+}
+
+/// The groups this node is in, lowest first
+pub fn groups() -> impl Iterator<Item = u8> {
+    let mask = membership();
+    (1..=MAX_GROUP).filter(move |&group| mask & bit(group) != 0)
+}
@@ -0,0 +1,221 @@
+//! Frequency hopping over a set of channels (`hop`)
+//!
+//! One channel can be busy: a neighbour's LoRaWAN gateway, a wireless
+//! meter reading every few seconds. With `hop <MHz> <MHz> ...` on both
+//! ends, the network moves between those channels instead, `DWELL_MS` on
+//! each, in a pseudo-random order both ends work out from the network ID,
+//! so interference on one channel only costs the readings sent on it.
+//!
+//! The schedule runs on Node 2's clock. Node 2 retunes its module with
+//! `AT+BAND` at the start of every dwell; a sensor node learns Node 2's
+//! clock from the `rx_ms` of its slot grants (see `slots`) and retunes
+//! before each uplink to the channel Node 2 is on. Every `HOME_EVERY`th
+//! dwell is on the home channel, the setup menu's band, which is where a
+//! node that hasn't heard an ACK yet sends, or one that has gone
+//! `LOST_AFTER_MISSES` readings without one: Node 2 rebooted, say, and its
+//! clock started again. It then sends on the home channel until an ACK
+//! comes back.
+//!
+//! A reading sent at the very end of a dwell can miss its ACK as Node 2
+//! moves on; the retry goes out on the next channel. Downlinks from Node
+//! 2's shell go out on the channel of the moment, while a sensor node
+//! stays on the one of its last uplink, so they are best sent right after
+//! a reading or with hopping off. Peer mode (see `pair`) has no Node 2 to
+//! keep time and doesn't hop.
+//!
+//! The channel list is kept in its own record of the config sector, and
+//! both ends need the same one and the same network ID.
+//!
+//! A sensor node built with `low-power` can't hop: its monotonic clock
+//! stops in STOP, so it loses Node 2's schedule within a sleep. `hop` is
+//! refused there, and Node 2 must then be left on the home channel too.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::flash::FlashError;
+use crate::protocol::ConfigError;
+use crate::region;
+
+/// Channels a schedule can hold
+pub const MAX_CHANNELS: usize = 8;
+/// Time on each channel
+pub const DWELL_MS: u32 = 5_000;
+/// Every this many dwells, the home channel
+pub const HOME_EVERY: u32 = 4;
+/// Readings a sensor node lets go unanswered before it stops trusting its
+/// copy of Node 2's clock
+const LOST_AFTER_MISSES: u8 = 2;
+/// Channels the module can tune to, in kHz
+const TUNABLE_KHZ: core::ops::RangeInclusive<u32> = 410_000..=1_020_000;
+
+static CHANNELS: [AtomicU32; MAX_CHANNELS] = [const { AtomicU32::new(0) }; MAX_CHANNELS];
+static COUNT: AtomicU8 = AtomicU8::new(0);
+static HOME_KHZ: AtomicU32 = AtomicU32::new(0);
+static SEED: AtomicU8 = AtomicU8::new(0);
+/// The channel the module was last set to
+static TUNED_KHZ: AtomicU32 = AtomicU32::new(0);
+/// Sensor node: Node 2's clock minus ours, once an ACK has told us
+static OFFSET_MS: AtomicU32 = AtomicU32::new(0);
+static SYNCED: AtomicBool = AtomicBool::new(false);
+static MISSES: AtomicU8 = AtomicU8::new(0);
+/// Set on a sensor node whose clock stops while it sleeps
+static CLOCKLESS: AtomicBool = AtomicBool::new(false);
+
+/// The menu's band and network ID, which the module has just been set to
+pub fn set_network(band_mhz: u16, network_id: u8) {
+    HOME_KHZ.store(u32::from(band_mhz) * 1_000, Ordering::Relaxed);
+    SEED.store(network_id, Ordering::Relaxed);
+    TUNED_KHZ.store(u32::from(band_mhz) * 1_000, Ordering::Relaxed);
+}
+
+/// Sensor node built with `low-power`: SysTick, and so `now_ms`, stops in
+/// STOP, so Node 2's clock can't be followed and hopping stays off. Call
+/// from `init` before `Plan::apply`.
+pub fn set_clockless() {
+    CLOCKLESS.store(true, Ordering::Relaxed);
+}
+
+/// Whether this node can follow a schedule; `hop` is refused if not
+pub fn available() -> bool {
+    !CLOCKLESS.load(Ordering::Relaxed)
+}
+
+/// The channels hopped over, in kHz, empty when hopping is off
+pub fn channels() -> Vec<u32, MAX_CHANNELS> {
+    CHANNELS.iter().take(COUNT.load(Ordering::Relaxed) as usize).map(|c| c.load(Ordering::Relaxed)).collect()
+}
+
+/// Hop over `channels`, in kHz; empty for the home channel only. A list
+/// with a channel the region doesn't allow is refused whole.
+pub fn set_channels(channels: &[u32]) -> Result<(), ConfigError> {
+    if channels.len() > MAX_CHANNELS {
+        return Err(ConfigError::OutOfRange);
+    }
+    channels.iter().try_for_each(|&khz| region::check_khz(khz))?;
+    COUNT.store(0, Ordering::Relaxed);
+    for (slot, &khz) in CHANNELS.iter().zip(channels) {
+        slot.store(khz, Ordering::Relaxed);
+    }
+    COUNT.store(channels.len() as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The channel for the dwell Node 2's clock is in at `gateway_ms`
+pub fn channel_khz(gateway_ms: u32) -> u32 {
+    let home = HOME_KHZ.load(Ordering::Relaxed);
+    let count = COUNT.load(Ordering::Relaxed) as u32;
+    let dwell = gateway_ms / DWELL_MS;
+    if count == 0 || dwell % HOME_EVERY == 0 {
+        return home;
+    }
+    let seed = u32::from(SEED.load(Ordering::Relaxed)).wrapping_mul(0x9E37_79B9);
+    CHANNELS[(mix(seed ^ dwell) % count) as usize].load(Ordering::Relaxed)
+}
+
+/// Milliseconds from `gateway_ms` to the next dwell
+pub fn next_dwell_ms(gateway_ms: u32) -> u32 {
+    DWELL_MS - gateway_ms % DWELL_MS
+}
+
+/// A well-spread 32-bit hash, so neighbouring dwells land far apart
+fn mix(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^ (x >> 16)
+}
+
+/// `Some(khz)` if the module has to be set to `khz`; it is then taken as
+/// done
+pub fn retune(khz: u32) -> Option<u32> {
+    (TUNED_KHZ.swap(khz, Ordering::Relaxed) != khz).then_some(khz)
+}
+
+/// Sensor node: the channel to send on at `now_ms`, our clock
+pub fn uplink_khz(now_ms: u32) -> u32 {
+    if !SYNCED.load(Ordering::Relaxed) {
+        return HOME_KHZ.load(Ordering::Relaxed);
+    }
+    channel_khz(now_ms.wrapping_add(OFFSET_MS.load(Ordering::Relaxed)))
+}
+
+/// Sensor node: Node 2 heard a reading we sent at `sent_at_ms` at `rx_ms`
+/// on its clock
+pub fn sync(rx_ms: u32, sent_at_ms: u32) {
+    OFFSET_MS.store(rx_ms.wrapping_sub(sent_at_ms), Ordering::Relaxed);
+    SYNCED.store(true, Ordering::Relaxed);
+    MISSES.store(0, Ordering::Relaxed);
+}
+
+/// Sensor node: a reading was ACKed
+pub fn note_answered() {
+    MISSES.store(0, Ordering::Relaxed);
+}
+
+/// Sensor node: a reading went unanswered; `true` when that was one too
+/// many and it's back to the home channel
+pub fn note_missed() -> bool {
+    let misses = MISSES.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+    misses >= LOST_AFTER_MISSES && SYNCED.swap(false, Ordering::Relaxed)
+}
+
+/// `915.2` (MHz, up to three decimals) in kHz, if the module can tune to it
+pub fn parse_khz(text: &str) -> Option<u32> {
+    let (mhz, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut khz = mhz.parse::<u32>().ok()?.checked_mul(1_000)?;
+    for (digit, scale) in fraction.bytes().zip([100, 10, 1]) {
+        khz += u32::from(digit - b'0') * scale;
+    }
+    TUNABLE_KHZ.contains(&khz).then_some(khz)
+}
+
+/// The channel list as it is saved
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    channels: Vec<u32, MAX_CHANNELS>,
+}
+
+impl Plan {
+    /// The list in force now
+    pub fn current() -> Self {
+        Self { channels: channels() }
+    }
+
+    /// Put this list in force, less any channel the region doesn't allow;
+    /// call once from `init`
+    pub fn apply(&self) {
+        if !available() && !self.channels.is_empty() {
+            defmt::warn!("Saved hop channels ignored: the clock stops in low-power sleep");
+            return;
+        }
+        let allowed: Vec<u32, MAX_CHANNELS> = self.channels.iter().copied().filter(|&khz| region::allows_khz(khz)).collect();
+        if allowed.len() < self.channels.len() {
+            defmt::warn!("Hop channels outside the region dropped: {} of {}", self.channels.len() - allowed.len(), self.channels.len());
+        }
+        // Every channel left is allowed, and no more fit than before
+        let _ = set_channels(&allowed);
+    }
+}
+
+impl config::Record for Plan {
+    const MAGIC: u32 = 0x484F_5053; // "HOPS"
+    const VERSION: u8 = 1;
+}
+
+/// The saved list, if any
+pub fn load() -> Option<Plan> {
+    config::load_record()
+}
+
+/// Save `plan` as the newest list
+pub fn save(plan: &Plan) -> Result<(), FlashError> {
+    config::save_record(plan)
+}
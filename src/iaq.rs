@@ -0,0 +1,115 @@
+//! Indoor air quality (IAQ) estimate from gas resistance and humidity
+//!
+//! Bosch's BSEC library computes a proper IAQ index, but it is a closed
+//! binary blob. This is the usual open heuristic instead:
+//!
+//! - Gas resistance falls as volatile organic compounds rise. The sensor
+//!   drifts, so readings are judged against a baseline that follows the
+//!   cleanest air seen: it jumps up to any higher reading and otherwise
+//!   sinks slowly towards the current one.
+//! - Humidity also moves the resistance, and is itself part of comfort, so
+//!   it contributes a quarter of the score, best at 40 %RH.
+//!
+//! The combined 0-100 "goodness" score maps onto Bosch's 0-500 scale
+//! (0 excellent, 500 extremely polluted). Each heater step has its own
+//! baseline since resistance depends strongly on plate temperature (see
+//! `gas`). Until every step has seen `BURN_IN_SAMPLES` readings the
+//! baseline isn't trustworthy and no index is produced.
+
+use crate::display::Accent;
+use crate::gas::HEATER_PROFILE;
+
+/// Readings per heater step before the index is reported
+pub const BURN_IN_SAMPLES: u16 = 10;
+
+/// Humidity the score treats as ideal
+const HUMIDITY_IDEAL: f32 = 40.0;
+/// Share of the 0-100 score from humidity; the rest is gas
+const HUMIDITY_WEIGHT: f32 = 25.0;
+const GAS_WEIGHT: f32 = 100.0 - HUMIDITY_WEIGHT;
+/// Fraction of the gap the baseline sinks by per reading, so a polluted
+/// room drags it down over hours rather than minutes
+const BASELINE_DECAY: f32 = 1.0 / 512.0;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Baseline {
+    ohms: f32,
+    samples: u16,
+}
+
+impl Baseline {
+    fn update(&mut self, ohms: f32) {
+        if self.samples == 0 || ohms > self.ohms {
+            self.ohms = ohms;
+        } else {
+            self.ohms -= (self.ohms - ohms) * BASELINE_DECAY;
+        }
+        self.samples = self.samples.saturating_add(1);
+    }
+}
+
+/// Per-step gas baselines, fed every measurement
+pub struct Estimator {
+    baselines: [Baseline; HEATER_PROFILE.len()],
+}
+
+impl Estimator {
+    pub const fn new() -> Self {
+        Self { baselines: [Baseline { ohms: 0.0, samples: 0 }; HEATER_PROFILE.len()] }
+    }
+
+    /// Add a reading taken at `heater_step`; the IAQ index once burnt in
+    pub fn update(&mut self, heater_step: u8, gas_ohms: u32, humidity_pct: f32) -> Option<u16> {
+        let baseline = self.baselines.get_mut(heater_step as usize)?;
+        let gas = gas_ohms as f32;
+        baseline.update(gas);
+        let baseline = *baseline;
+
+        if self.baselines.iter().any(|b| b.samples < BURN_IN_SAMPLES) {
+            return None;
+        }
+
+        let gas_score = if baseline.ohms > 0.0 { (gas / baseline.ohms).min(1.0) * GAS_WEIGHT } else { 0.0 };
+        let score = humidity_score(humidity_pct) + gas_score;
+        Some(((100.0 - score) * 5.0).clamp(0.0, 500.0) as u16)
+    }
+}
+
+impl Default for Estimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Up to `HUMIDITY_WEIGHT` points, falling off linearly either side of ideal
+fn humidity_score(humidity_pct: f32) -> f32 {
+    let offset = humidity_pct - HUMIDITY_IDEAL;
+    let fraction = if offset > 0.0 {
+        (100.0 - HUMIDITY_IDEAL - offset) / (100.0 - HUMIDITY_IDEAL)
+    } else {
+        (HUMIDITY_IDEAL + offset) / HUMIDITY_IDEAL
+    };
+    fraction.clamp(0.0, 1.0) * HUMIDITY_WEIGHT
+}
+
+/// Bosch's wording for an index band, shortened for the display
+pub fn label(iaq: u16) -> &'static str {
+    match iaq {
+        0..=50 => "Excellent",
+        51..=100 => "Good",
+        101..=150 => "Light",
+        151..=200 => "Moderate",
+        201..=250 => "Heavy",
+        251..=350 => "Severe",
+        _ => "Extreme",
+    }
+}
+
+/// Colour for an index on the TFT
+pub fn accent(iaq: u16) -> Accent {
+    match iaq {
+        0..=100 => Accent::Good,
+        101..=200 => Accent::Fair,
+        _ => Accent::Poor,
+    }
+}
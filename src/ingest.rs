@@ -0,0 +1,50 @@
+//! Captured frames fed back into Node 2 (feature `ingest`)
+//!
+//! A parser or state-machine bug seen in the field is easiest to chase
+//! when the exact frames that caused it can be played again. `wk3 replay`
+//! sends a `wk3 pcap` capture back to Node 2 on the debug UART, as the same
+//! `RadioFrame` and `RawLine` records `stream tap` produced. Node 2 turns
+//! each one back into the line the LoRa module would have sent and hands
+//! it to `process_frame`, so it goes through exactly the code a received
+//! frame does: CRC, decode, ACK, gaps, stats and display.
+//!
+//! Records arrive through `uplink`, which takes them off the shell's port.
+//!
+//! Only for the bench: anyone with the serial port can make Node 2 believe
+//! it heard anything, and replayed frames are ACKed over the air.
+
+use core::fmt::Write as _;
+
+use heapless::{String, Vec};
+
+use crate::protocol::stream::{RadioFrame, RawLine, RADIO_FRAME, RAW_LINE};
+use crate::protocol::decode_frame;
+
+/// Longest module line, Node 2's receive buffer
+pub const LINE_LEN: usize = 255;
+
+/// The line the module sent for a `stream tap` record; `None` for any
+/// other record, or one that doesn't fit
+pub fn module_line(record: &[u8]) -> Option<Vec<u8, LINE_LEN>> {
+    let (msg_type, body) = decode_frame(record).ok()?;
+    let mut line = Vec::new();
+    match msg_type {
+        RADIO_FRAME => {
+            let frame: RadioFrame = postcard::from_bytes(body).ok()?;
+            // The sender's address isn't captured; Node 1 is address 1
+            let mut text: String<16> = String::new();
+            write!(text, "+RCV=1,{},", frame.payload.len()).ok()?;
+            line.extend_from_slice(text.as_bytes()).ok()?;
+            line.extend_from_slice(frame.payload).ok()?;
+            text.clear();
+            write!(text, ",{},{}\r\n", frame.rssi, frame.snr).ok()?;
+            line.extend_from_slice(text.as_bytes()).ok()?;
+        }
+        RAW_LINE => {
+            let raw: RawLine = postcard::from_bytes(body).ok()?;
+            line.extend_from_slice(raw.line).ok()?;
+        }
+        _ => return None,
+    }
+    Some(line)
+}
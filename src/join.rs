@@ -0,0 +1,100 @@
+//! Joining the network without a hand-set address
+//!
+//! Every node used to need its `AT+ADDRESS` set from the setup menu before
+//! it could be deployed, which meant keeping a list of who had which. A node
+//! whose address is 0 ("Join" in the menu, and the firmware default now)
+//! instead sends a `JoinRequest` every `RETRY_MS` or so, carrying its MCU's
+//! unique ID, and sends no readings. Node 2 picks it an address and answers
+//! with a `JoinAccept` to the broadcast address, adding its network ID and
+//! transmit interval; the node whose ID it names saves those like a menu
+//! change, and from then on is an ordinary sensor node.
+//!
+//! Node 2 remembers the addresses it has handed out (`Joined`, kept in the
+//! config sector), so a node that loses its settings and joins again gets
+//! its old address back. New ones get the lowest address that is neither
+//! remembered nor in the peer table, skipping Node 2's own. The request and
+//! the answer travel on whatever network the node is on, so a new node has
+//! to start on Node 2's; the network ID in the answer only matters once
+//! Node 2's has been changed since.
+
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::flash::{self, FlashError};
+
+/// Time between join requests; each node adds up to `SPREAD_MS` of its own
+/// so nodes powered up together don't keep colliding
+pub const RETRY_MS: u32 = 10_000;
+const SPREAD_MS: u32 = 2_000;
+
+/// Addresses handed out, as the setup menu allows them
+const ADDRESSES: core::ops::RangeInclusive<u8> = 1..=16;
+/// Nodes remembered; the one that joined longest ago makes way
+const MAX_JOINED: usize = 8;
+
+/// Where the STM32F4's 96-bit unique device ID is
+const UID_BASE: u32 = 0x1FFF_7A10;
+
+/// This MCU's unique ID folded to 32 bits, the `uid` of its requests
+pub fn uid() -> u32 {
+    let [a, b, c] = [0, 4, 8].map(|offset| flash::read_word(UID_BASE + offset));
+    a ^ b.rotate_left(11) ^ c.rotate_left(22)
+}
+
+/// Milliseconds until the next request from the node with `uid`
+pub fn retry_ms(uid: u32) -> u32 {
+    RETRY_MS + uid % SPREAD_MS
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    uid: u32,
+    address: u8,
+}
+
+/// Node 2: the address each joined node was given, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Joined {
+    entries: Vec<Entry, MAX_JOINED>,
+}
+
+impl Joined {
+    pub const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    /// The address `uid` was given before, if it is remembered
+    pub fn get(&self, uid: u32) -> Option<u8> {
+        self.entries.iter().find(|e| e.uid == uid).map(|e| e.address)
+    }
+
+    /// The address for `uid`: the one it had, or else the lowest one not
+    /// remembered for another node and not `taken`; `None` if there is none
+    pub fn assign(&mut self, uid: u32, taken: impl Fn(u8) -> bool) -> Option<u8> {
+        if let Some(address) = self.get(uid) {
+            return Some(address);
+        }
+        let address = ADDRESSES.clone().find(|&a| !taken(a) && self.entries.iter().all(|e| e.address != a))?;
+        if self.entries.is_full() {
+            self.entries.remove(0);
+        }
+        let _ = self.entries.push(Entry { uid, address });
+        Some(address)
+    }
+}
+
+impl config::Record for Joined {
+    const MAGIC: u32 = 0x4A4F_494E; // "JOIN"
+    const VERSION: u8 = 1;
+}
+
+/// The saved table, if any
+pub fn load() -> Option<Joined> {
+    config::load_record()
+}
+
+/// Save `joined` as the newest table
+pub fn save(joined: &Joined) -> Result<(), FlashError> {
+    config::save_record(joined)
+}
@@ -0,0 +1,82 @@
+#![no_std]
+
+//! Shared firmware support for Node 1 (sensor) and Node 2 (gateway).
+//!
+//! Both binaries link this library, so anything that must behave the same
+//! on each end of the link lives here instead of being copied between
+//! `main.rs` and `bin/node2.rs`.
+
+pub mod adr;
+pub mod airtime;
+pub mod backlog;
+pub mod backoff;
+pub mod backup;
+pub mod battery;
+pub mod broadcast;
+pub mod brownout;
+pub mod calibration;
+pub mod command;
+pub mod cpu;
+pub mod config;
+pub mod crash;
+pub mod delta;
+pub mod dirty;
+pub mod eeprom;
+pub mod export;
+pub mod factory;
+pub mod filter;
+pub mod display;
+pub mod ds18b20;
+pub mod flash;
+pub mod format;
+pub mod gas;
+pub mod gaps;
+pub mod group;
+pub mod hopping;
+pub mod iaq;
+#[cfg(feature = "ingest")]
+pub mod ingest;
+pub mod join;
+pub mod linkstats;
+pub mod log;
+pub mod loopback;
+pub mod menu;
+pub mod metrics;
+pub mod module;
+pub mod motion;
+pub mod mqtt;
+pub mod nmea;
+pub mod onewire;
+pub mod pair;
+pub mod panel;
+pub mod peers;
+pub mod ping;
+pub mod power;
+pub mod powertrace;
+pub mod preset;
+pub use wk3_protocol as protocol;
+pub mod raw;
+pub mod region;
+pub mod relay;
+pub mod reset;
+pub mod scan;
+#[cfg(feature = "sd-log")]
+pub mod sdlog;
+pub mod senders;
+pub mod sensor;
+pub mod shell;
+pub mod slots;
+pub mod soil;
+pub mod stats;
+#[cfg(feature = "littlefs")]
+pub mod storage;
+pub mod summary;
+pub mod time;
+pub mod tunnel;
+#[cfg(feature = "st7789")]
+pub mod tft;
+pub mod ui;
+pub mod uplink;
+#[cfg(feature = "usb-json")]
+pub mod usbjson;
+pub mod watchdog;
@@ -0,0 +1,113 @@
+//! Each end's count of how the link is going, swapped every few minutes
+//!
+//! Node 1 knows how many frames it sent and how many were ACKed, but not
+//! what Node 2 made of them: a frame lost in the air and one that arrived
+//! with a bad CRC look the same from the sending end. Node 2 knows what
+//! arrived damaged but not what never arrived. Every `PERIOD_MIN` minutes
+//! each end sends its counters as a `LinkStats` frame, Node 1 to Node 2
+//! and Node 2 to every node, and each logs the other's and shows it
+//! alongside its own with `linkstats` in the shell. Node 1 keeps Node 2's
+//! last report here; Node 2 keeps each sensor node's with its entry in the
+//! peer table (see peers.rs) and also shows them on its REMOTE page.
+//!
+//! Counters run from boot and wrap. Not ACKed and not resent: one that is
+//! lost is replaced by the next.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::interrupt::{self, Mutex};
+
+use crate::protocol::LinkStats;
+use crate::time;
+
+/// Minutes between reports
+pub const PERIOD_MIN: u32 = 5;
+pub const PERIOD_S: u32 = PERIOD_MIN * 60;
+
+static FRAMES_SENT: AtomicU32 = AtomicU32::new(0);
+static ACKS_RECEIVED: AtomicU32 = AtomicU32::new(0);
+static RETRIES: AtomicU32 = AtomicU32::new(0);
+static CRC_FAILURES: AtomicU32 = AtomicU32::new(0);
+static UART_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+/// A report from the other end
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Heard {
+    /// Node id in the frame header
+    pub node: u8,
+    pub stats: LinkStats,
+    /// When it arrived, in ms since boot
+    pub at_ms: u32,
+}
+
+impl Heard {
+    /// Seconds since it arrived
+    pub fn age_s(&self, now_ms: u32) -> u32 {
+        time::elapsed_ms(now_ms, self.at_ms) / 1_000
+    }
+}
+
+static HEARD: Mutex<Cell<Option<Heard>>> = Mutex::new(Cell::new(None));
+
+/// A frame went to the module for sending (any priority)
+pub fn note_sent() {
+    FRAMES_SENT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// An ACK, or another answer, came back for one
+pub fn note_ack() {
+    ACKS_RECEIVED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// One went unanswered or was NACKed and is being tried again
+pub fn note_retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A frame arrived whose CRC didn't match
+pub fn note_crc_failure() {
+    CRC_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The module's UART overran or saw noise or a framing error
+pub fn note_uart_error() {
+    UART_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// This end's counters, `uptime_s` into the run
+pub fn ours(uptime_s: u32) -> LinkStats {
+    LinkStats {
+        uptime_s,
+        frames_sent: FRAMES_SENT.load(Ordering::Relaxed),
+        acks_received: ACKS_RECEIVED.load(Ordering::Relaxed),
+        retries: RETRIES.load(Ordering::Relaxed),
+        crc_failures: CRC_FAILURES.load(Ordering::Relaxed),
+        uart_errors: UART_ERRORS.load(Ordering::Relaxed),
+    }
+}
+
+/// Node `node`'s report arrived at `now_ms`: log it, for the caller to keep
+pub fn heard(node: u8, stats: LinkStats, now_ms: u32) -> Heard {
+    defmt::info!(
+        "N{} link: sent {}, ACKed {}, retries {}, CRC fail {}, UART errors {} ({} s up)",
+        node,
+        stats.frames_sent,
+        stats.acks_received,
+        stats.retries,
+        stats.crc_failures,
+        stats.uart_errors,
+        stats.uptime_s
+    );
+    Heard { node, stats, at_ms: now_ms }
+}
+
+/// Node 1: keep Node 2's report for `last_heard`
+pub fn keep(heard: Heard) {
+    interrupt::free(|cs| HEARD.borrow(cs).set(Some(heard)));
+}
+
+/// Node 1: Node 2's last report, if one has arrived since boot
+pub fn last_heard() -> Option<Heard> {
+    interrupt::free(|cs| HEARD.borrow(cs).get())
+}
@@ -0,0 +1,79 @@
+//! Runtime log verbosity
+//!
+//! `DEFMT_LOG` (see `.cargo/config.toml`) decides which log statements are
+//! compiled in; this module decides which of those are actually emitted.
+//! Hot paths such as the UART ISR log through the `debug!`/`trace!` macros
+//! below, so they cost one atomic load until someone turns them on from the
+//! debug shell (`log debug`, `log trace`).
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Verbosity threshold, most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|l| l.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The level with discriminant `index`, as saved in `config::Settings`
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Change the runtime threshold (any priority)
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    Level::ALL[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+/// Whether messages at `level` should currently be emitted
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// `defmt::debug!`, emitted only while the runtime level is `debug` or above
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            defmt::debug!($($arg)+);
+        }
+    };
+}
+
+/// `defmt::trace!`, emitted only while the runtime level is `trace`
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Level::Trace) {
+            defmt::trace!($($arg)+);
+        }
+    };
+}
@@ -0,0 +1,329 @@
+//! The LoRa module replaced by an in-memory loopback (feature `loopback`)
+//!
+//! Exercising ARQ, duplicate handling and the link display otherwise
+//! takes two boards, two modules and a gateway in range. Built with
+//! `loopback`, Node 1 talks to a `Radio` instead of UART4: it answers AT
+//! commands like the RYLR998 does, and every frame sent with `AT+SEND`
+//! reaches a stand-in for Node 2 that checks its CRC, tracks its sequence
+//! number and answers it the way Node 2 would, an ACK for a reading or a
+//! stored one and a `Pong` for a ping. The answer lands `TURNAROUND_MS`
+//! later as a `+RCV=` line, through the same UART4 interrupt and parser a
+//! real one goes through, from the relay Node 1 sent the frame to if it
+//! goes by one (`via`, `route`), else from `GATEWAY`.
+//!
+//! `loopback <loss%> [corrupt%]` in the shell loses or corrupts that share
+//! of frames, the same odds each way: a lost reading or ACK brings the
+//! retry, a lost ACK a duplicate at the stand-in, and a flipped bit a CRC
+//! failure there, or an ACK for the wrong sequence number on the way back.
+//! `loopback` shows what has happened so far.
+//!
+//! The module's settings are kept as it was last given them (see `module`),
+//! so boot queries them rather than writing them again. Not with
+//! `low-power`: STOP mode stops the clock the answers wait on.
+
+use core::convert::Infallible;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use heapless::{Deque, String, Vec};
+
+use crate::backoff::Rng;
+use crate::module::{Applied, Item};
+use crate::protocol::{
+    decode_frame, encode_ack, encode_frame, frame_node, frame_type, AckPacket, Ping, SensorDataPacket, StoredReading,
+    MSG_TYPE_ACK, MSG_TYPE_PING, MSG_TYPE_PONG, MSG_TYPE_SENSOR_DATA, MSG_TYPE_STORED,
+};
+use crate::relay::{self, GATEWAY};
+use crate::stats::{RxError, RxStats, SeqEvent};
+use crate::time;
+
+#[cfg(all(feature = "loopback", feature = "low-power"))]
+compile_error!("`loopback` doesn't work with `low-power`: STOP mode stops the clock the answers wait on");
+
+/// From a frame going out to its answer coming back: about an SF7 frame,
+/// the gateway's turnaround and the ACK
+pub const TURNAROUND_MS: u32 = 150;
+/// Longest line either way: `AT+SEND=`, address, length and 240 bytes
+const LINE_LEN: usize = 272;
+/// Module output not yet read by the UART4 handler
+const RX_LEN: usize = 512;
+/// Answers on their way back at once
+const IN_FLIGHT: usize = 4;
+/// How the stand-in is heard
+const RSSI: i16 = -40;
+const SNR: i16 = 10;
+
+static LOSS_PCT: AtomicU8 = AtomicU8::new(0);
+static CORRUPT_PCT: AtomicU8 = AtomicU8::new(0);
+
+static FRAMES: AtomicU32 = AtomicU32::new(0);
+static LOST: AtomicU32 = AtomicU32::new(0);
+static CORRUPTED: AtomicU32 = AtomicU32::new(0);
+static READINGS: AtomicU32 = AtomicU32::new(0);
+static DUPLICATES: AtomicU32 = AtomicU32::new(0);
+static CRC_FAILURES: AtomicU32 = AtomicU32::new(0);
+
+/// Whether this build has the loopback in place of the module
+pub fn enabled() -> bool {
+    cfg!(feature = "loopback")
+}
+
+/// Lose `loss_pct` and corrupt `corrupt_pct` of the frames each way, both
+/// capped at 100
+pub fn set_faults(loss_pct: u8, corrupt_pct: u8) {
+    LOSS_PCT.store(loss_pct.min(100), Ordering::Relaxed);
+    CORRUPT_PCT.store(corrupt_pct.min(100), Ordering::Relaxed);
+}
+
+/// Loss and corruption in percent
+pub fn faults() -> (u8, u8) {
+    (LOSS_PCT.load(Ordering::Relaxed), CORRUPT_PCT.load(Ordering::Relaxed))
+}
+
+/// What the loopback has carried since boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Stats {
+    /// Frames either way
+    pub frames: u32,
+    pub lost: u32,
+    pub corrupted: u32,
+    /// Readings the stand-in took, duplicates included
+    pub readings: u32,
+    pub duplicates: u32,
+    pub crc_failures: u32,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        frames: FRAMES.load(Ordering::Relaxed),
+        lost: LOST.load(Ordering::Relaxed),
+        corrupted: CORRUPTED.load(Ordering::Relaxed),
+        readings: READINGS.load(Ordering::Relaxed),
+        duplicates: DUPLICATES.load(Ordering::Relaxed),
+        crc_failures: CRC_FAILURES.load(Ordering::Relaxed),
+    }
+}
+
+type Line = Vec<u8, LINE_LEN>;
+
+/// An answer on its way back
+struct Landing {
+    at_ms: u32,
+    line: Line,
+}
+
+/// The module and, behind it, Node 2; written and read like UART4
+pub struct Radio {
+    clock: fn() -> u32,
+    rng: Rng,
+    /// Written since the last complete command
+    line: Line,
+    rx: Deque<u8, RX_LEN>,
+    air: Deque<Landing, IN_FLIGHT>,
+    /// `AT+<item>=` values, in `Item::ALL` order
+    settings: [String<12>; Item::ALL.len()],
+    gateway: RxStats,
+}
+
+impl Radio {
+    /// A module that was last given `kept`; `clock` reads milliseconds
+    /// since boot, and isn't called until a frame goes out, so `init` can
+    /// talk AT to it before the monotonic runs
+    pub fn new(rng: Rng, clock: fn() -> u32, kept: Option<&Applied>) -> Self {
+        let mut settings: [String<12>; Item::ALL.len()] = Default::default();
+        if let Some(kept) = kept {
+            for (setting, item) in settings.iter_mut().zip(Item::ALL) {
+                let _ = setting.push_str(kept.value(item));
+            }
+        }
+        Self { clock, rng, line: Vec::new(), rx: Deque::new(), air: Deque::new(), settings, gateway: RxStats::new() }
+    }
+
+    /// One byte to the module
+    pub fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+        if self.line.push(byte).is_err() {
+            defmt::warn!("Loopback: command too long, dropped");
+            self.line.clear();
+        }
+        if self.complete() {
+            let line = core::mem::take(&mut self.line);
+            self.command(&line);
+        }
+        Ok(())
+    }
+
+    /// One byte from the module, if it has said anything
+    pub fn read(&mut self) -> nb::Result<u8, Infallible> {
+        self.rx.pop_front().ok_or(nb::Error::WouldBlock)
+    }
+
+    /// Hand over the answers due by now; `true` if there is anything to
+    /// read, for the caller to pend UART4
+    pub fn land(&mut self) -> bool {
+        let now = (self.clock)();
+        while self.air.front().is_some_and(|landing| time::deadline_passed(now, landing.at_ms)) {
+            if let Some(landing) = self.air.pop_front() {
+                self.say(&landing.line);
+            }
+        }
+        !self.rx.is_empty()
+    }
+
+    /// Whether `line` holds a whole command; `AT+SEND`'s payload may hold
+    /// a line end of its own, so that one goes by its length
+    fn complete(&self) -> bool {
+        if !self.line.ends_with(b"\r\n") {
+            return false;
+        }
+        match send_header(&self.line) {
+            Some((start, _, len)) => self.line.len() >= start + len + 2,
+            None => !self.line.starts_with(b"AT+SEND="),
+        }
+    }
+
+    fn command(&mut self, line: &[u8]) {
+        let text = line.strip_suffix(b"\r\n").unwrap_or(line);
+        if let Some((start, to, len)) = send_header(line) {
+            self.say(b"+OK\r\n");
+            self.transmit(to, &line[start..start + len]);
+            return;
+        }
+        let Ok(text) = core::str::from_utf8(text) else {
+            self.say(b"+ERR=1\r\n");
+            return;
+        };
+        let Some(setting) = text.strip_prefix("AT+") else {
+            self.say(if text == "AT" { b"+OK\r\n" } else { b"+ERR=1\r\n" });
+            return;
+        };
+        let (name, value) = setting.split_once('=').unwrap_or((setting, ""));
+        let query = name.strip_suffix('?');
+        let Some(index) = Item::ALL.iter().position(|item| query.unwrap_or(name) == item.name()) else {
+            // `AT+MODE` and the like: nothing to keep
+            self.say(b"+OK\r\n");
+            return;
+        };
+        let kept = &mut self.settings[index];
+        let mut reply: String<32> = String::new();
+        match query {
+            None => {
+                kept.clear();
+                let _ = kept.push_str(value);
+                let _ = reply.push_str("+OK");
+            }
+            // Never given one this boot or before: what the module says to
+            // a query it can't answer
+            Some(_) if kept.is_empty() => {
+                let _ = reply.push_str("+ERR=4");
+            }
+            Some(name) => {
+                let _ = write!(reply, "+{}={}", name, kept);
+            }
+        }
+        let _ = reply.push_str("\r\n");
+        self.say(reply.as_bytes());
+    }
+
+    /// A frame goes out to the module at `to`
+    fn transmit(&mut self, to: u16, payload: &[u8]) {
+        let Some(payload) = self.carry(payload) else { return };
+        let mut buf = [0u8; 32];
+        let node = frame_node(&payload).unwrap_or(0);
+        let answer = match decode_frame(&payload) {
+            Err(_) => {
+                CRC_FAILURES.fetch_add(1, Ordering::Relaxed);
+                self.gateway.record_error(RxError::Crc, (self.clock)());
+                defmt::warn!("Loopback: gateway CRC failure");
+                return;
+            }
+            Ok((MSG_TYPE_SENSOR_DATA, body)) => {
+                let Ok(data) = postcard::from_bytes::<SensorDataPacket>(body) else { return };
+                READINGS.fetch_add(1, Ordering::Relaxed);
+                if self.gateway.record(data.seq_num, (self.clock)()) == SeqEvent::Duplicate {
+                    DUPLICATES.fetch_add(1, Ordering::Relaxed);
+                    defmt::warn!("Loopback: gateway got #{} again", data.seq_num);
+                }
+                encode_ack(&AckPacket { msg_type: MSG_TYPE_ACK, seq_num: data.seq_num, node }, None, &mut buf)
+            }
+            // Backfills are ACKed but kept out of the sequence, as on Node 2
+            Ok((MSG_TYPE_STORED, body)) => {
+                let Ok(stored) = postcard::from_bytes::<StoredReading>(body) else { return };
+                encode_ack(&AckPacket { msg_type: MSG_TYPE_ACK, seq_num: stored.reading.seq_num, node }, None, &mut buf)
+            }
+            Ok((MSG_TYPE_PING, body)) => {
+                let Ok(ping) = postcard::from_bytes::<Ping>(body) else { return };
+                encode_frame(GATEWAY as u8, MSG_TYPE_PONG, &ping.answer(RSSI, SNR), &mut buf)
+            }
+            Ok((msg_type, _)) => {
+                defmt::debug!("Loopback: type {} not answered", msg_type);
+                return;
+            }
+        };
+        let Ok(answer) = answer else { return };
+        let Some(answer) = self.carry(answer) else { return };
+        defmt::debug!("Loopback: {} answered", frame_type(&answer));
+
+        let mut line = Line::new();
+        let mut text: String<24> = String::new();
+        let _ = write!(text, "+RCV={},{},", answered_from(to), answer.len());
+        let _ = line.extend_from_slice(text.as_bytes());
+        let _ = line.extend_from_slice(&answer);
+        text.clear();
+        let _ = write!(text, ",{},{}\r\n", RSSI, SNR);
+        let _ = line.extend_from_slice(text.as_bytes());
+        let at_ms = (self.clock)().wrapping_add(TURNAROUND_MS);
+        if self.air.push_back(Landing { at_ms, line }).is_err() {
+            defmt::warn!("Loopback: too many answers in flight, one dropped");
+        }
+    }
+
+    /// `payload` over the air: lost, or maybe with a bit flipped
+    fn carry(&mut self, payload: &[u8]) -> Option<Line> {
+        FRAMES.fetch_add(1, Ordering::Relaxed);
+        let (loss_pct, corrupt_pct) = faults();
+        if self.rng.below(100) < u32::from(loss_pct) {
+            LOST.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let mut frame = Line::from_slice(payload).ok()?;
+        if !frame.is_empty() && self.rng.below(100) < u32::from(corrupt_pct) {
+            let at = self.rng.below(frame.len() as u32) as usize;
+            frame[at] ^= 1 << self.rng.below(8);
+            CORRUPTED.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(frame)
+    }
+
+    /// The module says `bytes`; dropped past a full buffer, as an overrun
+    /// would
+    fn say(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.rx.push_back(byte).is_err() {
+                defmt::warn!("Loopback: UART4 not read, output dropped");
+                return;
+            }
+        }
+    }
+}
+
+/// The address the answer to a frame sent to `to` comes from: Node 1's
+/// relay, which passes the gateway's answer down, if `to` is it
+fn answered_from(to: u16) -> u16 {
+    let first_hop = relay::route().first().map_or(relay::upstream(), |&relay| relay.into());
+    if to == first_hop {
+        to
+    } else {
+        GATEWAY
+    }
+}
+
+/// `AT+SEND=<to>,<len>,` as where the payload starts, `to` and `len`
+fn send_header(line: &[u8]) -> Option<(usize, u16, usize)> {
+    let rest = line.strip_prefix(b"AT+SEND=")?;
+    let mut fields = rest.splitn(3, |&b| b == b',');
+    let to = core::str::from_utf8(fields.next()?).ok()?;
+    let len = core::str::from_utf8(fields.next()?).ok()?;
+    fields.next()?;
+    let start = b"AT+SEND=".len() + to.len() + len.len() + 2;
+    Some((start, to.parse().ok()?, len.parse().ok()?))
+}
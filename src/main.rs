@@ -1,14 +1,36 @@
 #![no_std]
 #![no_main]
 
-use panic_probe as _;
+use wk3_binary_protocol as _; // panic handler (crash.rs)
 use defmt_rtt as _;
 
-#[rtic::app(device = stm32f4xx_hal::pac, peripherals = true)]
+// Prefix every defmt log line with milliseconds since boot
+defmt::timestamp!("{=u64:ms}", app::monotonics::now().duration_since_epoch().to_millis());
+
+// `defmt::error!`/`warn!`/`info!` that also queues the line for Node 2 while
+// `tunnel` forwards that level (see tunnel.rs). The format string goes to
+// `core::fmt` as well, so plain `{}` only, and arguments are evaluated twice.
+macro_rules! remote {
+    (error, $($arg:tt)+) => {{
+        defmt::error!($($arg)+);
+        tunnel::push(Level::Error, uptime_s(), format_args!($($arg)+));
+    }};
+    (warn, $($arg:tt)+) => {{
+        defmt::warn!($($arg)+);
+        tunnel::push(Level::Warn, uptime_s(), format_args!($($arg)+));
+    }};
+    (info, $($arg:tt)+) => {{
+        defmt::info!($($arg)+);
+        tunnel::push(Level::Info, uptime_s(), format_args!($($arg)+));
+    }};
+}
+
+#[rtic::app(device = stm32f4xx_hal::pac, peripherals = true, dispatchers = [SAI1, SAI2])]
 mod app {
     use stm32f4xx_hal::{
         prelude::*,
-        gpio::{Output, Pin},
+        adc::{config::{AdcConfig, SampleTime}, Adc},
+        gpio::{Analog, Edge, ExtiPin, Input, Output, Pin},
         pac,
         timer::{CounterHz, Event, Delay},
         serial::{Serial, Config as SerialConfig, Event as SerialEvent},
@@ -17,10 +39,8 @@ mod app {
     };
 
     use shared_bus::CortexMMutex;
-    use ssd1306::{prelude::*, Ssd1306, mode::BufferedGraphicsMode};
-    use display_interface_i2c::I2CInterface;
     use embedded_graphics::{
-        mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
+        mono_font::MonoTextStyleBuilder,
         pixelcolor::BinaryColor,
         prelude::*,
         text::Text,
@@ -28,44 +48,185 @@ mod app {
     use heapless::{String, Vec};
     use core::fmt::Write as _;
 
-    use sht3x::{SHT3x, Repeatability, Address as ShtAddress};
-    use bme680::{Bme680, I2CAddress, IIRFilterSize, OversamplingSetting, SettingsBuilder, PowerMode};
-    use core::time::Duration;
+    use bme680::{Bme680, I2CAddress};
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use wk3_binary_protocol::adr;
+    use wk3_binary_protocol::airtime::{self, Accountant, Admit, LoraParams};
+    use wk3_binary_protocol::backlog::Backlog;
+    use wk3_binary_protocol::backoff::{self, Rng};
+    use wk3_binary_protocol::battery;
+    use wk3_binary_protocol::brownout;
+    use wk3_binary_protocol::calibration::{self, Calibration};
+    use wk3_binary_protocol::cpu::{self, IsrStats, LoadMeter};
+    use wk3_binary_protocol::config::{self, NodeConfig, Settings};
+    use wk3_binary_protocol::broadcast;
+    use wk3_binary_protocol::command::{self, Refusal};
+    use wk3_binary_protocol::crash;
+    use wk3_binary_protocol::display::{self, Panel};
+    use wk3_binary_protocol::eeprom::{self, Eeprom, Kind, Lifetime};
+    use wk3_binary_protocol::factory::{self, Hold};
+    use wk3_binary_protocol::group;
+    use wk3_binary_protocol::hopping;
+    use wk3_binary_protocol::linkstats;
+    use wk3_binary_protocol::pair::{self, Partner};
+    use wk3_binary_protocol::ping::{self, Echo, Pinger};
+    use wk3_binary_protocol::export::{self, ExportBegin, Item, Progress};
+    #[cfg(feature = "ds18b20")]
+    use wk3_binary_protocol::ds18b20::{self, Probes};
+    #[cfg(feature = "ds18b20")]
+    use wk3_binary_protocol::onewire::OneWire;
+    use wk3_binary_protocol::{debug, trace};
+    use wk3_binary_protocol::delta::{self, Reason};
+    use wk3_binary_protocol::filter::Smoother;
+    use wk3_binary_protocol::flash::FlashError;
+    use wk3_binary_protocol::gas;
+    use wk3_binary_protocol::iaq;
+    use wk3_binary_protocol::join;
+    use wk3_binary_protocol::format::{Humidity, Ohms, Temp};
+    use wk3_binary_protocol::menu::{self, Field, Menu, Outcome};
+    use wk3_binary_protocol::metrics;
+    use wk3_binary_protocol::module;
+    #[cfg(feature = "loopback")]
+    use wk3_binary_protocol::loopback;
+    use wk3_binary_protocol::motion;
+    use wk3_binary_protocol::nmea::{self, Fix};
+    use wk3_binary_protocol::panel;
+    use wk3_binary_protocol::power::{self, Board, DutyCycle, PowerProfile, Wake};
+    use wk3_binary_protocol::powertrace::{self, Phase};
+    use wk3_binary_protocol::preset;
+    use wk3_binary_protocol::raw;
+    use wk3_binary_protocol::region;
+    use wk3_binary_protocol::relay;
+    use wk3_binary_protocol::slots;
+    use wk3_binary_protocol::reset::ResetCause;
+    use wk3_binary_protocol::sensor::{Bme680Sensor, EnvironmentalSensor, Measurement, SensorFault, Sht31};
+    #[cfg(feature = "scd40")]
+    use wk3_binary_protocol::sensor::Scd40;
+    use wk3_binary_protocol::shell::{self, Command, LineBuffer};
+    use wk3_binary_protocol::soil::{self, SoilCalibration};
+    #[cfg(feature = "littlefs")]
+    use wk3_binary_protocol::storage::{self, W25q};
+    use wk3_binary_protocol::summary::{self, Accumulator};
+    use wk3_binary_protocol::time::{self, Mono};
+    use wk3_binary_protocol::tunnel;
+    use wk3_binary_protocol::log::Level;
+    use systick_monotonic::ExtU64;
+    use wk3_binary_protocol::watchdog::{self, Checkpoint, Supervisor};
 
     // --- Configuration Constants ---
     const NODE_ID: &str = "N1";              // Node identifier for display
-    const AUTO_TX_INTERVAL_SECS: u32 = 10;  // Auto-transmit every 10 seconds
-    const NETWORK_ID: u8 = 18;               // LoRa network ID
-    const LORA_FREQ: u32 = 915;              // LoRa frequency in MHz (915 for US)
-
-    // --- Binary Protocol Data Structures ---
-    use serde::{Serialize, Deserialize};
-
-    /// Sensor data packet for binary transmission
-    /// Size: ~12 bytes (postcard serialized) vs 24 bytes (text format)
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-    pub struct SensorDataPacket {
-        pub seq_num: u16,           // Sequence number for duplicate detection
-        pub temperature: i16,       // Temperature in centidegrees (e.g., 2710 = 27.1°C)
-        pub humidity: u16,          // Humidity in basis points (e.g., 5600 = 56.0%)
-        pub gas_resistance: u32,    // Gas resistance in ohms
-    }
-
-    /// ACK/NACK packet for acknowledgment
-    /// Size: 3 bytes (1 byte msg_type + 2 bytes seq_num)
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-    pub struct AckPacket {
-        pub msg_type: u8,   // 1 = ACK (success), 2 = NACK (CRC failure)
-        pub seq_num: u16,   // Which packet we're acknowledging
-    }
+    const BUTTON_DEBOUNCE_MS: u32 = 200;     // Ignore contact bounce after a press
+    const DOUBLE_PRESS_MS: u32 = 500;        // A second short press within this pings (see ping.rs)
+    const COUNTDOWN_STEP_MS: u64 = 1_000;    // Factory reset countdown redraw (see factory.rs)
+    #[cfg(feature = "loopback")]
+    const LOOPBACK_POLL_MS: u64 = 20;        // Loopback answers land this often (see loopback.rs)
+    const WATCHDOG_TIMEOUT_MS: u32 = 4_000;  // IWDG timeout (4 missed 1 Hz ticks)
+    const HEALTH_INTERVAL_SECS: u64 = 30;    // Health packet period (< cpu::LoadMeter's 51s wrap)
+    const SUMMARY_INTERVAL_SECS: u64 = summary::PERIOD_S as u64;
+    const SUMMARY_RETRY_SECS: u64 = 2;       // Summary wait while a packet awaits its ACK
+    const LINK_INTERVAL_SECS: u64 = linkstats::PERIOD_S as u64;
+    const GNSS_STALE_MS: u32 = 5_000;        // Older fixes aren't sent (GGA comes at 1 Hz)
+    const EVENT_RETRY_MS: u64 = 200;         // Event wait while a packet awaits its ACK
+    const REPLAY_RETRY_MS: u64 = 200;        // Backlog replay wait while a packet awaits its ACK
+    const LIFETIME_SAVE_SECS: u64 = 3_600;   // EEPROM lifetime counter period
+    const EXPORT_BATCH: usize = 16;          // Records per export run, ~60 ms at 115200 baud
+    const LOW_POWER: bool = cfg!(feature = "low-power");  // STOP between samples (see power.rs)
+    const RADIO_WAIT_MS: u64 = power::RADIO_WAKE_MS as u64;  // Sender retry while the radio wakes
+    const REBOOT_DELAY_SECS: u64 = 2;        // Remote reboot waits for its result frame to go out
+    const TUNNEL_INTERVAL_SECS: u64 = 10;    // Log batch period (see tunnel.rs)
+
+    // Pins set up in `init`; everything else is parked by `power::gate_unused`
+    const BOARD: Board = Board {
+        pins: [
+            // PA0/1/4 ADC, PA2/3 USART2, PA5 LED, PA8 power trace, PA9/10 USART1
+            0b0000_0110_0011_1111 | if cfg!(feature = "power-trace") { 1 << 8 } else { 0 },
+            // PB4 PIR, PB5 DS18B20, PB8/9 I2C1, PB12-15 SPI2 flash
+            0b0000_0011_0001_0000 | if cfg!(feature = "ds18b20") { 1 << 5 } else { 0 }
+                | if cfg!(feature = "littlefs") { 0b1111 << 12 } else { 0 },
+            // PC10/11 UART4, PC13 button
+            0b0010_1100_0000_0000,
+        ],
+        wake: &[Wake::Tim2, Wake::Usart1, Wake::Usart2, Wake::Uart4],
+    };
 
-    // Message type constants
-    const MSG_TYPE_ACK: u8 = 1;
-    const MSG_TYPE_NACK: u8 = 2;
+    // Per-task run times; every task body opens one of these first
+    static TIM2_STATS: IsrStats = IsrStats::new("tim2");
+    static UART4_STATS: IsrStats = IsrStats::new("uart4");
+    static SHELL_STATS: IsrStats = IsrStats::new("debug_shell");
+    static HEALTH_STATS: IsrStats = IsrStats::new("health_report");
+    static BUTTON_STATS: IsrStats = IsrStats::new("button");
+    static CONFIG_STATS: IsrStats = IsrStats::new("apply_config");
+    static ANNOUNCE_STATS: IsrStats = IsrStats::new("announce");
+    static CALIBRATION_STATS: IsrStats = IsrStats::new("apply_calibration");
+    static DRAW_STATS: IsrStats = IsrStats::new("draw_status");
+    static FAULT_STATS: IsrStats = IsrStats::new("report_fault");
+    static SUMMARY_STATS: IsrStats = IsrStats::new("summary_report");
+    static GNSS_STATS: IsrStats = IsrStats::new("gnss");
+    static SOIL_STATS: IsrStats = IsrStats::new("save_soil");
+    static PIR_STATS: IsrStats = IsrStats::new("pir");
+    static EVENT_STATS: IsrStats = IsrStats::new("send_event");
+    static WAKEUP_STATS: IsrStats = IsrStats::new("rtc_wakeup");
+    static POWER_STATS: IsrStats = IsrStats::new("power_manager");
+    static BATTERY_STATS: IsrStats = IsrStats::new("apply_battery_mode");
+    static PANEL_STATS: IsrStats = IsrStats::new("apply_panel");
+    static PVD_STATS: IsrStats = IsrStats::new("power_fail");
+    static REPLAY_STATS: IsrStats = IsrStats::new("replay");
+    static LIFETIME_STATS: IsrStats = IsrStats::new("save_lifetime");
+    static SETTINGS_STATS: IsrStats = IsrStats::new("save_settings");
+    static SERVE_STATS: IsrStats = IsrStats::new("serve_replay");
+    static EXPORT_STATS: IsrStats = IsrStats::new("export");
+    static COMMAND_STATS: IsrStats = IsrStats::new("remote_command");
+    static TUNNEL_STATS: IsrStats = IsrStats::new("log_tunnel");
+    static FIRMWARE_STATS: IsrStats = IsrStats::new("firmware_upload");
+    static JOIN_STATS: IsrStats = IsrStats::new("join_request");
+    static SF_STATS: IsrStats = IsrStats::new("apply_spreading_factor");
+    static PAIR_STATS: IsrStats = IsrStats::new("partner_reading");
+    static PING_STATS: IsrStats = IsrStats::new("ping");
+    static FACTORY_STATS: IsrStats = IsrStats::new("factory_hold");
+    static LOOPBACK_STATS: IsrStats = IsrStats::new("loopback_air");
+    static LINK_STATS: IsrStats = IsrStats::new("link_report");
+
+    // Radio settings and TX interval until the setup menu or a join saves
+    // others (see config.rs); the build can set the radio ones (preset.rs)
+    const DEFAULT_CONFIG: NodeConfig = NodeConfig {
+        address: preset::address_or(0), // Join: Node 2 hands one out (see join.rs)
+        network_id: preset::NETWORK_ID,
+        band_mhz: preset::LORA_FREQ_MHZ,
+        tx_interval_s: 10,       // Auto-transmit every 10 seconds
+    };
+    const MENU_FIELDS: &[Field] = &[Field::Address, Field::Network, Field::Band, Field::Interval];
+    const LORA_PARAMETER: &str = region::PARAMETER;  // AT+PARAMETER: the region's SF/BW (see region.rs)
+
+    /// Largest encoded sensor and summary frames, checked up front against
+    /// the duty cycle
+    const SENSOR_FRAME_LEN: usize = 96;
+    const SUMMARY_FRAME_LEN: usize = 64;
+    /// The RYLR998's payload limit, which a `ReplayBatch` is filled up to
+    const REPLAY_FRAME_LEN: usize = 240;
+    /// Largest export record: a crash log slot's report, framed
+    const EXPORT_RECORD_LEN: usize = 136;
+
+    // --- Binary Protocol (shared with Node 2, see protocol.rs) ---
+    use wk3_binary_protocol::protocol::{
+        decode_ack, decode_frame, encode_frame, frame_type, tlv, AckPacket, SlotGrant, CalibrationCommand, CrashReport, EventPacket, HealthPacket,
+        NodeInfo, ReplayBatch, ReplayRequest, SensorDataPacket, MSG_TYPE_ACK, MSG_TYPE_CALIBRATE, MSG_TYPE_CRASH_REPORT,
+        MSG_TYPE_DISPLAY, MSG_TYPE_EVENT, MSG_TYPE_HEALTH, MSG_TYPE_NACK, MSG_TYPE_NODE_INFO, MSG_TYPE_REPLAY_BATCH,
+        MSG_TYPE_REPLAY_REQUEST, MSG_TYPE_SENSOR_DATA, MSG_TYPE_SENSOR_FAULT, MSG_TYPE_STORED, MSG_TYPE_SUMMARY,
+        REPLAY_MAX_RANGE, TLV_CAPACITY, CommandPacket, CommandResult, RemoteCommand, MSG_TYPE_COMMAND,
+        MSG_TYPE_COMMAND_RESULT, COMMAND_DONE, MSG_TYPE_LOG, FirmwareBegin, FirmwareChunk, FirmwareStatus,
+        FIRMWARE_CHUNK_LEN, FIRMWARE_DIGEST_LEN, MSG_TYPE_FW_BEGIN, MSG_TYPE_FW_CHUNK, MSG_TYPE_FW_STATUS, FW_IDLE, FW_NO_STORE, FW_RECEIVING,
+        BroadcastAck, BroadcastPacket, MSG_TYPE_BROADCAST, MSG_TYPE_BROADCAST_ACK, JoinAccept, JoinRequest,
+        MSG_TYPE_JOIN_ACCEPT, MSG_TYPE_JOIN_REQUEST, StoredReading, frame_node, encode_ack, Ping, Pong, MSG_TYPE_PING,
+        MSG_TYPE_PONG, ConfigAck, MSG_TYPE_CONFIG_ACK, MSG_TYPE_CONFIG_REPORT, FrameError, LinkStats,
+        MSG_TYPE_LINK_STATS,
+    };
+    #[cfg(feature = "littlefs")]
+    use wk3_binary_protocol::protocol::{FIRMWARE_MAX_LEN, FW_BAD_IMAGE, FW_REFUSED, FW_STAGED, FW_STORE_ERROR};
 
     // Transmission retry configuration
     const MAX_RETRIES: u8 = 3;
-    const ACK_TIMEOUT_SECS: u32 = 2;  // Wait 2 seconds for ACK before retry
+    const ACK_TIMEOUT_MS: u32 = 2_000;  // Wait 2 seconds for ACK before retry
 
     /// Transmission state for reliable delivery
     #[derive(Debug, Clone, Copy, PartialEq)]
@@ -73,22 +234,112 @@ mod app {
         Idle,                    // Waiting for next transmission trigger
         WaitingForAck {          // Packet sent, waiting for ACK
             seq_num: u16,        // Which packet we're waiting for
-            timeout_counter: u32, // Countdown in seconds until timeout
+            sent_at_ms: u32,     // Monotonic time the packet went out (for RTT)
+            deadline_ms: u32,    // Monotonic time at which the ACK wait times out
             retry_count: u8,     // How many retries attempted so far
         },
     }
 
-    /// Calculate CRC-16 checksum for data integrity
-    /// Uses CRC-16-IBM-3740 (CCITT with 0xFFFF initial value)
-    fn calculate_crc16(data: &[u8]) -> u16 {
-        use crc::{Crc, CRC_16_IBM_3740};
-        const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
-        CRC16.checksum(data)
+    /// Fate of the last sensor packet, for the status screen
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum TxResult {
+        Waiting,
+        Acked { rtt_ms: u32 },
+        Nacked,
+        /// Gave up after `MAX_RETRIES`
+        Lost,
+    }
+
+    /// Last TX seq, its result and how many ACK waits it took
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct LinkStatus {
+        pub seq_num: u16,
+        pub result: TxResult,
+        pub attempt: u8,
+    }
+
+    /// Everything the Node 1 status screen shows
+    ///
+    /// Updated by tim2 after each sample and by uart4 when the ACK (or NACK)
+    /// arrives; `draw_status` renders it, so the ACK result shows up as soon
+    /// as it's known rather than with the next sample.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Status {
+        temp_c: f32,
+        humid_pct: f32,
+        gas: u32,
+        heater_step: u8,
+        send_as: Option<&'static str>,
+        network_id: u8,
+        next_s: u32,
+        battery_pct: u8,
+        link: Option<LinkStatus>,
+    }
+
+    /// A message from Node 2 that Node 1 acts on
+    #[derive(Debug, Clone)]
+    pub enum Downlink {
+        /// With the transmit slot Node 2 holds for this node, after a live reading
+        Ack(AckPacket, Option<SlotGrant>),
+        Calibrate(CalibrationCommand),
+        Display(panel::Setting),
+        Replay(ReplayRequest),
+        Command(CommandPacket),
+        Firmware(Firmware),
+        /// Node 2's `all ...` or `group <g> ...`; answered after `delay_ms`
+        /// if `ack`, or not at all
+        Broadcast { id: u16, ack: bool, delay_ms: u32, payload: broadcast::Payload },
+        /// The address Node 2 picked for this node's `JoinRequest`
+        Join(JoinAccept),
+        /// `pair on`: a reading from the other node to ACK, and to show if
+        /// it's live rather than replayed
+        Partner { node: u8, seq_num: u16, reading: Option<pair::Reading> },
+        /// A link check from the module at `from`, to answer (see ping.rs)
+        Ping { from: u16, ping: Ping, link: ping::Link },
+        /// The answer to ours
+        Pong { from: u16, pong: Pong, link: ping::Link },
+        /// Node `node`'s view of the link (see linkstats.rs)
+        LinkStats { node: u8, stats: LinkStats },
     }
 
-    /// Parse ACK/NACK message from Node 2
+    /// Where the outcome of a remote command goes
+    #[derive(Debug, Clone, Copy, defmt::Format)]
+    pub enum Reply {
+        /// A `CommandResult`, straight away
+        Result,
+        /// A `BroadcastAck` for broadcast `id` after `delay_ms` if `ack`, or
+        /// nothing; a reading asked for waits as long
+        Broadcast { id: u16, ack: bool, delay_ms: u32 },
+    }
+
+    /// A `wk3 flash-remote` frame relayed by Node 2, owned for the task queue
+    #[derive(Debug, Clone)]
+    pub enum Firmware {
+        Begin(FirmwareBegin),
+        Chunk { offset: u32, data: Vec<u8, FIRMWARE_CHUNK_LEN> },
+    }
+
+    /// Milliseconds since boot from the RTIC monotonic
+    fn now_ms() -> u32 {
+        monotonics::now().duration_since_epoch().to_millis() as u32
+    }
+
+    /// Whole seconds since boot, for the display's night window
+    fn uptime_s() -> u32 {
+        monotonics::now().duration_since_epoch().to_secs() as u32
+    }
+
+    /// A display setting from Node 2, alone or broadcast: apply and save it
+    fn apply_display(setting: panel::Setting) {
+        setting.apply(uptime_s());
+        defmt::info!("Display {} from N2", setting);
+        let _ = apply_panel::spawn();
+        let _ = save_settings::spawn();
+    }
+
+    /// Parse ACK/NACK or downlink message from Node 2
     /// Format: +RCV=<Address>,<Length>,<BinaryData>,<RSSI>,<SNR>\r\n
-    fn parse_ack_message(buffer: &[u8]) -> Option<AckPacket> {
+    fn parse_downlink(buffer: &[u8]) -> Option<Downlink> {
         // Check prefix: must start with "+RCV="
         if buffer.len() < 10 || &buffer[0..5] != b"+RCV=" {
             return None;
@@ -112,6 +363,9 @@ mod app {
         let comma1 = comma1_pos?;
         let comma2 = comma2_pos?;
 
+        // The transmitting module's address
+        let from: u16 = core::str::from_utf8(&buffer[5..comma1]).ok()?.parse().ok()?;
+
         // Extract length
         let len_bytes = &buffer[comma1 + 1..comma2];
         let len_str = core::str::from_utf8(len_bytes).ok()?;
@@ -126,9 +380,169 @@ mod app {
         }
 
         let binary_payload = &buffer[payload_start..payload_end];
+        // `,<RSSI>,<SNR>` follows the payload
+        let link = link_figures(&buffer[payload_end..]);
+        // Whatever it was meant to be; ACKs have no CRC to fail
+        if let Err(FrameError::Crc { .. }) = decode_frame(binary_payload) {
+            linkstats::note_crc_failure();
+        }
+
+        match frame_type(binary_payload) {
+            // Node 2 panicked: log what it managed to send before resetting
+            Some(MSG_TYPE_CRASH_REPORT) => {
+                match decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<CrashReport>(body).ok())
+                {
+                    Some(report) => defmt::error!("N2 CRASHED at {}:{}: {}",
+                        report.file, report.line, report.message),
+                    None => defmt::warn!("N2 crash report corrupted"),
+                }
+                None
+            }
+            Some(MSG_TYPE_CALIBRATE) => {
+                let cmd = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<CalibrationCommand>(body).ok());
+                if cmd.is_none() {
+                    defmt::warn!("Calibration downlink corrupted");
+                }
+                cmd.map(Downlink::Calibrate)
+            }
+            Some(MSG_TYPE_DISPLAY) => {
+                let setting = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<panel::Setting>(body).ok());
+                if setting.is_none() {
+                    defmt::warn!("Display downlink corrupted");
+                }
+                setting.map(Downlink::Display)
+            }
+            Some(MSG_TYPE_REPLAY_REQUEST) => {
+                let request = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<ReplayRequest>(body).ok());
+                if request.is_none() {
+                    defmt::warn!("Replay request corrupted");
+                }
+                request.map(Downlink::Replay)
+            }
+            Some(MSG_TYPE_COMMAND) => {
+                let packet = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<CommandPacket>(body).ok());
+                if packet.is_none() {
+                    defmt::warn!("Remote command corrupted");
+                }
+                packet.map(Downlink::Command)
+            }
+            Some(MSG_TYPE_FW_BEGIN) => {
+                let begin = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<FirmwareBegin>(body).ok());
+                if begin.is_none() {
+                    defmt::warn!("Firmware begin corrupted");
+                }
+                begin.map(|begin| Downlink::Firmware(Firmware::Begin(begin)))
+            }
+            Some(MSG_TYPE_FW_CHUNK) => {
+                let chunk = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<FirmwareChunk>(body).ok())
+                    .and_then(|chunk| Some(Firmware::Chunk {
+                        offset: chunk.offset,
+                        data: Vec::from_slice(chunk.data).ok()?,
+                    }));
+                if chunk.is_none() {
+                    defmt::warn!("Firmware chunk corrupted");
+                }
+                chunk.map(Downlink::Firmware)
+            }
+            // Node 2's `all ...`: the same downlinks, for every node at once
+            Some(MSG_TYPE_BROADCAST) => {
+                let (_, body) = decode_frame(binary_payload).ok()?;
+                let Some((packet, payload)) = postcard::from_bytes::<BroadcastPacket>(body)
+                    .ok()
+                    .and_then(|packet| Some((packet, broadcast::Payload::decode(&packet)?)))
+                else {
+                    defmt::warn!("Broadcast corrupted or not for sensor nodes");
+                    return None;
+                };
+                if !group::takes(packet.group) {
+                    debug!("Broadcast #{} for group {}, not ours", packet.id, packet.group);
+                    return None;
+                }
+                let delay_ms = broadcast::delay_ms(&packet, config::node_id(), now_ms());
+                Some(Downlink::Broadcast { id: packet.id, ack: broadcast::wants_ack(&packet), delay_ms, payload })
+            }
+            // Broadcast to every node; only the one that asked takes it
+            Some(MSG_TYPE_JOIN_ACCEPT) => {
+                let accept = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<JoinAccept>(body).ok());
+                if accept.is_none() {
+                    defmt::warn!("Join accept corrupted");
+                }
+                accept.filter(|accept| accept.uid == join::uid()).map(Downlink::Join)
+            }
+            // The other node's readings in peer mode (see pair.rs)
+            Some(MSG_TYPE_SENSOR_DATA | MSG_TYPE_STORED) if pair::enabled() => {
+                let (msg_type, body) = decode_frame(binary_payload).ok()?;
+                let node = frame_node(binary_payload)?;
+                let packet = match msg_type {
+                    MSG_TYPE_STORED => postcard::from_bytes::<StoredReading>(body).ok().map(|stored| stored.reading),
+                    _ => postcard::from_bytes::<SensorDataPacket>(body).ok(),
+                };
+                let Some(packet) = packet else {
+                    defmt::warn!("Reading from N{} corrupted", node);
+                    return None;
+                };
+                let reading = (msg_type == MSG_TYPE_SENSOR_DATA).then(|| pair::Reading::new(node, &packet, link.rssi));
+                Some(Downlink::Partner { node, seq_num: packet.seq_num, reading })
+            }
+            // Link checks (see ping.rs)
+            Some(MSG_TYPE_PING) => {
+                let ping = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<Ping>(body).ok());
+                if ping.is_none() {
+                    defmt::warn!("Ping corrupted");
+                }
+                ping.map(|ping| Downlink::Ping { from, ping, link })
+            }
+            Some(MSG_TYPE_PONG) => {
+                let pong = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<Pong>(body).ok());
+                if pong.is_none() {
+                    defmt::warn!("Pong corrupted");
+                }
+                pong.map(|pong| Downlink::Pong { from, pong, link })
+            }
+            Some(MSG_TYPE_LINK_STATS) => {
+                let node = frame_node(binary_payload)?;
+                let stats = decode_frame(binary_payload)
+                    .ok()
+                    .and_then(|(_, body)| postcard::from_bytes::<LinkStats>(body).ok());
+                if stats.is_none() {
+                    defmt::warn!("Link stats corrupted");
+                }
+                stats.map(|stats| Downlink::LinkStats { node, stats })
+            }
+            // Deserialize ACK packet (no CRC on ACK packets - they're tiny!);
+            // another node's, passed down by a relay it shares, isn't ours
+            Some(MSG_TYPE_ACK | MSG_TYPE_NACK) => decode_ack(binary_payload)
+                .filter(|(ack, _)| ack.node == config::node_id())
+                .map(|(ack, slot)| Downlink::Ack(ack, slot)),
+            _ => None,
+        }
+    }
 
-        // Deserialize ACK packet (no CRC on ACK packets - they're tiny!)
-        postcard::from_bytes(binary_payload).ok()
+    /// RSSI and SNR from the `,<RSSI>,<SNR>` after a `+RCV` payload; 0 for
+    /// either one that doesn't parse
+    fn link_figures(tail: &[u8]) -> ping::Link {
+        let mut fields = core::str::from_utf8(tail).unwrap_or("").split(',').skip(1).map(|f| f.trim().parse().unwrap_or(0));
+        ping::Link { rssi: fields.next().unwrap_or(0), snr: fields.next().unwrap_or(0) }
     }
 
     // --- Bridge for embedded-hal 1.0 -> 0.2.7 ---
@@ -163,52 +577,208 @@ mod app {
     type BmeDelay = Delay<pac::TIM3, 1000000>;
     type BusManager = shared_bus::BusManager<CortexMMutex<I2cCompat<MyI2c>>>;
     type I2cProxy = shared_bus::I2cProxy<'static, CortexMMutex<I2cCompat<MyI2c>>>;
+    #[cfg(feature = "littlefs")]
+    type SpiFlash = W25q<embedded_hal_bus::spi::ExclusiveDevice<
+        stm32f4xx_hal::spi::Spi<pac::SPI2>, Pin<'B', 12, Output>, embedded_hal_bus::spi::NoDelay>>;
+    #[cfg(feature = "littlefs")]
+    type Store = storage::Store<SpiFlash>;
+    // Never present without the feature
+    #[cfg(not(feature = "littlefs"))]
+    type Store = core::convert::Infallible;
     
-    type LoraDisplay = Ssd1306<I2CInterface<I2cProxy>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+    type LoraDisplay = display::Oled<I2cProxy>;
+    #[cfg(not(feature = "loopback"))]
+    type LoraUart = Serial<pac::UART4>;
+    // The module, and Node 2 behind it, in memory
+    #[cfg(feature = "loopback")]
+    type LoraUart = loopback::Radio;
+    #[cfg(feature = "scd40")]
+    type Scd40Delay = Delay<pac::TIM4, 1000000>;
+    #[cfg(feature = "ds18b20")]
+    type ProbePin = Pin<'B', 5, Output<stm32f4xx_hal::gpio::OpenDrain>>;
+    #[cfg(feature = "ds18b20")]
+    type ProbeDelay = Delay<pac::TIM9, 1000000>;
+
+    /// Every fitted sensor, read in order of trust (see sensor.rs)
+    pub struct Sensors {
+        sht31: Sht31<I2cProxy, ShtDelay>,
+        bme680: Bme680Sensor<I2cProxy, BmeDelay>,
+        #[cfg(feature = "scd40")]
+        scd40: Scd40<I2cProxy, Scd40Delay>,
+        /// External probes, sent as their own TLV record (see ds18b20.rs)
+        #[cfg(feature = "ds18b20")]
+        probes: Probes<ProbePin, ProbeDelay>,
+    }
+
+    impl Sensors {
+        /// Bit mask announced in `NodeInfo::sensors`
+        fn kinds(&self) -> u8 {
+            let mask = self.sht31.kind().bit() | self.bme680.kind().bit();
+            #[cfg(feature = "scd40")]
+            let mask = mask | self.scd40.kind().bit();
+            mask
+        }
+
+        /// Read every sensor, trimmed; the first failure abandons the cycle
+        fn measure(&mut self, calibration: &Calibration) -> Result<Measurement, SensorFault> {
+            let mut m = Measurement::default();
+            self.sht31.read_into(&mut m, calibration)?;
+            self.bme680.read_into(&mut m, calibration)?;
+            #[cfg(feature = "scd40")]
+            self.scd40.read_into(&mut m, calibration)?;
+            Ok(m)
+        }
+    }
+
+    /// ADC1 inputs: battery divider on PA0, soil probes on PA1/PA4
+    pub struct AnalogInputs {
+        adc: Adc<pac::ADC1>,
+        battery: Pin<'A', 0, Analog>,
+        soil: (Pin<'A', 1, Analog>, Pin<'A', 4, Analog>),
+    }
+
+    impl AnalogInputs {
+        /// Battery voltage through the divider (see battery.rs)
+        fn battery_mv(&mut self) -> u16 {
+            // Longest sample time: the divider's 50k source impedance needs it
+            let sample = self.adc.convert(&self.battery, SampleTime::Cycles_480);
+            battery::from_pin_millivolts(self.adc.sample_to_millivolts(sample))
+        }
+
+        /// Each soil probe's output (see soil.rs)
+        fn soil_mv(&mut self) -> [u16; soil::PROBES] {
+            let first = self.adc.convert(&self.soil.0, SampleTime::Cycles_480);
+            let second = self.adc.convert(&self.soil.1, SampleTime::Cycles_480);
+            [first, second].map(|sample| self.adc.sample_to_millivolts(sample))
+        }
+    }
+
+    #[monotonic(binds = SysTick, default = true)]
+    type MonoTimer = Mono;
 
     #[shared]
     struct Shared {
-        lora_uart: Serial<pac::UART4>,
+        lora_uart: LoraUart,
         display: LoraDisplay,
-        sht31: SHT3x<I2cProxy, ShtDelay>,
-        bme680: Bme680<I2cProxy, BmeDelay>,
         tx_state: TxState,     // Transmission state machine (shared between tim2 and uart4)
+        config: NodeConfig,    // Radio settings and TX interval in use (flash sector 6)
+        menu: Option<Menu>,    // Setup menu, while open
+        tx_requested: bool,    // Short button press or `report` from N2: transmit on the next tick
+        slot_grant: Option<SlotGrant>,  // Node 2's latest slot, applied on the next tick (see slots.rs)
+        calibration: Calibration,  // Per-sensor trims (flash sector 6)
+        status: Option<Status>,    // Status screen contents, once there's a sample
+        summary: Accumulator,      // This hour's min/max/mean (see summary.rs)
+        gnss: Option<(Fix, u32)>,  // Latest GGA and when it arrived (ms)
+        analog: AnalogInputs,      // Battery and soil probe ADC
+        soil: SoilCalibration,     // Soil probe endpoints (flash sector 6)
+        duty: DutyCycle,           // Radio and MCU sleep schedule (see power.rs)
+        battery_mode: battery::Mode,  // Degraded operation on a low cell (see battery.rs)
+        airtime: Accountant,       // Duty-cycle budget over the last hour (see airtime.rs)
+        backlog: Backlog,          // Unacknowledged readings (flash sector 5, see backlog.rs)
+        partner: Partner,          // The other node's last reading in peer mode (see pair.rs)
+        pinger: Pinger,            // The last `ping` and its answer (see ping.rs)
+        eeprom: Option<Eeprom<I2cProxy>>,  // Settings and lifetime counters, if fitted
+        store: Option<Store>,  // SPI flash filesystem, ahead of the EEPROM if fitted
+        console: Serial<pac::USART2>,  // Debug shell on the ST-LINK VCP, and `export`
+        last_command: u32,     // Highest counter accepted, remote commands and firmware uploads alike
     }
 
     #[local]
     struct Local {
         led: Pin<'A', 5, Output>,
-        button: Pin<'C', 13>,  // Blue button on Nucleo (PC13)
+        button: Pin<'C', 13, Input>,  // Blue button on Nucleo (PC13), EXTI13
+        pir: Pin<'B', 4, Input>,      // PIR motion output, EXTI4
         timer: CounterHz<pac::TIM2>,
-        bme_delay: BmeDelay,
+        sensors: Sensors,
         packet_counter: u32,   // Counts packets sent
         tx_countdown: u32,     // Seconds until next auto-transmit
-        rx_buffer: Vec<u8, 128>,  // Buffer for incoming ACK/NACK packets
+        rng: Rng,              // Interval jitter and busy-channel backoff (see backoff.rs)
+        rx_buffer: Vec<u8, 255>,  // Buffer for incoming ACK/NACK and downlink lines, firmware chunks the longest
+        watchdog: Supervisor,
+        gnss_uart: Serial<pac::USART1>,  // NMEA from a GNSS module, if fitted
+        shell: LineBuffer,
+        reset_cause: ResetCause,       // Reported in every health packet
+        lifetime: Lifetime,            // EEPROM counters as of boot
+        settings: Settings,            // Shell settings as last saved
+        hop_plan: hopping::Plan,       // Hop channels as last saved
     }
 
     // Helper function to send AT command and wait for response
-    fn send_at_command(uart: &mut Serial<pac::UART4>, cmd: &str) {
+    fn send_at_command(uart: &mut LoraUart, cmd: &str) {
         defmt::info!("Sending AT command: {}", cmd);
+        write_line(uart, cmd);
+
+        // Wait a bit for module to process
+        time::busy_wait_ms(100);
+    }
 
-        // Send command
-        for byte in cmd.as_bytes() {
+    /// Write one AT command and its \r\n without waiting for the reply
+    fn write_line(uart: &mut LoraUart, cmd: &str) {
+        for byte in cmd.as_bytes().iter().chain(b"\r\n") {
             let _ = nb::block!(uart.write(*byte));
         }
+    }
 
-        // Send \r\n
-        let _ = nb::block!(uart.write(b'\r'));
-        let _ = nb::block!(uart.write(b'\n'));
-
-        // Wait a bit for module to process
-        cortex_m::asm::delay(8_400_000); // ~100ms at 84 MHz
+    /// Write one AT command and wait up to `module::REPLY_MS` for the
+    /// module's answer
+    fn exchange_at_command(uart: &mut LoraUart, cmd: &str) -> Option<module::Reply> {
+        defmt::info!("Sending AT command: {}", cmd);
+        write_line(uart, cmd);
+        let mut lines = module::Lines::new();
+        let mut reply = None;
+        time::poll_ms(module::REPLY_MS, || {
+            if let Ok(byte) = uart.read() {
+                reply = lines.push(byte);
+            }
+            reply.is_some()
+        });
+        reply
     }
 
     #[init]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
-        let dp = cx.device;
+        let mut dp = cx.device;
+        let mut core = cx.core;
+
+        // Reset cause must be read before anything can reset us again
+        let reset_cause = ResetCause::read_and_clear(&dp.RCC);
+        defmt::info!("N1 reset cause: {}", reset_cause);
+        if let Some(missed) = watchdog::take_missed() {
+            if reset_cause == ResetCause::IndependentWatchdog {
+                watchdog::log_missed(missed);
+            }
+        }
+        let previous_crash = crash::take_unreported();
+        if let Some(report) = previous_crash.as_ref().and_then(|crash| crash.report()) {
+            defmt::error!("N1 previous crash at {}:{}: {}", report.file, report.line, report.message);
+        }
+        let boot_count = config::count_boot();
+        defmt::info!("N1 boot #{} ({})", boot_count, reset_cause);
+        if command::DEV_KEY_IN_USE {
+            defmt::warn!("Remote commands use the development key; set WK3_COMMAND_KEY for a field build");
+        }
+        brownout::init();
+        let settings = config::load_settings().unwrap_or_else(Settings::current);
+        settings.apply();
+        let hop_plan = hopping::load().unwrap_or_default();
+        if LOW_POWER {
+            hopping::set_clockless();
+        }
+        hop_plan.apply();
+        // Relayed like any other uplink (see relay.rs)
+        crash::set_radio_peer(relay::upstream() as u8);
+        defmt::info!("N1 settings: {}", settings);
+        if let Some(record) = brownout::take_saved() {
+            defmt::warn!("N1 supply collapsed after {}s, last packet #{} ({} collapses so far)",
+                record.uptime_s, record.last_seq, record.failures);
+        }
         
         // 1. Configure RCC clocks (0.23.0 API uses freeze with Config)
-        let mut rcc = dp.RCC.freeze(Config::hsi().sysclk(84.MHz()));
+        let mut rcc = dp.RCC.freeze(Config::hsi().sysclk(time::SYSCLK_HZ.Hz()));
+
+        // Cycle counter first: busy_wait_ms() relies on it during AT configuration below
+        time::enable_cycle_counter(&mut core.DCB, &mut core.DWT);
+        let mono = Mono::new(core.SYST, time::SYSCLK_HZ);
 
         // 2. Split GPIOs (requires &mut rcc in 0.23.0)
         let gpioa = dp.GPIOA.split(&mut rcc);
@@ -216,38 +786,148 @@ mod app {
         let gpioc = dp.GPIOC.split(&mut rcc);
 
         let led = gpioa.pa5.into_push_pull_output();
-        let button = gpioc.pc13;  // Blue button (has built-in pull-up, active-low)
-
-        // Create delay instances for SHT31 and BME680
-        // SHT31 takes ownership of its delay (TIM5)
+        // Power-profiling marker on PA8 (Arduino D7), driven by powertrace.rs
+        #[cfg(feature = "power-trace")]
+        let _ = gpioa.pa8.into_push_pull_output();
+        // Blue button (has built-in pull-up, active-low): both edges, to time long presses
+        let mut syscfg = dp.SYSCFG.constrain(&mut rcc);
+        let mut button = gpioc.pc13.into_input();
+        button.make_interrupt_source(&mut syscfg);
+        button.trigger_on_edge(&mut dp.EXTI, Edge::RisingFalling);
+        button.enable_interrupt(&mut dp.EXTI);
+
+        // PIR motion sensor output on PB4 (Arduino D5), EXTI4 (see motion.rs)
+        let mut pir = gpiob.pb4.into_pull_down_input();
+        pir.make_interrupt_source(&mut syscfg);
+        pir.trigger_on_edge(&mut dp.EXTI, Edge::Rising);
+        pir.enable_interrupt(&mut dp.EXTI);
+
+        // Create delay instances for the sensors, each owns its own
         let sht_delay = dp.TIM5.delay_us(&mut rcc);
-        // BME680 delay (TIM3) will be moved to Local for use in handler
         let mut bme_delay = dp.TIM3.delay_us(&mut rcc);
+        #[cfg(feature = "scd40")]
+        let scd40_delay = dp.TIM4.delay_us(&mut rcc);
+        #[cfg(feature = "ds18b20")]
+        let probe_delay = dp.TIM9.delay_us(&mut rcc);
+
+        // --- I2C1 ---
+        let scl = gpiob.pb8.into_alternate_open_drain();
+        let sda = gpiob.pb9.into_alternate_open_drain();
+        let i2c = I2c::new(dp.I2C1, (scl, sda), 100.kHz(), &mut rcc);
+        
+        let i2c_compat = I2cCompat(i2c);
+        let bus: &'static BusManager = shared_bus::new_cortexm!(I2cCompat<MyI2c> = i2c_compat).unwrap();
+
+        // --- SPI flash filesystem, optional (see storage.rs) ---
+        #[cfg(feature = "littlefs")]
+        let mut store = {
+            use stm32f4xx_hal::spi::{Mode as SpiMode, Phase as SpiPhase, Polarity as SpiPolarity, Spi as FlashSpi};
+            let mode = SpiMode { polarity: SpiPolarity::IdleLow, phase: SpiPhase::CaptureOnFirstTransition };
+            let spi = FlashSpi::new(
+                dp.SPI2,
+                (gpiob.pb13.into_alternate(), gpiob.pb14.into_alternate(), gpiob.pb15.into_alternate()),
+                mode,
+                21.MHz(),
+                &mut rcc,
+            );
+            let cs = gpiob.pb12.into_push_pull_output();
+            let device = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(spi, cs).unwrap();
+            match W25q::probe(device) {
+                Some(flash) => {
+                    let flash = cortex_m::singleton!(: SpiFlash = flash).unwrap();
+                    let alloc = cortex_m::singleton!(: littlefs2::fs::Allocation<SpiFlash> =
+                        littlefs2::fs::Filesystem::allocate()).unwrap();
+                    match Store::mount(alloc, flash) {
+                        Ok(store) => {
+                            defmt::info!("N1 SPI flash: mounted, {} blocks free", store.free_blocks());
+                            Some(store)
+                        }
+                        Err(e) => {
+                            defmt::error!("N1 SPI flash mount failed: {}", e);
+                            None
+                        }
+                    }
+                }
+                None => {
+                    defmt::info!("N1 SPI flash: none on SPI2");
+                    None
+                }
+            }
+        };
+        #[cfg(not(feature = "littlefs"))]
+        let mut store: Option<Store> = None;
+
+        // --- EEPROM, optional: settings go to flash without one (see eeprom.rs) ---
+        let mut eeprom = Eeprom::probe(bus.acquire_i2c());
+        let mut lifetime = Lifetime::default();
+        if store.is_some() || eeprom.is_some() {
+            lifetime = load_setting(&mut store, &mut eeprom, Kind::Lifetime, || None).unwrap_or_default();
+            lifetime.boots = lifetime.boots.wrapping_add(1);
+            save_setting(&mut store, &mut eeprom, Kind::Lifetime, &lifetime, |_| Ok(()));
+            defmt::info!("N1 lifetime: {}", lifetime);
+        }
+        if eeprom.is_none() {
+            defmt::info!("N1 EEPROM: none at {=u8:#x}", eeprom::ADDRESS);
+        }
+        let mut event: String<96> = String::new();
+        let _ = core::write!(event, "boot #{} ({:?})", boot_count, reset_cause);
+        log_event(&mut store, &event);
+        if let Some(report) = previous_crash.as_ref().and_then(|crash| crash.report()) {
+            event.clear();
+            let _ = core::write!(event, "crash at {}:{}: {}", report.file, report.line, report.message);
+            log_event(&mut store, &event);
+        }
 
         // --- UART4 ---
-        let tx = gpioc.pc10.into_alternate();
-        let rx = gpioc.pc11.into_alternate();
+        #[cfg(not(feature = "loopback"))]
         let mut lora_uart = Serial::new(
             dp.UART4,
-            (tx, rx),
+            (gpioc.pc10.into_alternate(), gpioc.pc11.into_alternate()),
             SerialConfig::default().baudrate(115200.bps()),
             &mut rcc
         ).unwrap();
+        // No module fitted: it and Node 2 answer from memory (see loopback.rs)
+        #[cfg(feature = "loopback")]
+        let mut lora_uart = {
+            defmt::warn!("LOOPBACK build: no radio, frames are answered in memory");
+            let rng = Rng::new(0, boot_count, cortex_m::peripheral::DWT::cycle_count());
+            loopback::Radio::new(rng, now_ms, module::load().as_ref())
+        };
 
         // Configure LoRa module before enabling RX interrupt
         defmt::info!("Configuring LoRa module (Node 1)...");
+        let config = load_setting(&mut store, &mut eeprom, Kind::Config, config::load).unwrap_or(DEFAULT_CONFIG);
+        region::log();
+        let config = NodeConfig { band_mhz: region::checked_band(config.band_mhz), ..config };
+        defmt::info!("N1 config: {}", config);
         send_at_command(&mut lora_uart, "AT");
-        send_at_command(&mut lora_uart, "AT+ADDRESS=1");
-
-        let mut cmd_buf: String<32> = String::new();
-        let _ = core::write!(cmd_buf, "AT+NETWORKID={}", NETWORK_ID);
-        send_at_command(&mut lora_uart, cmd_buf.as_str());
-
-        cmd_buf.clear();
-        let _ = core::write!(cmd_buf, "AT+BAND={}000000", LORA_FREQ);
-        send_at_command(&mut lora_uart, cmd_buf.as_str());
-
-        send_at_command(&mut lora_uart, "AT+PARAMETER=7,9,1,7");
+        // Only what the module doesn't already have is written (see module.rs)
+        let applied = module::Applied::new(&config, LORA_PARAMETER, battery::tx_power_limit());
+        let last_applied = module::load();
+        let report = module::bring_up(&applied, last_applied.as_ref(), |cmd| exchange_at_command(&mut lora_uart, cmd));
+        defmt::info!("Module settings written: {} of {}", report.written, module::Item::ALL.len());
+        if report.failed == 0 && last_applied.as_ref() != Some(&applied) {
+            if let Err(e) = module::save(&applied) {
+                defmt::warn!("Module settings save failed: {}", e);
+            }
+        }
+        airtime::set_band(config.band_mhz);
+        hopping::set_network(config.band_mhz, config.network_id);
+        config::set_node_id(config.address);
+
+        // Send the last crash again now the radio is up: the frame sent
+        // while crashing often doesn't make it (see crash.rs)
+        let mut accountant = Accountant::new(LoraParams::parse(LORA_PARAMETER).expect("LORA_PARAMETER"));
+        if let Some(crash) = previous_crash {
+            match crash.transmit() {
+                Some(len) => {
+                    accountant.record(0, len);
+                    crash.mark_reported();
+                    defmt::info!("Crash report resent ({} bytes)", len);
+                }
+                None => defmt::warn!("Crash report not sent, retrying next boot"),
+            }
+        }
 
         // Flush any pending responses from configuration
         while lora_uart.read().is_ok() {}
@@ -263,112 +943,275 @@ mod app {
 
         defmt::info!("LoRa module configured");
 
+        #[cfg(not(feature = "loopback"))]
         lora_uart.listen(SerialEvent::RxNotEmpty);
-
-        // --- I2C1 ---
-        let scl = gpiob.pb8.into_alternate_open_drain();
-        let sda = gpiob.pb9.into_alternate_open_drain();
-        let i2c = I2c::new(dp.I2C1, (scl, sda), 100.kHz(), &mut rcc);
-        
-        let i2c_compat = I2cCompat(i2c);
-        let bus: &'static BusManager = shared_bus::new_cortexm!(I2cCompat<MyI2c> = i2c_compat).unwrap();
+        #[cfg(feature = "loopback")]
+        let _ = loopback_air::spawn();
 
         // --- Sensors ---
-        let sht31 = SHT3x::new(bus.acquire_i2c(), sht_delay, ShtAddress::Low);
-        let mut bme680 = Bme680::init(bus.acquire_i2c(), &mut bme_delay, I2CAddress::Secondary).unwrap();
-        
-        let settings = SettingsBuilder::new()
-            .with_humidity_oversampling(OversamplingSetting::OS2x)
-            .with_pressure_oversampling(OversamplingSetting::OS4x)
-            .with_temperature_oversampling(OversamplingSetting::OS2x)
-            .with_temperature_filter(IIRFilterSize::Size3)
-            .with_gas_measurement(Duration::from_millis(150), 300, 25)
-            .with_run_gas(true)
-            .build();
-        let _ = bme680.set_sensor_settings(&mut bme_delay, settings);
+        let bme680 = Bme680::init(bus.acquire_i2c(), &mut bme_delay, I2CAddress::Secondary).unwrap();
+        let sensors = Sensors {
+            sht31: Sht31::new(bus.acquire_i2c(), sht_delay),
+            bme680: Bme680Sensor::new(bme680, bme_delay),
+            #[cfg(feature = "scd40")]
+            scd40: Scd40::new(bus.acquire_i2c(), scd40_delay).unwrap(),
+            // 1-Wire on PB5 (Arduino D4), 4.7k pull-up to 3.3 V
+            #[cfg(feature = "ds18b20")]
+            probes: Probes::new(OneWire::new(gpiob.pb5.into_open_drain_output(), probe_delay)),
+        };
+        defmt::info!("N1 sensors: {=u8:b}", sensors.kinds());
+        #[cfg(feature = "ds18b20")]
+        defmt::info!("N1 DS18B20 probes: {}", sensors.probes.count());
+        let calibration = load_setting(&mut store, &mut eeprom, Kind::Calibration, calibration::load).unwrap_or_default();
+        defmt::info!("N1 calibration: {}", calibration);
+
+        // --- Battery and soil probes (ADC1 on PA0, PA1, PA4) ---
+        let mut adc = Adc::new(dp.ADC1, true, AdcConfig::default(), &mut rcc);
+        // Measure VDDA against VREFINT so millivolts don't assume 3.3 V
+        adc.calibrate();
+        let mut analog = AnalogInputs {
+            adc,
+            battery: gpioa.pa0.into_analog(),
+            soil: (gpioa.pa1.into_analog(), gpioa.pa4.into_analog()),
+        };
+        defmt::info!("N1 battery: {}mV", analog.battery_mv());
+        let soil = load_setting(&mut store, &mut eeprom, Kind::Soil, soil::load).unwrap_or_default();
+        defmt::info!("N1 soil endpoints: {}", soil);
 
         // --- Display ---
-        let interface = I2CInterface::new(bus.acquire_i2c(), 0x3C, 0x40);
-        let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
-            .into_buffered_graphics_mode();
-        display.init().unwrap();
+        let mut display = display::new(bus.acquire_i2c()).unwrap();
+
+        // --- USART2 debug shell (ST-LINK virtual COM port) ---
+        let mut console = Serial::new(
+            dp.USART2,
+            (gpioa.pa2.into_alternate(), gpioa.pa3.into_alternate()),
+            SerialConfig::default().baudrate(115200.bps()),
+            &mut rcc
+        ).unwrap();
+        console.listen(SerialEvent::RxNotEmpty);
+
+        // --- USART1 GNSS module, optional (see nmea.rs) ---
+        let mut gnss_uart = Serial::new(
+            dp.USART1,
+            (gpioa.pa9.into_alternate(), gpioa.pa10.into_alternate()),
+            SerialConfig::default().baudrate(9600.bps()),
+            &mut rcc
+        ).unwrap();
+        gnss_uart.listen(SerialEvent::RxNotEmpty);
 
         // --- Timer ---
         let mut timer = dp.TIM2.counter_hz(&mut rcc);
         timer.start(1.Hz()).unwrap();  // Still ticks at 1 Hz for countdown
-        timer.listen(Event::Update);
+        if LOW_POWER {
+            // TIM2 stops in STOP mode; the RTC wakeup pends its handler instead
+            power::init_wakeup();
+        } else {
+            timer.listen(Event::Update);
+        }
+
+        power::gate_unused(&BOARD);
+
+        // --- Watchdog (last: init itself takes longer than the timeout) ---
+        // Sensor reads, display and TX all run inline in tim2_handler, so the
+        // heartbeat check-in at the end of that handler covers all of them.
+        let watchdog = Supervisor::start(dp.IWDG, WATCHDOG_TIMEOUT_MS, &[Checkpoint::Heartbeat]);
+
+        // Replayed after the first ACK shows the link is up
+        let backlog = Backlog::load();
+        if backlog.pending() > 0 {
+            defmt::info!("N1 backlog: {} readings waiting for Node 2", backlog.pending());
+        }
+
+        let _ = health_report::spawn_after(HEALTH_INTERVAL_SECS.secs());
+        let _ = announce::spawn(NodeInfo { sensors: sensors.kinds(), boot_count, reset_cause: reset_cause as u8 });
+        let _ = summary_report::spawn_after(SUMMARY_INTERVAL_SECS.secs());
+        let _ = link_report::spawn_after(LINK_INTERVAL_SECS.secs());
+        let _ = save_lifetime::spawn_after(LIFETIME_SAVE_SECS.secs());
+        let _ = log_tunnel::spawn_after(TUNNEL_INTERVAL_SECS.secs());
+        // No address yet: ask Node 2 for one (see join.rs)
+        if config.address == 0 {
+            let _ = join_request::spawn();
+        }
 
         (
             Shared {
                 lora_uart,
                 display,
-                sht31,
-                bme680,
                 tx_state: TxState::Idle,              // Start in Idle state
+                config,
+                menu: None,
+                tx_requested: false,
+                slot_grant: None,
+                calibration,
+                status: None,
+                summary: Accumulator::new(0),
+                gnss: None,
+                analog,
+                soil,
+                duty: DutyCycle::new(LOW_POWER),
+                battery_mode: battery::Mode::Normal,
+                airtime: accountant,
+                backlog,
+                partner: Partner::new(),
+                pinger: Pinger::new(),
+                eeprom,
+                store,
+                console,
+                last_command: command::last_accepted(),
             },
             Local {
                 led,
                 button,
+                pir,
                 timer,
-                bme_delay,
+                sensors,
                 packet_counter: 0,                    // Start at packet #0
-                tx_countdown: AUTO_TX_INTERVAL_SECS,  // First TX in 10 seconds
+                tx_countdown: config.tx_interval_s as u32,  // First TX after one interval
+                // Cycles spent starting up vary with the sensors and the bus
+                rng: Rng::new(config::node_id(), boot_count, cortex_m::peripheral::DWT::cycle_count()),
                 rx_buffer: Vec::new(),                // Empty RX buffer
+                watchdog,
+                gnss_uart,
+                shell: LineBuffer::new(),
+                reset_cause,
+                lifetime,
+                settings,
+                hop_plan,
             },
-            init::Monotonics()
+            init::Monotonics(mono)
         )
     }
 
-    #[task(binds = TIM2, shared = [sht31, bme680, display, lora_uart, tx_state], local = [led, button, timer, bme_delay, packet_counter, tx_countdown])]
+    // Idle: sleep until the next interrupt
+    //
+    // With `low-power` the core goes into STOP rather than Sleep whenever
+    // nothing needs the clocks: the radio asleep (so no ACK or downlink can
+    // arrive), the last byte to it fully shifted out, and the setup menu
+    // closed. The check and the STOP happen with interrupts off, so a task
+    // that wakes the radio can't slip in between them.
+    #[idle(shared = [duty, menu])]
+    fn idle(mut cx: idle::Context) -> ! {
+        loop {
+            cortex_m::interrupt::free(|_| {
+                let stop = LOW_POWER
+                    && cx.shared.duty.lock(|duty| duty.radio_asleep())
+                    && cx.shared.menu.lock(|menu| menu.is_none())
+                    && lora_tx_complete();
+                if stop {
+                    let _sleep = powertrace::span(Phase::Sleep);
+                    power::stop();
+                } else {
+                    cortex_m::asm::wfi();
+                }
+            });
+        }
+    }
+
+    /// UART4 has finished sending its last byte (TC), not just buffered it
+    fn lora_tx_complete() -> bool {
+        let uart_ptr = unsafe { &*pac::UART4::ptr() };
+        uart_ptr.sr().read().tc().bit_is_set()
+    }
+
+    // RTC wakeup: the 1 Hz tick with `low-power`
+    //
+    // Fires whether or not the core was in STOP, so it replaces TIM2's update
+    // interrupt outright rather than only covering the sleeping seconds.
+    #[task(binds = RTC_WKUP, priority = 1)]
+    fn rtc_wakeup(_: rtc_wakeup::Context) {
+        let _busy = WAKEUP_STATS.enter();
+        power::clear_wakeup();
+        rtic::pend(pac::Interrupt::TIM2);
+    }
+
+    #[task(binds = TIM2, shared = [lora_uart, tx_state, config, tx_requested, slot_grant, calibration, status, summary, gnss, analog, soil, duty, battery_mode, airtime, backlog], local = [led, timer, sensors, packet_counter, tx_countdown, rng, slotted: bool = false, jitter_s: u32 = 0, watchdog, iaq_estimator: iaq::Estimator = iaq::Estimator::new(), delta: delta::Gate = delta::Gate::new(), smoother: Smoother = Smoother::new()])]
     fn tim2_handler(mut cx: tim2_handler::Context) {
+        let _busy = TIM2_STATS.enter();
         cx.local.timer.clear_flags(stm32f4xx_hal::timer::Flag::Update);
         cx.local.led.toggle();
 
         // State machine: Handle ACK timeout
-        cx.shared.tx_state.lock(|state| {
+        let now = now_ms();
+        let timed_out = cx.shared.tx_state.lock(|state| {
             match *state {
-                TxState::WaitingForAck { seq_num, timeout_counter, retry_count } => {
-                    if timeout_counter > 0 {
-                        // Countdown timeout
-                        *state = TxState::WaitingForAck {
-                            seq_num,
-                            timeout_counter: timeout_counter - 1,
-                            retry_count,
-                        };
-                    } else {
+                TxState::WaitingForAck { seq_num, sent_at_ms, deadline_ms, retry_count } => {
+                    if time::deadline_passed(now, deadline_ms) {
                         // Timeout reached - count it as a retry
                         let new_retry_count = retry_count + 1;
                         if new_retry_count < MAX_RETRIES {
-                            defmt::warn!("ACK timeout for packet #{}, attempt {}/{}, will keep waiting",
+                            remote!(warn, "ACK timeout for packet #{}, attempt {}/{}, will keep waiting",
                                 seq_num, new_retry_count + 1, MAX_RETRIES);
                             // Keep waiting with incremented retry counter and reset timeout
                             *state = TxState::WaitingForAck {
                                 seq_num,
-                                timeout_counter: ACK_TIMEOUT_SECS,
+                                sent_at_ms,
+                                deadline_ms: now.wrapping_add(ACK_TIMEOUT_MS),
                                 retry_count: new_retry_count,
                             };
                         } else {
-                            defmt::error!("Max retries ({}) exceeded for packet #{}, giving up", MAX_RETRIES, seq_num);
+                            remote!(error, "Max retries ({}) exceeded for packet #{}, giving up", MAX_RETRIES, seq_num);
                             *state = TxState::Idle;
                         }
+                        return Some((seq_num, new_retry_count));
                     }
+                    None
                 }
                 TxState::Idle => {
                     // Normal operation
+                    None
                 }
             }
         });
+        if let Some((seq_num, retries)) = timed_out {
+            if retries >= MAX_RETRIES {
+                eeprom::note_gave_up();
+                backoff::note_unanswered();
+                cx.shared.backlog.lock(|backlog| backlog.lost(seq_num));
+                // The slot may be gone with the link; jitter until a new one comes
+                *cx.local.slotted = false;
+                // A step from ADR may be what broke it (see adr.rs)
+                if adr::note_missed() {
+                    remote!(warn, "No ACKs since ADR changed settings, back to boot settings");
+                    let _ = battery::set_tx_power_limit(region::max_tx_dbm());
+                    let _ = apply_battery_mode::spawn(cx.shared.battery_mode.lock(|mode| *mode));
+                    let boot = LoraParams::parse(LORA_PARAMETER).expect("LORA_PARAMETER");
+                    let _ = apply_spreading_factor::spawn(boot.spreading_factor);
+                }
+                // Or Node 2's clock is no longer what we think (see hopping.rs)
+                if hopping::note_missed() {
+                    remote!(warn, "No ACKs on the hop schedule, back to the home channel");
+                }
+            } else {
+                linkstats::note_retry();
+            }
+            // Replayed readings time out too; the link line only follows live ones
+            update_link(&mut cx.shared.status, |link| {
+                if link.seq_num != seq_num {
+                    return;
+                }
+                if retries < MAX_RETRIES {
+                    link.attempt = retries + 1;
+                } else {
+                    link.result = TxResult::Lost;
+                }
+            });
+            let _ = draw_status::spawn();
+        }
 
-        // Determine if we should transmit this cycle
-        let mut should_transmit = false;
-        let mut trigger_source = "AUTO";
+        // Determine if we should sample (and maybe transmit) this cycle
+        let mut should_sample = false;
+        let mut button = false;
 
-        // Check button (active-low: pressed = low)
-        if cx.local.button.is_low() {
-            defmt::info!("Button pressed - triggering immediate transmission");
-            should_transmit = true;
-            trigger_source = "BTN";
-            *cx.local.tx_countdown = AUTO_TX_INTERVAL_SECS;  // Reset countdown
+        let config = cx.shared.config.lock(|config| *config);
+        let battery_mode = cx.shared.battery_mode.lock(|mode| *mode);
+        let interval = config.tx_interval_s as u32 * battery_mode.interval_factor();
+
+        // Short button press since the last tick (see button_handler), or `report`
+        if cx.shared.tx_requested.lock(|requested| core::mem::replace(requested, false)) {
+            defmt::info!("Transmission requested - sampling now");
+            should_sample = true;
+            button = true;
+            *cx.local.tx_countdown = interval;  // Reset countdown
         } else {
             // Auto-transmit countdown
             if *cx.local.tx_countdown > 0 {
@@ -376,234 +1219,2061 @@ mod app {
             }
 
             if *cx.local.tx_countdown == 0 {
-                defmt::info!("Auto-transmit countdown reached 0");
-                should_transmit = true;
-                *cx.local.tx_countdown = AUTO_TX_INTERVAL_SECS;  // Reset countdown
+                debug!("Sample countdown reached 0");
+                should_sample = true;
+                // Without a slot, a random extra keeps us out of step with
+                // other nodes (see backoff.rs)
+                *cx.local.jitter_s = if *cx.local.slotted { 0 } else { cx.local.rng.jitter_s() };
+                *cx.local.tx_countdown = interval + *cx.local.jitter_s;  // Reset countdown
+            }
+        }
+
+        // Readings wait for an address (see join.rs)
+        if should_sample && config.address == 0 {
+            debug!("Not joined yet, no reading sent");
+            should_sample = false;
+        }
+
+        // Move the next sample toward the slot Node 2 holds for us
+        if let Some(grant) = cx.shared.slot_grant.lock(Option::take) {
+            // The grant counts from an interval without jitter
+            let shift = slots::shift_s(&grant, interval) - core::mem::take(cx.local.jitter_s) as i32;
+            *cx.local.slotted = true;
+            if shift != 0 {
+                *cx.local.tx_countdown = (*cx.local.tx_countdown as i32 + shift).max(1) as u32;
+                defmt::info!("Slot {}: next sample moved {}s", grant.slot, shift);
             }
         }
 
-        // Only read sensors and transmit if triggered AND in Idle state
+        // Wake the radio a tick ahead of the sample that will need it
+        if *cx.local.tx_countdown == 1 {
+            cx.shared.duty.lock(|duty| duty.want_radio());
+        }
+
+        // Only read sensors and transmit if triggered AND in Idle state,
+        // with the radio awake to take the packet; otherwise the sample waits
+        // a tick (a backlog replay keeps the state busy for a while)
         let is_idle = cx.shared.tx_state.lock(|state| *state == TxState::Idle);
-        if should_transmit && is_idle {
-            let delay = cx.local.bme_delay;
+        // Listen before talk: a frame just heard may have its ACK to come
+        let busy = should_sample && is_idle && backoff::channel_busy(now);
+        let radio_ready = should_sample && is_idle && !busy && cx.shared.duty.lock(|duty| duty.claim_radio(now));
+        if should_sample && !radio_ready {
+            let wait_s = if busy {
+                let wait_s = cx.local.rng.defer_s();
+                defmt::info!("Channel busy, sampling in {}s", wait_s);
+                wait_s
+            } else if is_idle {
+                debug!("Radio still waking, sampling on the next tick");
+                1
+            } else {
+                debug!("Awaiting an ACK, sampling on the next tick");
+                1
+            };
+            if button {
+                cx.shared.tx_requested.lock(|requested| *requested = true);
+            } else {
+                *cx.local.tx_countdown = wait_s;
+            }
+        }
+        if radio_ready {
+            boost();
+            let calibration = cx.shared.calibration.lock(|calibration| *calibration);
+            let measured = {
+                let _read = powertrace::span(Phase::SensorRead);
+                cx.local.sensors.measure(&calibration)
+            };
+            let reading = match measured {
+                // The SHT31 (or whichever sensor replaces it) must have answered
+                Ok(mut m) => {
+                    cx.local.smoother.apply(&mut m);
+                    m.temperature_c.zip(m.humidity_pct).map(|(t, h)| (m, t, h))
+                }
+                Err(fault) => {
+                    defmt::warn!("Sensor read failed, reporting instead of sending: {}", fault);
+                    let _ = report_fault::spawn(fault);
+                    None
+                }
+            };
+            if let Some((m, temp_c, humid_pct)) = reading {
+                cx.shared.summary.lock(|summary| summary.add(&m));
+                let gas = m.gas_ohms.unwrap_or(0);
+                let heater_step = m.heater_step.unwrap_or(0);
+                let iaq = m.gas_ohms.and_then(|gas| cx.local.iaq_estimator.update(heater_step, gas, humid_pct));
+
+                let (battery_mv, soil_mv) = cx.shared.analog.lock(|analog| (analog.battery_mv(), analog.soil_mv()));
+                let new_mode = battery_mode.update(battery_mv);
+                if new_mode != battery_mode {
+                    cx.shared.battery_mode.lock(|mode| *mode = new_mode);
+                    let _ = apply_battery_mode::spawn(new_mode);
+                }
+                let moisture = cx.shared.soil.lock(|soil| soil.percents(&soil_mv));
 
-            cx.shared.bme680.lock(|bme| {
-                let _ = bme.set_sensor_mode(delay, PowerMode::ForcedMode);
-            });
+                let mut tlv: Vec<u8, TLV_CAPACITY> = Vec::new();
+                if let Some(co2) = m.co2_ppm {
+                    tlv::push(&mut tlv, tlv::CO2_PPM, &co2.to_le_bytes());
+                }
+                tlv::push(&mut tlv, tlv::BATTERY_MV, &battery_mv.to_le_bytes());
 
-            delay.delay_ms(200u32);
-
-            cx.shared.bme680.lock(|bme| {
-                if let Ok((data, _state)) = bme.get_sensor_data(delay) {
-                    // BME680 used only for gas resistance (SHT31 is more accurate for temp/humidity)
-                    let gas = data.gas_resistance_ohm();
-
-                    cx.shared.sht31.lock(|sht| {
-                        if let Ok(meas) = sht.measure(Repeatability::High) {
-                            let temp_c = meas.temperature as f32 / 100.0;
-                            let humid_pct = meas.humidity as f32 / 100.0;
-
-                            // Increment packet counter
-                            *cx.local.packet_counter += 1;
-
-                            cx.shared.display.lock(|disp: &mut LoraDisplay| {
-                                let _ = disp.clear(BinaryColor::Off);
-                                let style = MonoTextStyleBuilder::new()
-                                    .font(&FONT_6X10)
-                                    .text_color(BinaryColor::On)
-                                    .build();
-
-                                let mut buf: String<64> = String::new();
-                                // Line 1: Temp & Humidity (compact)
-                                let _ = core::write!(buf, "T:{:.1}C H:{:.0}%", temp_c, humid_pct);
-                                Text::new(&buf, Point::new(0, 8), style).draw(disp).ok();
-
-                                buf.clear();
-                                // Line 2: Gas resistance
-                                let _ = core::write!(buf, "Gas:{:.0}k", gas as f32 / 1000.0);
-                                Text::new(&buf, Point::new(0, 20), style).draw(disp).ok();
-
-                                buf.clear();
-                                // Line 3: Node ID and TX status with packet counter
-                                let _ = core::write!(buf, "{} TX:{} #{:04}", NODE_ID, trigger_source, *cx.local.packet_counter);
-                                Text::new(&buf, Point::new(0, 32), style).draw(disp).ok();
-
-                                buf.clear();
-                                // Line 4: Network ID and frequency
-                                let _ = core::write!(buf, "Net:{} {}MHz", NETWORK_ID, LORA_FREQ);
-                                Text::new(&buf, Point::new(0, 44), style).draw(disp).ok();
-
-                                buf.clear();
-                                // Line 5: Countdown to next auto-TX
-                                let _ = core::write!(buf, "Next:{}s", *cx.local.tx_countdown);
-                                Text::new(&buf, Point::new(0, 56), style).draw(disp).ok();
-
-                                let _ = disp.flush();
-                            });
-
-                            let current_seq = *cx.local.packet_counter as u16;
-                            let mut tx_success = false;
-
-                            cx.shared.lora_uart.lock(|uart| {
-                                // === BINARY PROTOCOL ===
-                                // Convert to centidegrees and basis points for binary protocol
-                                let temp_centidegrees = (temp_c * 10.0) as i16;
-                                let humid_basis_points = (humid_pct * 100.0) as u16;
-
-                                let binary_packet = SensorDataPacket {
-                                    seq_num: current_seq,
-                                    temperature: temp_centidegrees,
-                                    humidity: humid_basis_points,
-                                    gas_resistance: gas,
-                                };
+                #[cfg(feature = "ds18b20")]
+                {
+                    let temps = cx.local.sensors.probes.read();
+                    if !temps.is_empty() {
+                        tlv::push(&mut tlv, tlv::PROBE_TEMPS, &ds18b20::encode(&temps));
+                    }
+                }
+
+                if moisture.iter().any(Option::is_some) {
+                    tlv::push(&mut tlv, tlv::SOIL_MOISTURE, &soil::encode(&moisture));
+                }
+
+                // Position for range testing, if a GNSS module is reporting
+                let gnss = cx.shared.gnss.lock(|gnss| *gnss);
+                if let Some((fix, _)) = gnss.filter(|&(_, at_ms)| time::elapsed_ms(now, at_ms) < GNSS_STALE_MS) {
+                    tlv::push(&mut tlv, tlv::GNSS_FIX, &fix.encode());
+                }
+
+                // Raw mode: the SHT31's own ticks instead of the scaled values (see raw.rs)
+                let raw_th = m.raw_th.filter(|_| raw::enabled());
+                if raw_th.is_some() {
+                    tlv::push(&mut tlv, tlv::FLAGS, &[raw::flags::RAW_TH]);
+                }
+
+                // Send-on-delta: a scheduled sample only goes out if it moved (see delta.rs)
+                let send_as = if button {
+                    Some("BTN")
+                } else {
+                    cx.local.delta.check(&m, now).map(Reason::label)
+                };
+                // Over the duty-cycle budget the sample is dropped before it
+                // takes a sequence number; the next one replaces it anyway
+                let send_as = send_as.filter(|_| {
+                    cx.shared.airtime.lock(|airtime| airtime.check(now, SENSOR_FRAME_LEN)) == Admit::Now
+                });
+                match send_as {
+                    Some(reason) => {
+                        debug!("Sending sample ({})", reason);
+                        cx.local.delta.sent(&m, now);
+                        *cx.local.packet_counter += 1;
+                    }
+                    None => debug!("Sample within thresholds, not sent"),
+                }
+
+                let next_s = *cx.local.tx_countdown;
+                cx.shared.status.lock(|status| {
+                    let link = status.and_then(|s| s.link);
+                    *status = Some(Status {
+                        temp_c,
+                        humid_pct,
+                        gas,
+                        heater_step,
+                        send_as,
+                        network_id: config.network_id,
+                        next_s,
+                        battery_pct: battery::percent(battery_mv),
+                        link,
+                    });
+                });
+
+                if let Some(trigger_source) = send_as {
+                    let current_seq = *cx.local.packet_counter as u16;
+                    let mut tx_success = false;
+                    let mut sent_len = 0;
+                    let mut sent_packet = None;
+
+                    cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| {
+                        let _tx = powertrace::span(Phase::RadioTx);
+                        // === BINARY PROTOCOL ===
+                        // Convert to centidegrees and basis points for binary protocol,
+                        // unless sending raw ticks (the i16 field carries them bit for bit)
+                        let (temperature, humidity) = match raw_th {
+                            Some((t, h)) => (t as i16, h),
+                            None => ((temp_c * 10.0) as i16, (humid_pct * 100.0) as u16),
+                        };
+
+                        let binary_packet = SensorDataPacket {
+                            seq_num: current_seq,
+                            temperature,
+                            humidity,
+                            gas_resistance: gas,
+                            heater_step,
+                            iaq,
+                            tlv,
+                        };
+
+                        // Serialize to binary: [header][postcard][CRC16]
+                        let mut binary_buffer = [0u8; SENSOR_FRAME_LEN];
+                        match encode_frame(config::node_id(), MSG_TYPE_SENSOR_DATA, &binary_packet, &mut binary_buffer) {
+                            Ok(frame) => {
+                                // With a route pinned, it goes in the header (see relay.rs)
+                                let mut routed = [0u8; REPLAY_FRAME_LEN];
+                                let (to, frame) = relay::uplink(frame, &mut routed);
+                                let total_len = frame.len();
+                                tune(uart);
+
+                                defmt::info!("Binary packet: {} bytes (type + data + CRC)", total_len);
+
+                                // Send AT command prefix as ASCII: "AT+SEND=<upstream>,<total_length>,"
+                                // (the length includes the CRC)
+                                let mut len_str: String<24> = String::new();
+                                let _ = core::write!(len_str, "AT+SEND={},{},", to, total_len);
+                                for b in len_str.as_bytes() {
+                                    let _ = nb::block!(uart.write(*b));
+                                }
 
-                                // Serialize to binary
-                                let mut binary_buffer = [0u8; 32];
-                                match postcard::to_slice(&binary_packet, &mut binary_buffer) {
-                                    Ok(serialized) => {
-                                        let data_len = serialized.len();
-                                        let crc = calculate_crc16(serialized);
-
-                                        // Total payload: data + 2-byte CRC
-                                        let total_len = data_len + 2;
-
-                                        defmt::info!("Binary packet: {} bytes data + 2 bytes CRC = {} total, CRC: 0x{:04X}",
-                                            data_len, total_len, crc);
-
-                                        // Send AT command prefix: "AT+SEND=2,<total_length>,"
-                                        let cmd_prefix = "AT+SEND=2,";
-                                        for b in cmd_prefix.as_bytes() {
-                                            let _ = nb::block!(uart.write(*b));
-                                        }
-
-                                        // Send total length as ASCII (includes CRC)
-                                        let mut len_str: String<8> = String::new();
-                                        let _ = core::write!(len_str, "{},", total_len);
-                                        for b in len_str.as_bytes() {
-                                            let _ = nb::block!(uart.write(*b));
-                                        }
-
-                                        // Send binary payload (data)
-                                        for b in serialized {
-                                            let _ = nb::block!(uart.write(*b));
-                                        }
-
-                                        // Send CRC-16 (big-endian: high byte first, low byte second)
-                                        let _ = nb::block!(uart.write((crc >> 8) as u8));   // High byte
-                                        let _ = nb::block!(uart.write((crc & 0xFF) as u8)); // Low byte
-
-                                        // Send \r\n terminator
-                                        let _ = nb::block!(uart.write(b'\r'));
-                                        let _ = nb::block!(uart.write(b'\n'));
-
-                                        defmt::info!("Binary TX [{}]: {} bytes sent, packet #{}",
-                                            trigger_source, total_len, current_seq);
-
-                                        tx_success = true;
-                                    }
-                                    Err(_) => {
-                                        defmt::error!("Binary serialization failed!");
-                                    }
+                                // Send binary payload (type, data, CRC big-endian)
+                                for b in frame {
+                                    let _ = nb::block!(uart.write(*b));
                                 }
-                            });
-
-                            // Transition to WaitingForAck state (outside uart lock)
-                            if tx_success {
-                                cx.shared.tx_state.lock(|state| {
-                                    *state = TxState::WaitingForAck {
-                                        seq_num: current_seq,
-                                        timeout_counter: ACK_TIMEOUT_SECS,
-                                        retry_count: 0,
-                                    };
-                                });
-                                defmt::info!("State: WaitingForAck ({}s timeout)", ACK_TIMEOUT_SECS);
+
+                                // Send \r\n terminator
+                                let _ = nb::block!(uart.write(b'\r'));
+                                let _ = nb::block!(uart.write(b'\n'));
+
+                                defmt::info!("Binary TX [{}]: {} bytes sent, packet #{}",
+                                    trigger_source, total_len, current_seq);
+                                brownout::note_sent(current_seq);
+                                eeprom::note_sent();
+                                linkstats::note_sent();
+
+                                tx_success = true;
+                                sent_len = total_len;
+                                sent_packet = Some(binary_packet);
+                            }
+                            Err(_) => {
+                                defmt::error!("Binary serialization failed!");
                             }
                         }
-                    });
+                    }));
+
+                    // Transition to WaitingForAck state (outside uart lock)
+                    if tx_success {
+                        // Before the state change, so an ACK can't be overwritten by "wait"
+                        update_link(&mut cx.shared.status, |link| {
+                            *link = LinkStatus { seq_num: current_seq, result: TxResult::Waiting, attempt: 1 };
+                        });
+                        let sent_at_ms = now_ms();
+                        cx.shared.airtime.lock(|airtime| airtime.record(sent_at_ms, sent_len));
+                        // Stored pending its ACK, for replay if it never comes
+                        if let Some(packet) = sent_packet {
+                            cx.shared.backlog.lock(|backlog| backlog.sent_live(uptime_s(), &packet));
+                        }
+                        cx.shared.tx_state.lock(|state| {
+                            *state = TxState::WaitingForAck {
+                                seq_num: current_seq,
+                                sent_at_ms,
+                                deadline_ms: sent_at_ms.wrapping_add(ACK_TIMEOUT_MS),
+                                retry_count: 0,
+                            };
+                        });
+                        defmt::info!("State: WaitingForAck ({}ms timeout)", ACK_TIMEOUT_MS);
+                    }
                 }
-            });
+                let _ = draw_status::spawn();
+            }
         }
+
+        // Radio wake/sleep for this tick, after any TX above
+        let _ = power_manager::spawn();
+        // Starts and ends the night window within a second of its times
+        let _ = apply_panel::spawn();
+
+        // End of the tick: sensors, display and UART writes all completed
+        watchdog::check_in(Checkpoint::Heartbeat);
+        cx.local.watchdog.poll();
     }
 
-    // UART interrupt: Collect incoming bytes for ACK/NACK parsing
-    #[task(binds = UART4, shared = [lora_uart, tx_state], local = [rx_buffer])]
-    fn uart4_handler(mut cx: uart4_handler::Context) {
-        let mut ack_packet: Option<AckPacket> = None;
+    // Health report: CPU load, task run times and lock hold times
+    //
+    // Logged locally and sent to Node 2 as an un-ACKed HealthPacket. Skipped
+    // (until the next period) while a sensor packet awaits its ACK, so the
+    // radio is never transmitting when the ACK arrives.
+    #[task(priority = 1, shared = [lora_uart, tx_state, duty, battery_mode, airtime], local = [reset_cause, meter: LoadMeter = LoadMeter::new(0)])]
+    fn health_report(mut cx: health_report::Context) {
+        let _busy = HEALTH_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = health_report::spawn_after(RADIO_WAIT_MS.millis());
+            return;
+        }
+        let _ = health_report::spawn_after(HEALTH_INTERVAL_SECS.secs());
 
-        // Collect bytes and parse (inside uart lock)
-        cx.shared.lora_uart.lock(|uart| {
-            // Collect bytes into buffer
-            while let Ok(byte) = uart.read() {
-                if cx.local.rx_buffer.push(byte).is_err() {
-                    defmt::warn!("N1 RX buffer full, clearing");
-                    cx.local.rx_buffer.clear();
-                }
+        let now = now_ms();
+        let load = cx.local.meter.take_load(now);
+        let max_isr_us = cpu::report(load, &[&TIM2_STATS, &UART4_STATS, &SHELL_STATS, &HEALTH_STATS, &BUTTON_STATS, &CONFIG_STATS, &ANNOUNCE_STATS, &CALIBRATION_STATS, &DRAW_STATS, &FAULT_STATS, &SUMMARY_STATS, &GNSS_STATS, &SOIL_STATS, &PIR_STATS, &EVENT_STATS, &WAKEUP_STATS, &POWER_STATS, &BATTERY_STATS, &PANEL_STATS, &PVD_STATS, &REPLAY_STATS, &LIFETIME_STATS, &SETTINGS_STATS, &SERVE_STATS, &EXPORT_STATS, &COMMAND_STATS, &TUNNEL_STATS, &FIRMWARE_STATS, &JOIN_STATS, &SF_STATS, &PAIR_STATS, &PING_STATS, &FACTORY_STATS, &LOOPBACK_STATS, &LINK_STATS]);
+        let max_lock_us = metrics::report();
 
-                // Check for complete message (ends with \r\n)
-                if byte == b'\n' && cx.local.rx_buffer.len() >= 2 {
-                    let len = cx.local.rx_buffer.len();
-                    if cx.local.rx_buffer[len - 2] == b'\r' {
-                        // Complete message received
-                        defmt::info!("N1 UART: {} bytes received", cx.local.rx_buffer.len());
+        if !cx.shared.tx_state.lock(|state| *state == TxState::Idle) {
+            debug!("Health packet skipped: waiting for ACK");
+            return;
+        }
 
-                        // Try to parse ACK/NACK
-                        ack_packet = parse_ack_message(cx.local.rx_buffer.as_slice());
+        let health = HealthPacket {
+            uptime_s: now / 1_000,
+            reset_cause: *cx.local.reset_cause as u8,
+            cpu_load_permille: load,
+            max_isr_us: max_isr_us.min(u16::MAX as u32) as u16,
+            max_lock_us: max_lock_us.min(u16::MAX as u32) as u16,
+            battery_mode: cx.shared.battery_mode.lock(|mode| *mode) as u8,
+        };
+
+        let mut frame_buf = [0u8; 32];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_HEALTH, &health, &mut frame_buf) else {
+            defmt::error!("Health packet serialization failed!");
+            return;
+        };
+        // Over the duty cycle: skip it, the next one is already scheduled
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
 
-                        // Clear buffer for next message
-                        cx.local.rx_buffer.clear();
-                    }
-                }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        defmt::info!("Health TX: {} bytes", frame.len());
+    }
+
+    // Node info: tell the gateway which sensors this node has, and which
+    // boot this is and why it happened, once after boot
+    //
+    // Not ACKed; the gateway only logs it. Sent before the first sensor
+    // packet (one TX interval later), so the radio is free.
+    #[task(priority = 1, shared = [lora_uart, duty, airtime])]
+    fn announce(mut cx: announce::Context, info: NodeInfo) {
+        let _busy = ANNOUNCE_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = announce::spawn_after(RADIO_WAIT_MS.millis(), info);
+            return;
+        }
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_NODE_INFO, &info, &mut frame_buf) else {
+            defmt::error!("Node info serialization failed!");
+            return;
+        };
+        match charge_airtime(&mut cx.shared.airtime, frame.len()) {
+            Admit::Now => {}
+            Admit::After(ms) => {
+                let _ = announce::spawn_after((ms as u64).millis(), info);
+                return;
             }
+            Admit::Never => return,
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        defmt::info!("Node info TX: sensors {=u8:b}, boot #{}", info.sensors, info.boot_count);
+    }
 
-            // Check and clear error flags
-            let uart_ptr = unsafe { &*pac::UART4::ptr() };
-            let sr = uart_ptr.sr().read();
+    // Sensor fault: tell the gateway why this cycle has no reading
+    //
+    // Spawned by tim2 in place of a sensor packet, so the radio is free (and
+    // already claimed for the sample). Not
+    // ACKed; if it's lost, the next cycle's reading or fault replaces it.
+    #[task(priority = 1, shared = [lora_uart, airtime])]
+    fn report_fault(mut cx: report_fault::Context, fault: SensorFault) {
+        let _busy = FAULT_STATS.enter();
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_SENSOR_FAULT, &fault.packet(), &mut frame_buf) else {
+            defmt::error!("Sensor fault serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        defmt::info!("Sensor fault TX: {}", fault);
+    }
 
-            if sr.ore().bit_is_set() || sr.nf().bit_is_set() || sr.fe().bit_is_set() {
-                let _ = uart_ptr.dr().read();
-                defmt::warn!("N1 UART4 errors cleared (ORE={} NF={} FE={})",
-                    sr.ore().bit_is_set(), sr.nf().bit_is_set(), sr.fe().bit_is_set());
+    // Hourly summary: min/max/mean of every sample since the last one
+    //
+    // Not ACKed. While a sensor packet awaits its ACK the summary is retried
+    // shortly rather than dropped like a health packet.
+    #[task(priority = 1, shared = [lora_uart, tx_state, summary, duty, airtime])]
+    fn summary_report(mut cx: summary_report::Context) {
+        let _busy = SUMMARY_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = summary_report::spawn_after(RADIO_WAIT_MS.millis());
+            return;
+        }
+        if !cx.shared.tx_state.lock(|state| *state == TxState::Idle) {
+            debug!("Summary deferred: waiting for ACK");
+            let _ = summary_report::spawn_after(SUMMARY_RETRY_SECS.secs());
+            return;
+        }
+        // Checked at the largest size before the period is taken: an hour's
+        // statistics are worth waiting for. One that can never fit stays in
+        // the accumulator until the limit changes.
+        let now = now_ms();
+        match cx.shared.airtime.lock(|airtime| airtime.check(now, SUMMARY_FRAME_LEN)) {
+            Admit::Now => {}
+            Admit::After(ms) => {
+                let _ = summary_report::spawn_after((ms as u64).millis());
+                return;
             }
-        });
+            Admit::Never => {
+                let _ = summary_report::spawn_after(SUMMARY_INTERVAL_SECS.secs());
+                return;
+            }
+        }
+        let _ = summary_report::spawn_after(SUMMARY_INTERVAL_SECS.secs());
+
+        let Some(packet) = cx.shared.summary.lock(|summary| summary.take(now)) else {
+            remote!(warn, "Summary skipped: no readings this period");
+            return;
+        };
+
+        let mut frame_buf = [0u8; SUMMARY_FRAME_LEN];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_SUMMARY, &packet, &mut frame_buf) else {
+            defmt::error!("Summary serialization failed!");
+            return;
+        };
+        cx.shared.airtime.lock(|airtime| airtime.record(now, frame.len()));
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        defmt::info!("Summary TX: {} samples over {}s, {} bytes", packet.samples, packet.period_s, frame.len());
+    }
 
-        // Handle ACK/NACK state transitions (outside uart lock)
-        if let Some(ack_pkt) = ack_packet {
-            if ack_pkt.msg_type == MSG_TYPE_ACK {
-                defmt::info!("ACK received for packet #{}", ack_pkt.seq_num);
+    // Link stats: this end's counters for Node 2 (see linkstats.rs)
+    //
+    // Not ACKed. Held back like the summary while a sensor packet awaits
+    // its ACK; over the duty cycle it is skipped, the next carries the same
+    // counters further on.
+    #[task(priority = 1, shared = [lora_uart, tx_state, duty, airtime])]
+    fn link_report(mut cx: link_report::Context) {
+        let _busy = LINK_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = link_report::spawn_after(RADIO_WAIT_MS.millis());
+            return;
+        }
+        if !cx.shared.tx_state.lock(|state| *state == TxState::Idle) {
+            let _ = link_report::spawn_after(SUMMARY_RETRY_SECS.secs());
+            return;
+        }
+        let _ = link_report::spawn_after(LINK_INTERVAL_SECS.secs());
+
+        let stats = linkstats::ours(uptime_s());
+        let mut frame_buf = [0u8; 40];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_LINK_STATS, &stats, &mut frame_buf) else {
+            defmt::error!("Link stats serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        defmt::info!("Link stats TX: sent {}, ACKed {}, retries {}", stats.frames_sent, stats.acks_received, stats.retries);
+    }
 
-                // Check if this ACK matches what we're waiting for
-                cx.shared.tx_state.lock(|state| {
-                    if let TxState::WaitingForAck { seq_num, .. } = *state {
-                        if ack_pkt.seq_num == seq_num {
-                            defmt::info!("State: Idle (ACK matched, transmission successful)");
+    // PIR motion: debounce and rate-limit, then send without waiting for tim2
+    #[task(binds = EXTI4, priority = 2, local = [pir, limiter: motion::Limiter = motion::Limiter::new()])]
+    fn pir_handler(cx: pir_handler::Context) {
+        let _busy = PIR_STATS.enter();
+        cx.local.pir.clear_interrupt_pending_bit();
+        if let Some(event) = cx.local.limiter.trigger(now_ms()) {
+            let _ = send_event::spawn(event);
+        }
+    }
+
+    // Event packet: sent ahead of tim2's work, not ACKed
+    //
+    // Priority 2 so it preempts a sensor read in tim2; the radio lock still
+    // waits out a frame tim2 is already writing. While a sensor packet awaits
+    // its ACK the event is held back briefly so it can't collide with it.
+    #[task(priority = 2, capacity = 2, shared = [lora_uart, tx_state, duty, airtime])]
+    fn send_event(mut cx: send_event::Context, event: EventPacket) {
+        let _busy = EVENT_STATS.enter();
+        if !cx.shared.tx_state.lock(|state| *state == TxState::Idle) {
+            let _ = send_event::spawn_after(EVENT_RETRY_MS.millis(), event);
+            return;
+        }
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = send_event::spawn_after(RADIO_WAIT_MS.millis(), event);
+            return;
+        }
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_EVENT, &event, &mut frame_buf) else {
+            defmt::error!("Event serialization failed!");
+            return;
+        };
+        match charge_airtime(&mut cx.shared.airtime, frame.len()) {
+            Admit::Now => {}
+            Admit::After(ms) => {
+                let _ = send_event::spawn_after((ms as u64).millis(), event);
+                return;
+            }
+            Admit::Never => return,
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        defmt::info!("Event TX: {}", event);
+    }
+
+    // Backlog replay: one stored reading per run, oldest first
+    //
+    // Spawned by uart4 when an ACK arrives while readings are stored; the
+    // replayed reading waits for its own ACK in `tx_state` like a live one,
+    // and that ACK spawns the next run. A reading that times out stays
+    // stored, and the chain picks up again after the next live ACK.
+    #[task(priority = 1, shared = [lora_uart, tx_state, duty, airtime, backlog])]
+    fn replay(mut cx: replay::Context) {
+        let _busy = REPLAY_STATS.enter();
+        if !cx.shared.tx_state.lock(|state| *state == TxState::Idle) {
+            let _ = replay::spawn_after(REPLAY_RETRY_MS.millis());
+            return;
+        }
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = replay::spawn_after(RADIO_WAIT_MS.millis());
+            return;
+        }
+        let Some(stored) = cx.shared.backlog.lock(|backlog| backlog.next(uptime_s())) else {
+            return;
+        };
+        let seq_num = stored.reading.seq_num;
+        let mut frame_buf = [0u8; SENSOR_FRAME_LEN];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_STORED, &stored, &mut frame_buf) else {
+            defmt::error!("Replay serialization failed for #{}", seq_num);
+            cx.shared.backlog.lock(|backlog| backlog.release());
+            return;
+        };
+        match charge_airtime(&mut cx.shared.airtime, frame.len()) {
+            Admit::Now => {}
+            Admit::After(ms) => {
+                cx.shared.backlog.lock(|backlog| backlog.release());
+                let _ = replay::spawn_after((ms as u64).millis());
+                return;
+            }
+            Admit::Never => {
+                cx.shared.backlog.lock(|backlog| backlog.release());
+                return;
+            }
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        let sent_at_ms = now_ms();
+        cx.shared.tx_state.lock(|state| {
+            *state = TxState::WaitingForAck {
+                seq_num,
+                sent_at_ms,
+                deadline_ms: sent_at_ms.wrapping_add(ACK_TIMEOUT_MS),
+                retry_count: 0,
+            };
+        });
+        defmt::info!("Replay TX: packet #{} ({} bytes, age {}s)", seq_num, frame.len(), stored.age_s);
+    }
+
+    // Replay on request: the readings Node 2 asked for, one batch per run
+    //
+    // Spawned by uart4 for a `ReplayRequest`. Batches aren't ACKed: each
+    // run queues the rest of the range `REPLAY_RETRY_MS` later, and Node 2
+    // asks again for whatever still didn't arrive. Readings no longer in
+    // flash, or from before the last reset, are skipped.
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, tx_state, duty, airtime, backlog])]
+    fn serve_replay(mut cx: serve_replay::Context, request: ReplayRequest) {
+        let _busy = SERVE_STATS.enter();
+        if !cx.shared.tx_state.lock(|state| *state == TxState::Idle) {
+            let _ = serve_replay::spawn_after(REPLAY_RETRY_MS.millis(), request);
+            return;
+        }
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = serve_replay::spawn_after(RADIO_WAIT_MS.millis(), request);
+            return;
+        }
+
+        // One lock per lookup, so uart4 isn't held off for the whole scan
+        let now_s = uptime_s();
+        let mut batch = ReplayBatch { readings: Vec::new() };
+        let mut frame_buf = [0u8; REPLAY_FRAME_LEN];
+        let mut next = Some(request.from);
+        while let Some(seq) = next.filter(|_| !batch.readings.is_full()) {
+            if let Some(stored) = cx.shared.backlog.lock(|backlog| backlog.lookup(seq, now_s)) {
+                let _ = batch.readings.push(stored);
+                if batch.readings.len() > 1 && encode_frame(config::node_id(), MSG_TYPE_REPLAY_BATCH, &batch, &mut frame_buf).is_err() {
+                    // Leads the next batch instead
+                    batch.readings.pop();
+                    break;
+                }
+            }
+            next = (seq != request.to).then(|| seq.wrapping_add(1));
+        }
+        if batch.readings.is_empty() {
+            defmt::info!("Replay: none of #{}..={} in flash", request.from, request.to);
+            return;
+        }
+
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_REPLAY_BATCH, &batch, &mut frame_buf) else {
+            defmt::error!("Replay batch serialization failed");
+            return;
+        };
+        match charge_airtime(&mut cx.shared.airtime, frame.len()) {
+            Admit::Now => {}
+            Admit::After(ms) => {
+                let _ = serve_replay::spawn_after((ms as u64).millis(), request);
+                return;
+            }
+            Admit::Never => return,
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        defmt::info!("Replay batch TX: {} readings from #{} ({} bytes)",
+            batch.readings.len(), batch.readings[0].reading.seq_num, frame.len());
+        if let Some(from) = next {
+            let _ = serve_replay::spawn_after(REPLAY_RETRY_MS.millis(), ReplayRequest { from, ..request });
+        }
+    }
+
+    /// Claim the radio for a frame, starting its wakeup if it's asleep
+    ///
+    /// `false` means the caller should try again after `RADIO_WAIT_MS`.
+    fn claim_radio(duty: &mut impl rtic::Mutex<T = DutyCycle>) -> bool {
+        let ready = duty.lock(|duty| duty.claim_radio(now_ms()));
+        if !ready {
+            let _ = power_manager::spawn();
+        }
+        ready
+    }
+
+    /// Charge a frame to the duty-cycle budget (see airtime.rs)
+    ///
+    /// Anything but `Admit::Now` means the frame must not be sent yet.
+    fn charge_airtime(airtime: &mut impl rtic::Mutex<T = Accountant>, len: usize) -> Admit {
+        airtime.lock(|airtime| airtime.admit(now_ms(), len))
+    }
+
+    /// Full clock for a burst of work; `power_manager` drops it again
+    fn boost() {
+        if LOW_POWER {
+            power::set_profile(PowerProfile::Performance);
+        }
+    }
+
+    // Power manager: the one place the radio is woken and put to sleep
+    //
+    // Spawned by tim2 every tick, by uart4 when an ACK ends the wait and by
+    // any sender that found the radio asleep; reschedules itself for the end
+    // of the linger. See power.rs for the cycle it drives. Also drops the
+    // core clock back to Economy after a `boost`, unless the menu is open.
+    #[task(priority = 1, shared = [lora_uart, tx_state, duty, menu, pinger])]
+    fn power_manager(mut cx: power_manager::Context) {
+        let _busy = POWER_STATS.enter();
+        let now = now_ms();
+        // In peer mode a reading can come at any time, so the radio stays
+        // up; so it does until a ping is answered or given up on
+        let listening = pair::enabled() || cx.shared.pinger.lock(|pinger| pinger.waiting());
+        let busy = listening || cx.shared.tx_state.lock(|state| *state != TxState::Idle);
+        let (command, recheck_ms) = cx.shared.duty.lock(|duty| {
+            if listening {
+                duty.want_radio();
+            }
+            (duty.poll(now, busy), duty.recheck_in(now))
+        });
+        if let Some(command) = command {
+            cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| write_line(uart, command.at_command())));
+            debug!("Radio {}", command);
+        }
+        if let Some(ms) = recheck_ms {
+            let _ = power_manager::spawn_after((ms as u64).millis());
+        }
+        if LOW_POWER && cx.shared.menu.lock(|menu| menu.is_none()) {
+            power::set_profile(PowerProfile::Economy);
+        }
+    }
+
+    /// Hand a complete frame to the module for Node 2, or the relay `via`
+    /// or the pinned route names
+    fn send_frame(uart: &mut LoraUart, frame: &[u8]) {
+        let mut routed = [0u8; REPLAY_FRAME_LEN];
+        let (to, frame) = relay::uplink(frame, &mut routed);
+        send_frame_to(uart, to, frame);
+    }
+
+    /// Hand a complete frame to the module for the module at `to` itself,
+    /// not by `via` or a route
+    fn send_frame_to(uart: &mut LoraUart, to: u16, frame: &[u8]) {
+        let _tx = powertrace::span(Phase::RadioTx);
+        tune(uart);
+        let mut prefix: String<24> = String::new();
+        let _ = core::write!(prefix, "AT+SEND={},{},", to, frame.len());
+        for b in prefix.as_bytes().iter().chain(frame).chain(b"\r\n") {
+            let _ = nb::block!(uart.write(*b));
+        }
+        linkstats::note_sent();
+    }
+
+    /// Move to the channel Node 2 is on before an uplink (see hopping.rs)
+    fn tune(uart: &mut LoraUart) {
+        if let Some(khz) = hopping::retune(hopping::uplink_khz(now_ms())) {
+            let mut cmd: String<24> = String::new();
+            let _ = core::write!(cmd, "AT+BAND={}000", khz);
+            send_at_command(uart, &cmd);
+        }
+    }
+
+    /// Change the status screen's link line (a no-op before the first sample)
+    fn update_link(status: &mut impl rtic::Mutex<T = Option<Status>>, f: impl FnOnce(&mut LinkStatus)) {
+        status.lock(|status| {
+            if let Some(status) = status {
+                let link = status.link.get_or_insert(LinkStatus { seq_num: 0, result: TxResult::Waiting, attempt: 1 });
+                f(link);
+            }
+        });
+    }
+
+    // Status screen: latest sample, why it was (not) sent, and its ACK
+    //
+    // Spawned after every sample and every ACK/NACK/timeout. The setup
+    // menu owns the screen while it's open.
+    #[task(priority = 1, shared = [display, menu, status, battery_mode, partner, pinger])]
+    fn draw_status(mut cx: draw_status::Context) {
+        let _busy = DRAW_STATS.enter();
+        if cx.shared.menu.lock(|menu| menu.is_some()) {
+            return;
+        }
+        // Panel switched off for the battery, the night or from the shell
+        if !panel_wanted(false, cx.shared.battery_mode.lock(|mode| *mode)) {
+            return;
+        }
+        boost();
+        let Some(status) = cx.shared.status.lock(|status| *status) else { return };
+        let partner = cx.shared.partner.lock(|partner| partner.last()).filter(|_| pair::enabled());
+        let ping = cx.shared.pinger.lock(|pinger| pinger.shown(now_ms()));
+
+        cx.shared.display.lock(|disp: &mut LoraDisplay| {
+            disp.clear_frame();
+            let style = MonoTextStyleBuilder::new()
+                .font(display::FONT)
+                .text_color(BinaryColor::On)
+                .build();
+
+            let mut buf: String<64> = String::new();
+            // Line 1: Temp & Humidity (compact)
+            let _ = core::write!(buf, "T:{} H:{}", Temp(status.temp_c), Humidity(status.humid_pct));
+            Text::new(&buf, Point::new(0, display::ROWS[0]), style).draw(disp).ok();
+
+            buf.clear();
+            // Line 2: Gas resistance and the heater set-point it was read at,
+            // or in peer mode the other node's last reading
+            let _ = match (partner, gas::step(status.heater_step)) {
+                (Some(p), _) => core::write!(buf, "N{} {} {} {}", p.node, Temp(p.temp_c), Humidity(p.humid_pct), p.rssi),
+                (None, Some(step)) => core::write!(buf, "Gas:{} @{}°C", Ohms(status.gas), step.temp_c),
+                (None, None) => core::write!(buf, "Gas:--"),
+            };
+            Text::new(&buf, Point::new(0, display::ROWS[1]), style).draw(disp).ok();
+
+            buf.clear();
+            // Line 3: Node ID, why this sample was sent and the network
+            let _ = core::write!(buf, "{} TX:{} Net:{}", NODE_ID, status.send_as.unwrap_or("--"), status.network_id);
+            Text::new(&buf, Point::new(0, display::ROWS[2]), style).draw(disp).ok();
+
+            buf.clear();
+            // Line 4: Last packet sent, its ACK result and round trip
+            let _ = match status.link {
+                Some(link) => {
+                    let _ = core::write!(buf, "#{:04} ", link.seq_num);
+                    let _ = match link.result {
+                        TxResult::Waiting => core::write!(buf, "wait"),
+                        TxResult::Acked { rtt_ms } => core::write!(buf, "ACK {}ms", rtt_ms),
+                        TxResult::Nacked => core::write!(buf, "NACK"),
+                        TxResult::Lost => core::write!(buf, "LOST"),
+                    };
+                    core::write!(buf, " {}/{}", link.attempt, MAX_RETRIES)
+                }
+                None => core::write!(buf, "No TX yet"),
+            };
+            Text::new(&buf, Point::new(0, display::ROWS[3]), style).draw(disp).ok();
+
+            buf.clear();
+            // Line 5: Countdown to the next sample and battery charge, the
+            // crash notice until the button is pressed, or for a while after
+            // a ping its round trip and RSSI/SNR there and back
+            let _ = match ping {
+                _ if crash::notice() => core::write!(buf, "RECOVERED FROM CRASH"),
+                Some(ping::Outcome::Waiting { id, .. }) => core::write!(buf, "Ping #{} ...", id),
+                Some(ping::Outcome::Echo(e)) => core::write!(buf, "{}ms {}/{} {}/{}",
+                    e.rtt_ms, e.there.rssi, e.there.snr, e.back.rssi, e.back.snr),
+                Some(ping::Outcome::Lost { id, .. }) => core::write!(buf, "Ping #{} no answer", id),
+                None => core::write!(buf, "Next:{}s Bat:{}%", status.next_s, status.battery_pct),
+            };
+            Text::new(&buf, Point::new(0, display::ROWS[4]), style).draw(disp).ok();
+
+            let _ = disp.flush();
+        });
+    }
+
+    // User button: short press transmits now, a second one straight after
+    // pings, long press opens the setup menu, ten seconds resets to
+    // factory settings (see factory.rs)
+    //
+    // Fires on both edges: the press starts timing, the release acts. Same
+    // priority as the TIM2 tick, so the menu never draws over a status
+    // screen halfway through its flush.
+    #[task(binds = EXTI15_10, priority = 1, shared = [display, menu, config, tx_requested, battery_mode], local = [button, last_edge_ms: u32 = 0, pressed_at: Option<u32> = None, last_short_ms: Option<u32> = None])]
+    fn button_handler(mut cx: button_handler::Context) {
+        let _busy = BUTTON_STATS.enter();
+        cx.local.button.clear_interrupt_pending_bit();
+        boost();
+
+        let now = now_ms();
+        if time::elapsed_ms(now, *cx.local.last_edge_ms) < BUTTON_DEBOUNCE_MS {
+            return;
+        }
+        *cx.local.last_edge_ms = now;
+
+        // Active-low: low after the edge means it was just pressed
+        if cx.local.button.is_low() {
+            *cx.local.pressed_at = Some(now);
+            factory::press(now);
+            let _ = factory_hold::spawn_after((factory::SHOW_AFTER_MS as u64).millis(), now);
+            return;
+        }
+        let Some(pressed_at) = cx.local.pressed_at.take() else { return };
+        let long = time::elapsed_ms(now, pressed_at) >= menu::LONG_PRESS_MS;
+        // Let go during the countdown: no reset, and no menu either
+        if factory::release(now) {
+            defmt::info!("Factory reset cancelled");
+            cx.shared.display.lock(|disp: &mut LoraDisplay| {
+                disp.clear_frame();
+                draw_note(disp, "Reset cancelled");
+                let _ = disp.flush();
+            });
+            return;
+        }
+
+        let menu_open = cx.shared.menu.lock(|menu| menu.is_some());
+        let outcome = match (menu_open, long) {
+            // The first short press after a crash only takes the notice down
+            (false, false) if crash::acknowledge() => {
+                defmt::info!("Crash notice acknowledged");
+                let _ = draw_status::spawn();
+                return;
+            }
+            // A second one soon after pings `via`; the first has already
+            // asked for a reading
+            (false, false) if cx.local.last_short_ms.take().is_some_and(|at| time::elapsed_ms(now, at) < DOUBLE_PRESS_MS) => {
+                let _ = send_ping::spawn(relay::upstream());
+                return;
+            }
+            (false, false) => {
+                // Picked up by the next TIM2 tick
+                cx.shared.tx_requested.lock(|requested| *requested = true);
+                *cx.local.last_short_ms = Some(now);
+                return;
+            }
+            (false, true) => {
+                let config = cx.shared.config.lock(|config| *config);
+                cx.shared.menu.lock(|menu| *menu = Some(Menu::open(config, MENU_FIELDS)));
+                defmt::info!("Setup menu opened");
+                None
+            }
+            (true, false) => {
+                cx.shared.menu.lock(|menu| {
+                    if let Some(menu) = menu {
+                        menu.short_press();
+                    }
+                });
+                None
+            }
+            (true, true) => cx.shared.menu.lock(|menu| {
+                let outcome = menu.as_mut()?.long_press();
+                if outcome.is_some() {
+                    *menu = None;
+                }
+                outcome
+            }),
+        };
+
+        let menu = cx.shared.menu.lock(|menu| *menu);
+        cx.shared.display.lock(|disp: &mut LoraDisplay| {
+            disp.clear_frame();
+            match (menu, outcome) {
+                (Some(menu), _) => menu::draw(disp, &menu),
+                // The status screen comes back with the next sample or ACK
+                (None, Some(Outcome::Save(_))) => draw_note(disp, "Settings saved"),
+                (None, _) => draw_note(disp, "Setup cancelled"),
+            }
+            let _ = disp.flush();
+        });
+        // Lights the panel for the menu, or darkens it again once closed
+        let _ = apply_panel::spawn();
+
+        match outcome {
+            Some(Outcome::Save(config)) => {
+                let _ = apply_config::spawn(config);
+            }
+            Some(Outcome::Cancel) => defmt::info!("Setup menu cancelled"),
+            None => {}
+        }
+    }
+
+    fn draw_note(disp: &mut LoraDisplay, text: &str) {
+        let style = MonoTextStyleBuilder::new()
+            .font(display::FONT)
+            .text_color(BinaryColor::On)
+            .build();
+        Text::new(text, Point::new(0, display::ROWS[0]), style).draw(disp).ok();
+    }
+
+    // Low-battery mode change: TX power and display to match
+    //
+    // The TX interval follows the mode in tim2 directly; the health packet
+    // carries it to Node 2.
+    #[task(priority = 1, shared = [lora_uart, duty])]
+    fn apply_battery_mode(mut cx: apply_battery_mode::Context, mode: battery::Mode) {
+        let _busy = BATTERY_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = apply_battery_mode::spawn_after(RADIO_WAIT_MS.millis(), mode);
+            return;
+        }
+        defmt::warn!("Battery mode: {} (interval x{}, {} dBm)", mode, mode.interval_factor(), mode.tx_power_dbm());
+
+        let mut cmd: String<16> = String::new();
+        let _ = core::write!(cmd, "AT+CRFOP={}", mode.tx_power_dbm());
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_at_command(uart, &cmd)));
+        let _ = apply_panel::spawn();
+    }
+
+    // Spreading factor from ADR, or back to the boot one (see adr.rs)
+    //
+    // The airtime budget is charged at the new settings from here on.
+    #[task(priority = 1, shared = [lora_uart, duty, airtime])]
+    fn apply_spreading_factor(mut cx: apply_spreading_factor::Context, sf: u8) {
+        let _busy = SF_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = apply_spreading_factor::spawn_after(RADIO_WAIT_MS.millis(), sf);
+            return;
+        }
+        let params = cx.shared.airtime.lock(|airtime| airtime.params()).with_spreading_factor(sf);
+        defmt::warn!("Spreading factor: SF{}", sf);
+
+        let mut cmd: String<32> = String::new();
+        let _ = cmd.push_str("AT+PARAMETER=");
+        let _ = params.write_parameter(&mut cmd);
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_at_command(uart, &cmd)));
+        cx.shared.airtime.lock(|airtime| airtime.set_params(params));
+    }
+
+    /// Whether the panel should be lit; the setup menu always lights it
+    fn panel_wanted(menu_open: bool, mode: battery::Mode) -> bool {
+        menu_open || (mode.display_on() && panel::wanted_on(uptime_s()))
+    }
+
+    // Panel on/off and contrast from the menu, battery mode and `panel`
+    //
+    // Spawned by every TIM2 tick and whenever one of those changes; the
+    // controller is only written when the result differs from last time.
+    #[task(priority = 1, shared = [display, menu, battery_mode], local = [shown: Option<(bool, u8)> = None])]
+    fn apply_panel(mut cx: apply_panel::Context) {
+        let _busy = PANEL_STATS.enter();
+        let menu_open = cx.shared.menu.lock(|menu| menu.is_some());
+        let mode = cx.shared.battery_mode.lock(|mode| *mode);
+        let wanted = (panel_wanted(menu_open, mode), panel::contrast());
+        let shown = cx.local.shown.replace(wanted);
+        if shown == Some(wanted) {
+            return;
+        }
+        let (on, contrast) = wanted;
+        defmt::info!("Display {} contrast {}", if on { "on" } else { "off" }, contrast);
+        cx.shared.display.lock(|disp: &mut LoraDisplay| {
+            let _ = disp.set_contrast_level(contrast);
+            let _ = disp.set_on(on);
+        });
+        // The status screen isn't drawn while dark, so it may be stale
+        if on && !menu_open && shown.map_or(true, |(was_on, _)| !was_on) {
+            let _ = draw_status::spawn();
+        }
+    }
+
+    /// A saved setting: from the SPI flash store if mounted and it has one,
+    /// else the EEPROM if fitted and it has one, else flash
+    ///
+    /// Falling back carries settings over when a store or EEPROM is first
+    /// fitted; they move across at the next save.
+    fn load_setting<T: DeserializeOwned>(store: &mut Option<Store>, eeprom: &mut Option<Eeprom<I2cProxy>>,
+        kind: Kind, from_flash: fn() -> Option<T>) -> Option<T> {
+        #[cfg(feature = "littlefs")]
+        if let Some(record) = store.as_mut().and_then(|store| store.load(kind)) {
+            return Some(record);
+        }
+        #[cfg(not(feature = "littlefs"))]
+        let _ = store;
+        eeprom.as_mut().and_then(|eeprom| eeprom.load(kind)).or_else(from_flash)
+    }
+
+    /// Drop a setting from the SPI flash store and the EEPROM, whichever are
+    /// fitted, for a factory reset; flash sector 6 is wiped whole
+    fn forget_setting(store: &mut Option<Store>, eeprom: &mut Option<Eeprom<I2cProxy>>, kind: Kind) {
+        #[cfg(feature = "littlefs")]
+        if let Some(Err(e)) = store.as_mut().map(|store| store.forget(kind)) {
+            defmt::error!("{} not removed from SPI flash: {}", kind, e);
+        }
+        #[cfg(not(feature = "littlefs"))]
+        let _ = store;
+        if let Some(Err(e)) = eeprom.as_mut().map(|eeprom| eeprom.forget(kind)) {
+            defmt::error!("{} not removed from EEPROM: {}", kind, e);
+        }
+    }
+
+    /// Save a setting to the SPI flash store if mounted, else the EEPROM if
+    /// fitted, else flash sector 6
+    fn save_setting<T: Serialize>(store: &mut Option<Store>, eeprom: &mut Option<Eeprom<I2cProxy>>, kind: Kind,
+        record: &T, to_flash: fn(&T) -> Result<(), FlashError>) {
+        #[cfg(feature = "littlefs")]
+        if let Some(store) = store.as_mut() {
+            match store.save(kind, record) {
+                Ok(written) => debug!("{} saved to SPI flash (changed: {})", kind, written),
+                Err(e) => defmt::error!("{} save to SPI flash failed: {}", kind, e),
+            }
+            return;
+        }
+        #[cfg(not(feature = "littlefs"))]
+        let _ = store;
+        match eeprom.as_mut() {
+            Some(eeprom) => match eeprom.save(kind, record) {
+                Ok(written) => debug!("{} saved to EEPROM (changed: {})", kind, written),
+                Err(e) => defmt::error!("{} save to EEPROM failed: {}", kind, e),
+            },
+            None => {
+                if let Err(e) = to_flash(record) {
+                    defmt::error!("{} save failed: {}", kind, e);
+                }
+            }
+        }
+    }
+
+    // Remote command from Node 2: check it, remember it, carry it out
+    //
+    // The counter is saved before anything happens, so neither a reset nor
+    // a replayed frame can run the same command twice. See command.rs.
+    #[task(priority = 1, capacity = 2, shared = [config, battery_mode, store, airtime, last_command])]
+    fn remote_command(mut cx: remote_command::Context, packet: CommandPacket, reply: Reply) {
+        let _busy = COMMAND_STATS.enter();
+        let params = cx.shared.airtime.lock(|airtime| airtime.params());
+        let floor_s = airtime::interval_floor_s(&params, SENSOR_FRAME_LEN);
+        let last = cx.shared.last_command.lock(|last| *last);
+        let verdict = command::check(&packet, last, params, floor_s);
+        let status = verdict.map_or_else(Refusal::status, |()| COMMAND_DONE);
+        if status == COMMAND_DONE {
+            if let Err(e) = command::accept(packet.counter) {
+                defmt::error!("Remote command counter not saved: {}", e);
+            }
+            cx.shared.last_command.lock(|last| *last = packet.counter);
+            defmt::info!("Remote command #{}: {}", packet.counter, packet.command);
+            match packet.command {
+                RemoteCommand::SetInterval(seconds) => {
+                    let config = cx.shared.config.lock(|config| NodeConfig { tx_interval_s: seconds, ..*config });
+                    let _ = apply_config::spawn(config);
+                }
+                RemoteCommand::SetTxPower(dbm) => {
+                    // Checked in `command::check`
+                    let _ = battery::set_tx_power_limit(dbm);
+                    adr::note_adjusted();
+                    let _ = apply_battery_mode::spawn(cx.shared.battery_mode.lock(|mode| *mode));
+                    let _ = save_settings::spawn();
+                }
+                RemoteCommand::SetSpreadingFactor(sf) => {
+                    adr::note_adjusted();
+                    let _ = apply_spreading_factor::spawn(sf);
+                }
+                RemoteCommand::ReportNow => {
+                    // Spread over the broadcast's window, like its ACKs
+                    let delay_ms = match reply {
+                        Reply::Result => 0,
+                        Reply::Broadcast { delay_ms, .. } => delay_ms,
+                    };
+                    let _ = report_now::spawn_after(delay_ms.millis());
+                }
+                RemoteCommand::Reboot => {
+                    cx.shared.store.lock(|store| log_event(store, "remote reboot"));
+                    let _ = reboot::spawn_after(REBOOT_DELAY_SECS.secs());
+                }
+                RemoteCommand::SetConfig(change) => {
+                    command::apply_change(&change);
+                    if let Some(seconds) = change.interval_s {
+                        let config = cx.shared.config.lock(|config| NodeConfig { tx_interval_s: seconds, ..*config });
+                        let _ = apply_config::spawn(config);
+                    }
+                    if change.tx_power_dbm.is_some() {
+                        adr::note_adjusted();
+                        let _ = apply_battery_mode::spawn(cx.shared.battery_mode.lock(|mode| *mode));
+                    }
+                    let _ = save_settings::spawn();
+                }
+                RemoteCommand::GetConfig => {
+                    // The report is the answer: below, or after the
+                    // broadcast's delay in place of its ACK
+                    if let Reply::Broadcast { delay_ms, .. } = reply {
+                        let _ = config_report::spawn_after(delay_ms.millis(), packet.counter, status);
+                    }
+                }
+            }
+        }
+        let result = CommandResult { counter: packet.counter, status };
+        if let Err(refusal) = verdict {
+            remote!(warn, "Remote command #{} refused: {}", packet.counter, refusal.name());
+        }
+        match reply {
+            // Spawned after the saves above, which run first
+            Reply::Result => match packet.command {
+                RemoteCommand::SetConfig(change) => {
+                    let kept = cx.shared.config.lock(|config| config.tx_interval_s);
+                    let interval_s = change.interval_s.filter(|_| status == COMMAND_DONE).unwrap_or(kept);
+                    let error = verdict.err().and_then(Refusal::error);
+                    let _ = config_ack::spawn(command::config_ack(packet.counter, status, interval_s, error));
+                }
+                RemoteCommand::GetConfig => {
+                    let _ = config_report::spawn(packet.counter, status);
+                }
+                _ => {
+                    let _ = command_result::spawn(result);
+                }
+            },
+            Reply::Broadcast { .. } if packet.command == RemoteCommand::GetConfig && status == COMMAND_DONE => {}
+            Reply::Broadcast { id, ack: true, delay_ms } => {
+                let _ = broadcast_ack::spawn_after(delay_ms.millis(), BroadcastAck { id, status });
+            }
+            Reply::Broadcast { ack: false, .. } => {}
+        }
+    }
+
+    // Peer mode: ACK the other node's reading straight back, then show it
+    // unless it's a repeat (see pair.rs)
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, duty, airtime, partner])]
+    fn partner_reading(mut cx: partner_reading::Context, node: u8, seq_num: u16, reading: Option<pair::Reading>) {
+        let _busy = PAIR_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = partner_reading::spawn_after(RADIO_WAIT_MS.millis(), node, seq_num, reading);
+            return;
+        }
+        let mut buf = [0u8; 8];
+        let Ok(ack) = encode_ack(&AckPacket { msg_type: MSG_TYPE_ACK, seq_num, node }, None, &mut buf) else { return };
+        if charge_airtime(&mut cx.shared.airtime, ack.len()) == Admit::Now {
+            let mut prefix: String<24> = String::new();
+            let _ = core::write!(prefix, "AT+SEND={},{},", node, ack.len());
+            cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| {
+                for b in prefix.as_bytes().iter().chain(ack).chain(b"\r\n") {
+                    let _ = nb::block!(uart.write(*b));
+                }
+            }));
+            linkstats::note_sent();
+        }
+
+        let Some(reading) = reading else { return };
+        if cx.shared.partner.lock(|partner| partner.record(reading)) {
+            defmt::info!("N{} #{}: {}°C {}% RSSI {}", node, seq_num, reading.temp_c, reading.humid_pct, reading.rssi);
+            let _ = draw_status::spawn();
+        } else {
+            debug!("N{} #{} repeated, ACKed again", node, seq_num);
+        }
+    }
+
+    // `report` from Node 2: sample and send on the next tick, as a short
+    // button press does
+    #[task(priority = 1, shared = [tx_requested])]
+    fn report_now(mut cx: report_now::Context) {
+        let _busy = COMMAND_STATS.enter();
+        cx.shared.tx_requested.lock(|requested| *requested = true);
+    }
+
+    // Tell Node 2 what became of a remote command; not ACKed
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, duty, airtime])]
+    fn command_result(mut cx: command_result::Context, result: CommandResult) {
+        let _busy = COMMAND_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = command_result::spawn_after(RADIO_WAIT_MS.millis(), result);
+            return;
+        }
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_COMMAND_RESULT, &result, &mut frame_buf) else {
+            defmt::error!("Command result serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+    }
+
+    // Tell Node 2 what a `SetConfig` left in force (see command.rs); not
+    // ACKed
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, duty, airtime])]
+    fn config_ack(mut cx: config_ack::Context, ack: ConfigAck) {
+        let _busy = COMMAND_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = config_ack::spawn_after(RADIO_WAIT_MS.millis(), ack);
+            return;
+        }
+        let mut frame_buf = [0u8; 40];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_CONFIG_ACK, &ack, &mut frame_buf) else {
+            defmt::error!("Config ACK serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+    }
+
+    // Tell Node 2 everything that governs what this node sends, as of now
+    // (see command.rs); not ACKed
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, duty, airtime, config, battery_mode])]
+    fn config_report(mut cx: config_report::Context, counter: u32, status: u8) {
+        let _busy = COMMAND_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = config_report::spawn_after(RADIO_WAIT_MS.millis(), counter, status);
+            return;
+        }
+        let config = cx.shared.config.lock(|config| *config);
+        let params = cx.shared.airtime.lock(|airtime| airtime.params());
+        let tx_power_dbm = cx.shared.battery_mode.lock(|mode| mode.tx_power_dbm());
+        let report = command::config_report(counter, status, &config, params, tx_power_dbm);
+        let mut frame_buf = [0u8; 64];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_CONFIG_REPORT, &report, &mut frame_buf) else {
+            defmt::error!("Config report serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+    }
+
+    // Answer a broadcast that asked for it (see broadcast.rs); not ACKed
+    //
+    // Scheduled at the node's own random delay, so answers from several
+    // nodes mostly miss each other.
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, duty, airtime])]
+    fn broadcast_ack(mut cx: broadcast_ack::Context, ack: BroadcastAck) {
+        let _busy = COMMAND_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = broadcast_ack::spawn_after(RADIO_WAIT_MS.millis(), ack);
+            return;
+        }
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_BROADCAST_ACK, &ack, &mut frame_buf) else {
+            defmt::error!("Broadcast ACK serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+    }
+
+    // `ping` or a double press: check the link to `to` (see ping.rs)
+    //
+    // The round trip starts when the frame goes to the module, so a wait
+    // for the radio or the duty cycle doesn't count.
+    #[task(priority = 1, shared = [lora_uart, duty, airtime, pinger, console])]
+    fn send_ping(mut cx: send_ping::Context, to: u16) {
+        let _busy = PING_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = send_ping::spawn_after(RADIO_WAIT_MS.millis(), to);
+            return;
+        }
+        let mut frame_buf = [0u8; 16];
+        if charge_airtime(&mut cx.shared.airtime, frame_buf.len()) != Admit::Now {
+            cx.shared.console.lock(|console| console.write_str("\rping: over the duty cycle\r\n> ").ok());
+            return;
+        }
+        let now = now_ms();
+        let id = cx.shared.pinger.lock(|pinger| pinger.start(to, now));
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_PING, &Ping { id, sent_ms: now }, &mut frame_buf) else {
+            defmt::error!("Ping serialization failed!");
+            return;
+        };
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame_to(uart, to, frame)));
+        cx.shared.console.lock(|console| print_ping(console, ping::Outcome::Waiting { id, to }));
+        let _ = ping_result::spawn_after((ping::TIMEOUT_MS as u64).millis(), id, None);
+        // Keeps the radio up for the answer
+        let _ = power_manager::spawn();
+        let _ = draw_status::spawn();
+    }
+
+    // Answer a ping straight away with how it was heard; not ACKed
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, duty, airtime])]
+    fn answer_ping(mut cx: answer_ping::Context, to: u16, pong: Pong) {
+        let _busy = PING_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = answer_ping::spawn_after(RADIO_WAIT_MS.millis(), to, pong);
+            return;
+        }
+        let mut frame_buf = [0u8; 24];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_PONG, &pong, &mut frame_buf) else {
+            defmt::error!("Pong serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame_to(uart, to, frame)));
+    }
+
+    // A ping's answer, or `TIMEOUT_MS` after it went out with `None`: to
+    // the shell and the status screen, and the radio may sleep again
+    #[task(priority = 1, capacity = 3, shared = [pinger, console])]
+    fn ping_result(mut cx: ping_result::Context, id: u16, echo: Option<Echo>) {
+        let _busy = PING_STATS.enter();
+        let outcome = match echo {
+            Some(echo) => ping::Outcome::Echo(echo),
+            None => match cx.shared.pinger.lock(|pinger| pinger.expire(id, now_ms())) {
+                Some(lost) => lost,
+                // Answered in time
+                None => return,
+            },
+        };
+        defmt::info!("Ping {}", outcome);
+        cx.shared.console.lock(|console| print_ping(console, outcome));
+        let _ = draw_status::spawn();
+        let _ = power_manager::spawn();
+    }
+
+    /// A ping line between shell commands, over the waiting prompt
+    fn print_ping(console: &mut Serial<pac::USART2>, outcome: ping::Outcome) {
+        let _ = console.write_str("\r");
+        let _ = shell::write_ping(console, outcome);
+        let _ = console.write_str("> ");
+    }
+
+    // Firmware upload from `wk3 flash-remote`, relayed by Node 2
+    //
+    // Each frame is written to the SPI flash staging area (see storage.rs)
+    // and answered with where the upload stands, so the host always knows
+    // which chunk to send next. Only the chunk at that offset is taken;
+    // anything else, such as a repeat after a lost status, just gets the
+    // status again. Erasing as the image grows busy-waits for up to 400 ms,
+    // hence the lowest priority.
+    #[task(priority = 1, capacity = 2, shared = [store, last_command], local = [
+        upload: FirmwareStatus = FirmwareStatus { len: 0, id: 0, next: 0, state: FW_IDLE },
+        digest: [u8; FIRMWARE_DIGEST_LEN] = [0; FIRMWARE_DIGEST_LEN],
+    ])]
+    fn firmware_upload(cx: firmware_upload::Context, frame: Firmware) {
+        let _busy = FIRMWARE_STATS.enter();
+        let upload = cx.local.upload;
+        let digest = cx.local.digest;
+        let status = (cx.shared.store, cx.shared.last_command)
+            .lock(|store, last| stage_firmware(store, last, upload, digest, frame));
+        if status.state != FW_RECEIVING {
+            remote!(info, "Firmware {} bytes: {}", status.len, status.state_name());
+        }
+        let _ = firmware_status::spawn(status);
+    }
+
+    // Tell Node 2 where a firmware upload stands; not ACKed
+    #[task(priority = 1, capacity = 2, shared = [lora_uart, duty, airtime])]
+    fn firmware_status(mut cx: firmware_status::Context, status: FirmwareStatus) {
+        let _busy = FIRMWARE_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = firmware_status::spawn_after(RADIO_WAIT_MS.millis(), status);
+            return;
+        }
+        let mut frame_buf = [0u8; 24];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_FW_STATUS, &status, &mut frame_buf) else {
+            defmt::error!("Firmware status serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) != Admit::Now {
+            return;
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+    }
+
+    /// Apply one upload frame to `upload`, the image's `digest` and the
+    /// staging area; a begin must be signed with a counter above `last`
+    fn stage_firmware(
+        store: &mut Option<Store>,
+        last: &mut u32,
+        upload: &mut FirmwareStatus,
+        digest: &mut [u8; FIRMWARE_DIGEST_LEN],
+        frame: Firmware,
+    ) -> FirmwareStatus {
+        #[cfg(feature = "littlefs")]
+        if let Some(store) = store.as_mut() {
+            match frame {
+                Firmware::Begin(begin) => {
+                    // A forged or replayed begin leaves the upload under way alone
+                    if let Err(refusal) = command::check_firmware(&begin, *last) {
+                        remote!(warn, "Firmware begin #{} refused: {}", begin.counter, refusal.name());
+                        return FirmwareStatus { len: begin.len, id: begin.id(), next: 0, state: FW_REFUSED };
+                    }
+                    if let Err(e) = command::accept(begin.counter) {
+                        defmt::error!("Remote command counter not saved: {}", e);
+                    }
+                    *last = begin.counter;
+                    *upload = FirmwareStatus { len: begin.len, id: begin.id(), next: 0, state: FW_RECEIVING };
+                    if begin.len > FIRMWARE_MAX_LEN {
+                        upload.state = FW_BAD_IMAGE;
+                        return *upload;
+                    }
+                    *digest = begin.digest;
+                    match store.stage_resume(begin.len, digest) {
+                        Ok(next) => upload.next = next,
+                        Err(e) => {
+                            defmt::warn!("Firmware staging failed: {}", e);
+                            upload.state = FW_STORE_ERROR;
+                            return *upload;
+                        }
+                    }
+                    if upload.next > 0 {
+                        defmt::info!("Firmware upload resumed at {} of {}", upload.next, upload.len);
+                    }
+                }
+                Firmware::Chunk { offset, data } => {
+                    let fits = upload.len - upload.next >= data.len() as u32;
+                    if upload.state != FW_RECEIVING || offset != upload.next || !fits {
+                        return *upload;
+                    }
+                    if let Err(e) = store.stage_write(offset, &data) {
+                        defmt::warn!("Firmware chunk at {} not written: {}", offset, e);
+                        upload.state = FW_STORE_ERROR;
+                        return *upload;
+                    }
+                    upload.next += data.len() as u32;
+                }
+            }
+            if upload.next == upload.len {
+                upload.state = match store.stage_commit(upload.len, digest) {
+                    Ok(()) => FW_STAGED,
+                    Err(storage::StoreError::Mismatch) => FW_BAD_IMAGE,
+                    Err(e) => {
+                        defmt::warn!("Firmware not staged: {}", e);
+                        FW_STORE_ERROR
+                    }
+                };
+            }
+            return *upload;
+        }
+        let _ = (store, last, digest, frame);
+        FirmwareStatus { state: FW_NO_STORE, ..*upload }
+    }
+
+    // Log tunnel: Node 1's queued log lines to Node 2, un-ACKed
+    //
+    // Runs whether or not `tunnel` is on, so lines queued just before it was
+    // turned off still go. Like the health report it never transmits while
+    // a sensor packet awaits its ACK. A batch the duty cycle defers stays
+    // queued; a queue holding more than one batch sends again straight away.
+    #[task(priority = 1, shared = [lora_uart, tx_state, duty, airtime])]
+    fn log_tunnel(mut cx: log_tunnel::Context) {
+        let _busy = TUNNEL_STATS.enter();
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = log_tunnel::spawn_after(RADIO_WAIT_MS.millis());
+            return;
+        }
+        let idle = cx.shared.tx_state.lock(|state| *state == TxState::Idle);
+        let Some(batch) = tunnel::next_batch().filter(|_| idle) else {
+            let _ = log_tunnel::spawn_after(TUNNEL_INTERVAL_SECS.secs());
+            return;
+        };
+
+        let mut frame_buf = [0u8; 240];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_LOG, &batch, &mut frame_buf) else {
+            defmt::error!("Log batch serialization failed!");
+            tunnel::sent(&batch);
+            let _ = log_tunnel::spawn_after(TUNNEL_INTERVAL_SECS.secs());
+            return;
+        };
+        match charge_airtime(&mut cx.shared.airtime, frame.len()) {
+            Admit::Now => {}
+            Admit::After(ms) => {
+                let _ = log_tunnel::spawn_after((ms as u64).millis());
+                return;
+            }
+            Admit::Never => {
+                tunnel::sent(&batch);
+                let _ = log_tunnel::spawn_after(TUNNEL_INTERVAL_SECS.secs());
+                return;
+            }
+        }
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+        tunnel::sent(&batch);
+        debug!("Log TX: {} lines, {} dropped", batch.lines.len(), batch.dropped);
+
+        let next = if tunnel::pending() > 0 { RADIO_WAIT_MS } else { TUNNEL_INTERVAL_SECS * 1_000 };
+        let _ = log_tunnel::spawn_after(next.millis());
+    }
+
+    #[task(priority = 1)]
+    fn reboot(_: reboot::Context) {
+        defmt::warn!("Rebooting on remote command");
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    // Count a button hold down to a factory reset, then wipe the settings
+    // and reboot on the defaults (see factory.rs)
+    #[task(priority = 1, capacity = 2, shared = [display, store, eeprom])]
+    fn factory_hold(mut cx: factory_hold::Context, pressed_at: u32) {
+        let _busy = FACTORY_STATS.enter();
+        let seconds = match factory::hold(pressed_at, now_ms()) {
+            Hold::Released => return,
+            Hold::Counting(seconds) => Some(seconds),
+            Hold::Due => None,
+        };
+        cx.shared.display.lock(|disp: &mut LoraDisplay| {
+            disp.clear_frame();
+            factory::draw(disp, seconds);
+            let _ = disp.flush();
+        });
+        if seconds.is_some() {
+            let _ = factory_hold::spawn_after(COUNTDOWN_STEP_MS.millis(), pressed_at);
+            return;
+        }
+
+        remote!(warn, "Factory reset from the button");
+        if let Err(e) = config::factory_reset() {
+            defmt::error!("Factory reset of flash failed: {}", e);
+        }
+        (cx.shared.store, cx.shared.eeprom).lock(|store, eeprom| {
+            for kind in [Kind::Config, Kind::Calibration, Kind::Soil] {
+                forget_setting(store, eeprom, kind);
+            }
+        });
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    /// Add a line to the SPI flash event log, if there is one
+    fn log_event(store: &mut Option<Store>, event: &str) {
+        #[cfg(feature = "littlefs")]
+        if let Some(store) = store.as_mut() {
+            if let Err(e) = store.log(now_ms() / 1_000, event) {
+                defmt::warn!("Event log write failed: {}", e);
+            }
+        }
+        #[cfg(not(feature = "littlefs"))]
+        let _ = (store, event);
+    }
+
+    // Save settings from the setup menu and push them to the radio module
+    //
+    // The AT commands pace themselves with 100 ms busy waits while holding
+    // the UART, so ACKs wait about a third of a second. That's acceptable
+    // for a one-off re-provisioning.
+    #[task(priority = 1, shared = [lora_uart, config, duty, eeprom, store])]
+    fn apply_config(mut cx: apply_config::Context, config: NodeConfig) {
+        let _busy = CONFIG_STATS.enter();
+        if let Err(e) = config.check() {
+            defmt::warn!("Config {} refused: {}", config, e);
+            return;
+        }
+        // A sleeping module could miss the first command
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = apply_config::spawn_after(RADIO_WAIT_MS.millis(), config);
+            return;
+        }
+        defmt::info!("Applying config: {}", config);
+        (cx.shared.store, cx.shared.eeprom).lock(|store, eeprom| {
+            save_setting(store, eeprom, Kind::Config, &config, config::save);
+            log_event(store, "config saved");
+        });
+        // The module's "+OK" replies land in uart4 afterwards and are ignored there
+        cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| {
+            config.write_at_commands(|cmd| send_at_command(uart, cmd));
+        }));
+        cx.shared.config.lock(|current| *current = config);
+        airtime::set_band(config.band_mhz);
+        hopping::set_network(config.band_mhz, config.network_id);
+        config::set_node_id(config.address);
+        // Set to Join from the menu
+        if config.address == 0 {
+            let _ = join_request::spawn();
+        }
+    }
+
+    // Ask Node 2 for an address while this node has none (see join.rs)
+    //
+    // Repeats every ten seconds or so until an answer has been saved; a
+    // node without an address sends no readings.
+    #[task(priority = 1, shared = [lora_uart, config, duty, airtime])]
+    fn join_request(mut cx: join_request::Context) {
+        let _busy = JOIN_STATS.enter();
+        if cx.shared.config.lock(|config| config.address) != 0 {
+            return;
+        }
+        if !claim_radio(&mut cx.shared.duty) {
+            let _ = join_request::spawn_after(RADIO_WAIT_MS.millis());
+            return;
+        }
+        let request = JoinRequest { uid: join::uid() };
+        let mut frame_buf = [0u8; 16];
+        let Ok(frame) = encode_frame(config::node_id(), MSG_TYPE_JOIN_REQUEST, &request, &mut frame_buf) else {
+            defmt::error!("Join request serialization failed!");
+            return;
+        };
+        if charge_airtime(&mut cx.shared.airtime, frame.len()) == Admit::Now {
+            cx.shared.lora_uart.lock(|uart| metrics::LORA_UART.measure(|| send_frame(uart, frame)));
+            defmt::info!("Join request TX: uid {=u32:08X}", request.uid);
+        }
+        let _ = join_request::spawn_after((join::retry_ms(request.uid) as u64).millis());
+    }
+
+    // Save a sensor trim, from the shell or a Node 2 downlink
+    //
+    // Takes effect from the next sample. The flash write blocks for a few
+    // tens of microseconds (longer if the sector must be erased), an EEPROM
+    // write about 5 ms, which is why it runs here rather than in the UART ISR.
+    #[task(priority = 1, capacity = 2, shared = [calibration, eeprom, store])]
+    fn apply_calibration(mut cx: apply_calibration::Context, cmd: CalibrationCommand) {
+        let _busy = CALIBRATION_STATS.enter();
+        let Some(calibration) = cx.shared.calibration.lock(|calibration| {
+            calibration.apply_command(&cmd).then_some(*calibration)
+        }) else {
+            defmt::warn!("Calibration for unknown sensor {}", cmd.sensor);
+            return;
+        };
+        defmt::info!("Calibration set: {}", cmd);
+        (cx.shared.store, cx.shared.eeprom).lock(|store, eeprom| {
+            save_setting(store, eeprom, Kind::Calibration, &calibration, calibration::save);
+            log_event(store, "calibration saved");
+        });
+    }
+
+    // Save soil probe endpoints captured in the shell
+    //
+    // Deferred like apply_calibration, so the shell's reply goes out before
+    // a possible sector erase.
+    #[task(priority = 1, shared = [eeprom, store])]
+    fn save_soil(mut cx: save_soil::Context, soil: SoilCalibration) {
+        let _busy = SOIL_STATS.enter();
+        defmt::info!("Soil endpoints set: {}", soil);
+        (cx.shared.store, cx.shared.eeprom).lock(|store, eeprom| {
+            save_setting(store, eeprom, Kind::Soil, &soil, soil::save);
+            log_event(store, "soil endpoints saved");
+        });
+    }
+
+    // Hourly: add this boot's uptime and frame counts to the lifetime counters
+    //
+    // Only whole hours are counted, so with the EEPROM's 32-slot ring each
+    // cell is written once every 32 hours. Without an SPI flash store or an
+    // EEPROM the task stops.
+    #[task(priority = 1, shared = [eeprom, store], local = [lifetime])]
+    fn save_lifetime(cx: save_lifetime::Context) {
+        let _busy = LIFETIME_STATS.enter();
+        let lifetime = cx.local.lifetime.with_session(now_ms() / 1_000);
+        let saved = (cx.shared.store, cx.shared.eeprom).lock(|store, eeprom| {
+            let fitted = store.is_some() || eeprom.is_some();
+            if fitted {
+                save_setting(store, eeprom, Kind::Lifetime, &lifetime, |_| Ok(()));
+            }
+            fitted
+        });
+        if !saved {
+            return;
+        }
+        defmt::info!("Lifetime: {}", lifetime);
+        let _ = save_lifetime::spawn_after(LIFETIME_SAVE_SECS.secs());
+    }
+
+    // Debug shell: echo typed characters and run each completed line
+    //
+    // Same priority as the TIM2 tick, so a keystroke may wait out a sensor
+    // read; that's fine for typing, but paste commands slowly. Input is
+    // dropped unechoed while an export streams.
+    #[task(binds = USART2, priority = 1, shared = [analog, soil, airtime, backlog, console], local = [shell])]
+    fn debug_shell(mut cx: debug_shell::Context) {
+        let _busy = SHELL_STATS.enter();
+        cx.shared.console.lock(|console| while let Ok(byte) = console.read() {
+            if export::active() {
+                continue;
+            }
+            let _ = nb::block!(console.write(byte));
+            if cx.local.shell.push(byte) {
+                let _ = console.write_str("\r\n");
+                match shell::execute(cx.local.shell.line(), console) {
+                    Some(Command::Calibrate(sensor, trim)) => {
+                        let _ = apply_calibration::spawn(calibration::command(sensor, trim));
+                        let _ = shell::write_trim(console, sensor, &trim);
+                    }
+                    Some(Command::ShowSoil) => {
+                        let mv = cx.shared.analog.lock(|analog| analog.soil_mv());
+                        let soil = cx.shared.soil.lock(|soil| *soil);
+                        let _ = shell::write_soil(console, &mv, &soil);
+                    }
+                    Some(Command::SetSoil(probe, endpoint)) => {
+                        let mv = cx.shared.analog.lock(|analog| analog.soil_mv());
+                        let soil = cx.shared.soil.lock(|soil| {
+                            soil.set(probe as usize, endpoint, mv[probe as usize]);
+                            *soil
+                        });
+                        let _ = save_soil::spawn(soil);
+                        let _ = shell::write_soil(console, &mv, &soil);
+                    }
+                    Some(Command::ShowDisplay) => {
+                        let _ = shell::write_display(console, uptime_s());
+                    }
+                    Some(Command::SetDisplay(setting)) => {
+                        setting.apply(uptime_s());
+                        defmt::info!("Display {} from shell", setting);
+                        let _ = apply_panel::spawn();
+                        let _ = shell::write_display(console, uptime_s());
+                    }
+                    Some(Command::SendDisplay(_) | Command::SendRemote(_) | Command::SendAll { .. } | Command::ShowStream | Command::SetStream(_) | Command::SetTap | Command::ShowPeers | Command::ShowAdr | Command::ShowScan) => {
+                        let _ = console.write_str("Node 2 only\r\n");
+                    }
+                    Some(Command::Ping(to)) => {
+                        // The line comes once it's on the air
+                        if send_ping::spawn(to.unwrap_or_else(relay::upstream)).is_err() {
+                            let _ = console.write_str("ping: the last one is still going out\r\n");
+                        }
+                    }
+                    Some(Command::ShowAirtime) => {
+                        let (used_us, refused) = cx.shared.airtime.lock(|airtime| (airtime.used_us(now_ms()), airtime.refused()));
+                        let _ = shell::write_airtime(console, used_us, refused);
+                    }
+                    Some(Command::ShowLinkStats) => {
+                        let _ = shell::write_linkstats(console, uptime_s(), linkstats::last_heard(), now_ms());
+                    }
+                    Some(Command::Export) => {
+                        let begin = ExportBegin {
+                            uptime_s: uptime_s(),
+                            readings: cx.shared.backlog.lock(|backlog| backlog.slots()),
+                            crashes: crash::logged(),
+                        };
+                        if let Some(progress) = Progress::start(begin) {
+                            defmt::info!("Export started: {}", begin);
+                            let mut buf = [0u8; EXPORT_RECORD_LEN];
+                            if let Ok(frame) = export::encode(config::node_id(), export::EXPORT_BEGIN, &begin, &mut buf) {
+                                write_bytes(console, frame);
+                            }
+                            let _ = export_batch::spawn(progress);
+                            // The prompt comes back with the end record
+                            cx.local.shell.clear();
+                            continue;
+                        }
+                    }
+                    _ => {}
+                }
+                let _ = save_settings::spawn();
+                let _ = console.write_str("> ");
+                cx.local.shell.clear();
+            }
+        });
+    }
+
+    fn write_bytes(console: &mut Serial<pac::USART2>, bytes: &[u8]) {
+        for &b in bytes {
+            let _ = nb::block!(console.write(b));
+        }
+    }
+
+    // Export: stream the backlog, then the crash log, to the shell's host
+    //
+    // `EXPORT_BATCH` records per run, then it spawns itself again, so the
+    // tick and the radio get a turn in between (see export.rs). Unreadable
+    // slots are skipped; `ExportEnd` counts what was actually sent.
+    #[task(priority = 1, shared = [backlog, console])]
+    fn export_batch(mut cx: export_batch::Context, mut progress: Progress) {
+        let _busy = EXPORT_STATS.enter();
+        let mut buf = [0u8; EXPORT_RECORD_LEN];
+        for _ in 0..EXPORT_BATCH {
+            let frame = match progress.item() {
+                Item::Reading(slot) => cx.shared.backlog
+                    .lock(|backlog| backlog.stored(slot, progress.begin.uptime_s))
+                    .and_then(|stored| export::encode(config::node_id(), MSG_TYPE_STORED, &stored, &mut buf).ok()),
+                Item::Crash(index) => crash::logged_report(index)
+                    .and_then(|report| export::encode(config::node_id(), MSG_TYPE_CRASH_REPORT, &report, &mut buf).ok()),
+                Item::Done => {
+                    let end = progress.finish();
+                    cx.shared.console.lock(|console| {
+                        if let Ok(frame) = export::encode(config::node_id(), export::EXPORT_END, &end, &mut buf) {
+                            write_bytes(console, frame);
+                        }
+                        let _ = console.write_str("\r\n> ");
+                    });
+                    defmt::info!("Export done: {} records", end.records);
+                    return;
+                }
+            };
+            progress.next += 1;
+            if let Some(frame) = frame {
+                cx.shared.console.lock(|console| write_bytes(console, frame));
+                progress.records += 1;
+            }
+        }
+        let _ = export_batch::spawn(progress);
+    }
+
+    // Save the shell settings and hop channels if a command or downlink
+    // changed any
+    //
+    // Spawned after every shell line, so it only writes when something
+    // differs from the last save.
+    #[task(priority = 1, local = [settings, hop_plan])]
+    fn save_settings(cx: save_settings::Context) {
+        let _busy = SETTINGS_STATS.enter();
+        let hop_plan = hopping::Plan::current();
+        if hop_plan != *cx.local.hop_plan {
+            match hopping::save(&hop_plan) {
+                Ok(()) => *cx.local.hop_plan = hop_plan,
+                Err(e) => defmt::error!("Hop channels save failed: {}", e),
+            }
+        }
+        let settings = Settings::current();
+        if settings == *cx.local.settings {
+            return;
+        }
+        defmt::info!("Saving settings: {}", settings);
+        match config::save_settings(&settings) {
+            Ok(()) => *cx.local.settings = settings,
+            Err(e) => defmt::error!("Settings save failed: {}", e),
+        }
+    }
+
+    // Supply collapsing: save a record and tell Node 2 before the MCU stops
+    //
+    // Above every other task so none of the few milliseconds left are spent
+    // waiting. It writes UART4 directly instead of locking it, cutting off
+    // whatever a preempted task was sending.
+    #[task(binds = PVD, priority = 3)]
+    fn power_fail(_: power_fail::Context) {
+        let _busy = PVD_STATS.enter();
+        brownout::last_gasp(uptime_s());
+    }
+
+    // GNSS module: keep the latest GGA fix for the next sensor packet
+    //
+    // Priority 2 like uart4: at 9600 baud a byte lasts ~1ms, much less than
+    // a tim2 sensor read.
+    #[task(binds = USART1, priority = 2, shared = [gnss], local = [gnss_uart, nmea: nmea::Receiver = nmea::Receiver::new()])]
+    fn gnss_handler(mut cx: gnss_handler::Context) {
+        let _busy = GNSS_STATS.enter();
+        while let Ok(byte) = cx.local.gnss_uart.read() {
+            if let Some(fix) = cx.local.nmea.push(byte) {
+                trace!("GNSS: {}", fix);
+                let now = now_ms();
+                cx.shared.gnss.lock(|gnss| *gnss = Some((fix, now)));
+            }
+        }
+    }
+
+    // Loopback build: hand over what the loopback has to say and let the
+    // UART4 handler take it as if the module had sent it (see loopback.rs)
+    #[cfg(feature = "loopback")]
+    #[task(priority = 1, shared = [lora_uart])]
+    fn loopback_air(mut cx: loopback_air::Context) {
+        let _busy = LOOPBACK_STATS.enter();
+        if cx.shared.lora_uart.lock(|radio| radio.land()) {
+            rtic::pend(pac::Interrupt::UART4);
+        }
+        let _ = loopback_air::spawn_after(LOOPBACK_POLL_MS.millis());
+    }
+
+    // UART interrupt: Collect incoming bytes for ACK/NACK parsing
+    //
+    // Priority 2 (above tim2_handler): the TIM2 tick spends ~250ms in sensor
+    // reads and the display flush, far longer than the 1-byte UART FIFO lasts.
+    #[task(binds = UART4, priority = 2, shared = [lora_uart, tx_state, config, status, backlog, slot_grant, pinger], local = [rx_buffer])]
+    fn uart4_handler(mut cx: uart4_handler::Context) {
+        let _busy = UART4_STATS.enter();
+        let mut downlink: Option<Downlink> = None;
+
+        // Collect bytes and parse (inside uart lock)
+        cx.shared.lora_uart.lock(|uart| {
+            // Collect bytes into buffer
+            while let Ok(byte) = uart.read() {
+                if cx.local.rx_buffer.push(byte).is_err() {
+                    remote!(warn, "N1 RX buffer full, clearing");
+                    cx.local.rx_buffer.clear();
+                }
+
+                // Check for complete message (ends with \r\n)
+                if byte == b'\n' && cx.local.rx_buffer.len() >= 2 {
+                    let len = cx.local.rx_buffer.len();
+                    if cx.local.rx_buffer[len - 2] == b'\r' {
+                        // Complete message received
+                        debug!("N1 UART: {} bytes received", cx.local.rx_buffer.len());
+
+                        // Someone was on the air (see backoff.rs)
+                        if cx.local.rx_buffer.starts_with(b"+RCV=") {
+                            backoff::heard(now_ms());
+                        }
+
+                        // Try to parse ACK/NACK or a downlink command
+                        downlink = parse_downlink(cx.local.rx_buffer.as_slice());
+
+                        // Clear buffer for next message
+                        cx.local.rx_buffer.clear();
+                    }
+                }
+            }
+
+            // Check and clear error flags
+            let uart_ptr = unsafe { &*pac::UART4::ptr() };
+            let sr = uart_ptr.sr().read();
+
+            if sr.ore().bit_is_set() || sr.nf().bit_is_set() || sr.fe().bit_is_set() {
+                let _ = uart_ptr.dr().read();
+                linkstats::note_uart_error();
+                remote!(warn, "N1 UART4 errors cleared (ORE={} NF={} FE={})",
+                    sr.ore().bit_is_set(), sr.nf().bit_is_set(), sr.fe().bit_is_set());
+            }
+        });
+
+        // Handle ACK/NACK state transitions (outside uart lock)
+        let ack_packet = match downlink {
+            Some(Downlink::Ack(ack, slot)) => Some((ack, slot)),
+            Some(Downlink::Calibrate(cmd)) => {
+                if apply_calibration::spawn(cmd).is_err() {
+                    defmt::warn!("Calibration downlink dropped: still saving the last one");
+                }
+                None
+            }
+            Some(Downlink::Display(setting)) => {
+                apply_display(setting);
+                None
+            }
+            Some(Downlink::Replay(mut request)) => {
+                if request.to.wrapping_sub(request.from) >= REPLAY_MAX_RANGE {
+                    request.to = request.from.wrapping_add(REPLAY_MAX_RANGE - 1);
+                }
+                defmt::info!("N2 asks for #{}..={}", request.from, request.to);
+                if serve_replay::spawn(request).is_err() {
+                    remote!(warn, "Replay request dropped: still serving earlier ones");
+                }
+                None
+            }
+            Some(Downlink::Command(packet)) => {
+                if remote_command::spawn(packet, Reply::Result).is_err() {
+                    remote!(warn, "Remote command #{} dropped: still handling earlier ones", packet.counter);
+                }
+                None
+            }
+            Some(Downlink::Broadcast { id, ack, delay_ms, payload }) => {
+                defmt::info!("Broadcast #{} from N2: {}", id, payload);
+                match payload {
+                    broadcast::Payload::Display(setting) => {
+                        apply_display(setting);
+                        if ack {
+                            let _ = broadcast_ack::spawn_after(delay_ms.millis(), BroadcastAck { id, status: COMMAND_DONE });
+                        }
+                    }
+                    broadcast::Payload::Remote(packet) => {
+                        let reply = Reply::Broadcast { id, ack, delay_ms };
+                        if remote_command::spawn(packet, reply).is_err() {
+                            remote!(warn, "Remote command #{} dropped: still handling earlier ones", packet.counter);
+                        }
+                    }
+                }
+                None
+            }
+            Some(Downlink::Firmware(frame)) => {
+                // The host sends again when no status comes back
+                if firmware_upload::spawn(frame).is_err() {
+                    defmt::warn!("Firmware frame dropped: still writing the last one");
+                }
+                None
+            }
+            Some(Downlink::Partner { node, seq_num, reading }) => {
+                if partner_reading::spawn(node, seq_num, reading).is_err() {
+                    defmt::warn!("Reading #{} from N{} dropped: still answering the last one", seq_num, node);
+                }
+                None
+            }
+            Some(Downlink::Ping { from, ping, link }) => {
+                debug!("Ping #{} from {} at {}dBm", ping.id, from, link.rssi);
+                if answer_ping::spawn(from, ping.answer(link.rssi, link.snr)).is_err() {
+                    defmt::warn!("Ping #{} from {} dropped: still answering the last one", ping.id, from);
+                }
+                None
+            }
+            Some(Downlink::Pong { from, pong, link }) => {
+                let echo = cx.shared.pinger.lock(|pinger| pinger.answered(from, &pong, link, now_ms()));
+                match echo {
+                    Some(echo) => {
+                        let _ = ping_result::spawn(echo.id, Some(echo));
+                    }
+                    None => debug!("Pong #{} from {} not for the ping out", pong.id, from),
+                }
+                None
+            }
+            Some(Downlink::LinkStats { node, stats }) => {
+                linkstats::keep(linkstats::heard(node, stats, now_ms()));
+                None
+            }
+            Some(Downlink::Join(accept)) => {
+                let config = cx.shared.config.lock(|config| *config);
+                // A repeat answer to a request sent before the first arrived
+                if config.address == 0 {
+                    defmt::info!("Joined as node {} (network {}, every {}s)", accept.address, accept.network_id, accept.tx_interval_s);
+                    let _ = apply_config::spawn(NodeConfig {
+                        address: accept.address,
+                        network_id: accept.network_id,
+                        tx_interval_s: accept.tx_interval_s,
+                        ..config
+                    });
+                }
+                None
+            }
+            None => None,
+        };
+        if let Some((ack_pkt, slot)) = ack_packet {
+            let now = now_ms();
+            if ack_pkt.msg_type == MSG_TYPE_ACK {
+                remote!(info, "ACK received for packet #{}", ack_pkt.seq_num);
+
+                // Check if this ACK matches what we're waiting for
+                let matched = cx.shared.tx_state.lock(|state| {
+                    if let TxState::WaitingForAck { seq_num, sent_at_ms, retry_count, .. } = *state {
+                        if ack_pkt.seq_num == seq_num {
+                            let rtt_ms = time::elapsed_ms(now, sent_at_ms);
+                            remote!(info, "State: Idle (ACK matched, RTT {}ms)", rtt_ms);
                             *state = TxState::Idle;
+                            return Some((sent_at_ms, rtt_ms, retry_count));
                         } else {
-                            defmt::warn!("ACK seq mismatch: expected {}, got {}", seq_num, ack_pkt.seq_num);
+                            remote!(warn, "ACK seq mismatch: expected {}, got {}", seq_num, ack_pkt.seq_num);
                         }
                     }
+                    None
                 });
+                if let Some((sent_at_ms, rtt_ms, retries)) = matched {
+                    linkstats::note_ack();
+                    adr::note_answered();
+                    hopping::note_answered();
+                    // A retry went out late, so only a first attempt shows
+                    // where the samples land, and what Node 2's clock reads
+                    if let (0, Some(grant)) = (retries, slot) {
+                        cx.shared.slot_grant.lock(|slot_grant| *slot_grant = Some(grant));
+                        hopping::sync(grant.rx_ms, sent_at_ms);
+                    }
+                    update_link(&mut cx.shared.status, |link| {
+                        if link.seq_num == ack_pkt.seq_num {
+                            link.result = TxResult::Acked { rtt_ms };
+                        }
+                    });
+                    let _ = draw_status::spawn();
+                    // The link is up: send whatever piled up while it wasn't
+                    if cx.shared.backlog.lock(|backlog| backlog.acked(ack_pkt.seq_num)) {
+                        let _ = replay::spawn();
+                    }
+                    // Start the linger now rather than on the next tick
+                    let _ = power_manager::spawn();
+                }
             } else if ack_pkt.msg_type == MSG_TYPE_NACK {
-                defmt::warn!("NACK received for packet #{}", ack_pkt.seq_num);
+                remote!(warn, "NACK received for packet #{}", ack_pkt.seq_num);
 
-                // NACK means CRC failed - should retry
-                cx.shared.tx_state.lock(|state| {
-                    if let TxState::WaitingForAck { seq_num, retry_count, .. } = *state {
+                // NACK means CRC failed - should retry; Some(gave up) if it was ours
+                let nacked = cx.shared.tx_state.lock(|state| {
+                    if let TxState::WaitingForAck { seq_num, sent_at_ms, retry_count, .. } = *state {
                         if ack_pkt.seq_num == seq_num {
                             if retry_count < MAX_RETRIES {
-                                defmt::warn!("Will retry packet #{}", seq_num);
+                                remote!(warn, "Will retry packet #{}", seq_num);
                                 // Reset timeout for retry
                                 *state = TxState::WaitingForAck {
                                     seq_num,
-                                    timeout_counter: 0, // Trigger immediate retry
+                                    sent_at_ms,
+                                    deadline_ms: now, // Trigger immediate retry
                                     retry_count: retry_count + 1,
                                 };
                             } else {
-                                defmt::error!("Max retries reached after NACK");
+                                remote!(error, "Max retries reached after NACK");
                                 *state = TxState::Idle;
+                                return Some(true);
                             }
+                            return Some(false);
                         }
                     }
+                    None
                 });
+                if let Some(gave_up) = nacked {
+                    if gave_up {
+                        eeprom::note_gave_up();
+                        cx.shared.backlog.lock(|backlog| backlog.lost(ack_pkt.seq_num));
+                    }
+                    update_link(&mut cx.shared.status, |link| {
+                        if link.seq_num == ack_pkt.seq_num {
+                            link.result = TxResult::Nacked;
+                        }
+                    });
+                    let _ = draw_status::spawn();
+                }
             }
         }
     }
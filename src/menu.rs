@@ -0,0 +1,142 @@
+//! One-button setup menu for re-provisioning a unit in the field
+//!
+//! A long press (see `LONG_PRESS_MS`) on the user button opens the menu. In
+//! the menu a short press steps the selected value through its allowed
+//! settings and a long press moves to the next field. The last row is
+//! Save/Cancel: a short press toggles between them and a long press leaves
+//! the menu with that choice. The node then writes the new settings to
+//! flash (`config::save`) and to the radio module.
+//!
+//! Values cycle through short lists rather than counting freely, since a
+//! single button makes large ranges tedious.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::config::NodeConfig;
+use crate::display::{Canvas, ROWS};
+use crate::region;
+use crate::ui;
+
+/// Press duration that counts as a long press
+pub const LONG_PRESS_MS: u32 = 800;
+
+/// 0 is "Join": ask Node 2 for an address (see `join`)
+const ADDRESSES: core::ops::RangeInclusive<u16> = 0..=16;
+/// `AT+NETWORKID` values the module accepts
+pub const NETWORK_IDS: [u8; 14] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 18];
+const TX_INTERVALS_S: [u16; 7] = [5, 10, 15, 30, 60, 120, 300];
+
+/// An editable setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Field {
+    Address,
+    Network,
+    Band,
+    Interval,
+}
+
+impl Field {
+    fn label(self) -> &'static str {
+        match self {
+            Field::Address => "Addr",
+            Field::Network => "Net",
+            Field::Band => "Band",
+            Field::Interval => "TX every",
+        }
+    }
+
+    /// Next allowed value after the current one, wrapping
+    fn step(self, config: &mut NodeConfig) {
+        match self {
+            Field::Address => {
+                let next = config.address.saturating_add(1);
+                config.address = if ADDRESSES.contains(&next) { next } else { *ADDRESSES.start() };
+            }
+            Field::Network => config.network_id = next_in(&NETWORK_IDS, config.network_id),
+            Field::Band => config.band_mhz = next_in(region::bands_mhz(), config.band_mhz),
+            Field::Interval => config.tx_interval_s = next_in(&TX_INTERVALS_S, config.tx_interval_s),
+        }
+    }
+
+    fn write_value<W: Write>(self, config: &NodeConfig, out: &mut W) {
+        let _ = match self {
+            Field::Address if config.address == 0 => write!(out, "Join"),
+            Field::Address => write!(out, "{}", config.address),
+            Field::Network => write!(out, "{}", config.network_id),
+            Field::Band => write!(out, "{}MHz", config.band_mhz),
+            Field::Interval => write!(out, "{}s", config.tx_interval_s),
+        };
+    }
+}
+
+/// The list entry after `current` (the first one if `current` isn't listed)
+fn next_in<T: Copy + PartialEq>(list: &[T], current: T) -> T {
+    let index = list.iter().position(|&v| v == current).map_or(0, |i| (i + 1) % list.len());
+    list[index]
+}
+
+/// How the menu was left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Outcome {
+    Save(NodeConfig),
+    Cancel,
+}
+
+#[derive(Clone, Copy)]
+pub struct Menu {
+    fields: &'static [Field],
+    /// Selected row; `fields.len()` is the Save/Cancel row
+    selected: usize,
+    draft: NodeConfig,
+    save: bool,
+}
+
+impl Menu {
+    /// Start editing `config`, offering only `fields`
+    pub fn open(config: NodeConfig, fields: &'static [Field]) -> Self {
+        Self { fields, selected: 0, draft: config, save: true }
+    }
+
+    /// Short press: change the selected value
+    pub fn short_press(&mut self) {
+        match self.fields.get(self.selected) {
+            Some(field) => field.step(&mut self.draft),
+            None => self.save = !self.save,
+        }
+    }
+
+    /// Long press: next field, or leave the menu from the last row
+    pub fn long_press(&mut self) -> Option<Outcome> {
+        if self.selected < self.fields.len() {
+            self.selected += 1;
+            return None;
+        }
+        Some(if self.save { Outcome::Save(self.draft) } else { Outcome::Cancel })
+    }
+}
+
+/// Title row, then a window of rows that keeps the selection visible
+pub fn draw<D: Canvas>(d: &mut D, menu: &Menu) {
+    let visible = ROWS.len() - 1;
+    let rows = menu.fields.len() + 1;
+    let first = (menu.selected + 1).saturating_sub(visible).min(rows.saturating_sub(visible));
+
+    ui::line(d, 0, "SETUP (hold: next)");
+    let mut buf: String<32> = String::new();
+    for (row, index) in (first..rows).take(visible).enumerate() {
+        buf.clear();
+        let _ = buf.push_str(if index == menu.selected { "> " } else { "  " });
+        match menu.fields.get(index) {
+            Some(field) => {
+                let _ = write!(buf, "{:<9}", field.label());
+                field.write_value(&menu.draft, &mut buf);
+            }
+            None => {
+                let _ = buf.push_str(if menu.save { "Save" } else { "Cancel" });
+            }
+        }
+        ui::line(d, row + 1, &buf);
+    }
+}
@@ -0,0 +1,115 @@
+//! Lock hold-time counters (feature `lock-metrics`)
+//!
+//! Under RTIC's stack resource policy a lock never blocks: it raises BASEPRI
+//! to the resource ceiling for as long as the closure runs. The cost of a lock
+//! is therefore paid by every *higher* priority task that becomes pending
+//! during that window. Measuring how long each shared resource is held (in
+//! DWT cycles) gives the worst-case delay it can impose on the tasks above it.
+//!
+//! With the feature disabled `LockStats::measure` compiles down to a plain
+//! call, so call sites don't need their own `cfg`s.
+
+#[cfg(feature = "lock-metrics")]
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(feature = "lock-metrics")]
+use cortex_m::peripheral::DWT;
+
+/// Hold-time statistics for one shared resource
+pub struct LockStats {
+    pub name: &'static str,
+    #[cfg(feature = "lock-metrics")]
+    count: AtomicU32,
+    #[cfg(feature = "lock-metrics")]
+    max_cycles: AtomicU32,
+    #[cfg(feature = "lock-metrics")]
+    total_cycles: AtomicU32,
+}
+
+/// Point-in-time copy of a `LockStats`, reset on read
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct LockSnapshot {
+    pub name: &'static str,
+    pub count: u32,
+    pub max_us: u32,
+    pub avg_us: u32,
+}
+
+impl LockStats {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            #[cfg(feature = "lock-metrics")]
+            count: AtomicU32::new(0),
+            #[cfg(feature = "lock-metrics")]
+            max_cycles: AtomicU32::new(0),
+            #[cfg(feature = "lock-metrics")]
+            total_cycles: AtomicU32::new(0),
+        }
+    }
+
+    /// Run `f` (the body of a `lock` closure) and record how long it took
+    #[inline(always)]
+    pub fn measure<R>(&self, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "lock-metrics")]
+        {
+            let start = DWT::cycle_count();
+            let result = f();
+            let held = DWT::cycle_count().wrapping_sub(start);
+            self.count.fetch_add(1, Ordering::Relaxed);
+            self.total_cycles.fetch_add(held, Ordering::Relaxed);
+            self.max_cycles.fetch_max(held, Ordering::Relaxed);
+            result
+        }
+        #[cfg(not(feature = "lock-metrics"))]
+        {
+            f()
+        }
+    }
+
+    /// Take a snapshot and start a new measurement window
+    ///
+    /// Returns `None` when the feature is disabled.
+    pub fn take(&self) -> Option<LockSnapshot> {
+        #[cfg(feature = "lock-metrics")]
+        {
+            let cycles_per_us = crate::time::core_hz() / 1_000_000;
+            let count = self.count.swap(0, Ordering::Relaxed);
+            let total = self.total_cycles.swap(0, Ordering::Relaxed);
+            let max = self.max_cycles.swap(0, Ordering::Relaxed);
+            Some(LockSnapshot {
+                name: self.name,
+                count,
+                max_us: max / cycles_per_us,
+                avg_us: if count > 0 { total / count / cycles_per_us } else { 0 },
+            })
+        }
+        #[cfg(not(feature = "lock-metrics"))]
+        {
+            None
+        }
+    }
+}
+
+/// `lora_uart`: shared by the UART ISR and the radio TX path
+pub static LORA_UART: LockStats = LockStats::new("lora_uart");
+
+/// `display`: held for the full clear/draw/flush over I2C
+pub static DISPLAY: LockStats = LockStats::new("display");
+
+/// Receive statistics shared between frame processing and the display
+pub static COUNTERS: LockStats = LockStats::new("counters");
+
+/// Log and reset every counter above; returns the longest hold seen (µs)
+///
+/// Always 0 (and logs nothing) without the feature.
+pub fn report() -> u32 {
+    let mut worst = 0;
+    for stats in [&LORA_UART, &DISPLAY, &COUNTERS] {
+        if let Some(snap) = stats.take() {
+            defmt::info!("LOCK {}: n={} max={}us avg={}us", snap.name, snap.count, snap.max_us, snap.avg_us);
+            worst = worst.max(snap.max_us);
+        }
+    }
+    worst
+}
@@ -0,0 +1,189 @@
+//! Bringing the LoRa module up at boot without rewriting what it has
+//!
+//! The module keeps `AT+ADDRESS`, `AT+NETWORKID`, `AT+BAND`,
+//! `AT+PARAMETER` and `AT+CRFOP` across power cycles, so writing them all
+//! on every boot only adds time and flash wear inside the module, and a
+//! reset or brownout in the middle of those writes is how a module ends up
+//! on half its settings. The values last written are kept in their own record of
+//! the config sector. At boot, a setting that matches that record is asked
+//! for (`AT+ADDRESS?`) and only written if the module says otherwise; one
+//! that doesn't match, a new setup menu choice say, is written straight
+//! away. The record is saved again once every write has been answered
+//! `+OK`.
+//!
+//! Hopping, ADR, the scan and the battery mode change the band, parameters
+//! and power at runtime without touching the record; the module then
+//! answers the boot query with something else and gets the setting written
+//! again. Boot power is the TX power limit, so a module left at more than
+//! the region allows (see `region`) is turned down before it sends.
+
+use core::fmt::Write;
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, NodeConfig};
+use crate::flash::FlashError;
+
+/// How long to wait for the module's answer to one command
+pub const REPLY_MS: u32 = 200;
+/// Longest answer kept; anything longer isn't one of ours
+pub const REPLY_LEN: usize = 32;
+
+/// One line from the module
+pub type Reply = String<REPLY_LEN>;
+
+/// A setting the module keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Item {
+    Address,
+    NetworkId,
+    Band,
+    Parameter,
+    Power,
+}
+
+impl Item {
+    /// In the order they are written
+    pub const ALL: [Item; 5] = [Item::Address, Item::NetworkId, Item::Band, Item::Parameter, Item::Power];
+
+    /// The AT command's name
+    pub fn name(self) -> &'static str {
+        match self {
+            Item::Address => "ADDRESS",
+            Item::NetworkId => "NETWORKID",
+            Item::Band => "BAND",
+            Item::Parameter => "PARAMETER",
+            Item::Power => "CRFOP",
+        }
+    }
+}
+
+/// The module's settings as written, in the module's own text
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Applied {
+    address: String<12>,
+    network_id: String<12>,
+    band: String<12>,
+    parameter: String<12>,
+    power: String<12>,
+}
+
+impl Applied {
+    /// What `config`, the `AT+PARAMETER` value `parameter` and TX power
+    /// `power_dbm` ask for
+    pub fn new(config: &NodeConfig, parameter: &str, power_dbm: u8) -> Self {
+        let mut applied = Self::default();
+        let _ = write!(applied.address, "{}", config.address);
+        let _ = write!(applied.network_id, "{}", config.network_id);
+        let _ = write!(applied.band, "{}000000", config.band_mhz);
+        let _ = applied.parameter.push_str(parameter);
+        let _ = write!(applied.power, "{}", power_dbm);
+        applied
+    }
+
+    pub fn value(&self, item: Item) -> &str {
+        match item {
+            Item::Address => &self.address,
+            Item::NetworkId => &self.network_id,
+            Item::Band => &self.band,
+            Item::Parameter => &self.parameter,
+            Item::Power => &self.power,
+        }
+    }
+}
+
+impl config::Record for Applied {
+    const MAGIC: u32 = 0x4D4F_444D; // "MODM"
+    const VERSION: u8 = 2;
+}
+
+/// The settings last written, if any
+pub fn load() -> Option<Applied> {
+    config::load_record()
+}
+
+/// Save `applied` as what the module now has
+pub fn save(applied: &Applied) -> Result<(), FlashError> {
+    config::save_record(applied)
+}
+
+/// What `bring_up` did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct Report {
+    /// Settings written
+    pub written: u8,
+    /// Writes not answered `+OK`
+    pub failed: u8,
+}
+
+/// Give the module `wanted`, given it was last given `last`; `at` sends
+/// one command and returns the module's answer, if one came
+pub fn bring_up(wanted: &Applied, last: Option<&Applied>, mut at: impl FnMut(&str) -> Option<Reply>) -> Report {
+    let mut report = Report::default();
+    let mut cmd: String<32> = String::new();
+    for item in Item::ALL {
+        let value = wanted.value(item);
+        if last.map(|last| last.value(item)) == Some(value) {
+            cmd.clear();
+            let _ = write!(cmd, "AT+{}?", item.name());
+            let reply = at(&cmd);
+            if reply.as_deref().and_then(|reply| answer(reply, item)) == Some(value) {
+                continue;
+            }
+            defmt::warn!("Module {} is {}, not {}", item.name(), reply.as_deref().unwrap_or("silent"), value);
+        }
+        cmd.clear();
+        let _ = write!(cmd, "AT+{}={}", item.name(), value);
+        report.written += 1;
+        let reply = at(&cmd);
+        if reply.as_deref() != Some("+OK") {
+            defmt::warn!("{}: {}", cmd.as_str(), reply.as_deref().unwrap_or("no answer"));
+            report.failed += 1;
+        }
+    }
+    report
+}
+
+/// The value in a `+ADDRESS=2` answer to a query for `item`
+fn answer(reply: &str, item: Item) -> Option<&str> {
+    reply.strip_prefix('+')?.strip_prefix(item.name())?.strip_prefix('=')
+}
+
+/// Collects UART bytes into the module's answers, passing over frames it
+/// hears meanwhile and its `+READY` at power-up
+pub struct Lines {
+    line: Reply,
+    overflow: bool,
+}
+
+impl Lines {
+    pub const fn new() -> Self {
+        Self { line: String::new(), overflow: false }
+    }
+
+    /// The next byte; an answer when it ends one
+    pub fn push(&mut self, byte: u8) -> Option<Reply> {
+        match byte {
+            b'\r' => None,
+            b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                let overflow = core::mem::take(&mut self.overflow);
+                let ours = line.starts_with('+') && !line.starts_with("+RCV=") && line != "+READY";
+                (ours && !overflow).then_some(line)
+            }
+            _ => {
+                if self.line.push(byte as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Self::new()
+    }
+}
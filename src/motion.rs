@@ -0,0 +1,59 @@
+//! PIR motion events, sent as soon as they happen
+//!
+//! A PIR module (HC-SR501 or similar) on PB4 drives its output high when it
+//! sees movement. Node 1 takes the rising edge on EXTI4 and sends an
+//! `EventPacket` straight away instead of waiting for the next sample, so
+//! Node 2 hears about motion within a second.
+//!
+//! `Limiter` keeps that from flooding the channel: edges closer together
+//! than `DEBOUNCE_MS` are one event, and after a packet further events are
+//! only counted until `MIN_GAP_MS` has passed. The next packet reports how
+//! many were held back, and `count` lets the gateway spot lost packets.
+
+use crate::protocol::{EventPacket, EVENT_MOTION};
+use crate::time;
+
+/// Edges within this of the previous one are the same event
+pub const DEBOUNCE_MS: u32 = 50;
+
+/// Shortest time between two event packets
+pub const MIN_GAP_MS: u32 = 10_000;
+
+pub struct Limiter {
+    last_edge_ms: Option<u32>,
+    last_sent_ms: Option<u32>,
+    /// Events since boot, wrapping
+    count: u16,
+    /// Events not sent since the last packet
+    suppressed: u16,
+}
+
+impl Limiter {
+    pub const fn new() -> Self {
+        Self { last_edge_ms: None, last_sent_ms: None, count: 0, suppressed: 0 }
+    }
+
+    /// A rising edge at `now_ms`; the packet to send, if one is due
+    pub fn trigger(&mut self, now_ms: u32) -> Option<EventPacket> {
+        let bounce = self.last_edge_ms.is_some_and(|t| time::elapsed_ms(now_ms, t) < DEBOUNCE_MS);
+        self.last_edge_ms = Some(now_ms);
+        if bounce {
+            return None;
+        }
+
+        self.count = self.count.wrapping_add(1);
+        if self.last_sent_ms.is_some_and(|t| time::elapsed_ms(now_ms, t) < MIN_GAP_MS) {
+            self.suppressed = self.suppressed.saturating_add(1);
+            return None;
+        }
+        self.last_sent_ms = Some(now_ms);
+        let suppressed = core::mem::take(&mut self.suppressed);
+        Some(EventPacket { kind: EVENT_MOTION, count: self.count, suppressed })
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
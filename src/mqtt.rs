@@ -0,0 +1,23 @@
+//! MQTT bridge output: Node 2 prints readings as `topic<TAB>payload` lines
+//!
+//! With `mqtt on` in Node 2's shell, every live reading is also written to
+//! the debug UART as lines like `sensors/n1/temperature<TAB>27.10`, topic
+//! and payload separated by a single tab (see `protocol::mqtt` for the
+//! topics). A host script reads them from the VCP
+//! and publishes each one, which is all Home Assistant needs. Backfilled
+//! readings are left out, so a broker's retained value is always the
+//! newest. The setting is saved with the other shell settings.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub use crate::protocol::mqtt::{write_lines, Reading, NODE_1};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
@@ -0,0 +1,154 @@
+//! Position from a serial GNSS module's NMEA output
+//!
+//! For range testing, Node 1 can carry a GNSS module (u-blox NEO-6M or
+//! similar, 9600 baud) on USART1. Only the GGA sentence is used: it has the
+//! position, the fix quality and the satellite count, which is all a range
+//! map needs. The latest fix travels with each sensor packet as a
+//! `tlv::GNSS_FIX` record, so Node 2's log pairs every RSSI with where Node
+//! 1 was. Without a module nothing is received and the record is left out.
+//!
+//! Coordinates are kept in 1e-7 degrees as integers, so the module's
+//! precision survives without floats.
+
+use heapless::Vec;
+
+/// Longest sentence NMEA 0183 allows, including `$` and the checksum
+pub const SENTENCE_LEN: usize = 82;
+
+/// Length of an encoded `Fix`
+pub const FIX_LEN: usize = 10;
+
+/// One GGA report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Fix {
+    /// Latitude, 1e-7 degrees, north positive
+    pub lat_e7: i32,
+    /// Longitude, 1e-7 degrees, east positive
+    pub lon_e7: i32,
+    /// GGA fix quality: 0 none, 1 GPS, 2 DGPS, ...; position is 0 without one
+    pub quality: u8,
+    pub satellites: u8,
+}
+
+impl Fix {
+    pub fn has_position(&self) -> bool {
+        self.quality != 0
+    }
+
+    /// `tlv::GNSS_FIX` value: lat, lon (i32 LE), quality, satellites
+    pub fn encode(&self) -> [u8; FIX_LEN] {
+        let mut out = [0u8; FIX_LEN];
+        out[..4].copy_from_slice(&self.lat_e7.to_le_bytes());
+        out[4..8].copy_from_slice(&self.lon_e7.to_le_bytes());
+        out[8] = self.quality;
+        out[9] = self.satellites;
+        out
+    }
+
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        let value: &[u8; FIX_LEN] = value.try_into().ok()?;
+        Some(Fix {
+            lat_e7: i32::from_le_bytes([value[0], value[1], value[2], value[3]]),
+            lon_e7: i32::from_le_bytes([value[4], value[5], value[6], value[7]]),
+            quality: value[8],
+            satellites: value[9],
+        })
+    }
+}
+
+/// Assembles sentences from the module's byte stream
+pub struct Receiver {
+    buf: Vec<u8, SENTENCE_LEN>,
+    overflowed: bool,
+}
+
+impl Receiver {
+    pub const fn new() -> Self {
+        Self { buf: Vec::new(), overflowed: false }
+    }
+
+    /// Add one received byte; returns the fix once a valid GGA completes
+    pub fn push(&mut self, byte: u8) -> Option<Fix> {
+        match byte {
+            b'$' => {
+                self.buf.clear();
+                self.overflowed = false;
+                let _ = self.buf.push(byte);
+                None
+            }
+            b'\r' | b'\n' => {
+                let fix = if self.overflowed {
+                    None
+                } else {
+                    core::str::from_utf8(&self.buf).ok().and_then(parse_gga)
+                };
+                self.buf.clear();
+                fix
+            }
+            _ => {
+                if self.buf.push(byte).is_err() {
+                    self.overflowed = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse a `$xxGGA,...*hh` sentence (any talker), checking its checksum
+pub fn parse_gga(sentence: &str) -> Option<Fix> {
+    let body = sentence.strip_prefix('$')?;
+    let (body, checksum) = body.split_once('*')?;
+    let checksum = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+    if body.bytes().fold(0, |acc, b| acc ^ b) != checksum {
+        return None;
+    }
+
+    let mut fields = body.split(',');
+    if !fields.next()?.ends_with("GGA") {
+        return None;
+    }
+    let _time = fields.next()?;
+    let (lat, ns) = (fields.next()?, fields.next()?);
+    let (lon, ew) = (fields.next()?, fields.next()?);
+    let quality: u8 = fields.next()?.parse().ok()?;
+    let satellites: u8 = fields.next()?.parse().unwrap_or(0);
+
+    let (lat_e7, lon_e7) = if quality == 0 {
+        (0, 0)
+    } else {
+        (signed(degrees_e7(lat)?, ns)?, signed(degrees_e7(lon)?, ew)?)
+    };
+    Some(Fix { lat_e7, lon_e7, quality, satellites })
+}
+
+/// `(d)ddmm.mmmm` to 1e-7 degrees
+fn degrees_e7(field: &str) -> Option<i32> {
+    let (whole, frac) = field.split_once('.').unwrap_or((field, ""));
+    let whole: u32 = whole.parse().ok()?;
+    let (degrees, minutes) = (whole / 100, whole % 100);
+
+    // Minutes in 1e-5, from the first five fraction digits
+    let mut minutes_e5 = minutes as i64 * 100_000;
+    let mut place = 10_000;
+    for digit in frac.bytes().take(5) {
+        minutes_e5 += (digit.checked_sub(b'0').filter(|d| *d <= 9)? as i64) * place;
+        place /= 10;
+    }
+    // 1e-5 minutes = 1e-5 / 60 degrees = (100 / 60) * 1e-7 degrees
+    Some((degrees as i64 * 10_000_000 + minutes_e5 * 5 / 3) as i32)
+}
+
+fn signed(value: i32, hemisphere: &str) -> Option<i32> {
+    match hemisphere {
+        "N" | "E" => Some(value),
+        "S" | "W" => Some(-value),
+        _ => None,
+    }
+}
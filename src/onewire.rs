@@ -0,0 +1,160 @@
+//! Bit-banged 1-Wire bus master on one open-drain GPIO
+//!
+//! Enough of the protocol for DS18B20 probes (see `ds18b20`): reset and
+//! presence, byte I/O and the ROM search that finds every device on the
+//! bus. The pin needs an external 4.7k pull-up to 3.3 V.
+//!
+//! Slot timings are the standard-speed values from Maxim's application note
+//! 126. Each slot runs with interrupts masked, since an ISR landing in the
+//! middle of one corrupts the bit; a slot is at most ~70 µs, the reset pulse
+//! ~1 ms.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use heapless::Vec;
+
+/// 64-bit device ID: family code, serial number, CRC
+pub type Rom = [u8; 8];
+
+const SEARCH_ROM: u8 = 0xF0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// Nothing answered the reset pulse
+    NoPresence,
+    /// A ROM or data block failed its CRC
+    Crc,
+}
+
+/// Maxim/Dallas CRC-8 (poly 0x31 reflected), used for ROMs and scratchpads
+pub fn crc8(data: &[u8]) -> u8 {
+    use crc::{Crc, CRC_8_MAXIM_DOW};
+    const CRC8: Crc<u8> = Crc::<u8>::new(&CRC_8_MAXIM_DOW);
+    CRC8.checksum(data)
+}
+
+pub struct OneWire<P, D> {
+    pin: P,
+    delay: D,
+}
+
+impl<P: InputPin + OutputPin, D: DelayNs> OneWire<P, D> {
+    /// `pin` in open-drain mode, released (high)
+    pub fn new(pin: P, delay: D) -> Self {
+        Self { pin, delay }
+    }
+
+    /// Reset pulse; `Err(NoPresence)` if no device pulled the line low after
+    pub fn reset(&mut self) -> Result<(), Error> {
+        let present = cortex_m::interrupt::free(|_| {
+            let _ = self.pin.set_low();
+            self.delay.delay_us(480);
+            let _ = self.pin.set_high();
+            self.delay.delay_us(70);
+            self.pin.is_low().unwrap_or(false)
+        });
+        self.delay.delay_us(410);
+        if present { Ok(()) } else { Err(Error::NoPresence) }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        cortex_m::interrupt::free(|_| {
+            let _ = self.pin.set_low();
+            self.delay.delay_us(if bit { 6 } else { 60 });
+            let _ = self.pin.set_high();
+            self.delay.delay_us(if bit { 64 } else { 10 });
+        });
+    }
+
+    pub fn read_bit(&mut self) -> bool {
+        cortex_m::interrupt::free(|_| {
+            let _ = self.pin.set_low();
+            self.delay.delay_us(6);
+            let _ = self.pin.set_high();
+            self.delay.delay_us(9);
+            let bit = self.pin.is_high().unwrap_or(false);
+            self.delay.delay_us(55);
+            bit
+        })
+    }
+
+    /// LSB first
+    pub fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0);
+        }
+    }
+
+    pub fn read_byte(&mut self) -> u8 {
+        (0..8).fold(0, |byte, i| byte | (self.read_bit() as u8) << i)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.write_byte(b);
+        }
+    }
+
+    pub fn read_bytes(&mut self, buf: &mut [u8]) {
+        for b in buf {
+            *b = self.read_byte();
+        }
+    }
+
+    /// Every device on the bus, in ROM order, up to `N` of them
+    ///
+    /// The binary-tree search from Maxim application note 187: each pass
+    /// follows the 0 branch at the deepest unexplored conflict.
+    pub fn search<const N: usize>(&mut self) -> Result<Vec<Rom, N>, Error> {
+        let mut found = Vec::new();
+        let mut rom: Rom = [0; 8];
+        // Bit (1-based) where the last pass took the 0 branch; 0 = none left
+        let mut last_discrepancy = 0;
+        loop {
+            self.reset()?;
+            self.write_byte(SEARCH_ROM);
+
+            let mut last_zero = 0;
+            for bit in 1..=64 {
+                let (byte, mask) = ((bit - 1) / 8, 1u8 << ((bit - 1) % 8));
+                let id = self.read_bit();
+                let complement = self.read_bit();
+                let take_one = match (id, complement) {
+                    // Everyone dropped out: a device left mid-search
+                    (true, true) => return Err(Error::NoPresence),
+                    (id, complement) if id != complement => id,
+                    // Devices disagree here
+                    _ => {
+                        let take_one = if bit < last_discrepancy {
+                            rom[byte] & mask != 0
+                        } else {
+                            bit == last_discrepancy
+                        };
+                        if !take_one {
+                            last_zero = bit;
+                        }
+                        take_one
+                    }
+                };
+                if take_one {
+                    rom[byte] |= mask;
+                } else {
+                    rom[byte] &= !mask;
+                }
+                self.write_bit(take_one);
+            }
+
+            if crc8(&rom[..7]) != rom[7] {
+                return Err(Error::Crc);
+            }
+            if found.push(rom).is_err() {
+                break;
+            }
+            last_discrepancy = last_zero;
+            if last_discrepancy == 0 {
+                break;
+            }
+        }
+        Ok(found)
+    }
+}
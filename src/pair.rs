@@ -0,0 +1,80 @@
+//! Symmetric peer mode: two sensor nodes reporting to each other (`pair`)
+//!
+//! The usual deployment has one end sensing and Node 2 receiving. Some
+//! want two sensing ends that each show what the other measures, inside
+//! and outside a building say, with no gateway. Both boards then run the
+//! Node 1 firmware, each with `via` set to the other's address and
+//! `pair on`. Each keeps its own sampling, TX schedule, retries and
+//! backlog as with a gateway, and on top of that:
+//!
+//!  - keeps its radio awake, as the other's readings can come at any time
+//!  - ACKs the other's readings and replayed readings, so the other's
+//!    retries stop and its backlog drains as they would with Node 2
+//!  - takes a repeat of the last reading (its ACK was lost) as a repeat:
+//!    ACKed again, but not shown again
+//!  - shows the other's last reading on its status screen in place of
+//!    the gas line
+//!
+//! Nothing is logged or forwarded; that still needs a gateway.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::protocol::{tlv, SensorDataPacket};
+use crate::raw;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// A reading from the other node, as its status line shows it
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Reading {
+    pub node: u8,
+    pub seq_num: u16,
+    pub temp_c: f32,
+    pub humid_pct: f32,
+    pub rssi: i16,
+}
+
+impl Reading {
+    /// `packet` from `node`, heard at `rssi`; raw ticks are scaled back
+    pub fn new(node: u8, packet: &SensorDataPacket, rssi: i16) -> Self {
+        let flags = tlv::find_u8(&packet.tlv, tlv::FLAGS).unwrap_or(0);
+        let (temp_c, humid_pct) = if flags & raw::flags::RAW_TH != 0 {
+            (raw::temperature_c(packet.temperature as u16), raw::humidity_pct(packet.humidity))
+        } else {
+            (packet.temperature as f32 / 10.0, packet.humidity as f32 / 100.0)
+        };
+        Self { node, seq_num: packet.seq_num, temp_c, humid_pct, rssi }
+    }
+}
+
+/// What this node knows of the other
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Partner {
+    last: Option<Reading>,
+}
+
+impl Partner {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Take `reading`; `false` if it repeats the last one
+    pub fn record(&mut self, reading: Reading) -> bool {
+        let repeat = self.last.is_some_and(|last| last.node == reading.node && last.seq_num == reading.seq_num);
+        self.last = Some(reading);
+        !repeat
+    }
+
+    /// The other node's last reading, if one has come
+    pub fn last(&self) -> Option<Reading> {
+        self.last
+    }
+}
@@ -0,0 +1,145 @@
+//! Display power, contrast and night-time blanking
+//!
+//! A battery receiver saves most by keeping its panel dark, and a unit in a
+//! bedroom shouldn't glow all night. These settings come from the shell
+//! (`display ...`) or, for Node 1, from Node 2 as a `MSG_TYPE_DISPLAY`
+//! downlink carrying a `Setting`. They live in atomics like the other shell
+//! settings and are saved with them (see `config::Settings`), apart from
+//! the clock.
+//!
+//! Neither node has a battery-backed clock, so the night window runs off a
+//! time of day set with `display clock HH:MM`: the offset from uptime is
+//! kept, and until a clock is set there is no night. On a low-power Node 1
+//! the uptime stands still in STOP (see `power`), so its clock falls behind
+//! and wants setting again every day or so.
+//!
+//! The screensaver and the low-battery mode can still darken a panel this
+//! leaves on; the setup menu lights it whatever is set here.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// Contrast after boot, the controllers' usual mid setting
+pub const DEFAULT_CONTRAST: u8 = 0x80;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const SECONDS_PER_DAY: u32 = MINUTES_PER_DAY * 60;
+/// `NIGHT` value for "no night window"
+const NO_NIGHT: u32 = u32::MAX;
+
+static FORCED_OFF: AtomicBool = AtomicBool::new(false);
+static CONTRAST: AtomicU8 = AtomicU8::new(DEFAULT_CONTRAST);
+/// Night window as `start << 16 | end`, minutes of the day
+static NIGHT: AtomicU32 = AtomicU32::new(NO_NIGHT);
+/// Seconds to add to uptime to get the second of the day
+static CLOCK_OFFSET_S: AtomicU32 = AtomicU32::new(0);
+static CLOCK_SET: AtomicBool = AtomicBool::new(false);
+
+/// One change, as typed in the shell or sent over the air
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub enum Setting {
+    /// `false` keeps the panel dark until switched back on
+    Power(bool),
+    /// Controller contrast, 0-255 (OLEDs only)
+    Contrast(u8),
+    /// Blank from the first minute of the day until the second; `None`
+    /// drops the window. A start after the end spans midnight.
+    Night(Option<(u16, u16)>),
+    /// The current minute of the day
+    Clock(u16),
+}
+
+impl Setting {
+    /// Parse the words after `display`: `on`, `off`, `contrast <0-255>`,
+    /// `night <HH:MM> <HH:MM>`, `night off` or `clock <HH:MM>`
+    pub fn parse<'a>(what: &str, mut args: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let setting = match what {
+            "on" => Setting::Power(true),
+            "off" => Setting::Power(false),
+            "contrast" => Setting::Contrast(args.next()?.parse().ok()?),
+            "night" => match args.next()? {
+                "off" => Setting::Night(None),
+                start => Setting::Night(Some((parse_hhmm(start)?, parse_hhmm(args.next()?)?))),
+            },
+            "clock" => Setting::Clock(parse_hhmm(args.next()?)?),
+            _ => return None,
+        };
+        Some(setting)
+    }
+
+    /// Take effect from the next display refresh
+    ///
+    /// `uptime_s` anchors a `Clock` setting; the others ignore it.
+    pub fn apply(&self, uptime_s: u32) {
+        match *self {
+            Setting::Power(on) => FORCED_OFF.store(!on, Ordering::Relaxed),
+            Setting::Contrast(level) => CONTRAST.store(level, Ordering::Relaxed),
+            Setting::Night(None) => NIGHT.store(NO_NIGHT, Ordering::Relaxed),
+            Setting::Night(Some((start, end))) => {
+                NIGHT.store((start as u32) << 16 | end as u32, Ordering::Relaxed)
+            }
+            Setting::Clock(minute) => {
+                let second = minute as u32 % MINUTES_PER_DAY * 60;
+                let offset = (second + SECONDS_PER_DAY - uptime_s % SECONDS_PER_DAY) % SECONDS_PER_DAY;
+                CLOCK_OFFSET_S.store(offset, Ordering::Relaxed);
+                CLOCK_SET.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// `HH:MM` to minutes of the day
+fn parse_hhmm(text: &str) -> Option<u16> {
+    let (hours, minutes) = text.split_once(':')?;
+    let hours: u16 = hours.parse().ok().filter(|&h| h < 24)?;
+    let minutes: u16 = minutes.parse().ok().filter(|&m| m < 60)?;
+    Some(hours * 60 + minutes)
+}
+
+/// Whether the panel should be lit at `uptime_s`, by these settings alone
+pub fn wanted_on(uptime_s: u32) -> bool {
+    !FORCED_OFF.load(Ordering::Relaxed) && !minute_of_day(uptime_s).is_some_and(is_night)
+}
+
+pub fn forced_off() -> bool {
+    FORCED_OFF.load(Ordering::Relaxed)
+}
+
+pub fn contrast() -> u8 {
+    CONTRAST.load(Ordering::Relaxed)
+}
+
+/// The night window as start and end minutes, if one is set
+pub fn night() -> Option<(u16, u16)> {
+    match NIGHT.load(Ordering::Relaxed) {
+        NO_NIGHT => None,
+        packed => Some(((packed >> 16) as u16, packed as u16)),
+    }
+}
+
+/// Minutes since midnight at `uptime_s`, once a clock has been set
+pub fn minute_of_day(uptime_s: u32) -> Option<u16> {
+    if !CLOCK_SET.load(Ordering::Relaxed) {
+        return None;
+    }
+    let second = (uptime_s % SECONDS_PER_DAY + CLOCK_OFFSET_S.load(Ordering::Relaxed)) % SECONDS_PER_DAY;
+    Some((second / 60) as u16)
+}
+
+/// Days since boot, rolling over at midnight once a clock has been set
+///
+/// Setting the clock can move this by one either way.
+pub fn day_number(uptime_s: u32) -> u32 {
+    let offset = if CLOCK_SET.load(Ordering::Relaxed) { CLOCK_OFFSET_S.load(Ordering::Relaxed) } else { 0 };
+    (uptime_s + offset) / SECONDS_PER_DAY
+}
+
+fn is_night(minute: u16) -> bool {
+    match night() {
+        None => false,
+        Some((start, end)) if start <= end => (start..end).contains(&minute),
+        // Spans midnight, e.g. 22:00-07:00
+        Some((start, end)) => minute >= start || minute < end,
+    }
+}
@@ -0,0 +1,157 @@
+//! Node 2's table of the sensor nodes it hears
+//!
+//! Every frame now carries its sender's address (the header's node byte,
+//! set from the setup menu), so one receiver can serve several sensor
+//! nodes. Each one's last reading, sequence number, link figures and when
+//! it was last heard are kept here for the NODES page, which cycles through
+//! them. Node 1 also keeps its full treatment (sequence stats, replay,
+//! logging); other nodes get an ACK and an entry here.
+//!
+//! Per node the table also keeps link statistics: readings heard and
+//! missed (from the sequence numbers), the share lost, and an RSSI average
+//! that follows the trend rather than the last frame's fading. `peers` in
+//! Node 2's shell lists them all. The node's own counters, from its last
+//! `LinkStats` report, are kept with them (see linkstats.rs).
+//!
+//! The table is small; when a new node turns up with it full, the node
+//! heard least recently makes way. Each entry also holds the node's
+//! transmit slot, the lowest free one when it was added (see slots.rs).
+
+use heapless::Vec;
+
+use crate::linkstats::Heard;
+
+/// Nodes tracked at once
+pub const MAX_PEERS: usize = 8;
+
+/// Weight of each new frame in `Peer::rssi_avg`; about the last eight count
+const RSSI_WEIGHT: f32 = 1.0 / 8.0;
+
+/// One node's last reading and link statistics
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct Peer {
+    pub node: u8,
+    pub slot: u8,
+    pub last_seq: u16,
+    pub temperature: f32,
+    pub humidity: f32,
+    pub iaq: Option<u16>,
+    pub rssi: i16,
+    /// Exponentially weighted moving average of `rssi`
+    pub rssi_avg: f32,
+    pub snr: i16,
+    /// Monotonic time of the last reading; compare with `wrapping_sub`
+    pub last_seen_ms: u32,
+    /// Readings heard, and readings skipped going by the sequence numbers
+    pub received: u32,
+    pub missed: u32,
+    /// The node's last report of its own link counters
+    pub link: Option<Heard>,
+}
+
+impl Peer {
+    /// Share of readings missed, in permille (0 before any were)
+    pub fn loss_permille(&self) -> u16 {
+        let total = u64::from(self.received) + u64::from(self.missed);
+        (u64::from(self.missed) * 1000).checked_div(total).unwrap_or(0) as u16
+    }
+
+    /// `rssi_avg` rounded to whole dBm; RSSI is always below zero
+    pub fn rssi_avg_dbm(&self) -> i16 {
+        (self.rssi_avg - 0.5) as i16
+    }
+}
+
+#[derive(Clone)]
+pub struct Peers {
+    peers: Vec<Peer, MAX_PEERS>,
+}
+
+impl Peers {
+    pub const fn new() -> Self {
+        Self { peers: Vec::new() }
+    }
+
+    /// A reading from `node`, heard at `now_ms`
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(&mut self, node: u8, seq: u16, temperature: f32, humidity: f32, iaq: Option<u16>, rssi: i16, snr: i16, now_ms: u32) {
+        let index = match self.peers.iter().position(|p| p.node == node) {
+            Some(index) => index,
+            None => {
+                if self.peers.is_full() {
+                    let stalest = (0..self.peers.len())
+                        .max_by_key(|&i| now_ms.wrapping_sub(self.peers[i].last_seen_ms))
+                        .unwrap_or(0);
+                    defmt::warn!("Peer table full, dropping node {}", self.peers[stalest].node);
+                    self.peers.remove(stalest);
+                }
+                let slot = (0..MAX_PEERS as u8).find(|&slot| self.peers.iter().all(|p| p.slot != slot)).unwrap_or(0);
+                let fresh = Peer {
+                    node,
+                    slot,
+                    last_seq: seq,
+                    temperature,
+                    humidity,
+                    iaq,
+                    rssi,
+                    rssi_avg: rssi as f32,
+                    snr,
+                    last_seen_ms: now_ms,
+                    received: 0,
+                    missed: 0,
+                    link: None,
+                };
+                let _ = self.peers.push(fresh);
+                self.peers.len() - 1
+            }
+        };
+        let peer = &mut self.peers[index];
+        // Forward jumps only; a restart or a repeat isn't a loss
+        let skipped = seq.wrapping_sub(peer.last_seq).wrapping_sub(1);
+        if peer.received > 0 && skipped < u16::MAX / 2 {
+            peer.missed += skipped as u32;
+        }
+        let rssi_avg = peer.rssi_avg + (rssi as f32 - peer.rssi_avg) * RSSI_WEIGHT;
+        *peer = Peer { last_seq: seq, temperature, humidity, iaq, rssi, rssi_avg, snr, last_seen_ms: now_ms, received: peer.received + 1, ..*peer };
+    }
+
+    /// A node's link report, kept with its entry; `false` if it has no
+    /// entry, not having sent a reading yet
+    pub fn record_link(&mut self, heard: Heard) -> bool {
+        match self.peers.iter_mut().find(|p| p.node == heard.node) {
+            Some(peer) => {
+                peer.link = Some(heard);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// `node`'s transmit slot, once it has been heard
+    pub fn slot(&self, node: u8) -> Option<u8> {
+        self.peers.iter().find(|p| p.node == node).map(|p| p.slot)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Entry `index`, in the order the nodes were first heard
+    pub fn get(&self, index: usize) -> Option<&Peer> {
+        self.peers.get(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.iter()
+    }
+}
+
+impl Default for Peers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,118 @@
+//! Link check between two modules (`ping`, or a double press on Node 1)
+//!
+//! Where to put an antenna is easiest to find with a reading taken there
+//! and then, both ways. `ping` sends a `Ping` to one address: Node 1's
+//! goes to `via`, Node 2's to Node 1 unless another address is given. The
+//! module it reaches answers straight away with a `Pong` saying how the
+//! ping was heard, so the sender gets the round trip and the RSSI and SNR
+//! of both directions from the one exchange. A relay answers pings sent
+//! to it rather than passing them on, so each hop can be checked alone.
+//!
+//! The round trip is measured on the sender's clock, carried out in the
+//! ping and back in the pong, and includes both frames' airtime. A sensor
+//! node only hears a ping while its radio is up: right after a reading,
+//! or in peer mode. A ping with no answer within `TIMEOUT_MS` is lost.
+
+use crate::protocol::Pong;
+
+/// Wait for a pong before calling the ping lost
+pub const TIMEOUT_MS: u32 = 5_000;
+/// How long the display shows the last ping's outcome
+pub const SHOW_MS: u32 = 30_000;
+
+/// How one direction was heard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Link {
+    pub rssi: i16,
+    pub snr: i16,
+}
+
+/// A ping that was answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Echo {
+    pub id: u16,
+    /// Address it was sent to
+    pub to: u16,
+    pub rtt_ms: u32,
+    /// How the other end heard the ping
+    pub there: Link,
+    /// How this end heard the pong
+    pub back: Link,
+}
+
+/// Where the last ping stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Outcome {
+    Waiting { id: u16, to: u16 },
+    Echo(Echo),
+    Lost { id: u16, to: u16 },
+}
+
+/// The sending end's pings
+#[derive(Debug, Clone, Copy)]
+pub struct Pinger {
+    next_id: u16,
+    /// The last ping's outcome and when it last changed
+    last: Option<(Outcome, u32)>,
+}
+
+impl Pinger {
+    pub const fn new() -> Self {
+        Self { next_id: 1, last: None }
+    }
+
+    /// A new ping to `to`; the id to send it with
+    pub fn start(&mut self, to: u16, now_ms: u32) -> u16 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.last = Some((Outcome::Waiting { id, to }, now_ms));
+        id
+    }
+
+    /// `pong` came back from `from`, heard at `back`; the echo if it
+    /// answers the ping waiting for one
+    pub fn answered(&mut self, from: u16, pong: &Pong, back: Link, now_ms: u32) -> Option<Echo> {
+        let Some((Outcome::Waiting { id, to }, _)) = self.last else { return None };
+        if pong.id != id || from != to {
+            return None;
+        }
+        let echo = Echo {
+            id,
+            to,
+            rtt_ms: now_ms.wrapping_sub(pong.sent_ms),
+            there: Link { rssi: pong.rssi, snr: pong.snr },
+            back,
+        };
+        self.last = Some((Outcome::Echo(echo), now_ms));
+        Some(echo)
+    }
+
+    /// `TIMEOUT_MS` after ping `id` went out: the outcome if that made it
+    /// lost
+    pub fn expire(&mut self, id: u16, now_ms: u32) -> Option<Outcome> {
+        let Some((Outcome::Waiting { id: waiting, to }, _)) = self.last else { return None };
+        if waiting != id {
+            return None;
+        }
+        let lost = Outcome::Lost { id, to };
+        self.last = Some((lost, now_ms));
+        Some(lost)
+    }
+
+    /// A ping is out and no answer has come back yet
+    pub fn waiting(&self) -> bool {
+        matches!(self.last, Some((Outcome::Waiting { .. }, _)))
+    }
+
+    /// The last ping's outcome, for `SHOW_MS` after it changed
+    pub fn shown(&self, now_ms: u32) -> Option<Outcome> {
+        let (outcome, at_ms) = self.last?;
+        (now_ms.wrapping_sub(at_ms) < SHOW_MS).then_some(outcome)
+    }
+}
+
+impl Default for Pinger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
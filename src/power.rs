@@ -0,0 +1,474 @@
+//! STOP-mode sleep between samples on Node 1, and a slower core clock on
+//! both nodes (feature `low-power`)
+//!
+//! With the core in Sleep (plain WFI) the regulator, PLL and every
+//! peripheral clock stay on, so a node sitting between transmissions still
+//! draws tens of mA. STOP turns off the PLL, HSI and all 1.8 V domain clocks
+//! while keeping SRAM and register contents, which gets the MCU down to a
+//! few hundred µA with the low-power regulator and flash powered down.
+//!
+//! Nothing clocked from the PLL runs in STOP, including TIM2 and SysTick,
+//! so the 1 Hz tick comes from the RTC wakeup timer instead: the RTC runs
+//! from LSI, and its wakeup event on EXTI line 22 is one of the few things
+//! that can bring the core out of STOP (GPIO EXTI lines such as the button
+//! and PIR are the others). The core wakes on HSI, so `stop` restarts the
+//! PLL and switches back to it before returning; peripheral registers are
+//! kept, so nothing else needs setting up again.
+//!
+//! STOP alone still leaves the RYLR998 listening at ~17 mA, so `DutyCycle`
+//! also sleeps the module (`AT+MODE=1`) between uses and wakes it again
+//! before the next frame. Each cycle runs the same way:
+//!
+//!   radio wake -> sensor read -> TX -> ACK wait -> linger -> radio sleep -> MCU STOP
+//!
+//! with the radio woken a tick before the sample is due and kept up for
+//! `RADIO_LINGER_MS` after the last frame or ACK. Every timeout is on the
+//! monotonic, which is only trusted while the radio is up: the MCU doesn't
+//! STOP until the radio is asleep. While the radio sleeps nothing reaches
+//! Node 1 over the air, so downlinks only land in the awake window.
+//!
+//! While awake, `PowerProfile::Economy` halves the core clock for the time
+//! spent waiting (between ticks, on an ACK) and bursts of real work (sensor
+//! reads, display updates) switch back to `Performance` first.
+//!
+//! Separately from all that, `gate_unused` parks whatever hardware a build
+//! doesn't use, on both nodes and with or without `low-power`: pins nothing
+//! drives go to analog mode, where their input buffer and Schmitt trigger
+//! are off and a floating pin stops drawing current, and only the
+//! peripherals that wake the core keep a clock while it sleeps.
+//!
+//! Registers are accessed through raw bit constants like `flash` and
+//! `reset`, since `idle` runs without the HAL objects `init` handed out.
+
+use cortex_m::peripheral::{SCB, SYST};
+use stm32f4xx_hal::pac;
+
+use crate::time;
+
+/// Settle time after `AT+MODE=0` before the module takes a frame
+pub const RADIO_WAKE_MS: u32 = 50;
+
+/// How long the radio stays up after its last frame or ACK wait, for
+/// back-to-back sends and a downlink that follows an ACK
+pub const RADIO_LINGER_MS: u32 = 500;
+
+// RCC bits (RM0390 section 6.3)
+const RCC_CR_PLLON: u32 = 1 << 24;
+const RCC_CR_PLLRDY: u32 = 1 << 25;
+const RCC_CFGR_SW_MASK: u32 = 0b11;
+const RCC_CFGR_SW_PLL: u32 = 0b10;
+const RCC_CFGR_SWS_MASK: u32 = 0b11 << 2;
+const RCC_CFGR_SWS_PLL: u32 = 0b10 << 2;
+const RCC_CFGR_HPRE_MASK: u32 = 0b1111 << 4;
+const RCC_CFGR_HPRE_DIV2: u32 = 0b1000 << 4;
+const RCC_CFGR_PPRE1_MASK: u32 = 0b111 << 10;
+const RCC_CFGR_PPRE1_DIV2: u32 = 0b100 << 10;
+const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+const RCC_BDCR_RTCSEL_LSI: u32 = 0b10 << 8;
+const RCC_BDCR_RTCEN: u32 = 1 << 15;
+const RCC_CSR_LSION: u32 = 1 << 0;
+const RCC_CSR_LSIRDY: u32 = 1 << 1;
+const RCC_AHB1ENR_GPIOD_H: u32 = 0b1_1111 << 3;
+const RCC_AHB1LPENR_GPIOA_C: u32 = 0b111;
+const RCC_AHB1LPENR_FLITF: u32 = 1 << 15;
+const RCC_AHB1LPENR_SRAM1: u32 = 1 << 16;
+const RCC_AHB1LPENR_SRAM2: u32 = 1 << 17;
+const RCC_AHB2LPENR_OTGFS: u32 = 1 << 7;
+
+// PWR_CR bits (RM0390 section 5.4.1)
+const PWR_CR_LPDS: u32 = 1 << 0;
+const PWR_CR_PDDS: u32 = 1 << 1;
+const PWR_CR_DBP: u32 = 1 << 8;
+const PWR_CR_FPDS: u32 = 1 << 9;
+
+// RTC bits (RM0390 section 22.6)
+const RTC_CR_WUCKSEL_MASK: u32 = 0b111;
+const RTC_CR_WUCKSEL_DIV16: u32 = 0b000;
+const RTC_CR_WUTE: u32 = 1 << 10;
+const RTC_CR_WUTIE: u32 = 1 << 14;
+const RTC_ISR_WUTWF: u32 = 1 << 2;
+const RTC_ISR_WUTF: u32 = 1 << 10;
+
+// GPIO register offsets (RM0390 section 7.4)
+const GPIO_MODER: usize = 0x00;
+const GPIO_PUPDR: usize = 0x0C;
+/// PA13/PA14 stay on SWD, so a probe can still attach without reset
+const SWD_PINS: u16 = (1 << 13) | (1 << 14);
+
+// Peripheral register offsets rescaled by `set_profile`
+const TIM_PSC: usize = 0x28;
+const TIM_ARR: usize = 0x2C;
+const USART_BRR: usize = 0x08;
+
+/// EXTI line wired to the RTC wakeup event
+const EXTI_RTC_WAKEUP: u32 = 1 << 22;
+
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
+#[cfg(debug_assertions)]
+const DBGMCU_CR_DBG_STOP: u32 = 1 << 1;
+
+/// Nominal LSI frequency; the real one is 17-47 kHz across parts and
+/// temperature, so the tick (and TX interval) can be off by a few percent
+const LSI_HZ: u32 = 32_000;
+
+/// Start the RTC wakeup timer as a 1 Hz interrupt on `RTC_WKUP`
+///
+/// Runs once from `init`. The RTC is in the backup domain, so this also
+/// enables writes to it (`brownout` keeps its record there too).
+pub fn init_wakeup() {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+    let rtc = unsafe { &*pac::RTC::ptr() };
+    let exti = unsafe { &*pac::EXTI::ptr() };
+
+    rcc.apb1enr().modify(|r, w| unsafe { w.bits(r.bits() | RCC_APB1ENR_PWREN) });
+    pwr.cr().modify(|r, w| unsafe { w.bits(r.bits() | PWR_CR_DBP) });
+
+    rcc.csr().modify(|r, w| unsafe { w.bits(r.bits() | RCC_CSR_LSION) });
+    while rcc.csr().read().bits() & RCC_CSR_LSIRDY == 0 {}
+    // RTCSEL only takes a new value after a backup domain reset; from power
+    // on it's "no clock" and this sets LSI
+    rcc.bdcr().modify(|r, w| unsafe { w.bits(r.bits() | RCC_BDCR_RTCSEL_LSI | RCC_BDCR_RTCEN) });
+
+    // Unlock, stop the wakeup timer and wait until it may be reprogrammed
+    rtc.wpr().write(|w| w.key().bits(0xCA));
+    rtc.wpr().write(|w| w.key().bits(0x53));
+    rtc.cr().modify(|r, w| unsafe { w.bits(r.bits() & !(RTC_CR_WUTE | RTC_CR_WUTIE)) });
+    while rtc.isr().read().bits() & RTC_ISR_WUTWF == 0 {}
+
+    // LSI / 16 = 2 kHz, reload every 2000 counts for one second
+    rtc.wutr().write(|w| w.wut().bits((LSI_HZ / 16 - 1) as u16));
+    rtc.cr().modify(|r, w| unsafe {
+        w.bits((r.bits() & !RTC_CR_WUCKSEL_MASK) | RTC_CR_WUCKSEL_DIV16 | RTC_CR_WUTIE | RTC_CR_WUTE)
+    });
+    rtc.wpr().write(|w| w.key().bits(0xFF));
+
+    exti.rtsr().modify(|r, w| unsafe { w.bits(r.bits() | EXTI_RTC_WAKEUP) });
+    exti.imr().modify(|r, w| unsafe { w.bits(r.bits() | EXTI_RTC_WAKEUP) });
+
+    // Debug builds keep the debug clocks alive in STOP so RTT and the probe
+    // stay attached (at the cost of most of the saving)
+    #[cfg(debug_assertions)]
+    unsafe {
+        (*pac::DBGMCU::ptr()).cr().modify(|r, w| w.bits(r.bits() | DBGMCU_CR_DBG_STOP));
+    }
+}
+
+/// Acknowledge a wakeup timer interrupt; call from the `RTC_WKUP` handler
+pub fn clear_wakeup() {
+    let rtc = unsafe { &*pac::RTC::ptr() };
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    // WUTF is rc_w0 and not write protected
+    rtc.isr().modify(|r, w| unsafe { w.bits(r.bits() & !RTC_ISR_WUTF) });
+    exti.pr().write(|w| unsafe { w.bits(EXTI_RTC_WAKEUP) });
+}
+
+/// Enter STOP until the next interrupt, then restore the 84 MHz clock
+///
+/// Call with interrupts disabled (`cortex_m::interrupt::free`): a pending
+/// interrupt still ends the WFI, but its handler only runs once the caller
+/// re-enables interrupts, after the PLL is back.
+pub fn stop() {
+    let pwr = unsafe { &*pac::PWR::ptr() };
+    // Low-power regulator and flash powered down in STOP, not Standby
+    pwr.cr().modify(|r, w| unsafe { w.bits((r.bits() | PWR_CR_LPDS | PWR_CR_FPDS) & !PWR_CR_PDDS) });
+
+    unsafe { (*SCB::PTR).scr.modify(|scr| scr | SCB_SCR_SLEEPDEEP) };
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+    unsafe { (*SCB::PTR).scr.modify(|scr| scr & !SCB_SCR_SLEEPDEEP) };
+
+    restore_clocks();
+}
+
+/// Back onto the PLL after waking on HSI; its configuration survived STOP
+fn restore_clocks() {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.cr().modify(|r, w| unsafe { w.bits(r.bits() | RCC_CR_PLLON) });
+    while rcc.cr().read().bits() & RCC_CR_PLLRDY == 0 {}
+    rcc.cfgr().modify(|r, w| unsafe { w.bits((r.bits() & !RCC_CFGR_SW_MASK) | RCC_CFGR_SW_PLL) });
+    while rcc.cfgr().read().bits() & RCC_CFGR_SWS_MASK != RCC_CFGR_SWS_PLL {}
+}
+
+/// Core clock while the MCU is awake
+///
+/// `Economy` divides HCLK by two and drops the APB1 divider from 2 to 1, so
+/// APB1 (UART4, USART2, I2C1) stays at 42 MHz and needs nothing changed.
+/// Everything that sees HCLK or APB2 runs at half speed and is rescaled by
+/// `set_profile`: SysTick, the 1 MHz delay timers (TIM3/4/5/9), TIM2's
+/// periodic tick and USART1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PowerProfile {
+    /// 84 MHz, for sensor reads, display updates and the menu
+    Performance,
+    /// 42 MHz, while only waiting
+    Economy,
+}
+
+impl PowerProfile {
+    pub fn core_hz(self) -> u32 {
+        match self {
+            PowerProfile::Performance => time::SYSCLK_HZ,
+            PowerProfile::Economy => time::SYSCLK_HZ / 2,
+        }
+    }
+}
+
+/// The profile in effect
+pub fn profile() -> PowerProfile {
+    if time::core_hz() == time::SYSCLK_HZ {
+        PowerProfile::Performance
+    } else {
+        PowerProfile::Economy
+    }
+}
+
+/// Switch the core clock and rescale everything that depends on it
+///
+/// Runs with interrupts off so no task sees a half-switched clock tree. A
+/// byte USART1 is receiving at that moment may be garbled (NMEA checksums
+/// catch it), and the SysTick period in flight is off by up to 1 ms. The
+/// setting survives STOP: `restore_clocks` only touches the PLL and SW.
+pub fn set_profile(profile: PowerProfile) {
+    cortex_m::interrupt::free(|_| {
+        if self::profile() == profile {
+            return;
+        }
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let dividers = match profile {
+            PowerProfile::Performance => RCC_CFGR_PPRE1_DIV2,
+            PowerProfile::Economy => RCC_CFGR_HPRE_DIV2,
+        };
+        rcc.cfgr().modify(|r, w| unsafe {
+            w.bits((r.bits() & !(RCC_CFGR_HPRE_MASK | RCC_CFGR_PPRE1_MASK)) | dividers)
+        });
+
+        // Timer and APB2 clocks all halve or double with HCLK
+        let rescale = |value: u32| match profile {
+            PowerProfile::Performance => value * 2,
+            PowerProfile::Economy => value / 2,
+        };
+        unsafe {
+            (*SYST::PTR).rvr.write(profile.core_hz() / 1_000 - 1);
+            let timers = [pac::TIM3::ptr() as usize, pac::TIM4::ptr() as usize, pac::TIM5::ptr() as usize, pac::TIM9::ptr() as usize];
+            for base in timers {
+                // Delays reload PSC with an update event on every call; an
+                // unclocked timer reads 0 and ignores the write
+                let psc = (base + TIM_PSC) as *mut u32;
+                psc.write_volatile(rescale(psc.read_volatile() + 1).max(1) - 1);
+            }
+            // TIM2 runs free at its tick rate, so stretch the period instead
+            let arr = (pac::TIM2::ptr() as usize + TIM_ARR) as *mut u32;
+            arr.write_volatile(rescale(arr.read_volatile() + 1).max(1) - 1);
+            let brr = (pac::USART1::ptr() as usize + USART_BRR) as *mut u32;
+            brr.write_volatile(rescale(brr.read_volatile()));
+        }
+        time::set_core_hz(profile.core_hz());
+    });
+}
+
+/// A peripheral that has to keep its clock while the core sleeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Wake {
+    /// Periodic tick
+    Tim2,
+    /// GNSS receiver (Node 1)
+    Usart1,
+    /// Shell
+    Usart2,
+    /// LoRa module
+    Uart4,
+    /// USB serial port (Node 2, feature `usb-json`)
+    OtgFs,
+}
+
+impl Wake {
+    /// Its bit in `APB1LPENR` or `APB2LPENR`, as (APB1, APB2)
+    ///
+    /// The OTG core is on AHB2, which `gate_unused` handles itself.
+    fn lpenr_bits(self) -> (u32, u32) {
+        match self {
+            Wake::Tim2 => (1 << 0, 0),
+            Wake::Usart1 => (0, 1 << 4),
+            Wake::Usart2 => (1 << 17, 0),
+            Wake::Uart4 => (1 << 19, 0),
+            Wake::OtgFs => (0, 0),
+        }
+    }
+}
+
+/// The hardware a build uses, for `gate_unused`
+pub struct Board {
+    /// Pins something drives on ports A, B and C, bit n for pin n
+    pub pins: [u16; 3],
+    /// Peripherals whose interrupts end the idle WFI
+    pub wake: &'static [Wake],
+}
+
+/// Put every unused pin in analog mode and stop clocks nothing needs
+///
+/// Call at the end of `init`, once the HAL has set up every pin `board`
+/// lists. Ports D-H aren't bonded out on the Nucleo-64 apart from PD2 and
+/// PH0/PH1 (HSE, unused on HSI) and are parked completely, then unclocked.
+/// In Sleep only GPIOA-C, flash, SRAM, PWR (the brown-out detector) and
+/// `board.wake` stay clocked; the I2C, SPI, ADC and delay timers are only
+/// used with the core running. STOP gates all of these anyway, so on a
+/// low-power Node 1 it's the pins that make the difference there.
+pub fn gate_unused(board: &Board) {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+
+    // Ports D-H were never split, so clock them just long enough to park them
+    rcc.ahb1enr().modify(|r, w| unsafe { w.bits(r.bits() | RCC_AHB1ENR_GPIOD_H) });
+    cortex_m::asm::dsb();
+    let ports = [
+        (pac::GPIOA::ptr() as usize, board.pins[0] | SWD_PINS),
+        (pac::GPIOB::ptr() as usize, board.pins[1]),
+        (pac::GPIOC::ptr() as usize, board.pins[2]),
+        (pac::GPIOD::ptr() as usize, 0),
+        (pac::GPIOE::ptr() as usize, 0),
+        (pac::GPIOF::ptr() as usize, 0),
+        (pac::GPIOG::ptr() as usize, 0),
+        (pac::GPIOH::ptr() as usize, 0),
+    ];
+    let mut parked = 0;
+    for (base, used) in ports {
+        // Two bits per pin in both registers: MODER 0b11 is analog, PUPDR 0b00 no pull
+        let unused = (0..16).filter(|pin| used & (1 << pin) == 0).fold(0u32, |mask, pin| mask | 0b11 << (2 * pin));
+        unsafe {
+            let moder = (base + GPIO_MODER) as *mut u32;
+            moder.write_volatile(moder.read_volatile() | unused);
+            let pupdr = (base + GPIO_PUPDR) as *mut u32;
+            pupdr.write_volatile(pupdr.read_volatile() & !unused);
+        }
+        parked += unused.count_ones() / 2;
+    }
+    rcc.ahb1enr().modify(|r, w| unsafe { w.bits(r.bits() & !RCC_AHB1ENR_GPIOD_H) });
+
+    let (apb1, apb2) = board.wake.iter().fold((RCC_APB1ENR_PWREN, 0), |(apb1, apb2), wake| {
+        let (bit1, bit2) = wake.lpenr_bits();
+        (apb1 | bit1, apb2 | bit2)
+    });
+    rcc.ahb1lpenr().write(|w| unsafe {
+        w.bits(RCC_AHB1LPENR_GPIOA_C | RCC_AHB1LPENR_FLITF | RCC_AHB1LPENR_SRAM1 | RCC_AHB1LPENR_SRAM2)
+    });
+    let ahb2 = if board.wake.contains(&Wake::OtgFs) { RCC_AHB2LPENR_OTGFS } else { 0 };
+    rcc.ahb2lpenr().write(|w| unsafe { w.bits(ahb2) });
+    rcc.ahb3lpenr().write(|w| unsafe { w.bits(0) });
+    rcc.apb1lpenr().write(|w| unsafe { w.bits(apb1) });
+    rcc.apb2lpenr().write(|w| unsafe { w.bits(apb2) });
+
+    defmt::info!("Parked {} unused pins, {} peripherals clocked in Sleep", parked, board.wake.len());
+}
+
+/// The LoRa module's power state, as `DutyCycle` last set it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Radio {
+    Asleep,
+    /// `AT+MODE=0` sent; frames may go out from `ready_at_ms`
+    Waking { ready_at_ms: u32 },
+    Awake { last_used_ms: u32 },
+}
+
+/// What the module must be told; sent by the task driving `DutyCycle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RadioCommand {
+    Wake,
+    Sleep,
+}
+
+impl RadioCommand {
+    pub fn at_command(self) -> &'static str {
+        match self {
+            RadioCommand::Wake => "AT+MODE=0",
+            RadioCommand::Sleep => "AT+MODE=1",
+        }
+    }
+}
+
+/// Node 1's radio/MCU sleep schedule
+pub struct DutyCycle {
+    enabled: bool,
+    radio: Radio,
+    /// Someone asked for the radio while it was asleep
+    wanted: bool,
+    /// An ACK was awaited at the last `poll`
+    was_busy: bool,
+}
+
+impl DutyCycle {
+    /// Disabled, the radio is never put to sleep and always ready
+    pub const fn new(enabled: bool) -> Self {
+        // The module comes out of `init` awake
+        Self { enabled, radio: Radio::Awake { last_used_ms: 0 }, wanted: false, was_busy: false }
+    }
+
+    pub fn radio(&self) -> Radio {
+        self.radio
+    }
+
+    /// Nothing can arrive over the air, so the MCU may STOP
+    pub fn radio_asleep(&self) -> bool {
+        self.radio == Radio::Asleep
+    }
+
+    /// Ask for the radio ahead of use; the next `poll` wakes it
+    pub fn want_radio(&mut self) {
+        if self.radio == Radio::Asleep {
+            self.wanted = true;
+        }
+    }
+
+    /// A frame wants to go out at `now_ms`: true if the radio can take it,
+    /// otherwise it has been asked for (retry after `RADIO_WAKE_MS`)
+    pub fn claim_radio(&mut self, now_ms: u32) -> bool {
+        let ready = match self.radio {
+            Radio::Awake { .. } => true,
+            Radio::Waking { ready_at_ms } => time::deadline_passed(now_ms, ready_at_ms),
+            Radio::Asleep => false,
+        };
+        if ready {
+            self.radio = Radio::Awake { last_used_ms: now_ms };
+        } else {
+            self.want_radio();
+        }
+        ready
+    }
+
+    /// Advance the cycle at `now_ms`; `busy` while an ACK is awaited
+    pub fn poll(&mut self, now_ms: u32, busy: bool) -> Option<RadioCommand> {
+        let was_busy = core::mem::replace(&mut self.was_busy, busy);
+        if !self.enabled {
+            return None;
+        }
+        match self.radio {
+            Radio::Asleep if core::mem::take(&mut self.wanted) => {
+                self.radio = Radio::Waking { ready_at_ms: now_ms.wrapping_add(RADIO_WAKE_MS) };
+                Some(RadioCommand::Wake)
+            }
+            Radio::Waking { ready_at_ms } if time::deadline_passed(now_ms, ready_at_ms) => {
+                self.radio = Radio::Awake { last_used_ms: now_ms };
+                None
+            }
+            // The linger counts from the end of the ACK wait
+            Radio::Awake { .. } if busy || was_busy => {
+                self.radio = Radio::Awake { last_used_ms: now_ms };
+                None
+            }
+            Radio::Awake { last_used_ms } if time::elapsed_ms(now_ms, last_used_ms) >= RADIO_LINGER_MS => {
+                self.radio = Radio::Asleep;
+                Some(RadioCommand::Sleep)
+            }
+            _ => None,
+        }
+    }
+
+    /// Milliseconds until `poll` is due to put the radio to sleep
+    pub fn recheck_in(&self, now_ms: u32) -> Option<u32> {
+        match self.radio {
+            Radio::Awake { last_used_ms } if self.enabled && !self.was_busy => {
+                Some(RADIO_LINGER_MS.saturating_sub(time::elapsed_ms(now_ms, last_used_ms)))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! GPIO phase markers for power profiling (feature `power-trace`)
+//!
+//! A current probe shows how much the node draws, not what it was doing at
+//! the time. With `power-trace`, Node 1 toggles PA8 (Arduino D7) at the
+//! start and end of each `Phase` and logs the same marker over defmt, so a
+//! logic analyzer channel on PA8 beside the current trace ties every step
+//! in the waveform to a log line. Toggling rather than driving a level
+//! keeps the edges meaningful when phases overlap (a TX inside a sensor
+//! tick). Without the feature every function here compiles to nothing.
+
+#[cfg(feature = "power-trace")]
+use stm32f4xx_hal::pac;
+
+/// Marker pin number on GPIOA; `init` makes it a push-pull output
+pub const PIN: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Phase {
+    SensorRead,
+    /// UART writes of one frame to the module
+    RadioTx,
+    /// STOP mode, entry to wakeup
+    Sleep,
+}
+
+/// Mark the start of `phase`
+#[inline(always)]
+pub fn begin(phase: Phase) {
+    mark(phase, "begin");
+}
+
+/// Mark the end of `phase`
+#[inline(always)]
+pub fn end(phase: Phase) {
+    mark(phase, "end");
+}
+
+/// `begin(phase)` now and `end(phase)` when the guard drops
+/// (`let _read = powertrace::span(Phase::SensorRead);`)
+#[inline(always)]
+pub fn span(phase: Phase) -> Span {
+    begin(phase);
+    Span(phase)
+}
+
+pub struct Span(Phase);
+
+impl Drop for Span {
+    #[inline(always)]
+    fn drop(&mut self) {
+        end(self.0);
+    }
+}
+
+#[inline(always)]
+fn mark(phase: Phase, edge: &str) {
+    #[cfg(feature = "power-trace")]
+    {
+        toggle();
+        defmt::info!("PWR {} {=str}", phase, edge);
+    }
+    #[cfg(not(feature = "power-trace"))]
+    let _ = (phase, edge);
+}
+
+#[cfg(feature = "power-trace")]
+fn toggle() {
+    let gpioa = unsafe { &*pac::GPIOA::ptr() };
+    // ODR read and BSRR write must not be split by another marker
+    cortex_m::interrupt::free(|_| {
+        let high = gpioa.odr().read().bits() & (1 << PIN) != 0;
+        let bit = if high { 1 << (PIN + 16) } else { 1 << PIN };
+        gpioa.bsrr().write(|w| unsafe { w.bits(bit) });
+    });
+}
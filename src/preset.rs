@@ -0,0 +1,30 @@
+//! Radio settings baked in at build time
+//!
+//! A unit with no saved config starts on these until the setup menu or a
+//! join saves others. Flashing a row of units with different addresses
+//! then takes an environment variable per build rather than a source edit:
+//!
+//! ```text
+//! WK3_NODE_ID=3 WK3_NETWORK_ID=5 WK3_LORA_FREQ=868 cargo build --release
+//! ```
+//!
+//! or the same as `node_id`, `network_id` and `lora_freq` in a `node.toml`
+//! next to Cargo.toml, which the variables override. `build.rs` checks them
+//! against what the setup menu offers and fails the build on anything
+//! else. `node_id` is a sensor node's: unset, Node 1 joins. Node 2 ignores
+//! it and starts as `relay::GATEWAY`, the address every sensor node is
+//! built to send to. Both are on network 18 at 915 MHz unless set. A config already saved in flash still wins, so reflashing
+//! a unit that was set up keeps its settings.
+//!
+//! `WK3_REGION` (`region`) picks a region's rules (see region.rs); the
+//! band then defaults to the region's, and one outside it fails the build.
+
+include!(concat!(env!("OUT_DIR"), "/preset.rs"));
+
+/// `NODE_ID`, or the node's own `default` if the build didn't set one
+pub const fn address_or(default: u16) -> u16 {
+    match NODE_ID {
+        Some(address) => address,
+        None => default,
+    }
+}
@@ -0,0 +1,40 @@
+//! Raw mode: sending the SHT31's own ticks instead of scaled values
+//!
+//! Normally Node 1 packs temperature and humidity scaled to fixed point,
+//! after calibration and smoothing. A host doing its own compensation wants
+//! the sensor's untouched 16-bit readings instead, so with raw mode on
+//! (`values raw` in Node 1's shell) the packet's `temperature` and
+//! `humidity` fields carry the SHT31 ticks and a `tlv::FLAGS` record has
+//! `flags::RAW_TH` set. Node 2 converts them back with the datasheet
+//! formulas below for its display.
+//!
+//! Only the SHT31 reports ticks; without one, packets stay scaled and
+//! unflagged whatever the setting.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// Bits of the `tlv::FLAGS` record
+pub mod flags {
+    /// `temperature`/`humidity` are raw SHT31 ticks
+    pub const RAW_TH: u8 = crate::protocol::tlv::FLAG_RAW_TH;
+}
+
+/// SHT31 temperature ticks to °C (datasheet section 4.13)
+pub fn temperature_c(ticks: u16) -> f32 {
+    -45.0 + 175.0 * ticks as f32 / 65535.0
+}
+
+/// SHT31 humidity ticks to %RH
+pub fn humidity_pct(ticks: u16) -> f32 {
+    100.0 * ticks as f32 / 65535.0
+}
@@ -0,0 +1,209 @@
+//! Regional radio rules, chosen at build time (`region` in preset.rs)
+//!
+//! What is legal to transmit depends on where the unit is: 863-870 MHz at
+//! 14 dBm and a 1% duty cycle in Europe, 902-928 MHz with no duty cycle in
+//! the US, 915-928 MHz in Australia. A build with `WK3_REGION` (or
+//! `region` in node.toml) set to one of `eu868`, `us915` or `au915` takes
+//! that region's rules as a whole rather than leaving each to be set right:
+//!
+//! - the build fails on a `lora_freq` outside the region, and the default
+//!   band is the region's own;
+//! - the setup menu offers only the region's bands, `hop` takes only its
+//!   channels, and a saved band or channel outside it is replaced at boot;
+//! - TX power above the region's limit is refused, from the shell or a
+//!   remote command alike, and ADR steps no higher;
+//! - the duty-cycle limit is the region's, which `airtime` can tighten but
+//!   not lift;
+//! - `AT+PARAMETER` starts on the region's spreading factor and bandwidth,
+//!   and only the region's bandwidths are accepted.
+//!
+//! A value the rules refuse comes back as a `ConfigError` saying which
+//! rule, in a `ConfigAck` when it came from Node 2.
+//!
+//! A build without a region keeps the old behaviour: every band the menu
+//! has, the module's full power, and the duty cycle the band implies (see
+//! `airtime`). It says so at boot.
+
+use core::ops::RangeInclusive;
+
+use crate::adr::SF_RANGE;
+use crate::airtime::LoraParams;
+use crate::battery::MAX_TX_POWER_DBM;
+use crate::preset;
+use crate::protocol::{ConfigError, REGION_AU915, REGION_EU868, REGION_NONE, REGION_US915};
+
+/// Bands the setup menu offers without a region
+const ALL_BANDS_MHZ: [u16; 3] = [433, 868, 915];
+/// `AT+PARAMETER` without a region: SF7, BW500k, CR4/5, preamble 7
+const DEFAULT_PARAMETER: &str = "7,9,1,7";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Region {
+    Eu868,
+    Us915,
+    Au915,
+}
+
+/// One region's rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Where a channel's centre may be, in kHz
+    pub khz: RangeInclusive<u32>,
+    /// What the setup menu offers, the first being the default
+    pub bands_mhz: &'static [u16],
+    /// `AT+PARAMETER` to start on
+    pub parameter: &'static str,
+    /// Bandwidths allowed, in Hz
+    pub bandwidths_hz: &'static [u32],
+    pub max_tx_dbm: u8,
+    /// Duty-cycle limit in permille, 0 for none
+    pub duty_permille: u16,
+}
+
+/// ETSI EN 300 220 g1: 25 mW ERP, 1%; SF9 at 125 kHz, and 125 or
+/// 250 kHz, the widths LoRaWAN uses there
+const EU868: Profile = Profile {
+    khz: 863_000..=870_000,
+    bands_mhz: &[868],
+    parameter: "9,7,1,7",
+    bandwidths_hz: &[125_000, 250_000],
+    max_tx_dbm: 14,
+    duty_permille: 10,
+};
+
+/// FCC part 15.247: up to 30 dBm, so the module's 22 is the limit; a
+/// channel that doesn't hop needs 500 kHz to count as digital modulation
+const US915: Profile = Profile {
+    khz: 902_000..=928_000,
+    bands_mhz: &[915],
+    parameter: DEFAULT_PARAMETER,
+    bandwidths_hz: &[500_000],
+    max_tx_dbm: MAX_TX_POWER_DBM,
+    duty_permille: 0,
+};
+
+/// ACMA LIPD class licence, 915-928 MHz at up to 30 dBm
+const AU915: Profile = Profile {
+    khz: 915_000..=928_000,
+    bands_mhz: &[923, 916],
+    parameter: DEFAULT_PARAMETER,
+    bandwidths_hz: &[125_000, 250_000, 500_000],
+    max_tx_dbm: MAX_TX_POWER_DBM,
+    duty_permille: 0,
+};
+
+impl Region {
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Eu868 => "eu868",
+            Region::Us915 => "us915",
+            Region::Au915 => "au915",
+        }
+    }
+
+    pub const fn profile(self) -> &'static Profile {
+        match self {
+            Region::Eu868 => &EU868,
+            Region::Us915 => &US915,
+            Region::Au915 => &AU915,
+        }
+    }
+}
+
+/// The region this build is for, if any
+pub const REGION: Option<Region> = preset::REGION;
+
+/// `AT+PARAMETER` to boot with
+pub const PARAMETER: &str = match REGION {
+    Some(region) => region.profile().parameter,
+    None => DEFAULT_PARAMETER,
+};
+
+fn profile() -> Option<&'static Profile> {
+    REGION.map(Region::profile)
+}
+
+/// Whether a channel centred on `khz` is allowed
+pub fn allows_khz(khz: u32) -> bool {
+    profile().is_none_or(|profile| profile.khz.contains(&khz))
+}
+
+pub fn allows_band_mhz(band_mhz: u16) -> bool {
+    allows_khz(u32::from(band_mhz) * 1_000)
+}
+
+/// A band or channel, in kHz, if the region allows it
+pub fn check_khz(khz: u32) -> Result<(), ConfigError> {
+    allows_khz(khz).then_some(()).ok_or(ConfigError::FrequencyOutOfRegion)
+}
+
+/// `params`, if the module can run its spreading factor at its bandwidth
+/// and the region allows that bandwidth
+pub fn check_modulation(params: &LoraParams) -> Result<(), ConfigError> {
+    let sf = params.spreading_factor;
+    let module = sf >= *SF_RANGE.start() && sf <= params.max_spreading_factor().min(*SF_RANGE.end());
+    let region = profile().is_none_or(|profile| profile.bandwidths_hz.contains(&params.bandwidth_hz));
+    (module && region).then_some(()).ok_or(ConfigError::IllegalModulation)
+}
+
+/// The bands the setup menu offers
+pub fn bands_mhz() -> &'static [u16] {
+    profile().map_or(&ALL_BANDS_MHZ, |profile| profile.bands_mhz)
+}
+
+/// The band to use instead of one that isn't allowed
+pub fn default_band_mhz() -> u16 {
+    bands_mhz()[0]
+}
+
+/// Highest TX power allowed
+pub fn max_tx_dbm() -> u8 {
+    profile().map_or(MAX_TX_POWER_DBM, |profile| profile.max_tx_dbm)
+}
+
+/// The region's duty-cycle limit in permille, if it has one
+pub fn duty_permille() -> Option<u16> {
+    profile().map(|profile| profile.duty_permille).filter(|&permille| permille > 0)
+}
+
+/// The region as a `ConfigReport` carries it
+pub fn code() -> u8 {
+    match REGION {
+        None => REGION_NONE,
+        Some(Region::Eu868) => REGION_EU868,
+        Some(Region::Us915) => REGION_US915,
+        Some(Region::Au915) => REGION_AU915,
+    }
+}
+
+/// The region's name, `none` without one
+pub fn name() -> &'static str {
+    REGION.map_or("none", Region::name)
+}
+
+/// `band_mhz`, or the region's default if it isn't allowed; for a band
+/// saved before the build had a region
+pub fn checked_band(band_mhz: u16) -> u16 {
+    if allows_band_mhz(band_mhz) {
+        return band_mhz;
+    }
+    defmt::warn!("{} MHz is outside region {}, using {} MHz", band_mhz, name(), default_band_mhz());
+    default_band_mhz()
+}
+
+/// Say at boot which rules are in force
+pub fn log() {
+    match REGION {
+        Some(region) => {
+            let profile = region.profile();
+            defmt::info!(
+                "Region {}: {} dBm max, duty cycle {}/1000, AT+PARAMETER={}",
+                region.name(),
+                profile.max_tx_dbm,
+                profile.duty_permille,
+                profile.parameter
+            );
+        }
+        None => defmt::warn!("No region set (WK3_REGION): band, power and duty cycle are unchecked"),
+    }
+}
@@ -0,0 +1,205 @@
+//! Relay mode: a Node 2 passing frames on to extend range (`relay`, `via`)
+//!
+//! With `relay on`, a Node 2 stops acting as a gateway and becomes a
+//! repeater for a sensor node out of the gateway's reach (a basement, an
+//! outbuilding). That node's shell is pointed at the relay with `via
+//! <address>`, and the relay sends every frame it hears from anyone but its
+//! upstream on to `via`'s address (the gateway by default), with the
+//! header's hop count one higher (`protocol::relay_frame`). The gateway
+//! ACKs whichever address it heard a reading from, so the ACK comes back
+//! to the relay, which passes it down to the address it heard the reading
+//! from: the node, or the relay before it on a chain. The ACK names the
+//! node as well as the sequence number, since two nodes behind one relay
+//! can have a reading of the same number in flight.
+//!
+//! Loops are cut twice over: a frame that has already made `MAX_HOPS` hops
+//! goes no further, and a frame forwarded in the last `ECHO_MS` isn't
+//! forwarded again. The window is shorter than Node 1's ACK timeout, so a
+//! genuine retry still gets through.
+//!
+//! Only uplinks are relayed. Downlinks from the gateway (`n1 ...`, replay
+//! requests, firmware uploads) still go straight to their node's address.
+//!
+//! `via` leaves each relay to pick the next hop. An installer who has
+//! found a path that works can pin it instead: `route <relay> [relay]` in
+//! the sensor node's shell puts those addresses in the header of every
+//! uplink (`protocol::route_frame`), and sends it to the first of them.
+//! A relay only sends a routed frame on if the route names it for the hop
+//! the frame is at, and then to the route's next address instead of its
+//! own `via`; past the route's end, relays go by `via`. ACKs come back
+//! down the same path.
+
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+
+use heapless::{Deque, Vec};
+
+use crate::protocol::{
+    calculate_crc16, decode_frame, frame_hops, frame_node, frame_route, relay_frame, route_frame, SensorDataPacket,
+    StoredReading, MAX_HOPS, MAX_ROUTE, MSG_TYPE_SENSOR_DATA, MSG_TYPE_STORED,
+};
+
+/// The gateway's address, where uplinks go unless `via` says otherwise
+pub const GATEWAY: u16 = 2;
+
+/// Frames remembered for spotting echoes and routing ACKs
+const RECENT: usize = 16;
+/// A repeat of a forwarded frame within this long is an echo
+const ECHO_MS: u32 = 1_500;
+/// How long a forwarded reading's ACK is waited for
+const ACK_MS: u32 = 5_000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static UPSTREAM: AtomicU16 = AtomicU16::new(GATEWAY);
+/// Node 1's pinned route, 0 past its end
+static ROUTE: [AtomicU8; MAX_ROUTE] = [const { AtomicU8::new(0) }; MAX_ROUTE];
+
+/// Node 2: relay frames instead of acting as the gateway
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// Address uplink frames are sent to: the gateway, or a relay
+pub fn upstream() -> u16 {
+    UPSTREAM.load(Ordering::Relaxed)
+}
+
+pub fn set_upstream(address: u16) {
+    UPSTREAM.store(address, Ordering::Relaxed);
+}
+
+/// Node 1: the relays its uplinks are routed through, empty for none
+pub fn route() -> Vec<u8, MAX_ROUTE> {
+    ROUTE.iter().map(|hop| hop.load(Ordering::Relaxed)).take_while(|&address| address != 0).collect()
+}
+
+/// Pin uplinks to `route`, at most `MAX_ROUTE` relays; empty to go by
+/// `via` again. Address 0 ends the route.
+pub fn set_route(route: &[u8]) {
+    for (i, hop) in ROUTE.iter().enumerate() {
+        hop.store(route.get(i).copied().unwrap_or(0), Ordering::Relaxed);
+    }
+}
+
+/// The address to send uplink `frame` to, and the frame to send: with a
+/// route pinned, a copy in `buf` that carries it, for its first relay
+///
+/// A frame that has no room left for the route goes by `via` unrouted.
+pub fn uplink<'b>(frame: &'b [u8], buf: &'b mut [u8]) -> (u16, &'b [u8]) {
+    let route = route();
+    match route.first() {
+        Some(&first) => match route_frame(frame, &route, buf) {
+            Ok(routed) => (first.into(), routed),
+            Err(_) => (upstream(), frame),
+        },
+        None => (upstream(), frame),
+    }
+}
+
+/// Why a received frame wasn't sent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Skip {
+    /// Not a valid frame
+    Bad,
+    /// Sent by this node (heard back from another relay)
+    Own,
+    /// Already made `MAX_HOPS` hops
+    TooFar,
+    /// Forwarded moments ago
+    Echo,
+    /// Routed through another relay
+    OffRoute,
+}
+
+/// A frame this relay sent on
+#[derive(Debug, Clone, Copy)]
+struct Forwarded {
+    node: u8,
+    msg_type: u8,
+    /// CRC of the body alone, which a relay doesn't change
+    body_crc: u16,
+    /// The reading's sequence number and the address it came from, for
+    /// passing its ACK back
+    seq: Option<u16>,
+    from: u16,
+    at_ms: u32,
+}
+
+pub struct Relay {
+    recent: Deque<Forwarded, RECENT>,
+}
+
+impl Relay {
+    pub const fn new() -> Self {
+        Self { recent: Deque::new() }
+    }
+
+    /// `payload`, heard from address `from`, one hop further on in `buf`,
+    /// with the address to send it to
+    ///
+    /// `own` is this node's address. A frame sent on is remembered, so an
+    /// echo of it is skipped and its reading's ACK can be passed down.
+    pub fn forward<'b>(
+        &mut self,
+        payload: &[u8],
+        from: u16,
+        own: u8,
+        now_ms: u32,
+        buf: &'b mut [u8],
+    ) -> Result<(u16, &'b [u8]), Skip> {
+        let (msg_type, body) = decode_frame(payload).map_err(|_| Skip::Bad)?;
+        let node = frame_node(payload).ok_or(Skip::Bad)?;
+        if node == own {
+            return Err(Skip::Own);
+        }
+        let hops = frame_hops(payload).ok_or(Skip::Bad)?;
+        if hops >= MAX_HOPS {
+            return Err(Skip::TooFar);
+        }
+        let route = frame_route(payload).ok_or(Skip::Bad)?;
+        // Unrouted, or past the end of its route: as `via` says
+        let to = match route.get(hops as usize) {
+            None => upstream(),
+            Some(&relay) if relay != own => return Err(Skip::OffRoute),
+            Some(_) => route.get(hops as usize + 1).map_or(upstream(), |&next| next.into()),
+        };
+        let body_crc = calculate_crc16(body);
+        let echo = self.recent.iter().any(|f| {
+            f.node == node && f.msg_type == msg_type && f.body_crc == body_crc && now_ms.wrapping_sub(f.at_ms) < ECHO_MS
+        });
+        if echo {
+            return Err(Skip::Echo);
+        }
+        let frame = relay_frame(payload, buf).map_err(|_| Skip::TooFar)?;
+
+        let seq = match msg_type {
+            MSG_TYPE_SENSOR_DATA => postcard::from_bytes::<SensorDataPacket>(body).ok().map(|p| p.seq_num),
+            MSG_TYPE_STORED => postcard::from_bytes::<StoredReading>(body).ok().map(|s| s.reading.seq_num),
+            _ => None,
+        };
+        if self.recent.is_full() {
+            self.recent.pop_front();
+        }
+        let _ = self.recent.push_back(Forwarded { node, msg_type, body_crc, seq, from, at_ms: now_ms });
+        Ok((to, frame))
+    }
+
+    /// The address node `node`'s reading `seq` came through here from
+    /// lately, the node or the relay before, to pass the ACK for it on to
+    pub fn ack_target(&self, node: u8, seq: u16, now_ms: u32) -> Option<u16> {
+        self.recent
+            .iter()
+            .rev()
+            .find(|f| f.node == node && f.seq == Some(seq) && now_ms.wrapping_sub(f.at_ms) < ACK_MS)
+            .map(|f| f.from)
+    }
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
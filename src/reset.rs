@@ -0,0 +1,76 @@
+//! Reset cause decoding from RCC_CSR
+//!
+//! The flags are sticky across resets until RMVF is written, so
+//! `read_and_clear` must run once, early in `init`, before anything else
+//! can trigger a reset.
+
+use stm32f4xx_hal::pac;
+
+// RCC_CSR reset flag bits (RM0390 6.3.21)
+const LPWRRSTF: u32 = 1 << 31;
+const WWDGRSTF: u32 = 1 << 30;
+const IWDGRSTF: u32 = 1 << 29;
+const SFTRSTF: u32 = 1 << 28;
+const PORRSTF: u32 = 1 << 27;
+const PINRSTF: u32 = 1 << 26;
+const BORRSTF: u32 = 1 << 25;
+const RMVF: u32 = 1 << 24;
+
+/// Why the MCU last came out of reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ResetCause {
+    PowerOn,
+    BrownOut,
+    Pin,
+    Software,
+    IndependentWatchdog,
+    WindowWatchdog,
+    LowPower,
+    Unknown,
+}
+
+impl ResetCause {
+    /// The cause sent as `self as u8` in health and node info packets
+    pub fn from_u8(value: u8) -> Option<Self> {
+        const ALL: [ResetCause; 8] = [
+            ResetCause::PowerOn,
+            ResetCause::BrownOut,
+            ResetCause::Pin,
+            ResetCause::Software,
+            ResetCause::IndependentWatchdog,
+            ResetCause::WindowWatchdog,
+            ResetCause::LowPower,
+            ResetCause::Unknown,
+        ];
+        ALL.get(value as usize).copied()
+    }
+
+    /// Decode the RCC reset flags, then clear them for the next boot
+    pub fn read_and_clear(rcc: &pac::RCC) -> Self {
+        let bits = rcc.csr().read().bits();
+        rcc.csr().modify(|r, w| unsafe { w.bits(r.bits() | RMVF) });
+        Self::from_csr(bits)
+    }
+
+    /// Most specific cause first: a watchdog reset also asserts PINRSTF,
+    /// and a power-on reset also asserts BORRSTF and PINRSTF.
+    fn from_csr(bits: u32) -> Self {
+        if bits & IWDGRSTF != 0 {
+            ResetCause::IndependentWatchdog
+        } else if bits & WWDGRSTF != 0 {
+            ResetCause::WindowWatchdog
+        } else if bits & LPWRRSTF != 0 {
+            ResetCause::LowPower
+        } else if bits & SFTRSTF != 0 {
+            ResetCause::Software
+        } else if bits & PORRSTF != 0 {
+            ResetCause::PowerOn
+        } else if bits & BORRSTF != 0 {
+            ResetCause::BrownOut
+        } else if bits & PINRSTF != 0 {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        }
+    }
+}
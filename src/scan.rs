@@ -0,0 +1,164 @@
+//! Looking for sensor nodes on the wrong radio settings (`scan`)
+//!
+//! Two modules on different network IDs or spreading factors don't hear
+//! each other at all, so a sensor node set up with the wrong ones leaves
+//! Node 2 looking like nothing is there. With `scan on`, a Node 2 that
+//! has heard no good frame for `SILENCE_MS` goes looking: it moves its
+//! module through the other network IDs the setup menu offers at its own
+//! spreading factor, then the other spreading factors on its own network,
+//! then everything else, listening on each for one of the node's
+//! intervals. The first good frame it hears names the settings the node
+//! is on, which the display then shows in place of sitting silent, and
+//! Node 2 goes back to its own. Nothing is changed on either node: the
+//! setup menu is still where the mismatch is put right.
+//!
+//! A scan that hears nothing ends on Node 2's own settings again, and the
+//! next one starts after another `SILENCE_MS`. The band is not scanned;
+//! a node on another band needs another module anyway.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::adr::SF_RANGE;
+use crate::menu::NETWORK_IDS;
+
+/// Silence that starts a scan
+pub const SILENCE_MS: u32 = 600_000;
+/// Added to a node's interval to make the time spent on each setting
+pub const MARGIN_MS: u32 = 5_000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// Radio settings to listen on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Tune {
+    pub network_id: u8,
+    pub sf: u8,
+}
+
+/// A node heard on settings other than Node 2's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Mismatch {
+    pub node: u8,
+    pub on: Tune,
+}
+
+/// What the scan is doing, for the display and `scan`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Status {
+    /// On Node 2's own settings
+    Listening,
+    /// Trying `Tune`, `index` of `of`
+    Scanning { tune: Tune, index: u8, of: u8 },
+    /// A node was found on other settings; cleared when a good frame
+    /// comes in on Node 2's own
+    Found(Mismatch),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Listening,
+    Scanning { index: u8, since_ms: u32, home: Tune },
+}
+
+/// Node 2's scan state
+#[derive(Debug, Clone, Copy)]
+pub struct Scan {
+    state: State,
+    found: Option<Mismatch>,
+    last_heard_ms: u32,
+}
+
+impl Scan {
+    pub const fn new() -> Self {
+        Self { state: State::Listening, found: None, last_heard_ms: 0 }
+    }
+
+    /// A good frame from `node`; the settings to go back to if it ended a
+    /// scan
+    pub fn heard(&mut self, node: u8, now_ms: u32) -> Option<Tune> {
+        self.last_heard_ms = now_ms;
+        match self.state {
+            State::Listening => {
+                self.found = None;
+                None
+            }
+            State::Scanning { index, home, .. } => {
+                self.found = candidate(index, home).map(|on| Mismatch { node, on });
+                self.state = State::Listening;
+                Some(home)
+            }
+        }
+    }
+
+    /// Called every tick with Node 2's own settings and the time to spend
+    /// on each candidate; the settings to move the module to, if any
+    pub fn poll(&mut self, now_ms: u32, home: Tune, dwell_ms: u32) -> Option<Tune> {
+        match self.state {
+            State::Listening => {
+                if !enabled() || now_ms.wrapping_sub(self.last_heard_ms) < SILENCE_MS {
+                    return None;
+                }
+                self.state = State::Scanning { index: 0, since_ms: now_ms, home };
+                candidate(0, home)
+            }
+            State::Scanning { index, since_ms, home } => {
+                if enabled() && now_ms.wrapping_sub(since_ms) < dwell_ms {
+                    return None;
+                }
+                let next = index + 1;
+                match candidate(next, home).filter(|_| enabled()) {
+                    Some(tune) => {
+                        self.state = State::Scanning { index: next, since_ms: now_ms, home };
+                        Some(tune)
+                    }
+                    // Tried everything, or switched off: wait for another silence
+                    None => {
+                        self.state = State::Listening;
+                        self.last_heard_ms = now_ms;
+                        Some(home)
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match (self.state, self.found) {
+            (State::Scanning { index, home, .. }, _) => match candidate(index, home) {
+                Some(tune) => Status::Scanning { tune, index: index + 1, of: candidates(home).count() as u8 },
+                None => Status::Listening,
+            },
+            (State::Listening, Some(found)) => Status::Found(found),
+            (State::Listening, None) => Status::Listening,
+        }
+    }
+}
+
+impl Default for Scan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Every setting other than `home`, most likely mistakes first: one
+/// setting wrong before both
+fn candidates(home: Tune) -> impl Iterator<Item = Tune> {
+    let networks = NETWORK_IDS.into_iter().filter(move |&id| id != home.network_id);
+    let sfs = SF_RANGE.filter(move |&sf| sf != home.sf);
+    let network_only = networks.clone().map(move |network_id| Tune { network_id, sf: home.sf });
+    let sf_only = sfs.clone().map(move |sf| Tune { network_id: home.network_id, sf });
+    let both = sfs.flat_map(move |sf| networks.clone().map(move |network_id| Tune { network_id, sf }));
+    network_only.chain(sf_only).chain(both)
+}
+
+fn candidate(index: u8, home: Tune) -> Option<Tune> {
+    candidates(home).nth(index as usize)
+}
@@ -0,0 +1,212 @@
+//! Packet log on an SD card, one CSV file per day (feature `sd-log`)
+//!
+//! A receiver left in a shed for weeks has no host PC to catch its defmt
+//! output. With a microSD breakout on SPI1, Node 2 appends every decoded
+//! reading to a CSV file on the card's first FAT volume:
+//!
+//! ```text
+//! uptime_s,time,seq,source,age_s,temp_c,humidity_pct,gas_ohm,iaq,co2_ppm,battery_mv,rssi_dbm,snr_db
+//! 3605,14:02,361,live,,21.4,48.20,51234,87,,3912,-71,9
+//! ```
+//!
+//! Files are named `RrrrDddd.CSV`: `rrr` numbers the boot (one more than the
+//! highest run already on the card) and `ddd` the day within it, which rolls
+//! over at midnight once the shell's `display clock` has been set (see
+//! `panel`) and every 24 h of uptime until then. Backfilled readings from
+//! Node 1's flash (see `backlog`) are logged with `source` `backfill`, their
+//! age and no link figures.
+//!
+//! Each line is flushed straight away, directory entry included, so pulling
+//! the card or the power loses at most the line being written. The card is
+//! initialised at 400 kHz as SD requires and then run at APB2 / 8.
+//!
+//! Wiring (Node 2, SPI1):
+//!
+//! | Signal | Pin |
+//! |--------|-----|
+//! | SCK    | PB3 |
+//! | MISO   | PB4 |
+//! | MOSI   | PB5 |
+//! | CS     | PB6 |
+
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embedded_sdmmc::{BlockDevice, Mode, RawDirectory, RawFile, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use heapless::String;
+use stm32f4xx_hal::pac;
+
+use crate::panel;
+
+// SPI_CR1 bits (RM0390 section 26.7.1)
+const SPI_CR1_BR_MASK: u32 = 0b111 << 3;
+const SPI_CR1_BR_DIV8: u32 = 0b010 << 3;
+const SPI_CR1_SPE: u32 = 1 << 6;
+
+const HEADER: &str = "uptime_s,time,seq,source,age_s,temp_c,humidity_pct,gas_ohm,iaq,co2_ppm,battery_mv,rssi_dbm,snr_db\n";
+
+/// Uptime for the files' FAT timestamps, set before every write
+static NOW_S: AtomicU32 = AtomicU32::new(0);
+
+/// Where a logged reading came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Source {
+    Live { rssi: i16, snr: i16 },
+    /// Replayed from Node 1's backlog; `None` if from before its last reset
+    Backfill { age_s: Option<u32> },
+}
+
+/// One CSV line
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    pub seq: u16,
+    pub source: Source,
+    pub temperature_c: f32,
+    pub humidity_pct: f32,
+    pub gas_ohms: u32,
+    pub iaq: Option<u16>,
+    pub co2_ppm: Option<u16>,
+    pub battery_mv: Option<u16>,
+}
+
+/// FAT timestamps: no calendar date is known, so files are dated 1 January
+/// 2000 at the `panel` clock's time of day (or midnight until it's set)
+pub struct Clock;
+
+impl TimeSource for Clock {
+    fn get_timestamp(&self) -> Timestamp {
+        let minute = panel::minute_of_day(NOW_S.load(Ordering::Relaxed)).unwrap_or(0);
+        Timestamp {
+            year_since_1970: 30,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: (minute / 60) as u8,
+            minutes: (minute % 60) as u8,
+            seconds: 0,
+        }
+    }
+}
+
+/// Run SPI1 at APB2 / 8 (10.5 MHz, half that in `PowerProfile::Economy`)
+/// now that the card is past its 400 kHz initialisation
+pub fn spi1_full_speed() {
+    let spi = unsafe { &*pac::SPI1::ptr() };
+    spi.cr1().modify(|r, w| unsafe { w.bits(r.bits() & !SPI_CR1_SPE) });
+    spi.cr1().modify(|r, w| unsafe { w.bits((r.bits() & !SPI_CR1_BR_MASK) | SPI_CR1_BR_DIV8 | SPI_CR1_SPE) });
+}
+
+pub struct Logger<D: BlockDevice> {
+    volumes: VolumeManager<D, Clock>,
+    root: RawDirectory,
+    run: u16,
+    /// Day number and handle of the file being appended to
+    file: Option<(u32, RawFile)>,
+    lines: u32,
+    errors: u32,
+}
+
+impl<D: BlockDevice> Logger<D>
+where
+    D::Error: defmt::Format,
+{
+    /// Open the card's first volume and pick this boot's run number
+    pub fn mount(device: D) -> Result<Self, embedded_sdmmc::Error<D::Error>> {
+        let volumes = VolumeManager::new(device, Clock);
+        let volume = volumes.open_raw_volume(VolumeIdx(0))?;
+        let root = volumes.open_root_dir(volume)?;
+
+        let mut last_run = None;
+        volumes.iterate_dir(root, |entry| {
+            if let Some(run) = parse_run(entry.name.base_name()) {
+                last_run = last_run.max(Some(run));
+            }
+        })?;
+        let run = last_run.map_or(0, |run| (run + 1) % 1000);
+        Ok(Self { volumes, root, run, file: None, lines: 0, errors: 0 })
+    }
+
+    pub fn run(&self) -> u16 {
+        self.run
+    }
+
+    /// Append `record`, received at `uptime_s`, to the day's file
+    pub fn append(&mut self, record: &Record, uptime_s: u32) {
+        NOW_S.store(uptime_s, Ordering::Relaxed);
+        let mut line: String<128> = String::new();
+        format_line(&mut line, record, uptime_s);
+        match self.write(uptime_s, line.as_bytes()) {
+            Ok(()) => self.lines += 1,
+            Err(e) => {
+                self.errors += 1;
+                defmt::warn!("SD write failed ({} so far): {}", self.errors, e);
+                // Reopen on the next line, in case the card was swapped
+                if let Some((_, file)) = self.file.take() {
+                    let _ = self.volumes.close_file(file);
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, uptime_s: u32, bytes: &[u8]) -> Result<(), embedded_sdmmc::Error<D::Error>> {
+        let day = panel::day_number(uptime_s) % 1000;
+        let file = match self.file {
+            Some((open_day, file)) if open_day == day => file,
+            _ => {
+                if let Some((_, file)) = self.file.take() {
+                    self.volumes.close_file(file)?;
+                }
+                let mut name: String<12> = String::new();
+                let _ = write!(name, "R{:03}D{:03}.CSV", self.run, day);
+                let file = self.volumes.open_file_in_dir(self.root, name.as_str(), Mode::ReadWriteCreateOrAppend)?;
+                self.file = Some((day, file));
+                if self.volumes.file_length(file)? == 0 {
+                    self.volumes.write(file, HEADER.as_bytes())?;
+                }
+                defmt::info!("SD: logging to {=str}", name.as_str());
+                file
+            }
+        };
+        self.volumes.write(file, bytes)?;
+        self.volumes.flush_file(file)
+    }
+}
+
+/// `Rrrr` from an `RrrrDddd` base name
+fn parse_run(base: &[u8]) -> Option<u16> {
+    match base {
+        [b'R', r @ .., b'D', _, _, _] if r.len() == 3 => core::str::from_utf8(r).ok()?.parse().ok(),
+        _ => None,
+    }
+}
+
+fn format_line(line: &mut String<128>, r: &Record, uptime_s: u32) {
+    let _ = write!(line, "{},", uptime_s);
+    if let Some(minute) = panel::minute_of_day(uptime_s) {
+        let _ = write!(line, "{:02}:{:02}", minute / 60, minute % 60);
+    }
+    let _ = write!(line, ",{},", r.seq);
+    match r.source {
+        Source::Live { .. } => {
+            let _ = line.push_str("live,");
+        }
+        Source::Backfill { age_s } => {
+            let _ = line.push_str("backfill,");
+            if let Some(age_s) = age_s {
+                let _ = write!(line, "{}", age_s);
+            }
+        }
+    }
+    let _ = write!(line, ",{:.1},{:.2},{},", r.temperature_c, r.humidity_pct, r.gas_ohms);
+    for value in [r.iaq, r.co2_ppm, r.battery_mv] {
+        if let Some(value) = value {
+            let _ = write!(line, "{}", value);
+        }
+        let _ = line.push(',');
+    }
+    if let Source::Live { rssi, snr } = r.source {
+        let _ = write!(line, "{},{}", rssi, snr);
+    } else {
+        let _ = line.push(',');
+    }
+    let _ = line.push('\n');
+}
@@ -0,0 +1,117 @@
+//! Which senders Node 2 listens to (`senders`)
+//!
+//! The network ID keeps other LoRa users out, but not a neighbour running
+//! this firmware on the same network: its nodes' readings would be ACKed,
+//! logged and shown as if they were ours. `senders allow` turns the list
+//! into an allow-list, so only the nodes on it are heard; `senders deny`
+//! into a deny-list, so everyone but them is. Frames from anyone else are
+//! dropped right after their CRC check, before they are ACKed, counted or
+//! logged, and only show in the dropped count. Forwarding with `stream on`
+//! and relaying still see them.
+//!
+//! The list holds node IDs 0-`MAX_NODE`, a bit each, which covers every
+//! address the setup menu and joining hand out. It is saved with the other
+//! shell settings.
+
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+/// Highest node ID the list can hold
+pub const MAX_NODE: u8 = 31;
+
+/// What the list means
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Mode {
+    /// Every sender is heard; the list is kept but unused
+    Off = 0,
+    /// Only the listed nodes are heard
+    Allow = 1,
+    /// The listed nodes are ignored
+    Deny = 2,
+}
+
+impl Mode {
+    pub fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Mode::Off),
+            1 => Some(Mode::Allow),
+            2 => Some(Mode::Deny),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Mode::Off),
+            "allow" => Some(Mode::Allow),
+            "deny" => Some(Mode::Deny),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Off => "off",
+            Mode::Allow => "allow",
+            Mode::Deny => "deny",
+        }
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Off as u8);
+static LISTED: AtomicU32 = AtomicU32::new(0);
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+pub fn mode() -> Mode {
+    Mode::from_index(MODE.load(Ordering::Relaxed)).unwrap_or(Mode::Off)
+}
+
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// The list as a bit per node ID, as it is saved
+pub fn listed_mask() -> u32 {
+    LISTED.load(Ordering::Relaxed)
+}
+
+pub fn set_listed_mask(mask: u32) {
+    LISTED.store(mask, Ordering::Relaxed);
+}
+
+/// Put `node` on the list, or take it off; `false` if it is above `MAX_NODE`
+pub fn set_listed(node: u8, on: bool) -> bool {
+    if node > MAX_NODE {
+        return false;
+    }
+    if on {
+        LISTED.fetch_or(1 << node, Ordering::Relaxed);
+    } else {
+        LISTED.fetch_and(!(1 << node), Ordering::Relaxed);
+    }
+    true
+}
+
+/// The listed node IDs, lowest first
+pub fn listed() -> impl Iterator<Item = u8> {
+    let mask = listed_mask();
+    (0..=MAX_NODE).filter(move |&node| mask & (1 << node) != 0)
+}
+
+/// Whether to take a frame from `node`; counts the ones that aren't
+pub fn admit(node: u8) -> bool {
+    let listed = node <= MAX_NODE && listed_mask() & (1 << node) != 0;
+    let admitted = match mode() {
+        Mode::Off => true,
+        Mode::Allow => listed,
+        Mode::Deny => !listed,
+    };
+    if !admitted {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    admitted
+}
+
+/// Frames dropped since boot
+pub fn dropped() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
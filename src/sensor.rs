@@ -0,0 +1,356 @@
+//! Environmental sensors behind one trait
+//!
+//! Node 1 started out hard-wired to an SHT31 for temperature/humidity and a
+//! BME680 for gas. Each sensor now implements `EnvironmentalSensor`, which
+//! adds whatever quantities it measures to a shared `Measurement`. Sensors
+//! are read in order of trust and never overwrite a value an earlier one
+//! already supplied, so the SHT31 stays the temperature reference while the
+//! BME680 contributes gas and an SCD40 (feature `scd40`) contributes CO2.
+//!
+//! The set of fitted sensors is announced to the gateway as a bit mask in
+//! the `NodeInfo` packet (see `SensorKind::bit`). A sensor that fails to
+//! answer, or answers with values outside its physical range, is reported
+//! in a `SensorFaultPacket` instead of the cycle's reading.
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+use bme680::{Bme680, IIRFilterSize, OversamplingSetting, PowerMode, Settings, SettingsBuilder};
+use embedded_hal_0_2::blocking::delay::DelayMs;
+use embedded_hal_0_2::blocking::i2c::{Read, Write};
+#[cfg(feature = "scd40")]
+use embedded_hal_0_2::blocking::i2c::WriteRead;
+use crc::{Crc, CRC_8_NRSC_5};
+
+use crate::calibration::Calibration;
+use crate::gas::{HeaterStep, Scanner};
+use crate::protocol::SensorFaultPacket;
+use crate::raw;
+
+/// A sensor model, as announced in `NodeInfo::sensors`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum SensorKind {
+    Sht31 = 0,
+    Bme680 = 1,
+    Scd40 = 2,
+}
+
+impl SensorKind {
+    pub const ALL: [SensorKind; 3] = [SensorKind::Sht31, SensorKind::Bme680, SensorKind::Scd40];
+
+    /// This sensor's bit in the announced mask
+    pub const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SensorKind::Sht31 => "SHT31",
+            SensorKind::Bme680 => "BME680",
+            SensorKind::Scd40 => "SCD40",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The kind with discriminant `index`, as sent over the air
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+/// Why a sensor couldn't be read; the discriminant is the on-air code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum SensorError {
+    /// The I2C transaction failed (NACK, arbitration loss, ...)
+    Bus = 1,
+    /// It answered, but with a value no real environment produces
+    Implausible = 2,
+}
+
+impl SensorError {
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(SensorError::Bus),
+            2 => Some(SensorError::Implausible),
+            _ => None,
+        }
+    }
+
+    /// Short label for the display
+    pub fn label(self) -> &'static str {
+        match self {
+            SensorError::Bus => "NO ANSWER",
+            SensorError::Implausible => "BAD VALUE",
+        }
+    }
+}
+
+/// A failed read and the sensor it came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct SensorFault {
+    pub sensor: SensorKind,
+    pub error: SensorError,
+}
+
+impl SensorFault {
+    pub fn packet(&self) -> SensorFaultPacket {
+        SensorFaultPacket { sensor: self.sensor as u8, error: self.error as u8 }
+    }
+
+    /// `None` for a sensor or code this build doesn't know
+    pub fn from_packet(packet: &SensorFaultPacket) -> Option<Self> {
+        Some(SensorFault {
+            sensor: SensorKind::from_index(packet.sensor)?,
+            error: SensorError::from_code(packet.error)?,
+        })
+    }
+}
+
+/// One cycle's worth of readings from every fitted sensor
+#[derive(Debug, Clone, Copy, Default, PartialEq, defmt::Format)]
+pub struct Measurement {
+    pub temperature_c: Option<f32>,
+    pub humidity_pct: Option<f32>,
+    pub gas_ohms: Option<u32>,
+    /// `gas::HEATER_PROFILE` index the gas reading was taken at
+    pub heater_step: Option<u8>,
+    pub co2_ppm: Option<u16>,
+    /// The SHT31's unconverted temperature and humidity ticks, for raw mode
+    pub raw_th: Option<(u16, u16)>,
+}
+
+impl Measurement {
+    /// Whether every reading present is physically possible
+    ///
+    /// Garbage from a sensor in a bad state (a BME680 mid-reset reads
+    /// -40 °C / 100 %) passes the I2C layer, so it is caught here.
+    pub fn plausible(&self) -> bool {
+        self.temperature_c.is_none_or(|t| (-40.0..=85.0).contains(&t))
+            && self.humidity_pct.is_none_or(|h| (0.0..=100.0).contains(&h))
+            && self.gas_ohms.is_none_or(|g| g > 0)
+            && self.co2_ppm.is_none_or(|c| c <= 40_000)
+    }
+
+    /// Take any of `other`'s readings that `self` doesn't have yet
+    pub fn merge(&mut self, other: &Measurement) {
+        fn keep<T: Copy>(slot: &mut Option<T>, value: Option<T>) {
+            if let Some(value) = value {
+                fill(slot, value);
+            }
+        }
+        keep(&mut self.temperature_c, other.temperature_c);
+        keep(&mut self.humidity_pct, other.humidity_pct);
+        keep(&mut self.gas_ohms, other.gas_ohms);
+        keep(&mut self.heater_step, other.heater_step);
+        keep(&mut self.co2_ppm, other.co2_ppm);
+        keep(&mut self.raw_th, other.raw_th);
+    }
+}
+
+fn fill<T>(slot: &mut Option<T>, value: T) {
+    slot.get_or_insert(value);
+}
+
+pub trait EnvironmentalSensor {
+    const KIND: SensorKind;
+
+    /// Add this sensor's readings to `m`, keeping any already present
+    fn measure(&mut self, m: &mut Measurement) -> Result<(), SensorError>;
+
+    fn kind(&self) -> SensorKind {
+        Self::KIND
+    }
+
+    /// `measure`, checked and corrected by this sensor's trim, with a
+    /// failure naming it
+    fn read_into(&mut self, m: &mut Measurement, calibration: &Calibration) -> Result<(), SensorFault> {
+        let mut own = Measurement::default();
+        self.measure(&mut own).map_err(|error| SensorFault { sensor: Self::KIND, error })?;
+        if !own.plausible() {
+            return Err(SensorFault { sensor: Self::KIND, error: SensorError::Implausible });
+        }
+        calibration.trim(Self::KIND).apply(&mut own);
+        m.merge(&own);
+        Ok(())
+    }
+}
+
+/// Sensirion SHT31: temperature and humidity, the node's reference
+///
+/// Driven directly rather than through a driver crate so the raw ticks are
+/// kept alongside the converted values (see `raw`).
+pub struct Sht31<I2C, D> {
+    i2c: I2C,
+    delay: D,
+}
+
+impl<I2C, D> Sht31<I2C, D> {
+    /// ADDR pin low
+    const ADDRESS: u8 = 0x44;
+    /// Single shot, high repeatability, no clock stretching
+    const MEASURE: [u8; 2] = [0x24, 0x00];
+    /// Word checksum, CRC-8 poly 0x31 init 0xFF (datasheet section 4.12)
+    const CRC8: Crc<u8> = Crc::<u8>::new(&CRC_8_NRSC_5);
+
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Self { i2c, delay }
+    }
+}
+
+impl<I2C, D, E> Sht31<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E>,
+    D: DelayMs<u8>,
+{
+    /// One high-repeatability conversion: (temperature, humidity) ticks
+    fn read_ticks(&mut self) -> Result<(u16, u16), SensorError> {
+        self.i2c.write(Self::ADDRESS, &Self::MEASURE).map_err(|_| SensorError::Bus)?;
+        // Max conversion time at high repeatability
+        self.delay.delay_ms(15);
+        let mut buf = [0u8; 6];
+        self.i2c.read(Self::ADDRESS, &mut buf).map_err(|_| SensorError::Bus)?;
+
+        let mut words = buf.chunks_exact(3).map(|w| {
+            (Self::CRC8.checksum(&w[..2]) == w[2]).then(|| u16::from_be_bytes([w[0], w[1]]))
+        });
+        match (words.next().flatten(), words.next().flatten()) {
+            (Some(t), Some(h)) => Ok((t, h)),
+            // A corrupted transfer is a bus problem, not a bad reading
+            _ => Err(SensorError::Bus),
+        }
+    }
+}
+
+impl<I2C, D, E> EnvironmentalSensor for Sht31<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E>,
+    D: DelayMs<u8>,
+{
+    const KIND: SensorKind = SensorKind::Sht31;
+
+    fn measure(&mut self, m: &mut Measurement) -> Result<(), SensorError> {
+        let (t, h) = self.read_ticks()?;
+        fill(&mut m.temperature_c, raw::temperature_c(t));
+        fill(&mut m.humidity_pct, raw::humidity_pct(h));
+        fill(&mut m.raw_th, (t, h));
+        Ok(())
+    }
+}
+
+/// Bosch BME680/BME688: gas resistance, scanning the heater profile
+///
+/// Also measures temperature and humidity, used only if no better sensor
+/// is fitted.
+pub struct Bme680Sensor<I2C, D> {
+    device: Bme680<I2C, D>,
+    delay: D,
+    scanner: Scanner,
+    /// Ambient temperature for the heater power calculation
+    ambient_c: i8,
+}
+
+impl<I2C, D> Bme680Sensor<I2C, D>
+where
+    I2C: Read + Write,
+    <I2C as Read>::Error: Debug,
+    <I2C as Write>::Error: Debug,
+    D: DelayMs<u8> + DelayMs<u32>,
+{
+    pub fn new(device: Bme680<I2C, D>, delay: D) -> Self {
+        Self { device, delay, scanner: Scanner::new(), ambient_c: 25 }
+    }
+
+    /// Settings for a forced measurement at heater `step`
+    fn settings(&self, step: HeaterStep) -> Settings {
+        SettingsBuilder::new()
+            .with_humidity_oversampling(OversamplingSetting::OS2x)
+            .with_pressure_oversampling(OversamplingSetting::OS4x)
+            .with_temperature_oversampling(OversamplingSetting::OS2x)
+            .with_temperature_filter(IIRFilterSize::Size3)
+            .with_gas_measurement(Duration::from_millis(step.duration_ms as u64), step.temp_c, self.ambient_c)
+            .with_run_gas(true)
+            .build()
+    }
+}
+
+impl<I2C, D> EnvironmentalSensor for Bme680Sensor<I2C, D>
+where
+    I2C: Read + Write,
+    <I2C as Read>::Error: Debug,
+    <I2C as Write>::Error: Debug,
+    D: DelayMs<u8> + DelayMs<u32>,
+{
+    const KIND: SensorKind = SensorKind::Bme680;
+
+    fn measure(&mut self, m: &mut Measurement) -> Result<(), SensorError> {
+        // Each measurement heats the plate to the next step of the scan
+        let (index, step) = self.scanner.advance();
+        let settings = self.settings(step);
+        self.device.set_sensor_settings(&mut self.delay, settings).map_err(|_| SensorError::Bus)?;
+        self.device.set_sensor_mode(&mut self.delay, PowerMode::ForcedMode).map_err(|_| SensorError::Bus)?;
+
+        // Heater time plus ~50ms for the T/P/H conversions
+        DelayMs::<u32>::delay_ms(&mut self.delay, step.duration_ms as u32 + 50);
+        let (data, _state) = self.device.get_sensor_data(&mut self.delay).map_err(|_| SensorError::Bus)?;
+
+        fill(&mut m.temperature_c, data.temperature_celsius());
+        fill(&mut m.humidity_pct, data.humidity_percent());
+        fill(&mut m.gas_ohms, data.gas_resistance_ohm());
+        fill(&mut m.heater_step, index);
+
+        // Heater power for the next step is computed from the sensor's own temperature
+        if let Some(t) = m.temperature_c {
+            self.ambient_c = t.clamp(-40.0, 85.0) as i8;
+        }
+        Ok(())
+    }
+}
+
+/// Sensirion SCD40: CO2 (photoacoustic), plus temperature and humidity
+///
+/// Runs in periodic mode, producing a sample every 5 s; a cycle that finds
+/// no new sample leaves `co2_ppm` empty rather than resending an old one.
+#[cfg(feature = "scd40")]
+pub struct Scd40<I2C, D> {
+    device: scd4x::Scd4x<I2C, D>,
+}
+
+#[cfg(feature = "scd40")]
+impl<I2C, D, E> Scd40<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>,
+    D: DelayMs<u32>,
+{
+    /// Restart periodic measurement (it survives an MCU reset)
+    pub fn new(i2c: I2C, delay: D) -> Result<Self, SensorError> {
+        let mut device = scd4x::Scd4x::new(i2c, delay);
+        device.stop_periodic_measurement().map_err(|_| SensorError::Bus)?;
+        device.start_periodic_measurement().map_err(|_| SensorError::Bus)?;
+        Ok(Self { device })
+    }
+}
+
+#[cfg(feature = "scd40")]
+impl<I2C, D, E> EnvironmentalSensor for Scd40<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>,
+    D: DelayMs<u32>,
+{
+    const KIND: SensorKind = SensorKind::Scd40;
+
+    fn measure(&mut self, m: &mut Measurement) -> Result<(), SensorError> {
+        if !self.device.data_ready_status().map_err(|_| SensorError::Bus)? {
+            return Ok(());
+        }
+        let data = self.device.measurement().map_err(|_| SensorError::Bus)?;
+        fill(&mut m.co2_ppm, data.co2);
+        fill(&mut m.temperature_c, data.temperature);
+        fill(&mut m.humidity_pct, data.humidity);
+        Ok(())
+    }
+}
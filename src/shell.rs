@@ -0,0 +1,1046 @@
+//! Line-based debug shell on the ST-LINK virtual COM port (USART2)
+//!
+//! Open the Nucleo's VCP at 115200 8N1 and type commands terminated by
+//! Enter. The shell is deliberately tiny: `LineBuffer` collects bytes from
+//! the USART2 ISR, `Command::parse` turns a line into a command, and
+//! `execute` applies the commands that don't need node-specific resources,
+//! writing the reply back to the terminal. The others (`cal`, `soil`,
+//! `display`, `airtime`, `export`) are handed back for the node's own shell
+//! task to carry out.
+
+use core::fmt::Write;
+
+use heapless::Vec;
+
+use crate::adr::{self, Adr};
+use crate::airtime;
+use crate::backoff;
+use crate::calibration::Trim;
+use crate::delta::{self, Setting};
+use crate::filter::{self, Mode};
+use crate::format::{self, Age, Db, Dbm, Permille};
+use crate::group;
+use crate::hopping::{self, MAX_CHANNELS};
+use crate::linkstats::{self, Heard};
+use crate::log::{self, Level};
+use crate::loopback;
+use crate::mqtt;
+use crate::pair;
+use crate::panel;
+use crate::peers::Peers;
+use crate::ping::Outcome;
+use crate::protocol::{ConfigChange, LinkStats, RemoteCommand, MAX_ROUTE};
+use crate::raw;
+use crate::region;
+use crate::relay;
+use crate::scan::{self, Status};
+use crate::senders;
+use crate::sensor::SensorKind;
+use crate::soil::{self, Endpoint, SoilCalibration};
+use crate::tunnel;
+use crate::ui;
+
+/// Longest accepted command line
+pub const LINE_LEN: usize = 64;
+
+/// Collects echoed input until CR or LF
+pub struct LineBuffer {
+    buf: Vec<u8, LINE_LEN>,
+    overflowed: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self { buf: Vec::new(), overflowed: false }
+    }
+
+    /// Add one received byte; returns `true` once a line is complete
+    ///
+    /// Over-long lines are discarded whole rather than executed truncated.
+    /// After `true`, read the line with `line` and then call `clear`.
+    pub fn push(&mut self, byte: u8) -> bool {
+        match byte {
+            b'\r' | b'\n' => {
+                if core::mem::replace(&mut self.overflowed, false) {
+                    self.buf.clear();
+                    return false;
+                }
+                !self.buf.is_empty()
+            }
+            // Backspace / DEL from terminal emulators
+            0x08 | 0x7F => {
+                self.buf.pop();
+                false
+            }
+            _ => {
+                if self.buf.push(byte).is_err() {
+                    self.overflowed = true;
+                }
+                false
+            }
+        }
+    }
+
+    /// The completed line (empty if it wasn't valid UTF-8)
+    pub fn line(&self) -> &str {
+        core::str::from_utf8(&self.buf).unwrap_or("")
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A parsed shell command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    /// `log` - show the runtime log level
+    ShowLog,
+    /// `log <error|warn|info|debug|trace>`
+    SetLog(Level),
+    /// `saver` - show the screensaver timeout
+    ShowSaver,
+    /// `saver <minutes>` - dim after N minutes idle, blank after 2N (0 = off)
+    SetSaver(u16),
+    /// `units` - show the display temperature unit
+    ShowUnits,
+    /// `units <c|f>` - `true` for Fahrenheit
+    SetUnits(bool),
+    /// `delta` - show the send-on-delta thresholds (Node 1)
+    ShowDelta,
+    /// `delta <on|off>`
+    SetDeltaEnabled(bool),
+    /// `delta <temp|hum|gas|beat> <value>`
+    SetDelta(Setting),
+    /// `filter` - show the temperature/humidity smoothing (Node 1)
+    ShowFilter,
+    /// `filter off`, `filter <mean|median> <samples>`
+    SetFilter(Mode, u8),
+    /// `values` - show whether T/H go out raw or scaled (Node 1)
+    ShowValues,
+    /// `values <raw|scaled>` - `true` for raw SHT31 ticks
+    SetRaw(bool),
+    /// `cal <sht31|bme680|scd40> <offset C> <humidity slope %>` - trim a
+    /// sensor on Node 1 (applied there, or sent as a downlink from Node 2)
+    Calibrate(SensorKind, Trim),
+    /// `soil` - show soil probe readings and endpoints (Node 1)
+    ShowSoil,
+    /// `soil <1|2> <dry|wet>` - store a probe's current reading as an
+    /// endpoint; the probe index is 0-based
+    SetSoil(u8, Endpoint),
+    /// `display` - show the panel settings and clock
+    ShowDisplay,
+    /// `display <on|off>`, `display contrast <0-255>`,
+    /// `display night <HH:MM> <HH:MM>|off`, `display clock <HH:MM>`
+    SetDisplay(panel::Setting),
+    /// `n1 display ...` - the same for Node 1's panel, sent as a downlink
+    /// from Node 2
+    SendDisplay(panel::Setting),
+    /// `airtime` - show the duty-cycle limit and the last hour's airtime
+    ShowAirtime,
+    /// `airtime <percent|off|auto>` - `None` follows the band again, 0 is
+    /// no limit
+    SetAirtime(Option<u16>),
+    /// `export` - stream the stored readings and crash log to the host as
+    /// binary records (Node 1, see `export`)
+    Export,
+    /// `stream` - show whether received frames are forwarded (Node 2)
+    ShowStream,
+    /// `stream <on|off>` - forward every received frame to the host as a
+    /// binary record, for `wk3 watch` (Node 2, see `export`)
+    SetStream(bool),
+    /// `stream tap` - as `stream on`, plus every other line from the LoRa
+    /// module as a raw record, for `wk3 pcap` (Node 2)
+    SetTap,
+    /// `n1 interval <s>`, `n1 txpower <dBm>`, `n1 reboot`, `n1 report`,
+    /// `n1 set <name> <value> ...`, `n1 config` - manage Node 1 remotely
+    /// with an authenticated downlink from Node 2 (see `command`)
+    SendRemote(RemoteCommand),
+    /// `all [ack] display ...|interval <s>|txpower <dBm>|reboot|report|config` -
+    /// the same for every sensor node in one broadcast, answered only with
+    /// `ack`; `group <g> [ack] ...` for group g's members (Node 2, see
+    /// `broadcast` and `group`)
+    SendAll { ack: bool, group: u8, order: Order },
+    /// `mqtt` - show whether readings are printed for an MQTT bridge (Node 2)
+    ShowMqtt,
+    /// `mqtt <on|off>` - print each live reading as `topic<TAB>payload`
+    /// lines (Node 2, see `mqtt`)
+    SetMqtt(bool),
+    /// `tunnel` - show which log lines go to Node 2 over LoRa (Node 1)
+    ShowTunnel,
+    /// `tunnel <off|error|warn|info>` - forward log lines at that level
+    /// and above (Node 1, see `tunnel`)
+    SetTunnel(Option<Level>),
+    /// `relay` - show whether this node relays frames (Node 2)
+    ShowRelay,
+    /// `relay <on|off>` - pass other nodes' frames on to the gateway
+    /// instead of acting as one (Node 2, see `relay`)
+    SetRelay(bool),
+    /// `via` - show the address uplinks are sent to
+    ShowVia,
+    /// `via <address>` - send uplinks to a relay, or to the gateway (2)
+    SetVia(u16),
+    /// `hop` - show the channels the network hops over
+    ShowHop,
+    /// `hop <MHz> <MHz> [...]` or `hop off` - hop over those channels, in
+    /// kHz and 0 past the last, or stay on the menu's band (see `hopping`)
+    SetHop([u32; MAX_CHANNELS]),
+    /// `route` - show the relays uplinks are pinned to (Node 1)
+    ShowRoute,
+    /// `route <relay> [relay]` or `route off` - pin uplinks to a path
+    /// through those relays, 0 past its end (Node 1, see `relay`)
+    SetRoute([u8; MAX_ROUTE]),
+    /// `backoff` - show collision avoidance and what it has done (Node 1)
+    ShowBackoff,
+    /// `backoff <on|off>` - jitter the interval without a slot and hold
+    /// back for a busy channel (Node 1, see `backoff`)
+    SetBackoff(bool),
+    /// `loopback` - show what the loopback has carried (Node 1, feature
+    /// `loopback`)
+    ShowLoopback,
+    /// `loopback <loss%> [corrupt%]` - lose and corrupt that share of the
+    /// loopback's frames each way (see `loopback`)
+    SetLoopback(u8, u8),
+    /// `peers` - list the sensor nodes heard, with their link statistics
+    /// (Node 2, see `peers`)
+    ShowPeers,
+    /// `adr` - show adaptive data rate and each node's link (Node 2)
+    ShowAdr,
+    /// `adr <on|off>` - step the nodes' TX power and spreading factor to
+    /// their links (Node 2, see `adr`)
+    SetAdr(bool),
+    /// `scan` - show whether Node 2 is scanning, or what it found (Node 2)
+    ShowScan,
+    /// `scan <on|off>` - look for nodes on other network IDs and spreading
+    /// factors after a long silence (Node 2, see `scan`)
+    SetScan(bool),
+    /// `senders` - show which senders are heard and how many were dropped
+    /// (Node 2)
+    ShowSenders,
+    /// `senders <off|allow|deny>` - ignore no one, everyone off the list,
+    /// or everyone on it (Node 2, see `senders`)
+    SetSendersMode(senders::Mode),
+    /// `senders add|del <node>` - put a node ID on the list or take it off
+    ListSender(u8, bool),
+    /// `groups` - show the broadcast groups this node is in
+    ShowGroups,
+    /// `groups <g>...|none` - join groups 1-8, leaving any others (see
+    /// `group`)
+    SetGroups(u8),
+    /// `pair` - show whether this node is in peer mode (Node 1)
+    ShowPair,
+    /// `pair <on|off>` - ACK and show the readings of the node `via`
+    /// names, which does the same for ours (Node 1, see `pair`)
+    SetPair(bool),
+    /// `ping [address]` - check the link to `via` (Node 1) or to Node 1
+    /// (Node 2), or to `address`: round trip and both directions' RSSI
+    /// and SNR (see `ping`)
+    Ping(Option<u16>),
+    /// `linkstats` - this end's link counters and the other end's last
+    /// report of its own (see `linkstats`)
+    ShowLinkStats,
+}
+
+/// A downlink `n1 ...` sends to Node 1 and `all ...` to every node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Display(panel::Setting),
+    Remote(RemoteCommand),
+}
+
+impl Order {
+    /// `display ...`, `interval <s>`, `txpower <dBm>`, `reboot`, `report`,
+    /// `set ...` or `config`, the first word already taken
+    fn parse<'a>(what: &str, words: &mut impl Iterator<Item = &'a str>) -> Result<Self, ParseError> {
+        Ok(match what {
+            "display" => {
+                let what = words.next().ok_or(ParseError::BadArgument)?;
+                Order::Display(panel::Setting::parse(what, words).ok_or(ParseError::BadArgument)?)
+            }
+            "interval" => {
+                let seconds = words.next().and_then(|s| s.parse().ok()).ok_or(ParseError::BadArgument)?;
+                Order::Remote(RemoteCommand::SetInterval(seconds))
+            }
+            "txpower" => {
+                let dbm = words.next().and_then(|s| s.parse().ok()).ok_or(ParseError::BadArgument)?;
+                Order::Remote(RemoteCommand::SetTxPower(dbm))
+            }
+            "reboot" => Order::Remote(RemoteCommand::Reboot),
+            "report" => Order::Remote(RemoteCommand::ReportNow),
+            "set" => Order::Remote(RemoteCommand::SetConfig(parse_change(words)?)),
+            "config" => Order::Remote(RemoteCommand::GetConfig),
+            _ => return Err(ParseError::Unknown),
+        })
+    }
+}
+
+/// `interval <s>`, `txpower <dBm>`, `delta on|off` and `delta`'s `temp`,
+/// `hum`, `gas` and `beat`, in any order; none at all changes nothing
+fn parse_change<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<ConfigChange, ParseError> {
+    let mut change = ConfigChange::default();
+    while let Some(name) = words.next() {
+        let value = words.next().ok_or(ParseError::BadArgument)?;
+        match name {
+            "interval" => change.interval_s = Some(value.parse().map_err(|_| ParseError::BadArgument)?),
+            "txpower" => change.tx_power_dbm = Some(value.parse().map_err(|_| ParseError::BadArgument)?),
+            "delta" => {
+                change.delta_enabled = Some(match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(ParseError::BadArgument),
+                })
+            }
+            _ => match Setting::parse(name, value).ok_or(ParseError::BadArgument)? {
+                Setting::Temp(v) => change.delta_temp_deci_c = Some(v),
+                Setting::Humidity(v) => change.delta_humidity_deci_pct = Some(v),
+                Setting::Gas(v) => change.delta_gas_percent = Some(v),
+                Setting::Heartbeat(v) => change.delta_heartbeat_s = Some(v),
+            },
+        }
+    }
+    Ok(change)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    Unknown,
+    BadArgument,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut words = line.split_ascii_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("help") | Some("?"), None) => Command::Help,
+            (Some("log"), None) => Command::ShowLog,
+            (Some("log"), Some(level)) => {
+                Command::SetLog(Level::from_name(level).ok_or(ParseError::BadArgument)?)
+            }
+            (Some("saver"), None) => Command::ShowSaver,
+            (Some("saver"), Some(minutes)) => {
+                Command::SetSaver(minutes.parse().map_err(|_| ParseError::BadArgument)?)
+            }
+            (Some("units"), None) => Command::ShowUnits,
+            (Some("units"), Some("c")) => Command::SetUnits(false),
+            (Some("units"), Some("f")) => Command::SetUnits(true),
+            (Some("units"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("delta"), None) => Command::ShowDelta,
+            (Some("delta"), Some("on")) => Command::SetDeltaEnabled(true),
+            (Some("delta"), Some("off")) => Command::SetDeltaEnabled(false),
+            (Some("delta"), Some(name)) => {
+                let value = words.next().ok_or(ParseError::BadArgument)?;
+                Command::SetDelta(Setting::parse(name, value).ok_or(ParseError::BadArgument)?)
+            }
+            (Some("filter"), None) => Command::ShowFilter,
+            (Some("filter"), Some("off")) => Command::SetFilter(Mode::Off, filter::window() as u8),
+            (Some("filter"), Some(mode)) => {
+                let mode = Mode::from_name(mode).ok_or(ParseError::BadArgument)?;
+                let window = words
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| (1..=filter::MAX_WINDOW as u8).contains(&n))
+                    .ok_or(ParseError::BadArgument)?;
+                Command::SetFilter(mode, window)
+            }
+            (Some("values"), None) => Command::ShowValues,
+            (Some("values"), Some("raw")) => Command::SetRaw(true),
+            (Some("values"), Some("scaled")) => Command::SetRaw(false),
+            (Some("values"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("cal"), Some(sensor)) => {
+                let sensor = SensorKind::from_name(sensor).ok_or(ParseError::BadArgument)?;
+                let (offset, slope) = words.next().zip(words.next()).ok_or(ParseError::BadArgument)?;
+                Command::Calibrate(sensor, Trim::parse(offset, slope).ok_or(ParseError::BadArgument)?)
+            }
+            (Some("soil"), None) => Command::ShowSoil,
+            (Some("soil"), Some(probe)) => {
+                let probe = probe
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|n| (1..=soil::PROBES as u8).contains(n))
+                    .ok_or(ParseError::BadArgument)?;
+                let endpoint = words.next().and_then(Endpoint::from_name).ok_or(ParseError::BadArgument)?;
+                Command::SetSoil(probe - 1, endpoint)
+            }
+            (Some("display"), None) => Command::ShowDisplay,
+            (Some("display"), Some(what)) => {
+                Command::SetDisplay(panel::Setting::parse(what, &mut words).ok_or(ParseError::BadArgument)?)
+            }
+            (Some("airtime"), None) => Command::ShowAirtime,
+            (Some("airtime"), Some("auto")) => Command::SetAirtime(None),
+            (Some("airtime"), Some("off")) => Command::SetAirtime(Some(0)),
+            (Some("airtime"), Some(percent)) => {
+                let percent: f32 = percent.parse().map_err(|_| ParseError::BadArgument)?;
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(ParseError::BadArgument);
+                }
+                Command::SetAirtime(Some((percent * 10.0 + 0.5) as u16))
+            }
+            (Some("export"), None) => Command::Export,
+            (Some("stream"), None) => Command::ShowStream,
+            (Some("stream"), Some("on")) => Command::SetStream(true),
+            (Some("stream"), Some("off")) => Command::SetStream(false),
+            (Some("stream"), Some("tap")) => Command::SetTap,
+            (Some("stream"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("mqtt"), None) => Command::ShowMqtt,
+            (Some("mqtt"), Some("on")) => Command::SetMqtt(true),
+            (Some("mqtt"), Some("off")) => Command::SetMqtt(false),
+            (Some("mqtt"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("tunnel"), None) => Command::ShowTunnel,
+            (Some("tunnel"), Some("off")) => Command::SetTunnel(None),
+            (Some("tunnel"), Some(level)) => {
+                let level = Level::from_name(level).filter(|&l| l <= Level::Info).ok_or(ParseError::BadArgument)?;
+                Command::SetTunnel(Some(level))
+            }
+            (Some("relay"), None) => Command::ShowRelay,
+            (Some("relay"), Some("on")) => Command::SetRelay(true),
+            (Some("relay"), Some("off")) => Command::SetRelay(false),
+            (Some("relay"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("hop"), None) => Command::ShowHop,
+            (Some("hop"), Some("off")) => Command::SetHop([0; MAX_CHANNELS]),
+            (Some("hop"), Some(first)) => {
+                let mut channels = [0; MAX_CHANNELS];
+                let mut count = 0;
+                for word in core::iter::once(first).chain(&mut words) {
+                    let khz = hopping::parse_khz(word).ok_or(ParseError::BadArgument)?;
+                    *channels.get_mut(count).ok_or(ParseError::BadArgument)? = khz;
+                    count += 1;
+                }
+                // One channel is no hopping at all
+                if count < 2 {
+                    return Err(ParseError::BadArgument);
+                }
+                Command::SetHop(channels)
+            }
+            (Some("route"), None) => Command::ShowRoute,
+            (Some("route"), Some("off")) => Command::SetRoute([0; MAX_ROUTE]),
+            (Some("route"), Some(first)) => {
+                let mut route = [0; MAX_ROUTE];
+                for (i, word) in core::iter::once(first).chain(&mut words).enumerate() {
+                    // 0 is the broadcast address, and ends the route
+                    let address = word.parse().ok().filter(|&a| a != 0).ok_or(ParseError::BadArgument)?;
+                    *route.get_mut(i).ok_or(ParseError::BadArgument)? = address;
+                }
+                Command::SetRoute(route)
+            }
+            (Some("via"), None) => Command::ShowVia,
+            (Some("via"), Some(address)) => {
+                // 0 is the broadcast address
+                Command::SetVia(address.parse().ok().filter(|&a| a != 0).ok_or(ParseError::BadArgument)?)
+            }
+            (Some("backoff"), None) => Command::ShowBackoff,
+            (Some("backoff"), Some("on")) => Command::SetBackoff(true),
+            (Some("backoff"), Some("off")) => Command::SetBackoff(false),
+            (Some("backoff"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("loopback"), None) => Command::ShowLoopback,
+            (Some("loopback"), Some(loss)) => {
+                let pct = |text: &str| text.parse().ok().filter(|&pct: &u8| pct <= 100).ok_or(ParseError::BadArgument);
+                Command::SetLoopback(pct(loss)?, words.next().map_or(Ok(0), pct)?)
+            }
+            (Some("peers"), None) => Command::ShowPeers,
+            (Some("scan"), None) => Command::ShowScan,
+            (Some("scan"), Some("on")) => Command::SetScan(true),
+            (Some("scan"), Some("off")) => Command::SetScan(false),
+            (Some("scan"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("adr"), None) => Command::ShowAdr,
+            (Some("adr"), Some("on")) => Command::SetAdr(true),
+            (Some("adr"), Some("off")) => Command::SetAdr(false),
+            (Some("adr"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("senders"), None) => Command::ShowSenders,
+            (Some("senders"), Some(what @ ("add" | "del"))) => {
+                let node = words
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n <= senders::MAX_NODE)
+                    .ok_or(ParseError::BadArgument)?;
+                Command::ListSender(node, what == "add")
+            }
+            (Some("senders"), Some(mode)) => {
+                Command::SetSendersMode(senders::Mode::from_name(mode).ok_or(ParseError::BadArgument)?)
+            }
+            (Some("n1"), Some(what)) => match Order::parse(what, &mut words)? {
+                Order::Display(setting) => Command::SendDisplay(setting),
+                Order::Remote(command) => Command::SendRemote(command),
+            },
+            (Some(to @ ("all" | "group")), Some(first)) => {
+                // `group` names its group before the rest of `all`
+                let (group, first) = if to == "group" {
+                    let group = first.parse().ok().filter(|&g| group::bit(g) != 0).ok_or(ParseError::BadArgument)?;
+                    (group, words.next().ok_or(ParseError::BadArgument)?)
+                } else {
+                    (group::BROADCAST_EVERYONE, first)
+                };
+                let ack = first == "ack";
+                let what = if ack { words.next().ok_or(ParseError::BadArgument)? } else { first };
+                Command::SendAll { ack, group, order: Order::parse(what, &mut words)? }
+            }
+            (Some("ping"), None) => Command::Ping(None),
+            (Some("linkstats"), None) => Command::ShowLinkStats,
+            (Some("ping"), Some(address)) => {
+                // 0 is the broadcast address: every node would answer
+                Command::Ping(Some(address.parse().ok().filter(|&a| a != 0).ok_or(ParseError::BadArgument)?))
+            }
+            (Some("pair"), None) => Command::ShowPair,
+            (Some("pair"), Some("on")) => Command::SetPair(true),
+            (Some("pair"), Some("off")) => Command::SetPair(false),
+            (Some("pair"), Some(_)) => return Err(ParseError::BadArgument),
+            (Some("groups"), None) => Command::ShowGroups,
+            (Some("groups"), Some("none")) => Command::SetGroups(0),
+            (Some("groups"), Some(first)) => {
+                let mut mask = 0;
+                for word in core::iter::once(first).chain(&mut words) {
+                    let bit = word.parse().map(group::bit).map_err(|_| ParseError::BadArgument)?;
+                    if bit == 0 {
+                        return Err(ParseError::BadArgument);
+                    }
+                    mask |= bit;
+                }
+                Command::SetGroups(mask)
+            }
+            _ => return Err(ParseError::Unknown),
+        };
+        if words.next().is_some() {
+            return Err(ParseError::BadArgument);
+        }
+        Ok(command)
+    }
+}
+
+/// Parse and run one line, writing the reply to `out`
+///
+/// Returns the commands that need node-specific resources, unexecuted and
+/// without a reply; the caller carries them out (or refuses them).
+pub fn execute<W: Write>(line: &str, out: &mut W) -> Option<Command> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let _ = match Command::parse(line) {
+        Ok(Command::Help) => out.write_str(
+            "commands:\r\n\
+             \x20 help                 this text\r\n\
+             \x20 log [level]          show/set log level (error warn info debug trace)\r\n\
+             \x20 saver [minutes]      show/set display dim timeout (blank at 2x, 0 = off)\r\n\
+             \x20 units [c|f]          show/set display temperature unit\r\n\
+             \x20 delta [on|off]       show/switch send-on-delta (Node 1)\r\n\
+             \x20 delta <what> <value> set a threshold: temp C, hum %, gas %, beat s\r\n\
+             \x20 filter [mode] [n]    show/set smoothing: off, mean or median of 1-8 (Node 1)\r\n\
+             \x20 values [raw|scaled]  show/set how T/H are sent (Node 1)\r\n\
+             \x20 cal <sensor> <C> <%> trim a Node 1 sensor: temp offset, humidity slope\r\n\
+             \x20 soil [n dry|wet]     show soil probes / capture an endpoint (Node 1)\r\n\
+             \x20 display [on|off]     show/switch the panel\r\n\
+             \x20 display contrast <n> set panel contrast 0-255\r\n\
+             \x20 display night <a b>  blank from HH:MM a to b ('night off' to stop)\r\n\
+             \x20 display clock <t>    set the time of day as HH:MM\r\n\
+             \x20 n1 display ...       any of the above for Node 1's panel (Node 2)\r\n\
+             \x20 n1 interval <s>      set Node 1's TX interval, 5-3600 s (Node 2)\r\n\
+             \x20 n1 txpower <dBm>     cap Node 1's TX power, 0-22 (Node 2)\r\n\
+             \x20 n1 reboot            reset Node 1 (Node 2)\r\n\
+             \x20 n1 report            have Node 1 send a reading now (Node 2)\r\n\
+             \x20 n1 set <name> <v>... interval, txpower, delta and its thresholds at once (Node 2)\r\n\
+             \x20 n1 config           have Node 1 report its radio settings, power and thresholds (Node 2)\r\n\
+             \x20 all [ack] <n1 cmd>   broadcast an n1 command to every node (Node 2)\r\n\
+             \x20 group <g> [ack] <..> the same for the members of group 1-8 only (Node 2)\r\n\
+             \x20 groups [g...|none]   show/set the broadcast groups this node is in\r\n\
+             \x20 pair [on|off]        show/switch peer mode with the node 'via' names (Node 1)\r\n\
+             \x20 ping [address]       check the link: round trip, RSSI/SNR both ways\r\n\
+             \x20 linkstats            show link counters here and as the other end last reported them\r\n\
+             \x20 airtime [%|off|auto] show/set the duty-cycle limit (auto: from the band; never above the region)\r\n\
+             \x20 export               stream stored readings and crashes as binary (Node 1)\r\n\
+             \x20 stream [on|off|tap]  show/switch forwarding received frames as binary (Node 2)\r\n\
+             \x20 mqtt [on|off]        show/switch topic/payload lines per reading (Node 2)\r\n\
+             \x20 tunnel [level]       show/set log lines sent to Node 2: off error warn info (Node 1)\r\n\
+             \x20 relay [on|off]       show/switch passing other nodes' frames on (Node 2)\r\n\
+             \x20 via [address]        show/set where uplinks go: a relay, or 2 for the gateway\r\n\
+             \x20 route [r [r]|off]    show/pin the relays uplinks go through (Node 1)\r\n\
+             \x20 hop [MHz...|off]     show/set the channels to hop over, 2-8, on both nodes\r\n\
+             \x20 backoff [on|off]     show/switch interval jitter and busy-channel waits (Node 1)\r\n\
+             \x20 loopback [loss% [corrupt%]] show/set faults injected by the loopback build (Node 1)\r\n\
+             \x20 peers                list sensor nodes with loss and average RSSI (Node 2)\r\n\
+             \x20 adr [on|off]         show/switch stepping TX power and SF to the links (Node 2)\r\n\
+             \x20 scan [on|off]        show/switch looking for nodes on other settings when silent (Node 2)\r\n\
+             \x20 senders [mode]       show/set whose frames are heard: off, allow or deny (Node 2)\r\n\
+             \x20 senders add|del <n>  put node n (0-31) on the list or take it off\r\n",
+        ),
+        Ok(Command::ShowLog) => write!(out, "log level: {}\r\n", log::level().name()),
+        Ok(Command::SetLog(level)) => {
+            log::set_level(level);
+            defmt::info!("Log level set to {} from shell", level);
+            write!(out, "log level: {}\r\n", level.name())
+        }
+        Ok(Command::ShowSaver) => write!(out, "saver: {} min\r\n", ui::saver_minutes()),
+        Ok(Command::SetSaver(minutes)) => {
+            ui::set_saver_minutes(minutes);
+            write!(out, "saver: {} min\r\n", minutes)
+        }
+        Ok(Command::ShowUnits) => write!(out, "units: {}\r\n", unit_name()),
+        Ok(Command::SetUnits(fahrenheit)) => {
+            format::set_fahrenheit(fahrenheit);
+            write!(out, "units: {}\r\n", unit_name())
+        }
+        Ok(Command::ShowDelta) => write_delta(out),
+        Ok(Command::SetDeltaEnabled(on)) => {
+            delta::set_enabled(on);
+            write_delta(out)
+        }
+        Ok(Command::SetDelta(setting)) => {
+            setting.apply();
+            defmt::info!("Send-on-delta {} from shell", setting);
+            write_delta(out)
+        }
+        Ok(Command::ShowFilter) => write_filter(out),
+        Ok(Command::SetFilter(mode, window)) => {
+            filter::set(mode, window as usize);
+            defmt::info!("Filter {} over {} from shell", mode, window);
+            write_filter(out)
+        }
+        Ok(Command::SetAirtime(permille)) => match airtime::set_limit(permille) {
+            Ok(()) => {
+                defmt::info!("Duty-cycle limit {}/1000 from shell", airtime::limit_permille());
+                write_airtime_limit(out)
+            }
+            Err(e) => write!(out, "airtime: refused, {}\r\n", e.name()),
+        },
+        Ok(Command::ShowMqtt) => write!(out, "mqtt: {}\r\n", if mqtt::enabled() { "on" } else { "off" }),
+        Ok(Command::SetMqtt(on)) => {
+            mqtt::set_enabled(on);
+            defmt::info!("MQTT lines {} from shell", on);
+            write!(out, "mqtt: {}\r\n", if on { "on" } else { "off" })
+        }
+        Ok(Command::ShowTunnel) => write_tunnel(out),
+        Ok(Command::SetTunnel(level)) => {
+            tunnel::set_level(level);
+            defmt::info!("Log tunnel {} from shell", level);
+            write_tunnel(out)
+        }
+        Ok(Command::ShowRelay) => write!(out, "relay: {}\r\n", if relay::enabled() { "on" } else { "off" }),
+        Ok(Command::SetRelay(on)) => {
+            relay::set_enabled(on);
+            defmt::info!("Relay mode {} from shell", on);
+            write!(out, "relay: {}\r\n", if on { "on" } else { "off" })
+        }
+        Ok(Command::ShowHop) => write_hop(out),
+        Ok(Command::SetHop(channels)) => {
+            let count = channels.iter().take_while(|&&khz| khz != 0).count();
+            if count > 0 && !hopping::available() {
+                write!(out, "hop: refused, the clock stops in low-power sleep\r\n")
+            } else {
+                match hopping::set_channels(&channels[..count]) {
+                    Ok(()) => {
+                        defmt::info!("Hopping over {} channels from shell", count);
+                        write_hop(out)
+                    }
+                    Err(e) => write!(out, "hop: refused, {}\r\n", e.name()),
+                }
+            }
+        }
+        Ok(Command::ShowRoute) => write_route(out),
+        Ok(Command::SetRoute(route)) => {
+            relay::set_route(&route);
+            defmt::info!("Uplinks routed via {} from shell", relay::route().as_slice());
+            write_route(out)
+        }
+        Ok(Command::ShowVia) => write_via(out),
+        Ok(Command::SetVia(address)) => {
+            relay::set_upstream(address);
+            defmt::info!("Uplinks via {} from shell", address);
+            write_via(out)
+        }
+        Ok(Command::ShowBackoff) => write_backoff(out),
+        Ok(Command::SetBackoff(on)) => {
+            backoff::set_enabled(on);
+            defmt::info!("Backoff {} from shell", on);
+            write_backoff(out)
+        }
+        Ok(Command::ShowLoopback) => write_loopback(out),
+        Ok(Command::SetLoopback(loss_pct, corrupt_pct)) => {
+            loopback::set_faults(loss_pct, corrupt_pct);
+            defmt::info!("Loopback loss {}%, corruption {}% from shell", loss_pct, corrupt_pct);
+            write_loopback(out)
+        }
+        Ok(Command::SetAdr(on)) => {
+            adr::set_enabled(on);
+            defmt::info!("ADR {} from shell", on);
+            write!(out, "adr: {}\r\n", if on { "on" } else { "off" })
+        }
+        Ok(Command::SetScan(on)) => {
+            scan::set_enabled(on);
+            defmt::info!("Scan {} from shell", on);
+            write!(out, "scan: {}\r\n", if on { "on" } else { "off" })
+        }
+        Ok(Command::ShowPair) => write_pair(out),
+        Ok(Command::SetPair(on)) => {
+            pair::set_enabled(on);
+            defmt::info!("Peer mode {} from shell", on);
+            write_pair(out)
+        }
+        Ok(Command::ShowGroups) => write_groups(out),
+        Ok(Command::SetGroups(mask)) => {
+            group::set_membership(mask);
+            write_groups(out)
+        }
+        Ok(Command::ShowSenders) => write_senders(out),
+        Ok(Command::SetSendersMode(mode)) => {
+            senders::set_mode(mode);
+            defmt::info!("Sender list {} from shell", mode);
+            write_senders(out)
+        }
+        Ok(Command::ListSender(node, on)) => {
+            senders::set_listed(node, on);
+            write_senders(out)
+        }
+        Ok(Command::ShowValues) => write_values(out),
+        Ok(Command::SetRaw(on)) => {
+            raw::set_enabled(on);
+            defmt::info!("Raw T/H values {} from shell", on);
+            write_values(out)
+        }
+        Ok(
+            command @ (Command::Calibrate(..)
+            | Command::ShowSoil
+            | Command::SetSoil(..)
+            | Command::ShowDisplay
+            | Command::SetDisplay(..)
+            | Command::SendDisplay(..)
+            | Command::SendRemote(_)
+            | Command::SendAll { .. }
+            | Command::ShowAirtime
+            | Command::Export
+            | Command::ShowStream
+            | Command::SetStream(_)
+            | Command::SetTap
+            | Command::ShowPeers
+            | Command::ShowAdr
+            | Command::ShowScan
+            | Command::Ping(_)
+            | Command::ShowLinkStats),
+        ) => return Some(command),
+        Err(ParseError::Unknown) => write!(out, "unknown command '{}', try 'help'\r\n", line),
+        Err(ParseError::BadArgument) => write!(out, "bad argument in '{}'\r\n", line),
+    };
+    None
+}
+
+/// Reply line for a `cal` command
+pub fn write_trim<W: Write>(out: &mut W, sensor: SensorKind, trim: &Trim) -> core::fmt::Result {
+    write!(
+        out,
+        "cal {}: temp {:+.2}C hum x{:.1}%\r\n",
+        sensor.name(),
+        trim.temp_offset_c(),
+        trim.humidity_slope_pct()
+    )
+}
+
+/// Reply for `soil`: each probe's reading, moisture and endpoints
+pub fn write_soil<W: Write>(out: &mut W, mv: &[u16; soil::PROBES], calibration: &SoilCalibration) -> core::fmt::Result {
+    for (i, (&mv, ends)) in mv.iter().zip(&calibration.probes).enumerate() {
+        write!(out, "soil {}: {}mV", i + 1, mv)?;
+        match ends.percent(mv) {
+            Some(pct) => write!(out, " {}% (dry {}mV wet {}mV)\r\n", pct, ends.dry_mv, ends.wet_mv)?,
+            None => write!(out, " not calibrated (dry {}mV wet {}mV)\r\n", ends.dry_mv, ends.wet_mv)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reply for `display`: panel settings, night window and time of day
+pub fn write_display<W: Write>(out: &mut W, uptime_s: u32) -> core::fmt::Result {
+    write!(
+        out,
+        "display: {} contrast {}",
+        if panel::forced_off() { "off" } else { "on" },
+        panel::contrast()
+    )?;
+    match panel::night() {
+        Some((start, end)) => write!(out, " night {}-{}", Clock(start), Clock(end))?,
+        None => write!(out, " night off")?,
+    }
+    match panel::minute_of_day(uptime_s) {
+        Some(minute) => write!(out, " clock {}\r\n", Clock(minute)),
+        None => write!(out, " clock not set\r\n"),
+    }
+}
+
+/// Reply for `airtime`: the limit, then the last hour's use of it
+pub fn write_airtime<W: Write>(out: &mut W, used_us: u64, refused: u32) -> core::fmt::Result {
+    write_airtime_limit(out)?;
+    let used_ms = used_us / 1000;
+    write!(out, "airtime: {}.{:03}s in the last hour, {} frames refused\r\n", used_ms / 1000, used_ms % 1000, refused)
+}
+
+fn write_airtime_limit<W: Write>(out: &mut W) -> core::fmt::Result {
+    let permille = airtime::limit_permille();
+    let source = match region::duty_permille() {
+        Some(cap) if permille == cap => "region",
+        _ if airtime::limit_overridden() => "set",
+        _ => "band",
+    };
+    match permille {
+        0 => write!(out, "airtime limit: none ({})\r\n", source),
+        p => write!(out, "airtime limit: {}.{}% ({})\r\n", p / 10, p % 10, source),
+    }
+}
+
+/// Minutes of the day as `HH:MM`
+struct Clock(u16);
+
+impl core::fmt::Display for Clock {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+fn write_filter<W: Write>(out: &mut W) -> core::fmt::Result {
+    match filter::mode() {
+        Mode::Off => write!(out, "filter: off\r\n"),
+        mode => write!(out, "filter: {} of {}\r\n", mode.name(), filter::window()),
+    }
+}
+
+fn write_values<W: Write>(out: &mut W) -> core::fmt::Result {
+    write!(out, "values: {}\r\n", if raw::enabled() { "raw" } else { "scaled" })
+}
+
+/// Reply for `peers`: one line per node, as of `now_ms`
+pub fn write_peers<W: Write>(out: &mut W, peers: &Peers, now_ms: u32) -> core::fmt::Result {
+    if peers.is_empty() {
+        return write!(out, "peers: none heard yet\r\n");
+    }
+    for peer in peers.iter() {
+        write!(
+            out,
+            "N{} slot {}: #{} {} ago, {} heard {} missed ({}), RSSI {} avg {}, SNR {}\r\n",
+            peer.node,
+            peer.slot,
+            peer.last_seq,
+            Age(now_ms.wrapping_sub(peer.last_seen_ms) / 1000),
+            peer.received,
+            peer.missed,
+            Permille(peer.loss_permille()),
+            Dbm(peer.rssi),
+            Dbm(peer.rssi_avg_dbm()),
+            Db(peer.snr),
+        )?;
+    }
+    Ok(())
+}
+
+/// Reply for `pair`: the switch and the node it pairs with
+fn write_pair<W: Write>(out: &mut W) -> core::fmt::Result {
+    if pair::enabled() {
+        write!(out, "pair: on, with {}\r\n", relay::upstream())
+    } else {
+        out.write_str("pair: off\r\n")
+    }
+}
+
+/// Reply for `groups`
+fn write_groups<W: Write>(out: &mut W) -> core::fmt::Result {
+    out.write_str("groups:")?;
+    let mut none = true;
+    for g in group::groups() {
+        write!(out, " {}", g)?;
+        none = false;
+    }
+    out.write_str(if none { " none\r\n" } else { "\r\n" })
+}
+
+/// Reply for `senders`: the mode, the list and what it has kept out
+fn write_senders<W: Write>(out: &mut W) -> core::fmt::Result {
+    write!(out, "senders: {}, list:", senders::mode().name())?;
+    let mut empty = true;
+    for node in senders::listed() {
+        write!(out, " {}", node)?;
+        empty = false;
+    }
+    if empty {
+        out.write_str(" none")?;
+    }
+    write!(out, ", {} dropped\r\n", senders::dropped())
+}
+
+/// Reply for `adr`: the switch and spreading factor, then what is known
+/// of each node's link
+pub fn write_adr<W: Write>(out: &mut W, state: &Adr) -> core::fmt::Result {
+    let sf = state.sf();
+    write!(out, "adr: {}, SF{}\r\n", if adr::enabled() { "on" } else { "off" }, sf)?;
+    for link in state.links() {
+        write!(
+            out,
+            "N{}: {}dBm, SNR {:.1}dB avg, {:.1}dB margin\r\n",
+            link.node,
+            link.tx_power_dbm,
+            link.snr_avg,
+            link.snr_avg - adr::required_snr_db(sf)
+        )?;
+    }
+    Ok(())
+}
+
+/// Reply for `scan`: the switch, then what a scan is trying or found
+pub fn write_scan<W: Write>(out: &mut W, status: Status) -> core::fmt::Result {
+    write!(out, "scan: {}", if scan::enabled() { "on" } else { "off" })?;
+    match status {
+        Status::Listening => out.write_str(", on own settings\r\n"),
+        Status::Scanning { tune, index, of } => {
+            write!(out, ", trying net {} SF{} ({}/{})\r\n", tune.network_id, tune.sf, index, of)
+        }
+        Status::Found(found) => write!(
+            out,
+            ", heard N{} on net {} SF{}\r\n",
+            found.node, found.on.network_id, found.on.sf
+        ),
+    }
+}
+
+/// Reply for `ping`, and the line its answer, or the lack of one, adds
+pub fn write_ping<W: Write>(out: &mut W, outcome: Outcome) -> core::fmt::Result {
+    match outcome {
+        Outcome::Waiting { id, to } => write!(out, "ping #{} to {}\r\n", id, to),
+        Outcome::Echo(echo) => write!(
+            out,
+            "pong #{} from {}: {} ms, there {} {}, back {} {}\r\n",
+            echo.id,
+            echo.to,
+            echo.rtt_ms,
+            Dbm(echo.there.rssi),
+            Db(echo.there.snr),
+            Dbm(echo.back.rssi),
+            Db(echo.back.snr)
+        ),
+        Outcome::Lost { id, to } => write!(out, "ping #{} to {}: no answer\r\n", id, to),
+    }
+}
+
+fn write_backoff<W: Write>(out: &mut W) -> core::fmt::Result {
+    let stats = backoff::stats();
+    write!(
+        out,
+        "backoff: {}, {} intervals jittered, {} samples held for a busy channel, {} readings unanswered\r\n",
+        if backoff::enabled() { "on" } else { "off" },
+        stats.jittered,
+        stats.deferred,
+        stats.unanswered,
+    )
+}
+
+fn write_loopback<W: Write>(out: &mut W) -> core::fmt::Result {
+    if !loopback::enabled() {
+        return out.write_str("loopback: not in this build (feature loopback, Node 1)\r\n");
+    }
+    let (loss_pct, corrupt_pct) = loopback::faults();
+    let stats = loopback::stats();
+    write!(
+        out,
+        "loopback: loss {}%, corrupt {}%; {} frames, {} lost, {} corrupted; gateway took {} readings, {} duplicates, {} CRC failures\r\n",
+        loss_pct,
+        corrupt_pct,
+        stats.frames,
+        stats.lost,
+        stats.corrupted,
+        stats.readings,
+        stats.duplicates,
+        stats.crc_failures,
+    )
+}
+
+/// Reply for `linkstats`: this end's counters `uptime_s` into the run,
+/// then the last report of each node at the other end as of `now_ms`
+pub fn write_linkstats<W: Write>(
+    out: &mut W,
+    uptime_s: u32,
+    heard: impl IntoIterator<Item = Heard>,
+    now_ms: u32,
+) -> core::fmt::Result {
+    out.write_str("here: ")?;
+    write_link_counts(out, &linkstats::ours(uptime_s))?;
+    let mut any = false;
+    for heard in heard {
+        write!(out, "N{}, {} ago: ", heard.node, Age(heard.age_s(now_ms)))?;
+        write_link_counts(out, &heard.stats)?;
+        any = true;
+    }
+    if !any {
+        write!(out, "other end: nothing heard yet (every {} min)\r\n", linkstats::PERIOD_MIN)?;
+    }
+    Ok(())
+}
+
+fn write_link_counts<W: Write>(out: &mut W, stats: &LinkStats) -> core::fmt::Result {
+    write!(
+        out,
+        "sent {}, ACKed {}, retries {}, CRC failures {}, UART errors {}, up {}\r\n",
+        stats.frames_sent,
+        stats.acks_received,
+        stats.retries,
+        stats.crc_failures,
+        stats.uart_errors,
+        Age(stats.uptime_s),
+    )
+}
+
+/// Reply for `hop`: the channels in MHz
+fn write_hop<W: Write>(out: &mut W) -> core::fmt::Result {
+    let channels = hopping::channels();
+    if channels.is_empty() {
+        return out.write_str("hop: off\r\n");
+    }
+    out.write_str("hop:")?;
+    for khz in &channels {
+        write!(out, " {}.{:03}", khz / 1_000, khz % 1_000)?;
+    }
+    write!(
+        out,
+        " MHz, {} s each, the menu's band every {}\r\n",
+        hopping::DWELL_MS / 1_000,
+        hopping::HOME_EVERY
+    )
+}
+
+/// Reply for `route`: the pinned relays, then `via`'s address beyond them
+fn write_route<W: Write>(out: &mut W) -> core::fmt::Result {
+    let route = relay::route();
+    if route.is_empty() {
+        return out.write_str("route: off, uplinks go by via\r\n");
+    }
+    out.write_str("route:")?;
+    for address in &route {
+        write!(out, " {}", address)?;
+    }
+    write!(out, ", then relay {}'s via\r\n", route[route.len() - 1])
+}
+
+fn write_via<W: Write>(out: &mut W) -> core::fmt::Result {
+    match relay::upstream() {
+        relay::GATEWAY => write!(out, "via: {} (the gateway)\r\n", relay::GATEWAY),
+        address => write!(out, "via: relay {}\r\n", address),
+    }
+}
+
+fn write_tunnel<W: Write>(out: &mut W) -> core::fmt::Result {
+    write!(out, "tunnel: {}\r\n", tunnel::level().map_or("off", Level::name))
+}
+
+fn write_delta<W: Write>(out: &mut W) -> core::fmt::Result {
+    let t = delta::thresholds();
+    write!(
+        out,
+        "delta: {}, temp {:.1}C hum {:.1}% gas {}% beat {}s\r\n",
+        if t.enabled { "on" } else { "off" },
+        t.temp_c,
+        t.humidity_pct,
+        t.gas_percent,
+        t.heartbeat_s
+    )
+}
+
+fn unit_name() -> &'static str {
+    if format::fahrenheit() { "F" } else { "C" }
+}
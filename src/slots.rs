@@ -0,0 +1,49 @@
+//! Receiver-managed transmit slots for several sensor nodes
+//!
+//! Sensor nodes on one channel and the same interval drift into step, and
+//! from then on their readings collide every time. Node 2 keeps them apart:
+//! each node in its peer table holds a slot, and the ACK of each live
+//! reading carries a `SlotGrant` with it (see `protocol`). From the grant a
+//! node works out how far its readings land from its slot on Node 2's
+//! clock and moves its next sample to close the gap, in whole seconds as
+//! its tick counts them. One ACK is enough to line a node up; later ones
+//! only take out the drift between the two clocks.
+//!
+//! Slots go in the order nodes are first heard, so eight nodes need an
+//! interval of at least `MAX_PEERS * SLOT_S` = 16 s; with a shorter one the
+//! slots wrap round and some nodes share. Retries and replays go out when
+//! they must and may land in another node's slot.
+
+use crate::protocol::SlotGrant;
+
+/// Slot width: a reading, its ACK and some clock error at the slower
+/// spreading factors
+pub const SLOT_S: u8 = 2;
+
+/// Closer than this to its slot, a node leaves its timing alone; keeps the
+/// one-second steps from hunting round the slot's start
+const TOLERANCE_MS: i64 = 600;
+
+/// The grant for a node in `slot` whose reading arrived at `rx_ms`
+pub fn grant(slot: u8, rx_ms: u32) -> SlotGrant {
+    SlotGrant { slot, slot_s: SLOT_S, rx_ms }
+}
+
+/// Seconds to move the next sample by to reach `grant`'s slot, negative
+/// for sooner; 0 if it's close enough already
+///
+/// The next sample comes an interval after the acknowledged one, so it
+/// arrives at the same point of the interval on Node 2's clock.
+pub fn shift_s(grant: &SlotGrant, interval_s: u32) -> i32 {
+    let interval_ms = i64::from(interval_s.max(1)) * 1_000;
+    let slot_ms = i64::from(grant.slot) * i64::from(grant.slot_s) * 1_000;
+    let mut error_ms = (slot_ms - i64::from(grant.rx_ms)).rem_euclid(interval_ms);
+    // Whichever way is shorter
+    if error_ms > interval_ms / 2 {
+        error_ms -= interval_ms;
+    }
+    if error_ms.abs() < TOLERANCE_MS {
+        return 0;
+    }
+    ((error_ms + error_ms.signum() * 500) / 1_000) as i32
+}
@@ -0,0 +1,112 @@
+//! Capacitive soil-moisture probes on ADC1
+//!
+//! For garden monitoring, Node 1 reads up to `PROBES` capacitive probes
+//! (the common v1.2/v2.0 boards) on PA1 and PA4. Their output falls as the
+//! soil gets wetter, but where "dry" and "wet" land depends on the probe,
+//! its supply and the soil, so each probe is calibrated in place: in the
+//! shell, `soil 1 dry` with the probe in air and `soil 1 wet` with it in
+//! water store the current readings as its endpoints. Moisture is then a
+//! linear 0-100 % between them.
+//!
+//! Endpoints are saved in the config sector (see `config`). A probe without
+//! both endpoints is treated as not fitted and sent as `NOT_CALIBRATED`;
+//! with none calibrated the `tlv::SOIL_MOISTURE` record is left out.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::flash::FlashError;
+
+/// Probe inputs: PA1 (ADC1 IN1) and PA4 (ADC1 IN4)
+pub const PROBES: usize = 2;
+
+/// Record value for a probe that isn't calibrated
+pub const NOT_CALIBRATED: u8 = 0xFF;
+
+/// Which endpoint a `soil <n> <dry|wet>` command captures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Endpoint {
+    Dry,
+    Wet,
+}
+
+impl Endpoint {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dry" => Some(Endpoint::Dry),
+            "wet" => Some(Endpoint::Wet),
+            _ => None,
+        }
+    }
+}
+
+/// One probe's readings in air and in water, in ADC millivolts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, defmt::Format)]
+pub struct Endpoints {
+    pub dry_mv: u16,
+    pub wet_mv: u16,
+}
+
+impl Endpoints {
+    /// Both captured, and in the order a capacitive probe produces
+    pub fn is_set(&self) -> bool {
+        self.wet_mv != 0 && self.dry_mv > self.wet_mv
+    }
+
+    /// Moisture for a reading of `mv`, clamped to 0-100
+    pub fn percent(&self, mv: u16) -> Option<u8> {
+        if !self.is_set() {
+            return None;
+        }
+        let mv = mv.clamp(self.wet_mv, self.dry_mv);
+        let span = (self.dry_mv - self.wet_mv) as u32;
+        Some(((self.dry_mv - mv) as u32 * 100 / span) as u8)
+    }
+}
+
+/// Endpoints for every probe input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, defmt::Format)]
+pub struct SoilCalibration {
+    pub probes: [Endpoints; PROBES],
+}
+
+impl SoilCalibration {
+    /// Store `mv` as `probe`'s `endpoint`
+    pub fn set(&mut self, probe: usize, endpoint: Endpoint, mv: u16) {
+        let probe = &mut self.probes[probe];
+        match endpoint {
+            Endpoint::Dry => probe.dry_mv = mv,
+            Endpoint::Wet => probe.wet_mv = mv,
+        }
+    }
+
+    /// Moisture per probe for readings `mv`, `None` where not calibrated
+    pub fn percents(&self, mv: &[u16; PROBES]) -> [Option<u8>; PROBES] {
+        core::array::from_fn(|i| self.probes[i].percent(mv[i]))
+    }
+}
+
+/// The value of a `SOIL_MOISTURE` record: one percent per probe
+pub fn encode(percents: &[Option<u8>; PROBES]) -> [u8; PROBES] {
+    percents.map(|p| p.unwrap_or(NOT_CALIBRATED))
+}
+
+/// Moisture per probe from a `SOIL_MOISTURE` record
+pub fn decode(value: &[u8]) -> [Option<u8>; PROBES] {
+    core::array::from_fn(|i| value.get(i).copied().filter(|&p| p != NOT_CALIBRATED))
+}
+
+impl config::Record for SoilCalibration {
+    const MAGIC: u32 = 0x534F_494C; // "SOIL"
+    const VERSION: u8 = 1;
+}
+
+/// The saved endpoints, if any
+pub fn load() -> Option<SoilCalibration> {
+    config::load_record()
+}
+
+/// Save `calibration` as the newest endpoints
+pub fn save(calibration: &SoilCalibration) -> Result<(), FlashError> {
+    config::save_record(calibration)
+}
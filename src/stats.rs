@@ -0,0 +1,305 @@
+//! Receive-side link statistics from sequence numbers
+//!
+//! A raw "packets received" total says nothing about how the link is doing
+//! right now. `RxStats` keeps the recent arrivals, which gives a packet
+//! rate over the last minute, and whether each of the last `LOSS_WINDOW`
+//! sequence numbers it expected arrived or was skipped by a gap, which
+//! gives a rolling loss percentage that a long outage an hour ago no
+//! longer weighs on. Frames that fail CRC or
+//! parsing are counted per kind, and the most recent one is kept so the
+//! display can flag it. Sensor faults reported by the sender are kept the
+//! same way, and so is a last-gasp power failure report, which marks the
+//! silence that follows as a dead sender rather than a bad link.
+//!
+//! The running totals and the last sequence number can be carried over a
+//! reset as `RxCounters` (see `backup`); the window starts empty again.
+
+use heapless::HistoryBuffer;
+
+use crate::sensor::SensorFault;
+
+/// Arrivals kept for the packet rate (5+ minutes at 10 s per packet)
+pub const WINDOW: usize = 32;
+
+/// Expected sequence numbers the loss estimate covers
+pub const LOSS_WINDOW: u8 = 100;
+
+/// A gap larger than this is a sender restart, not lost packets
+const MAX_GAP: u16 = 1000;
+
+const RATE_WINDOW_MS: u32 = 60_000;
+
+/// How a sequence number relates to the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SeqEvent {
+    First,
+    InOrder,
+    /// This many sequence numbers were skipped
+    Gap(u16),
+    Duplicate,
+    /// Sequence went backwards or jumped too far: sender rebooted
+    Restart,
+}
+
+/// Why a received frame was thrown away
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RxError {
+    /// Payload CRC mismatch (RF corruption)
+    Crc,
+    /// Malformed `+RCV` line or frame
+    Format,
+    /// CRC fine but the body didn't deserialize
+    Decode,
+    /// CRC fine but a message type we don't know
+    UnknownType,
+}
+
+impl RxError {
+    const ALL: [RxError; 4] = [RxError::Crc, RxError::Format, RxError::Decode, RxError::UnknownType];
+
+    /// Short label for the display
+    pub fn label(self) -> &'static str {
+        match self {
+            RxError::Crc => "CRC FAIL",
+            RxError::Format => "BAD FRAME",
+            RxError::Decode => "DECODE ERR",
+            RxError::UnknownType => "UNKNOWN MSG",
+        }
+    }
+}
+
+/// The latest receive error and how many of that kind there have been
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct LastError {
+    pub kind: RxError,
+    pub count: u32,
+    pub at_ms: u32,
+}
+
+/// The latest sensor fault Node 1 reported, and how many in total
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct LastFault {
+    pub fault: SensorFault,
+    pub count: u32,
+    pub at_ms: u32,
+}
+
+/// Missed or not, for each of the last `LOSS_WINDOW` sequence numbers
+/// expected, the newest in bit 0
+#[derive(Debug, Clone, Copy)]
+struct LossWindow {
+    missed: u128,
+    len: u8,
+}
+
+impl LossWindow {
+    const MASK: u128 = (1 << LOSS_WINDOW) - 1;
+
+    const fn new() -> Self {
+        Self { missed: 0, len: 0 }
+    }
+
+    /// A packet arrived after `missed` skipped sequence numbers; a gap
+    /// longer than the window leaves only this packet in it as received
+    fn arrived(&mut self, missed: u16) {
+        for _ in 0..missed.min(u16::from(LOSS_WINDOW)) {
+            self.push(true);
+        }
+        self.push(false);
+    }
+
+    fn push(&mut self, missed: bool) {
+        self.missed = (self.missed << 1 | u128::from(missed)) & Self::MASK;
+        self.len = (self.len + 1).min(LOSS_WINDOW);
+    }
+
+    /// Missed over expected, in permille
+    fn loss_permille(&self) -> u16 {
+        match self.len {
+            0 => 0,
+            len => (self.missed.count_ones() * 1000 / u32::from(len)) as u16,
+        }
+    }
+}
+
+/// The part of `RxStats` that survives a reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct RxCounters {
+    pub total: u32,
+    pub lost: u32,
+    pub duplicates: u32,
+    pub crc_errors: u32,
+    pub last_seq: Option<u16>,
+}
+
+pub struct RxStats {
+    total: u32,
+    lost: u32,
+    duplicates: u32,
+    last_seq: Option<u16>,
+    /// When each recent packet arrived
+    window: HistoryBuffer<u32, WINDOW>,
+    loss: LossWindow,
+    errors: [u32; RxError::ALL.len()],
+    last_error: Option<LastError>,
+    faults: u32,
+    last_fault: Option<LastFault>,
+    power_fail_ms: Option<u32>,
+    backfilled: u32,
+}
+
+/// Point-in-time summary for display and logging
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct RxSnapshot {
+    pub total: u32,
+    pub lost: u32,
+    pub duplicates: u32,
+    /// Packets received in the last 60 s
+    pub per_minute: u16,
+    /// Missing / expected over the last `loss_expected` sequence numbers,
+    /// in permille
+    pub loss_permille: u16,
+    /// Sequence numbers the loss covers: `LOSS_WINDOW` once that many have
+    /// been expected
+    pub loss_expected: u8,
+    pub last_error: Option<LastError>,
+    pub last_fault: Option<LastFault>,
+    /// When Node 1 reported its supply failing, until its next packet
+    pub power_fail_ms: Option<u32>,
+}
+
+impl RxStats {
+    pub const fn new() -> Self {
+        Self {
+            total: 0,
+            lost: 0,
+            duplicates: 0,
+            last_seq: None,
+            window: HistoryBuffer::new(),
+            loss: LossWindow::new(),
+            errors: [0; RxError::ALL.len()],
+            last_error: None,
+            faults: 0,
+            last_fault: None,
+            power_fail_ms: None,
+            backfilled: 0,
+        }
+    }
+
+    /// Carry on from counters saved before a reset
+    pub fn restore(counters: RxCounters) -> Self {
+        let mut stats = Self::new();
+        stats.total = counters.total;
+        stats.lost = counters.lost;
+        stats.duplicates = counters.duplicates;
+        stats.errors[RxError::Crc as usize] = counters.crc_errors;
+        stats.last_seq = counters.last_seq;
+        stats
+    }
+
+    pub fn counters(&self) -> RxCounters {
+        RxCounters {
+            total: self.total,
+            lost: self.lost,
+            duplicates: self.duplicates,
+            crc_errors: self.errors(RxError::Crc),
+            last_seq: self.last_seq,
+        }
+    }
+
+    /// Account for a `SensorFaultPacket`; returns the total so far
+    pub fn record_fault(&mut self, fault: SensorFault, now_ms: u32) -> u32 {
+        self.faults += 1;
+        self.last_fault = Some(LastFault { fault, count: self.faults, at_ms: now_ms });
+        self.faults
+    }
+
+    /// Account for a `PowerFailPacket`
+    pub fn record_power_fail(&mut self, now_ms: u32) {
+        self.power_fail_ms = Some(now_ms);
+    }
+
+    /// Account for a `StoredReading`; returns the total so far
+    ///
+    /// Replayed readings stay out of the sequence tracking: their numbers
+    /// were counted as lost when they went missing.
+    pub fn record_backfill(&mut self) -> u32 {
+        self.backfilled += 1;
+        self.backfilled
+    }
+
+    /// Account for a rejected frame; returns the count for that kind
+    pub fn record_error(&mut self, kind: RxError, now_ms: u32) -> u32 {
+        let count = &mut self.errors[kind as usize];
+        *count += 1;
+        self.last_error = Some(LastError { kind, count: *count, at_ms: now_ms });
+        *count
+    }
+
+    /// Count of rejected frames of `kind` since boot
+    pub fn errors(&self, kind: RxError) -> u32 {
+        self.errors[kind as usize]
+    }
+
+    /// Rolling loss over the last `LOSS_WINDOW` sequence numbers expected,
+    /// in permille
+    pub fn loss_permille(&self) -> u16 {
+        self.loss.loss_permille()
+    }
+
+    /// Account for a valid packet with sequence number `seq`
+    pub fn record(&mut self, seq: u16, now_ms: u32) -> SeqEvent {
+        let event = match self.last_seq {
+            None => SeqEvent::First,
+            Some(last) => match seq.wrapping_sub(last) {
+                0 => SeqEvent::Duplicate,
+                1 => SeqEvent::InOrder,
+                d if d <= MAX_GAP => SeqEvent::Gap(d - 1),
+                _ => SeqEvent::Restart,
+            },
+        };
+
+        if event == SeqEvent::Duplicate {
+            self.duplicates += 1;
+            return event;
+        }
+
+        let missed_before = match event {
+            SeqEvent::Gap(n) => n,
+            _ => 0,
+        };
+        self.total += 1;
+        self.lost += missed_before as u32;
+        self.power_fail_ms = None;
+        self.last_seq = Some(seq);
+        self.window.write(now_ms);
+        self.loss.arrived(missed_before);
+        event
+    }
+
+    pub fn snapshot(&self, now_ms: u32) -> RxSnapshot {
+        let per_minute = self
+            .window
+            .iter()
+            .filter(|&&at_ms| now_ms.wrapping_sub(at_ms) < RATE_WINDOW_MS)
+            .count() as u16;
+
+        RxSnapshot {
+            total: self.total,
+            lost: self.lost,
+            duplicates: self.duplicates,
+            per_minute,
+            loss_permille: self.loss.loss_permille(),
+            loss_expected: self.loss.len,
+            last_error: self.last_error,
+            last_fault: self.last_fault,
+            power_fail_ms: self.power_fail_ms,
+        }
+    }
+}
+
+impl Default for RxStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
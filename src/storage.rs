@@ -0,0 +1,369 @@
+//! littlefs on an external SPI NOR flash (feature `littlefs`)
+//!
+//! Node 1's settings live in flash sector 6 or the optional EEPROM, each
+//! with its own slot format and its own answer to a write cut short, and
+//! there's no room in either for a log or a firmware image. A W25Q32 or larger on Node 1's SPI2 gives them one
+//! place instead: a littlefs filesystem, which copies on write and commits
+//! a file only when it's closed, so a reset or power cut at any point
+//! leaves either the old contents or the new. It also wear-levels, so no
+//! layout has to count erase cycles again.
+//!
+//! ```text
+//! /cfg/config, /cfg/calibration, /cfg/soil, /cfg/lifetime
+//!     one record each: [version u8][CRC-16 u16 LE][postcard record ...]
+//! /log/events, /log/events.old
+//!     text lines, rotated at LOG_LIMIT bytes
+//! /fw/incoming, /fw/incoming.hdr, /fw/staged
+//!     a firmware image being received with its length and SHA-256
+//!     ([len u32 LE][digest 32 bytes]), and the last complete one
+//! ```
+//!
+//! When the chip answers at boot the records take precedence over the
+//! EEPROM and flash copies, which are still read once to carry settings
+//! over and then left alone. Without one everything stays as before.
+//! `config::Settings` and the boot counter are read before the clocks are
+//! up and stay in sector 6; readings and crash reports keep their flash
+//! sectors, which the crash handler and the sensor tick can write without
+//! a lock.
+//!
+//! Nothing boots from `/fw/staged` yet: there is no bootloader. Staging
+//! only gets an image onto the node intact, with its length and SHA-256
+//! checked before it's renamed into place. `wk3 flash-remote` fills it over
+//! the radio; the length and digest come in a signed `FirmwareBegin` (see
+//! command.rs), so a staged image is one Node 2 was given to send.
+//!
+//! Only the first 4 MB are used, whatever the part. Programming a page
+//! takes under a millisecond, but erasing a 4 KB block up to 400 ms, all
+//! busy-waited; the store is only used from priority 1 tasks, which the
+//! sensor tick and the watchdog check-in preempt.
+//!
+//! Wiring (Node 1, SPI2):
+//!
+//! | Signal | Pin  |
+//! |--------|------|
+//! | SCK    | PB13 |
+//! | MISO   | PB14 |
+//! | MOSI   | PB15 |
+//! | CS     | PB12 |
+
+use core::fmt::Write as _;
+
+use crc::{Crc, CRC_16_IBM_3740};
+use embedded_hal::spi::{Operation, SpiDevice};
+use heapless::String;
+use littlefs2::consts;
+use littlefs2::driver::Storage;
+use littlefs2::fs::{Allocation, Filesystem};
+use littlefs2::io::{self, Read as _, Seek as _, SeekFrom, Write as _};
+use littlefs2::path;
+use littlefs2::path::Path;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::eeprom::Kind;
+use crate::protocol::FIRMWARE_DIGEST_LEN;
+use crate::time;
+
+// W25Qxx commands (Winbond W25Q32JV datasheet, section 8)
+const CMD_READ: u8 = 0x03;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_JEDEC_ID: u8 = 0x9F;
+const STATUS_BUSY: u8 = 1 << 0;
+/// JEDEC capacity code of a 32 Mbit part; larger parts count up from it
+const CAPACITY_4MB: u8 = 0x16;
+
+const PAGE_SIZE: usize = 256;
+const SECTOR_SIZE: usize = 4096;
+const SECTOR_COUNT: usize = 1024;
+/// Worst-case page program and 4 KB erase times, with margin
+const PROGRAM_TIMEOUT_MS: u32 = 5;
+const ERASE_TIMEOUT_MS: u32 = 500;
+
+/// Bumped whenever a record's fields change
+const LAYOUT_VERSION: u8 = 1;
+const RECORD_HEADER: usize = 3;
+const RECORD_MAX: usize = 64;
+/// The event log is rotated once it reaches this size
+const LOG_LIMIT: usize = 16 * 1024;
+
+const EVENTS: &Path = path!("/log/events");
+const EVENTS_OLD: &Path = path!("/log/events.old");
+const FW_INCOMING: &Path = path!("/fw/incoming");
+const FW_INCOMING_HEADER: &Path = path!("/fw/incoming.hdr");
+const FW_STAGED: &Path = path!("/fw/staged");
+
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum StoreError {
+    /// The chip didn't answer, or a write or erase didn't finish in time
+    Io,
+    /// littlefs found damaged metadata
+    Corrupt,
+    /// No free blocks left
+    Full,
+    /// Any other littlefs error, such as a missing file
+    Fs,
+    /// The record doesn't fit in `RECORD_MAX` (a firmware bug)
+    TooLarge,
+    /// A staged image's length or digest isn't what the sender announced
+    Mismatch,
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        match e {
+            io::Error::Io => StoreError::Io,
+            io::Error::Corruption => StoreError::Corrupt,
+            io::Error::NoSpace => StoreError::Full,
+            _ => StoreError::Fs,
+        }
+    }
+}
+
+/// A W25Qxx NOR flash as littlefs block storage
+pub struct W25q<SPI> {
+    spi: SPI,
+}
+
+impl<SPI: SpiDevice> W25q<SPI> {
+    /// `None` unless a 4 MB or larger part answers the JEDEC ID command
+    pub fn probe(spi: SPI) -> Option<Self> {
+        let mut flash = Self { spi };
+        let mut id = [0u8; 3];
+        flash.spi.transaction(&mut [Operation::Write(&[CMD_JEDEC_ID]), Operation::Read(&mut id)]).ok()?;
+        // An empty socket reads all zeros or all ones
+        let present = id[0] != 0x00 && id[0] != 0xFF && (CAPACITY_4MB..0x20).contains(&id[2]);
+        present.then_some(flash)
+    }
+
+    /// Set the write enable latch, send `header` and `data`, and wait
+    fn write_command(&mut self, header: &[u8], data: &[u8], timeout_ms: u32) -> io::Result<()> {
+        self.spi.write(&[CMD_WRITE_ENABLE]).map_err(|_| io::Error::Io)?;
+        self.spi.transaction(&mut [Operation::Write(header), Operation::Write(data)]).map_err(|_| io::Error::Io)?;
+        for _ in 0..timeout_ms {
+            let mut status = [0u8];
+            self.spi.transaction(&mut [Operation::Write(&[CMD_READ_STATUS]), Operation::Read(&mut status)])
+                .map_err(|_| io::Error::Io)?;
+            if status[0] & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+            time::busy_wait_ms(1);
+        }
+        Err(io::Error::Io)
+    }
+}
+
+/// The command byte and a 24-bit address
+fn header(cmd: u8, addr: usize) -> [u8; 4] {
+    let [_, a2, a1, a0] = (addr as u32).to_be_bytes();
+    [cmd, a2, a1, a0]
+}
+
+impl<SPI: SpiDevice> Storage for W25q<SPI> {
+    const READ_SIZE: usize = 16;
+    // littlefs only programs whole pages, so no write crosses a page boundary
+    const WRITE_SIZE: usize = PAGE_SIZE;
+    const BLOCK_SIZE: usize = SECTOR_SIZE;
+    const BLOCK_COUNT: usize = SECTOR_COUNT;
+    const BLOCK_CYCLES: isize = 500;
+    type CACHE_SIZE = consts::U256;
+    /// 512 blocks per lookahead scan
+    type LOOKAHEAD_SIZE = consts::U8;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> io::Result<usize> {
+        self.spi.transaction(&mut [Operation::Write(&header(CMD_READ, off)), Operation::Read(buf)])
+            .map_err(|_| io::Error::Io)?;
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> io::Result<usize> {
+        for (i, page) in data.chunks(PAGE_SIZE).enumerate() {
+            self.write_command(&header(CMD_PAGE_PROGRAM, off + i * PAGE_SIZE), page, PROGRAM_TIMEOUT_MS)?;
+        }
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> io::Result<usize> {
+        for addr in (off..off + len).step_by(SECTOR_SIZE) {
+            self.write_command(&header(CMD_SECTOR_ERASE, addr), &[], ERASE_TIMEOUT_MS)?;
+        }
+        Ok(len)
+    }
+}
+
+fn record_path(kind: Kind) -> &'static Path {
+    match kind {
+        Kind::Config => path!("/cfg/config"),
+        Kind::Calibration => path!("/cfg/calibration"),
+        Kind::Soil => path!("/cfg/soil"),
+        Kind::Lifetime => path!("/cfg/lifetime"),
+    }
+}
+
+pub struct Store<S: Storage + 'static> {
+    fs: Filesystem<'static, S>,
+}
+
+impl<S: Storage + 'static> Store<S> {
+    /// Mount the filesystem, formatting the chip if it holds none
+    ///
+    /// Both buffers live for good, so `init` takes them from
+    /// `cortex_m::singleton!`.
+    pub fn mount(alloc: &'static mut Allocation<S>, storage: &'static mut S) -> Result<Self, StoreError> {
+        if !Filesystem::is_mountable(storage) {
+            defmt::warn!("SPI flash holds no filesystem, formatting");
+            Filesystem::format(storage)?;
+        }
+        let fs = Filesystem::mount(alloc, storage)?;
+        for dir in [path!("/cfg"), path!("/log"), path!("/fw")] {
+            fs.create_dir_all(dir)?;
+        }
+        Ok(Self { fs })
+    }
+
+    /// Blocks still free, of `SECTOR_COUNT`
+    pub fn free_blocks(&self) -> usize {
+        self.fs.available_blocks().unwrap_or(0)
+    }
+
+    /// The saved record of `kind`, if there is an intact one of this layout
+    pub fn load<T: DeserializeOwned>(&mut self, kind: Kind) -> Option<T> {
+        let mut buf = [0u8; RECORD_MAX];
+        let len = self.fs.open_file_and_then(record_path(kind), |file| file.read(&mut buf)).ok()?;
+        if len < RECORD_HEADER || buf[0] != LAYOUT_VERSION {
+            return None;
+        }
+        let body = &buf[RECORD_HEADER..len];
+        let crc = u16::from_le_bytes([buf[1], buf[2]]);
+        if crc != CRC16.checksum(body) {
+            return None;
+        }
+        postcard::from_bytes(body).ok()
+    }
+
+    /// Replace the record of `kind`, unless it's unchanged
+    ///
+    /// Returns whether the file was written.
+    pub fn save<T: Serialize>(&mut self, kind: Kind, record: &T) -> Result<bool, StoreError> {
+        let mut buf = [0u8; RECORD_MAX];
+        let len = postcard::to_slice(record, &mut buf[RECORD_HEADER..])
+            .map_err(|_| StoreError::TooLarge)?
+            .len();
+        let crc = CRC16.checksum(&buf[RECORD_HEADER..RECORD_HEADER + len]);
+        buf[0] = LAYOUT_VERSION;
+        buf[1..RECORD_HEADER].copy_from_slice(&crc.to_le_bytes());
+        let bytes = &buf[..RECORD_HEADER + len];
+
+        let mut current = [0u8; RECORD_MAX];
+        let read = self.fs.open_file_and_then(record_path(kind), |file| file.read(&mut current));
+        if read.is_ok_and(|n| &current[..n] == bytes) {
+            return Ok(false);
+        }
+        self.fs.write(record_path(kind), bytes)?;
+        Ok(true)
+    }
+
+    /// Remove the record of `kind`, if there is one, for a factory reset
+    pub fn forget(&mut self, kind: Kind) -> Result<(), StoreError> {
+        match self.fs.remove(record_path(kind)) {
+            Ok(()) | Err(io::Error::NoSuchEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Append a line to the event log, stamped with `uptime_s`
+    pub fn log(&mut self, uptime_s: u32, event: &str) -> Result<(), StoreError> {
+        if self.fs.metadata(EVENTS).is_ok_and(|m| m.len() >= LOG_LIMIT) {
+            // littlefs replaces the old file in the same commit
+            self.fs.rename(EVENTS, EVENTS_OLD)?;
+        }
+        let mut line: String<128> = String::new();
+        let _ = write!(line, "{} {}", uptime_s, event);
+        let _ = line.push('\n');
+        self.fs.open_file_with_options_and_then(
+            |options| options.write(true).create(true).append(true),
+            EVENTS,
+            |file| file.write(line.as_bytes()),
+        )?;
+        Ok(())
+    }
+
+    /// Start receiving a firmware image, dropping any half-received one
+    pub fn stage_begin(&mut self) -> Result<(), StoreError> {
+        self.fs.write(FW_INCOMING, &[])?;
+        Ok(())
+    }
+
+    /// Start or resume receiving the image of `len` bytes with SHA-256
+    /// `digest`; the offset to carry on from
+    ///
+    /// The same image as the one half received resumes, across resets;
+    /// any other drops it and starts over.
+    pub fn stage_resume(&mut self, len: u32, digest: &[u8; FIRMWARE_DIGEST_LEN]) -> Result<u32, StoreError> {
+        let mut header = [0u8; 4 + FIRMWARE_DIGEST_LEN];
+        header[..4].copy_from_slice(&len.to_le_bytes());
+        header[4..].copy_from_slice(digest);
+        let mut saved = [0u8; 4 + FIRMWARE_DIGEST_LEN];
+        let same = self
+            .fs
+            .open_file_and_then(FW_INCOMING_HEADER, |file| file.read(&mut saved))
+            .is_ok_and(|n| n == saved.len() && saved == header);
+        if same {
+            if let Ok(meta) = self.fs.metadata(FW_INCOMING) {
+                return Ok((meta.len() as u32).min(len));
+            }
+        }
+        self.stage_begin()?;
+        self.fs.write(FW_INCOMING_HEADER, &header)?;
+        Ok(0)
+    }
+
+    /// Write part of the incoming image at `offset`
+    pub fn stage_write(&mut self, offset: u32, chunk: &[u8]) -> Result<(), StoreError> {
+        self.fs.open_file_with_options_and_then(
+            |options| options.write(true),
+            FW_INCOMING,
+            |file| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.write(chunk)
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Check the incoming image against the sender's length and SHA-256,
+    /// and if it matches make it the staged image
+    pub fn stage_commit(&mut self, len: u32, digest: &[u8; FIRMWARE_DIGEST_LEN]) -> Result<(), StoreError> {
+        let mut sha = Sha256::new();
+        let read = self.fs.open_file_and_then(FW_INCOMING, |file| {
+            let mut buf = [0u8; PAGE_SIZE];
+            let mut total = 0;
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    return Ok(total);
+                }
+                sha.update(&buf[..n]);
+                total += n;
+            }
+        })?;
+        // Either way this image is finished with; a resume starts over
+        let _ = self.fs.remove(FW_INCOMING_HEADER);
+        if read != len as usize || sha.finalize().as_slice() != digest {
+            defmt::warn!("Staged firmware rejected: {} bytes received of {}", read, len);
+            return Err(StoreError::Mismatch);
+        }
+        self.fs.rename(FW_INCOMING, FW_STAGED)?;
+        Ok(())
+    }
+
+    /// Length of the staged firmware image, if there is one
+    pub fn staged(&self) -> Option<usize> {
+        self.fs.metadata(FW_STAGED).ok().map(|m| m.len())
+    }
+}
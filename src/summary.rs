@@ -0,0 +1,98 @@
+//! Hourly min/max/mean of every sample Node 1 takes
+//!
+//! Live packets only carry the instant they were sent, and send-on-delta
+//! holds most samples back anyway, so a gateway that misses a few packets
+//! loses that stretch of history entirely. `Accumulator` folds every sample
+//! into per-quantity statistics and, once an hour, Node 1 sends them as one
+//! `SummaryPacket`. Losing a summary costs one hour's aggregate rather than
+//! the detail within it.
+//!
+//! Values are packed with the same scaling as `SensorDataPacket`.
+
+use crate::protocol::SummaryPacket;
+use crate::sensor::Measurement;
+
+/// Time each summary covers
+pub const PERIOD_S: u32 = 3_600;
+
+/// Running minimum, maximum and sum of one quantity
+#[derive(Debug, Clone, Copy, Default)]
+struct Stat {
+    min: f32,
+    max: f32,
+    sum: f32,
+    count: u32,
+}
+
+impl Stat {
+    fn add(&mut self, value: f32) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// `[min, max, mean]`, each multiplied by `scale`; `None` without samples
+    fn scaled(&self, scale: f32) -> Option<[f32; 3]> {
+        (self.count > 0).then(|| [self.min * scale, self.max * scale, self.sum / self.count as f32 * scale])
+    }
+}
+
+/// Statistics for the summary period in progress
+#[derive(Debug, Clone, Copy)]
+pub struct Accumulator {
+    temperature: Stat,
+    humidity: Stat,
+    gas: Stat,
+    co2: Stat,
+    samples: u32,
+    started_ms: u32,
+}
+
+impl Accumulator {
+    pub const fn new(now_ms: u32) -> Self {
+        const EMPTY: Stat = Stat { min: 0.0, max: 0.0, sum: 0.0, count: 0 };
+        Self { temperature: EMPTY, humidity: EMPTY, gas: EMPTY, co2: EMPTY, samples: 0, started_ms: now_ms }
+    }
+
+    /// Fold in one sample, whether or not it was transmitted
+    pub fn add(&mut self, m: &Measurement) {
+        if let Some(t) = m.temperature_c {
+            self.temperature.add(t);
+        }
+        if let Some(h) = m.humidity_pct {
+            self.humidity.add(h);
+        }
+        if let Some(g) = m.gas_ohms {
+            self.gas.add(g as f32);
+        }
+        if let Some(c) = m.co2_ppm {
+            self.co2.add(c as f32);
+        }
+        self.samples += 1;
+    }
+
+    /// The period's summary, starting a new period at `now_ms`
+    ///
+    /// `None` (and nothing reset) if no sample with temperature and
+    /// humidity was taken, e.g. because the SHT31 failed all hour.
+    pub fn take(&mut self, now_ms: u32) -> Option<SummaryPacket> {
+        let temperature = self.temperature.scaled(10.0)?;
+        let humidity = self.humidity.scaled(100.0)?;
+        let packet = SummaryPacket {
+            period_s: (now_ms.wrapping_sub(self.started_ms) / 1_000).min(u16::MAX as u32) as u16,
+            samples: self.samples.min(u16::MAX as u32) as u16,
+            temperature: temperature.map(|v| v as i16),
+            humidity: humidity.map(|v| v as u16),
+            gas: self.gas.scaled(1.0).map_or([0; 3], |g| g.map(|v| v as u32)),
+            co2: self.co2.scaled(1.0).map(|c| c.map(|v| v as u16)),
+        };
+        *self = Self::new(now_ms);
+        Some(packet)
+    }
+}
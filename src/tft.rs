@@ -0,0 +1,165 @@
+//! ST7789 colour TFT behind the monochrome `Panel` interface
+//!
+//! The pages in `ui` are written for a `BinaryColor` target. Rather than
+//! duplicating them for colour, `Tft` maps `BinaryColor::On` to a pen colour
+//! chosen with `Canvas::set_accent` and `Off` to the background, so the same
+//! drawing code renders on either panel and the TFT just adds colour where
+//! the pages ask for it (RSSI bars, the error banner).
+//!
+//! 240x240 RGB565 would need 112K of frame buffer, so drawing goes straight
+//! to the controller over SPI and `flush` has nothing to do.
+//!
+//! Wiring (Node 2, SPI2):
+//!
+//! | Signal | Pin  |
+//! |--------|------|
+//! | SCK    | PB13 |
+//! | MOSI   | PB15 |
+//! | CS     | PB12 |
+//! | DC     | PB14 |
+//! | RST    | PB1  |
+//! | BL     | PB2  |
+
+use display_interface::WriteOnlyDataCommand;
+use embedded_graphics::{
+    pixelcolor::{BinaryColor, Rgb565},
+    prelude::*,
+    primitives::Rectangle,
+};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use mipidsi::{models::ST7789, options::ColorInversion, Builder, Display};
+
+use crate::display::{self, Accent, Canvas, Panel, PanelError};
+
+const BACKGROUND: Rgb565 = Rgb565::BLACK;
+
+fn pen(accent: Accent) -> Rgb565 {
+    match accent {
+        Accent::Normal => Rgb565::WHITE,
+        Accent::Good => Rgb565::GREEN,
+        Accent::Fair => Rgb565::YELLOW,
+        Accent::Poor => Rgb565::RED,
+    }
+}
+
+pub struct Tft<DI, RST, BL>
+where
+    DI: WriteOnlyDataCommand,
+    RST: OutputPin,
+{
+    display: Display<DI, ST7789, RST>,
+    backlight: BL,
+    pen: Rgb565,
+}
+
+/// Reset and initialise the panel, then switch the backlight on
+pub fn new<DI, RST, BL>(
+    interface: DI,
+    reset: RST,
+    mut backlight: BL,
+    delay: &mut impl DelayNs,
+) -> Result<Tft<DI, RST, BL>, PanelError>
+where
+    DI: WriteOnlyDataCommand,
+    RST: OutputPin,
+    BL: OutputPin,
+{
+    let display = Builder::new(ST7789, interface)
+        .display_size(display::WIDTH as u16, display::HEIGHT as u16)
+        .invert_colors(ColorInversion::Inverted)
+        .reset_pin(reset)
+        .init(delay)
+        .map_err(|_| PanelError)?;
+    backlight.set_high().map_err(|_| PanelError)?;
+    Ok(Tft { display, backlight, pen: pen(Accent::Normal) })
+}
+
+impl<DI, RST, BL> Tft<DI, RST, BL>
+where
+    DI: WriteOnlyDataCommand,
+    RST: OutputPin,
+{
+    fn colour(&self, color: BinaryColor) -> Rgb565 {
+        match color {
+            BinaryColor::On => self.pen,
+            BinaryColor::Off => BACKGROUND,
+        }
+    }
+}
+
+impl<DI, RST, BL> OriginDimensions for Tft<DI, RST, BL>
+where
+    DI: WriteOnlyDataCommand,
+    RST: OutputPin,
+{
+    fn size(&self) -> Size {
+        Size::new(display::WIDTH, display::HEIGHT)
+    }
+}
+
+impl<DI, RST, BL> DrawTarget for Tft<DI, RST, BL>
+where
+    DI: WriteOnlyDataCommand,
+    RST: OutputPin,
+{
+    type Color = BinaryColor;
+    type Error = PanelError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let (on, off) = (self.pen, BACKGROUND);
+        let pixels = pixels.into_iter().map(|Pixel(point, color)| {
+            Pixel(point, if color.is_on() { on } else { off })
+        });
+        self.display.draw_iter(pixels).map_err(|_| PanelError)
+    }
+
+    // Rectangles (clears, banners, bars) go out as one window write
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let color = self.colour(color);
+        self.display.fill_solid(area, color).map_err(|_| PanelError)
+    }
+}
+
+impl<DI, RST, BL> Panel for Tft<DI, RST, BL>
+where
+    DI: WriteOnlyDataCommand,
+    RST: OutputPin,
+    BL: OutputPin,
+{
+    fn clear_frame(&mut self) {
+        let _ = self.display.clear(BACKGROUND);
+    }
+
+    fn flush(&mut self) -> Result<(), PanelError> {
+        // Unbuffered: every draw call has already reached the controller
+        Ok(())
+    }
+
+    fn set_dimmed(&mut self, _dimmed: bool) -> Result<(), PanelError> {
+        // The backlight is on/off only; LCDs don't burn in like OLEDs anyway
+        Ok(())
+    }
+
+    fn set_contrast_level(&mut self, _level: u8) -> Result<(), PanelError> {
+        Ok(())
+    }
+
+    fn set_on(&mut self, on: bool) -> Result<(), PanelError> {
+        let result = if on { self.backlight.set_high() } else { self.backlight.set_low() };
+        result.map_err(|_| PanelError)
+    }
+}
+
+impl<DI, RST, BL> Canvas for Tft<DI, RST, BL>
+where
+    DI: WriteOnlyDataCommand,
+    RST: OutputPin,
+{
+    fn set_accent(&mut self, accent: Accent) {
+        self.pen = pen(accent);
+    }
+}
@@ -0,0 +1,97 @@
+//! Monotonic time base shared by both nodes
+//!
+//! The RTIC monotonic is SysTick ticking at 1 kHz, so one tick is one
+//! millisecond since boot. SysTick keeps running in Sleep mode (it is what
+//! wakes the core from WFI), unlike the DWT cycle counter which stops while
+//! the core clock is gated. DWT is still enabled for short cycle-accurate
+//! measurements and busy waits that never sleep.
+//!
+//! Application code works in milliseconds since boot as a `u32`
+//! (wraps after ~49 days), so comparisons must use the wrapping helpers
+//! below rather than plain `<`/`>`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::peripheral::{DCB, DWT};
+use embedded_hal::delay::DelayNs;
+use systick_monotonic::Systick;
+
+/// Core clock configured in `init` on both nodes (HSI -> PLL)
+pub const SYSCLK_HZ: u32 = 84_000_000;
+
+/// Core clock right now; lower than `SYSCLK_HZ` under `power::PowerProfile::Economy`
+static CORE_HZ: AtomicU32 = AtomicU32::new(SYSCLK_HZ);
+
+/// Current core clock, for converting DWT cycles to time
+#[inline]
+pub fn core_hz() -> u32 {
+    CORE_HZ.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_core_hz(hz: u32) {
+    CORE_HZ.store(hz, Ordering::Relaxed);
+}
+
+/// RTIC monotonic type, one tick per millisecond
+pub type Mono = Systick<1_000>;
+
+/// Enable the DWT cycle counter used by `busy_wait_ms` and cycle metrics
+pub fn enable_cycle_counter(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+}
+
+/// Milliseconds elapsed from `earlier` to `now`, correct across wraparound
+#[inline]
+pub fn elapsed_ms(now: u32, earlier: u32) -> u32 {
+    now.wrapping_sub(earlier)
+}
+
+/// True once `now` has reached or passed `deadline` (wraparound safe)
+#[inline]
+pub fn deadline_passed(now: u32, deadline: u32) -> bool {
+    (now.wrapping_sub(deadline) as i32) >= 0
+}
+
+/// `DelayNs` on the DWT cycle counter, for driver init code that wants one
+///
+/// SysTick belongs to the monotonic, so this is what gets handed to drivers
+/// during `init`. Same requirement as `busy_wait_ms`.
+pub struct CycleDelay;
+
+impl DelayNs for CycleDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        let cycles = (ns as u64 * core_hz() as u64 / 1_000_000_000) as u32;
+        let start = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(start) < cycles {}
+    }
+}
+
+/// Busy-wait on the DWT cycle counter
+///
+/// Used during `init` (AT command pacing) where the monotonic is not yet
+/// handed to RTIC. Requires `enable_cycle_counter` to have run first.
+pub fn busy_wait_ms(ms: u32) {
+    let cycles_per_ms = core_hz() / 1_000;
+    for _ in 0..ms {
+        let start = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(start) < cycles_per_ms {}
+    }
+}
+
+/// Busy-poll `ready` for up to `ms` on the DWT cycle counter; whether it
+/// came true in time
+///
+/// Same requirement as `busy_wait_ms`.
+pub fn poll_ms(ms: u32, mut ready: impl FnMut() -> bool) -> bool {
+    let cycles_per_ms = core_hz() / 1_000;
+    for _ in 0..ms {
+        let start = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(start) < cycles_per_ms {
+            if ready() {
+                return true;
+            }
+        }
+    }
+    false
+}
@@ -0,0 +1,114 @@
+//! Node 1's log lines forwarded to Node 2 over LoRa (`tunnel`)
+//!
+//! A sender in the field has no probe attached, so its defmt output is
+//! lost. With `tunnel warn` (or `error`, `info`) the statements Node 1 logs
+//! through `remote!` are also queued here as compact text, and the
+//! `log_tunnel` task sends what has queued as an un-ACKed `LogBatch` every
+//! few seconds, after the readings and only while the radio is idle. Node 2
+//! logs each line and streams it to the host (`wk3 log`).
+//!
+//! Off by default: every batch costs airtime against the duty cycle. The
+//! queue is small; lines logged faster than they can be sent are counted
+//! as dropped rather than kept.
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as _};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use cortex_m::interrupt::{self, Mutex};
+use heapless::{Deque, String};
+
+use crate::log::Level;
+use crate::protocol::{LogBatch, LogLine, LOG_BATCH_LEN, LOG_TEXT_LEN};
+
+/// Lines held between batches
+pub const QUEUE_LEN: usize = 16;
+
+/// `OFF`, or the most verbose `Level` forwarded
+static LEVEL: AtomicU8 = AtomicU8::new(OFF);
+const OFF: u8 = u8::MAX;
+
+struct Queue {
+    lines: Deque<LogLine, QUEUE_LEN>,
+    dropped: u16,
+}
+
+static QUEUE: Mutex<RefCell<Queue>> = Mutex::new(RefCell::new(Queue { lines: Deque::new(), dropped: 0 }));
+
+/// The most verbose level forwarded, `None` while off
+pub fn level() -> Option<Level> {
+    Level::from_index(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Turn forwarding on at `level`, or off; lines already queued are kept
+pub fn set_level(level: Option<Level>) {
+    LEVEL.store(level.map_or(OFF, |l| l as u8), Ordering::Relaxed);
+}
+
+/// Whether lines at `level` are currently forwarded
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    let max = LEVEL.load(Ordering::Relaxed);
+    max != OFF && level as u8 <= max
+}
+
+/// Queue a line if forwarding is on for `level` (any priority)
+///
+/// Text beyond `LOG_TEXT_LEN` bytes is cut.
+pub fn push(level: Level, uptime_s: u32, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let mut text = Truncating(String::new());
+    let _ = text.write_fmt(args);
+    let line = LogLine { uptime_s, level: level as u8, text: text.0 };
+    interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+        if queue.lines.push_back(line).is_err() {
+            queue.dropped = queue.dropped.saturating_add(1);
+        }
+    });
+}
+
+/// The next batch, oldest lines first, left queued until `sent`; `None`
+/// if there is nothing to report
+pub fn next_batch() -> Option<LogBatch> {
+    interrupt::free(|cs| {
+        let queue = QUEUE.borrow(cs).borrow();
+        if queue.lines.is_empty() && queue.dropped == 0 {
+            return None;
+        }
+        let lines = queue.lines.iter().take(LOG_BATCH_LEN).cloned().collect();
+        Some(LogBatch { dropped: queue.dropped, lines })
+    })
+}
+
+/// `batch` went out: remove its lines and the drops it reported
+pub fn sent(batch: &LogBatch) {
+    interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+        for _ in 0..batch.lines.len() {
+            queue.lines.pop_front();
+        }
+        queue.dropped -= batch.dropped;
+    });
+}
+
+/// Lines still waiting, e.g. for another batch straight away
+pub fn pending() -> usize {
+    interrupt::free(|cs| QUEUE.borrow(cs).borrow().lines.len())
+}
+
+/// A `String` that keeps what fits rather than failing
+struct Truncating(String<LOG_TEXT_LEN>);
+
+impl fmt::Write for Truncating {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
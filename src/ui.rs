@@ -0,0 +1,874 @@
+//! Node 2 display pages
+//!
+//! The OLED fits five lines of text (see `display::ROWS`), not enough for
+//! readings, link quality and node status at once. The UI is therefore a
+//! set of pages, each drawn by its own function from a `Screen` snapshot,
+//! and the user button steps through them.
+//!
+//! Rendering is generic over `DrawTarget` so the pages don't depend on the
+//! concrete display driver; the caller clears and flushes. Positions come
+//! from the `display` geometry constants, so the same pages render on the
+//! 128x32 build, just in the smaller font. `draw` takes a `Canvas` so it can
+//! set an accent colour where one means something; the OLEDs ignore it.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use embedded_graphics::{
+    mono_font::{
+        iso_8859_1::FONT_10X20,
+        MonoTextStyle, MonoTextStyleBuilder,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+use heapless::{HistoryBuffer, String};
+use qrcodegen_no_heap::{QrCode, QrCodeEcc, Version};
+
+use crate::battery;
+use crate::display::{self, Accent, Canvas, FONT, ROWS};
+use crate::format::{Age, Db, Dbm, Humidity, Ohms, Permille, Temp};
+use crate::gas;
+use crate::iaq;
+use crate::log;
+use crate::peers::Peers;
+use crate::ping::Outcome as PingOutcome;
+use crate::reset::ResetCause;
+use crate::scan::Status as ScanStatus;
+use crate::stats::RxSnapshot;
+
+/// How long a receive error stays on screen
+const ERROR_BANNER_MS: u32 = 3_000;
+/// How long the NODES page shows each sensor node
+const NODES_CYCLE_MS: u32 = 4_000;
+
+/// One screenful of information
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Page {
+    Live,
+    Glance,
+    Air,
+    Qr,
+    Trends,
+    Link,
+    Remote,
+    Node,
+    Nodes,
+    Config,
+}
+
+impl Page {
+    const ALL: [Page; 10] = [
+        Page::Live,
+        Page::Glance,
+        Page::Air,
+        Page::Qr,
+        Page::Trends,
+        Page::Link,
+        Page::Remote,
+        Page::Node,
+        Page::Nodes,
+        Page::Config,
+    ];
+
+    /// The page after this one, wrapping around
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Page::Live => "LIVE",
+            Page::Glance => "GLANCE",
+            Page::Air => "AIR",
+            Page::Qr => "QR",
+            Page::Trends => "TRENDS",
+            Page::Link => "LINK",
+            Page::Remote => "REMOTE",
+            Page::Node => "NODE",
+            Page::Nodes => "NODES",
+            Page::Config => "CONFIG",
+        }
+    }
+}
+
+/// Latest decoded sensor packet
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    pub temperature: f32,
+    pub humidity: f32,
+    pub gas_resistance: u32,
+    /// Heater profile step the gas reading belongs to (see `gas`)
+    pub heater_step: u8,
+    /// Sender's IAQ estimate, `None` while it is burning in (see `iaq`)
+    pub iaq: Option<u16>,
+    /// Only from nodes with an SCD40
+    pub co2_ppm: Option<u16>,
+    /// Only from battery-powered nodes
+    pub battery_mv: Option<u16>,
+    pub packet_num: u16,
+    pub rssi: i16,
+    pub snr: i16,
+}
+
+/// Samples kept per sparkline: 2 px each across the 128 px display
+pub const TREND_LEN: usize = 64;
+
+/// Recent readings for the sparkline page, one sample per packet
+pub struct Trends {
+    temperature: HistoryBuffer<f32, TREND_LEN>,
+    rssi: HistoryBuffer<i16, TREND_LEN>,
+}
+
+impl Trends {
+    pub const fn new() -> Self {
+        Self { temperature: HistoryBuffer::new(), rssi: HistoryBuffer::new() }
+    }
+
+    pub fn push(&mut self, reading: &Reading) {
+        self.temperature.write(reading.temperature);
+        self.rssi.write(reading.rssi);
+    }
+}
+
+impl Default for Trends {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Everything any page may show, gathered by the refresh task
+#[derive(Clone, Copy)]
+pub struct Screen<'a> {
+    pub node_id: &'static str,
+    pub reading: Option<Reading>,
+    pub link_age_ms: Option<u32>,
+    pub rx: RxSnapshot,
+    /// Monotonic "now"; wraps after ~49 days, compare with `wrapping_sub`
+    pub uptime_ms: u32,
+    /// Seconds since boot, for display (doesn't wrap in practice)
+    pub uptime_s: u32,
+    pub reset_cause: ResetCause,
+    /// The last boot ended in a crash, not yet acknowledged (see `crash`)
+    pub crash_notice: bool,
+    pub network_id: u8,
+    pub lora_freq_mhz: u32,
+    pub lora_parameter: &'static str,
+    pub watchdog_timeout_ms: u32,
+    pub trends: &'a Trends,
+    pub peers: &'a Peers,
+    /// What a scan for nodes on other settings is doing (see `scan`)
+    pub scan: ScanStatus,
+    /// The last `ping`, while it's still shown (see `ping`)
+    pub ping: Option<PingOutcome>,
+}
+
+fn style() -> MonoTextStyle<'static, BinaryColor> {
+    MonoTextStyleBuilder::new()
+        .font(FONT)
+        .text_color(BinaryColor::On)
+        .build()
+}
+
+/// Draw `text` on row `row` (0-4)
+pub(crate) fn line<D: DrawTarget<Color = BinaryColor>>(d: &mut D, row: usize, text: &str) {
+    Text::new(text, Point::new(0, ROWS[row]), style()).draw(d).ok();
+}
+
+/// Page title on the first row, "n/N" right-aligned, sender battery between
+fn header<D: Canvas>(d: &mut D, page: Page, screen: &Screen) {
+    let mut buf: String<24> = String::new();
+    let _ = write!(buf, "{} {}", screen.node_id, page.title());
+    line(d, 0, &buf);
+
+    // Right-aligned short of the activity indicator in the corner
+    buf.clear();
+    let _ = write!(buf, "{}/{}", page as usize + 1, Page::ALL.len());
+    let right = ACTIVITY_ORIGIN.x - 2;
+    Text::with_alignment(&buf, Point::new(right, ROWS[0]), style(), Alignment::Right)
+        .draw(d)
+        .ok();
+
+    if let Some(mv) = screen.reading.and_then(|r| r.battery_mv) {
+        let page_width = (buf.len() as u32 * FONT.character_size.width) as i32;
+        draw_battery(d, right - page_width - 3, battery::percent(mv));
+    }
+}
+
+/// Battery outline filled in proportion to `percent`, with the figure to
+/// its left, ending at `right`
+fn draw_battery<D: Canvas>(d: &mut D, right: i32, percent: u8) {
+    let height = FONT.character_size.height.min(8) - 2;
+    let body = Size::new(height * 2, height);
+    let top = ROWS[0] - FONT.baseline as i32 + (FONT.character_size.height - height) as i32 / 2;
+    let origin = Point::new(right - body.width as i32 - 1, top);
+
+    if percent <= battery::LOW_PERCENT {
+        d.set_accent(Accent::Poor);
+    }
+    Rectangle::new(origin, body)
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(d)
+        .ok();
+    // Terminal nub on the right
+    Rectangle::new(origin + Point::new(body.width as i32, height as i32 / 4), Size::new(1, height / 2))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(d)
+        .ok();
+    let fill = (body.width - 2) * percent.min(100) as u32 / 100;
+    Rectangle::new(origin + Point::new(1, 1), Size::new(fill, height - 2))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(d)
+        .ok();
+
+    let mut buf: String<8> = String::new();
+    let _ = write!(buf, "{}%", percent);
+    Text::with_alignment(&buf, Point::new(origin.x - 2, ROWS[0]), style(), Alignment::Right)
+        .draw(d)
+        .ok();
+    d.set_accent(Accent::Normal);
+}
+
+const CENTRE_X: i32 = display::WIDTH as i32 / 2;
+
+/// Top-right corner cell used by `draw_activity`
+const ACTIVITY_ORIGIN: Point = Point::new(display::WIDTH as i32 - 7, 0);
+const ACTIVITY_SIZE: u32 = 7;
+
+/// What the corner indicator is currently showing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Activity {
+    Idle,
+    /// A frame was received: filled dot
+    Rx,
+    /// An ACK was sent: hollow dot
+    Tx,
+}
+
+/// Redraw only the corner indicator
+///
+/// The buffered drivers flush just the changed area, so pulsing this costs
+/// a few bytes of I2C rather than a full 1K frame.
+pub fn draw_activity<D: DrawTarget<Color = BinaryColor>>(d: &mut D, activity: Activity) {
+    Rectangle::new(ACTIVITY_ORIGIN, Size::new_equal(ACTIVITY_SIZE))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(d)
+        .ok();
+
+    let dot = Circle::new(ACTIVITY_ORIGIN + Point::new(1, 1), ACTIVITY_SIZE - 2);
+    let style = match activity {
+        Activity::Idle => return,
+        Activity::Rx => PrimitiveStyle::with_fill(BinaryColor::On),
+        Activity::Tx => PrimitiveStyle::with_stroke(BinaryColor::On, 1),
+    };
+    dot.into_styled(style).draw(d).ok();
+}
+
+/// Render `page`; the caller clears before and flushes after
+pub fn draw<D: Canvas>(d: &mut D, page: Page, screen: &Screen) {
+    // The glance and air pages are meant to be read from across the room
+    // and the QR code needs the full height: no header on those
+    if !matches!(page, Page::Glance | Page::Air | Page::Qr) {
+        header(d, page, screen);
+    }
+    match page {
+        Page::Live => draw_live(d, screen),
+        Page::Glance => draw_glance(d, screen),
+        Page::Air => draw_air(d, screen),
+        Page::Qr => draw_qr(d, screen),
+        Page::Trends => draw_trends(d, screen),
+        Page::Link => draw_link(d, screen),
+        Page::Remote => draw_remote(d, screen),
+        Page::Node => draw_node(d, screen),
+        Page::Nodes => draw_nodes(d, screen),
+        Page::Config => draw_config(d, screen),
+    }
+    crash_banner(d, screen);
+    scan_banner(d, screen);
+    fault_banner(d, screen);
+    power_banner(d, screen);
+    ping_banner(d, screen);
+    error_banner(d, screen);
+}
+
+/// Inverted bottom row after a crash reset, until the button is pressed;
+/// any other banner goes over it
+fn crash_banner<D: Canvas>(d: &mut D, screen: &Screen) {
+    if screen.crash_notice {
+        banner(d, "RECOVERED FROM CRASH");
+    }
+}
+
+/// Inverted bottom row while scanning other radio settings, and naming
+/// the ones a node was found on until one is heard on ours
+fn scan_banner<D: Canvas>(d: &mut D, screen: &Screen) {
+    let mut buf: String<24> = String::new();
+    let _ = match screen.scan {
+        ScanStatus::Listening => return,
+        ScanStatus::Scanning { tune, .. } => write!(buf, "SCAN NET {} SF{}", tune.network_id, tune.sf),
+        ScanStatus::Found(found) => write!(buf, "N{} ON NET {} SF{}", found.node, found.on.network_id, found.on.sf),
+    };
+    banner(d, &buf);
+}
+
+/// Inverted bottom row naming a sensor fault Node 1 reported, until the
+/// next good reading arrives
+fn fault_banner<D: Canvas>(d: &mut D, screen: &Screen) {
+    let Some(fault) = screen.rx.last_fault else { return };
+    let fault_age = screen.uptime_ms.wrapping_sub(fault.at_ms);
+    if screen.link_age_ms.is_some_and(|reading_age| reading_age <= fault_age) {
+        return;
+    }
+    let mut buf: String<24> = String::new();
+    let _ = write!(buf, "{} {}", fault.fault.sensor.name(), fault.fault.error.label());
+    banner(d, &buf);
+}
+
+/// Inverted bottom row while Node 1 is down after a power failure report
+fn power_banner<D: Canvas>(d: &mut D, screen: &Screen) {
+    if screen.rx.power_fail_ms.is_some() {
+        banner(d, "N1 POWER LOST");
+    }
+}
+
+/// Inverted bottom row with the last ping's round trip and RSSI/SNR there
+/// and back, for a while after it
+fn ping_banner<D: Canvas>(d: &mut D, screen: &Screen) {
+    let mut buf: String<24> = String::new();
+    let _ = match screen.ping {
+        None => return,
+        Some(PingOutcome::Waiting { id, .. }) => write!(buf, "PING #{} ...", id),
+        Some(PingOutcome::Echo(e)) => {
+            write!(buf, "{}ms {}/{} {}/{}", e.rtt_ms, e.there.rssi, e.there.snr, e.back.rssi, e.back.snr)
+        }
+        Some(PingOutcome::Lost { id, .. }) => write!(buf, "PING #{} NO ANSWER", id),
+    };
+    banner(d, &buf);
+}
+
+/// Inverted bottom row naming the latest CRC/parse failure, for a few seconds
+fn error_banner<D: Canvas>(d: &mut D, screen: &Screen) {
+    let Some(error) = screen.rx.last_error else { return };
+    if screen.uptime_ms.wrapping_sub(error.at_ms) >= ERROR_BANNER_MS {
+        return;
+    }
+    let mut buf: String<24> = String::new();
+    let _ = write!(buf, "{} x{}", error.kind.label(), error.count);
+    banner(d, &buf);
+}
+
+/// `text` centred in an inverted bar over the bottom row
+fn banner<D: Canvas>(d: &mut D, text: &str) {
+    let font_height = FONT.character_size.height;
+    let top = ROWS[4] - FONT.baseline as i32;
+    d.set_accent(Accent::Poor);
+    Rectangle::new(Point::new(0, top - 1), Size::new(display::WIDTH, font_height + 2))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(d)
+        .ok();
+
+    let inverted = MonoTextStyleBuilder::new()
+        .font(FONT)
+        .text_color(BinaryColor::Off)
+        .build();
+    Text::with_alignment(text, Point::new(CENTRE_X, ROWS[4]), inverted, Alignment::Center)
+        .draw(d)
+        .ok();
+    d.set_accent(Accent::Normal);
+}
+
+fn draw_live<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    status_line(d, 4, screen);
+    let Some(r) = screen.reading else {
+        line(d, 2, "Waiting...");
+        return;
+    };
+    let mut buf: String<32> = String::new();
+
+    let _ = write!(buf, "T:{} H:{}", Temp(r.temperature), Humidity(r.humidity));
+    line(d, 1, &buf);
+
+    // Gas resistance is only meaningful next to its heater set-point
+    buf.clear();
+    let _ = match gas::step(r.heater_step) {
+        Some(step) => write!(buf, "Gas:{}@{}° #{:04}", Ohms(r.gas_resistance), step.temp_c, r.packet_num),
+        None => write!(buf, "Gas:{}@S{} #{:04}", Ohms(r.gas_resistance), r.heater_step, r.packet_num),
+    };
+    line(d, 2, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "{} SNR:{}", Dbm(r.rssi), Db(r.snr));
+    line(d, 3, &buf);
+}
+
+/// "up 2d03h / last 12s": node uptime and the age of the newest packet
+///
+/// The reading above it is only as good as its age; a sender that died an
+/// hour ago otherwise looks exactly like a live one.
+fn status_line<D: DrawTarget<Color = BinaryColor>>(d: &mut D, row: usize, screen: &Screen) {
+    let mut buf: String<32> = String::new();
+    let _ = match screen.link_age_ms {
+        Some(age) => write!(buf, "up {} / last {}", Age(screen.uptime_s), Age(age / 1000)),
+        None => write!(buf, "up {} / last --", Age(screen.uptime_s)),
+    };
+    line(d, row, &buf);
+}
+
+/// Temperature and humidity only, in 10x20 digits centred on the screen
+///
+/// Two lines on a 64 px panel; a 32 px panel only has room for one.
+fn draw_glance<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    let big = MonoTextStyleBuilder::new()
+        .font(&FONT_10X20)
+        .text_color(BinaryColor::On)
+        .build();
+    let mut buf: String<16> = String::new();
+
+    if display::HEIGHT < 64 {
+        match screen.reading {
+            Some(r) => {
+                let _ = write!(buf, "{} {}", Temp(r.temperature), Humidity(r.humidity));
+            }
+            None => {
+                let _ = buf.push_str("--.-° --%");
+            }
+        }
+        Text::with_alignment(&buf, Point::new(CENTRE_X, 22), big, Alignment::Center).draw(d).ok();
+        return;
+    }
+
+    // Baselines 26 and 54 on the 64 px OLED
+    let (upper, lower) = (display::HEIGHT as i32 / 2 - 6, display::HEIGHT as i32 - 10);
+
+    match screen.reading {
+        Some(r) => {
+            let _ = write!(buf, "{}", Temp(r.temperature));
+            Text::with_alignment(&buf, Point::new(CENTRE_X, upper), big, Alignment::Center).draw(d).ok();
+            buf.clear();
+            let _ = write!(buf, "{}RH", Humidity(r.humidity));
+            Text::with_alignment(&buf, Point::new(CENTRE_X, lower), big, Alignment::Center).draw(d).ok();
+        }
+        None => {
+            Text::with_alignment("--.-°", Point::new(CENTRE_X, upper), big, Alignment::Center).draw(d).ok();
+            Text::with_alignment("--%RH", Point::new(CENTRE_X, lower), big, Alignment::Center).draw(d).ok();
+        }
+    }
+}
+
+/// IAQ index in large digits with its band name, coloured by band on the TFT
+fn draw_air<D: Canvas>(d: &mut D, screen: &Screen) {
+    let big = MonoTextStyleBuilder::new()
+        .font(&FONT_10X20)
+        .text_color(BinaryColor::On)
+        .build();
+    let mut buf: String<16> = String::new();
+    let index = screen.reading.and_then(|r| r.iaq);
+    let label = match (screen.reading, index) {
+        (_, Some(iaq)) => iaq::label(iaq),
+        (Some(_), None) => "warming up",
+        (None, None) => "no data",
+    };
+
+    if let Some(iaq) = index {
+        d.set_accent(iaq::accent(iaq));
+    }
+    if display::HEIGHT < 64 {
+        let _ = match index {
+            Some(iaq) => write!(buf, "{} {}", iaq, label),
+            None => write!(buf, "IAQ --"),
+        };
+        Text::with_alignment(&buf, Point::new(CENTRE_X, 22), big, Alignment::Center).draw(d).ok();
+    } else {
+        // Same baselines as the glance page
+        let (upper, lower) = (display::HEIGHT as i32 / 2 - 6, display::HEIGHT as i32 - 10);
+        let _ = match index {
+            Some(iaq) => write!(buf, "IAQ {}", iaq),
+            None => write!(buf, "IAQ --"),
+        };
+        Text::with_alignment(&buf, Point::new(CENTRE_X, upper), big, Alignment::Center).draw(d).ok();
+        let style = if index.is_some() { big } else { style() };
+        Text::with_alignment(label, Point::new(CENTRE_X, lower), style, Alignment::Center).draw(d).ok();
+    }
+    d.set_accent(Accent::Normal);
+
+    // CO2 in small print across the top, from nodes that measure it
+    if let Some(co2) = screen.reading.and_then(|r| r.co2_ppm) {
+        buf.clear();
+        let _ = write!(buf, "CO2 {}ppm", co2);
+        Text::with_alignment(&buf, Point::new(CENTRE_X, ROWS[0]), style(), Alignment::Center).draw(d).ok();
+    }
+}
+
+/// Largest QR version used: 29x29 modules, fits the 32 px OLED at 1 px per
+/// module and takes 53 bytes in byte mode at the lowest error correction
+const QR_VERSION: Version = Version::new(3);
+
+/// The reading as compact JSON, in fixed units (°C, %, ohms) whatever the
+/// display is set to, so a phone can paste it straight into a log
+fn reading_json(r: &Reading, out: &mut String<64>) {
+    let _ = write!(
+        out,
+        "{{\"t\":{:.1},\"h\":{:.0},\"g\":{},\"s\":{},\"n\":{},\"r\":{}}}",
+        r.temperature, r.humidity, r.gas_resistance, r.heater_step, r.packet_num, r.rssi
+    );
+}
+
+/// The latest reading as a QR code on the left, the values beside it
+fn draw_qr<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    let Some(r) = screen.reading else {
+        line(d, 1, "QR: no data yet");
+        return;
+    };
+
+    let mut json: String<64> = String::new();
+    reading_json(&r, &mut json);
+
+    let mut temp = [0u8; QR_VERSION.buffer_len()];
+    let mut out = [0u8; QR_VERSION.buffer_len()];
+    let Ok(qr) = QrCode::encode_text(
+        &json,
+        &mut temp,
+        &mut out,
+        QrCodeEcc::Low,
+        Version::MIN,
+        QR_VERSION,
+        None,
+        true,
+    ) else {
+        line(d, 1, "QR: too long");
+        return;
+    };
+
+    // One module of quiet zone each side (the spec asks for four, but phone
+    // scanners cope and there isn't the room). Dark modules are drawn unlit
+    // on a lit square, the way scanners expect them.
+    let modules = qr.size() + 2;
+    let scale = (display::HEIGHT as i32 / modules).max(1);
+    let side = (modules * scale) as u32;
+    let origin = Point::new(0, (display::HEIGHT as i32 - side as i32) / 2);
+    d.fill_solid(&Rectangle::new(origin, Size::new_equal(side)), BinaryColor::On).ok();
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if qr.get_module(x, y) {
+                let at = origin + Point::new((x + 1) * scale, (y + 1) * scale);
+                d.fill_solid(&Rectangle::new(at, Size::new_equal(scale as u32)), BinaryColor::Off).ok();
+            }
+        }
+    }
+
+    // Legend to the right, where there's room for it (not on the TFT)
+    let left = side as i32 + 4;
+    if display::WIDTH as i32 - left < 8 * FONT.character_size.width as i32 {
+        return;
+    }
+    let mut buf: String<16> = String::new();
+    for (row, y) in ROWS.into_iter().enumerate().take(4) {
+        buf.clear();
+        let _ = match row {
+            0 => write!(buf, "{}", Temp(r.temperature)),
+            1 => write!(buf, "{}RH", Humidity(r.humidity)),
+            2 => match gas::step(r.heater_step) {
+                Some(step) => write!(buf, "{}@{}°", Ohms(r.gas_resistance), step.temp_c),
+                None => write!(buf, "{}", Ohms(r.gas_resistance)),
+            },
+            _ => write!(buf, "#{:04}", r.packet_num),
+        };
+        Text::new(&buf, Point::new(left, y), style()).draw(d).ok();
+    }
+}
+
+fn draw_trends<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    let trends = screen.trends;
+    if trends.rssi.is_empty() {
+        line(d, 2, "No data yet");
+        return;
+    }
+
+    // Two plots, each under a label row with the current value: the first
+    // stops above the second label, the second runs to the bottom edge
+    let row_height = FONT.character_size.height as i32;
+    let mut buf: String<32> = String::new();
+    let temp_now = trends.temperature.recent().copied().unwrap_or(0.0);
+    let _ = write!(buf, "T {}", Temp(temp_now));
+    line(d, 1, &buf);
+    sparkline(d, trends.temperature.oldest_ordered().copied(), ROWS[1] + 3, ROWS[3] - row_height);
+
+    buf.clear();
+    let rssi_now = trends.rssi.recent().copied().unwrap_or(0);
+    let _ = write!(buf, "RSSI {}", Dbm(rssi_now));
+    line(d, 3, &buf);
+    sparkline(d, trends.rssi.oldest_ordered().map(|&r| r as f32), ROWS[3] + 3, display::HEIGHT as i32 - 1);
+}
+
+/// Plot `values` (oldest first) between rows `top` and `bottom`,
+/// auto-scaled to their own min/max
+fn sparkline<D, I>(d: &mut D, values: I, top: i32, bottom: i32)
+where
+    D: DrawTarget<Color = BinaryColor>,
+    I: Iterator<Item = f32> + Clone,
+{
+    let (min, max) = values
+        .clone()
+        .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let span = if max > min { max - min } else { 1.0 };
+    let height = (bottom - top) as f32;
+    let y = |v: f32| bottom - ((v - min) / span * height) as i32;
+
+    let stroke = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+    let mut prev: Option<Point> = None;
+    for (i, v) in values.enumerate() {
+        let point = Point::new(i as i32 * 2, y(v));
+        match prev {
+            Some(p) => {
+                Line::new(p, point).into_styled(stroke).draw(d).ok();
+            }
+            None => {
+                Pixel(point, BinaryColor::On).draw(d).ok();
+            }
+        }
+        prev = Some(point);
+    }
+}
+
+fn draw_link<D: Canvas>(d: &mut D, screen: &Screen) {
+    let mut buf: String<32> = String::new();
+
+    match screen.reading {
+        Some(r) => {
+            let _ = write!(buf, "{} SNR:{}", Dbm(r.rssi), Db(r.snr));
+            line(d, 1, &buf);
+            rssi_bars(d, 1, r.rssi);
+        }
+        None => line(d, 1, "No packets yet"),
+    }
+
+    buf.clear();
+    let _ = write!(buf, "RX:{} Lost:{}", screen.rx.total, screen.rx.lost);
+    line(d, 2, &buf);
+
+    buf.clear();
+    let rx = &screen.rx;
+    let _ = write!(buf, "{}/min Loss:{}", rx.per_minute, Permille(rx.loss_permille));
+    line(d, 3, &buf);
+
+    status_line(d, 4, screen);
+}
+
+/// The link as each sensor node last reported it, one at a time like the
+/// NODES page
+fn draw_remote<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    let reported = screen.peers.iter().filter(|peer| peer.link.is_some()).count();
+    let index = (screen.uptime_ms / NODES_CYCLE_MS) as usize % reported.max(1);
+    let Some(heard) = screen.peers.iter().filter_map(|peer| peer.link).nth(index) else {
+        line(d, 1, "No link stats yet");
+        return;
+    };
+    let stats = heard.stats;
+    let mut buf: String<32> = String::new();
+
+    let _ = write!(buf, "N{} TX:{} ACK:{}", heard.node, stats.frames_sent, stats.acks_received);
+    line(d, 1, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "Retry:{} CRC:{}", stats.retries, stats.crc_failures);
+    line(d, 2, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "UART:{}", stats.uart_errors);
+    line(d, 3, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "{} ago", Age(heard.age_s(screen.uptime_ms)));
+    line(d, 4, &buf);
+    buf.clear();
+    let _ = write!(buf, "{}/{}", index + 1, reported);
+    Text::with_alignment(&buf, Point::new(display::WIDTH as i32 - 1, ROWS[4]), style(), Alignment::Right)
+        .draw(d)
+        .ok();
+}
+
+/// Signal-strength bars right-aligned on `row`, coloured by quality
+fn rssi_bars<D: Canvas>(d: &mut D, row: usize, rssi: i16) {
+    let (bars, accent) = match rssi {
+        r if r >= -70 => (4, Accent::Good),
+        r if r >= -85 => (3, Accent::Good),
+        r if r >= -100 => (2, Accent::Fair),
+        _ => (1, Accent::Poor),
+    };
+
+    let bar_width = FONT.character_size.width / 2;
+    let max_height = FONT.character_size.height - 1;
+    let step = bar_width as i32 + 1;
+    let bottom = ROWS[row] + FONT.character_size.height as i32 - FONT.baseline as i32 - 1;
+    let left = display::WIDTH as i32 - 4 * step;
+
+    d.set_accent(accent);
+    for i in 0..4u32 {
+        let height = max_height * (i + 1) / 4;
+        let x = left + i as i32 * step;
+        let area = Rectangle::new(Point::new(x, bottom - height as i32 + 1), Size::new(bar_width, height));
+        let style = if i < bars {
+            PrimitiveStyle::with_fill(BinaryColor::On)
+        } else {
+            PrimitiveStyle::with_stroke(BinaryColor::On, 1)
+        };
+        area.into_styled(style).draw(d).ok();
+    }
+    d.set_accent(Accent::Normal);
+}
+
+fn draw_node<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    let mut buf: String<32> = String::new();
+
+    let secs = screen.uptime_s;
+    let _ = write!(buf, "Up:{}d{:02}h{:02}m{:02}s", secs / 86_400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    line(d, 1, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "Reset:{}", reset_name(screen.reset_cause));
+    line(d, 2, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "Log:{}", log::level().name());
+    line(d, 3, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "v{}", env!("CARGO_PKG_VERSION"));
+    line(d, 4, &buf);
+}
+
+/// One sensor node from the peer table at a time, moving on every
+/// `NODES_CYCLE_MS`
+fn draw_nodes<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    let peers = screen.peers;
+    let index = (screen.uptime_ms / NODES_CYCLE_MS) as usize % peers.len().max(1);
+    let Some(peer) = peers.get(index) else {
+        line(d, 1, "No nodes yet");
+        return;
+    };
+    let mut buf: String<32> = String::new();
+
+    let age = screen.uptime_ms.wrapping_sub(peer.last_seen_ms) / 1000;
+    let _ = write!(buf, "N{} #{} {} ago", peer.node, peer.last_seq, Age(age));
+    line(d, 1, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "{} {}", Temp(peer.temperature), Humidity(peer.humidity));
+    if let Some(iaq) = peer.iaq {
+        let _ = write!(buf, " IAQ:{}", iaq);
+    }
+    line(d, 2, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "{} ~{} SNR:{}", Dbm(peer.rssi), peer.rssi_avg_dbm(), Db(peer.snr));
+    line(d, 3, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "Lost:{}/{} {}", peer.missed, peer.received + peer.missed, Permille(peer.loss_permille()));
+    line(d, 4, &buf);
+    buf.clear();
+    let _ = write!(buf, "{}/{}", index + 1, peers.len());
+    Text::with_alignment(&buf, Point::new(display::WIDTH as i32 - 1, ROWS[4]), style(), Alignment::Right)
+        .draw(d)
+        .ok();
+}
+
+fn draw_config<D: DrawTarget<Color = BinaryColor>>(d: &mut D, screen: &Screen) {
+    let mut buf: String<32> = String::new();
+
+    let _ = write!(buf, "Net:{} {}MHz", screen.network_id, screen.lora_freq_mhz);
+    line(d, 1, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "Param:{}", screen.lora_parameter);
+    line(d, 2, &buf);
+
+    buf.clear();
+    let _ = write!(buf, "WDT:{}ms", screen.watchdog_timeout_ms);
+    line(d, 3, &buf);
+}
+
+fn reset_name(cause: ResetCause) -> &'static str {
+    match cause {
+        ResetCause::PowerOn => "power-on",
+        ResetCause::BrownOut => "brown-out",
+        ResetCause::Pin => "pin",
+        ResetCause::Software => "software",
+        ResetCause::IndependentWatchdog => "IWDG",
+        ResetCause::WindowWatchdog => "WWDG",
+        ResetCause::LowPower => "low-power",
+        ResetCause::Unknown => "unknown",
+    }
+}
+
+// --- Screensaver ---
+//
+// An OLED shows the same header and labels for months on end, which burns
+// them in. With no button press and no new packet for `saver_minutes`, the
+// display drops to minimum contrast; after twice that it is switched off.
+// Either kind of activity restores it.
+
+/// Minutes of inactivity before dimming (0 = never); set from the shell
+static SAVER_MINUTES: AtomicU16 = AtomicU16::new(10);
+
+pub fn saver_minutes() -> u16 {
+    SAVER_MINUTES.load(Ordering::Relaxed)
+}
+
+pub fn set_saver_minutes(minutes: u16) {
+    SAVER_MINUTES.store(minutes, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DisplayPower {
+    On,
+    Dimmed,
+    Blank,
+}
+
+/// Tracks activity and decides when the display should dim or blank
+pub struct Screensaver {
+    last_activity_ms: u32,
+    power: DisplayPower,
+}
+
+impl Screensaver {
+    pub const fn new() -> Self {
+        Self { last_activity_ms: 0, power: DisplayPower::On }
+    }
+
+    pub fn power(&self) -> DisplayPower {
+        self.power
+    }
+
+    /// Record activity; returns `true` if the display was dimmed or blank
+    pub fn wake(&mut self, now_ms: u32) -> bool {
+        self.last_activity_ms = now_ms;
+        self.power != DisplayPower::On
+    }
+
+    /// The power state the display should be in now, if it changed
+    pub fn update(&mut self, now_ms: u32) -> Option<DisplayPower> {
+        let dim_ms = (saver_minutes() as u32).saturating_mul(60_000);
+        let idle_ms = now_ms.wrapping_sub(self.last_activity_ms);
+        let wanted = if dim_ms == 0 || idle_ms < dim_ms {
+            DisplayPower::On
+        } else if idle_ms < dim_ms.saturating_mul(2) {
+            DisplayPower::Dimmed
+        } else {
+            DisplayPower::Blank
+        };
+        (wanted != self.power).then(|| {
+            self.power = wanted;
+            wanted
+        })
+    }
+}
+
+impl Default for Screensaver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
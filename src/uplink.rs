@@ -0,0 +1,52 @@
+//! Binary records from the host on Node 2's debug UART
+//!
+//! `wk3` sends Node 2 the same framed records `stream on` produces (see
+//! `protocol::stream`), on the port the shell uses: firmware upload frames
+//! from `wk3 flash-remote`, relayed to Node 1 unchanged, and with the
+//! `ingest` feature the captures `wk3 replay` plays back.
+//!
+//! A byte that starts or continues a record is taken here and not echoed;
+//! anything else goes on to the shell as usual. The sync bytes are never
+//! typed, so an interactive session is unaffected.
+
+use crate::protocol::stream::{Deframer, SYNC};
+
+/// Longest record accepted, as Node 2's `stream tap` sends them
+pub const RECORD_LEN: usize = 272;
+
+/// What became of a byte from the debug UART
+pub enum Byte<'a> {
+    /// Not part of a record: for the shell
+    Shell,
+    /// Taken as part of a record
+    Taken,
+    /// Completed a record whose CRC checks: `[header][body][CRC]`
+    Record(&'a [u8]),
+}
+
+pub struct Uplink {
+    deframer: Deframer<RECORD_LEN>,
+}
+
+impl Uplink {
+    pub const fn new() -> Self {
+        Self { deframer: Deframer::new() }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Byte<'_> {
+        // Only a sync byte leaves the idle state
+        if self.deframer.idle() && byte != SYNC[0] {
+            return Byte::Shell;
+        }
+        match self.deframer.push(byte) {
+            Some(record) => Byte::Record(record),
+            None => Byte::Taken,
+        }
+    }
+}
+
+impl Default for Uplink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
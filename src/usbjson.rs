@@ -0,0 +1,232 @@
+//! Decoded readings as JSON lines on a USB serial port (feature `usb-json`)
+//!
+//! Node 2's defmt output needs a probe and a decoder on the host. With the
+//! Nucleo's user USB connector wired up, Node 2 also enumerates as a
+//! CDC-ACM serial device and writes one JSON object per reading, so any
+//! script that can open `/dev/ttyACM0` and parse a line has the data:
+//!
+//! ```text
+//! {"seq":361,"source":"live","uptime_ms":3605123,"time":"14:02","age_s":null,"rssi_dbm":-71,"snr_db":9,
+//!  "loss_pct":2.0,"temp_c":21.40,"humidity_pct":48.20,"gas_ohm":51234,"iaq":87,"co2_ppm":null,"battery_mv":3912,
+//!  "probes_c":[null,null,null,null],"soil_pct":[41,null],"lat":null,"lon":null}
+//! ```
+//!
+//! (one line on the wire). Every key is always present, `null` where a
+//! value is unknown; `time` is the `panel` clock's time of day once set.
+//! `loss_pct` is the rolling loss over the last 100 sequence numbers
+//! expected (see `stats`). Backfilled readings from Node 1's flash (see
+//! `backlog`) have `source` `backfill`, their age, and no link figures.
+//!
+//! Lines are only written while a host holds the port open (DTR set). They
+//! queue in a `BUFFER_LEN` buffer that the USB interrupt drains as the host
+//! reads; a line that doesn't fit whole is dropped and counted, so the host
+//! never sees half a line. Host input is read and ignored.
+//!
+//! The device uses the shared pid.codes test ID 16c0:27dd, fine for a
+//! bench but not for shipping.
+
+use core::fmt::Write as _;
+
+use heapless::{Deque, String};
+use usb_device::bus::{UsbBus, UsbBusAllocator};
+use usb_device::device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+use crate::ds18b20::MAX_PROBES;
+use crate::nmea::Fix;
+use crate::panel;
+use crate::soil;
+
+const VID_PID: UsbVidPid = UsbVidPid(0x16c0, 0x27dd);
+/// Bytes waiting for the host; a few lines' worth
+const BUFFER_LEN: usize = 1024;
+const LINE_LEN: usize = 384;
+
+/// Where a reading came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Source {
+    /// With the loss over the last `stats::LOSS_WINDOW` sequence numbers,
+    /// this one included
+    Live { rssi: i16, snr: i16, loss_permille: u16 },
+    /// Replayed from Node 1's backlog; `None` if from before its last reset
+    Backfill { age_s: Option<u32> },
+}
+
+/// One JSON line
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    pub seq: u16,
+    pub source: Source,
+    pub temperature_c: f32,
+    pub humidity_pct: f32,
+    pub gas_ohms: u32,
+    pub iaq: Option<u16>,
+    pub co2_ppm: Option<u16>,
+    pub battery_mv: Option<u16>,
+    pub probes_c: [Option<f32>; MAX_PROBES],
+    pub soil_pct: [Option<u8>; soil::PROBES],
+    pub gnss: Option<Fix>,
+}
+
+pub struct Gateway<B: UsbBus + 'static> {
+    device: UsbDevice<'static, B>,
+    serial: SerialPort<'static, B>,
+    pending: Deque<u8, BUFFER_LEN>,
+    dropped: u32,
+}
+
+impl<B: UsbBus> Gateway<B> {
+    pub fn new(bus: &'static UsbBusAllocator<B>) -> Self {
+        // The class allocates its endpoints first, so it comes before the device
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .strings(&[StringDescriptors::default()
+                .manufacturer("wk3-binary-protocol")
+                .product("LoRa gateway (Node 2)")
+                .serial_number("N2")])
+            .expect("one language")
+            .device_class(USB_CLASS_CDC)
+            .build();
+        Self { device, serial, pending: Deque::new(), dropped: 0 }
+    }
+
+    /// Service the peripheral and send what the host has room for; call
+    /// from the USB interrupt
+    pub fn poll(&mut self) {
+        if self.device.poll(&mut [&mut self.serial]) {
+            let mut discard = [0u8; 64];
+            let _ = self.serial.read(&mut discard);
+        }
+        self.flush();
+    }
+
+    /// Queue `record`, received at `uptime_ms`, if a host is listening
+    pub fn send(&mut self, record: &Record, uptime_ms: u32) {
+        if !self.serial.dtr() {
+            return;
+        }
+        let mut line: String<LINE_LEN> = String::new();
+        format_line(&mut line, record, uptime_ms);
+        if self.pending.capacity() - self.pending.len() < line.len() {
+            self.dropped += 1;
+            defmt::warn!("USB host not reading, packet #{} dropped ({} so far)", record.seq, self.dropped);
+            return;
+        }
+        for &byte in line.as_bytes() {
+            let _ = self.pending.push_back(byte);
+        }
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        loop {
+            let (front, _) = self.pending.as_slices();
+            if front.is_empty() {
+                return;
+            }
+            let Ok(written) = self.serial.write(front) else { return };
+            for _ in 0..written {
+                self.pending.pop_front();
+            }
+        }
+    }
+}
+
+fn format_line(line: &mut String<LINE_LEN>, r: &Record, uptime_ms: u32) {
+    let _ = write!(line, "{{\"seq\":{},", r.seq);
+    let (source, age_s, link) = match r.source {
+        Source::Live { rssi, snr, loss_permille } => ("live", None, Some((rssi, snr, loss_permille))),
+        Source::Backfill { age_s } => ("backfill", age_s, None),
+    };
+    let _ = write!(line, "\"source\":\"{}\",\"uptime_ms\":{},\"time\":", source, uptime_ms);
+    match panel::minute_of_day(uptime_ms / 1000) {
+        Some(minute) => {
+            let _ = write!(line, "\"{:02}:{:02}\"", minute / 60, minute % 60);
+        }
+        None => {
+            let _ = line.push_str("null");
+        }
+    }
+    let _ = line.push_str(",\"age_s\":");
+    write_value(line, age_s);
+    let _ = line.push_str(",\"rssi_dbm\":");
+    write_value(line, link.map(|(rssi, _, _)| rssi));
+    let _ = line.push_str(",\"snr_db\":");
+    write_value(line, link.map(|(_, snr, _)| snr));
+    let _ = line.push_str(",\"loss_pct\":");
+    match link {
+        Some((_, _, loss_permille)) => {
+            let _ = write!(line, "{}.{}", loss_permille / 10, loss_permille % 10);
+        }
+        None => {
+            let _ = line.push_str("null");
+        }
+    }
+    let _ = line.push_str(",\"temp_c\":");
+    write_float(line, Some(r.temperature_c));
+    let _ = line.push_str(",\"humidity_pct\":");
+    write_float(line, Some(r.humidity_pct));
+    let _ = write!(line, ",\"gas_ohm\":{},\"iaq\":", r.gas_ohms);
+    write_value(line, r.iaq);
+    let _ = line.push_str(",\"co2_ppm\":");
+    write_value(line, r.co2_ppm);
+    let _ = line.push_str(",\"battery_mv\":");
+    write_value(line, r.battery_mv);
+    let _ = line.push_str(",\"probes_c\":[");
+    for (i, probe) in r.probes_c.iter().enumerate() {
+        if i > 0 {
+            let _ = line.push(',');
+        }
+        write_float(line, *probe);
+    }
+    let _ = line.push_str("],\"soil_pct\":[");
+    for (i, soil) in r.soil_pct.iter().enumerate() {
+        if i > 0 {
+            let _ = line.push(',');
+        }
+        write_value(line, *soil);
+    }
+    let _ = line.push_str("],\"lat\":");
+    let position = r.gnss.filter(Fix::has_position);
+    write_degrees(line, position.map(|fix| fix.lat_e7));
+    let _ = line.push_str(",\"lon\":");
+    write_degrees(line, position.map(|fix| fix.lon_e7));
+    let _ = line.push_str("}\n");
+}
+
+fn write_value<T: core::fmt::Display>(line: &mut String<LINE_LEN>, value: Option<T>) {
+    match value {
+        Some(value) => {
+            let _ = write!(line, "{}", value);
+        }
+        None => {
+            let _ = line.push_str("null");
+        }
+    }
+}
+
+/// JSON has no NaN or infinity, so those are `null` too
+fn write_float(line: &mut String<LINE_LEN>, value: Option<f32>) {
+    match value.filter(|v| v.is_finite()) {
+        Some(value) => {
+            let _ = write!(line, "{:.2}", value);
+        }
+        None => {
+            let _ = line.push_str("null");
+        }
+    }
+}
+
+/// 1e-7 degrees as a decimal, without going through f32's 7 digits
+fn write_degrees(line: &mut String<LINE_LEN>, value_e7: Option<i32>) {
+    match value_e7 {
+        Some(value) => {
+            let sign = if value < 0 { "-" } else { "" };
+            let abs = value.unsigned_abs();
+            let _ = write!(line, "{}{}.{:07}", sign, abs / 10_000_000, abs % 10_000_000);
+        }
+        None => {
+            let _ = line.push_str("null");
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Independent watchdog with a task check-in registry
+//!
+//! Feeding the IWDG from a single timer only proves that the timer runs.
+//! Instead every supervised task calls `check_in` when it completes a unit
+//! of work, and `Supervisor::poll` (called from the heartbeat) feeds the
+//! dog only once *all* required checkpoints have been seen since the last
+//! feed. A wedged I2C transfer or a stuck software task therefore stops the
+//! feeding and the IWDG resets the node.
+//!
+//! The IWDG gives no chance to run code at reset time, so each failed poll
+//! stores the missing checkpoints in a no-init RAM record. After an IWDG
+//! reset `take_missed` returns that record, naming the task that hung.
+
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use stm32f4xx_hal::{pac, prelude::*, watchdog::IndependentWatchdog};
+
+/// Supervised units of work, one bit each in the registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Checkpoint {
+    Heartbeat = 0,
+    Processing = 1,
+    RadioTx = 2,
+    Display = 3,
+    Sensor = 4,
+}
+
+impl Checkpoint {
+    const ALL: [Checkpoint; 5] = [
+        Checkpoint::Heartbeat,
+        Checkpoint::Processing,
+        Checkpoint::RadioTx,
+        Checkpoint::Display,
+        Checkpoint::Sensor,
+    ];
+
+    const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+static SEEN: AtomicU32 = AtomicU32::new(0);
+
+/// Record that `checkpoint` completed a unit of work (any priority)
+pub fn check_in(checkpoint: Checkpoint) {
+    SEEN.fetch_or(checkpoint.bit(), Ordering::Relaxed);
+}
+
+// --- Missed check-in record (survives the IWDG reset) ---
+
+const RECORD_MAGIC: u32 = 0x5744_4F47; // "WDOG"
+
+#[link_section = ".uninit.WATCHDOG_RECORD"]
+static mut RECORD: MaybeUninit<[u32; 2]> = MaybeUninit::uninit();
+
+fn store_record(missing: u32) {
+    unsafe {
+        let rec = addr_of_mut!(RECORD) as *mut u32;
+        rec.write_volatile(RECORD_MAGIC);
+        rec.add(1).write_volatile(missing);
+    }
+}
+
+fn clear_record() {
+    unsafe { (addr_of_mut!(RECORD) as *mut u32).write_volatile(0) };
+}
+
+/// Checkpoints that were missing when the watchdog last starved, if any
+///
+/// Only meaningful after an IWDG reset; the record is cleared either way.
+pub fn take_missed() -> Option<u32> {
+    let (magic, missing) = unsafe {
+        let rec = addr_of_mut!(RECORD) as *mut u32;
+        (rec.read_volatile(), rec.add(1).read_volatile())
+    };
+    clear_record();
+    (magic == RECORD_MAGIC).then_some(missing)
+}
+
+/// Log each checkpoint named in `mask`
+pub fn log_missed(mask: u32) {
+    for checkpoint in Checkpoint::ALL {
+        if mask & checkpoint.bit() != 0 {
+            defmt::error!("Watchdog: {} did not check in", checkpoint);
+        }
+    }
+}
+
+/// Owns the IWDG and feeds it when every required checkpoint is present
+pub struct Supervisor {
+    iwdg: IndependentWatchdog,
+    required: u32,
+}
+
+impl Supervisor {
+    /// Start the IWDG; it cannot be stopped again until reset
+    pub fn start(iwdg: pac::IWDG, timeout_ms: u32, required: &[Checkpoint]) -> Self {
+        // Halt the IWDG while the core is halted by the debugger
+        unsafe { (*pac::DBGMCU::ptr()).apb1_fz().modify(|_, w| w.dbg_iwdg_stop().set_bit()) };
+
+        let mut iwdg = IndependentWatchdog::new(iwdg);
+        iwdg.start(timeout_ms.millis());
+
+        SEEN.store(0, Ordering::Relaxed);
+        clear_record();
+
+        let required = required.iter().fold(0, |mask, c| mask | c.bit());
+        Self { iwdg, required }
+    }
+
+    /// Feed the dog if all required checkpoints were seen since the last feed
+    ///
+    /// Returns `false` (and updates the no-init record) while any are missing.
+    pub fn poll(&mut self) -> bool {
+        let missing = self.required & !SEEN.load(Ordering::Relaxed);
+        if missing == 0 {
+            self.iwdg.feed();
+            SEEN.fetch_and(!self.required, Ordering::Relaxed);
+            clear_record();
+            true
+        } else {
+            store_record(missing);
+            false
+        }
+    }
+}
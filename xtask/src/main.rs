@@ -0,0 +1,38 @@
+//! Repository tasks: `cargo xtask <task>`
+//!
+//! ```text
+//! cargo xtask schema               every frame layout as JSON, to stdout
+//! cargo xtask schema wk3.json      ... to a file
+//! ```
+//!
+//! The alias in `.cargo/config.toml` builds this for the PC; the workspace
+//! default target is the STM32.
+
+use std::process::ExitCode;
+use std::{env, fs, io};
+
+mod schema;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["schema"] => schema::json().map(|json| println!("{}", json)),
+        ["schema", file] => schema::json().and_then(|json| fs::write(file, json + "\n")),
+        _ => {
+            eprintln!("usage: cargo xtask schema [FILE]");
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("xtask: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// An `io::Error` for a failure from elsewhere
+fn other(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
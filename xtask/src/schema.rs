@@ -0,0 +1,148 @@
+//! `schema`: every frame layout as JSON, for decoders outside this repo
+//!
+//! Body layouts are traced from the protocol crate's serde derives with
+//! `serde-reflection`, the same derives postcard encodes with, so field
+//! order and types can't differ from what the firmware sends. `types` is
+//! serde-reflection's registry (`STRUCT`, `ENUM`, `U16`, `SEQ`, ...);
+//! `encoding` says how postcard lays each of those out. Heapless
+//! capacities aren't part of serde's model; PROTOCOL.md gives them.
+//!
+//! The `Display` body is the firmware's `panel::Setting`, outside the
+//! protocol crate, so it is listed without a traced layout.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serde_reflection::{Tracer, TracerConfig};
+
+use wk3_protocol::stream::{self, ExportBegin, ExportEnd, RadioFrame, RawLine};
+use wk3_protocol::{
+    tlv, AckPacket, BroadcastAck, BroadcastPacket, CalibrationCommand, CommandPacket, CommandResult, ConfigAck,
+    ConfigError, ConfigReport, CrashReport, EventPacket, FirmwareBegin, FirmwareChunk, FirmwareStatus, HealthPacket,
+    JoinAccept, JoinRequest, LinkStats, LogBatch, NodeInfo, Ping, Pong, PowerFailPacket, RemoteCommand, ReplayBatch,
+    ReplayRequest, SensorDataPacket, SensorFaultPacket, SlotGrant, StoredReading, SummaryPacket, CRC_LEN, FRAME_MAGIC,
+    FRAME_VERSION, HEADER_LEN, MAX_ROUTE, MESSAGE_TYPES, MSG_TYPE_ACK, MSG_TYPE_BROADCAST, MSG_TYPE_BROADCAST_ACK,
+    MSG_TYPE_CALIBRATE, MSG_TYPE_COMMAND, MSG_TYPE_COMMAND_RESULT, MSG_TYPE_CONFIG_ACK, MSG_TYPE_CONFIG_REPORT,
+    MSG_TYPE_CRASH_REPORT, MSG_TYPE_DISPLAY, MSG_TYPE_EVENT, MSG_TYPE_FW_BEGIN, MSG_TYPE_FW_CHUNK, MSG_TYPE_FW_STATUS,
+    MSG_TYPE_HEALTH, MSG_TYPE_JOIN_ACCEPT, MSG_TYPE_JOIN_REQUEST, MSG_TYPE_LINK_STATS, MSG_TYPE_LOG, MSG_TYPE_NACK,
+    MSG_TYPE_NODE_INFO, MSG_TYPE_PING, MSG_TYPE_PONG, MSG_TYPE_POWER_FAIL, MSG_TYPE_REPLAY_BATCH,
+    MSG_TYPE_REPLAY_REQUEST, MSG_TYPE_SENSOR_DATA, MSG_TYPE_SENSOR_FAULT, MSG_TYPE_STORED, MSG_TYPE_SUMMARY,
+    OFFSET_HOPS, OFFSET_LEN, OFFSET_MAGIC, OFFSET_NODE, OFFSET_TYPE, OFFSET_VERSION,
+};
+
+use crate::other;
+
+const ENCODING: &str = "postcard: BOOL, U8 and I8 one byte; U16, U32 and U64 LEB128 varints; I16, I32 and I64 \
+zigzag varints; F32 four bytes little-endian; OPTION a 0 or 1 byte, then the value if 1; SEQ, STR and BYTES a \
+varint length, then the items; ENUM a varint variant index, then its fields; STRUCT, TUPLE and TUPLEARRAY their \
+fields in order, with no names or lengths";
+
+/// The schema document, pretty-printed
+pub fn json() -> std::io::Result<String> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+
+    let mut messages = Vec::new();
+    for &(msg_type, name) in MESSAGE_TYPES {
+        let framed = !matches!(msg_type, MSG_TYPE_ACK | MSG_TYPE_NACK);
+        messages.push(json!({
+            "type": msg_type,
+            "name": name,
+            "framed": framed,
+            "body": message_body(&mut tracer, msg_type)?,
+        }));
+    }
+    let records = vec![
+        json!({ "type": stream::EXPORT_BEGIN, "name": "ExportBegin", "body": trace::<ExportBegin>(&mut tracer)? }),
+        json!({ "type": stream::EXPORT_END, "name": "ExportEnd", "body": trace::<ExportEnd>(&mut tracer)? }),
+        json!({ "type": stream::RADIO_FRAME, "name": "RadioFrame", "body": trace::<RadioFrame>(&mut tracer)? }),
+        json!({ "type": stream::RAW_LINE, "name": "RawLine", "body": trace::<RawLine>(&mut tracer)? }),
+    ];
+
+    // serde-reflection only explores every variant of the type it's asked
+    // for; an enum inside a body needs tracing on its own
+    trace::<RemoteCommand>(&mut tracer)?;
+    trace::<ConfigError>(&mut tracer)?;
+
+    let schema = json!({
+        "encoding": ENCODING,
+        "frame": {
+            "magic": FRAME_MAGIC,
+            "version": FRAME_VERSION,
+            "header_len": HEADER_LEN,
+            "offsets": {
+                "magic": OFFSET_MAGIC,
+                "version": OFFSET_VERSION,
+                "node": OFFSET_NODE,
+                "hops": OFFSET_HOPS,
+                "type": OFFSET_TYPE,
+                "len": OFFSET_LEN,
+            },
+            "route": "bits 4-5 of the hops byte give a route length, up to MAX_ROUTE; that many relay addresses, \
+a byte each, follow the header and come before the body; the hop count is the low four bits",
+            "max_route": MAX_ROUTE,
+            "crc_len": CRC_LEN,
+            "crc": "CRC-16/IBM-3740 (polynomial 0x1021, initial 0xFFFF) over header and body, big-endian",
+            "unframed": "Ack and Nack are the postcard AckPacket alone: [type][seq varint][node], no header or CRC; \
+the Ack of a live reading is followed by a postcard SlotGrant",
+            "slot_grant": trace::<SlotGrant>(&mut tracer)?,
+        },
+        "messages": messages,
+        "tlv": [
+            { "tag": tlv::CO2_PPM, "name": "CO2_PPM", "value": "u16 LE, ppm" },
+            { "tag": tlv::BATTERY_MV, "name": "BATTERY_MV", "value": "u16 LE, mV" },
+            { "tag": tlv::FLAGS, "name": "FLAGS", "value": "u8, bit 0 raw SHT31 ticks" },
+            { "tag": tlv::PROBE_TEMPS, "name": "PROBE_TEMPS", "value": "i16 LE per probe, 0.01 °C" },
+            { "tag": tlv::GNSS_FIX, "name": "GNSS_FIX", "value": "10 bytes, see nmea::Fix::encode" },
+            { "tag": tlv::SOIL_MOISTURE, "name": "SOIL_MOISTURE", "value": "u8 percent per probe" },
+        ],
+        "stream": {
+            "sync": stream::SYNC,
+            "prefix": "sync, then u16 LE length of the frame that follows (header, body and CRC)",
+            "records": records,
+        },
+        "types": tracer.registry().map_err(other)?,
+    });
+    serde_json::to_string_pretty(&schema).map_err(other)
+}
+
+/// The traced body of radio message `msg_type`
+fn message_body(tracer: &mut Tracer, msg_type: u8) -> std::io::Result<Value> {
+    match msg_type {
+        MSG_TYPE_ACK | MSG_TYPE_NACK => trace::<AckPacket>(tracer),
+        MSG_TYPE_SENSOR_DATA => trace::<SensorDataPacket>(tracer),
+        MSG_TYPE_CRASH_REPORT => trace::<CrashReport>(tracer),
+        MSG_TYPE_HEALTH => trace::<HealthPacket>(tracer),
+        MSG_TYPE_NODE_INFO => trace::<NodeInfo>(tracer),
+        MSG_TYPE_CALIBRATE => trace::<CalibrationCommand>(tracer),
+        MSG_TYPE_SENSOR_FAULT => trace::<SensorFaultPacket>(tracer),
+        MSG_TYPE_SUMMARY => trace::<SummaryPacket>(tracer),
+        MSG_TYPE_EVENT => trace::<EventPacket>(tracer),
+        MSG_TYPE_DISPLAY => Ok(json!({ "firmware": "panel::Setting" })),
+        MSG_TYPE_POWER_FAIL => trace::<PowerFailPacket>(tracer),
+        MSG_TYPE_STORED => trace::<StoredReading>(tracer),
+        MSG_TYPE_REPLAY_REQUEST => trace::<ReplayRequest>(tracer),
+        MSG_TYPE_REPLAY_BATCH => trace::<ReplayBatch>(tracer),
+        MSG_TYPE_COMMAND => trace::<CommandPacket>(tracer),
+        MSG_TYPE_COMMAND_RESULT => trace::<CommandResult>(tracer),
+        MSG_TYPE_LOG => trace::<LogBatch>(tracer),
+        MSG_TYPE_FW_BEGIN => trace::<FirmwareBegin>(tracer),
+        MSG_TYPE_FW_CHUNK => trace::<FirmwareChunk>(tracer),
+        MSG_TYPE_FW_STATUS => trace::<FirmwareStatus>(tracer),
+        MSG_TYPE_BROADCAST => trace::<BroadcastPacket>(tracer),
+        MSG_TYPE_BROADCAST_ACK => trace::<BroadcastAck>(tracer),
+        MSG_TYPE_JOIN_REQUEST => trace::<JoinRequest>(tracer),
+        MSG_TYPE_JOIN_ACCEPT => trace::<JoinAccept>(tracer),
+        MSG_TYPE_PING => trace::<Ping>(tracer),
+        MSG_TYPE_PONG => trace::<Pong>(tracer),
+        MSG_TYPE_CONFIG_ACK => trace::<ConfigAck>(tracer),
+        MSG_TYPE_CONFIG_REPORT => trace::<ConfigReport>(tracer),
+        MSG_TYPE_LINK_STATS => trace::<LinkStats>(tracer),
+        // A new MESSAGE_TYPES entry needs its body here
+        t => Err(other(format!("no body type for message type {}", t))),
+    }
+}
+
+/// `T`'s format, adding it and everything it contains to the registry
+fn trace<'de, T: Deserialize<'de>>(tracer: &mut Tracer) -> std::io::Result<Value> {
+    let (format, _) = tracer.trace_simple_type::<T>().map_err(other)?;
+    serde_json::to_value(format).map_err(other)
+}